@@ -0,0 +1,74 @@
+//! Compares decoding a whole wide document with decoding a single field through the
+//! field offset table, which is what a projected table scan does.
+//!
+//! Run with `cargo bench --bench document_encoding`.
+
+use rulodb::ast::Document;
+use rulodb::storage::encoding::{EncodedDocument, decode_document, encode_document};
+use rulodb::{Datum, datum};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const FIELD_COUNTS: [usize; 3] = [10, 50, 200];
+const DOCUMENTS: usize = 1_000;
+const ROUNDS: usize = 20;
+
+fn wide_document(id: usize, fields: usize) -> Document {
+    let mut doc = Document::with_capacity(fields + 1);
+    doc.insert(
+        "id".to_string(),
+        Datum {
+            value: Some(datum::Value::String(format!("doc_{id:06}"))),
+        },
+    );
+    for i in 0..fields {
+        let value = match i % 3 {
+            0 => datum::Value::Int(i as i64),
+            1 => datum::Value::Float(i as f64 / 3.0),
+            _ => datum::Value::String(format!("value_{i}_{}", "x".repeat(24))),
+        };
+        doc.insert(format!("field_{i:03}"), Datum { value: Some(value) });
+    }
+    doc
+}
+
+fn measure(encoded: &[Vec<u8>], decode: impl Fn(&[u8])) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for data in encoded {
+            decode(data);
+        }
+    }
+    start.elapsed() / (ROUNDS * encoded.len()) as u32
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>12} {:>14} {:>14} {:>9}",
+        "fields", "bytes/doc", "full decode", "single field", "speedup"
+    );
+
+    for fields in FIELD_COUNTS {
+        let encoded: Vec<Vec<u8>> = (0..DOCUMENTS)
+            .map(|id| encode_document(&wide_document(id, fields)).expect("encode document"))
+            .collect();
+        let bytes = encoded.iter().map(Vec::len).sum::<usize>() / encoded.len();
+
+        let full = measure(&encoded, |data| {
+            black_box(decode_document(data).expect("decode document"));
+        });
+        let single = measure(&encoded, |data| {
+            let doc = EncodedDocument::parse(data).expect("parse document");
+            black_box(doc.field("id").expect("decode field"));
+        });
+
+        println!(
+            "{:>8} {:>12} {:>14?} {:>14?} {:>8.1}x",
+            fields,
+            bytes,
+            full,
+            single,
+            full.as_secs_f64() / single.as_secs_f64()
+        );
+    }
+}
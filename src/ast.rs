@@ -8,8 +8,12 @@ use std::collections::HashMap;
 /// The Document is a map of string keys to datum values.
 pub type Document = HashMap<String, Datum>;
 
-/// Predicate is a function that takes a document and returns a boolean value.
-pub type Predicate = Box<dyn Fn(Document) -> bool + Send + Sync>;
+/// Predicate is a function that takes a document and returns whether it matches, or
+/// the error that deciding it raised.
+pub type Predicate = Box<dyn Fn(Document) -> Result<bool, PredicateError> + Send + Sync>;
+
+/// An error raised by a predicate, passed through storage to whoever made it.
+pub type PredicateError = Box<dyn std::error::Error + Send + Sync>;
 
 /// Represents a field in an ORDER BY clause
 #[derive(Debug, Clone, PartialEq)]
@@ -28,6 +32,10 @@ impl std::fmt::Display for datum::Value {
             datum::Value::Binary(v) => write!(f, "{v:?}"),
             datum::Value::Object(v) => write!(f, "{v}"),
             datum::Value::Array(v) => write!(f, "{v}"),
+            datum::Value::Geometry(v) => write!(f, "{v}"),
+            datum::Value::Time(v) => write!(f, "{v}"),
+            datum::Value::Decimal(v) => write!(f, "{}", v.value),
+            datum::Value::Uuid(v) => write!(f, "{v}"),
             datum::Value::Null(_) => write!(f, "NULL"),
         }
     }
@@ -78,6 +86,48 @@ impl std::fmt::Display for DatumArray {
     }
 }
 
+impl std::fmt::Display for Point {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "POINT({} {})", self.longitude, self.latitude)
+    }
+}
+
+/// Geometries are written in the well-known text format.
+impl std::fmt::Display for Geometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let coordinates = |points: &[Point]| {
+            points
+                .iter()
+                .map(|p| format!("{} {}", p.longitude, p.latitude))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match &self.kind {
+            Some(geometry::Kind::Point(point)) => write!(f, "{point}"),
+            Some(geometry::Kind::Line(line)) => {
+                write!(f, "LINESTRING({})", coordinates(&line.points))
+            }
+            Some(geometry::Kind::Polygon(polygon)) => {
+                write!(f, "POLYGON(({}))", coordinates(&polygon.points))
+            }
+            None => write!(f, "GEOMETRYCOLLECTION EMPTY"),
+        }
+    }
+}
+
+/// Times are written in ISO 8601.
+impl std::fmt::Display for Time {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", crate::time::format_iso8601(self))
+    }
+}
+
+impl std::fmt::Display for Uuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", crate::uuid::format(self))
+    }
+}
+
 impl From<&DatumObject> for Document {
     fn from(obj: &DatumObject) -> Self {
         obj.fields.clone()
@@ -109,6 +159,81 @@ impl std::fmt::Display for FieldRef {
     }
 }
 
+impl From<crate::storage::replication::ReplicationUpdates> for ReplicationUpdates {
+    fn from(updates: crate::storage::replication::ReplicationUpdates) -> Self {
+        use crate::storage::replication::WalOperation as Operation;
+
+        let batches = updates
+            .batches
+            .into_iter()
+            .map(|batch| WalBatch {
+                sequence: batch.sequence,
+                count: batch.count,
+                operations: batch
+                    .operations
+                    .into_iter()
+                    .map(|operation| match operation {
+                        Operation::Put {
+                            column_family,
+                            key,
+                            value,
+                        } => WalOperation {
+                            column_family,
+                            key,
+                            value: Some(value),
+                        },
+                        Operation::Delete { column_family, key } => WalOperation {
+                            column_family,
+                            key,
+                            value: None,
+                        },
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        ReplicationUpdates {
+            batches,
+            latest_sequence: updates.latest_sequence,
+        }
+    }
+}
+
+impl From<ReplicationUpdates> for crate::storage::replication::ReplicationUpdates {
+    fn from(updates: ReplicationUpdates) -> Self {
+        use crate::storage::replication::{WalBatch as Batch, WalOperation as Operation};
+
+        let batches = updates
+            .batches
+            .into_iter()
+            .map(|batch| Batch {
+                sequence: batch.sequence,
+                count: batch.count,
+                operations: batch
+                    .operations
+                    .into_iter()
+                    .map(|operation| match operation.value {
+                        Some(value) => Operation::Put {
+                            column_family: operation.column_family,
+                            key: operation.key,
+                            value,
+                        },
+                        None => Operation::Delete {
+                            column_family: operation.column_family,
+                            key: operation.key,
+                        },
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            batches,
+            latest_sequence: updates.latest_sequence,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -171,6 +296,25 @@ mod tests {
         assert_eq!(format!("{value}"), "[255, 0, 128]");
     }
 
+    #[test]
+    fn test_datum_value_display_geometry() {
+        let point = |longitude, latitude| Point {
+            longitude,
+            latitude,
+        };
+        let value = datum::Value::Geometry(Geometry {
+            kind: Some(geometry::Kind::Point(point(-0.5, 51.25))),
+        });
+        assert_eq!(format!("{value}"), "POINT(-0.5 51.25)");
+
+        let value = datum::Value::Geometry(Geometry {
+            kind: Some(geometry::Kind::Polygon(Polygon {
+                points: vec![point(0.0, 0.0), point(1.0, 0.0), point(1.0, 1.0)],
+            })),
+        });
+        assert_eq!(format!("{value}"), "POLYGON((0 0, 1 0, 1 1))");
+    }
+
     #[test]
     fn test_datum_value_display_object() {
         let mut fields = HashMap::new();
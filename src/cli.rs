@@ -1,4 +1,5 @@
 use clap::{Args, Parser, Subcommand};
+use rulodb::cluster::Member;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -28,6 +29,10 @@ pub struct ServerConfig {
     /// Address for the database server.
     #[arg(long, short, env = "RULODB_ADDRESS", default_value = "127.0.0.1:6090")]
     pub address: String,
+    /// Queries a connection can have running at once. Its further requests are not
+    /// read until one of them is answered.
+    #[arg(long, env = "RULODB_MAX_INFLIGHT_QUERIES", default_value_t = 32)]
+    pub max_inflight_queries: usize,
 }
 
 #[derive(Debug, Clone, Args)]
@@ -89,6 +94,67 @@ pub struct EngineConfig {
         default_value_t = 268435456
     )]
     pub max_bytes_for_level_base: u64,
+    /// Seconds to keep obsolete WAL files for replicas to catch up from.
+    #[arg(long, env = "RULODB_WAL_TTL_SECONDS", default_value_t = 3600)]
+    pub wal_ttl_seconds: u64,
+    /// Megabytes of obsolete WAL files to keep for replicas to catch up from.
+    #[arg(long, env = "RULODB_WAL_SIZE_LIMIT_MB", default_value_t = 1024)]
+    pub wal_size_limit_mb: u64,
+}
+
+/// Replication settings.
+#[derive(Debug, Clone, Args)]
+pub struct ReplicationConfig {
+    /// Address of a primary to follow. The server then serves read-only queries as a replica
+    /// of it. The replica must start empty or from a copy of the primary's data directory.
+    #[arg(long, env = "RULODB_REPLICA_OF", conflicts_with = "node_id")]
+    pub replica_of: Option<String>,
+    /// Data directory of a primary running on this machine. The server then opens it as a
+    /// read-only secondary instance, keeping its own files in the data directory.
+    #[arg(
+        long,
+        env = "RULODB_SECONDARY_OF",
+        conflicts_with_all = ["node_id", "replica_of"]
+    )]
+    pub secondary_of: Option<String>,
+    /// Keep obsolete WAL files, within the WAL TTL and size limit, for replicas to follow
+    /// this server from.
+    #[arg(long, env = "RULODB_SERVE_REPLICAS", default_value_t = false)]
+    pub serve_replicas: bool,
+    /// Milliseconds to wait before polling the primary again once caught up.
+    #[arg(long, env = "RULODB_REPLICATION_POLL_INTERVAL", default_value_t = 100)]
+    pub replication_poll_interval: u64,
+}
+
+/// Clustering settings.
+#[derive(Debug, Clone, Args)]
+pub struct ClusterConfig {
+    /// Id of this node in a Raft cluster. Writes then go through the cluster's log.
+    #[arg(long, env = "RULODB_NODE_ID")]
+    pub node_id: Option<u64>,
+    /// Members to bootstrap the cluster with, as `id=address` pairs that include this node.
+    /// Leave empty when joining an existing cluster, whose leader then adds the node.
+    #[arg(
+        long = "cluster-member",
+        env = "RULODB_CLUSTER_MEMBERS",
+        value_delimiter = ',',
+        requires = "node_id"
+    )]
+    pub members: Vec<Member>,
+    /// Milliseconds between leader heartbeats.
+    #[arg(long, env = "RULODB_HEARTBEAT_INTERVAL", default_value_t = 100)]
+    pub heartbeat_interval: u64,
+    /// Milliseconds without a heartbeat before a follower starts an election.
+    #[arg(long, env = "RULODB_ELECTION_TIMEOUT", default_value_t = 1000)]
+    pub election_timeout: u64,
+    /// Applied entries the Raft log keeps before older ones are compacted into a snapshot.
+    /// Followers further behind catch up from a snapshot of the leader's data.
+    #[arg(long, env = "RULODB_SNAPSHOT_THRESHOLD", default_value_t = 10_000)]
+    pub snapshot_threshold: u64,
+    /// Directory of the node's Raft log, which must not be inside the data directory.
+    /// Defaults to the data directory's path followed by `-raft`.
+    #[arg(long, env = "RULODB_RAFT_DIR", requires = "node_id")]
+    pub raft_dir: Option<String>,
 }
 
 #[derive(Debug, Clone, Args)]
@@ -98,4 +164,10 @@ pub struct StartCommand {
 
     #[command(flatten)]
     pub engine_config: EngineConfig,
+
+    #[command(flatten)]
+    pub replication: ReplicationConfig,
+
+    #[command(flatten)]
+    pub cluster: ClusterConfig,
 }
@@ -0,0 +1,582 @@
+//! Raft-based clustering.
+//!
+//! The nodes of a cluster share a log of table writes, replicated by the Raft consensus
+//! algorithm. Each node applies the committed log to its own storage backend, so the
+//! `StorageBackend` trait doubles as the state machine. `ClusterStorage` puts a node in
+//! front of its local backend: writes are proposed to the log and only return once they
+//! are committed and applied, while reads are served locally. Followers reject writes
+//! and point clients at the leader, and a new leader is elected when the current one
+//! fails or steps down.
+
+mod log_store;
+mod node;
+mod raft;
+#[cfg(test)]
+mod tests;
+mod transport;
+
+use crate::ast::{Datum, Document, Predicate};
+use crate::geo::{Point, Shape};
+use crate::storage::index::IndexDefinition;
+use crate::storage::replication::{ReplicationStatus, ReplicationUpdates};
+use crate::storage::sharding::RebalanceSummary;
+use crate::storage::statistics::{StatisticsProvider, TableStatistics};
+use crate::storage::{
+    DocumentUpdate, Durability, Result, ScanProjection, SnapshotId, StorageBackend, StorageError,
+    TableConfig,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio_stream::wrappers::ReceiverStream;
+
+pub use log_store::{
+    LogSnapshot, LogStore, LogWrite, MemoryLogStore, PersistentState, RocksLogStore,
+};
+pub use node::{ClusterNode, NodeConfig};
+pub use raft::{Request, Response};
+pub use transport::{LocalTransport, TcpTransport, Transport, decode_message, encode_message};
+
+pub type NodeId = u64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    pub id: NodeId,
+    pub address: String,
+}
+
+impl std::str::FromStr for Member {
+    type Err = String;
+
+    /// Parse a member given as `id=address`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (id, address) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `id=address`, got `{s}`"))?;
+        let id = id
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid node id `{id}`"))?;
+        Ok(Self {
+            id,
+            address: address.trim().to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Follower => write!(f, "follower"),
+            Self::Candidate => write!(f, "candidate"),
+            Self::Leader => write!(f, "leader"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MembershipChange {
+    Add(Member),
+    Remove(NodeId),
+}
+
+/// A node's view of its cluster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterStatus {
+    pub node_id: NodeId,
+    pub role: Role,
+    pub term: u64,
+    pub leader: Option<Member>,
+    /// Index of the last entry known to be committed.
+    pub commit_index: u64,
+    /// Index of the last entry applied to this node's storage.
+    pub applied_index: u64,
+    pub members: Vec<Member>,
+}
+
+/// A state machine operation recorded in the log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    /// Appended by every new leader to commit the entries of earlier terms.
+    Noop,
+    CreateDatabase {
+        name: String,
+    },
+    DropDatabase {
+        name: String,
+    },
+    CreateTable {
+        database: String,
+        table: String,
+        config: TableConfig,
+        shards: u32,
+    },
+    DropTable {
+        database: String,
+        table: String,
+    },
+    /// Applied by every node alike, as their data is the same.
+    Rebalance {
+        database: String,
+        table: String,
+        shards: Option<u32>,
+    },
+    CreateIndex {
+        database: String,
+        table: String,
+        definition: IndexDefinition,
+    },
+    DropIndex {
+        database: String,
+        table: String,
+        name: String,
+    },
+    Put {
+        database: String,
+        table: String,
+        documents: Vec<(String, Document)>,
+        durability: Option<Durability>,
+    },
+    Delete {
+        database: String,
+        table: String,
+        keys: Vec<String>,
+        durability: Option<Durability>,
+    },
+    /// Replace the cluster's members. Takes effect as soon as it is appended.
+    ChangeMembership {
+        members: Vec<Member>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub term: u64,
+    pub command: Command,
+}
+
+/// A storage backend whose writes go through the cluster's log.
+pub struct ClusterStorage {
+    node: Arc<ClusterNode>,
+    local: Arc<dyn StorageBackend>,
+    /// Serializes updates, which read documents here and propose their new versions.
+    update_lock: tokio::sync::Mutex<()>,
+}
+
+impl ClusterStorage {
+    /// Wrap `local`, which must be the state machine `node` applies entries to.
+    pub fn new(node: Arc<ClusterNode>, local: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            node,
+            local,
+            update_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn node(&self) -> &Arc<ClusterNode> {
+        &self.node
+    }
+}
+
+impl StatisticsProvider for ClusterStorage {
+    fn table_statistics(&self, db: &str, table: &str) -> Option<TableStatistics> {
+        self.local.table_statistics(db, table)
+    }
+
+    fn table_count(&self, db: &str) -> Option<u64> {
+        self.local.table_count(db)
+    }
+
+    fn database_count(&self) -> Option<u64> {
+        self.local.database_count()
+    }
+
+    fn schema_version(&self) -> u64 {
+        self.local.schema_version()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for ClusterStorage {
+    async fn create_database(&self, name: &str) -> Result<()> {
+        self.node
+            .propose(Command::CreateDatabase {
+                name: name.to_string(),
+            })
+            .await
+    }
+
+    async fn drop_database(&self, name: &str) -> Result<()> {
+        self.node
+            .propose(Command::DropDatabase {
+                name: name.to_string(),
+            })
+            .await
+    }
+
+    async fn database_exists(&self, name: &str) -> Result<bool> {
+        self.local.database_exists(name).await
+    }
+
+    async fn create_table(&self, db: &str, table: &str, config: &TableConfig) -> Result<()> {
+        self.create_sharded_table(db, table, config, 1).await
+    }
+
+    async fn create_sharded_table(
+        &self,
+        db: &str,
+        table: &str,
+        config: &TableConfig,
+        shards: u32,
+    ) -> Result<()> {
+        // Reject bad options before they reach the log
+        config.validate()?;
+        self.node
+            .propose(Command::CreateTable {
+                database: db.to_string(),
+                table: table.to_string(),
+                config: config.clone(),
+                shards,
+            })
+            .await
+    }
+
+    async fn drop_table(&self, db: &str, table: &str) -> Result<()> {
+        self.node
+            .propose(Command::DropTable {
+                database: db.to_string(),
+                table: table.to_string(),
+            })
+            .await
+    }
+
+    async fn table_exists(&self, db: &str, table: &str) -> Result<bool> {
+        self.local.table_exists(db, table).await
+    }
+
+    async fn put(
+        &self,
+        db: &str,
+        table: &str,
+        key: &str,
+        doc: &Document,
+        durability: Option<Durability>,
+    ) -> Result<()> {
+        self.put_batch(db, table, &[(key.to_string(), doc.clone())], durability)
+            .await
+    }
+
+    async fn put_batch(
+        &self,
+        db: &str,
+        table: &str,
+        docs: &[(String, Document)],
+        durability: Option<Durability>,
+    ) -> Result<()> {
+        if docs.is_empty() {
+            return Ok(());
+        }
+        self.node
+            .propose(Command::Put {
+                database: db.to_string(),
+                table: table.to_string(),
+                documents: docs.to_vec(),
+                durability,
+            })
+            .await
+    }
+
+    async fn update_batch(
+        &self,
+        db: &str,
+        table: &str,
+        keys: &[String],
+        update: DocumentUpdate,
+        durability: Option<Durability>,
+    ) -> Result<usize> {
+        // Only the leader proposes writes, and it applies each before the next update
+        // reads, so holding the lock across the proposal keeps updates from crossing.
+        // A new leader may not have applied the writes of its predecessors yet, though
+        let _update = self.update_lock.lock().await;
+        self.node.read_barrier().await?;
+        let mut docs = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(stored) = self.local.get(db, table, key, None).await? {
+                docs.push((key.clone(), update(stored)?));
+            }
+        }
+        self.put_batch(db, table, &docs, durability).await?;
+        Ok(docs.len())
+    }
+
+    async fn get(
+        &self,
+        db: &str,
+        table: &str,
+        key: &str,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Option<Document>> {
+        self.local.get(db, table, key, snapshot).await
+    }
+
+    async fn scan_table(
+        &self,
+        db: &str,
+        table: &str,
+        start_key: Option<String>,
+        limit: Option<usize>,
+        skip: Option<usize>,
+        predicate: Option<Predicate>,
+        projection: ScanProjection,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<ReceiverStream<Result<Document>>> {
+        self.local
+            .scan_table(
+                db, table, start_key, limit, skip, predicate, projection, snapshot,
+            )
+            .await
+    }
+
+    async fn delete(
+        &self,
+        db: &str,
+        table: &str,
+        key: &str,
+        durability: Option<Durability>,
+    ) -> Result<()> {
+        self.delete_batch(db, table, &[key.to_string()], durability)
+            .await
+    }
+
+    async fn delete_batch(
+        &self,
+        db: &str,
+        table: &str,
+        keys: &[String],
+        durability: Option<Durability>,
+    ) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        self.node
+            .propose(Command::Delete {
+                database: db.to_string(),
+                table: table.to_string(),
+                keys: keys.to_vec(),
+                durability,
+            })
+            .await
+    }
+
+    async fn analyze_table(
+        &self,
+        db: &str,
+        table: &str,
+        sample_size: usize,
+    ) -> Result<TableStatistics> {
+        // Statistics describe the local copy of the data and are not replicated
+        self.local.analyze_table(db, table, sample_size).await
+    }
+
+    async fn rebalance_table(
+        &self,
+        db: &str,
+        table: &str,
+        shards: Option<u32>,
+    ) -> Result<RebalanceSummary> {
+        self.node.rebalance(db, table, shards).await
+    }
+
+    async fn create_index(
+        &self,
+        db: &str,
+        table: &str,
+        definition: &IndexDefinition,
+    ) -> Result<()> {
+        definition.validate()?;
+        self.node
+            .propose(Command::CreateIndex {
+                database: db.to_string(),
+                table: table.to_string(),
+                definition: definition.clone(),
+            })
+            .await
+    }
+
+    async fn drop_index(&self, db: &str, table: &str, name: &str) -> Result<()> {
+        self.node
+            .propose(Command::DropIndex {
+                database: db.to_string(),
+                table: table.to_string(),
+                name: name.to_string(),
+            })
+            .await
+    }
+
+    async fn list_indexes(&self, db: &str, table: &str) -> Result<Vec<IndexDefinition>> {
+        self.local.list_indexes(db, table).await
+    }
+
+    async fn search(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        query: &str,
+        limit: Option<usize>,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<(Document, f64)>> {
+        self.local
+            .search(db, table, index, query, limit, snapshot)
+            .await
+    }
+
+    async fn get_all_indexed(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        values: &[Datum],
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<Document>> {
+        self.local
+            .get_all_indexed(db, table, index, values, snapshot)
+            .await
+    }
+
+    async fn between(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        lower: Option<&Datum>,
+        upper: Option<&Datum>,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<Document>> {
+        self.local
+            .between(db, table, index, lower, upper, snapshot)
+            .await
+    }
+
+    async fn get_intersecting(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        shape: &Shape,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<Document>> {
+        self.local
+            .get_intersecting(db, table, index, shape, snapshot)
+            .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn get_nearest(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        point: Point,
+        max_dist: f64,
+        max_results: usize,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<(Document, f64)>> {
+        self.local
+            .get_nearest(db, table, index, point, max_dist, max_results, snapshot)
+            .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn nearest(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        vector: &[f64],
+        k: usize,
+        probes: usize,
+        predicate: Option<Predicate>,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<(Document, f64)>> {
+        self.local
+            .nearest(db, table, index, vector, k, probes, predicate, snapshot)
+            .await
+    }
+
+    async fn create_snapshot(&self) -> Result<SnapshotId> {
+        self.local.create_snapshot().await
+    }
+
+    async fn release_snapshot(&self, snapshot: SnapshotId) -> Result<()> {
+        self.local.release_snapshot(snapshot).await
+    }
+
+    async fn updates_since(&self, sequence: u64, max_batches: usize) -> Result<ReplicationUpdates> {
+        self.local.updates_since(sequence, max_batches).await
+    }
+
+    async fn apply_updates(&self, _updates: ReplicationUpdates) -> Result<u64> {
+        Err(StorageError::Cluster(
+            "cluster nodes cannot be replicas".to_string(),
+        ))
+    }
+
+    fn replication_status(&self) -> ReplicationStatus {
+        self.local.replication_status()
+    }
+
+    fn cluster_status(&self) -> Result<ClusterStatus> {
+        Ok(self.node.status())
+    }
+
+    async fn change_membership(&self, change: MembershipChange) -> Result<ClusterStatus> {
+        self.node.change_membership(change).await
+    }
+
+    async fn transfer_leadership(&self, target: Option<NodeId>) -> Result<ClusterStatus> {
+        self.node.transfer_leadership(target)?;
+        Ok(self.node.status())
+    }
+
+    async fn stream_databases(
+        &self,
+        start_key: Option<String>,
+        limit: Option<usize>,
+        skip: Option<usize>,
+    ) -> Result<ReceiverStream<Result<String>>> {
+        self.local.stream_databases(start_key, limit, skip).await
+    }
+
+    async fn stream_tables(
+        &self,
+        db: &str,
+        start_key: Option<String>,
+        limit: Option<usize>,
+        skip: Option<usize>,
+    ) -> Result<ReceiverStream<Result<String>>> {
+        self.local.stream_tables(db, start_key, limit, skip).await
+    }
+
+    async fn stream_get_all(
+        &self,
+        db: &str,
+        table: &str,
+        keys: &[String],
+        start_key: Option<String>,
+        limit: Option<usize>,
+        skip: Option<usize>,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<ReceiverStream<Result<Document>>> {
+        self.local
+            .stream_get_all(db, table, keys, start_key, limit, skip, snapshot)
+            .await
+    }
+}
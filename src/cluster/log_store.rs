@@ -0,0 +1,235 @@
+//! Durable storage of a node's Raft log and vote.
+
+use super::{Entry, Member, NodeId};
+use crate::storage::{Result, StorageError};
+use rocksdb::{
+    ColumnFamilyDescriptor, DBWithThreadMode, IteratorMode, MultiThreaded, Options, ReadOptions,
+    WriteBatch, WriteOptions,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+const HARD_STATE_KEY: &[u8] = b"hard_state";
+const SNAPSHOT_KEY: &[u8] = b"snapshot";
+const ENTRY_KEY_PREFIX: &[u8] = b"entry:";
+
+/// What a node needs to rejoin the cluster after a restart, besides the index of the
+/// last entry its state machine applied, which the state machine records itself.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PersistentState {
+    pub term: u64,
+    pub voted_for: Option<NodeId>,
+    /// Where the log starts, as the entries before it were compacted.
+    pub snapshot: LogSnapshot,
+    /// The entries following the snapshot.
+    pub entries: Vec<Entry>,
+}
+
+/// The last entry a snapshot of the state machine covers, and the membership as of
+/// that entry. The log keeps only the entries after it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LogSnapshot {
+    pub index: u64,
+    pub term: u64,
+    pub members: Vec<Member>,
+}
+
+/// A change to a node's log or vote, made durable before the messages that depend on it
+/// are sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogWrite {
+    HardState {
+        term: u64,
+        voted_for: Option<NodeId>,
+    },
+    /// Replace the entries from `index` to `last_index` with `entries`.
+    Entries {
+        index: u64,
+        entries: Vec<Entry>,
+        last_index: u64,
+    },
+    /// Start the log after `snapshot`, dropping the entries up to `through`.
+    Snapshot { snapshot: LogSnapshot, through: u64 },
+}
+
+#[derive(Serialize, Deserialize)]
+struct HardState {
+    term: u64,
+    voted_for: Option<NodeId>,
+}
+
+/// Where a node keeps its log. Writes must be durable when they return, as the node
+/// acknowledges them to its peers right after.
+pub trait LogStore: Send {
+    fn load(&self) -> Result<PersistentState>;
+    fn save_hard_state(&mut self, term: u64, voted_for: Option<NodeId>) -> Result<()>;
+    /// Replace the entries from `index` to `last_index` with `entries`.
+    fn replace_from(&mut self, index: u64, entries: &[Entry], last_index: u64) -> Result<()>;
+    /// Start the log after `snapshot`, dropping the entries up to `through`.
+    fn save_snapshot(&mut self, snapshot: &LogSnapshot, through: u64) -> Result<()>;
+
+    fn write(&mut self, write: &LogWrite) -> Result<()> {
+        match write {
+            LogWrite::HardState { term, voted_for } => self.save_hard_state(*term, *voted_for),
+            LogWrite::Entries {
+                index,
+                entries,
+                last_index,
+            } => self.replace_from(*index, entries, *last_index),
+            LogWrite::Snapshot { snapshot, through } => self.save_snapshot(snapshot, *through),
+        }
+    }
+}
+
+/// A log store that keeps everything in memory, for nodes that don't need to survive
+/// a restart. Clones share their state, so a test can restart a node with the log of
+/// its previous incarnation.
+#[derive(Clone, Default)]
+pub struct MemoryLogStore {
+    state: Arc<Mutex<PersistentState>>,
+}
+
+impl MemoryLogStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl LogStore for MemoryLogStore {
+    fn load(&self) -> Result<PersistentState> {
+        Ok(self.state.lock().unwrap().clone())
+    }
+
+    fn save_hard_state(&mut self, term: u64, voted_for: Option<NodeId>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.term = term;
+        state.voted_for = voted_for;
+        Ok(())
+    }
+
+    fn replace_from(&mut self, index: u64, entries: &[Entry], _last_index: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let kept = (index - state.snapshot.index - 1) as usize;
+        state.entries.truncate(kept);
+        state.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn save_snapshot(&mut self, snapshot: &LogSnapshot, through: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let dropped = ((through - state.snapshot.index) as usize).min(state.entries.len());
+        state.entries.drain(..dropped);
+        state.snapshot = snapshot.clone();
+        Ok(())
+    }
+}
+
+/// A log store backed by its own RocksDB database, next to the node's data.
+pub struct RocksLogStore {
+    db: DBWithThreadMode<MultiThreaded>,
+}
+
+impl RocksLogStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let db = DBWithThreadMode::open_cf_descriptors(
+            &opts,
+            path,
+            vec![ColumnFamilyDescriptor::new("default", Options::default())],
+        )?;
+        Ok(Self { db })
+    }
+
+    fn entry_key(index: u64) -> Vec<u8> {
+        [ENTRY_KEY_PREFIX, &index.to_be_bytes()].concat()
+    }
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::serde::encode_to_vec(
+            value,
+            bincode::config::standard(),
+        )?)
+    }
+
+    fn decode<T: for<'de> Deserialize<'de>>(bytes: &[u8]) -> Result<T> {
+        let (value, _) = bincode::serde::decode_from_slice(bytes, bincode::config::standard())?;
+        Ok(value)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let mut write_opts = WriteOptions::default();
+        write_opts.set_sync(true);
+        Ok(self.db.write_opt(batch, &write_opts)?)
+    }
+
+    fn default_cf(&self) -> Result<std::sync::Arc<rocksdb::BoundColumnFamily<'_>>> {
+        self.db
+            .cf_handle("default")
+            .ok_or_else(|| StorageError::MissingColumnFamily("default".to_string()))
+    }
+}
+
+impl LogStore for RocksLogStore {
+    fn load(&self) -> Result<PersistentState> {
+        let cf = self.default_cf()?;
+        let mut state = PersistentState::default();
+
+        if let Some(bytes) = self.db.get_cf(&cf, HARD_STATE_KEY)? {
+            let hard_state: HardState = Self::decode(&bytes)?;
+            state.term = hard_state.term;
+            state.voted_for = hard_state.voted_for;
+        }
+
+        if let Some(bytes) = self.db.get_cf(&cf, SNAPSHOT_KEY)? {
+            state.snapshot = Self::decode(&bytes)?;
+        }
+
+        let first_key = Self::entry_key(state.snapshot.index + 1);
+        let iter = self.db.iterator_cf_opt(
+            &cf,
+            ReadOptions::default(),
+            IteratorMode::From(&first_key, rocksdb::Direction::Forward),
+        );
+        for item in iter {
+            let (key, value) = item?;
+            if !key.starts_with(ENTRY_KEY_PREFIX) {
+                break;
+            }
+            state.entries.push(Self::decode(&value)?);
+        }
+
+        Ok(state)
+    }
+
+    fn save_hard_state(&mut self, term: u64, voted_for: Option<NodeId>) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.put_cf(
+            &self.default_cf()?,
+            HARD_STATE_KEY,
+            Self::encode(&HardState { term, voted_for })?,
+        );
+        self.write(batch)
+    }
+
+    fn replace_from(&mut self, index: u64, entries: &[Entry], last_index: u64) -> Result<()> {
+        let cf = self.default_cf()?;
+        let mut batch = WriteBatch::default();
+        for stale in index..=last_index {
+            batch.delete_cf(&cf, Self::entry_key(stale));
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            batch.put_cf(&cf, Self::entry_key(index + i as u64), Self::encode(entry)?);
+        }
+        self.write(batch)
+    }
+
+    fn save_snapshot(&mut self, snapshot: &LogSnapshot, through: u64) -> Result<()> {
+        let cf = self.default_cf()?;
+        let mut batch = WriteBatch::default();
+        batch.delete_range_cf(&cf, Self::entry_key(0), Self::entry_key(through + 1));
+        batch.put_cf(&cf, SNAPSHOT_KEY, Self::encode(snapshot)?);
+        self.write(batch)
+    }
+}
@@ -0,0 +1,698 @@
+//! Runs a Raft node: drives its clock, writes its log on a thread of its own, delivers
+//! its messages and applies committed entries to the local storage backend. Applied
+//! entries are compacted from the log, and followers that need them are sent a snapshot
+//! of the storage backend instead.
+
+use super::log_store::{LogSnapshot, LogStore, LogWrite};
+use super::raft::{Raft, Ready, Request, Response};
+use super::transport::Transport;
+use super::{ClusterStatus, Command, Member, MembershipChange, NodeId};
+use crate::storage::sharding::RebalanceSummary;
+use crate::storage::{Durability, Result, StorageBackend, StorageError};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Duration;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+
+/// Size of the chunks a snapshot is sent in.
+const SNAPSHOT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Settings of a cluster node.
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub node_id: NodeId,
+    /// Members the cluster was bootstrapped with. Nodes joining an existing cluster
+    /// start without members and learn them from the leader.
+    pub members: Vec<Member>,
+    /// Interval between leader heartbeats, and the unit of the election timeout.
+    pub heartbeat_interval: Duration,
+    /// Minimum time without hearing from a leader before a node starts an election.
+    pub election_timeout: Duration,
+    /// Time to wait for a proposed write to be committed and applied.
+    pub proposal_timeout: Duration,
+    /// Number of applied entries the log keeps. It is compacted once it holds twice as
+    /// many, so followers that fall further behind catch up from a snapshot.
+    pub snapshot_threshold: u64,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            node_id: 1,
+            members: Vec::new(),
+            heartbeat_interval: Duration::from_millis(100),
+            election_timeout: Duration::from_millis(1000),
+            proposal_timeout: Duration::from_secs(10),
+            snapshot_threshold: 10_000,
+        }
+    }
+}
+
+/// What applying a command produced, for the commands that report an outcome.
+type Applied = Option<RebalanceSummary>;
+
+type Waiter = (u64, oneshot::Sender<Result<Applied>>);
+
+/// Log writes for the log writer, and where to report once they are durable.
+type LogBatch = (Vec<LogWrite>, oneshot::Sender<Result<()>>);
+
+pub struct ClusterNode {
+    id: NodeId,
+    config: NodeConfig,
+    raft: Mutex<Raft>,
+    /// Feeds the thread that writes the log, in the order the writes were made, so that
+    /// the Raft state machine stays available while it waits for the disk.
+    log_writer: mpsc::Sender<LogBatch>,
+    state_machine: Arc<dyn StorageBackend>,
+    transport: Arc<dyn Transport>,
+    /// Proposals waiting for their entry to be applied, by log index.
+    waiters: Mutex<HashMap<u64, Waiter>>,
+    /// Serializes the application of committed entries.
+    apply_lock: tokio::sync::Mutex<()>,
+    /// Index of the last entry applied to the state machine.
+    applied: watch::Sender<u64>,
+    /// Snapshot being received from the leader: who sends it, the last entry it covers
+    /// and the data received so far.
+    incoming_snapshot: Mutex<Option<(NodeId, u64, Vec<u8>)>>,
+    stopped: AtomicBool,
+    ticker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ClusterNode {
+    /// Restore the node from `log_store` and start its clock. Committed entries are
+    /// applied to `state_machine`, which must not be written to otherwise.
+    pub fn start(
+        config: NodeConfig,
+        log_store: Box<dyn LogStore>,
+        state_machine: Arc<dyn StorageBackend>,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Arc<Self>> {
+        let interval = config.heartbeat_interval.max(Duration::from_millis(1));
+        let election_ticks = (config.election_timeout.as_millis() / interval.as_millis()).max(2);
+        let state = log_store.load()?;
+        let applied = state_machine.applied_index()?;
+        // The state machine lost entries the log no longer holds, as installing a
+        // snapshot was cut short
+        if applied < state.snapshot.index {
+            return Err(StorageError::Cluster(format!(
+                "the storage of node {} is missing entries compacted from its log, remove \
+                 the node from the cluster and add it back with empty data",
+                config.node_id
+            )));
+        }
+        let raft = Raft::new(
+            config.node_id,
+            config.members.clone(),
+            u32::try_from(election_ticks).unwrap_or(u32::MAX),
+            state,
+            applied,
+        );
+        let (log_writer, batches) = mpsc::channel();
+        std::thread::spawn(move || write_log(log_store, &batches));
+
+        let node = Arc::new(Self {
+            id: config.node_id,
+            config,
+            raft: Mutex::new(raft),
+            log_writer,
+            state_machine,
+            transport,
+            waiters: Mutex::new(HashMap::new()),
+            apply_lock: tokio::sync::Mutex::new(()),
+            applied: watch::Sender::new(applied),
+            incoming_snapshot: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+            ticker: Mutex::new(None),
+        });
+
+        let ticker = tokio::spawn({
+            let node = node.clone();
+            async move {
+                // Entries committed before a restart but not applied yet
+                node.apply_committed().await;
+                loop {
+                    tokio::time::sleep(interval).await;
+                    let result = node.step(|raft| {
+                        raft.tick();
+                        Ok(())
+                    });
+                    if let Err(e) = result {
+                        log::error!("node {} failed to tick: {e}", node.id);
+                    }
+                }
+            }
+        });
+        *node.ticker.lock().unwrap() = Some(ticker);
+
+        Ok(node)
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn status(&self) -> ClusterStatus {
+        self.raft.lock().unwrap().status()
+    }
+
+    /// Stop the node. It no longer ticks, sends or answers requests.
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(ticker) = self.ticker.lock().unwrap().take() {
+            ticker.abort();
+        }
+    }
+
+    /// Handle a request from another node of the cluster.
+    pub async fn handle_request(
+        self: &Arc<Self>,
+        from: NodeId,
+        mut request: Request,
+    ) -> Result<Response> {
+        if self.stopped.load(Ordering::SeqCst) {
+            return Err(StorageError::Cluster(format!(
+                "node {} is stopped",
+                self.id
+            )));
+        }
+
+        // Raft checks who sends a chunk of a snapshot, and the chunks are collected here
+        let chunk = match &mut request {
+            Request::InstallSnapshot {
+                snapshot,
+                offset,
+                data,
+                done,
+                ..
+            } => Some((snapshot.clone(), *offset, std::mem::take(data), *done)),
+            _ => None,
+        };
+        let (response, durable) = self.step(|raft| Ok(raft.handle_request(from, request)))?;
+        // The response vouches for the vote and entries it records
+        durable
+            .await
+            .unwrap_or_else(|_| Err(self.writer_stopped()))?;
+
+        match (chunk, response) {
+            (
+                Some((snapshot, offset, data, done)),
+                Response::InstallSnapshot {
+                    term,
+                    success: true,
+                },
+            ) => {
+                let success = self
+                    .receive_snapshot(from, snapshot, offset, data, done)
+                    .await?;
+                Ok(Response::InstallSnapshot { term, success })
+            }
+            (_, response) => Ok(response),
+        }
+    }
+
+    /// Replicate `command` and wait until it is applied on this node, returning the
+    /// result of applying it.
+    pub async fn propose(self: &Arc<Self>, command: Command) -> Result<()> {
+        self.submit(command).await.map(|_| ())
+    }
+
+    /// Rebalance a table on every node, returning the outcome on this one.
+    pub async fn rebalance(
+        self: &Arc<Self>,
+        database: &str,
+        table: &str,
+        shards: Option<u32>,
+    ) -> Result<RebalanceSummary> {
+        let command = Command::Rebalance {
+            database: database.to_string(),
+            table: table.to_string(),
+            shards,
+        };
+        self.submit(command).await?.ok_or_else(|| {
+            StorageError::Cluster("the rebalance did not report its outcome".to_string())
+        })
+    }
+
+    async fn submit(self: &Arc<Self>, command: Command) -> Result<Applied> {
+        let (receiver, _) = self.step(|raft| {
+            let (index, term) = raft.propose(command)?;
+            let (sender, receiver) = oneshot::channel();
+            self.waiters.lock().unwrap().insert(index, (term, sender));
+            Ok(receiver)
+        })?;
+
+        match tokio::time::timeout(self.config.proposal_timeout, receiver).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(StorageError::Cluster(
+                "the write was dropped before it was applied".to_string(),
+            )),
+            Err(_) => Err(StorageError::Cluster(
+                "timed out waiting for the write to be committed".to_string(),
+            )),
+        }
+    }
+
+    /// Wait until this node, as the leader, has applied every write acknowledged so far,
+    /// so that reads of its storage see them.
+    pub async fn read_barrier(&self) -> Result<()> {
+        let index = self.raft.lock().unwrap().read_index()?;
+        let mut applied = self.applied.subscribe();
+        let caught_up = applied.wait_for(|applied| *applied >= index);
+        match tokio::time::timeout(self.config.proposal_timeout, caught_up).await {
+            Ok(result) => result.map(|_| ()).map_err(|_| {
+                StorageError::Cluster(format!("node {} stopped applying writes", self.id))
+            }),
+            Err(_) => Err(StorageError::Cluster(
+                "timed out waiting for committed writes to be applied".to_string(),
+            )),
+        }
+    }
+
+    /// Add or remove a node, returning once the change is applied.
+    pub async fn change_membership(
+        self: &Arc<Self>,
+        change: MembershipChange,
+    ) -> Result<ClusterStatus> {
+        let mut members = self.raft.lock().unwrap().members().to_vec();
+        match change {
+            MembershipChange::Add(member) => {
+                if members.iter().any(|m| m.id == member.id) {
+                    return Err(StorageError::Cluster(format!(
+                        "node {} is already a member of the cluster",
+                        member.id
+                    )));
+                }
+                members.push(member);
+            }
+            MembershipChange::Remove(id) => {
+                if !members.iter().any(|m| m.id == id) {
+                    return Err(StorageError::Cluster(format!(
+                        "node {id} is not a member of the cluster"
+                    )));
+                }
+                members.retain(|m| m.id != id);
+            }
+        }
+
+        self.propose(Command::ChangeMembership { members }).await?;
+        Ok(self.status())
+    }
+
+    /// Ask the leader to hand its role over to `target`, or to the follower that is
+    /// furthest ahead.
+    pub fn transfer_leadership(self: &Arc<Self>, target: Option<NodeId>) -> Result<NodeId> {
+        let (target, _) = self.step(|raft| raft.transfer_leadership(target))?;
+        Ok(target)
+    }
+
+    /// Run `step` on the Raft state machine and hand the log writes it queued to the log
+    /// writer. Once they are durable, the messages queued along with them are sent, and
+    /// newly committed entries are applied in the background. Returns what `step`
+    /// returned and a receiver of the outcome of the writes.
+    fn step<T>(
+        self: &Arc<Self>,
+        step: impl FnOnce(&mut Raft) -> Result<T>,
+    ) -> Result<(T, oneshot::Receiver<Result<()>>)> {
+        let (output, ready, written) = {
+            let mut raft = self.raft.lock().unwrap();
+            let output = step(&mut raft)?;
+            let mut ready = raft.take_ready();
+            let (sender, written) = oneshot::channel();
+            if !ready.is_empty() {
+                // Queued under the lock, so that the writer sees the writes in order
+                let writes = std::mem::take(&mut ready.writes);
+                self.log_writer
+                    .send((writes, sender))
+                    .map_err(|_| self.writer_stopped())?;
+            } else {
+                let _ = sender.send(Ok(()));
+            }
+            (output, ready, written)
+        };
+
+        let (sender, durable) = oneshot::channel();
+        let node = self.clone();
+        tokio::spawn(async move {
+            let result = written.await.unwrap_or_else(|_| Err(node.writer_stopped()));
+            match &result {
+                Ok(()) => {
+                    let Ready {
+                        messages,
+                        snapshots,
+                        last_entry: (index, term),
+                        ..
+                    } = ready;
+                    node.raft.lock().unwrap().persisted(index, term);
+                    node.send_messages(messages);
+                    for peer in snapshots {
+                        let leader = node.clone();
+                        tokio::spawn(async move { leader.send_snapshot(peer).await });
+                    }
+                    let applier = node.clone();
+                    tokio::spawn(async move { applier.apply_committed().await });
+                }
+                Err(e) => log::error!("node {} failed to write its log: {e}", node.id),
+            }
+            let _ = sender.send(result);
+        });
+        Ok((output, durable))
+    }
+
+    fn writer_stopped(&self) -> StorageError {
+        StorageError::Cluster(format!("the log writer of node {} stopped", self.id))
+    }
+
+    fn send_messages(self: &Arc<Self>, messages: Vec<(NodeId, Request)>) {
+        if self.stopped.load(Ordering::SeqCst) || messages.is_empty() {
+            return;
+        }
+
+        let messages: Vec<(Member, Request)> = {
+            let raft = self.raft.lock().unwrap();
+            messages
+                .into_iter()
+                .filter_map(|(to, request)| Some((raft.member(to)?.clone(), request)))
+                .collect()
+        };
+
+        for (to, request) in messages {
+            let node = self.clone();
+            tokio::spawn(async move {
+                let call = node.transport.call(node.id, &to, request);
+                let response = match tokio::time::timeout(node.config.election_timeout, call).await
+                {
+                    Ok(Ok(response)) => response,
+                    Ok(Err(e)) => {
+                        log::debug!("node {}: {e}", node.id);
+                        return;
+                    }
+                    Err(_) => return,
+                };
+                if node.stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                let result = node.step(|raft| {
+                    raft.handle_response(to.id, response);
+                    Ok(())
+                });
+                if let Err(e) = result {
+                    log::error!("node {} failed to handle a response: {e}", node.id);
+                }
+            });
+        }
+    }
+
+    /// Send `to` a snapshot of the state machine, as it needs entries compacted from the
+    /// log, and let Raft know when it is installed or the transfer failed.
+    async fn send_snapshot(self: &Arc<Self>, to: NodeId) {
+        let term = self.status().term;
+        if let Err(e) = self.transfer_snapshot(to, term).await {
+            log::warn!(
+                "node {} failed to send a snapshot to node {to}: {e}",
+                self.id
+            );
+            let result = self.step(|raft| {
+                raft.snapshot_failed(to, term);
+                Ok(())
+            });
+            if let Err(e) = result {
+                log::error!("node {} failed to handle a snapshot transfer: {e}", self.id);
+            }
+        }
+    }
+
+    async fn transfer_snapshot(self: &Arc<Self>, to: NodeId, term: u64) -> Result<()> {
+        let member = self
+            .raft
+            .lock()
+            .unwrap()
+            .member(to)
+            .cloned()
+            .ok_or_else(|| StorageError::Cluster(format!("node {to} is not a member")))?;
+
+        // Taken while no entry is being applied, so that it matches the applied index
+        let (snapshot, data) = {
+            let _lock = self.apply_lock.lock().await;
+            let applied = *self.applied.borrow();
+            let (leader_term, snapshot) = self.raft.lock().unwrap().snapshot_at(applied)?;
+            if leader_term != term {
+                return Err(StorageError::Cluster(
+                    "leadership changed before the snapshot was taken".to_string(),
+                ));
+            }
+            (snapshot, self.state_machine.export_snapshot().await?)
+        };
+        log::info!(
+            "node {} sends node {to} a snapshot of the log up to entry {}",
+            self.id,
+            snapshot.index
+        );
+
+        let mut offset = 0;
+        loop {
+            if self.stopped.load(Ordering::SeqCst) {
+                return Err(StorageError::Cluster(format!(
+                    "node {} is stopped",
+                    self.id
+                )));
+            }
+            let end = data.len().min(offset + SNAPSHOT_CHUNK_SIZE);
+            let request = Request::InstallSnapshot {
+                term,
+                snapshot: snapshot.clone(),
+                offset: offset as u64,
+                data: data[offset..end].to_vec(),
+                done: end == data.len(),
+            };
+            let call = self.transport.call(self.id, &member, request);
+            let response = tokio::time::timeout(self.config.proposal_timeout, call)
+                .await
+                .map_err(|_| {
+                    StorageError::Cluster(format!("node {to} did not install the snapshot in time"))
+                })??;
+            let success = matches!(response, Response::InstallSnapshot { success: true, .. });
+            // A follower of a later term makes this node step down
+            self.step(|raft| {
+                raft.handle_response(to, response);
+                Ok(())
+            })?;
+            if !success {
+                return Err(StorageError::Cluster(format!(
+                    "node {to} rejected the snapshot"
+                )));
+            }
+            if end == data.len() {
+                break;
+            }
+            offset = end;
+        }
+
+        self.step(|raft| {
+            raft.snapshot_sent(to, term, snapshot.index);
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Collect a chunk of a snapshot from the leader `from`, and install the snapshot
+    /// once it is complete. Returns false for a chunk that doesn't follow the ones
+    /// received, as a transfer starts over at offset zero.
+    async fn receive_snapshot(
+        self: &Arc<Self>,
+        from: NodeId,
+        snapshot: LogSnapshot,
+        offset: u64,
+        data: Vec<u8>,
+        done: bool,
+    ) -> Result<bool> {
+        let complete = {
+            let mut incoming = self.incoming_snapshot.lock().unwrap();
+            if offset == 0 {
+                *incoming = Some((from, snapshot.index, Vec::new()));
+            }
+            match incoming.as_mut() {
+                Some((sender, index, received))
+                    if *sender == from
+                        && *index == snapshot.index
+                        && received.len() as u64 == offset =>
+                {
+                    received.extend(data);
+                }
+                _ => return Ok(false),
+            }
+            if !done {
+                return Ok(true);
+            }
+            incoming
+                .take()
+                .map(|(.., received)| received)
+                .unwrap_or_default()
+        };
+
+        self.install_snapshot(snapshot, &complete).await?;
+        Ok(true)
+    }
+
+    /// Replace the state machine with a snapshot from the leader, and start the log
+    /// after the last entry it covers.
+    async fn install_snapshot(self: &Arc<Self>, snapshot: LogSnapshot, data: &[u8]) -> Result<()> {
+        let _lock = self.apply_lock.lock().await;
+        if snapshot.index <= *self.applied.borrow() {
+            return Ok(());
+        }
+
+        log::info!(
+            "node {} installs a snapshot of the log up to entry {}",
+            self.id,
+            snapshot.index
+        );
+        self.state_machine.install_snapshot(data).await?;
+        self.applied.send_replace(snapshot.index);
+        let (_, durable) = self.step(|raft| {
+            raft.restore(snapshot);
+            Ok(())
+        })?;
+        durable.await.unwrap_or_else(|_| Err(self.writer_stopped()))
+    }
+
+    async fn apply_committed(self: &Arc<Self>) {
+        let _lock = self.apply_lock.lock().await;
+        let entries = self.raft.lock().unwrap().take_committed();
+
+        for (index, entry) in entries {
+            // After a crash between the two, the entry is applied again on restart. Its
+            // command then leaves the data as it was, failing at worst, which is only
+            // logged as nobody waits for it
+            let result = self.apply(entry.command).await;
+            let recorded = self
+                .state_machine
+                .record_applied_index(index, Durability::Soft)
+                .await;
+            if let Err(e) = recorded {
+                log::error!("node {} failed to record applied entries: {e}", self.id);
+            }
+            self.applied.send_replace(index);
+
+            let waiter = self.waiters.lock().unwrap().remove(&index);
+            if let Some((term, sender)) = waiter {
+                // The proposal was replaced by another leader's entry
+                let result = if term == entry.term {
+                    result
+                } else {
+                    Err(StorageError::Cluster(
+                        "leadership changed before the write was committed".to_string(),
+                    ))
+                };
+                let _ = sender.send(result);
+            } else if let Err(e) = result {
+                log::debug!("node {} applied entry {index} with an error: {e}", self.id);
+            }
+        }
+
+        // Once the log holds twice the applied entries it keeps, the older half is
+        // compacted, after the state machine made sure it can't lose them
+        let keep = self.config.snapshot_threshold.max(1);
+        let applied = *self.applied.borrow();
+        let compacted = self.raft.lock().unwrap().snapshot_index();
+        if applied.saturating_sub(compacted) < keep.saturating_mul(2) {
+            return;
+        }
+        let synced = self
+            .state_machine
+            .record_applied_index(applied, Durability::Hard)
+            .await;
+        if let Err(e) = synced {
+            log::error!("node {} failed to sync applied entries: {e}", self.id);
+            return;
+        }
+        let result = self.step(|raft| {
+            raft.compact(applied - keep);
+            Ok(())
+        });
+        if let Err(e) = result {
+            log::error!("node {} failed to compact its log: {e}", self.id);
+        }
+    }
+
+    async fn apply(&self, command: Command) -> Result<Applied> {
+        let storage = &self.state_machine;
+        let result = match command {
+            Command::Noop | Command::ChangeMembership { .. } => Ok(()),
+            Command::CreateDatabase { name } => storage.create_database(&name).await,
+            Command::DropDatabase { name } => storage.drop_database(&name).await,
+            Command::CreateTable {
+                database,
+                table,
+                config,
+                shards,
+            } => {
+                storage
+                    .create_sharded_table(&database, &table, &config, shards)
+                    .await
+            }
+            Command::DropTable { database, table } => storage.drop_table(&database, &table).await,
+            Command::Rebalance {
+                database,
+                table,
+                shards,
+            } => {
+                return storage
+                    .rebalance_table(&database, &table, shards)
+                    .await
+                    .map(Some);
+            }
+            Command::CreateIndex {
+                database,
+                table,
+                definition,
+            } => storage.create_index(&database, &table, &definition).await,
+            Command::DropIndex {
+                database,
+                table,
+                name,
+            } => storage.drop_index(&database, &table, &name).await,
+            Command::Put {
+                database,
+                table,
+                documents,
+                durability,
+            } => {
+                storage
+                    .put_batch(&database, &table, &documents, durability)
+                    .await
+            }
+            Command::Delete {
+                database,
+                table,
+                keys,
+                durability,
+            } => {
+                storage
+                    .delete_batch(&database, &table, &keys, durability)
+                    .await
+            }
+        };
+        result.map(|()| None)
+    }
+}
+
+/// Write batches of log writes in the order they come, until the node is dropped.
+fn write_log(mut store: Box<dyn LogStore>, batches: &mpsc::Receiver<LogBatch>) {
+    let mut failed = false;
+    while let Ok((writes, written)) = batches.recv() {
+        // The log no longer holds what the node thinks it does after a failed write, so
+        // none of the later writes may be reported durable
+        let result = if failed {
+            Err(StorageError::Cluster(
+                "an earlier write to the log failed".to_string(),
+            ))
+        } else {
+            writes.iter().try_for_each(|write| store.write(write))
+        };
+        failed |= result.is_err();
+        let _ = written.send(result);
+    }
+}
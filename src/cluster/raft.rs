@@ -0,0 +1,953 @@
+//! The Raft consensus algorithm, without any I/O.
+//!
+//! `Raft` holds a node's share of the replicated log and reacts to ticks of a logical
+//! clock, to requests from its peers and to their responses. Changes to its log and vote
+//! and the messages it wants to send are queued for the caller to drain: the caller
+//! makes the changes durable first, as the messages may promise what they record, and
+//! reports back how far the log is durable. Committed entries that are durable are
+//! handed out for the caller to apply to its state machine. Membership changes add or
+//! remove one node at a time and take effect as soon as their entry is appended.
+//!
+//! Once the state machine holds applied entries durably, the log can be compacted up
+//! to them. Followers that need compacted entries are named for the caller to send a
+//! snapshot of its state machine, and a follower restores its log from the snapshot
+//! it installed.
+
+use super::log_store::{LogSnapshot, LogWrite, PersistentState};
+use super::{ClusterStatus, Command, Entry, Member, NodeId, Role};
+use crate::storage::{Result, StorageError};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+
+/// Maximum number of entries sent in a single append request.
+const MAX_ENTRIES_PER_APPEND: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    Vote {
+        term: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+        /// Whether the election was started by a leadership transfer, which lets the
+        /// candidate unseat a leader its voters still hear from.
+        transfer: bool,
+    },
+    Append {
+        term: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry>,
+        leader_commit: u64,
+    },
+    /// Ask a caught-up follower to start an election right away.
+    TimeoutNow { term: u64 },
+    /// A chunk of a snapshot of the leader's state machine, starting `offset` bytes into
+    /// it, for a follower that needs entries the leader compacted.
+    InstallSnapshot {
+        term: u64,
+        snapshot: LogSnapshot,
+        offset: u64,
+        data: Vec<u8>,
+        done: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    Append {
+        term: u64,
+        success: bool,
+        /// Last index known to match the leader's log on success, or the index to
+        /// retry from minus one on failure.
+        match_index: u64,
+    },
+    TimeoutNow {
+        term: u64,
+    },
+    InstallSnapshot {
+        term: u64,
+        success: bool,
+    },
+}
+
+impl Response {
+    fn term(&self) -> u64 {
+        match self {
+            Self::Vote { term, .. }
+            | Self::Append { term, .. }
+            | Self::TimeoutNow { term }
+            | Self::InstallSnapshot { term, .. } => *term,
+        }
+    }
+}
+
+/// Log writes and messages queued by a node since they were last taken.
+#[derive(Debug, Default)]
+pub struct Ready {
+    pub writes: Vec<LogWrite>,
+    pub messages: Vec<(NodeId, Request)>,
+    /// Followers to send a snapshot of the state machine, as the entries they need next
+    /// were compacted.
+    pub snapshots: Vec<NodeId>,
+    /// Index and term of the last entry of the log the writes leave.
+    pub last_entry: (u64, u64),
+}
+
+impl Ready {
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty() && self.messages.is_empty() && self.snapshots.is_empty()
+    }
+}
+
+/// Replication progress of a follower, as tracked by the leader.
+#[derive(Debug, Clone, Copy)]
+struct Progress {
+    next_index: u64,
+    match_index: u64,
+    /// Whether the follower is being sent a snapshot, during which it only gets
+    /// heartbeats.
+    sending_snapshot: bool,
+}
+
+pub struct Raft {
+    id: NodeId,
+    term: u64,
+    voted_for: Option<NodeId>,
+    /// Where the log starts, as the entries up to it were compacted.
+    snapshot: LogSnapshot,
+    /// The entries following the snapshot.
+    log: Vec<Entry>,
+    /// Index of the last entry known to be durable.
+    persisted: u64,
+    commit_index: u64,
+    last_applied: u64,
+    role: Role,
+    leader: Option<NodeId>,
+    initial_members: Vec<Member>,
+    members: Vec<Member>,
+    /// Index of the entry the current membership comes from, or 0 for the initial one.
+    config_index: u64,
+    election_ticks: u32,
+    election_elapsed: u32,
+    election_timeout: u32,
+    votes: HashSet<NodeId>,
+    progress: HashMap<NodeId, Progress>,
+    /// Followers heard from since the leader last checked it still has a quorum.
+    active: HashSet<NodeId>,
+    /// Index of the entry the leader appended when it was elected.
+    term_start: u64,
+    transfer_target: Option<NodeId>,
+    writes: Vec<LogWrite>,
+    outbox: Vec<(NodeId, Request)>,
+    snapshot_requests: Vec<NodeId>,
+}
+
+impl Raft {
+    /// Restore a node from the state loaded from its log store, with its state machine
+    /// holding every entry up to `applied`, which is at least the snapshot's last one.
+    /// `initial_members` is the membership the cluster was bootstrapped with, which
+    /// applies until the log or its snapshot holds a change.
+    pub fn new(
+        id: NodeId,
+        initial_members: Vec<Member>,
+        election_ticks: u32,
+        state: PersistentState,
+        applied: u64,
+    ) -> Self {
+        let mut raft = Self {
+            id,
+            term: state.term,
+            voted_for: state.voted_for,
+            persisted: state.snapshot.index + state.entries.len() as u64,
+            snapshot: state.snapshot,
+            log: state.entries,
+            commit_index: applied,
+            last_applied: applied,
+            role: Role::Follower,
+            leader: None,
+            initial_members: initial_members.clone(),
+            members: initial_members,
+            config_index: 0,
+            election_ticks: election_ticks.max(1),
+            election_elapsed: 0,
+            election_timeout: 0,
+            votes: HashSet::new(),
+            progress: HashMap::new(),
+            active: HashSet::new(),
+            term_start: 0,
+            transfer_target: None,
+            writes: Vec::new(),
+            outbox: Vec::new(),
+            snapshot_requests: Vec::new(),
+        };
+        raft.reload_membership();
+        raft.reset_election_timeout();
+        raft
+    }
+
+    pub fn leader(&self) -> Option<&Member> {
+        self.leader.and_then(|leader| self.member(leader))
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    pub fn member(&self, id: NodeId) -> Option<&Member> {
+        self.members.iter().find(|member| member.id == id)
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.snapshot.index + self.log.len() as u64
+    }
+
+    /// Index of the last entry compacted into a snapshot, or 0 if none was.
+    pub fn snapshot_index(&self) -> u64 {
+        self.snapshot.index
+    }
+
+    pub fn status(&self) -> ClusterStatus {
+        ClusterStatus {
+            node_id: self.id,
+            role: self.role,
+            term: self.term,
+            leader: self.leader().cloned(),
+            commit_index: self.commit_index,
+            applied_index: self.last_applied,
+            members: self.members.clone(),
+        }
+    }
+
+    /// Log writes and messages queued since the last call. The writes must be durable
+    /// before the messages are sent.
+    pub fn take_ready(&mut self) -> Ready {
+        Ready {
+            writes: std::mem::take(&mut self.writes),
+            messages: std::mem::take(&mut self.outbox),
+            snapshots: std::mem::take(&mut self.snapshot_requests),
+            last_entry: (self.last_index(), self.last_term()),
+        }
+    }
+
+    /// Record that the log is durable up to `index`, whose entry is of `term`. Ignored
+    /// if that entry was replaced since.
+    pub fn persisted(&mut self, index: u64, term: u64) {
+        if index <= self.persisted || index > self.last_index() || self.term_at(index) != term {
+            return;
+        }
+        self.persisted = index;
+        if self.role == Role::Leader {
+            self.maybe_commit();
+        }
+    }
+
+    /// Committed entries that are durable and were not handed out yet, with their
+    /// indexes.
+    pub fn take_committed(&mut self) -> Vec<(u64, Entry)> {
+        let end = self.commit_index.min(self.persisted);
+        let entries = (self.last_applied + 1..=end)
+            .map(|index| (index, self.entry(index).clone()))
+            .collect();
+        self.last_applied = self.last_applied.max(end);
+        entries
+    }
+
+    /// Drop the entries up to `index` from the log, which the state machine must hold
+    /// durably, and start the log with a snapshot of it instead. Ignored for entries
+    /// that were not handed out yet.
+    pub fn compact(&mut self, index: u64) {
+        if index <= self.snapshot.index || index > self.last_applied.min(self.persisted) {
+            return;
+        }
+        let snapshot = LogSnapshot {
+            index,
+            term: self.term_at(index),
+            members: self.membership_at(index),
+        };
+        self.log.drain(..(index - self.snapshot.index) as usize);
+        self.writes.push(LogWrite::Snapshot {
+            snapshot: snapshot.clone(),
+            through: index,
+        });
+        self.snapshot = snapshot;
+    }
+
+    /// The leader's term and the point of its log covered by a snapshot of its state
+    /// machine taken with the entries up to `index` applied, for a follower.
+    pub fn snapshot_at(&self, index: u64) -> Result<(u64, LogSnapshot)> {
+        if self.role != Role::Leader {
+            return Err(self.not_leader());
+        }
+        if index < self.snapshot.index || index > self.last_applied {
+            return Err(StorageError::Cluster(format!(
+                "entry {index} was compacted or is not applied yet"
+            )));
+        }
+        let snapshot = LogSnapshot {
+            index,
+            term: self.term_at(index),
+            members: self.membership_at(index),
+        };
+        Ok((self.term, snapshot))
+    }
+
+    /// Record that `peer` installed a snapshot covering the log up to `index`, which
+    /// this node sent it as the leader of `term`.
+    pub fn snapshot_sent(&mut self, peer: NodeId, term: u64, index: u64) {
+        if self.role != Role::Leader || term != self.term {
+            return;
+        }
+        let Some(progress) = self.progress.get_mut(&peer) else {
+            return;
+        };
+        progress.sending_snapshot = false;
+        progress.match_index = progress.match_index.max(index);
+        progress.next_index = progress.next_index.max(index + 1);
+        self.active.insert(peer);
+        self.maybe_commit();
+        self.send_append(peer);
+    }
+
+    /// Record that sending `peer` a snapshot as the leader of `term` failed, for a
+    /// later heartbeat to try again.
+    pub fn snapshot_failed(&mut self, peer: NodeId, term: u64) {
+        if term != self.term {
+            return;
+        }
+        if let Some(progress) = self.progress.get_mut(&peer) {
+            progress.sending_snapshot = false;
+        }
+    }
+
+    /// Start the log after a snapshot the state machine installed, keeping the entries
+    /// that follow it if the log holds the last entry it covers. Ignored for a snapshot
+    /// the state machine is already past.
+    pub fn restore(&mut self, snapshot: LogSnapshot) {
+        if snapshot.index <= self.last_applied {
+            return;
+        }
+        let last_index = self.last_index();
+        let through =
+            if snapshot.index <= last_index && self.term_at(snapshot.index) == snapshot.term {
+                self.log
+                    .drain(..(snapshot.index - self.snapshot.index) as usize);
+                self.persisted = self.persisted.max(snapshot.index);
+                snapshot.index
+            } else {
+                self.log.clear();
+                self.persisted = snapshot.index;
+                last_index.max(snapshot.index)
+            };
+        self.writes.push(LogWrite::Snapshot {
+            snapshot: snapshot.clone(),
+            through,
+        });
+        self.commit_index = self.commit_index.max(snapshot.index);
+        self.last_applied = snapshot.index;
+        self.snapshot = snapshot;
+        self.reload_membership();
+    }
+
+    /// Advance the logical clock by one tick.
+    pub fn tick(&mut self) {
+        self.election_elapsed += 1;
+
+        if self.role == Role::Leader {
+            if self.election_elapsed >= self.election_ticks {
+                self.election_elapsed = 0;
+                self.transfer_target = None;
+                // Step down if cut off from a majority, so clients look for a leader
+                // that can still commit their writes
+                let mut active = std::mem::take(&mut self.active);
+                active.insert(self.id);
+                if !self.is_quorum(&active) {
+                    log::warn!("node {} lost contact with a quorum, stepping down", self.id);
+                    self.become_follower(self.term, None);
+                    return;
+                }
+            }
+            self.broadcast_append();
+        } else if self.election_elapsed >= self.election_timeout && self.is_voter() {
+            self.campaign(false);
+        }
+    }
+
+    /// Index up to which a leader must have applied the log for reads to see every write
+    /// acknowledged so far: its commit index, or the entry it appended when it was
+    /// elected until that one commits, which commits the entries of earlier terms too.
+    pub fn read_index(&self) -> Result<u64> {
+        if self.role != Role::Leader {
+            return Err(self.not_leader());
+        }
+        Ok(self.commit_index.max(self.term_start))
+    }
+
+    /// Append a command to the log, returning its index and term. Only the leader
+    /// accepts proposals.
+    pub fn propose(&mut self, command: Command) -> Result<(u64, u64)> {
+        if self.role != Role::Leader {
+            return Err(self.not_leader());
+        }
+        if self.transfer_target.is_some() {
+            return Err(StorageError::Cluster(
+                "leadership is being transferred, retry the write shortly".to_string(),
+            ));
+        }
+
+        if let Command::ChangeMembership { members } = &command {
+            if self.config_index > self.commit_index {
+                return Err(StorageError::Cluster(
+                    "another membership change is in progress".to_string(),
+                ));
+            }
+            let old: HashSet<NodeId> = self.members.iter().map(|m| m.id).collect();
+            let new: HashSet<NodeId> = members.iter().map(|m| m.id).collect();
+            if new.is_empty() || old.symmetric_difference(&new).count() > 1 {
+                return Err(StorageError::Cluster(
+                    "membership changes must add or remove a single node".to_string(),
+                ));
+            }
+        }
+
+        let index = self.last_index() + 1;
+        self.append_entries(
+            index,
+            vec![Entry {
+                term: self.term,
+                command,
+            }],
+        );
+        self.maybe_commit();
+        self.broadcast_append();
+        Ok((index, self.term))
+    }
+
+    /// Hand leadership over to `target`, or to the most up-to-date follower.
+    pub fn transfer_leadership(&mut self, target: Option<NodeId>) -> Result<NodeId> {
+        if self.role != Role::Leader {
+            return Err(self.not_leader());
+        }
+
+        let target = match target {
+            Some(target) if target == self.id => return Ok(target),
+            Some(target) if self.progress.contains_key(&target) => target,
+            Some(target) => {
+                return Err(StorageError::Cluster(format!(
+                    "node {target} is not a member of the cluster"
+                )));
+            }
+            None => self
+                .progress
+                .iter()
+                .max_by_key(|(id, progress)| (progress.match_index, std::cmp::Reverse(**id)))
+                .map(|(id, _)| *id)
+                .ok_or_else(|| {
+                    StorageError::Cluster("there is no other node to transfer to".to_string())
+                })?,
+        };
+
+        self.transfer_target = Some(target);
+        self.election_elapsed = 0;
+        if self.progress[&target].match_index == self.last_index() {
+            self.outbox
+                .push((target, Request::TimeoutNow { term: self.term }));
+        } else {
+            self.send_append(target);
+        }
+        Ok(target)
+    }
+
+    /// Handle a request from a peer and build the response to send back once the log
+    /// writes it queued are durable.
+    pub fn handle_request(&mut self, from: NodeId, request: Request) -> Response {
+        match request {
+            Request::Vote {
+                term,
+                last_log_index,
+                last_log_term,
+                transfer,
+            } => {
+                // Ignore candidates while a leader is known to be alive, so nodes that
+                // were removed or partitioned can't disrupt the cluster
+                let leader_alive = self.leader.is_some()
+                    && (self.role == Role::Leader || self.election_elapsed < self.election_ticks);
+                if term > self.term && leader_alive && !transfer {
+                    return Response::Vote {
+                        term: self.term,
+                        granted: false,
+                    };
+                }
+
+                if term > self.term {
+                    self.become_follower(term, None);
+                }
+                let up_to_date =
+                    (last_log_term, last_log_index) >= (self.last_term(), self.last_index());
+                let granted = term == self.term
+                    && up_to_date
+                    && self.voted_for.is_none_or(|voted_for| voted_for == from);
+                if granted && self.voted_for.is_none() {
+                    self.writes.push(LogWrite::HardState {
+                        term: self.term,
+                        voted_for: Some(from),
+                    });
+                    self.voted_for = Some(from);
+                }
+                if granted {
+                    self.election_elapsed = 0;
+                }
+
+                Response::Vote {
+                    term: self.term,
+                    granted,
+                }
+            }
+            Request::Append {
+                term,
+                mut prev_log_index,
+                mut prev_log_term,
+                mut entries,
+                leader_commit,
+            } => {
+                if term < self.term {
+                    return Response::Append {
+                        term: self.term,
+                        success: false,
+                        match_index: 0,
+                    };
+                }
+                if term > self.term || self.role != Role::Follower {
+                    self.become_follower(term, Some(from));
+                }
+                self.leader = Some(from);
+                self.election_elapsed = 0;
+
+                // The entries up to the snapshot are committed, so they match the leader's
+                if prev_log_index < self.snapshot.index {
+                    let compacted = (self.snapshot.index - prev_log_index) as usize;
+                    entries.drain(..compacted.min(entries.len()));
+                    prev_log_index = self.snapshot.index;
+                    prev_log_term = self.snapshot.term;
+                }
+                if prev_log_index > self.last_index() {
+                    return Response::Append {
+                        term: self.term,
+                        success: false,
+                        match_index: self.last_index(),
+                    };
+                }
+                if self.term_at(prev_log_index) != prev_log_term {
+                    // Skip back over the whole conflicting term at once
+                    let conflict_term = self.term_at(prev_log_index);
+                    let mut index = prev_log_index;
+                    while index > self.commit_index && self.term_at(index) == conflict_term {
+                        index -= 1;
+                    }
+                    return Response::Append {
+                        term: self.term,
+                        success: false,
+                        match_index: index,
+                    };
+                }
+
+                let match_index = prev_log_index + entries.len() as u64;
+                // Entries that are already in the log are kept, so a delayed request
+                // can't truncate entries appended after it was sent
+                let new_entries = entries
+                    .iter()
+                    .enumerate()
+                    .find(|(i, entry)| {
+                        let index = prev_log_index + 1 + *i as u64;
+                        index > self.last_index() || self.term_at(index) != entry.term
+                    })
+                    .map(|(i, _)| i);
+                if let Some(first) = new_entries {
+                    let index = prev_log_index + 1 + first as u64;
+                    self.append_entries(index, entries[first..].to_vec());
+                }
+
+                self.commit_index = self.commit_index.max(leader_commit.min(match_index));
+                Response::Append {
+                    term: self.term,
+                    success: true,
+                    match_index,
+                }
+            }
+            Request::TimeoutNow { term } => {
+                if term == self.term && self.is_voter() {
+                    self.campaign(true);
+                }
+                Response::TimeoutNow { term: self.term }
+            }
+            Request::InstallSnapshot { term, .. } => {
+                if term < self.term {
+                    return Response::InstallSnapshot {
+                        term: self.term,
+                        success: false,
+                    };
+                }
+                if term > self.term || self.role != Role::Follower {
+                    self.become_follower(term, Some(from));
+                }
+                self.leader = Some(from);
+                self.election_elapsed = 0;
+
+                // The caller collects the chunks, and restores the log once the state
+                // machine installed the whole snapshot
+                Response::InstallSnapshot {
+                    term: self.term,
+                    success: true,
+                }
+            }
+        }
+    }
+
+    /// Handle a peer's response to a request this node sent it.
+    pub fn handle_response(&mut self, from: NodeId, response: Response) {
+        if response.term() > self.term {
+            return self.become_follower(response.term(), None);
+        }
+        if response.term() < self.term {
+            return;
+        }
+
+        match response {
+            Response::Vote { granted, .. } => {
+                if self.role == Role::Candidate && granted {
+                    self.votes.insert(from);
+                    if self.is_quorum(&self.votes) {
+                        self.become_leader();
+                    }
+                }
+            }
+            Response::Append {
+                success,
+                match_index,
+                ..
+            } => {
+                if self.role != Role::Leader {
+                    return;
+                }
+                self.active.insert(from);
+                let last_index = self.last_index();
+                let Some(progress) = self.progress.get_mut(&from) else {
+                    return;
+                };
+
+                if success {
+                    progress.match_index = progress.match_index.max(match_index);
+                    progress.next_index = progress.next_index.max(match_index + 1);
+                    let caught_up = progress.match_index == last_index;
+                    self.maybe_commit();
+
+                    if self.transfer_target == Some(from) && caught_up {
+                        self.outbox
+                            .push((from, Request::TimeoutNow { term: self.term }));
+                    } else if !caught_up && self.progress.contains_key(&from) {
+                        self.send_append(from);
+                    }
+                } else {
+                    // Heartbeats to a follower waiting for a snapshot fail until it has it
+                    if progress.sending_snapshot {
+                        return;
+                    }
+                    progress.next_index = (progress.next_index - 1)
+                        .min(match_index + 1)
+                        .max(progress.match_index + 1);
+                    self.send_append(from);
+                }
+            }
+            Response::TimeoutNow { .. } | Response::InstallSnapshot { .. } => {}
+        }
+    }
+
+    fn not_leader(&self) -> StorageError {
+        StorageError::NotLeader(self.leader().map(|leader| leader.address.clone()))
+    }
+
+    fn is_voter(&self) -> bool {
+        self.member(self.id).is_some()
+    }
+
+    fn is_quorum(&self, nodes: &HashSet<NodeId>) -> bool {
+        let votes = self
+            .members
+            .iter()
+            .filter(|m| nodes.contains(&m.id))
+            .count();
+        votes > self.members.len() / 2
+    }
+
+    fn last_term(&self) -> u64 {
+        self.term_at(self.last_index())
+    }
+
+    /// Term of the entry at `index`, or 0 if the log doesn't hold it or it was compacted
+    /// before the snapshot's last entry.
+    fn term_at(&self, index: u64) -> u64 {
+        match index.cmp(&self.snapshot.index) {
+            Ordering::Less => 0,
+            Ordering::Equal => self.snapshot.term,
+            Ordering::Greater => self
+                .log
+                .get((index - self.snapshot.index - 1) as usize)
+                .map_or(0, |entry| entry.term),
+        }
+    }
+
+    /// The entry at `index`, which must follow the snapshot.
+    fn entry(&self, index: u64) -> &Entry {
+        &self.log[(index - self.snapshot.index - 1) as usize]
+    }
+
+    fn reset_election_timeout(&mut self) {
+        let jitter = std::collections::hash_map::RandomState::new().hash_one(self.id);
+        self.election_timeout =
+            self.election_ticks + (jitter % u64::from(self.election_ticks)) as u32;
+    }
+
+    fn campaign(&mut self, transfer: bool) {
+        let term = self.term + 1;
+        self.writes.push(LogWrite::HardState {
+            term,
+            voted_for: Some(self.id),
+        });
+        self.term = term;
+        self.voted_for = Some(self.id);
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.election_elapsed = 0;
+        self.reset_election_timeout();
+        self.votes = HashSet::from([self.id]);
+        log::info!("node {} starts an election for term {term}", self.id);
+
+        if self.is_quorum(&self.votes) {
+            return self.become_leader();
+        }
+
+        let request = Request::Vote {
+            term,
+            last_log_index: self.last_index(),
+            last_log_term: self.last_term(),
+            transfer,
+        };
+        for member in &self.members {
+            if member.id != self.id {
+                self.outbox.push((member.id, request.clone()));
+            }
+        }
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<NodeId>) {
+        if term > self.term {
+            self.writes.push(LogWrite::HardState {
+                term,
+                voted_for: None,
+            });
+            self.term = term;
+            self.voted_for = None;
+        }
+        self.role = Role::Follower;
+        self.leader = leader;
+        self.votes.clear();
+        self.progress.clear();
+        self.active.clear();
+        self.transfer_target = None;
+        self.election_elapsed = 0;
+        self.reset_election_timeout();
+    }
+
+    fn become_leader(&mut self) {
+        log::info!("node {} is the leader for term {}", self.id, self.term);
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        self.election_elapsed = 0;
+        self.active.clear();
+        self.progress.clear();
+        self.sync_progress();
+
+        // Entries of earlier terms only commit along with one of the leader's own
+        let index = self.last_index() + 1;
+        self.term_start = index;
+        self.append_entries(
+            index,
+            vec![Entry {
+                term: self.term,
+                command: Command::Noop,
+            }],
+        );
+        self.maybe_commit();
+        self.broadcast_append();
+    }
+
+    /// Track the progress of every member but the leader itself.
+    fn sync_progress(&mut self) {
+        let next_index = self.last_index() + 1;
+        let members: HashSet<NodeId> = self.members.iter().map(|m| m.id).collect();
+        self.progress.retain(|id, _| members.contains(id));
+        for id in members {
+            if id != self.id {
+                self.progress.entry(id).or_insert(Progress {
+                    next_index,
+                    match_index: 0,
+                    sending_snapshot: false,
+                });
+            }
+        }
+    }
+
+    /// Replace the log from `index` on with `entries`.
+    fn append_entries(&mut self, index: u64, entries: Vec<Entry>) {
+        debug_assert!(
+            index > self.commit_index,
+            "committed entries are never replaced"
+        );
+        self.writes.push(LogWrite::Entries {
+            index,
+            entries: entries.clone(),
+            last_index: self.last_index(),
+        });
+
+        let truncated = index <= self.last_index();
+        if truncated {
+            self.persisted = self.persisted.min(index - 1);
+        }
+        self.log
+            .truncate((index - self.snapshot.index - 1) as usize);
+        self.log.extend(entries);
+
+        if truncated && self.config_index >= index {
+            self.reload_membership();
+        } else if let Some((config_index, members)) = self.latest_membership(index) {
+            self.config_index = config_index;
+            self.members = members;
+        }
+
+        if self.role == Role::Leader {
+            self.sync_progress();
+        }
+    }
+
+    /// Take the membership from the latest change in the log.
+    fn reload_membership(&mut self) {
+        (self.config_index, self.members) = self
+            .latest_membership(self.snapshot.index + 1)
+            .unwrap_or_else(|| self.base_membership());
+    }
+
+    /// The latest membership change in the log at or after index `from`.
+    fn latest_membership(&self, from: u64) -> Option<(u64, Vec<Member>)> {
+        let offset = self.snapshot.index;
+        self.log
+            .iter()
+            .enumerate()
+            .skip((from - offset - 1) as usize)
+            .rev()
+            .find_map(|(i, entry)| match &entry.command {
+                Command::ChangeMembership { members } => {
+                    Some((offset + i as u64 + 1, members.clone()))
+                }
+                _ => None,
+            })
+    }
+
+    /// The membership as of the entry at `index`, which must not precede the snapshot.
+    fn membership_at(&self, index: u64) -> Vec<Member> {
+        self.log[..(index - self.snapshot.index) as usize]
+            .iter()
+            .rev()
+            .find_map(|entry| match &entry.command {
+                Command::ChangeMembership { members } => Some(members.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| self.base_membership().1)
+    }
+
+    /// The membership the log starts with, and the index of the entry it comes from:
+    /// the snapshot's, or the one the cluster was bootstrapped with.
+    fn base_membership(&self) -> (u64, Vec<Member>) {
+        if self.snapshot.index == 0 {
+            (0, self.initial_members.clone())
+        } else {
+            (self.snapshot.index, self.snapshot.members.clone())
+        }
+    }
+
+    fn maybe_commit(&mut self) {
+        // The leader counts only the entries it has made durable itself
+        let mut matched: Vec<u64> = self
+            .members
+            .iter()
+            .map(|member| match self.progress.get(&member.id) {
+                Some(progress) => progress.match_index,
+                None if member.id == self.id => self.persisted,
+                None => 0,
+            })
+            .collect();
+        if matched.is_empty() {
+            return;
+        }
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let quorum_index = matched[self.members.len() / 2];
+
+        if quorum_index > self.commit_index && self.term_at(quorum_index) == self.term {
+            self.commit_index = quorum_index;
+            // A leader that removed itself leaves once the removal is committed
+            if self.config_index <= self.commit_index && !self.is_voter() {
+                log::info!("node {} was removed from the cluster", self.id);
+                self.become_follower(self.term, None);
+            }
+        }
+    }
+
+    fn broadcast_append(&mut self) {
+        let peers: Vec<NodeId> = self.progress.keys().copied().collect();
+        for peer in peers {
+            self.send_append(peer);
+        }
+    }
+
+    fn send_append(&mut self, peer: NodeId) {
+        let Some(progress) = self.progress.get_mut(&peer) else {
+            return;
+        };
+        let prev_log_index = progress.next_index - 1;
+
+        // The follower needs entries that were compacted, so it is sent a snapshot, and
+        // heartbeats that keep it from starting an election meanwhile
+        if prev_log_index < self.snapshot.index {
+            if !progress.sending_snapshot {
+                progress.sending_snapshot = true;
+                self.snapshot_requests.push(peer);
+            }
+            let heartbeat = Request::Append {
+                term: self.term,
+                prev_log_index: self.snapshot.index,
+                prev_log_term: self.snapshot.term,
+                entries: Vec::new(),
+                leader_commit: self.commit_index,
+            };
+            self.outbox.push((peer, heartbeat));
+            return;
+        }
+
+        let end = self
+            .last_index()
+            .min(prev_log_index + MAX_ENTRIES_PER_APPEND as u64);
+        let offset = self.snapshot.index;
+        let request = Request::Append {
+            term: self.term,
+            prev_log_index,
+            prev_log_term: self.term_at(prev_log_index),
+            entries: self.log[(prev_log_index - offset) as usize..(end - offset) as usize].to_vec(),
+            leader_commit: self.commit_index,
+        };
+        self.outbox.push((peer, request));
+    }
+}
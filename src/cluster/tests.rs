@@ -0,0 +1,807 @@
+use super::*;
+use crate::ast::{Datum, datum};
+use crate::storage::{Config, DefaultStorage};
+use std::collections::HashMap;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn member(id: NodeId) -> Member {
+    Member {
+        id,
+        address: format!("node-{id}"),
+    }
+}
+
+fn doc(name: &str) -> Document {
+    Document::from([(
+        "name".to_string(),
+        Datum {
+            value: Some(datum::Value::String(name.to_string())),
+        },
+    )])
+}
+
+struct TestNode {
+    node: Arc<ClusterNode>,
+    storage: ClusterStorage,
+    local: Arc<dyn StorageBackend>,
+    log_store: MemoryLogStore,
+    _data_dir: TempDir,
+}
+
+struct TestCluster {
+    transport: Arc<LocalTransport>,
+    nodes: HashMap<NodeId, TestNode>,
+    snapshot_threshold: u64,
+}
+
+impl TestCluster {
+    fn new() -> Self {
+        Self {
+            transport: Arc::new(LocalTransport::new()),
+            nodes: HashMap::new(),
+            snapshot_threshold: NodeConfig::default().snapshot_threshold,
+        }
+    }
+
+    /// Start a cluster bootstrapped with nodes `1..=size`.
+    fn start(size: NodeId) -> Self {
+        Self::start_with(size, NodeConfig::default().snapshot_threshold)
+    }
+
+    /// Start a cluster bootstrapped with nodes `1..=size`, whose logs keep
+    /// `snapshot_threshold` applied entries.
+    fn start_with(size: NodeId, snapshot_threshold: u64) -> Self {
+        let mut cluster = Self::new();
+        cluster.snapshot_threshold = snapshot_threshold;
+        let members: Vec<Member> = (1..=size).map(member).collect();
+        for id in 1..=size {
+            cluster.add_node(
+                id,
+                members.clone(),
+                MemoryLogStore::new(),
+                TempDir::new().unwrap(),
+            );
+        }
+        cluster
+    }
+
+    fn add_node(
+        &mut self,
+        id: NodeId,
+        members: Vec<Member>,
+        log_store: MemoryLogStore,
+        data_dir: TempDir,
+    ) {
+        let local: Arc<dyn StorageBackend> = Arc::new(
+            DefaultStorage::open(&Config {
+                data_dir: data_dir.path().to_string_lossy().to_string(),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let config = NodeConfig {
+            node_id: id,
+            members,
+            heartbeat_interval: Duration::from_millis(10),
+            election_timeout: Duration::from_millis(100),
+            proposal_timeout: Duration::from_secs(5),
+            snapshot_threshold: self.snapshot_threshold,
+        };
+        let node = ClusterNode::start(
+            config,
+            Box::new(log_store.clone()),
+            local.clone(),
+            self.transport.clone(),
+        )
+        .unwrap();
+        self.transport.register(&node);
+
+        let storage = ClusterStorage::new(node.clone(), local.clone());
+        let test_node = TestNode {
+            node,
+            storage,
+            local,
+            log_store,
+            _data_dir: data_dir,
+        };
+        self.nodes.insert(id, test_node);
+    }
+
+    fn stop_node(&mut self, id: NodeId) -> TestNode {
+        let node = self.nodes.remove(&id).unwrap();
+        node.node.shutdown();
+        node
+    }
+
+    fn storage(&self, id: NodeId) -> &ClusterStorage {
+        &self.nodes[&id].storage
+    }
+
+    /// Wait until a node other than those in `exclude` leads the cluster.
+    async fn wait_for_leader(&self, exclude: &[NodeId]) -> NodeId {
+        for _ in 0..500 {
+            let leader = self.nodes.iter().find(|(id, node)| {
+                !exclude.contains(id) && node.node.status().role == Role::Leader
+            });
+            if let Some((id, _)) = leader {
+                return *id;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no leader was elected");
+    }
+
+    /// Wait until the local storage of node `id` holds `key` in `test_db.users`.
+    async fn wait_for_document(&self, id: NodeId, key: &str) -> Document {
+        for _ in 0..500 {
+            let local = &self.nodes[&id].local;
+            if let Ok(Some(doc)) = local.get("test_db", "users", key, None).await {
+                return doc;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("document {key} did not reach node {id}");
+    }
+
+    async fn create_users_table(&self, leader: NodeId) {
+        let storage = self.storage(leader);
+        storage.create_database("test_db").await.unwrap();
+        storage
+            .create_table("test_db", "users", &TableConfig::default())
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_single_node_cluster_commits_writes() {
+    let cluster = TestCluster::start(1);
+    let leader = cluster.wait_for_leader(&[]).await;
+    assert_eq!(leader, 1);
+
+    cluster.create_users_table(leader).await;
+    let storage = cluster.storage(leader);
+    storage
+        .put("test_db", "users", "a", &doc("alice"), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.get("test_db", "users", "a", None).await.unwrap(),
+        Some(doc("alice"))
+    );
+
+    // Errors of the state machine reach the client
+    assert!(matches!(
+        storage.create_database("bad name").await,
+        Err(StorageError::InvalidDatabaseName(_))
+    ));
+
+    let status = storage.cluster_status().unwrap();
+    assert_eq!(status.role, Role::Leader);
+    assert_eq!(status.leader, Some(member(1)));
+    assert_eq!(status.applied_index, status.commit_index);
+}
+
+#[tokio::test]
+async fn test_writes_replicate_to_followers() {
+    let cluster = TestCluster::start(3);
+    let leader = cluster.wait_for_leader(&[]).await;
+    cluster.create_users_table(leader).await;
+    cluster
+        .storage(leader)
+        .put("test_db", "users", "a", &doc("alice"), None)
+        .await
+        .unwrap();
+
+    for id in 1..=3 {
+        assert_eq!(cluster.wait_for_document(id, "a").await, doc("alice"));
+    }
+
+    let follower = (1..=3).find(|id| *id != leader).unwrap();
+    let result = cluster
+        .storage(follower)
+        .put("test_db", "users", "b", &doc("bob"), None)
+        .await;
+    match result {
+        Err(StorageError::NotLeader(Some(address))) => {
+            assert_eq!(address, member(leader).address);
+        }
+        other => panic!("expected a not-leader error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_sharded_tables_replicate_rebalancing() {
+    let cluster = TestCluster::start(3);
+    let leader = cluster.wait_for_leader(&[]).await;
+    let storage = cluster.storage(leader);
+    storage.create_database("test_db").await.unwrap();
+    storage
+        .create_sharded_table("test_db", "users", &TableConfig::default(), 4)
+        .await
+        .unwrap();
+
+    let keys = ["alice", "bob", "carol", "dave", "erin", "frank"];
+    for key in keys {
+        storage
+            .put("test_db", "users", key, &doc(key), None)
+            .await
+            .unwrap();
+    }
+    // All keys start out in the third shard, which no longer exists afterwards
+    let summary = storage
+        .rebalance_table("test_db", "users", Some(2))
+        .await
+        .unwrap();
+    assert_eq!(summary.shards.len(), 2);
+    assert_eq!(summary.shards[1].start.as_deref(), Some("dave"));
+    assert_eq!(summary.moved, 6);
+
+    // Every node routes reads through its own copy of the new shard map
+    storage
+        .put("test_db", "users", "zoe", &doc("zoe"), None)
+        .await
+        .unwrap();
+    for id in 1..=3 {
+        cluster.wait_for_document(id, "zoe").await;
+        for key in keys {
+            assert_eq!(cluster.wait_for_document(id, key).await, doc(key));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_leader_failover() {
+    let cluster = TestCluster::start(3);
+    let old_leader = cluster.wait_for_leader(&[]).await;
+    let old_term = cluster.storage(old_leader).cluster_status().unwrap().term;
+    cluster.create_users_table(old_leader).await;
+
+    cluster.transport.isolate(old_leader);
+    let new_leader = cluster.wait_for_leader(&[old_leader]).await;
+    assert!(cluster.storage(new_leader).cluster_status().unwrap().term > old_term);
+    cluster
+        .storage(new_leader)
+        .put("test_db", "users", "a", &doc("alice"), None)
+        .await
+        .unwrap();
+
+    // The old leader steps down once it notices it lost its quorum, and catches up
+    // after the partition heals
+    cluster.transport.reconnect(old_leader);
+    assert_eq!(
+        cluster.wait_for_document(old_leader, "a").await,
+        doc("alice")
+    );
+    let status = cluster.storage(old_leader).cluster_status().unwrap();
+    assert_eq!(status.role, Role::Follower);
+    assert_eq!(status.leader.map(|leader| leader.id), Some(new_leader));
+}
+
+#[tokio::test]
+async fn test_membership_changes() {
+    let mut cluster = TestCluster::start(3);
+    let leader = cluster.wait_for_leader(&[]).await;
+    cluster.create_users_table(leader).await;
+    cluster
+        .storage(leader)
+        .put("test_db", "users", "a", &doc("alice"), None)
+        .await
+        .unwrap();
+
+    // A new node starts without members and learns the log from the leader
+    cluster.add_node(
+        4,
+        Vec::new(),
+        MemoryLogStore::new(),
+        TempDir::new().unwrap(),
+    );
+    let status = cluster
+        .storage(leader)
+        .change_membership(MembershipChange::Add(member(4)))
+        .await
+        .unwrap();
+    assert_eq!(status.members.len(), 4);
+    assert_eq!(cluster.wait_for_document(4, "a").await, doc("alice"));
+    assert_eq!(
+        cluster.storage(4).cluster_status().unwrap().members.len(),
+        4
+    );
+
+    assert!(matches!(
+        cluster
+            .storage(leader)
+            .change_membership(MembershipChange::Add(member(4)))
+            .await,
+        Err(StorageError::Cluster(_))
+    ));
+
+    let removed = (1..=3).find(|id| *id != leader).unwrap();
+    let status = cluster
+        .storage(leader)
+        .change_membership(MembershipChange::Remove(removed))
+        .await
+        .unwrap();
+    assert!(status.members.iter().all(|m| m.id != removed));
+
+    // The remaining three nodes still commit writes without the removed one
+    cluster.stop_node(removed);
+    cluster
+        .storage(leader)
+        .put("test_db", "users", "b", &doc("bob"), None)
+        .await
+        .unwrap();
+    assert_eq!(cluster.wait_for_document(4, "b").await, doc("bob"));
+}
+
+#[tokio::test]
+async fn test_transfer_leadership() {
+    let cluster = TestCluster::start(3);
+    let leader = cluster.wait_for_leader(&[]).await;
+    cluster.create_users_table(leader).await;
+
+    let target = (1..=3).find(|id| *id != leader).unwrap();
+    assert!(matches!(
+        cluster.storage(target).transfer_leadership(None).await,
+        Err(StorageError::NotLeader(_))
+    ));
+    cluster
+        .storage(leader)
+        .transfer_leadership(Some(target))
+        .await
+        .unwrap();
+
+    assert_eq!(cluster.wait_for_leader(&[leader]).await, target);
+    cluster
+        .storage(target)
+        .put("test_db", "users", "a", &doc("alice"), None)
+        .await
+        .unwrap();
+    assert_eq!(cluster.wait_for_document(leader, "a").await, doc("alice"));
+}
+
+#[tokio::test]
+async fn test_restarted_node_keeps_its_log() {
+    let mut cluster = TestCluster::start(1);
+    let leader = cluster.wait_for_leader(&[]).await;
+    cluster.create_users_table(leader).await;
+    cluster
+        .storage(leader)
+        .put("test_db", "users", "a", &doc("alice"), None)
+        .await
+        .unwrap();
+    let before = cluster.storage(leader).cluster_status().unwrap();
+
+    let TestNode {
+        local,
+        log_store,
+        _data_dir: data_dir,
+        ..
+    } = cluster.stop_node(leader);
+    assert_eq!(local.applied_index().unwrap(), before.applied_index);
+    assert!(log_store.load().unwrap().term >= before.term);
+
+    // Wait for the stopped node's tasks to let go of its database before reopening it
+    let database = Arc::downgrade(&local);
+    drop(local);
+    for _ in 0..500 {
+        if database.strong_count() == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    cluster.add_node(leader, vec![member(leader)], log_store, data_dir);
+    cluster.wait_for_leader(&[]).await;
+    let after = cluster.storage(leader).cluster_status().unwrap();
+    assert!(after.term > before.term);
+    assert!(after.commit_index > before.commit_index);
+    cluster
+        .storage(leader)
+        .put("test_db", "users", "b", &doc("bob"), None)
+        .await
+        .unwrap();
+    assert_eq!(cluster.wait_for_document(leader, "a").await, doc("alice"));
+}
+
+#[tokio::test]
+async fn test_lagging_follower_catches_up_from_a_snapshot() {
+    let cluster = TestCluster::start_with(3, 4);
+    let leader = cluster.wait_for_leader(&[]).await;
+    cluster.create_users_table(leader).await;
+    let follower = (1..=3).find(|id| *id != leader).unwrap();
+
+    // The leader compacts the entries the isolated follower misses
+    cluster.transport.isolate(follower);
+    let keys: Vec<String> = (0..20).map(|i| format!("user{i}")).collect();
+    for key in &keys {
+        cluster
+            .storage(leader)
+            .put("test_db", "users", key, &doc(key), None)
+            .await
+            .unwrap();
+    }
+    let log_store = &cluster.nodes[&leader].log_store;
+    for _ in 0..500 {
+        if log_store.load().unwrap().snapshot.index > 4 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(log_store.load().unwrap().snapshot.index > 4);
+
+    cluster.transport.reconnect(follower);
+    for key in &keys {
+        assert_eq!(cluster.wait_for_document(follower, key).await, doc(key));
+    }
+
+    // The follower is back to following the log after installing the snapshot
+    let leader = cluster.wait_for_leader(&[]).await;
+    cluster
+        .storage(leader)
+        .put("test_db", "users", "late", &doc("late"), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        cluster.wait_for_document(follower, "late").await,
+        doc("late")
+    );
+    let local = &cluster.nodes[&follower].local;
+    assert!(local.table_exists("test_db", "users").await.unwrap());
+}
+
+#[test]
+fn test_follower_replaces_conflicting_entries() {
+    let entry = |term, name: &str| Entry {
+        term,
+        command: Command::CreateDatabase {
+            name: name.to_string(),
+        },
+    };
+    let mut log_store = MemoryLogStore::new();
+    log_store
+        .replace_from(1, &[entry(1, "a"), entry(2, "stale")], 0)
+        .unwrap();
+    log_store.save_hard_state(2, None).unwrap();
+    let mut raft = raft::Raft::new(
+        2,
+        vec![member(1), member(2)],
+        10,
+        log_store.load().unwrap(),
+        0,
+    );
+
+    // A leader of term 3 that never saw the entry of term 2 overwrites it
+    let response = raft.handle_request(
+        1,
+        Request::Append {
+            term: 3,
+            prev_log_index: 1,
+            prev_log_term: 1,
+            entries: vec![entry(3, "b")],
+            leader_commit: 2,
+        },
+    );
+    assert_eq!(
+        response,
+        Response::Append {
+            term: 3,
+            success: true,
+            match_index: 2,
+        }
+    );
+
+    // The new entry is only handed out to be applied once it is durable
+    assert_eq!(raft.take_committed(), vec![(1, entry(1, "a"))]);
+    let ready = raft.take_ready();
+    for write in &ready.writes {
+        log_store.write(write).unwrap();
+    }
+    raft.persisted(ready.last_entry.0, ready.last_entry.1);
+    assert_eq!(raft.take_committed(), vec![(2, entry(3, "b"))]);
+    let state = log_store.load().unwrap();
+    assert_eq!(state.term, 3);
+    assert_eq!(state.entries, vec![entry(1, "a"), entry(3, "b")]);
+
+    // Requests of older terms and with gaps are rejected
+    let response = raft.handle_request(
+        1,
+        Request::Append {
+            term: 2,
+            prev_log_index: 2,
+            prev_log_term: 3,
+            entries: Vec::new(),
+            leader_commit: 2,
+        },
+    );
+    assert!(matches!(response, Response::Append { success: false, .. }));
+    let response = raft.handle_request(
+        1,
+        Request::Append {
+            term: 3,
+            prev_log_index: 5,
+            prev_log_term: 3,
+            entries: Vec::new(),
+            leader_commit: 2,
+        },
+    );
+    assert_eq!(
+        response,
+        Response::Append {
+            term: 3,
+            success: false,
+            match_index: 2,
+        }
+    );
+}
+
+#[test]
+fn test_leader_commits_entries_once_durable() {
+    let mut raft = raft::Raft::new(1, vec![member(1)], 2, PersistentState::default(), 0);
+    for _ in 0..4 {
+        raft.tick();
+    }
+    let (index, term) = raft.propose(Command::Noop).unwrap();
+
+    // A single node is its own quorum, but only for what it wrote to its log
+    assert_eq!(raft.status().role, Role::Leader);
+    assert_eq!(raft.status().commit_index, 0);
+    assert!(raft.take_committed().is_empty());
+    let ready = raft.take_ready();
+    assert_eq!(
+        ready.writes[0],
+        LogWrite::HardState {
+            term,
+            voted_for: Some(1),
+        }
+    );
+    assert_eq!(ready.last_entry, (index, term));
+
+    // Writes reported after the entry they cover was replaced are ignored
+    raft.persisted(index, term + 1);
+    assert_eq!(raft.status().commit_index, 0);
+    raft.persisted(index, term);
+    assert_eq!(raft.status().commit_index, index);
+    assert_eq!(raft.take_committed().len(), 2);
+}
+
+#[test]
+fn test_read_index_covers_earlier_terms() {
+    let state = PersistentState {
+        term: 1,
+        entries: vec![
+            Entry {
+                term: 1,
+                command: Command::Noop,
+            };
+            2
+        ],
+        ..PersistentState::default()
+    };
+    let mut raft = raft::Raft::new(1, vec![member(1)], 2, state, 0);
+    assert!(matches!(raft.read_index(), Err(StorageError::NotLeader(_))));
+    for _ in 0..4 {
+        raft.tick();
+    }
+
+    // The entries of the previous term are only known to be committed once the entry
+    // the new leader appended is
+    assert_eq!(raft.status().commit_index, 0);
+    assert_eq!(raft.read_index().unwrap(), 3);
+    let ready = raft.take_ready();
+    raft.persisted(ready.last_entry.0, ready.last_entry.1);
+    assert_eq!(raft.status().commit_index, 3);
+    assert_eq!(raft.read_index().unwrap(), 3);
+}
+
+#[test]
+fn test_compacted_entries_reach_followers_as_snapshots() {
+    let members = vec![member(1), member(2), member(3)];
+    let mut raft = raft::Raft::new(1, members.clone(), 2, PersistentState::default(), 0);
+    while raft.status().role != Role::Candidate {
+        raft.tick();
+    }
+    let term = raft.status().term;
+    raft.handle_response(
+        2,
+        Response::Vote {
+            term,
+            granted: true,
+        },
+    );
+    raft.propose(Command::Noop).unwrap();
+    let ready = raft.take_ready();
+    raft.persisted(ready.last_entry.0, ready.last_entry.1);
+    raft.handle_response(
+        2,
+        Response::Append {
+            term,
+            success: true,
+            match_index: 2,
+        },
+    );
+    assert_eq!(raft.take_committed().len(), 2);
+
+    // Only entries handed out to be applied are compacted
+    raft.compact(3);
+    assert_eq!(raft.snapshot_index(), 0);
+    raft.compact(2);
+    assert_eq!(raft.snapshot_index(), 2);
+    assert_eq!(
+        raft.take_ready().writes,
+        vec![LogWrite::Snapshot {
+            snapshot: LogSnapshot {
+                index: 2,
+                term,
+                members,
+            },
+            through: 2,
+        }]
+    );
+
+    // Node 3 never got the compacted entries, so it is to be sent a snapshot, and only
+    // gets heartbeats until it installed it
+    raft.tick();
+    let ready = raft.take_ready();
+    assert_eq!(ready.snapshots, vec![3]);
+    assert!(ready.messages.contains(&(
+        3,
+        Request::Append {
+            term,
+            prev_log_index: 2,
+            prev_log_term: term,
+            entries: Vec::new(),
+            leader_commit: 2,
+        }
+    )));
+    raft.handle_response(
+        3,
+        Response::Append {
+            term,
+            success: false,
+            match_index: 0,
+        },
+    );
+    assert!(raft.take_ready().is_empty());
+
+    raft.snapshot_sent(3, term, 2);
+    let ready = raft.take_ready();
+    assert!(ready.snapshots.is_empty());
+    assert_eq!(
+        ready.messages,
+        vec![(
+            3,
+            Request::Append {
+                term,
+                prev_log_index: 2,
+                prev_log_term: term,
+                entries: Vec::new(),
+                leader_commit: 2,
+            }
+        )]
+    );
+}
+
+#[test]
+fn test_follower_restores_snapshots() {
+    let snapshot = LogSnapshot {
+        index: 2,
+        term: 1,
+        members: vec![member(1), member(2)],
+    };
+    let mut log_store = MemoryLogStore::new();
+    let mut raft = raft::Raft::new(2, Vec::new(), 10, PersistentState::default(), 0);
+    let response = raft.handle_request(
+        1,
+        Request::InstallSnapshot {
+            term: 1,
+            snapshot: snapshot.clone(),
+            offset: 0,
+            data: Vec::new(),
+            done: true,
+        },
+    );
+    assert_eq!(
+        response,
+        Response::InstallSnapshot {
+            term: 1,
+            success: true,
+        }
+    );
+
+    // The membership comes with the snapshot, and its entries are not applied again
+    raft.restore(snapshot.clone());
+    let status = raft.status();
+    assert_eq!((status.commit_index, status.applied_index), (2, 2));
+    assert_eq!(status.members, snapshot.members);
+    assert!(raft.take_committed().is_empty());
+
+    // Entries up to the snapshot are skipped, as they match the leader's
+    let entry = Entry {
+        term: 1,
+        command: Command::Noop,
+    };
+    let response = raft.handle_request(
+        1,
+        Request::Append {
+            term: 1,
+            prev_log_index: 0,
+            prev_log_term: 0,
+            entries: vec![entry.clone(); 3],
+            leader_commit: 3,
+        },
+    );
+    assert_eq!(
+        response,
+        Response::Append {
+            term: 1,
+            success: true,
+            match_index: 3,
+        }
+    );
+    let ready = raft.take_ready();
+    for write in &ready.writes {
+        log_store.write(write).unwrap();
+    }
+    raft.persisted(ready.last_entry.0, ready.last_entry.1);
+    assert_eq!(raft.take_committed(), vec![(3, entry.clone())]);
+
+    let state = log_store.load().unwrap();
+    assert_eq!(state.snapshot, snapshot);
+    assert_eq!(state.entries, vec![entry]);
+}
+
+#[test]
+fn test_rocks_log_store_roundtrip() {
+    let data_dir = TempDir::new().unwrap();
+    let path = data_dir.path().join("raft").to_string_lossy().to_string();
+    let entries: Vec<Entry> = (1..=3)
+        .map(|term| Entry {
+            term,
+            command: Command::Noop,
+        })
+        .collect();
+
+    {
+        let mut store = RocksLogStore::open(&path).unwrap();
+        store.save_hard_state(3, Some(2)).unwrap();
+        store.replace_from(1, &entries, 0).unwrap();
+        store.replace_from(3, &entries[..1], 3).unwrap();
+    }
+
+    let mut store = RocksLogStore::open(&path).unwrap();
+    assert_eq!(
+        store.load().unwrap(),
+        PersistentState {
+            term: 3,
+            voted_for: Some(2),
+            snapshot: LogSnapshot::default(),
+            entries: vec![entries[0].clone(), entries[1].clone(), entries[0].clone()],
+        }
+    );
+
+    // Compacted entries are gone, and later ones keep their indexes
+    let snapshot = LogSnapshot {
+        index: 1,
+        term: 1,
+        members: vec![member(1)],
+    };
+    store.save_snapshot(&snapshot, 1).unwrap();
+    store.replace_from(3, &entries[2..], 3).unwrap();
+    let state = store.load().unwrap();
+    assert_eq!(state.snapshot, snapshot);
+    assert_eq!(state.entries, vec![entries[1].clone(), entries[2].clone()]);
+}
+
+#[test]
+fn test_parse_member() {
+    assert_eq!(
+        "2=127.0.0.1:7002".parse::<Member>(),
+        Ok(Member {
+            id: 2,
+            address: "127.0.0.1:7002".to_string(),
+        })
+    );
+    assert!("127.0.0.1:7002".parse::<Member>().is_err());
+    assert!("two=127.0.0.1:7002".parse::<Member>().is_err());
+}
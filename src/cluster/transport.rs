@@ -0,0 +1,176 @@
+//! Delivery of Raft requests between the nodes of a cluster.
+
+use super::raft::{Request, Response};
+use super::{ClusterNode, Member, NodeId};
+use crate::ast::proto;
+use crate::storage::{Result, StorageError};
+use async_trait::async_trait;
+use prost::Message;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock, Weak};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Send `request` from node `from` to `to` and wait for its response.
+    async fn call(&self, from: NodeId, to: &Member, request: Request) -> Result<Response>;
+}
+
+fn transport_error(to: &Member, err: impl std::fmt::Display) -> StorageError {
+    StorageError::Cluster(format!(
+        "failed to reach node {} at {}: {err}",
+        to.id, to.address
+    ))
+}
+
+/// Sends requests to other server processes over the client protocol, as envelopes of
+/// type `RAFT` carrying the sender's id and the request serialized with bincode.
+#[derive(Default)]
+pub struct TcpTransport {
+    connections: tokio::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<TcpStream>>>>>,
+}
+
+impl TcpTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    async fn exchange(stream: &mut TcpStream, envelope: &proto::Envelope) -> Result<Response> {
+        let payload = envelope.encode_to_vec();
+        let len = u32::try_from(payload.len())
+            .map_err(|_| StorageError::Cluster("request is too large".to_string()))?;
+        let mut out = len.to_be_bytes().to_vec();
+        out.extend(payload);
+        stream.write_all(&out).await.map_err(io_error)?;
+
+        let mut len_buf = [0u8; 4];
+        stream.read_exact(&mut len_buf).await.map_err(io_error)?;
+        let mut buffer = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+        stream.read_exact(&mut buffer).await.map_err(io_error)?;
+
+        let response = proto::Envelope::decode(buffer.as_slice())
+            .map_err(|e| StorageError::Cluster(e.to_string()))?;
+        match proto::MessageType::try_from(response.r#type) {
+            Ok(proto::MessageType::Raft) => decode_message(&response.payload),
+            Ok(proto::MessageType::Error) => {
+                let error = proto::ErrorInfo::decode(response.payload.as_slice())
+                    .map_err(|e| StorageError::Cluster(e.to_string()))?;
+                Err(StorageError::Cluster(error.message))
+            }
+            _ => Err(StorageError::Cluster(format!(
+                "unexpected response of type {}",
+                response.r#type
+            ))),
+        }
+    }
+}
+
+fn io_error(err: std::io::Error) -> StorageError {
+    StorageError::Cluster(err.to_string())
+}
+
+/// Serialize a Raft message for an envelope payload.
+pub fn encode_message<T: serde::Serialize>(message: &T) -> Result<Vec<u8>> {
+    Ok(bincode::serde::encode_to_vec(
+        message,
+        bincode::config::standard(),
+    )?)
+}
+
+/// Deserialize a Raft message from an envelope payload.
+pub fn decode_message<T: for<'de> serde::Deserialize<'de>>(payload: &[u8]) -> Result<T> {
+    let (message, _) = bincode::serde::decode_from_slice(payload, bincode::config::standard())?;
+    Ok(message)
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn call(&self, from: NodeId, to: &Member, request: Request) -> Result<Response> {
+        let connection = self
+            .connections
+            .lock()
+            .await
+            .entry(to.address.clone())
+            .or_default()
+            .clone();
+        let mut connection = connection.lock().await;
+
+        if connection.is_none() {
+            let stream = TcpStream::connect(&to.address)
+                .await
+                .map_err(|e| transport_error(to, e))?;
+            stream
+                .set_nodelay(true)
+                .map_err(|e| transport_error(to, e))?;
+            *connection = Some(stream);
+        }
+
+        let envelope = proto::Envelope {
+            version: proto::ProtocolVersion::Version1.into(),
+            query_id: format!("raft-{from}"),
+            r#type: proto::MessageType::Raft.into(),
+            payload: encode_message(&(from, request))?,
+        };
+        let stream = connection
+            .as_mut()
+            .expect("connection was just established");
+        let result = Self::exchange(stream, &envelope).await;
+        if result.is_err() {
+            // Reconnect on the next call rather than reading a stale response
+            *connection = None;
+        }
+        result.map_err(|e| transport_error(to, e))
+    }
+}
+
+/// Delivers requests to nodes running in the same process. Links between nodes can be
+/// cut to simulate network partitions.
+#[derive(Default)]
+pub struct LocalTransport {
+    nodes: RwLock<HashMap<NodeId, Weak<ClusterNode>>>,
+    isolated: RwLock<HashSet<NodeId>>,
+}
+
+impl LocalTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, node: &Arc<ClusterNode>) {
+        self.nodes
+            .write()
+            .unwrap()
+            .insert(node.id(), Arc::downgrade(node));
+    }
+
+    /// Drop every request to or from `node` until it is reconnected.
+    pub fn isolate(&self, node: NodeId) {
+        self.isolated.write().unwrap().insert(node);
+    }
+
+    pub fn reconnect(&self, node: NodeId) {
+        self.isolated.write().unwrap().remove(&node);
+    }
+}
+
+#[async_trait]
+impl Transport for LocalTransport {
+    async fn call(&self, from: NodeId, to: &Member, request: Request) -> Result<Response> {
+        let isolated = {
+            let isolated = self.isolated.read().unwrap();
+            isolated.contains(&from) || isolated.contains(&to.id)
+        };
+        let node = self
+            .nodes
+            .read()
+            .unwrap()
+            .get(&to.id)
+            .and_then(Weak::upgrade);
+
+        match node {
+            Some(node) if !isolated => node.handle_request(from, request).await,
+            _ => Err(transport_error(to, "node is unreachable")),
+        }
+    }
+}
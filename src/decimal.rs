@@ -0,0 +1,615 @@
+//! Decimal values and the exact arithmetic over them.
+//!
+//! A decimal is an integer of up to 38 digits scaled by a power of ten, so amounts such
+//! as `19.99` are held exactly. Decimals are written as text such as `-12.50` or `1.5e3`
+//! and keep their scale, so `1.50` stays `1.50`, but two decimals are the same value
+//! when they are the same number. Sums, differences and remainders are exact; products
+//! and quotients are rounded to 28 fractional digits, halves away from zero, and a
+//! result that needs more than 38 digits left of that is an error.
+//!
+//! Integers convert to decimals exactly and floats by their shortest text, so the float
+//! `0.1` becomes the decimal `0.1`. Decimals convert to the nearest float, and to
+//! integers when they have no fraction. Numbers of every type compare exactly.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Most fractional digits a decimal keeps.
+pub const MAX_SCALE: u32 = 28;
+
+/// Most digits a decimal holds.
+const MAX_DIGITS: usize = 38;
+
+#[derive(Debug)]
+pub struct DecimalError(pub String);
+
+impl fmt::Display for DecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for DecimalError {}
+
+/// A decimal number, `mantissa` divided by ten to the power of `scale`.
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    pub fn new(mantissa: i128, scale: u32) -> Result<Self, DecimalError> {
+        Self::from_digits(mantissa < 0, &digits(mantissa.unsigned_abs()), scale)
+    }
+
+    /// Parse a decimal such as `-12.50`, `.5` or `1.5e3`, rounding any fractional digits
+    /// past the 28th.
+    pub fn parse(text: &str) -> Result<Self, DecimalError> {
+        let invalid = || DecimalError(format!("{text} is not a decimal"));
+        let (negative, unsigned) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (number, exponent) = match unsigned.split_once(['e', 'E']) {
+            Some((number, exponent)) => (number, exponent.parse::<i64>().map_err(|_| invalid())?),
+            None => (unsigned, 0),
+        };
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        if (whole.is_empty() && fraction.is_empty())
+            || !whole
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        let mut digits: Vec<u8> = whole
+            .bytes()
+            .chain(fraction.bytes())
+            .map(|b| b - b'0')
+            .collect();
+        let scale = (fraction.len() as i64)
+            .checked_sub(exponent)
+            .ok_or_else(out_of_range)?;
+        if scale < 0 {
+            if strip_leading_zeros(&digits).is_empty() {
+                return Ok(Self::from(0));
+            }
+            // Too many digits either way, without filling memory with zeros
+            let zeros = usize::try_from(-scale).map_err(|_| out_of_range())?;
+            if zeros > MAX_DIGITS {
+                return Err(out_of_range());
+            }
+            digits.resize(digits.len() + zeros, 0);
+        }
+        let scale = u32::try_from(scale.max(0)).map_err(|_| invalid())?;
+        Self::from_digits(negative, &digits, scale)
+    }
+
+    /// The decimal written by the float's shortest text.
+    pub fn from_f64(value: f64) -> Result<Self, DecimalError> {
+        if !value.is_finite() {
+            return Err(DecimalError(format!("{value} is not a decimal")));
+        }
+        Self::parse(&value.to_string())
+    }
+
+    /// The nearest float.
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    /// The integer, if the decimal has no fraction and is in range.
+    pub fn to_i64(&self) -> Option<i64> {
+        let divisor = 10i128.pow(self.scale);
+        if self.mantissa % divisor != 0 {
+            return None;
+        }
+        i64::try_from(self.mantissa / divisor).ok()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa == 0
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn checked_add(&self, other: &Self) -> Result<Self, DecimalError> {
+        let scale = self.scale.max(other.scale);
+        let (a, b) = (self.magnitude(scale), other.magnitude(scale));
+        let (a_negative, b_negative) = (self.mantissa < 0, other.mantissa < 0);
+        if a_negative == b_negative {
+            return Self::from_digits(a_negative, &add_magnitudes(&a, &b), scale);
+        }
+        match compare_magnitudes(&a, &b) {
+            Ordering::Less => Self::from_digits(b_negative, &sub_magnitudes(&b, &a), scale),
+            _ => Self::from_digits(a_negative, &sub_magnitudes(&a, &b), scale),
+        }
+    }
+
+    pub fn checked_sub(&self, other: &Self) -> Result<Self, DecimalError> {
+        self.checked_add(&Self {
+            mantissa: -other.mantissa,
+            scale: other.scale,
+        })
+    }
+
+    pub fn checked_mul(&self, other: &Self) -> Result<Self, DecimalError> {
+        let product = mul_magnitudes(
+            &digits(self.mantissa.unsigned_abs()),
+            &digits(other.mantissa.unsigned_abs()),
+        );
+        Self::from_digits(
+            (self.mantissa < 0) != (other.mantissa < 0),
+            &product,
+            self.scale + other.scale,
+        )
+    }
+
+    /// The quotient, rounded to 28 fractional digits and without trailing zeros past
+    /// the larger scale of the two.
+    pub fn checked_div(&self, other: &Self) -> Result<Self, DecimalError> {
+        if other.is_zero() {
+            return Err(DecimalError("division by zero".to_string()));
+        }
+        // One digit more than is kept, to round by
+        let scale = MAX_SCALE + 1;
+        let mut dividend = digits(self.mantissa.unsigned_abs());
+        dividend.resize(
+            dividend.len() + (scale + other.scale - self.scale) as usize,
+            0,
+        );
+        let (quotient, _) = div_rem_magnitudes(&dividend, &digits(other.mantissa.unsigned_abs()));
+        let quotient = Self::from_digits(
+            (self.mantissa < 0) != (other.mantissa < 0),
+            &quotient,
+            scale,
+        )?;
+        Ok(quotient.trimmed(self.scale.max(other.scale)))
+    }
+
+    /// The remainder, with the sign of the dividend.
+    pub fn checked_rem(&self, other: &Self) -> Result<Self, DecimalError> {
+        if other.is_zero() {
+            return Err(DecimalError("division by zero".to_string()));
+        }
+        let scale = self.scale.max(other.scale);
+        let (_, remainder) = div_rem_magnitudes(&self.magnitude(scale), &other.magnitude(scale));
+        Self::from_digits(self.mantissa < 0, &remainder, scale)
+    }
+
+    /// The digits of the absolute value at a scale no smaller than the decimal's.
+    fn magnitude(&self, scale: u32) -> Vec<u8> {
+        let mut magnitude = digits(self.mantissa.unsigned_abs());
+        magnitude.resize(magnitude.len() + (scale - self.scale) as usize, 0);
+        magnitude
+    }
+
+    /// Drop trailing fractional zeros down to `scale`.
+    fn trimmed(mut self, scale: u32) -> Self {
+        while self.scale > scale && self.mantissa % 10 == 0 {
+            self.mantissa /= 10;
+            self.scale -= 1;
+        }
+        self
+    }
+
+    /// The decimal of the given digits and scale, rounding off any digits past the
+    /// 28th fractional one, or as many as make it fit in 38 digits.
+    fn from_digits(negative: bool, digits: &[u8], scale: u32) -> Result<Self, DecimalError> {
+        let mut digits = strip_leading_zeros(digits).to_vec();
+        let mut scale = scale;
+        let excess = (scale.saturating_sub(MAX_SCALE) as usize)
+            .max(digits.len().saturating_sub(MAX_DIGITS))
+            .min(scale as usize);
+        if excess > 0 {
+            // Halves round away from zero, so only the first digit dropped matters
+            let round_up = digits.len() >= excess && digits[digits.len() - excess] >= 5;
+            digits.truncate(digits.len().saturating_sub(excess));
+            scale -= excess as u32;
+            if round_up {
+                digits = add_magnitudes(&digits, &[1]);
+                if digits.len() > MAX_DIGITS && scale > 0 {
+                    // Carried all the way, so the last digit is a zero
+                    digits.pop();
+                    scale -= 1;
+                }
+            }
+        }
+        if digits.len() > MAX_DIGITS {
+            return Err(out_of_range());
+        }
+
+        let magnitude = digits
+            .iter()
+            .fold(0i128, |value, &digit| value * 10 + i128::from(digit));
+        Ok(Self {
+            mantissa: if negative { -magnitude } else { magnitude },
+            scale,
+        })
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(value: i64) -> Self {
+        Self {
+            mantissa: value.into(),
+            scale: 0,
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let digits = self.mantissa.unsigned_abs().to_string();
+        if self.scale == 0 {
+            return write!(f, "{sign}{digits}");
+        }
+        let scale = self.scale as usize;
+        let digits = format!("{digits:0>width$}", width = scale + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - scale);
+        write!(f, "{sign}{whole}.{fraction}")
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        ExactNumber::from(self).cmp(&ExactNumber::from(other))
+    }
+}
+
+/// A finite number of any type as its sign, digits and exponent, to compare numbers of
+/// different types exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExactNumber {
+    negative: bool,
+    /// Digits without leading or trailing zeros, none for zero.
+    digits: Vec<u8>,
+    /// The number is `0.` followed by the digits, times ten to this power.
+    exponent: i64,
+}
+
+impl ExactNumber {
+    fn new(negative: bool, digits: &[u8], exponent: i64) -> Self {
+        let leading = digits.iter().take_while(|&&digit| digit == 0).count();
+        let digits = &digits[leading..];
+        let trailing = digits.iter().rev().take_while(|&&digit| digit == 0).count();
+        let digits = digits[..digits.len() - trailing].to_vec();
+        if digits.is_empty() {
+            return Self {
+                negative: false,
+                digits,
+                exponent: 0,
+            };
+        }
+        Self {
+            negative,
+            digits,
+            exponent: exponent - leading as i64,
+        }
+    }
+
+    pub fn from_i128(value: i128) -> Self {
+        let digits = digits(value.unsigned_abs());
+        Self::new(value < 0, &digits, digits.len() as i64)
+    }
+
+    /// The exact value of a float, if it is finite.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+        // No float has more than 767 significant digits
+        let text = format!("{:.800e}", value.abs());
+        let (mantissa, exponent) = text.split_once('e')?;
+        let digits: Vec<u8> = mantissa
+            .bytes()
+            .filter(u8::is_ascii_digit)
+            .map(|b| b - b'0')
+            .collect();
+        Some(Self::new(
+            value.is_sign_negative(),
+            &digits,
+            exponent.parse::<i64>().ok()? + 1,
+        ))
+    }
+
+    /// Append a key that sorts as the numbers do and that begins no other key.
+    pub fn encode_key(&self, out: &mut Vec<u8>) {
+        out.push(match (self.digits.is_empty(), self.negative) {
+            (false, true) => 0,
+            (true, _) => 1,
+            (false, false) => 2,
+        });
+        let start = out.len();
+        out.extend_from_slice(&((self.exponent as u64) ^ 1 << 63).to_be_bytes());
+        // Digits from one, so that the end sorts before them
+        out.extend(self.digits.iter().map(|digit| digit + 1));
+        out.push(0);
+        if self.negative {
+            for byte in &mut out[start..] {
+                *byte = !*byte;
+            }
+        }
+    }
+}
+
+impl From<&Decimal> for ExactNumber {
+    fn from(decimal: &Decimal) -> Self {
+        let digits = digits(decimal.mantissa.unsigned_abs());
+        Self::new(
+            decimal.mantissa < 0,
+            &digits,
+            digits.len() as i64 - i64::from(decimal.scale),
+        )
+    }
+}
+
+impl PartialOrd for ExactNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ExactNumber {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let sign = |number: &Self| match (number.digits.is_empty(), number.negative) {
+            (false, true) => -1,
+            (true, _) => 0,
+            (false, false) => 1,
+        };
+        sign(self).cmp(&sign(other)).then_with(|| {
+            let magnitude = self
+                .exponent
+                .cmp(&other.exponent)
+                .then_with(|| self.digits.cmp(&other.digits));
+            if self.negative {
+                magnitude.reverse()
+            } else {
+                magnitude
+            }
+        })
+    }
+}
+
+fn out_of_range() -> DecimalError {
+    DecimalError(format!("decimal needs more than {MAX_DIGITS} digits"))
+}
+
+// Unsigned numbers as their decimal digits, most significant first
+
+fn digits(value: u128) -> Vec<u8> {
+    if value == 0 {
+        return Vec::new();
+    }
+    value.to_string().bytes().map(|b| b - b'0').collect()
+}
+
+fn strip_leading_zeros(digits: &[u8]) -> &[u8] {
+    let leading = digits.iter().take_while(|&&digit| digit == 0).count();
+    &digits[leading..]
+}
+
+fn compare_magnitudes(a: &[u8], b: &[u8]) -> Ordering {
+    let (a, b) = (strip_leading_zeros(a), strip_leading_zeros(b));
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+fn add_magnitudes(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let (mut a, mut b) = (a.iter().rev(), b.iter().rev());
+    let mut carry = 0;
+    loop {
+        let (x, y) = (a.next(), b.next());
+        if x.is_none() && y.is_none() {
+            break;
+        }
+        let digit = x.unwrap_or(&0) + y.unwrap_or(&0) + carry;
+        sum.push(digit % 10);
+        carry = digit / 10;
+    }
+    if carry > 0 {
+        sum.push(carry);
+    }
+    sum.reverse();
+    sum
+}
+
+/// `a - b`, where `a` is no smaller than `b`.
+fn sub_magnitudes(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut b = b.iter().rev();
+    let mut borrow = 0;
+    for &x in a.iter().rev() {
+        let y = b.next().unwrap_or(&0) + borrow;
+        borrow = u8::from(x < y);
+        difference.push(x + borrow * 10 - y);
+    }
+    difference.reverse();
+    strip_leading_zeros(&difference).to_vec()
+}
+
+fn mul_magnitudes(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut product = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate().rev() {
+        for (j, &y) in b.iter().enumerate().rev() {
+            product[i + j + 1] += u32::from(x) * u32::from(y);
+        }
+    }
+    for k in (1..product.len()).rev() {
+        product[k - 1] += product[k] / 10;
+        product[k] %= 10;
+    }
+    let product: Vec<u8> = product.into_iter().map(|digit| digit as u8).collect();
+    strip_leading_zeros(&product).to_vec()
+}
+
+/// Long division of `a` by `b`, which isn't zero.
+fn div_rem_magnitudes(a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let b = strip_leading_zeros(b);
+    let mut quotient = Vec::with_capacity(a.len());
+    let mut remainder = Vec::with_capacity(b.len() + 1);
+    for &digit in a {
+        remainder.push(digit);
+        remainder = strip_leading_zeros(&remainder).to_vec();
+        let mut count = 0;
+        while compare_magnitudes(&remainder, b).is_ge() {
+            remainder = sub_magnitudes(&remainder, b);
+            count += 1;
+        }
+        quotient.push(count);
+    }
+    (strip_leading_zeros(&quotient).to_vec(), remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(text: &str) -> Decimal {
+        Decimal::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        let cases = [
+            ("0", "0"),
+            ("-12.50", "-12.50"),
+            ("+.5", "0.5"),
+            ("1.", "1"),
+            ("007.10", "7.10"),
+            ("1.5e3", "1500"),
+            ("15e-4", "0.0015"),
+            ("0e99999", "0"),
+            (
+                "0.00000000000000000000000000015",
+                "0.0000000000000000000000000002",
+            ),
+            (
+                "99999999999999999999999999999999999999",
+                "99999999999999999999999999999999999999",
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(decimal(text).to_string(), expected, "{text}");
+        }
+
+        for invalid in [
+            "",
+            ".",
+            "-",
+            "1.2.3",
+            "1e",
+            "1,5",
+            "NaN",
+            "1e99",
+            "1e-99999999999999999999",
+            "1.5e-9223372036854775807",
+            "1e-9223372036854775808",
+        ] {
+            assert!(Decimal::parse(invalid).is_err(), "{invalid}");
+        }
+        assert!(Decimal::parse("100000000000000000000000000000000000000").is_err());
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(Decimal::from_f64(0.1).unwrap().to_string(), "0.1");
+        assert_eq!(Decimal::from_f64(-2.5e-3).unwrap().to_string(), "-0.0025");
+        assert!(Decimal::from_f64(f64::NAN).is_err());
+        assert!(Decimal::from_f64(1e300).is_err());
+        assert_eq!(decimal("19.99").to_f64(), 19.99);
+        assert_eq!(decimal("-42.000").to_i64(), Some(-42));
+        assert_eq!(decimal("42.5").to_i64(), None);
+        assert_eq!(decimal("1e30").to_i64(), None);
+        assert_eq!(Decimal::from(i64::MIN).to_string(), i64::MIN.to_string());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let check = |result: Result<Decimal, DecimalError>, expected: &str| {
+            assert_eq!(result.unwrap().to_string(), expected);
+        };
+        check(decimal("0.1").checked_add(&decimal("0.2")), "0.3");
+        check(decimal("19.99").checked_add(&decimal("-20")), "-0.01");
+        check(decimal("-5.5").checked_sub(&decimal("-5.50")), "0.00");
+        check(decimal("19.99").checked_mul(&decimal("3")), "59.97");
+        check(decimal("-1.5").checked_mul(&decimal("1.5")), "-2.25");
+        check(decimal("10.00").checked_div(&decimal("4")), "2.50");
+        check(
+            decimal("1").checked_div(&decimal("3")),
+            "0.3333333333333333333333333333",
+        );
+        check(
+            decimal("-2").checked_div(&decimal("3")),
+            "-0.6666666666666666666666666667",
+        );
+        check(decimal("7.5").checked_rem(&decimal("2")), "1.5");
+        check(decimal("-7.5").checked_rem(&decimal("2")), "-1.5");
+
+        // Products round their fraction, but too many whole digits are an error
+        let third = decimal("0.3333333333333333333333333333");
+        check(third.checked_mul(&third), "0.1111111111111111111111111111");
+        let large = decimal("1e37");
+        assert!(large.checked_mul(&decimal("100")).is_err());
+        assert!(large.checked_div(&decimal("0.01")).is_err());
+        assert!(decimal("1").checked_div(&decimal("0.000")).is_err());
+        assert!(decimal("1").checked_rem(&decimal("0")).is_err());
+    }
+
+    #[test]
+    fn test_exact_ordering() {
+        assert_eq!(decimal("1.50"), decimal("1.5"));
+        assert!(decimal("-0.001") < decimal("0"));
+        assert!(decimal("-2") < decimal("-1.99"));
+        assert!(decimal("10") > decimal("9.999"));
+
+        // The float 0.1 is a little more than the decimal 0.1
+        let float = ExactNumber::from_f64(0.1).unwrap();
+        assert!(ExactNumber::from(&decimal("0.1")) < float);
+        assert!(ExactNumber::from(&decimal("0.1000000000000000056")) > float);
+        assert_eq!(
+            ExactNumber::from_f64(-4.0).unwrap(),
+            ExactNumber::from_i128(-4)
+        );
+        assert_eq!(
+            ExactNumber::from_f64(-0.0).unwrap(),
+            ExactNumber::from_i128(0)
+        );
+        assert!(ExactNumber::from_f64(f64::INFINITY).is_none());
+
+        let ordered = [
+            "-100", "-1.5", "-1", "-0.5", "0", "0.0001", "0.5", "1", "1.05", "99",
+        ];
+        let keys: Vec<Vec<u8>> = ordered
+            .iter()
+            .map(|text| {
+                let mut key = Vec::new();
+                ExactNumber::from(&decimal(text)).encode_key(&mut key);
+                key
+            })
+            .collect();
+        for pair in keys.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} >= {:?}", pair[0], pair[1]);
+        }
+    }
+}
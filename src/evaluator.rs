@@ -1,30 +1,43 @@
+mod cluster;
 mod cursor;
 mod database;
+mod document;
 mod error;
-mod expression;
+pub(crate) mod expression;
+mod pattern;
 mod query;
+mod sequence;
+pub(crate) mod subquery;
 mod table;
-mod utils;
+mod update;
+pub(crate) mod utils;
 
 #[cfg(test)]
 mod tests;
 
 use crate::ast::*;
 use crate::planner::PlanNode;
-use crate::storage::{DEFAULT_DATABASE, StorageBackend};
+use crate::storage::{DEFAULT_DATABASE, ScanProjection, SnapshotId, StorageBackend, StorageError};
+use sequence::Sequence;
 use std::sync::Arc;
 use std::time::Instant;
 
 // Re-export commonly used types for backward compatibility
+pub use cursor::CursorSnapshots;
 pub use error::{EvalError, EvalResult, EvalStats};
 
 /// Main evaluator that orchestrates query execution using specialized processors
 pub struct Evaluator {
     database_ops: database::DatabaseOperations,
+    cluster_ops: cluster::ClusterOperations,
     table_ops: table::TableOperations,
     expression_eval: expression::ExpressionEvaluator,
     query_processor: query::QueryProcessor,
+    storage: Arc<dyn StorageBackend>,
     stats: EvalStats,
+    read_mode: ReadMode,
+    snapshot: Option<SnapshotId>,
+    cursor_snapshots: Option<Arc<CursorSnapshots>>,
     cursor_context: Option<Cursor>,
     skip_context: Option<u32>,
     limit_context: Option<u32>,
@@ -35,16 +48,32 @@ impl Evaluator {
     pub fn new(storage: Arc<dyn StorageBackend>) -> Self {
         Self {
             database_ops: database::DatabaseOperations::new(storage.clone()),
+            cluster_ops: cluster::ClusterOperations::new(storage.clone()),
             table_ops: table::TableOperations::new(storage.clone()),
             expression_eval: expression::ExpressionEvaluator::new(),
-            query_processor: query::QueryProcessor::new(storage),
+            query_processor: query::QueryProcessor::new(storage.clone()),
+            storage,
             stats: EvalStats::new(),
+            read_mode: ReadMode::default(),
+            snapshot: None,
+            cursor_snapshots: None,
             cursor_context: None,
             skip_context: None,
             limit_context: None,
         }
     }
 
+    /// Set how the reads of evaluated plans see writes made while they run
+    pub fn set_read_mode(&mut self, read_mode: ReadMode) {
+        self.read_mode = read_mode;
+    }
+
+    /// Tie the snapshots pinned for cursors to the connection holding `snapshots`: a
+    /// cursor can then only read from a snapshot pinned for that connection
+    pub fn set_cursor_snapshots(&mut self, snapshots: Arc<CursorSnapshots>) {
+        self.cursor_snapshots = Some(snapshots);
+    }
+
     /// Evaluate a query plan and return the result with statistics
     pub async fn eval(&mut self, plan: &PlanNode) -> Result<EvalResult, EvalError> {
         let start = Instant::now();
@@ -52,7 +81,7 @@ impl Evaluator {
         self.skip_context = None;
         self.limit_context = None;
 
-        let result = self.execute_plan(plan).await?;
+        let result = self.execute_pinned(plan).await?;
         self.stats.record_duration(start.elapsed());
 
         Ok(EvalResult::new(result, self.stats.clone()))
@@ -70,12 +99,57 @@ impl Evaluator {
         self.skip_context = None;
         self.limit_context = None;
 
-        let result = self.execute_plan(plan).await?;
+        let result = self.execute_pinned(plan).await?;
         self.stats.record_duration(start.elapsed());
 
         Ok(EvalResult::new(result, self.stats.clone()))
     }
 
+    /// Execute a plan with all of its reads served from one snapshot, unless the read
+    /// mode asks for the latest data or the plan reads no table. A snapshot read keeps
+    /// the snapshot pinned for as long as the result hands out a cursor for the next
+    /// batch, and held by the connection's cursor snapshots, if any, until then.
+    async fn execute_pinned(&mut self, plan: &PlanNode) -> Result<query_result::Result, EvalError> {
+        let cursor_snapshot = self.cursor_context.as_ref().and_then(|c| c.snapshot);
+        if let (Some(snapshot), Some(snapshots)) = (cursor_snapshot, &self.cursor_snapshots) {
+            if !snapshots.holds(snapshot) {
+                return Err(EvalError::StorageError(StorageError::SnapshotNotFound(
+                    snapshot,
+                )));
+            }
+        }
+        self.snapshot = match (cursor_snapshot, self.read_mode) {
+            (Some(snapshot), _) => Some(snapshot),
+            (None, ReadMode::Outdated) => None,
+            (None, _) if !plan.reads_tables() => None,
+            (None, _) => Some(self.storage.create_snapshot().await?),
+        };
+
+        if self.read_mode == ReadMode::Snapshot {
+            if let Some(cursor) = self.cursor_context.as_mut() {
+                cursor.snapshot = self.snapshot;
+            }
+        }
+
+        let result = self.execute_plan(plan).await;
+
+        if let Some(snapshot) = self.snapshot.take() {
+            let continued = result.as_ref().is_ok_and(|result| {
+                Cursor::from_result(result).is_some_and(|c| c.snapshot == Some(snapshot))
+            });
+            match &self.cursor_snapshots {
+                Some(snapshots) if continued => snapshots.hold(snapshot),
+                Some(snapshots) => snapshots.forget(snapshot),
+                None => {}
+            }
+            if !continued {
+                cursor::release_snapshot(self.storage.as_ref(), snapshot).await;
+            }
+        }
+
+        result
+    }
+
     /// Execute a plan node recursively
     async fn execute_plan(&mut self, plan: &PlanNode) -> Result<query_result::Result, EvalError> {
         match plan {
@@ -102,12 +176,37 @@ impl Evaluator {
                     .list_databases(cursor.clone(), &mut self.stats)
                     .await
             }
+            PlanNode::ReplicationStatus { .. } => {
+                self.database_ops.replication_status(&mut self.stats)
+            }
+            PlanNode::ClusterStatus { .. } => self.cluster_ops.status(&mut self.stats),
+            PlanNode::ChangeMembership { change, .. } => {
+                self.cluster_ops
+                    .change_membership(change, &mut self.stats)
+                    .await
+            }
+            PlanNode::TransferLeadership { node_id, .. } => {
+                self.cluster_ops
+                    .transfer_leadership(*node_id, &mut self.stats)
+                    .await
+            }
 
             // Table operations
-            PlanNode::CreateTable { table_ref, .. } => {
+            PlanNode::CreateTable {
+                table_ref,
+                options,
+                shards,
+                ..
+            } => {
                 let database = self.extract_database_name(table_ref);
                 self.table_ops
-                    .create_table(&database, &table_ref.name, &mut self.stats)
+                    .create_table(
+                        &database,
+                        &table_ref.name,
+                        options.as_ref(),
+                        *shards,
+                        &mut self.stats,
+                    )
                     .await
             }
             PlanNode::DropTable { table_ref, .. } => {
@@ -125,28 +224,61 @@ impl Evaluator {
                     .list_tables(&database_ref.name, cursor.clone(), &mut self.stats)
                     .await
             }
+            PlanNode::Analyze {
+                table_ref,
+                sample_size,
+                ..
+            } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .analyze_table(&database, &table_ref.name, *sample_size, &mut self.stats)
+                    .await
+            }
+            PlanNode::Rebalance {
+                table_ref, shards, ..
+            } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .rebalance_table(&database, &table_ref.name, *shards, &mut self.stats)
+                    .await
+            }
+            PlanNode::CreateIndex {
+                table_ref,
+                name,
+                kind,
+                ..
+            } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .create_index(&database, &table_ref.name, name, kind, &mut self.stats)
+                    .await
+            }
+            PlanNode::DropIndex {
+                table_ref, name, ..
+            } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .drop_index(&database, &table_ref.name, name, &mut self.stats)
+                    .await
+            }
+            PlanNode::ListIndexes { table_ref, .. } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .list_indexes(&database, &table_ref.name, &mut self.stats)
+                    .await
+            }
             PlanNode::TableScan {
                 table_ref,
                 cursor,
                 filter,
+                projection,
                 ..
             } => {
                 let database = self.extract_database_name(table_ref);
+                let scan_projection = Self::scan_projection(filter, projection);
 
                 // Create predicate if filter is provided
-                let predicate = filter.as_ref().map(|filter_expr| {
-                    let filter_clone = filter_expr.clone();
-                    Box::new(move |doc: Document| -> bool {
-                        let evaluator = expression::ExpressionEvaluator::new();
-                        match evaluator.evaluate_expression(&filter_clone, &Datum::from(doc)) {
-                            Ok(d) => matches!(d.value, Some(datum::Value::Bool(true))),
-                            Err(err) => {
-                                log::debug!("Error evaluating filter: {err}");
-                                false
-                            }
-                        }
-                    }) as Predicate
-                });
+                let predicate = filter.as_ref().map(Self::filter_predicate);
 
                 // Determine effective cursor with proper limit handling
                 let effective_cursor = self.combine_cursor_with_context(cursor.clone());
@@ -171,6 +303,8 @@ impl Evaluator {
                         effective_cursor,
                         predicate,
                         skip_count,
+                        scan_projection,
+                        self.snapshot,
                         &mut self.stats,
                     )
                     .await
@@ -180,15 +314,27 @@ impl Evaluator {
             PlanNode::Get { table_ref, key, .. } => {
                 let database = self.extract_database_name(table_ref);
                 self.table_ops
-                    .get_document(&database, &table_ref.name, key, &mut self.stats)
+                    .get_document(
+                        &database,
+                        &table_ref.name,
+                        key,
+                        self.snapshot,
+                        &mut self.stats,
+                    )
                     .await
             }
             PlanNode::GetAll {
                 table_ref,
                 keys,
+                key_expressions,
                 cursor,
                 ..
             } => {
+                if !key_expressions.is_empty() {
+                    return Err(EvalError::InvalidArgument(
+                        "GetAll keys with parameters must be bound by a prepared query".to_string(),
+                    ));
+                }
                 let database = self.extract_database_name(table_ref);
                 // Determine effective cursor with proper limit handling
                 let effective_cursor = self.combine_cursor_with_context(cursor.clone());
@@ -213,6 +359,135 @@ impl Evaluator {
                         keys,
                         effective_cursor,
                         skip_count,
+                        self.snapshot,
+                        &mut self.stats,
+                    )
+                    .await
+            }
+            PlanNode::Search {
+                table_ref,
+                index,
+                query,
+                ..
+            } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .search(
+                        &database,
+                        &table_ref.name,
+                        index,
+                        query,
+                        self.limit_context,
+                        self.skip_context,
+                        self.snapshot,
+                        &mut self.stats,
+                    )
+                    .await
+            }
+            PlanNode::GetIntersecting {
+                table_ref,
+                index,
+                geometry,
+                ..
+            } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .get_intersecting(
+                        &database,
+                        &table_ref.name,
+                        index,
+                        geometry,
+                        self.snapshot,
+                        &mut self.stats,
+                    )
+                    .await
+            }
+            PlanNode::GetAllByIndex {
+                table_ref,
+                index,
+                values,
+                value_expressions,
+                ..
+            } => {
+                if !value_expressions.is_empty() {
+                    return Err(EvalError::InvalidArgument(
+                        "GetAll keys with parameters must be bound by a prepared query".to_string(),
+                    ));
+                }
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .get_all_by_index(
+                        &database,
+                        &table_ref.name,
+                        index,
+                        values,
+                        self.snapshot,
+                        &mut self.stats,
+                    )
+                    .await
+            }
+            PlanNode::Between {
+                table_ref,
+                index,
+                lower,
+                upper,
+                ..
+            } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .between(
+                        &database,
+                        &table_ref.name,
+                        index,
+                        lower.as_ref(),
+                        upper.as_ref(),
+                        self.snapshot,
+                        &mut self.stats,
+                    )
+                    .await
+            }
+            PlanNode::GetNearest {
+                table_ref,
+                index,
+                point,
+                max_dist,
+                max_results,
+                ..
+            } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .get_nearest(
+                        &database,
+                        &table_ref.name,
+                        index,
+                        point,
+                        *max_dist,
+                        *max_results,
+                        self.snapshot,
+                        &mut self.stats,
+                    )
+                    .await
+            }
+            PlanNode::Nearest {
+                table_ref,
+                index,
+                vector,
+                k,
+                filter,
+                probes,
+                ..
+            } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .nearest(
+                        &database,
+                        &table_ref.name,
+                        index,
+                        vector,
+                        *k,
+                        *probes,
+                        filter.as_ref().map(Self::filter_predicate),
+                        self.snapshot,
                         &mut self.stats,
                     )
                     .await
@@ -220,25 +495,52 @@ impl Evaluator {
             PlanNode::Insert {
                 table_ref,
                 documents,
+                durability,
                 ..
             } => {
                 let database = self.extract_database_name(table_ref);
                 self.table_ops
-                    .insert_documents(&database, &table_ref.name, documents, &mut self.stats)
+                    .insert_documents(
+                        &database,
+                        &table_ref.name,
+                        documents,
+                        utils::write_durability(*durability),
+                        &mut self.stats,
+                    )
                     .await
             }
 
             // Query processing operations
-            PlanNode::Update { source, patch, .. } => {
+            PlanNode::Update {
+                source,
+                patch,
+                operations,
+                durability,
+                ..
+            } => {
                 let source_result = Box::pin(self.execute_plan(source)).await?;
                 self.query_processor
-                    .update_documents(source_result, patch, source, &mut self.stats)
+                    .update_documents(
+                        source_result,
+                        patch,
+                        operations,
+                        source,
+                        utils::write_durability(*durability),
+                        &mut self.stats,
+                    )
                     .await
             }
-            PlanNode::Delete { source, .. } => {
+            PlanNode::Delete {
+                source, durability, ..
+            } => {
                 let source_result = Box::pin(self.execute_plan(source)).await?;
                 self.query_processor
-                    .delete_documents(source_result, &mut self.stats)
+                    .delete_documents(
+                        source_result,
+                        source,
+                        utils::write_durability(*durability),
+                        &mut self.stats,
+                    )
                     .await
             }
             PlanNode::Filter {
@@ -254,6 +556,30 @@ impl Evaluator {
                     )
                     .await
             }
+            PlanNode::SubqueryFilter {
+                source,
+                predicate,
+                subqueries,
+                slots,
+                ..
+            } => {
+                let mut subqueries = subquery::Subqueries::new(
+                    self.storage.clone(),
+                    self.snapshot,
+                    subqueries,
+                    slots,
+                );
+                let source_result = Box::pin(self.execute_plan(source)).await?;
+                self.query_processor
+                    .filter_documents_with_subqueries(
+                        source_result,
+                        predicate,
+                        &mut subqueries,
+                        self.cursor_context.clone(),
+                        &mut self.stats,
+                    )
+                    .await
+            }
             PlanNode::OrderBy { source, fields, .. } => {
                 let source_result = Box::pin(self.execute_plan(source)).await?;
                 self.query_processor
@@ -338,6 +664,63 @@ impl Evaluator {
                     .count_documents(source_result, &mut self.stats)
                     .await
             }
+
+            // Sequence operations
+            PlanNode::Union {
+                source,
+                other,
+                interleave,
+                ..
+            } => {
+                let source = self.sequence_items(source).await?;
+                let other = self.sequence_items(other).await?;
+                self.query_processor
+                    .union_sequences(source, other, interleave, &mut self.stats)
+                    .await
+            }
+            PlanNode::Slice {
+                source,
+                start,
+                end,
+                left_open,
+                right_closed,
+                ..
+            } => {
+                let items = self.sequence_items(source).await?;
+                self.query_processor
+                    .slice_sequence(
+                        items,
+                        *start,
+                        *end,
+                        *left_open,
+                        *right_closed,
+                        &mut self.stats,
+                    )
+                    .await
+            }
+            PlanNode::Nth { source, index, .. } => {
+                let items = self.sequence_items(source).await?;
+                self.query_processor
+                    .nth_item(items, *index, &mut self.stats)
+                    .await
+            }
+            PlanNode::Sample { source, count, .. } => {
+                let items = self.sequence_items(source).await?;
+                self.query_processor
+                    .sample_sequence(items, *count, &mut self.stats)
+                    .await
+            }
+            PlanNode::IsEmpty { source, .. } => {
+                let items = self.sequence_items(source).await?;
+                self.query_processor.is_empty(items, &mut self.stats).await
+            }
+            PlanNode::OffsetsOf { source, target, .. } => {
+                let items = self.sequence_items(source).await?;
+                self.query_processor
+                    .offsets_of(items, target, &mut self.stats)
+                    .await
+            }
+
             PlanNode::Pluck { source, fields, .. } => {
                 let source_result = Box::pin(self.execute_plan(source)).await?;
                 self.query_processor
@@ -362,11 +745,102 @@ impl Evaluator {
                     .await
             }
 
+            PlanNode::DocumentFunction {
+                source,
+                op,
+                args,
+                fields,
+                ..
+            } => {
+                let source_result = Box::pin(self.execute_plan(source)).await?;
+                self.query_processor
+                    .apply_document_function(
+                        source_result,
+                        self.cursor_context.clone(),
+                        *op,
+                        args,
+                        fields,
+                        &mut self.stats,
+                    )
+                    .await
+            }
+
             // Subqueries
             PlanNode::Subquery { query, .. } => Box::pin(self.execute_plan(query)).await,
         }
     }
 
+    /// Execute the plan of a subquery, reading from the snapshot of the query it is part
+    /// of, and return its result as a value
+    async fn execute_subquery(
+        storage: Arc<dyn StorageBackend>,
+        snapshot: Option<SnapshotId>,
+        plan: &PlanNode,
+    ) -> Result<Datum, EvalError> {
+        let mut evaluator = Self::new(storage);
+        evaluator.snapshot = snapshot;
+        let result = Box::pin(evaluator.execute_plan(plan)).await?;
+        evaluator.query_processor.result_value(result)
+    }
+
+    /// The items of the source of a sequence operation, over its whole sequence rather
+    /// than the batch of a cursor or a limit pushed down for an enclosing operation.
+    /// Tables, lookups by key and unions of them are read only as far as the operation
+    /// consumes them.
+    async fn sequence_items(&mut self, source: &PlanNode) -> Result<Sequence, EvalError> {
+        match source {
+            PlanNode::TableScan {
+                table_ref,
+                filter,
+                projection,
+                ..
+            } => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .scan_sequence(
+                        &database,
+                        &table_ref.name,
+                        filter.as_ref().map(Self::filter_predicate),
+                        Self::scan_projection(filter, projection),
+                        self.snapshot,
+                    )
+                    .await
+            }
+            PlanNode::GetAll {
+                table_ref,
+                keys,
+                key_expressions,
+                ..
+            } if key_expressions.is_empty() => {
+                let database = self.extract_database_name(table_ref);
+                self.table_ops
+                    .get_all_sequence(&database, &table_ref.name, keys, self.snapshot)
+                    .await
+            }
+            PlanNode::Union {
+                source,
+                other,
+                interleave,
+                ..
+            } => {
+                let source = Box::pin(self.sequence_items(source)).await?;
+                let other = Box::pin(self.sequence_items(other)).await?;
+                Ok(sequence::union(source, other, interleave.clone()))
+            }
+            _ => {
+                let cursor_context = self.cursor_context.take();
+                let skip_context = self.skip_context.take();
+                let limit_context = self.limit_context.take();
+                let result = Box::pin(self.execute_plan(source)).await;
+                self.cursor_context = cursor_context;
+                self.skip_context = skip_context;
+                self.limit_context = limit_context;
+                let items = self.query_processor.extract_sequence(result?)?;
+                Ok(sequence::items(items))
+            }
+        }
+    }
+
     /// Extract database name from table reference, using default if not specified
     fn extract_database_name(&self, table_ref: &TableRef) -> String {
         table_ref
@@ -376,6 +850,40 @@ impl Evaluator {
             .unwrap_or_else(|| DEFAULT_DATABASE.to_string())
     }
 
+    /// A storage-side predicate accepting the documents for which `filter` is true. An
+    /// error evaluating it fails the scan.
+    fn filter_predicate(filter: &Expression) -> Predicate {
+        let filter = filter.clone();
+        let evaluator = expression::ExpressionEvaluator::new();
+        Box::new(move |doc: Document| {
+            let d = evaluator.evaluate_expression(&filter, &Datum::from(doc))?;
+            Ok(matches!(d.value, Some(datum::Value::Bool(true))))
+        })
+    }
+
+    /// The fields a table scan decodes for its filter and its pushed-down projection
+    fn scan_projection(
+        filter: &Option<Expression>,
+        projection: &Option<Vec<FieldRef>>,
+    ) -> ScanProjection {
+        ScanProjection {
+            predicate_fields: filter.as_ref().and_then(utils::referenced_fields),
+            output_fields: projection.as_deref().and_then(Self::projected_fields),
+        }
+    }
+
+    /// Top-level fields a table scan must return for a pushed-down projection. The
+    /// primary key is always kept since it drives cursor pagination.
+    fn projected_fields(fields: &[FieldRef]) -> Option<Vec<String>> {
+        let mut names = vec!["id".to_string()];
+        for field in fields {
+            names.push(field.path.first()?.clone());
+        }
+        names.sort_unstable();
+        names.dedup();
+        Some(names)
+    }
+
     /// Get current evaluation statistics
     pub fn get_stats(&self) -> &EvalStats {
         &self.stats
@@ -388,7 +896,10 @@ impl Evaluator {
 
     /// Check if we can push skip/limit down to the source operation
     fn can_push_down_to_source(&self, source: &PlanNode) -> bool {
-        matches!(source, PlanNode::TableScan { .. } | PlanNode::GetAll { .. })
+        matches!(
+            source,
+            PlanNode::TableScan { .. } | PlanNode::GetAll { .. } | PlanNode::Search { .. }
+        )
     }
 
     /// Combine cursor context with skip/limit context
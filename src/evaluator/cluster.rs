@@ -0,0 +1,69 @@
+use crate::ast::{ClusterMember, ClusterStatusResult, cluster_membership, query_result};
+use crate::cluster::{ClusterStatus, Member, MembershipChange};
+use crate::evaluator::error::{EvalError, EvalStats};
+use crate::storage::StorageBackend;
+use std::sync::Arc;
+
+/// Handler for cluster administration
+pub struct ClusterOperations {
+    storage: Arc<dyn StorageBackend>,
+}
+
+impl ClusterOperations {
+    /// Create a new cluster operations handler
+    pub fn new(storage: Arc<dyn StorageBackend>) -> Self {
+        Self { storage }
+    }
+
+    /// Report the node's role, the leader and the members of the cluster
+    pub fn status(&self, stats: &mut EvalStats) -> Result<query_result::Result, EvalError> {
+        let status = self.storage.cluster_status()?;
+        Ok(Self::status_result(status, stats))
+    }
+
+    /// Add a node to the cluster or remove one from it
+    pub async fn change_membership(
+        &self,
+        change: &cluster_membership::Change,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let change = match change {
+            cluster_membership::Change::Add(member) => MembershipChange::Add(Member {
+                id: member.id,
+                address: member.address.clone(),
+            }),
+            cluster_membership::Change::Remove(id) => MembershipChange::Remove(*id),
+        };
+        let status = self.storage.change_membership(change).await?;
+        stats.record_rows_processed(1);
+        Ok(Self::status_result(status, stats))
+    }
+
+    /// Hand the leader role over to another node
+    pub async fn transfer_leadership(
+        &self,
+        node_id: Option<u64>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let status = self.storage.transfer_leadership(node_id).await?;
+        Ok(Self::status_result(status, stats))
+    }
+
+    fn status_result(status: ClusterStatus, stats: &mut EvalStats) -> query_result::Result {
+        let member = |member: Member| ClusterMember {
+            id: member.id,
+            address: member.address,
+        };
+        stats.record_rows_returned(1);
+
+        query_result::Result::ClusterStatus(ClusterStatusResult {
+            node_id: status.node_id,
+            role: status.role.to_string(),
+            term: status.term,
+            leader: status.leader.map(member),
+            commit_index: status.commit_index,
+            applied_index: status.applied_index,
+            members: status.members.into_iter().map(member).collect(),
+        })
+    }
+}
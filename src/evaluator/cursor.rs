@@ -1,4 +1,10 @@
-use crate::ast::{Cursor, SortOptions};
+use crate::ast::{
+    Cursor, SortOptions, document_function_result, pluck_result, query_result, sequence_result,
+    without_result,
+};
+use crate::storage::{SnapshotId, StorageBackend};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
 
 pub const DEFAULT_BATCH_SIZE: u32 = 1000;
 
@@ -8,6 +14,7 @@ impl Cursor {
             start_key,
             batch_size,
             sort: None,
+            snapshot: None,
         }
     }
 
@@ -19,11 +26,12 @@ impl Cursor {
         if let (Some(prev), Some(last_key)) = (prev.as_ref(), last_key) {
             let batch_size = prev.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
             if iterable.len() as u32 >= batch_size {
-                Some(
-                    Self::new(Some(last_key), Some(batch_size))
+                Some(Self {
+                    snapshot: prev.snapshot,
+                    ..Self::new(Some(last_key), Some(batch_size))
                         .with_sort(prev.sort.clone())
-                        .clone(),
-                )
+                        .clone()
+                })
             } else {
                 None
             }
@@ -37,9 +45,87 @@ impl Cursor {
         self
     }
 
+    /// The cursor a result hands back for fetching its next batch, if any.
+    pub fn from_result(result: &query_result::Result) -> Option<&Cursor> {
+        match result {
+            query_result::Result::GetAll(r) => r.cursor.as_ref(),
+            query_result::Result::Table(r) => r.cursor.as_ref(),
+            query_result::Result::Filter(r) => r.cursor.as_ref(),
+            query_result::Result::OrderBy(r) => r.cursor.as_ref(),
+            query_result::Result::Limit(r) => r.cursor.as_ref(),
+            query_result::Result::Skip(r) => r.cursor.as_ref(),
+            query_result::Result::DatabaseList(r) => r.cursor.as_ref(),
+            query_result::Result::TableList(r) => r.cursor.as_ref(),
+            query_result::Result::Pluck(r) => match &r.result {
+                Some(pluck_result::Result::Collection(c)) => c.cursor.as_ref(),
+                _ => None,
+            },
+            query_result::Result::Without(r) => match &r.result {
+                Some(without_result::Result::Collection(c)) => c.cursor.as_ref(),
+                _ => None,
+            },
+            query_result::Result::DocumentFunction(r) => match &r.result {
+                Some(document_function_result::Result::Collection(c)) => c.cursor.as_ref(),
+                _ => None,
+            },
+            query_result::Result::Sequence(r) => match &r.result {
+                Some(sequence_result::Result::Collection(c)) => c.cursor.as_ref(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
     pub fn convert_to_page_params(cursor: Option<&Cursor>) -> (Option<String>, Option<usize>) {
         let start_key = cursor.and_then(|c| c.start_key.clone());
         let limit = cursor.and_then(|c| c.batch_size.map(|b| b as usize));
         (start_key, limit)
     }
 }
+
+/// The snapshots pinned for the cursors of one connection. Only that connection can
+/// read from them, and those its cursors still hold when it closes are released.
+pub struct CursorSnapshots {
+    storage: Arc<dyn StorageBackend>,
+    pinned: Mutex<HashSet<SnapshotId>>,
+}
+
+impl CursorSnapshots {
+    pub fn new(storage: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            storage,
+            pinned: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Keep a snapshot pinned for a cursor of the connection.
+    pub fn hold(&self, snapshot: SnapshotId) {
+        self.pinned.lock().unwrap().insert(snapshot);
+    }
+
+    /// Whether a cursor of the connection holds the snapshot.
+    pub fn holds(&self, snapshot: SnapshotId) -> bool {
+        self.pinned.lock().unwrap().contains(&snapshot)
+    }
+
+    /// Stop holding a snapshot, which no cursor of the connection reads from anymore.
+    pub fn forget(&self, snapshot: SnapshotId) {
+        self.pinned.lock().unwrap().remove(&snapshot);
+    }
+
+    /// Release every snapshot the connection's cursors still hold.
+    pub async fn release_all(&self) {
+        let pinned: Vec<SnapshotId> = self.pinned.lock().unwrap().drain().collect();
+        for snapshot in pinned {
+            release_snapshot(self.storage.as_ref(), snapshot).await;
+        }
+    }
+}
+
+/// Release a snapshot, logging a failure rather than returning it: the snapshot is
+/// released on its idle timeout anyway, so it must not fail the query releasing it.
+pub async fn release_snapshot(storage: &dyn StorageBackend, snapshot: SnapshotId) {
+    if let Err(err) = storage.release_snapshot(snapshot).await {
+        log::warn!("failed to release snapshot {snapshot}: {err}");
+    }
+}
@@ -1,8 +1,10 @@
 use crate::ast::{
-    Cursor, DatabaseCreateResult, DatabaseDropResult, DatabaseListResult, query_result,
+    Cursor, DatabaseCreateResult, DatabaseDropResult, DatabaseListResult, ReplicationStatusResult,
+    query_result,
 };
 use crate::evaluator::error::{EvalError, EvalStats};
 use crate::storage::StorageBackend;
+use crate::storage::replication::ReplicationRole;
 use futures_util::StreamExt;
 use std::sync::Arc;
 
@@ -75,4 +77,30 @@ impl DatabaseOperations {
             cursor: next_cursor,
         }))
     }
+
+    /// Report the replication role and progress of the database
+    pub fn replication_status(
+        &self,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let status = self.storage.replication_status();
+        stats.record_rows_returned(1);
+
+        let lag = status.lag();
+        let (role, primary) = match status.role {
+            ReplicationRole::Primary => ("primary", String::new()),
+            ReplicationRole::Replica { primary } => ("replica", primary),
+            ReplicationRole::Secondary { primary } => ("secondary", primary),
+        };
+        Ok(query_result::Result::ReplicationStatus(
+            ReplicationStatusResult {
+                role: role.to_string(),
+                primary,
+                applied_sequence: status.applied_sequence,
+                primary_sequence: status.primary_sequence,
+                lag,
+                last_contact_ms: status.last_contact.map(|d| d.as_millis() as u64),
+            },
+        ))
+    }
 }
@@ -0,0 +1,292 @@
+//! Document functions: merging, field tests, keys and values, defaults, types and
+//! coercions.
+//!
+//! A literal is an object whose only field is [`LITERAL_FIELD`]. Merged into a
+//! document it replaces the subtree at its place rather than merging into it, and
+//! without a value it removes the field.
+
+use crate::ast::{
+    Datum, DatumArray, DatumObject, Document, FieldRef, NullValue, Time, Uuid, datum,
+    document_op::Operator as DocumentOperator,
+};
+use crate::decimal::Decimal;
+use crate::evaluator::error::EvalError;
+use crate::evaluator::utils::{
+    bool_datum, datum_to_decimal, extract_field_from_ref, insert_field_by_ref, string_datum,
+};
+use crate::{time, uuid};
+
+/// The field marking an object as a literal.
+pub const LITERAL_FIELD: &str = "$literal";
+
+/// Apply a document function to its evaluated arguments, the first being the value it
+/// works on.
+pub fn apply(
+    operator: DocumentOperator,
+    args: &[Datum],
+    fields: &[FieldRef],
+) -> Result<Datum, EvalError> {
+    let arity_ok = match operator {
+        DocumentOperator::Merge => !args.is_empty(),
+        DocumentOperator::Literal => args.len() <= 1,
+        DocumentOperator::Default | DocumentOperator::CoerceTo => args.len() == 2,
+        _ => args.len() == 1,
+    };
+    if !arity_ok {
+        return Err(EvalError::InvalidArgument(format!(
+            "{operator:?} does not take {} arguments",
+            args.len()
+        )));
+    }
+
+    match operator {
+        DocumentOperator::Merge => Ok(args[1..]
+            .iter()
+            .fold(args[0].clone(), |merged, patch| merge(&merged, patch))),
+        DocumentOperator::Literal => Ok(literal(args.first().cloned())),
+        DocumentOperator::HasFields => Ok(bool_datum(has_fields(&args[0], fields))),
+        DocumentOperator::WithFields => Ok(with_fields(&args[0], fields)),
+        DocumentOperator::Keys => {
+            let keys = sorted_fields(&args[0])?
+                .into_iter()
+                .map(|(key, _)| string_datum(key.clone()))
+                .collect();
+            Ok(array_datum(keys))
+        }
+        DocumentOperator::Values => {
+            let values = sorted_fields(&args[0])?
+                .into_iter()
+                .map(|(_, value)| value.clone())
+                .collect();
+            Ok(array_datum(values))
+        }
+        DocumentOperator::Default => Ok(if is_null(&args[0]) {
+            args[1].clone()
+        } else {
+            args[0].clone()
+        }),
+        DocumentOperator::TypeOf => Ok(string_datum(type_name(&args[0]).to_string())),
+        DocumentOperator::CoerceTo => match &args[1].value {
+            Some(datum::Value::String(target)) => coerce_to(&args[0], target),
+            _ => Err(EvalError::InvalidArgument(format!(
+                "expected a type name, got {}",
+                args[1]
+            ))),
+        },
+    }
+}
+
+/// Deep-merge a patch into a value. Objects merge field by field; a literal or any
+/// other patch replaces the value.
+pub fn merge(base: &Datum, patch: &Datum) -> Datum {
+    if let Some(value) = literal_value(patch) {
+        return value.clone();
+    }
+    let Some(datum::Value::Object(patch)) = &patch.value else {
+        return patch.clone();
+    };
+
+    let mut fields = match &base.value {
+        Some(datum::Value::Object(base)) => base.fields.clone(),
+        _ => Document::new(),
+    };
+    for (key, value) in &patch.fields {
+        if literal_value(value).is_some_and(|literal| literal.value.is_none()) {
+            fields.remove(key);
+            continue;
+        }
+        let merged = merge(fields.get(key).unwrap_or(&Datum { value: None }), value);
+        fields.insert(key.clone(), merged);
+    }
+    fields.into()
+}
+
+/// A literal of a value, or of nothing to remove a field.
+fn literal(value: Option<Datum>) -> Datum {
+    Document::from([(
+        LITERAL_FIELD.to_string(),
+        value.unwrap_or(Datum { value: None }),
+    )])
+    .into()
+}
+
+/// The value of a literal, if the datum is one.
+fn literal_value(datum: &Datum) -> Option<&Datum> {
+    match &datum.value {
+        Some(datum::Value::Object(obj)) if obj.fields.len() == 1 => obj.fields.get(LITERAL_FIELD),
+        _ => None,
+    }
+}
+
+/// Whether a value is an object with all the fields, none of them null.
+pub fn has_fields(value: &Datum, fields: &[FieldRef]) -> bool {
+    matches!(value.value, Some(datum::Value::Object(_)))
+        && fields
+            .iter()
+            .all(|field| !is_null(&extract_field_from_ref(value, field)))
+}
+
+/// The fields of an object that has them all, or null.
+pub fn with_fields(value: &Datum, fields: &[FieldRef]) -> Datum {
+    if !has_fields(value, fields) {
+        return null_datum();
+    }
+    let mut output = Document::new();
+    for field in fields {
+        insert_field_by_ref(&mut output, field, extract_field_from_ref(value, field));
+    }
+    output.into()
+}
+
+fn sorted_fields(value: &Datum) -> Result<Vec<(&String, &Datum)>, EvalError> {
+    match &value.value {
+        Some(datum::Value::Object(obj)) => {
+            let mut fields: Vec<_> = obj.fields.iter().collect();
+            fields.sort_by(|a, b| a.0.cmp(b.0));
+            Ok(fields)
+        }
+        _ => Err(EvalError::InvalidArgument(format!(
+            "expected an object, got {value}"
+        ))),
+    }
+}
+
+fn is_null(value: &Datum) -> bool {
+    matches!(value.value, None | Some(datum::Value::Null(_)))
+}
+
+/// The name of a value's type.
+pub fn type_name(value: &Datum) -> &'static str {
+    match &value.value {
+        None | Some(datum::Value::Null(_)) => "NULL",
+        Some(datum::Value::Bool(_)) => "BOOL",
+        Some(datum::Value::Int(_)) | Some(datum::Value::Float(_)) => "NUMBER",
+        Some(datum::Value::Decimal(_)) => "DECIMAL",
+        Some(datum::Value::String(_)) => "STRING",
+        Some(datum::Value::Binary(_)) => "BINARY",
+        Some(datum::Value::Array(_)) => "ARRAY",
+        Some(datum::Value::Object(_)) => "OBJECT",
+        Some(datum::Value::Geometry(_)) => "GEOMETRY",
+        Some(datum::Value::Time(_)) => "TIME",
+        Some(datum::Value::Uuid(_)) => "UUID",
+    }
+}
+
+/// Convert a value to the type of the given name, in any case.
+///
+/// Strings convert to and from numbers, decimals, times, UUIDs and binary, numbers to
+/// decimals and times, UUIDs to and from binary, and objects to and from arrays of
+/// key and value pairs. Every value converts to its own type.
+pub fn coerce_to(value: &Datum, target: &str) -> Result<Datum, EvalError> {
+    let target = target.to_ascii_uppercase();
+    if type_name(value) == target {
+        return Ok(value.clone());
+    }
+    let invalid = || EvalError::InvalidCoercion(format!("cannot coerce {value} to {target}"));
+
+    let coerced = match (target.as_str(), &value.value) {
+        ("STRING", Some(datum::Value::Binary(bytes))) => {
+            datum::Value::String(String::from_utf8(bytes.clone()).map_err(|_| invalid())?)
+        }
+        (
+            "STRING",
+            Some(
+                v @ (datum::Value::Bool(_)
+                | datum::Value::Int(_)
+                | datum::Value::Float(_)
+                | datum::Value::Decimal(_)
+                | datum::Value::Time(_)
+                | datum::Value::Uuid(_)),
+            ),
+        ) => datum::Value::String(v.to_string()),
+        ("NUMBER", Some(datum::Value::String(text))) => {
+            let text = text.trim();
+            match text.parse::<i64>() {
+                Ok(int) => datum::Value::Int(int),
+                Err(_) => match text.parse::<f64>() {
+                    Ok(float) if float.is_finite() => datum::Value::Float(float),
+                    _ => return Err(invalid()),
+                },
+            }
+        }
+        ("NUMBER", Some(datum::Value::Decimal(decimal))) => {
+            let decimal = Decimal::parse(&decimal.value)?;
+            match decimal.to_i64() {
+                Some(int) => datum::Value::Int(int),
+                None => datum::Value::Float(decimal.to_f64()),
+            }
+        }
+        ("DECIMAL", Some(datum::Value::String(text))) => {
+            decimal_value(Decimal::parse(text.trim())?)
+        }
+        ("DECIMAL", Some(datum::Value::Int(_) | datum::Value::Float(_))) => {
+            decimal_value(datum_to_decimal(value)?)
+        }
+        ("TIME", Some(datum::Value::String(text))) => {
+            datum::Value::Time(time::parse_iso8601(text)?)
+        }
+        ("TIME", Some(datum::Value::Int(_) | datum::Value::Float(_))) => {
+            datum::Value::Time(time_from_seconds(value)?)
+        }
+        ("UUID", Some(datum::Value::String(text))) => datum::Value::Uuid(uuid::parse(text)?),
+        ("UUID", Some(datum::Value::Binary(bytes))) => datum::Value::Uuid(uuid::from_bytes(bytes)?),
+        ("BINARY", Some(datum::Value::String(text))) => datum::Value::Binary(text.clone().into()),
+        ("BINARY", Some(datum::Value::Uuid(Uuid { value }))) => datum::Value::Binary(value.clone()),
+        ("ARRAY", Some(datum::Value::Object(_))) => {
+            let pairs = sorted_fields(value)?
+                .into_iter()
+                .map(|(key, value)| array_datum(vec![string_datum(key.clone()), value.clone()]))
+                .collect();
+            return Ok(array_datum(pairs));
+        }
+        ("OBJECT", Some(datum::Value::Array(array))) => {
+            let mut fields = Document::new();
+            for pair in &array.items {
+                match &pair.value {
+                    Some(datum::Value::Array(DatumArray { items, .. })) if items.len() == 2 => {
+                        let Some(datum::Value::String(key)) = &items[0].value else {
+                            return Err(invalid());
+                        };
+                        fields.insert(key.clone(), items[1].clone());
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+            datum::Value::Object(DatumObject { fields })
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(Datum {
+        value: Some(coerced),
+    })
+}
+
+fn decimal_value(decimal: Decimal) -> datum::Value {
+    datum::Value::Decimal(crate::ast::Decimal {
+        value: decimal.to_string(),
+    })
+}
+
+fn time_from_seconds(value: &Datum) -> Result<Time, EvalError> {
+    let seconds = match value.value {
+        Some(datum::Value::Int(int)) => int as f64,
+        Some(datum::Value::Float(float)) => float,
+        _ => return Err(EvalError::TypeMismatch),
+    };
+    Ok(time::from_epoch_seconds(seconds)?)
+}
+
+fn array_datum(items: Vec<Datum>) -> Datum {
+    Datum {
+        value: Some(datum::Value::Array(DatumArray {
+            items,
+            element_type: String::new(),
+        })),
+    }
+}
+
+fn null_datum() -> Datum {
+    Datum {
+        value: Some(datum::Value::Null(NullValue::NullValue.into())),
+    }
+}
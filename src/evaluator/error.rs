@@ -1,5 +1,10 @@
 use crate::ast::Datum;
+use crate::decimal::DecimalError;
+use crate::geo::GeometryError;
+use crate::planner::PlanError;
 use crate::storage::StorageError;
+use crate::time::TimeError;
+use crate::uuid::UuidError;
 
 /// Evaluation errors that can occur during query execution
 #[derive(Debug)]
@@ -42,6 +47,38 @@ pub enum EvalError {
     InvalidLimit,
     /// Invalid skip value
     InvalidSkip,
+    /// Invalid geometry or geospatial operand
+    InvalidGeometry(String),
+    /// Invalid time or time operand
+    InvalidTime(String),
+    /// Invalid decimal, or a decimal result out of range
+    InvalidDecimal(String),
+    /// Integer arithmetic overflowed
+    ArithmeticOverflow,
+    /// Invalid UUID
+    InvalidUuid(String),
+    /// Invalid argument to a function
+    InvalidArgument(String),
+    /// Value cannot be converted to the requested type
+    InvalidCoercion(String),
+    /// Update cannot be applied to a document
+    InvalidUpdate(String),
+    /// Operation needs a value but was given null
+    NullValue(String),
+    /// Error raised by the query itself
+    UserError(String),
+    /// Index outside of a sequence
+    IndexOutOfBounds(i64),
+    /// A subquery could not be planned
+    PlanError(PlanError),
+}
+
+impl EvalError {
+    /// Whether the error comes from a missing field or a null value, which a default
+    /// replaces.
+    pub fn is_non_existence(&self) -> bool {
+        matches!(self, Self::MissingField(_) | Self::NullValue(_))
+    }
 }
 
 impl std::fmt::Display for EvalError {
@@ -66,6 +103,18 @@ impl std::fmt::Display for EvalError {
             Self::InvalidComparison => write!(f, "Invalid comparison"),
             Self::InvalidLimit => write!(f, "Invalid limit value"),
             Self::InvalidSkip => write!(f, "Invalid skip value"),
+            Self::InvalidGeometry(msg) => write!(f, "Invalid geometry: {msg}"),
+            Self::InvalidTime(msg) => write!(f, "Invalid time: {msg}"),
+            Self::InvalidDecimal(msg) => write!(f, "Invalid decimal: {msg}"),
+            Self::ArithmeticOverflow => write!(f, "Arithmetic overflow"),
+            Self::InvalidUuid(msg) => write!(f, "Invalid UUID: {msg}"),
+            Self::InvalidArgument(msg) => write!(f, "Invalid argument: {msg}"),
+            Self::InvalidCoercion(msg) => write!(f, "Invalid coercion: {msg}"),
+            Self::InvalidUpdate(msg) => write!(f, "Invalid update: {msg}"),
+            Self::NullValue(msg) => write!(f, "Null value: {msg}"),
+            Self::UserError(msg) => write!(f, "{msg}"),
+            Self::IndexOutOfBounds(index) => write!(f, "Index out of bounds: {index}"),
+            Self::PlanError(e) => write!(f, "Subquery planning error: {e}"),
         }
     }
 }
@@ -74,6 +123,7 @@ impl std::error::Error for EvalError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::StorageError(e) => Some(e),
+            Self::PlanError(e) => Some(e),
             _ => None,
         }
     }
@@ -81,7 +131,45 @@ impl std::error::Error for EvalError {
 
 impl From<StorageError> for EvalError {
     fn from(e: StorageError) -> Self {
-        Self::StorageError(e)
+        match e {
+            StorageError::InvalidUpdate(msg) => Self::InvalidUpdate(msg),
+            // A filter evaluated in storage fails with the evaluator's own error
+            StorageError::PredicateFailed(e) => match e.downcast::<Self>() {
+                Ok(e) => *e,
+                Err(e) => Self::StorageError(StorageError::PredicateFailed(e)),
+            },
+            e => Self::StorageError(e),
+        }
+    }
+}
+
+impl From<PlanError> for EvalError {
+    fn from(e: PlanError) -> Self {
+        Self::PlanError(e)
+    }
+}
+
+impl From<GeometryError> for EvalError {
+    fn from(e: GeometryError) -> Self {
+        Self::InvalidGeometry(e.0)
+    }
+}
+
+impl From<TimeError> for EvalError {
+    fn from(e: TimeError) -> Self {
+        Self::InvalidTime(e.0)
+    }
+}
+
+impl From<DecimalError> for EvalError {
+    fn from(e: DecimalError) -> Self {
+        Self::InvalidDecimal(e.0)
+    }
+}
+
+impl From<UuidError> for EvalError {
+    fn from(e: UuidError) -> Self {
+        Self::InvalidUuid(e.0)
     }
 }
 
@@ -1,21 +1,31 @@
-use pcre2::bytes::Regex;
-
 use crate::ast::{
-    BinaryOp, Datum, Expression, FieldRef, MatchExpr, UnaryOp, Variable,
-    binary_op::Operator as BinaryOperator, datum, expression, unary_op::Operator as UnaryOperator,
+    BinaryOp, BranchExpr, Datum, DocumentOp, ErrorExpr, Expression, FieldRef, GeoOp, MatchExpr,
+    NullValue, Time, TimeOp, UnaryOp, Variable, binary_op::Operator as BinaryOperator, datum,
+    document_op::Operator as DocumentOperator, expression, geo_op::Operator as GeoOperator,
+    time_op::Operator as TimeOperator, unary_op::Operator as UnaryOperator,
 };
+use crate::evaluator::document;
 use crate::evaluator::error::EvalError;
+use crate::evaluator::pattern::{MatchResult, PatternCache};
 use crate::evaluator::utils::{
-    bool_datum, compare_values, datum_to_bool, datums_equal, extract_field_from_ref,
+    bool_datum, compare_values, datum_to_bool, datum_to_decimal, datums_equal,
+    extract_field_from_ref, number_to_f64,
 };
+use crate::geo::Shape;
+use crate::time;
 
 /// Handler for evaluating expressions based on the proto-defined Expression structure
-pub struct ExpressionEvaluator;
+pub struct ExpressionEvaluator {
+    /// Patterns of match expressions compiled so far
+    patterns: PatternCache,
+}
 
 impl ExpressionEvaluator {
     /// Create a new expression evaluator
     pub fn new() -> Self {
-        Self
+        Self {
+            patterns: PatternCache::default(),
+        }
     }
 
     /// Evaluate an expression against a datum context
@@ -32,6 +42,11 @@ impl ExpressionEvaluator {
             Some(expression::Expr::Unary(op)) => self.evaluate_unary_operation(op, context),
             Some(expression::Expr::Match(ex)) => self.evaluate_match_expression(ex, context),
             Some(expression::Expr::Subquery(q)) => self.evaluate_simple_subquery(q, context),
+            Some(expression::Expr::Geo(op)) => self.evaluate_geo_operation(op, context),
+            Some(expression::Expr::Time(op)) => self.evaluate_time_operation(op, context),
+            Some(expression::Expr::Document(op)) => self.evaluate_document_operation(op, context),
+            Some(expression::Expr::Branch(branch)) => self.evaluate_branch(branch, context),
+            Some(expression::Expr::Error(error)) => self.evaluate_error(error, context),
             None => Err(EvalError::InvalidExpression),
         }
     }
@@ -41,10 +56,24 @@ impl ExpressionEvaluator {
         extract_field_from_ref(context, field_ref)
     }
 
+    /// Evaluate an operand that needs a value, failing on a missing field or null
+    fn evaluate_operand(&self, expr: &Expression, context: &Datum) -> Result<Datum, EvalError> {
+        let value = self.evaluate_expression(expr, context)?;
+        match (&value.value, &expr.expr) {
+            (None, Some(expression::Expr::Field(field_ref))) => {
+                Err(EvalError::MissingField(field_ref.to_string()))
+            }
+            (None | Some(datum::Value::Null(_)), _) => Err(EvalError::NullValue(
+                "expected a value, got null".to_string(),
+            )),
+            _ => Ok(value),
+        }
+    }
+
     /// Evaluate a variable expression
     fn evaluate_variable(&self, variable: &Variable, context: &Datum) -> Result<Datum, EvalError> {
-        // Variables would typically be resolved from some context/scope
-        // For now, we'll treat them as field references with the variable name
+        // The variables of table subqueries are bound to literals before they run, so
+        // the ones left name fields of the document itself
         let field_ref = FieldRef {
             path: vec![variable.name.clone()],
             separator: String::new(),
@@ -58,12 +87,24 @@ impl ExpressionEvaluator {
         binary_op: &BinaryOp,
         context: &Datum,
     ) -> Result<Datum, EvalError> {
-        let left = self.evaluate_expression(binary_op.left.as_ref().unwrap(), context)?;
-        let right = self.evaluate_expression(binary_op.right.as_ref().unwrap(), context)?;
-
         let operator =
             BinaryOperator::try_from(binary_op.op).map_err(|_| EvalError::InvalidExpression)?;
 
+        // Arithmetic needs values on both sides, while comparisons order null first
+        let operand = |side: &Option<Box<Expression>>| {
+            let side = side.as_ref().ok_or(EvalError::InvalidExpression)?;
+            match operator {
+                BinaryOperator::Add
+                | BinaryOperator::Sub
+                | BinaryOperator::Mul
+                | BinaryOperator::Div
+                | BinaryOperator::Mod => self.evaluate_operand(side, context),
+                _ => self.evaluate_expression(side, context),
+            }
+        };
+        let left = operand(&binary_op.left)?;
+        let right = operand(&binary_op.right)?;
+
         self.perform_binary_operation(&left, &operator, &right)
     }
 
@@ -87,16 +128,227 @@ impl ExpressionEvaluator {
         match_expr: &MatchExpr,
         context: &Datum,
     ) -> Result<Datum, EvalError> {
-        let value = self.evaluate_expression(match_expr.value.as_ref().unwrap(), context)?;
+        let value = self.evaluate_expression(
+            match_expr
+                .value
+                .as_ref()
+                .ok_or(EvalError::InvalidExpression)?,
+            context,
+        )?;
+        let Some(datum::Value::String(text)) = &value.value else {
+            return Err(EvalError::InvalidMatchValue(value));
+        };
+
+        let regex = self.patterns.get(&match_expr.pattern, &match_expr.flags)?;
+        if !match_expr.capture {
+            let is_match = regex.is_match(text.as_bytes()).map_err(|e| {
+                EvalError::InvalidMatchPattern(format!("{}: {e}", match_expr.pattern))
+            })?;
+            return Ok(bool_datum(is_match));
+        }
+        Ok(match MatchResult::find(&regex, text)? {
+            Some(result) => result.into_datum(),
+            None => Datum {
+                value: Some(datum::Value::Null(NullValue::NullValue.into())),
+            },
+        })
+    }
+
+    /// Evaluate a geospatial operation between two geometries
+    fn evaluate_geo_operation(&self, geo_op: &GeoOp, context: &Datum) -> Result<Datum, EvalError> {
+        let shape = |side: &Option<Box<Expression>>| -> Result<Shape, EvalError> {
+            let value =
+                self.evaluate_operand(side.as_ref().ok_or(EvalError::InvalidExpression)?, context)?;
+            match &value.value {
+                Some(datum::Value::Geometry(geometry)) => Ok(Shape::try_from(geometry)?),
+                _ => Err(EvalError::InvalidGeometry(format!(
+                    "expected a geometry, got {value}"
+                ))),
+            }
+        };
+        let left = shape(&geo_op.left)?;
+        let right = shape(&geo_op.right)?;
+
+        match GeoOperator::try_from(geo_op.op).map_err(|_| EvalError::InvalidExpression)? {
+            GeoOperator::Distance => Ok(Datum {
+                value: Some(datum::Value::Float(left.distance(&right))),
+            }),
+            GeoOperator::Intersects => Ok(bool_datum(left.intersects(&right))),
+            GeoOperator::Includes => Ok(bool_datum(left.includes(&right)?)),
+        }
+    }
+
+    /// Evaluate a time constructor, accessor or arithmetic operation
+    fn evaluate_time_operation(
+        &self,
+        time_op: &TimeOp,
+        context: &Datum,
+    ) -> Result<Datum, EvalError> {
+        let operator =
+            TimeOperator::try_from(time_op.op).map_err(|_| EvalError::InvalidExpression)?;
+        let args = time_op
+            .args
+            .iter()
+            .map(|arg| self.evaluate_operand(arg, context))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let arity = match operator {
+            TimeOperator::Now => 0,
+            TimeOperator::InTimezone | TimeOperator::Add | TimeOperator::Sub => 2,
+            TimeOperator::During => 3,
+            _ => 1,
+        };
+        if args.len() != arity {
+            return Err(EvalError::InvalidTime(format!(
+                "{operator:?} takes {arity} arguments, got {}",
+                args.len()
+            )));
+        }
+
+        let time_arg = |index: usize| -> Result<&Time, EvalError> {
+            match &args[index].value {
+                Some(datum::Value::Time(time)) => Ok(time),
+                _ => Err(EvalError::InvalidTime(format!(
+                    "expected a time, got {}",
+                    args[index]
+                ))),
+            }
+        };
+        let number_arg = |index: usize| -> Result<f64, EvalError> {
+            match &args[index].value {
+                Some(datum::Value::Int(value)) => Ok(*value as f64),
+                Some(datum::Value::Float(value)) => Ok(*value),
+                _ => Err(EvalError::InvalidTime(format!(
+                    "expected a number of seconds, got {}",
+                    args[index]
+                ))),
+            }
+        };
+        let string_arg = |index: usize| -> Result<&str, EvalError> {
+            match &args[index].value {
+                Some(datum::Value::String(value)) => Ok(value),
+                _ => Err(EvalError::InvalidTime(format!(
+                    "expected a string, got {}",
+                    args[index]
+                ))),
+            }
+        };
+        let time_datum = |time: Time| Datum {
+            value: Some(datum::Value::Time(time)),
+        };
+        let int_datum = |value: i64| Datum {
+            value: Some(datum::Value::Int(value)),
+        };
+        let float_datum = |value: f64| Datum {
+            value: Some(datum::Value::Float(value)),
+        };
+
+        match operator {
+            TimeOperator::Now => Ok(time_datum(time::now())),
+            TimeOperator::EpochTime => Ok(time_datum(time::from_epoch_seconds(number_arg(0)?)?)),
+            TimeOperator::Iso8601 => Ok(time_datum(time::parse_iso8601(string_arg(0)?)?)),
+            TimeOperator::Year => Ok(int_datum(time::civil(time_arg(0)?).year)),
+            TimeOperator::Month => Ok(int_datum(time::civil(time_arg(0)?).month.into())),
+            TimeOperator::Day => Ok(int_datum(time::civil(time_arg(0)?).day.into())),
+            TimeOperator::DayOfWeek => Ok(int_datum(time::civil(time_arg(0)?).day_of_week.into())),
+            TimeOperator::DayOfYear => Ok(int_datum(time::civil(time_arg(0)?).day_of_year.into())),
+            TimeOperator::Hours => Ok(int_datum(time::civil(time_arg(0)?).hours.into())),
+            TimeOperator::Minutes => Ok(int_datum(time::civil(time_arg(0)?).minutes.into())),
+            TimeOperator::Seconds => Ok(float_datum(time::civil(time_arg(0)?).seconds)),
+            TimeOperator::Timezone => Ok(Datum {
+                value: Some(datum::Value::String(time::format_offset(
+                    time_arg(0)?.offset_minutes,
+                ))),
+            }),
+            TimeOperator::Date => Ok(time_datum(time::date(time_arg(0)?))),
+            TimeOperator::TimeOfDay => Ok(float_datum(
+                time::civil(time_arg(0)?).micros_of_day as f64 / time::MICROS_PER_SECOND as f64,
+            )),
+            TimeOperator::ToEpochTime => Ok(float_datum(time::to_epoch_seconds(time_arg(0)?))),
+            TimeOperator::ToIso8601 => Ok(Datum {
+                value: Some(datum::Value::String(time::format_iso8601(time_arg(0)?))),
+            }),
+            TimeOperator::InTimezone => {
+                let offset = time::parse_offset(string_arg(1)?)?;
+                Ok(time_datum(time::in_timezone(time_arg(0)?, offset)))
+            }
+            TimeOperator::Add => Ok(time_datum(time::add_seconds(time_arg(0)?, number_arg(1)?)?)),
+            TimeOperator::Sub => match &args[1].value {
+                Some(datum::Value::Time(other)) => {
+                    Ok(float_datum(time::seconds_between(other, time_arg(0)?)))
+                }
+                _ => Ok(time_datum(time::add_seconds(
+                    time_arg(0)?,
+                    -number_arg(1)?,
+                )?)),
+            },
+            TimeOperator::During => {
+                let (time, start, end) = (time_arg(0)?, time_arg(1)?, time_arg(2)?);
+                Ok(bool_datum(
+                    start.epoch_micros <= time.epoch_micros && time.epoch_micros < end.epoch_micros,
+                ))
+            }
+        }
+    }
+
+    /// Evaluate a document function
+    fn evaluate_document_operation(
+        &self,
+        document_op: &DocumentOp,
+        context: &Datum,
+    ) -> Result<Datum, EvalError> {
+        let operator =
+            DocumentOperator::try_from(document_op.op).map_err(|_| EvalError::InvalidExpression)?;
+        if let (DocumentOperator::Default, [value, fallback]) =
+            (operator, document_op.args.as_slice())
+        {
+            return match self.evaluate_expression(value, context) {
+                Ok(value) if !matches!(value.value, None | Some(datum::Value::Null(_))) => {
+                    Ok(value)
+                }
+                Ok(_) => self.evaluate_expression(fallback, context),
+                Err(err) if err.is_non_existence() => self.evaluate_expression(fallback, context),
+                Err(err) => Err(err),
+            };
+        }
+        let args = document_op
+            .args
+            .iter()
+            .map(|arg| self.evaluate_expression(arg, context))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        // Simple pattern matching - could be extended for regex patterns
-        match (&value.value, &match_expr.pattern, &match_expr.flags) {
-            (Some(datum::Value::String(text)), pattern, flags) => {
-                let is_match = self.matches_pattern(text, pattern, flags);
-                Ok(bool_datum(is_match))
+        document::apply(operator, &args, &document_op.fields)
+    }
+
+    /// Evaluate the value of the first case whose condition holds, or the otherwise value
+    fn evaluate_branch(&self, branch: &BranchExpr, context: &Datum) -> Result<Datum, EvalError> {
+        for case in &branch.cases {
+            let condition = case
+                .condition
+                .as_ref()
+                .ok_or(EvalError::InvalidExpression)?;
+            if datum_to_bool(&self.evaluate_expression(condition, context)?) {
+                let value = case.value.as_ref().ok_or(EvalError::InvalidExpression)?;
+                return self.evaluate_expression(value, context);
             }
-            _ => Err(EvalError::InvalidMatchValue(value)),
         }
+        let otherwise = branch
+            .otherwise
+            .as_ref()
+            .ok_or(EvalError::InvalidExpression)?;
+        self.evaluate_expression(otherwise, context)
+    }
+
+    /// Abort with the error the expression raises
+    fn evaluate_error(&self, error: &ErrorExpr, context: &Datum) -> Result<Datum, EvalError> {
+        let message = match &error.message {
+            Some(message) => self.evaluate_expression(message, context)?,
+            None => return Err(EvalError::UserError(String::new())),
+        };
+        Err(EvalError::UserError(match message.value {
+            Some(datum::Value::String(message)) => message,
+            _ => message.to_string(),
+        }))
     }
 
     /// Perform a binary operation between two datums
@@ -118,9 +370,84 @@ impl ExpressionEvaluator {
             // Logical operators
             BinaryOperator::And => self.perform_logical_and(left, right),
             BinaryOperator::Or => self.perform_logical_or(left, right),
+
+            // Arithmetic operators
+            BinaryOperator::Add
+            | BinaryOperator::Sub
+            | BinaryOperator::Mul
+            | BinaryOperator::Div
+            | BinaryOperator::Mod => self.perform_arithmetic(left, operator, right),
         }
     }
 
+    /// Perform an arithmetic operation on two numbers
+    ///
+    /// A decimal on either side makes the result a decimal, the other side converted,
+    /// and otherwise a float makes it a float. Integers give integers, except through
+    /// division, which gives a float.
+    fn perform_arithmetic(
+        &self,
+        left: &Datum,
+        operator: &BinaryOperator,
+        right: &Datum,
+    ) -> Result<Datum, EvalError> {
+        let value = match (&left.value, &right.value) {
+            (Some(datum::Value::Decimal(_)), Some(_))
+            | (Some(_), Some(datum::Value::Decimal(_))) => {
+                let (a, b) = (datum_to_decimal(left)?, datum_to_decimal(right)?);
+                let result = match operator {
+                    BinaryOperator::Div | BinaryOperator::Mod if b.is_zero() => {
+                        return Err(EvalError::DivisionByZero);
+                    }
+                    BinaryOperator::Add => a.checked_add(&b)?,
+                    BinaryOperator::Sub => a.checked_sub(&b)?,
+                    BinaryOperator::Mul => a.checked_mul(&b)?,
+                    BinaryOperator::Div => a.checked_div(&b)?,
+                    BinaryOperator::Mod => a.checked_rem(&b)?,
+                    _ => return Err(EvalError::UnsupportedOperation),
+                };
+                datum::Value::Decimal(crate::ast::Decimal {
+                    value: result.to_string(),
+                })
+            }
+            (Some(datum::Value::Int(a)), Some(datum::Value::Int(b))) => match operator {
+                BinaryOperator::Div | BinaryOperator::Mod if *b == 0 => {
+                    return Err(EvalError::DivisionByZero);
+                }
+                BinaryOperator::Div => datum::Value::Float(*a as f64 / *b as f64),
+                _ => {
+                    let result = match operator {
+                        BinaryOperator::Add => a.checked_add(*b),
+                        BinaryOperator::Sub => a.checked_sub(*b),
+                        BinaryOperator::Mul => a.checked_mul(*b),
+                        BinaryOperator::Mod => a.checked_rem(*b),
+                        _ => return Err(EvalError::UnsupportedOperation),
+                    };
+                    datum::Value::Int(result.ok_or(EvalError::ArithmeticOverflow)?)
+                }
+            },
+            (
+                Some(a @ (datum::Value::Int(_) | datum::Value::Float(_))),
+                Some(b @ (datum::Value::Int(_) | datum::Value::Float(_))),
+            ) => {
+                let (a, b) = (number_to_f64(a), number_to_f64(b));
+                datum::Value::Float(match operator {
+                    BinaryOperator::Div | BinaryOperator::Mod if b == 0.0 => {
+                        return Err(EvalError::DivisionByZero);
+                    }
+                    BinaryOperator::Add => a + b,
+                    BinaryOperator::Sub => a - b,
+                    BinaryOperator::Mul => a * b,
+                    BinaryOperator::Div => a / b,
+                    BinaryOperator::Mod => a % b,
+                    _ => return Err(EvalError::UnsupportedOperation),
+                })
+            }
+            _ => return Err(EvalError::TypeMismatch),
+        };
+        Ok(Datum { value: Some(value) })
+    }
+
     /// Perform a unary operation on a datum
     pub fn perform_unary_operation(
         &self,
@@ -176,25 +503,12 @@ impl ExpressionEvaluator {
             Some(crate::ast::query::Kind::Expression(expr)) => {
                 self.evaluate_expression(expr, context)
             }
+            // Table subqueries read storage, so they are only run by filters, which
+            // resolve them before evaluating their predicates
             _ => Err(EvalError::UnsupportedOperation),
         }
     }
 
-    /// Simple pattern matching for match expressions
-    fn matches_pattern(&self, text: &str, pattern: &str, flags: &str) -> bool {
-        let mut pattern_str = String::from(flags);
-        pattern_str.push_str(pattern);
-
-        // Compile the regex
-        match Regex::new(&pattern_str) {
-            Ok(re) => re.is_match(text.as_bytes()).unwrap_or(false),
-            Err(err) => {
-                log::debug!("Regex compile error: {err}");
-                false
-            }
-        }
-    }
-
     /// Check if an expression evaluates to a boolean value
     #[allow(clippy::only_used_in_recursion)]
     pub fn is_boolean_expression(&self, expr: &Expression) -> bool {
@@ -219,7 +533,35 @@ impl ExpressionEvaluator {
             Some(expression::Expr::Unary(unary_op)) => {
                 matches!(UnaryOperator::try_from(unary_op.op), Ok(UnaryOperator::Not))
             }
-            Some(expression::Expr::Match(_)) => true,
+            Some(expression::Expr::Match(match_expr)) => !match_expr.capture,
+            Some(expression::Expr::Geo(geo_op)) => {
+                !matches!(GeoOperator::try_from(geo_op.op), Ok(GeoOperator::Distance))
+            }
+            Some(expression::Expr::Time(time_op)) => {
+                matches!(TimeOperator::try_from(time_op.op), Ok(TimeOperator::During))
+            }
+            Some(expression::Expr::Document(document_op)) => {
+                match DocumentOperator::try_from(document_op.op) {
+                    Ok(DocumentOperator::HasFields) => true,
+                    Ok(DocumentOperator::Default) => document_op
+                        .args
+                        .iter()
+                        .all(|arg| self.is_boolean_expression(arg)),
+                    _ => false,
+                }
+            }
+            Some(expression::Expr::Branch(branch)) => {
+                branch.cases.iter().all(|case| {
+                    case.value
+                        .as_ref()
+                        .is_some_and(|v| self.is_boolean_expression(v))
+                }) && branch
+                    .otherwise
+                    .as_ref()
+                    .is_some_and(|v| self.is_boolean_expression(v))
+            }
+            // An error never produces a value, so it stands in for any
+            Some(expression::Expr::Error(_)) => true,
             Some(expression::Expr::Subquery(query)) => {
                 // Recursively check if the subquery contains a boolean expression
                 match &query.kind {
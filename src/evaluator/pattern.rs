@@ -0,0 +1,222 @@
+//! Regular expressions of match expressions.
+//!
+//! Patterns are PCRE2 regular expressions, compiled once per query. Flags are letters
+//! for PCRE2 options: `i` ignores case, `m` makes `^` and `$` match at line breaks, `s`
+//! lets `.` match line breaks and `x` ignores whitespace and comments in the pattern.
+
+use crate::ast::{Datum, DatumArray, Document, NullValue, datum};
+use crate::evaluator::error::EvalError;
+use crate::evaluator::utils::{int_datum, string_datum};
+use pcre2::bytes::{Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Compiled patterns, by pattern and flags
+#[derive(Default)]
+pub struct PatternCache {
+    patterns: Mutex<HashMap<(String, String), Arc<Regex>>>,
+}
+
+impl PatternCache {
+    /// The compiled pattern, compiling it on first use
+    pub fn get(&self, pattern: &str, flags: &str) -> Result<Arc<Regex>, EvalError> {
+        let key = (pattern.to_string(), flags.to_string());
+        let mut patterns = self.patterns.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(regex) = patterns.get(&key) {
+            return Ok(regex.clone());
+        }
+        let regex = Arc::new(compile(pattern, flags)?);
+        patterns.insert(key, regex.clone());
+        Ok(regex)
+    }
+}
+
+/// Compile a pattern with its flags as options
+fn compile(pattern: &str, flags: &str) -> Result<Regex, EvalError> {
+    let mut builder = RegexBuilder::new();
+    builder.utf(true).ucp(true).jit_if_available(true);
+    for flag in flags.chars() {
+        match flag {
+            'i' => builder.caseless(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dotall(true),
+            'x' => builder.extended(true),
+            flag => {
+                return Err(EvalError::InvalidMatchPattern(format!(
+                    "unknown flag '{flag}'"
+                )));
+            }
+        };
+    }
+    builder
+        .build(pattern)
+        .map_err(|e| EvalError::InvalidMatchPattern(format!("{pattern}: {e}")))
+}
+
+/// A part of the text matched by a pattern or one of its groups, with offsets in
+/// characters
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The first match of a pattern in a text
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchResult {
+    /// The whole match
+    pub matched: Span,
+    /// Each group of the pattern in order, if it took part in the match
+    pub groups: Vec<Option<Span>>,
+    /// The names of the groups, for those that have one
+    pub names: Vec<Option<String>>,
+}
+
+impl MatchResult {
+    /// The first match of a pattern in a text, if there is one
+    pub fn find(regex: &Regex, text: &str) -> Result<Option<Self>, EvalError> {
+        let captures = regex
+            .captures(text.as_bytes())
+            .map_err(|e| EvalError::InvalidMatchPattern(format!("{}: {e}", regex.as_str())))?;
+        let Some(captures) = captures else {
+            return Ok(None);
+        };
+
+        let span = |i: usize| {
+            captures.get(i).map(|m| Span {
+                text: text[m.start()..m.end()].to_string(),
+                start: text[..m.start()].chars().count(),
+                end: text[..m.end()].chars().count(),
+            })
+        };
+        Ok(Some(Self {
+            matched: span(0).unwrap_or(Span {
+                text: String::new(),
+                start: 0,
+                end: 0,
+            }),
+            groups: (1..captures.len()).map(span).collect(),
+            names: regex.capture_names().iter().skip(1).cloned().collect(),
+        }))
+    }
+
+    /// The match as an object with the matched string in `str`, its offsets in `start`
+    /// and `end`, its groups in order in `groups` and the named ones in `named`. A group
+    /// that took no part in the match is null.
+    pub fn into_datum(self) -> Datum {
+        let mut named = Document::new();
+        for (name, group) in self.names.iter().zip(&self.groups) {
+            if let Some(name) = name {
+                named.insert(name.clone(), span_datum(group.clone()));
+            }
+        }
+        let groups = self.groups.into_iter().map(span_datum).collect();
+
+        let mut result = span_document(self.matched);
+        result.insert(
+            "groups".to_string(),
+            Datum {
+                value: Some(datum::Value::Array(DatumArray {
+                    items: groups,
+                    element_type: String::new(),
+                })),
+            },
+        );
+        result.insert("named".to_string(), Datum::from(named));
+        Datum::from(result)
+    }
+}
+
+fn span_document(span: Span) -> Document {
+    Document::from([
+        ("str".to_string(), string_datum(span.text)),
+        ("start".to_string(), int_datum(span.start as i64)),
+        ("end".to_string(), int_datum(span.end as i64)),
+    ])
+}
+
+fn span_datum(span: Option<Span>) -> Datum {
+    match span {
+        Some(span) => Datum::from(span_document(span)),
+        None => Datum {
+            value: Some(datum::Value::Null(NullValue::NullValue.into())),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_are_options() {
+        let cache = PatternCache::default();
+        assert!(
+            cache
+                .get("^hello$", "i")
+                .unwrap()
+                .is_match(b"HeLLo")
+                .unwrap()
+        );
+        assert!(
+            !cache
+                .get("^hello$", "")
+                .unwrap()
+                .is_match(b"HeLLo")
+                .unwrap()
+        );
+        assert!(cache.get("^b$", "m").unwrap().is_match(b"a\nb").unwrap());
+        assert!(cache.get("a.b", "s").unwrap().is_match(b"a\nb").unwrap());
+        assert!(
+            cache
+                .get("a b # comment", "x")
+                .unwrap()
+                .is_match(b"ab")
+                .unwrap()
+        );
+
+        // Compiled once per pattern and flags
+        let first = cache.get("^hello$", "i").unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.get("^hello$", "i").unwrap()));
+
+        assert!(matches!(
+            cache.get("a", "q"),
+            Err(EvalError::InvalidMatchPattern(_))
+        ));
+        assert!(matches!(
+            cache.get("(unclosed", ""),
+            Err(EvalError::InvalidMatchPattern(_))
+        ));
+    }
+
+    #[test]
+    fn test_match_result_groups() {
+        let cache = PatternCache::default();
+        let regex = cache.get(r"(?<user>\w+)@(\w+)(\.org)?", "").unwrap();
+        let result = MatchResult::find(&regex, "mail: ünï@example.com")
+            .unwrap()
+            .unwrap();
+        let span = |text: &str, start, end| Span {
+            text: text.to_string(),
+            start,
+            end,
+        };
+        assert_eq!(result.matched, span("ünï@example", 6, 17));
+        assert_eq!(
+            result.groups,
+            [Some(span("ünï", 6, 9)), Some(span("example", 10, 17)), None]
+        );
+        assert_eq!(result.names, [Some("user".to_string()), None, None]);
+        assert!(MatchResult::find(&regex, "no address").unwrap().is_none());
+
+        let Some(datum::Value::Object(object)) = result.into_datum().value else {
+            panic!("Expected an object");
+        };
+        assert_eq!(object.fields["start"], int_datum(6));
+        let Some(datum::Value::Object(named)) = &object.fields["named"].value else {
+            panic!("Expected the named groups");
+        };
+        assert!(named.fields.contains_key("user"));
+    }
+}
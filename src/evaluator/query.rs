@@ -1,18 +1,26 @@
 use crate::ast::{
-    CollectionResult, CountResult, Cursor, Datum, DeleteResult, Document, Expression, FieldRef,
-    FilterResult, LimitResult, OrderByField, OrderByResult, PluckResult, SkipResult, UpdateResult,
-    WithoutResult, proto, query_result,
+    ArrayOp, CollectionResult, CountResult, Cursor, Datum, DatumArray, DeleteResult, Document,
+    DocumentFunctionResult, Expression, FieldRef, FilterResult, LimitResult, OrderByField,
+    OrderByResult, PluckResult, SequenceResult, SkipResult, UpdateResult, WithoutResult, datum,
+    document_function_result, document_op::Operator as DocumentOperator, offsets_of, pluck_result,
+    proto, query_result, sequence_result, without_result,
 };
+use crate::evaluator::document;
 use crate::evaluator::error::{EvalError, EvalStats};
 use crate::evaluator::expression::ExpressionEvaluator;
+use crate::evaluator::sequence::Sequence;
+use crate::evaluator::subquery::Subqueries;
 use crate::evaluator::utils::{
-    compare_values, datum_to_bool, exclude_field_refs, extract_document_key,
-    extract_field_from_ref, extract_field_value, insert_field_by_ref, is_single_doc_source,
+    bool_datum, compare_values, datum_to_bool, datums_equal, exclude_field_refs,
+    extract_document_key, extract_field_from_ref, extract_field_value, insert_field_by_ref,
+    int_datum, is_single_doc_source,
 };
+use crate::evaluator::{sequence, update};
 use crate::planner::PlanNode;
-use crate::storage::StorageBackend;
+use crate::storage::{DocumentUpdate, Durability, StorageBackend, StorageError};
 
 use crate::DatumObject;
+use futures_util::{StreamExt, TryStreamExt};
 use std::sync::Arc;
 
 /// Handler for query processing operations like filtering, sorting, and streaming
@@ -55,6 +63,44 @@ impl QueryProcessor {
             }
         }
 
+        self.filter_result(filtered_docs, cursor, stats)
+    }
+
+    /// Filter documents based on a predicate expression that runs table subqueries,
+    /// running each where the predicate reaches it for a document
+    pub async fn filter_documents_with_subqueries(
+        &self,
+        source_result: query_result::Result,
+        predicate: &Expression,
+        subqueries: &mut Subqueries<'_>,
+        cursor: Option<Cursor>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let documents = self.extract_documents_from_result(source_result)?;
+        let boolean = self.expression_evaluator.is_boolean_expression(predicate);
+        let mut filtered_docs = Vec::new();
+
+        for doc in documents {
+            let result = subqueries.evaluate(predicate, &doc).await?;
+            if !boolean && !matches!(result.value, Some(datum::Value::Bool(_))) {
+                return Err(EvalError::InvalidPredicate);
+            }
+
+            if datum_to_bool(&result) {
+                filtered_docs.push(doc);
+            }
+        }
+
+        self.filter_result(filtered_docs, cursor, stats)
+    }
+
+    /// The result of filtering, continuing from the last document kept
+    fn filter_result(
+        &self,
+        filtered_docs: Vec<Datum>,
+        cursor: Option<Cursor>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
         stats.record_rows_processed(filtered_docs.len());
         stats.record_rows_returned(filtered_docs.len());
 
@@ -294,32 +340,238 @@ impl QueryProcessor {
         }
     }
 
+    /// Apply a document function to the single value of a source, or to each of its
+    /// documents
+    ///
+    /// Over many documents, `HasFields` and `WithFields` keep those that have the
+    /// fields, `Default` returns them as they are, and `TypeOf` and `CoerceTo` take them
+    /// together as an array.
+    pub async fn apply_document_function(
+        &self,
+        source_result: query_result::Result,
+        cursor: Option<Cursor>,
+        op: DocumentOperator,
+        args: &[Expression],
+        fields: &[FieldRef],
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let apply = |value: Datum| -> Result<Datum, EvalError> {
+            let mut values = Vec::with_capacity(args.len() + 1);
+            for arg in args {
+                values.push(self.expression_evaluator.evaluate_expression(arg, &value)?);
+            }
+            values.insert(0, value);
+            document::apply(op, &values, fields)
+        };
+        let value_result = |value: Datum| {
+            query_result::Result::DocumentFunction(DocumentFunctionResult {
+                result: Some(document_function_result::Result::Value(value)),
+            })
+        };
+
+        if let Some(value) = single_value(&source_result) {
+            stats.record_rows_processed(1);
+            stats.record_rows_returned(1);
+            return Ok(value_result(apply(value)?));
+        }
+
+        let docs = self.extract_documents_from_result(source_result)?;
+        stats.record_rows_processed(docs.len());
+        let docs: Vec<Datum> = match op {
+            DocumentOperator::HasFields => docs
+                .into_iter()
+                .filter(|doc| document::has_fields(doc, fields))
+                .collect(),
+            DocumentOperator::WithFields => docs
+                .iter()
+                .filter(|doc| document::has_fields(doc, fields))
+                .map(|doc| document::with_fields(doc, fields))
+                .collect(),
+            DocumentOperator::Default => docs,
+            DocumentOperator::TypeOf => {
+                stats.record_rows_returned(1);
+                return Ok(value_result(Datum {
+                    value: Some(datum::Value::String("SEQUENCE".to_string())),
+                }));
+            }
+            DocumentOperator::CoerceTo => {
+                stats.record_rows_returned(1);
+                return Ok(value_result(apply(array_datum(docs))?));
+            }
+            _ => docs.into_iter().map(apply).collect::<Result<_, _>>()?,
+        };
+        stats.record_rows_returned(docs.len());
+
+        let last_key = docs
+            .last()
+            .map(|doc| self.extract_document_key(doc))
+            .transpose()
+            .unwrap_or(None);
+        let next_cursor = Cursor::from_previous(cursor, last_key, &docs);
+
+        Ok(query_result::Result::DocumentFunction(
+            DocumentFunctionResult {
+                result: Some(document_function_result::Result::Collection(
+                    CollectionResult {
+                        documents: docs,
+                        cursor: next_cursor,
+                    },
+                )),
+            },
+        ))
+    }
+
+    /// Interleave two sequences, or merge them when each is in the interleave order
+    pub async fn union_sequences(
+        &self,
+        source: Sequence,
+        other: Sequence,
+        interleave: &[OrderByField],
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let items: Vec<Datum> = sequence::union(source, other, interleave.to_vec())
+            .try_collect()
+            .await?;
+        stats.record_rows_processed(items.len());
+        stats.record_rows_returned(items.len());
+        Ok(sequence_collection(items))
+    }
+
+    /// Keep the items of a sequence between two indexes
+    pub async fn slice_sequence(
+        &self,
+        items: Sequence,
+        start: i64,
+        end: Option<i64>,
+        left_open: bool,
+        right_closed: bool,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let mut read = 0;
+        let items = sequence::slice(
+            items.inspect(|_| read += 1),
+            start,
+            end,
+            left_open,
+            right_closed,
+        )
+        .await?;
+        stats.record_rows_processed(read);
+        stats.record_rows_returned(items.len());
+        Ok(sequence_collection(items))
+    }
+
+    /// Pick the item of a sequence at an index
+    pub async fn nth_item(
+        &self,
+        items: Sequence,
+        index: i64,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let mut read = 0;
+        let item = sequence::nth(items.inspect(|_| read += 1), index).await?;
+        stats.record_rows_processed(read);
+        stats.record_rows_returned(1);
+        Ok(sequence_value(item))
+    }
+
+    /// Pick a uniform random sample of the items of a sequence
+    pub async fn sample_sequence(
+        &self,
+        items: Sequence,
+        count: u32,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let mut read = 0;
+        let items = sequence::sample(items.inspect(|_| read += 1), count as usize).await?;
+        stats.record_rows_processed(read);
+        stats.record_rows_returned(items.len());
+        Ok(sequence_collection(items))
+    }
+
+    /// Check whether a sequence has no items
+    pub async fn is_empty(
+        &self,
+        items: Sequence,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let mut read = 0;
+        let is_empty = sequence::is_empty(items.inspect(|_| read += 1)).await?;
+        stats.record_rows_processed(read);
+        stats.record_rows_returned(1);
+        Ok(sequence_value(bool_datum(is_empty)))
+    }
+
+    /// Find the positions of the items of a sequence equal to a value, or for which a
+    /// predicate is true
+    pub async fn offsets_of(
+        &self,
+        items: Sequence,
+        target: &offsets_of::Target,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let mut read = 0;
+        let items = items.inspect(|_| read += 1);
+        let offsets = match target {
+            offsets_of::Target::Value(value) => {
+                sequence::offsets_of(items, |item| Ok(datums_equal(item, value))).await?
+            }
+            offsets_of::Target::Predicate(predicate) => {
+                if !self.expression_evaluator.is_boolean_expression(predicate) {
+                    return Err(EvalError::InvalidPredicate);
+                }
+                sequence::offsets_of(items, |item| {
+                    let result = self
+                        .expression_evaluator
+                        .evaluate_expression(predicate, item)?;
+                    Ok(datum_to_bool(&result))
+                })
+                .await?
+            }
+        };
+        stats.record_rows_processed(read);
+        stats.record_rows_returned(1);
+        Ok(sequence_value(array_datum(offsets)))
+    }
+
     /// Update documents based on a patch
     pub async fn update_documents(
         &self,
         source_result: query_result::Result,
         patch: &DatumObject,
+        operations: &[ArrayOp],
         source_plan: &PlanNode,
+        durability: Option<Durability>,
         stats: &mut EvalStats,
     ) -> Result<query_result::Result, EvalError> {
         let documents = self.extract_documents_from_result(source_result)?;
-        let mut updated_count = 0;
-
         let (database, table) = self.extract_table_context(source_plan)?;
 
-        for doc in documents {
-            let updated_doc = self.apply_patch_to_document(&doc, patch)?;
-            let key = self.extract_document_key(&updated_doc)?;
-
-            // Convert to storage document format
-            if let Some(crate::ast::datum::Value::Object(obj)) = &updated_doc.value {
-                let storage_doc = crate::ast::Document::from(obj);
-                self.storage
-                    .put(&database, &table, &key, &storage_doc)
-                    .await?;
-                updated_count += 1;
+        let keys = documents
+            .iter()
+            .map(|doc| self.extract_document_key(doc))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Patch the stored version of each document rather than the one read, so that
+        // concurrent updates are not lost, and write them all at once, so that a write
+        // refused by a unique index leaves none of them changed
+        let patch = patch.clone();
+        let operations = operations.to_vec();
+        let update: DocumentUpdate = Arc::new(move |doc| {
+            let id = doc.get("id").cloned();
+            let updated = update::apply(doc, &patch, &operations)
+                .map_err(|e| StorageError::InvalidUpdate(e.to_string()))?;
+            if updated.get("id") != id.as_ref() {
+                return Err(StorageError::InvalidUpdate(
+                    "the primary key cannot change".to_string(),
+                ));
             }
-        }
+            Ok(updated)
+        });
+        let updated_count = self
+            .storage
+            .update_batch(&database, &table, &keys, update, durability)
+            .await?;
 
         stats.record_rows_processed(updated_count);
 
@@ -328,17 +580,26 @@ impl QueryProcessor {
         }))
     }
 
-    /// Delete documents
+    /// Delete the documents produced by the source plan
     pub async fn delete_documents(
         &self,
         source_result: query_result::Result,
+        source_plan: &PlanNode,
+        durability: Option<Durability>,
         stats: &mut EvalStats,
     ) -> Result<query_result::Result, EvalError> {
         let documents = self.extract_documents_from_result(source_result)?;
-        let deleted_count = documents.len();
+        let (database, table) = self.extract_table_context(source_plan)?;
+
+        let keys = documents
+            .iter()
+            .map(|doc| self.extract_document_key(doc))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.storage
+            .delete_batch(&database, &table, &keys, durability)
+            .await?;
 
-        // Note: Actual deletion would require table context and storage operations
-        // For now, we'll just return the count
+        let deleted_count = documents.len();
         stats.record_rows_processed(deleted_count);
 
         Ok(query_result::Result::Delete(DeleteResult {
@@ -346,6 +607,30 @@ impl QueryProcessor {
         }))
     }
 
+    /// The result of a subquery as a value: its single value or count, or an array of
+    /// its documents
+    pub fn result_value(&self, result: query_result::Result) -> Result<Datum, EvalError> {
+        if let Some(value) = single_value(&result) {
+            return Ok(value);
+        }
+        match result {
+            query_result::Result::Count(CountResult { count }) => Ok(int_datum(count as i64)),
+            query_result::Result::Pluck(PluckResult {
+                result: Some(pluck_result::Result::Document(document)),
+            })
+            | query_result::Result::Without(WithoutResult {
+                result: Some(without_result::Result::Document(document)),
+            }) => Ok(document),
+            query_result::Result::Pluck(PluckResult {
+                result: Some(pluck_result::Result::Collection(collection)),
+            })
+            | query_result::Result::Without(WithoutResult {
+                result: Some(without_result::Result::Collection(collection)),
+            }) => Ok(array_datum(collection.documents)),
+            result => Ok(array_datum(self.extract_documents_from_result(result)?)),
+        }
+    }
+
     /// Extract documents from various result types
     fn extract_documents_from_result(
         &self,
@@ -355,41 +640,33 @@ impl QueryProcessor {
             query_result::Result::Table(scan_result) => Ok(scan_result.documents),
             query_result::Result::Get(get_result) => Ok(get_result.document.into_iter().collect()),
             query_result::Result::GetAll(get_all_result) => Ok(get_all_result.documents),
+            query_result::Result::Between(result) => Ok(result.documents),
+            query_result::Result::Search(search_result) => Ok(search_result.documents),
+            query_result::Result::GetIntersecting(result) => Ok(result.documents),
+            query_result::Result::GetNearest(result) => Ok(result.documents),
+            query_result::Result::Nearest(result) => Ok(result.documents),
             query_result::Result::Filter(filter_result) => Ok(filter_result.documents),
             query_result::Result::OrderBy(order_result) => Ok(order_result.documents),
             query_result::Result::Skip(skip_result) => Ok(skip_result.documents),
             query_result::Result::Limit(limit_result) => Ok(limit_result.documents),
+            query_result::Result::DocumentFunction(DocumentFunctionResult {
+                result: Some(document_function_result::Result::Collection(collection)),
+            }) => Ok(collection.documents),
+            query_result::Result::Sequence(SequenceResult {
+                result: Some(sequence_result::Result::Collection(collection)),
+            }) => Ok(collection.documents),
             _ => Err(EvalError::InvalidExpression),
         }
     }
 
-    /// Apply a patch to a document using various update operations
-    fn apply_patch_to_document(
-        &self,
-        doc: &Datum,
-        patch: &DatumObject,
-    ) -> Result<Datum, EvalError> {
-        if let Some(crate::ast::datum::Value::Object(obj)) = &doc.value {
-            let mut updated_fields = obj.fields.clone();
-
-            // Apply patch fields to the target object
-            for (key, value) in &patch.fields {
-                if value.value.is_none() {
-                    // Remove field if value is null
-                    updated_fields.remove(key);
-                } else {
-                    // Set or update field
-                    updated_fields.insert(key.clone(), value.clone());
-                }
-            }
-
-            Ok(Datum {
-                value: Some(crate::ast::datum::Value::Object(crate::ast::DatumObject {
-                    fields: updated_fields,
-                })),
-            })
-        } else {
-            Err(EvalError::InvalidInsertTarget)
+    /// Extract the items of a sequence, which is either a result of many documents or
+    /// a single array value
+    pub fn extract_sequence(&self, result: query_result::Result) -> Result<Vec<Datum>, EvalError> {
+        match single_value(&result) {
+            Some(Datum {
+                value: Some(datum::Value::Array(array)),
+            }) => Ok(array.items),
+            _ => self.extract_documents_from_result(result),
         }
     }
 
@@ -408,7 +685,13 @@ impl QueryProcessor {
             | PlanNode::TableScan { table_ref, .. }
             | PlanNode::Insert { table_ref, .. }
             | PlanNode::Get { table_ref, .. }
-            | PlanNode::GetAll { table_ref, .. } => {
+            | PlanNode::GetAll { table_ref, .. }
+            | PlanNode::GetAllByIndex { table_ref, .. }
+            | PlanNode::Between { table_ref, .. }
+            | PlanNode::Search { table_ref, .. }
+            | PlanNode::GetIntersecting { table_ref, .. }
+            | PlanNode::GetNearest { table_ref, .. }
+            | PlanNode::Nearest { table_ref, .. } => {
                 let database = table_ref
                     .database
                     .as_ref()
@@ -419,13 +702,58 @@ impl QueryProcessor {
             PlanNode::Update { source, .. }
             | PlanNode::Delete { source, .. }
             | PlanNode::Filter { source, .. }
+            | PlanNode::SubqueryFilter { source, .. }
             | PlanNode::OrderBy { source, .. }
             | PlanNode::Limit { source, .. }
             | PlanNode::Skip { source, .. }
             | PlanNode::Count { source, .. }
+            | PlanNode::Slice { source, .. }
+            | PlanNode::Sample { source, .. }
             | PlanNode::Pluck { source, .. }
-            | PlanNode::Without { source, .. } => self.extract_table_context(source),
+            | PlanNode::Without { source, .. }
+            | PlanNode::DocumentFunction { source, .. } => self.extract_table_context(source),
             _ => Err(EvalError::InvalidExpression),
         }
     }
 }
+
+/// The single value of a result that is not a sequence of documents, a missing
+/// document being a missing value.
+fn single_value(result: &query_result::Result) -> Option<Datum> {
+    match result {
+        query_result::Result::Get(result) => Some(result.document.clone().unwrap_or_default()),
+        query_result::Result::Literal(result) => Some(result.value.clone().unwrap_or_default()),
+        query_result::Result::DocumentFunction(DocumentFunctionResult {
+            result: Some(document_function_result::Result::Value(value)),
+        })
+        | query_result::Result::Sequence(SequenceResult {
+            result: Some(sequence_result::Result::Value(value)),
+        }) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+fn array_datum(items: Vec<Datum>) -> Datum {
+    Datum {
+        value: Some(datum::Value::Array(DatumArray {
+            items,
+            element_type: String::new(),
+        })),
+    }
+}
+
+fn sequence_value(value: Datum) -> query_result::Result {
+    query_result::Result::Sequence(SequenceResult {
+        result: Some(sequence_result::Result::Value(value)),
+    })
+}
+
+/// A sequence result of whole sequences, which has no cursor
+fn sequence_collection(items: Vec<Datum>) -> query_result::Result {
+    query_result::Result::Sequence(SequenceResult {
+        result: Some(sequence_result::Result::Collection(CollectionResult {
+            documents: items,
+            cursor: None,
+        })),
+    })
+}
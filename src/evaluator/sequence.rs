@@ -0,0 +1,357 @@
+//! Sequence operations: unions, slices, picking and sampling items, and finding them.
+//!
+//! Each reads the items of its sequence as a stream, stopping as soon as it has what it
+//! needs. Indexes count from the end of a sequence when negative.
+
+use crate::ast::{Datum, FieldRef, OrderByField};
+use crate::evaluator::error::EvalError;
+use crate::evaluator::utils::{compare_values, extract_field_from_ref, int_datum};
+use futures_util::stream::{self, Stream, StreamExt, TryStreamExt};
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::pin::Pin;
+
+/// The items of a sequence, read as they are needed
+pub type Sequence = Pin<Box<dyn Stream<Item = Result<Datum, EvalError>> + Send>>;
+
+/// A sequence of items already read
+pub fn items(items: Vec<Datum>) -> Sequence {
+    Box::pin(stream::iter(items.into_iter().map(Ok)))
+}
+
+/// Interleave two sequences as their items come, or merge them by the fields when
+/// each is already in that order, taking from the first on ties.
+pub fn union(left: Sequence, right: Sequence, interleave: Vec<OrderByField>) -> Sequence {
+    if interleave.is_empty() {
+        return Box::pin(stream::select(left, right));
+    }
+
+    let state = (left.peekable(), right.peekable(), interleave);
+    Box::pin(stream::unfold(
+        state,
+        |(mut left, mut right, interleave)| async move {
+            let take_left = match (
+                Pin::new(&mut left).peek().await,
+                Pin::new(&mut right).peek().await,
+            ) {
+                (Some(Ok(a)), Some(Ok(b))) => compare_by(a, b, &interleave) != Ordering::Greater,
+                (Some(Err(_)), _) | (Some(_), None) => true,
+                (_, Some(Err(_))) | (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            let item = if take_left { left.next() } else { right.next() }.await?;
+            Some((item, (left, right, interleave)))
+        },
+    ))
+}
+
+/// Order two documents by the fields, in turn.
+fn compare_by(a: &Datum, b: &Datum, fields: &[OrderByField]) -> Ordering {
+    fields
+        .iter()
+        .map(|field| {
+            let field_ref = FieldRef {
+                path: vec![field.field_name.clone()],
+                separator: String::new(),
+            };
+            let cmp = compare_values(
+                &extract_field_from_ref(a, &field_ref),
+                &extract_field_from_ref(b, &field_ref),
+            );
+            if field.ascending { cmp } else { cmp.reverse() }
+        })
+        .find(|cmp| cmp.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// The items from `start` up to `end`, or to the end of the sequence without one.
+/// Without negative indexes the items past the slice are not read.
+pub async fn slice<S>(
+    mut items: S,
+    start: i64,
+    end: Option<i64>,
+    left_open: bool,
+    right_closed: bool,
+) -> Result<Vec<Datum>, EvalError>
+where
+    S: Stream<Item = Result<Datum, EvalError>> + Unpin,
+{
+    if start < 0 || end.is_some_and(|end| end < 0) {
+        let items: Vec<Datum> = items.try_collect().await?;
+        let len = items.len() as i64;
+        let resolve = |index: i64| if index < 0 { len + index } else { index };
+        let from = resolve(start) + i64::from(left_open);
+        let to = end.map_or(len, |end| resolve(end) + i64::from(right_closed));
+        let (from, to) = (from.clamp(0, len) as usize, to.clamp(0, len) as usize);
+        return Ok(items
+            .into_iter()
+            .skip(from)
+            .take(to.saturating_sub(from))
+            .collect());
+    }
+
+    let from = start + i64::from(left_open);
+    let to = end.map(|end| end + i64::from(right_closed));
+    let mut sliced = Vec::new();
+    let mut index = 0;
+    while to.is_none_or(|to| index < to) {
+        let Some(item) = items.try_next().await? else {
+            break;
+        };
+        if index >= from {
+            sliced.push(item);
+        }
+        index += 1;
+    }
+    Ok(sliced)
+}
+
+/// The item at an index. Only the items up to it are read, or the last items kept
+/// when it counts from the end.
+pub async fn nth<S>(mut items: S, index: i64) -> Result<Datum, EvalError>
+where
+    S: Stream<Item = Result<Datum, EvalError>> + Unpin,
+{
+    if index >= 0 {
+        let mut at = 0;
+        while let Some(item) = items.try_next().await? {
+            if at == index {
+                return Ok(item);
+            }
+            at += 1;
+        }
+        return Err(EvalError::IndexOutOfBounds(index));
+    }
+
+    let keep = index.unsigned_abs() as usize;
+    let mut last = VecDeque::with_capacity(keep.min(1024));
+    while let Some(item) = items.try_next().await? {
+        if last.len() == keep {
+            last.pop_front();
+        }
+        last.push_back(item);
+    }
+    match last.pop_front() {
+        Some(item) if last.len() + 1 == keep => Ok(item),
+        _ => Err(EvalError::IndexOutOfBounds(index)),
+    }
+}
+
+/// Whether a sequence has no items, reading at most one.
+pub async fn is_empty<S>(mut items: S) -> Result<bool, EvalError>
+where
+    S: Stream<Item = Result<Datum, EvalError>> + Unpin,
+{
+    Ok(items.try_next().await?.is_none())
+}
+
+/// A uniform random sample of up to `count` items, kept by reservoir sampling so that
+/// the sequence is read once.
+pub async fn sample<S>(mut items: S, count: usize) -> Result<Vec<Datum>, EvalError>
+where
+    S: Stream<Item = Result<Datum, EvalError>> + Unpin,
+{
+    let mut rng = Rng::new();
+    let mut reservoir = Vec::new();
+    let mut seen = 0u64;
+    while let Some(item) = items.try_next().await? {
+        if reservoir.len() < count {
+            reservoir.push(item);
+        } else {
+            let at = rng.below(seen + 1) as usize;
+            if at < count {
+                reservoir[at] = item;
+            }
+        }
+        seen += 1;
+    }
+    Ok(reservoir)
+}
+
+/// The positions of the items that match, counted as the items are read.
+pub async fn offsets_of<S>(
+    mut items: S,
+    mut matches: impl FnMut(&Datum) -> Result<bool, EvalError>,
+) -> Result<Vec<Datum>, EvalError>
+where
+    S: Stream<Item = Result<Datum, EvalError>> + Unpin,
+{
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    while let Some(item) = items.try_next().await? {
+        if matches(&item)? {
+            offsets.push(int_datum(offset));
+        }
+        offset += 1;
+    }
+    Ok(offsets)
+}
+
+/// A xorshift generator seeded from the randomly keyed standard hasher, which is good
+/// enough for sampling without pulling in a random number crate.
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        Self(RandomState::new().build_hasher().finish() | 1)
+    }
+
+    /// A number below `bound`, which must not be zero.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        ((u128::from(self.0) * u128::from(bound)) >> 64) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ints(values: &[i64]) -> Vec<Datum> {
+        values.iter().copied().map(int_datum).collect()
+    }
+
+    fn int_items(values: &[i64]) -> Sequence {
+        items(ints(values))
+    }
+
+    /// A sequence whose items past `len` fail, to tell they were never read
+    fn prefix_only(len: i64) -> Sequence {
+        let valid = stream::iter((0..len).map(|n| Ok(int_datum(n))));
+        let past = stream::iter([Err(EvalError::InvalidArgument("read too far".to_string()))]);
+        Box::pin(valid.chain(past))
+    }
+
+    #[tokio::test]
+    async fn test_slice_bounds() {
+        let slice_of = |start, end, left_open, right_closed| {
+            slice(
+                int_items(&[0, 1, 2, 3, 4]),
+                start,
+                end,
+                left_open,
+                right_closed,
+            )
+        };
+        assert_eq!(
+            slice_of(1, Some(3), false, false).await.unwrap(),
+            ints(&[1, 2])
+        );
+        assert_eq!(
+            slice_of(1, Some(3), true, true).await.unwrap(),
+            ints(&[2, 3])
+        );
+        assert_eq!(
+            slice_of(-2, None, false, false).await.unwrap(),
+            ints(&[3, 4])
+        );
+        assert_eq!(
+            slice_of(0, Some(-1), false, false).await.unwrap(),
+            ints(&[0, 1, 2, 3])
+        );
+        assert_eq!(slice_of(3, Some(1), false, false).await.unwrap(), ints(&[]));
+        assert_eq!(slice_of(-9, Some(9), false, false).await.unwrap().len(), 5);
+        assert_eq!(slice_of(2, None, true, false).await.unwrap(), ints(&[3, 4]));
+
+        // The items past the slice are not read
+        let sliced = slice(prefix_only(4), 1, Some(3), false, true).await;
+        assert_eq!(sliced.unwrap(), ints(&[1, 2, 3]));
+    }
+
+    #[tokio::test]
+    async fn test_nth_counts_from_the_end_when_negative() {
+        let nth_of = |index| nth(int_items(&[10, 20, 30]), index);
+        assert_eq!(nth_of(0).await.unwrap(), int_datum(10));
+        assert_eq!(nth_of(-1).await.unwrap(), int_datum(30));
+        assert_eq!(nth_of(-3).await.unwrap(), int_datum(10));
+        assert!(matches!(
+            nth_of(3).await,
+            Err(EvalError::IndexOutOfBounds(3))
+        ));
+        assert!(matches!(
+            nth_of(-4).await,
+            Err(EvalError::IndexOutOfBounds(-4))
+        ));
+        assert_eq!(nth(prefix_only(3), 2).await.unwrap(), int_datum(2));
+    }
+
+    #[tokio::test]
+    async fn test_is_empty_reads_one_item() {
+        assert!(is_empty(int_items(&[])).await.unwrap());
+        assert!(!is_empty(prefix_only(1)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sample_keeps_distinct_items() {
+        let mut picked = sample(int_items(&[0, 1, 2, 3, 4, 5, 6, 7]), 3)
+            .await
+            .unwrap();
+        assert_eq!(picked.len(), 3);
+        picked.sort_by(compare_values);
+        picked.dedup();
+        assert_eq!(picked.len(), 3);
+        assert_eq!(sample(int_items(&[1, 2]), 5).await.unwrap().len(), 2);
+        assert!(sample(int_items(&[1, 2]), 0).await.unwrap().is_empty());
+
+        // Every item is picked some of the time
+        let mut seen = [false; 8];
+        for _ in 0..200 {
+            for item in sample(int_items(&[0, 1, 2, 3, 4, 5, 6, 7]), 2)
+                .await
+                .unwrap()
+            {
+                let crate::ast::datum::Value::Int(i) = item.value.unwrap() else {
+                    unreachable!()
+                };
+                seen[i as usize] = true;
+            }
+        }
+        assert!(seen.iter().all(|seen| *seen));
+    }
+
+    #[tokio::test]
+    async fn test_offsets_of_counts_positions() {
+        let offsets = offsets_of(int_items(&[1, 2, 1, 3]), |item| Ok(*item == int_datum(1)));
+        assert_eq!(offsets.await.unwrap(), ints(&[0, 2]));
+    }
+
+    #[tokio::test]
+    async fn test_union_interleaves_sequences() {
+        let doc = |n: i64| {
+            Datum::from(crate::ast::Document::from([(
+                "n".to_string(),
+                int_datum(n),
+            )]))
+        };
+        let docs = |ns: &[i64]| items(ns.iter().copied().map(doc).collect());
+        let order = vec![OrderByField {
+            field_name: "n".to_string(),
+            ascending: true,
+        }];
+        let merged: Vec<Datum> = union(docs(&[1, 4]), docs(&[2, 3, 5]), order)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(merged, vec![doc(1), doc(2), doc(3), doc(4), doc(5)]);
+
+        let mut interleaved: Vec<Datum> = union(docs(&[4]), docs(&[2]), vec![])
+            .try_collect()
+            .await
+            .unwrap();
+        interleaved.sort_by(|a, b| {
+            compare_by(
+                a,
+                b,
+                &[OrderByField {
+                    field_name: "n".to_string(),
+                    ascending: true,
+                }],
+            )
+        });
+        assert_eq!(interleaved, vec![doc(2), doc(4)]);
+    }
+}
@@ -0,0 +1,565 @@
+//! Table subqueries inside expressions.
+//!
+//! A table subquery reads from storage, so an expression running one is evaluated
+//! asynchronously, each subquery being run where the evaluation reaches it: those in
+//! the branches not taken or past the operand deciding a logical operation never run.
+//! Every subquery is planned once with the query it is part of. Those with variables
+//! are correlated: their variables name fields of the row the expression is evaluated
+//! against, and are bound to them in a copy of the plan before it runs. Those without
+//! give the same result for every row, so they run at most once.
+
+use crate::ast::{Datum, Expression, Query, binary_op, expression, offsets_of, query};
+use crate::evaluator::Evaluator;
+use crate::evaluator::error::EvalError;
+use crate::evaluator::expression::ExpressionEvaluator;
+use crate::evaluator::utils::{bool_datum, datum_to_bool, extract_field_value};
+use crate::planner::PlanNode;
+use crate::storage::{SnapshotId, StorageBackend};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Evaluates expressions running table subqueries, reading from the snapshot of the
+/// query they are part of
+pub struct Subqueries<'a> {
+    storage: Arc<dyn StorageBackend>,
+    snapshot: Option<SnapshotId>,
+    expressions: ExpressionEvaluator,
+    /// Plans of the table subqueries of the expressions, by the query each stands for
+    planned: &'a [(Query, PlanNode)],
+    /// The index in `planned` of each table subquery of the expression, in the order
+    /// they are reached walking it
+    slots: &'a [usize],
+    correlated: Vec<bool>,
+    /// Results of the uncorrelated subqueries that have run
+    memoized: Vec<Option<Datum>>,
+}
+
+impl<'a> Subqueries<'a> {
+    /// Create an evaluator running the planned subqueries, none of which has run yet
+    pub fn new(
+        storage: Arc<dyn StorageBackend>,
+        snapshot: Option<SnapshotId>,
+        planned: &'a [(Query, PlanNode)],
+        slots: &'a [usize],
+    ) -> Self {
+        Self {
+            storage,
+            snapshot,
+            expressions: ExpressionEvaluator::new(),
+            planned,
+            slots,
+            correlated: planned
+                .iter()
+                .map(|(query, _)| is_correlated(query))
+                .collect(),
+            memoized: vec![None; planned.len()],
+        }
+    }
+
+    /// Evaluate an expression against a row, running its table subqueries as they are
+    /// reached
+    pub async fn evaluate(&mut self, expr: &Expression, row: &Datum) -> Result<Datum, EvalError> {
+        self.evaluate_from(expr, 0, row).await
+    }
+
+    /// Evaluate an expression whose first table subquery is the `first` of the whole
+    /// expression
+    async fn evaluate_from(
+        &mut self,
+        expr: &Expression,
+        first: usize,
+        row: &Datum,
+    ) -> Result<Datum, EvalError> {
+        if !has_table_subqueries(expr) {
+            return self.expressions.evaluate_expression(expr, row);
+        }
+
+        match &expr.expr {
+            Some(expression::Expr::Subquery(query)) if is_table_query(query) => {
+                let index = *self
+                    .slots
+                    .get(first)
+                    .ok_or(EvalError::UnsupportedOperation)?;
+                self.run(index, row).await
+            }
+            Some(expression::Expr::Binary(op))
+                if op.op == binary_op::Operator::And as i32
+                    || op.op == binary_op::Operator::Or as i32 =>
+            {
+                // The left operand alone may decide, as false does for AND and true
+                // for OR
+                let and = op.op == binary_op::Operator::And as i32;
+                let left = op.left.as_deref().ok_or(EvalError::InvalidExpression)?;
+                let right_first = first + count_table_subqueries(left);
+                let left = datum_to_bool(&Box::pin(self.evaluate_from(left, first, row)).await?);
+                if left != and {
+                    return Ok(bool_datum(left));
+                }
+                let right = op.right.as_deref().ok_or(EvalError::InvalidExpression)?;
+                let right = Box::pin(self.evaluate_from(right, right_first, row)).await?;
+                Ok(bool_datum(datum_to_bool(&right)))
+            }
+            Some(expression::Expr::Branch(branch)) => {
+                let mut first = first;
+                for case in &branch.cases {
+                    let condition = case
+                        .condition
+                        .as_ref()
+                        .ok_or(EvalError::InvalidExpression)?;
+                    let value_first = first + count_table_subqueries(condition);
+                    if datum_to_bool(&Box::pin(self.evaluate_from(condition, first, row)).await?) {
+                        let value = case.value.as_ref().ok_or(EvalError::InvalidExpression)?;
+                        return Box::pin(self.evaluate_from(value, value_first, row)).await;
+                    }
+                    first = value_first + case.value.as_ref().map_or(0, count_table_subqueries);
+                }
+                let otherwise = branch
+                    .otherwise
+                    .as_deref()
+                    .ok_or(EvalError::InvalidExpression)?;
+                Box::pin(self.evaluate_from(otherwise, first, row)).await
+            }
+            _ => {
+                // Any other operation needs all of its operands
+                let mut resolved = expr.clone();
+                let mut first = first;
+                for operand in operands_mut(&mut resolved) {
+                    let count = count_table_subqueries(operand);
+                    if count > 0 {
+                        let value = Box::pin(self.evaluate_from(operand, first, row)).await?;
+                        operand.expr = Some(expression::Expr::Literal(value));
+                    }
+                    first += count;
+                }
+                self.expressions.evaluate_expression(&resolved, row)
+            }
+        }
+    }
+
+    /// Run the plan of a subquery for a row, or reuse its result if it is uncorrelated
+    /// and has run before
+    async fn run(&mut self, index: usize, row: &Datum) -> Result<Datum, EvalError> {
+        if let Some(value) = &self.memoized[index] {
+            return Ok(value.clone());
+        }
+
+        let plan = &self.planned[index].1;
+        if self.correlated[index] {
+            let mut plan = plan.clone();
+            plan.bind(&Bindings::Row(row))?;
+            return Evaluator::execute_subquery(self.storage.clone(), self.snapshot, &plan).await;
+        }
+        let value = Evaluator::execute_subquery(self.storage.clone(), self.snapshot, plan).await?;
+        self.memoized[index] = Some(value.clone());
+        Ok(value)
+    }
+}
+
+/// The values variables are bound to
+pub enum Bindings<'a> {
+    /// The parameters of a prepared query by name, bound down to the table subqueries
+    /// inside its expressions
+    Parameters(&'a HashMap<String, Datum>),
+    /// The fields of the row a correlated subquery runs for. The variables of the
+    /// table subqueries inside it name fields of their own rows, and are left alone.
+    Row(&'a Datum),
+}
+
+impl Bindings<'_> {
+    /// The value bound to a variable, if any
+    fn value(&self, name: &str) -> Option<Datum> {
+        match self {
+            Bindings::Parameters(parameters) => parameters.get(name).cloned(),
+            Bindings::Row(row) => Some(extract_field_value(row, name)),
+        }
+    }
+
+    /// Whether the variables of table subqueries are bound too
+    pub fn binds_subqueries(&self) -> bool {
+        matches!(self, Bindings::Parameters(_))
+    }
+}
+
+/// The table subqueries of an expression, leaving out those inside them
+pub fn table_subqueries(expr: &Expression) -> Vec<Query> {
+    let mut expr = expr.clone();
+    let mut subqueries = Vec::new();
+    collect_table_subqueries(&mut expr, &mut subqueries);
+    subqueries
+        .into_iter()
+        .filter_map(|subquery| match &subquery.expr {
+            Some(expression::Expr::Subquery(query)) => Some(query.as_ref().clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether a subquery uses variables, leaving out those of the table subqueries inside
+/// it, which name fields of their own rows
+pub fn is_correlated(query: &Query) -> bool {
+    let mut query = query.clone();
+    let mut correlated = false;
+    visit_variables(&mut query, &mut |_| correlated = true);
+    correlated
+}
+
+/// The names of the variables of a query, leaving out those of its table subqueries
+pub fn variable_names(query: &Query) -> Vec<String> {
+    let mut query = query.clone();
+    let mut names = Vec::new();
+    visit_variables(&mut query, &mut |expr| {
+        if let Some(expression::Expr::Variable(variable)) = &expr.expr {
+            if !names.contains(&variable.name) {
+                names.push(variable.name.clone());
+            }
+        }
+    });
+    names
+}
+
+/// Replace the variables of an expression with the values bound to them
+pub fn bind_expression(expr: &mut Expression, bindings: &Bindings<'_>) {
+    match &mut expr.expr {
+        Some(expression::Expr::Variable(variable)) => {
+            if let Some(value) = bindings.value(&variable.name) {
+                expr.expr = Some(expression::Expr::Literal(value));
+            }
+        }
+        Some(expression::Expr::Subquery(query)) if is_table_query(query) => {
+            if bindings.binds_subqueries() {
+                bind_query(query, bindings);
+            }
+        }
+        _ => {
+            for operand in operands_mut(expr) {
+                bind_expression(operand, bindings);
+            }
+        }
+    }
+}
+
+/// Replace the variables of a query with the values bound to them
+pub fn bind_query(query: &mut Query, bindings: &Bindings<'_>) {
+    let (expressions, sources) = query_parts_mut(query);
+    for expr in expressions {
+        bind_expression(expr, bindings);
+    }
+    for source in sources {
+        bind_query(source, bindings);
+    }
+}
+
+/// Whether an expression runs a table subquery
+fn has_table_subqueries(expr: &Expression) -> bool {
+    match &expr.expr {
+        Some(expression::Expr::Subquery(query)) if is_table_query(query) => true,
+        _ => operands(expr).into_iter().any(has_table_subqueries),
+    }
+}
+
+/// How many table subqueries an expression runs, leaving out those inside them
+fn count_table_subqueries(expr: &Expression) -> usize {
+    match &expr.expr {
+        Some(expression::Expr::Subquery(query)) if is_table_query(query) => 1,
+        _ => operands(expr).into_iter().map(count_table_subqueries).sum(),
+    }
+}
+
+fn collect_table_subqueries<'a>(
+    expr: &'a mut Expression,
+    subqueries: &mut Vec<&'a mut Expression>,
+) {
+    if matches!(&expr.expr, Some(expression::Expr::Subquery(query)) if is_table_query(query)) {
+        subqueries.push(expr);
+        return;
+    }
+    for operand in operands_mut(expr) {
+        collect_table_subqueries(operand, subqueries);
+    }
+}
+
+fn visit_variables(query: &mut Query, visit: &mut impl FnMut(&mut Expression)) {
+    let (expressions, sources) = query_parts_mut(query);
+    for expr in expressions {
+        visit_expression_variables(expr, visit);
+    }
+    for source in sources {
+        visit_variables(source, visit);
+    }
+}
+
+fn visit_expression_variables(expr: &mut Expression, visit: &mut impl FnMut(&mut Expression)) {
+    if matches!(expr.expr, Some(expression::Expr::Variable(_))) {
+        visit(expr);
+        return;
+    }
+    for operand in operands_mut(expr) {
+        visit_expression_variables(operand, visit);
+    }
+}
+
+/// Whether a subquery reads anything rather than being a plain expression
+fn is_table_query(query: &Query) -> bool {
+    !matches!(query.kind, Some(query::Kind::Expression(_)))
+}
+
+/// The operands of an expression, including the expression of a subquery that is only
+/// an expression, but not those of table subqueries
+fn operands_mut(expr: &mut Expression) -> Vec<&mut Expression> {
+    match &mut expr.expr {
+        Some(expression::Expr::Binary(op)) => [op.left.as_deref_mut(), op.right.as_deref_mut()]
+            .into_iter()
+            .flatten()
+            .collect(),
+        Some(expression::Expr::Geo(op)) => [op.left.as_deref_mut(), op.right.as_deref_mut()]
+            .into_iter()
+            .flatten()
+            .collect(),
+        Some(expression::Expr::Unary(op)) => op.expr.as_deref_mut().into_iter().collect(),
+        Some(expression::Expr::Match(m)) => m.value.as_deref_mut().into_iter().collect(),
+        Some(expression::Expr::Time(op)) => op.args.iter_mut().collect(),
+        Some(expression::Expr::Document(op)) => op.args.iter_mut().collect(),
+        Some(expression::Expr::Branch(branch)) => branch
+            .cases
+            .iter_mut()
+            .flat_map(|case| [case.condition.as_mut(), case.value.as_mut()])
+            .chain([branch.otherwise.as_deref_mut()])
+            .flatten()
+            .collect(),
+        Some(expression::Expr::Error(error)) => error.message.as_deref_mut().into_iter().collect(),
+        Some(expression::Expr::Subquery(query)) => match &mut query.kind {
+            Some(query::Kind::Expression(inner)) => vec![inner.as_mut()],
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// The operands of an expression, as `operands_mut` finds them
+fn operands(expr: &Expression) -> Vec<&Expression> {
+    match &expr.expr {
+        Some(expression::Expr::Binary(op)) => [op.left.as_deref(), op.right.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect(),
+        Some(expression::Expr::Geo(op)) => [op.left.as_deref(), op.right.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect(),
+        Some(expression::Expr::Unary(op)) => op.expr.as_deref().into_iter().collect(),
+        Some(expression::Expr::Match(m)) => m.value.as_deref().into_iter().collect(),
+        Some(expression::Expr::Time(op)) => op.args.iter().collect(),
+        Some(expression::Expr::Document(op)) => op.args.iter().collect(),
+        Some(expression::Expr::Branch(branch)) => branch
+            .cases
+            .iter()
+            .flat_map(|case| [case.condition.as_ref(), case.value.as_ref()])
+            .chain([branch.otherwise.as_deref()])
+            .flatten()
+            .collect(),
+        Some(expression::Expr::Error(error)) => error.message.as_deref().into_iter().collect(),
+        Some(expression::Expr::Subquery(query)) => match &query.kind {
+            Some(query::Kind::Expression(expr)) => vec![expr.as_ref()],
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// The expressions of a query and the queries it reads from, without looking into
+/// either
+fn query_parts_mut(query: &mut Query) -> (Vec<&mut Expression>, Vec<&mut Query>) {
+    use query::Kind;
+
+    let Some(kind) = &mut query.kind else {
+        return (Vec::new(), Vec::new());
+    };
+    let (expressions, source): (Vec<&mut Expression>, _) = match kind {
+        Kind::Expression(expr) => return (vec![expr.as_mut()], Vec::new()),
+        Kind::Subquery(subquery) => {
+            return (
+                Vec::new(),
+                subquery.query.as_deref_mut().into_iter().collect(),
+            );
+        }
+        Kind::Union(union) => {
+            let sources = [union.source.as_deref_mut(), union.other.as_deref_mut()];
+            return (Vec::new(), sources.into_iter().flatten().collect());
+        }
+        Kind::Nearest(nearest) => {
+            return (
+                nearest.filter.as_deref_mut().into_iter().collect(),
+                Vec::new(),
+            );
+        }
+        Kind::Filter(filter) => (
+            filter.predicate.as_deref_mut().into_iter().collect(),
+            &mut filter.source,
+        ),
+        Kind::GetAll(get_all) => (
+            get_all.key_expressions.iter_mut().collect(),
+            &mut get_all.source,
+        ),
+        Kind::Merge(merge) => (merge.values.iter_mut().collect(), &mut merge.source),
+        Kind::Default(default) => (
+            default.value.as_deref_mut().into_iter().collect(),
+            &mut default.source,
+        ),
+        Kind::OffsetsOf(offsets_of) => (
+            match &mut offsets_of.target {
+                Some(offsets_of::Target::Predicate(predicate)) => vec![predicate.as_mut()],
+                _ => Vec::new(),
+            },
+            &mut offsets_of.source,
+        ),
+        Kind::Get(get) => (Vec::new(), &mut get.source),
+        Kind::Insert(insert) => (Vec::new(), &mut insert.source),
+        Kind::Delete(delete) => (Vec::new(), &mut delete.source),
+        Kind::Update(update) => (Vec::new(), &mut update.source),
+        Kind::OrderBy(order_by) => (Vec::new(), &mut order_by.source),
+        Kind::Limit(limit) => (Vec::new(), &mut limit.source),
+        Kind::Skip(skip) => (Vec::new(), &mut skip.source),
+        Kind::Slice(slice) => (Vec::new(), &mut slice.source),
+        Kind::Nth(nth) => (Vec::new(), &mut nth.source),
+        Kind::Sample(sample) => (Vec::new(), &mut sample.source),
+        Kind::IsEmpty(is_empty) => (Vec::new(), &mut is_empty.source),
+        Kind::Count(count) => (Vec::new(), &mut count.source),
+        Kind::Pluck(pluck) => (Vec::new(), &mut pluck.source),
+        Kind::Without(without) => (Vec::new(), &mut without.source),
+        Kind::HasFields(has_fields) => (Vec::new(), &mut has_fields.source),
+        Kind::WithFields(with_fields) => (Vec::new(), &mut with_fields.source),
+        Kind::Keys(keys) => (Vec::new(), &mut keys.source),
+        Kind::Values(values) => (Vec::new(), &mut values.source),
+        Kind::TypeOf(type_of) => (Vec::new(), &mut type_of.source),
+        Kind::CoerceTo(coerce_to) => (Vec::new(), &mut coerce_to.source),
+        _ => return (Vec::new(), Vec::new()),
+    };
+    (expressions, source.as_deref_mut().into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{BinaryOp, Count, Filter, Table, Variable, binary_op, datum};
+
+    fn variable(name: &str) -> Expression {
+        Expression {
+            expr: Some(expression::Expr::Variable(Variable {
+                name: name.to_string(),
+            })),
+        }
+    }
+
+    fn string(value: &str) -> Datum {
+        Datum {
+            value: Some(datum::Value::String(value.to_string())),
+        }
+    }
+
+    fn count_where(predicate: Expression) -> Query {
+        let table = Query {
+            options: None,
+            cursor: None,
+            kind: Some(query::Kind::Table(Table { table: None })),
+        };
+        let filter = Query {
+            options: None,
+            cursor: None,
+            kind: Some(query::Kind::Filter(Box::new(Filter {
+                source: Some(Box::new(table)),
+                predicate: Some(Box::new(predicate)),
+            }))),
+        };
+        Query {
+            options: None,
+            cursor: None,
+            kind: Some(query::Kind::Count(Box::new(Count {
+                source: Some(Box::new(filter)),
+            }))),
+        }
+    }
+
+    fn equals(left: Expression, right: Expression) -> Expression {
+        Expression {
+            expr: Some(expression::Expr::Binary(Box::new(BinaryOp {
+                op: binary_op::Operator::Eq as i32,
+                left: Some(Box::new(left)),
+                right: Some(Box::new(right)),
+            }))),
+        }
+    }
+
+    fn subquery(query: Query) -> Expression {
+        Expression {
+            expr: Some(expression::Expr::Subquery(Box::new(query))),
+        }
+    }
+
+    #[test]
+    fn test_variables_are_bound_to_the_outer_row() {
+        let mut query = count_where(equals(variable("id"), variable("missing")));
+        assert!(is_correlated(&query));
+
+        let row = Datum::from(crate::ast::Document::from([(
+            "id".to_string(),
+            string("u1"),
+        )]));
+        bind_query(&mut query, &Bindings::Row(&row));
+        assert!(!is_correlated(&query));
+        assert_eq!(
+            query,
+            count_where(equals(
+                Expression {
+                    expr: Some(expression::Expr::Literal(string("u1"))),
+                },
+                Expression {
+                    expr: Some(expression::Expr::Literal(Datum::default())),
+                },
+            ))
+        );
+    }
+
+    #[test]
+    fn test_variables_of_inner_subqueries_are_their_own() {
+        let inner = count_where(variable("id"));
+        let outer = count_where(equals(subquery(inner.clone()), Expression::default()));
+        assert!(!is_correlated(&outer));
+
+        // Only the outermost table subqueries of an expression are planned with it
+        let predicate = equals(
+            subquery(outer.clone()),
+            subquery(count_where(variable("x"))),
+        );
+        let subqueries = table_subqueries(&predicate);
+        assert_eq!(subqueries.len(), 2);
+        assert_eq!(subqueries[0], outer);
+    }
+
+    #[test]
+    fn test_parameters_are_bound_everywhere() {
+        let literal = |value| Expression {
+            expr: Some(expression::Expr::Literal(value)),
+        };
+        let parameters = HashMap::from([("user".to_string(), string("u1"))]);
+
+        // Down to the subqueries of subqueries, leaving other variables as they are
+        let mut predicate = equals(
+            variable("user"),
+            subquery(count_where(equals(
+                variable("other"),
+                subquery(count_where(variable("user"))),
+            ))),
+        );
+        bind_expression(&mut predicate, &Bindings::Parameters(&parameters));
+        assert_eq!(
+            predicate,
+            equals(
+                literal(string("u1")),
+                subquery(count_where(equals(
+                    variable("other"),
+                    subquery(count_where(literal(string("u1")))),
+                ))),
+            )
+        );
+    }
+}
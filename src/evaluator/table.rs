@@ -1,12 +1,27 @@
 use crate::ast::{
-    Cursor, Datum, DatumObject, Document, GetAllResult, GetResult, InsertResult, Predicate,
-    TableCreateResult, TableDropResult, TableListResult, TableScanResult, query_result,
+    self, AnalyzeResult, BetweenResult, Cursor, Datum, DatumObject, Document, FieldRef, Geometry,
+    GetAllResult, GetIntersectingResult, GetNearestResult, GetResult, IndexCreateResult,
+    IndexDropResult, IndexInfo, IndexListResult, InsertResult, NearestResult, Predicate,
+    RebalanceResult, SearchResult, ShardRange, TableCreateResult, TableDropResult, TableListResult,
+    TableOptions, TableScanResult, index_create, query_result, table_options, vector_index,
 };
 use crate::evaluator::error::{EvalError, EvalStats};
-use crate::evaluator::utils::string_datum;
-use crate::storage::StorageBackend;
+use crate::evaluator::sequence::Sequence;
+use crate::evaluator::utils::{string_datum, write_durability};
+use crate::geo::{DEFAULT_MAX_DIST, DEFAULT_MAX_RESULTS, Point, Shape};
+use crate::storage::index::fulltext::FullTextOptions;
+use crate::storage::index::geo::GeoOptions;
+use crate::storage::index::secondary::{IndexKey, SecondaryOptions};
+use crate::storage::index::vector::{DEFAULT_LISTS, DEFAULT_PROBES, Metric, VectorOptions};
+use crate::storage::index::{IndexDefinition, IndexKind};
+use crate::storage::statistics::DEFAULT_SAMPLE_SIZE;
+use crate::storage::{
+    AccessPattern, Compression, Durability, ScanProjection, SnapshotId, StorageBackend,
+    StorageError, TableConfig,
+};
 use futures_util::StreamExt;
 use std::sync::Arc;
+use tokio_stream::wrappers::ReceiverStream;
 use ulid::Ulid;
 
 /// Handler for table-level operations
@@ -25,9 +40,14 @@ impl TableOperations {
         &self,
         database: &str,
         table: &str,
+        options: Option<&TableOptions>,
+        shards: Option<u32>,
         stats: &mut EvalStats,
     ) -> Result<query_result::Result, EvalError> {
-        self.storage.create_table(database, table).await?;
+        let config = options.map(table_config).transpose()?.unwrap_or_default();
+        self.storage
+            .create_sharded_table(database, table, &config, shards.unwrap_or(1))
+            .await?;
         stats.record_rows_processed(1);
 
         Ok(query_result::Result::TableCreate(TableCreateResult {
@@ -50,6 +70,347 @@ impl TableOperations {
         }))
     }
 
+    /// Sample a table's documents to refresh its statistics
+    pub async fn analyze_table(
+        &self,
+        database: &str,
+        table: &str,
+        sample_size: Option<u32>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let sample_size = sample_size.map_or(DEFAULT_SAMPLE_SIZE, |s| s as usize);
+        let statistics = self
+            .storage
+            .analyze_table(database, table, sample_size)
+            .await?;
+        stats.record_rows_processed(statistics.sampled_rows as usize);
+
+        Ok(query_result::Result::Analyze(AnalyzeResult {
+            rows: statistics.row_count,
+            sampled: statistics.sampled_rows,
+        }))
+    }
+
+    /// Redistribute a table's documents over its shards, optionally changing their number
+    pub async fn rebalance_table(
+        &self,
+        database: &str,
+        table: &str,
+        shards: Option<u32>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let summary = self
+            .storage
+            .rebalance_table(database, table, shards)
+            .await?;
+        stats.record_rows_processed(summary.moved as usize);
+
+        Ok(query_result::Result::Rebalance(RebalanceResult {
+            shards: summary
+                .shards
+                .into_iter()
+                .map(|range| ShardRange {
+                    id: range.id,
+                    start_key: range.start,
+                    end_key: range.end,
+                })
+                .collect(),
+            moved: summary.moved,
+        }))
+    }
+
+    /// Build an index over the documents of a table
+    pub async fn create_index(
+        &self,
+        database: &str,
+        table: &str,
+        name: &str,
+        kind: &index_create::Kind,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let definition = IndexDefinition {
+            name: name.to_string(),
+            kind: index_kind(kind),
+        };
+        self.storage
+            .create_index(database, table, &definition)
+            .await?;
+        stats.record_rows_processed(1);
+
+        Ok(query_result::Result::IndexCreate(IndexCreateResult {
+            created: 1,
+        }))
+    }
+
+    /// Drop an index of a table
+    pub async fn drop_index(
+        &self,
+        database: &str,
+        table: &str,
+        name: &str,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        self.storage.drop_index(database, table, name).await?;
+        stats.record_rows_processed(1);
+
+        Ok(query_result::Result::IndexDrop(IndexDropResult {
+            dropped: 1,
+        }))
+    }
+
+    /// List the indexes of a table
+    pub async fn list_indexes(
+        &self,
+        database: &str,
+        table: &str,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let indexes: Vec<IndexInfo> = self
+            .storage
+            .list_indexes(database, table)
+            .await?
+            .into_iter()
+            .map(|definition| {
+                let field_refs = |paths: Vec<Vec<String>>| {
+                    paths
+                        .into_iter()
+                        .map(|path| FieldRef {
+                            path,
+                            separator: ".".to_string(),
+                        })
+                        .collect()
+                };
+                let mut info = IndexInfo {
+                    name: definition.name,
+                    ..Default::default()
+                };
+                match definition.kind {
+                    IndexKind::FullText(options) => {
+                        info.kind = "fulltext".to_string();
+                        info.fields = field_refs(options.fields);
+                    }
+                    IndexKind::Geo(options) => {
+                        info.kind = "geo".to_string();
+                        info.fields = field_refs(vec![options.field]);
+                    }
+                    IndexKind::Vector(options) => {
+                        info.kind = "vector".to_string();
+                        info.fields = field_refs(vec![options.field]);
+                    }
+                    IndexKind::Secondary(options) => {
+                        info.kind = "secondary".to_string();
+                        info.multi = options.multi;
+                        info.unique = options.unique;
+                        match options.key {
+                            IndexKey::Fields(fields) => info.fields = field_refs(fields),
+                            IndexKey::Expression(expression) => info.expression = Some(expression),
+                        }
+                    }
+                }
+                info
+            })
+            .collect();
+
+        stats.record_rows_processed(indexes.len());
+        stats.record_rows_returned(indexes.len());
+
+        Ok(query_result::Result::IndexList(IndexListResult { indexes }))
+    }
+
+    /// Search a full-text index, returning the matches most relevant first
+    #[allow(clippy::too_many_arguments)]
+    pub async fn search(
+        &self,
+        database: &str,
+        table: &str,
+        index: &str,
+        query: &str,
+        limit: Option<u32>,
+        skip: Option<u32>,
+        snapshot: Option<SnapshotId>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let skip = skip.unwrap_or(0) as usize;
+        let limit = limit.map(|limit| limit as usize + skip);
+        let matches = self
+            .storage
+            .search(database, table, index, query, limit, snapshot)
+            .await?;
+        stats.record_rows_processed(matches.len());
+
+        let (documents, scores): (Vec<Datum>, Vec<f64>) = matches
+            .into_iter()
+            .skip(skip)
+            .map(|(doc, score)| (Datum::from(doc), score))
+            .unzip();
+        stats.record_rows_returned(documents.len());
+
+        Ok(query_result::Result::Search(SearchResult {
+            documents,
+            scores,
+        }))
+    }
+
+    /// Fetch the documents whose secondary index entries equal any of `values`
+    pub async fn get_all_by_index(
+        &self,
+        database: &str,
+        table: &str,
+        index: &str,
+        values: &[Datum],
+        snapshot: Option<SnapshotId>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let documents: Vec<Datum> = self
+            .storage
+            .get_all_indexed(database, table, index, values, snapshot)
+            .await?
+            .into_iter()
+            .map(Datum::from)
+            .collect();
+        stats.record_rows_processed(documents.len());
+        stats.record_rows_returned(documents.len());
+
+        Ok(query_result::Result::GetAll(GetAllResult {
+            documents,
+            cursor: None,
+        }))
+    }
+
+    /// Fetch the documents whose secondary index entries lie between `lower`
+    /// (inclusive) and `upper` (exclusive), in index order
+    #[allow(clippy::too_many_arguments)]
+    pub async fn between(
+        &self,
+        database: &str,
+        table: &str,
+        index: &str,
+        lower: Option<&Datum>,
+        upper: Option<&Datum>,
+        snapshot: Option<SnapshotId>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let documents: Vec<Datum> = self
+            .storage
+            .between(database, table, index, lower, upper, snapshot)
+            .await?
+            .into_iter()
+            .map(Datum::from)
+            .collect();
+        stats.record_rows_processed(documents.len());
+        stats.record_rows_returned(documents.len());
+
+        Ok(query_result::Result::Between(BetweenResult { documents }))
+    }
+
+    /// Find the documents whose geometry in a geospatial index intersects `geometry`
+    pub async fn get_intersecting(
+        &self,
+        database: &str,
+        table: &str,
+        index: &str,
+        geometry: &Geometry,
+        snapshot: Option<SnapshotId>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let shape = Shape::try_from(geometry)?;
+        let documents: Vec<Datum> = self
+            .storage
+            .get_intersecting(database, table, index, &shape, snapshot)
+            .await?
+            .into_iter()
+            .map(Datum::from)
+            .collect();
+        stats.record_rows_processed(documents.len());
+        stats.record_rows_returned(documents.len());
+
+        Ok(query_result::Result::GetIntersecting(
+            GetIntersectingResult { documents },
+        ))
+    }
+
+    /// Find the documents nearest to `point` in a geospatial index, nearest first
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_nearest(
+        &self,
+        database: &str,
+        table: &str,
+        index: &str,
+        point: &ast::Point,
+        max_dist: Option<f64>,
+        max_results: Option<u32>,
+        snapshot: Option<SnapshotId>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let max_dist = max_dist.unwrap_or(DEFAULT_MAX_DIST);
+        if !(max_dist.is_finite() && max_dist >= 0.0) {
+            return Err(EvalError::InvalidGeometry(format!(
+                "max_dist must be a non-negative distance, got {max_dist}"
+            )));
+        }
+        let max_results = max_results.unwrap_or(DEFAULT_MAX_RESULTS) as usize;
+        let nearest = self
+            .storage
+            .get_nearest(
+                database,
+                table,
+                index,
+                Point::try_from(point)?,
+                max_dist,
+                max_results,
+                snapshot,
+            )
+            .await?;
+        stats.record_rows_processed(nearest.len());
+
+        let (documents, distances): (Vec<Datum>, Vec<f64>) = nearest
+            .into_iter()
+            .map(|(doc, distance)| (Datum::from(doc), distance))
+            .unzip();
+        stats.record_rows_returned(documents.len());
+
+        Ok(query_result::Result::GetNearest(GetNearestResult {
+            documents,
+            distances,
+        }))
+    }
+
+    /// Find the `k` documents nearest to `vector` in a vector index that `predicate`
+    /// accepts, nearest first
+    #[allow(clippy::too_many_arguments)]
+    pub async fn nearest(
+        &self,
+        database: &str,
+        table: &str,
+        index: &str,
+        vector: &[f64],
+        k: u32,
+        probes: Option<u32>,
+        predicate: Option<Predicate>,
+        snapshot: Option<SnapshotId>,
+        stats: &mut EvalStats,
+    ) -> Result<query_result::Result, EvalError> {
+        let probes = probes.unwrap_or(DEFAULT_PROBES) as usize;
+        let nearest = self
+            .storage
+            .nearest(
+                database, table, index, vector, k as usize, probes, predicate, snapshot,
+            )
+            .await?;
+        stats.record_rows_processed(nearest.len());
+
+        let (documents, distances): (Vec<Datum>, Vec<f64>) = nearest
+            .into_iter()
+            .map(|(doc, distance)| (Datum::from(doc), distance))
+            .unzip();
+        stats.record_rows_returned(documents.len());
+
+        Ok(query_result::Result::Nearest(NearestResult {
+            documents,
+            distances,
+        }))
+    }
+
     /// List all tables in the specified database
     pub async fn list_tables(
         &self,
@@ -84,6 +445,7 @@ impl TableOperations {
     }
 
     /// Scan all documents in a table with optional filtering
+    #[allow(clippy::too_many_arguments)]
     pub async fn scan_table(
         &self,
         database: &str,
@@ -91,13 +453,17 @@ impl TableOperations {
         cursor: Option<Cursor>,
         predicate: Option<Predicate>,
         skip: Option<usize>,
+        projection: ScanProjection,
+        snapshot: Option<SnapshotId>,
         stats: &mut EvalStats,
     ) -> Result<query_result::Result, EvalError> {
         let (start_key, limit) = Cursor::convert_to_page_params(cursor.as_ref());
 
         let mut stream = self
             .storage
-            .scan_table(database, table, start_key, limit, skip, predicate)
+            .scan_table(
+                database, table, start_key, limit, skip, predicate, projection, snapshot,
+            )
             .await?;
 
         let mut documents: Vec<Datum> = Vec::new();
@@ -119,15 +485,50 @@ impl TableOperations {
         }))
     }
 
+    /// The documents of a table that pass the predicate, read as the sequence is
+    /// consumed so that dropping it stops the scan
+    pub async fn scan_sequence(
+        &self,
+        database: &str,
+        table: &str,
+        predicate: Option<Predicate>,
+        projection: ScanProjection,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Sequence, EvalError> {
+        let stream = self
+            .storage
+            .scan_table(
+                database, table, None, None, None, predicate, projection, snapshot,
+            )
+            .await?;
+        Ok(document_sequence(stream))
+    }
+
+    /// The documents with the keys, read as the sequence is consumed
+    pub async fn get_all_sequence(
+        &self,
+        database: &str,
+        table: &str,
+        keys: &[String],
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Sequence, EvalError> {
+        let stream = self
+            .storage
+            .stream_get_all(database, table, keys, None, None, None, snapshot)
+            .await?;
+        Ok(document_sequence(stream))
+    }
+
     /// Get a single document by key
     pub async fn get_document(
         &self,
         database: &str,
         table: &str,
         key: &str,
+        snapshot: Option<SnapshotId>,
         stats: &mut EvalStats,
     ) -> Result<query_result::Result, EvalError> {
-        let doc = self.storage.get(database, table, key).await?;
+        let doc = self.storage.get(database, table, key, snapshot).await?;
 
         stats.record_rows_processed(1);
         stats.record_rows_returned(1);
@@ -138,6 +539,7 @@ impl TableOperations {
     }
 
     /// Get multiple documents by their keys
+    #[allow(clippy::too_many_arguments)]
     pub async fn get_documents(
         &self,
         database: &str,
@@ -145,6 +547,7 @@ impl TableOperations {
         keys: &[String],
         cursor: Option<Cursor>,
         skip: Option<usize>,
+        snapshot: Option<SnapshotId>,
         stats: &mut EvalStats,
     ) -> Result<query_result::Result, EvalError> {
         let (start_key, limit) = Cursor::convert_to_page_params(cursor.as_ref());
@@ -154,7 +557,7 @@ impl TableOperations {
 
         let mut stream = self
             .storage
-            .stream_get_all(database, table, keys, start_key, limit, skip)
+            .stream_get_all(database, table, keys, start_key, limit, skip, snapshot)
             .await?;
 
         while let Some(doc) = stream.next().await.transpose()? {
@@ -179,6 +582,7 @@ impl TableOperations {
         database: &str,
         table: &str,
         documents: &[DatumObject],
+        durability: Option<Durability>,
         stats: &mut EvalStats,
     ) -> Result<query_result::Result, EvalError> {
         let mut generated_keys = Vec::new();
@@ -194,7 +598,9 @@ impl TableOperations {
             })
             .collect();
 
-        self.storage.put_batch(database, table, &docs).await?;
+        self.storage
+            .put_batch(database, table, &docs, durability)
+            .await?;
         stats.record_rows_processed(documents.len());
 
         Ok(query_result::Result::Insert(InsertResult {
@@ -204,6 +610,11 @@ impl TableOperations {
     }
 }
 
+/// The documents of a storage stream as the items of a sequence
+fn document_sequence(stream: ReceiverStream<Result<Document, StorageError>>) -> Sequence {
+    Box::pin(stream.map(|doc| doc.map(Datum::from).map_err(EvalError::from)))
+}
+
 /// Extract the document key from a document.
 fn extract_document_primary_key(document: &Document) -> Result<String, EvalError> {
     match document.get("id") {
@@ -212,6 +623,95 @@ fn extract_document_primary_key(document: &Document) -> Result<String, EvalError
     }
 }
 
+/// Convert the table options of a create request into the storage configuration.
+fn table_config(options: &TableOptions) -> Result<TableConfig, EvalError> {
+    let invalid = |msg: String| EvalError::StorageError(StorageError::InvalidTableOptions(msg));
+
+    let compression = match table_options::Compression::try_from(options.compression) {
+        Ok(table_options::Compression::Default) => Compression::default(),
+        Ok(table_options::Compression::None) => Compression::None,
+        Ok(table_options::Compression::Snappy) => Compression::Snappy,
+        Ok(table_options::Compression::Lz4) => Compression::Lz4,
+        Ok(table_options::Compression::Zstd) => Compression::Zstd,
+        Err(_) => {
+            return Err(invalid(format!(
+                "unknown compression {}",
+                options.compression
+            )));
+        }
+    };
+
+    let access_pattern = match table_options::AccessPattern::try_from(options.access_pattern) {
+        Ok(table_options::AccessPattern::Balanced) => AccessPattern::Balanced,
+        Ok(table_options::AccessPattern::PointLookup) => AccessPattern::PointLookup,
+        Ok(table_options::AccessPattern::Scan) => AccessPattern::Scan,
+        Err(_) => {
+            return Err(invalid(format!(
+                "unknown access pattern {}",
+                options.access_pattern
+            )));
+        }
+    };
+
+    Ok(TableConfig {
+        compression,
+        compression_level: options.compression_level,
+        bloom_bits_per_key: options.bloom_bits_per_key,
+        block_size: options.block_size.map(|size| size as usize),
+        access_pattern,
+        durability: write_durability(options.durability()).unwrap_or_default(),
+    })
+}
+
+/// Convert the kind of a create index request into its storage definition.
+fn index_kind(kind: &index_create::Kind) -> IndexKind {
+    match kind {
+        index_create::Kind::FullText(full_text) => IndexKind::FullText(FullTextOptions {
+            fields: full_text
+                .fields
+                .iter()
+                .map(|field| field.path.clone())
+                .collect(),
+            stemming: full_text.stemming,
+        }),
+        index_create::Kind::Geo(geo) => IndexKind::Geo(GeoOptions {
+            field: geo
+                .field
+                .as_ref()
+                .map(|field| field.path.clone())
+                .unwrap_or_default(),
+        }),
+        index_create::Kind::Vector(vector) => IndexKind::Vector(VectorOptions {
+            field: vector
+                .field
+                .as_ref()
+                .map(|field| field.path.clone())
+                .unwrap_or_default(),
+            dimension: vector.dimension,
+            metric: match vector.metric() {
+                vector_index::Metric::Cosine => Metric::Cosine,
+                vector_index::Metric::L2 => Metric::L2,
+                vector_index::Metric::Dot => Metric::Dot,
+            },
+            lists: vector.lists.unwrap_or(DEFAULT_LISTS),
+        }),
+        index_create::Kind::Secondary(secondary) => IndexKind::Secondary(SecondaryOptions {
+            key: match &secondary.expression {
+                Some(expression) => IndexKey::Expression(expression.as_ref().clone()),
+                None => IndexKey::Fields(
+                    secondary
+                        .fields
+                        .iter()
+                        .map(|field| field.path.clone())
+                        .collect(),
+                ),
+            },
+            multi: secondary.multi,
+            unique: secondary.unique,
+        }),
+    }
+}
+
 /// Ensure a document has a valid key, generating one if necessary.
 fn ensure_document_key(
     doc_fields: &mut std::collections::HashMap<String, Datum>,
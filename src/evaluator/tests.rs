@@ -1,18 +1,29 @@
 use crate::EvalError;
 use crate::ast::{
-    Cursor, Document, FieldRef, GetAllResult, GetResult, MatchExpr, OrderByField, Query,
-    pluck_result, query_result, without_result,
+    ArrayOp, BranchExpr, Cursor, DatabaseRef, Decimal, Document, DocumentOp, ErrorExpr, FieldRef,
+    GeoOp, Geometry, GetAllResult, GetResult, MatchExpr, NullValue, OrderByField, Point, Polygon,
+    Query, ReadMode, SequenceResult, TableOptions, TableRef, TableScanResult, Time, TimeOp,
+    array_op::Operator as ArrayOperator, branch_expr, document_function_result,
+    document_op::Operator as DocumentOperator, geo_op::Operator as GeoOperator, geometry,
+    offsets_of, pluck_result, query_result, sequence_result, table_options,
+    time_op::Operator as TimeOperator, without_result,
 };
 use crate::evaluator::database::DatabaseOperations;
 use crate::evaluator::expression::ExpressionEvaluator;
 use crate::evaluator::query::QueryProcessor;
 use crate::evaluator::table::TableOperations;
-use crate::evaluator::utils::{bool_datum, datum_to_bool, extract_field_value, string_datum};
+use crate::evaluator::update;
+use crate::evaluator::utils::{
+    bool_datum, compare_values, datum_to_bool, datums_equal, extract_field_value, string_datum,
+};
+use crate::evaluator::{CursorSnapshots, Evaluator};
 use crate::expression::Expr;
-use crate::storage::StorageBackend;
+use crate::planner::PlanNode;
 use crate::storage::memory::MemoryStorage;
+use crate::storage::statistics::StatisticsProvider;
+use crate::storage::{StorageBackend, StorageError, TableConfig};
 use crate::{
-    BinaryOp, Datum, DatumObject, EvalStats, Expression, UnaryOp,
+    BinaryOp, Datum, DatumArray, DatumObject, EvalStats, Expression, UnaryOp,
     binary_op::Operator as BinaryOperator, datum, unary_op::Operator as UnaryOperator,
 };
 use std::collections::HashMap;
@@ -52,6 +63,13 @@ pub fn int_datum(i: i64) -> Datum {
     }
 }
 
+/// Create a float datum
+pub fn float_datum(f: f64) -> Datum {
+    Datum {
+        value: Some(datum::Value::Float(f)),
+    }
+}
+
 #[tokio::test]
 async fn test_create_database() {
     let storage = Arc::new(MemoryStorage::new());
@@ -167,6 +185,258 @@ fn test_binary_comparison() {
     assert!(datum_to_bool(&result));
 }
 
+#[test]
+fn test_total_ordering() {
+    fn array(items: Vec<Datum>) -> Datum {
+        Datum {
+            value: Some(datum::Value::Array(DatumArray {
+                items,
+                element_type: String::new(),
+            })),
+        }
+    }
+    fn object(fields: Vec<(&str, Datum)>) -> Datum {
+        Datum {
+            value: Some(datum::Value::Object(DatumObject {
+                fields: fields
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value))
+                    .collect(),
+            })),
+        }
+    }
+
+    // Types sort in turn, and values of a type among themselves
+    let ordered = [
+        null_datum(),
+        bool_datum(false),
+        bool_datum(true),
+        int_datum(-3),
+        float_datum(0.5),
+        int_datum(1),
+        float_datum(f64::NAN),
+        Datum {
+            value: Some(datum::Value::Time(Time {
+                epoch_micros: 0,
+                offset_minutes: 0,
+            })),
+        },
+        string_datum(String::new()),
+        string_datum("a".to_string()),
+        Datum {
+            value: Some(datum::Value::Binary(vec![0])),
+        },
+        array(vec![]),
+        array(vec![int_datum(1)]),
+        array(vec![int_datum(1), null_datum()]),
+        array(vec![string_datum("a".to_string())]),
+        object(vec![]),
+        object(vec![("a", int_datum(1)), ("b", int_datum(1))]),
+        object(vec![("a", int_datum(2))]),
+        Datum {
+            value: Some(datum::Value::Geometry(Geometry {
+                kind: Some(geometry::Kind::Point(Point {
+                    longitude: 0.0,
+                    latitude: 0.0,
+                })),
+            })),
+        },
+    ];
+    for (i, a) in ordered.iter().enumerate() {
+        for (j, b) in ordered.iter().enumerate() {
+            assert_eq!(compare_values(a, b), i.cmp(&j), "{a} vs {b}");
+        }
+    }
+
+    // Equality is deep and exact, and numbers are equal whatever their type
+    let nested = || {
+        object(vec![
+            ("tags", array(vec![string_datum("x".to_string())])),
+            ("size", int_datum(2)),
+        ])
+    };
+    assert!(datums_equal(&nested(), &nested()));
+    assert!(datums_equal(
+        &nested(),
+        &object(vec![
+            ("size", float_datum(2.0)),
+            ("tags", array(vec![string_datum("x".to_string())])),
+        ])
+    ));
+    assert!(!datums_equal(
+        &nested(),
+        &object(vec![("tags", array(vec![string_datum("x".to_string())]))])
+    ));
+    assert!(!datums_equal(&float_datum(0.1 + 0.2), &float_datum(0.3)));
+    assert!(datums_equal(&float_datum(-0.0), &int_datum(0)));
+    assert!(datums_equal(
+        &null_datum(),
+        &Datum {
+            value: Some(datum::Value::Null(NullValue::NullValue.into())),
+        }
+    ));
+    assert!(!datums_equal(&int_datum(1), &string_datum("1".to_string())));
+
+    let evaluator = ExpressionEvaluator::new();
+    let eq = Expression {
+        expr: Some(Expr::Binary(Box::new(BinaryOp {
+            op: BinaryOperator::Eq.into(),
+            left: Some(Box::new(Expression {
+                expr: Some(Expr::Literal(nested())),
+            })),
+            right: Some(Box::new(Expression {
+                expr: Some(Expr::Literal(nested())),
+            })),
+        }))),
+    };
+    let result = evaluator.evaluate_expression(&eq, &null_datum()).unwrap();
+    assert!(datum_to_bool(&result));
+}
+
+#[test]
+fn test_arithmetic() {
+    fn decimal(value: &str) -> Datum {
+        Datum {
+            value: Some(datum::Value::Decimal(Decimal {
+                value: value.to_string(),
+            })),
+        }
+    }
+
+    let evaluator = ExpressionEvaluator::new();
+    let apply = |left: Datum, op: BinaryOperator, right: Datum| {
+        evaluator.perform_binary_operation(&left, &op, &right)
+    };
+
+    let cases = [
+        (
+            int_datum(7),
+            BinaryOperator::Add,
+            int_datum(5),
+            int_datum(12),
+        ),
+        (
+            int_datum(7),
+            BinaryOperator::Sub,
+            int_datum(9),
+            int_datum(-2),
+        ),
+        (
+            int_datum(7),
+            BinaryOperator::Mul,
+            int_datum(-3),
+            int_datum(-21),
+        ),
+        (
+            int_datum(7),
+            BinaryOperator::Div,
+            int_datum(2),
+            float_datum(3.5),
+        ),
+        (
+            int_datum(-7),
+            BinaryOperator::Mod,
+            int_datum(3),
+            int_datum(-1),
+        ),
+        (
+            int_datum(1),
+            BinaryOperator::Add,
+            float_datum(0.5),
+            float_datum(1.5),
+        ),
+        (
+            float_datum(7.5),
+            BinaryOperator::Mod,
+            int_datum(2),
+            float_datum(1.5),
+        ),
+        // Decimals stay exact, taking the other side as a decimal
+        (
+            decimal("0.10"),
+            BinaryOperator::Add,
+            decimal("0.2"),
+            decimal("0.30"),
+        ),
+        (
+            decimal("19.99"),
+            BinaryOperator::Mul,
+            int_datum(3),
+            decimal("59.97"),
+        ),
+        (
+            float_datum(0.1),
+            BinaryOperator::Add,
+            decimal("0.2"),
+            decimal("0.3"),
+        ),
+        (
+            decimal("10.00"),
+            BinaryOperator::Div,
+            int_datum(4),
+            decimal("2.50"),
+        ),
+        (
+            decimal("-7.5"),
+            BinaryOperator::Mod,
+            int_datum(2),
+            decimal("-1.5"),
+        ),
+        (
+            int_datum(1),
+            BinaryOperator::Sub,
+            decimal("0.01"),
+            decimal("0.99"),
+        ),
+    ];
+    for (left, op, right, expected) in cases {
+        let result = apply(left.clone(), op, right.clone()).unwrap();
+        // Check the type and the written value, not just that the numbers are equal
+        assert_eq!(result, expected, "{left} {op:?} {right}");
+    }
+
+    assert!(matches!(
+        apply(int_datum(1), BinaryOperator::Div, int_datum(0)),
+        Err(EvalError::DivisionByZero)
+    ));
+    assert!(matches!(
+        apply(float_datum(1.0), BinaryOperator::Mod, float_datum(0.0)),
+        Err(EvalError::DivisionByZero)
+    ));
+    assert!(matches!(
+        apply(decimal("1"), BinaryOperator::Div, decimal("0.00")),
+        Err(EvalError::DivisionByZero)
+    ));
+    assert!(matches!(
+        apply(int_datum(i64::MAX), BinaryOperator::Add, int_datum(1)),
+        Err(EvalError::ArithmeticOverflow)
+    ));
+    assert!(matches!(
+        apply(decimal("1e37"), BinaryOperator::Mul, int_datum(100)),
+        Err(EvalError::InvalidDecimal(_))
+    ));
+    assert!(matches!(
+        apply(decimal("1"), BinaryOperator::Add, float_datum(f64::NAN)),
+        Err(EvalError::InvalidDecimal(_))
+    ));
+    assert!(matches!(
+        apply(
+            int_datum(1),
+            BinaryOperator::Add,
+            string_datum("1".to_string())
+        ),
+        Err(EvalError::TypeMismatch)
+    ));
+
+    // Numbers of every type compare by value
+    assert!(datums_equal(&decimal("2.50"), &float_datum(2.5)));
+    assert!(datums_equal(&decimal("-3"), &int_datum(-3)));
+    assert!(compare_values(&decimal("0.1"), &float_datum(0.1)).is_lt());
+    assert!(compare_values(&int_datum(i64::MAX), &float_datum(9.223_372_036_854_776e18)).is_lt());
+    assert!(compare_values(&decimal("1e30"), &float_datum(f64::INFINITY)).is_lt());
+    assert!(compare_values(&decimal("1e30"), &float_datum(f64::NAN)).is_lt());
+}
+
 #[test]
 fn test_logical_and_short_circuit() {
     let evaluator = ExpressionEvaluator::new();
@@ -281,6 +551,7 @@ fn test_is_boolean_expression() {
             })),
             pattern: "@example\\.com$".to_string(),
             flags: "".to_string(),
+            capture: false,
         }))),
     };
     let subquery_expr = Expression {
@@ -470,6 +741,58 @@ async fn test_order_documents() {
     }
 }
 
+#[tokio::test]
+async fn test_order_documents_of_mixed_types() {
+    let storage = Arc::new(MemoryStorage::new());
+    let processor = QueryProcessor::new(storage);
+    let mut stats = EvalStats::new();
+
+    let document = |id: &str, age: Option<Datum>| {
+        let mut fields = HashMap::new();
+        fields.insert("id".to_string(), string_datum(id.to_string()));
+        if let Some(age) = age {
+            fields.insert("age".to_string(), age);
+        }
+        Datum {
+            value: Some(datum::Value::Object(DatumObject { fields })),
+        }
+    };
+    let documents = vec![
+        document("1", Some(string_datum("thirty".to_string()))),
+        document("2", Some(float_datum(20.5))),
+        document("3", None),
+        document("4", Some(int_datum(20))),
+        document("5", Some(bool_datum(true))),
+    ];
+    let order_fields = vec![OrderByField {
+        field_name: "age".to_string(),
+        ascending: true,
+    }];
+
+    let result = processor
+        .order_documents(
+            create_test_result(documents),
+            &order_fields,
+            None,
+            &mut stats,
+        )
+        .await;
+
+    // Missing values first, then booleans, numbers and strings
+    let Ok(query_result::Result::OrderBy(order_result)) = result else {
+        panic!("Expected ordered documents");
+    };
+    let ids: Vec<_> = order_result
+        .documents
+        .iter()
+        .map(|doc| extract_field_value(doc, "id"))
+        .collect();
+    assert_eq!(
+        ids,
+        ["3", "5", "4", "2", "1"].map(|id| string_datum(id.to_string()))
+    );
+}
+
 #[tokio::test]
 async fn test_filter_documents_with_cursor() {
     let storage = Arc::new(MemoryStorage::new());
@@ -511,6 +834,7 @@ async fn test_filter_documents_with_cursor() {
         start_key: Some("start".to_string()),
         batch_size: Some(10),
         sort: None,
+        snapshot: None,
     });
 
     let result = processor
@@ -561,6 +885,7 @@ async fn test_filter_documents_no_cursor_when_few_results() {
         start_key: Some("start".to_string()),
         batch_size: Some(10),
         sort: None,
+        snapshot: None,
     });
 
     let result = processor
@@ -583,13 +908,50 @@ async fn test_create_table() {
     storage.create_database("test_db").await.unwrap();
 
     let result = table_ops
-        .create_table("test_db", "test_table", &mut stats)
+        .create_table("test_db", "test_table", None, None, &mut stats)
         .await;
 
     assert!(result.is_ok());
     assert_eq!(stats.rows_processed, 1);
 }
 
+#[tokio::test]
+async fn test_create_table_with_options() {
+    let storage = Arc::new(MemoryStorage::new());
+    let table_ops = TableOperations::new(storage.clone());
+    let mut stats = EvalStats::new();
+
+    storage.create_database("test_db").await.unwrap();
+
+    let options = TableOptions {
+        compression: table_options::Compression::Zstd.into(),
+        compression_level: Some(3),
+        bloom_bits_per_key: Some(12.0),
+        block_size: Some(16 * 1024),
+        access_pattern: table_options::AccessPattern::PointLookup.into(),
+        durability: crate::ast::Durability::Hard.into(),
+    };
+    let result = table_ops
+        .create_table("test_db", "test_table", Some(&options), None, &mut stats)
+        .await;
+    assert!(result.is_ok());
+
+    let options = TableOptions {
+        compression: table_options::Compression::Snappy.into(),
+        compression_level: Some(3),
+        ..options
+    };
+    let result = table_ops
+        .create_table("test_db", "other_table", Some(&options), None, &mut stats)
+        .await;
+    assert!(matches!(
+        result,
+        Err(EvalError::StorageError(StorageError::InvalidTableOptions(
+            _
+        )))
+    ));
+}
+
 #[tokio::test]
 async fn test_insert_documents() {
     let storage = Arc::new(MemoryStorage::new());
@@ -599,7 +961,7 @@ async fn test_insert_documents() {
     storage.create_database("test_db").await.unwrap();
 
     table_ops
-        .create_table("test_db", "test_table", &mut stats)
+        .create_table("test_db", "test_table", None, None, &mut stats)
         .await
         .unwrap();
 
@@ -614,7 +976,7 @@ async fn test_insert_documents() {
     let doc = DatumObject { fields };
 
     let result = table_ops
-        .insert_documents("test_db", "test_table", &[doc], &mut stats)
+        .insert_documents("test_db", "test_table", &[doc], None, &mut stats)
         .await;
     assert!(result.is_ok());
 
@@ -635,7 +997,7 @@ async fn test_get_document() {
     storage.create_database("test_db").await.unwrap();
 
     table_ops
-        .create_table("test_db", "test_table", &mut stats)
+        .create_table("test_db", "test_table", None, None, &mut stats)
         .await
         .unwrap();
 
@@ -655,13 +1017,13 @@ async fn test_get_document() {
     let doc = DatumObject { fields };
 
     table_ops
-        .insert_documents("test_db", "test_table", &[doc], &mut stats)
+        .insert_documents("test_db", "test_table", &[doc], None, &mut stats)
         .await
         .unwrap();
 
     // Now get the document
     let result = table_ops
-        .get_document("test_db", "test_table", "test_id", &mut stats)
+        .get_document("test_db", "test_table", "test_id", None, &mut stats)
         .await;
     assert!(result.is_ok());
 
@@ -1297,3 +1659,1761 @@ async fn test_without_multi_object_nested_fields() {
         panic!("Expected Without result");
     }
 }
+
+async fn create_scan_test_storage(count: usize) -> Arc<MemoryStorage> {
+    let storage = Arc::new(MemoryStorage::new());
+    storage.create_database("test_db").await.unwrap();
+    storage
+        .create_table("test_db", "test_table", &TableConfig::default())
+        .await
+        .unwrap();
+
+    for i in 0..count {
+        let key = format!("doc{i}");
+        let mut doc = Document::new();
+        doc.insert("id".to_string(), string_datum(key.clone()));
+        storage
+            .put("test_db", "test_table", &key, &doc, None)
+            .await
+            .unwrap();
+    }
+
+    storage
+}
+
+fn create_scan_plan() -> PlanNode {
+    PlanNode::TableScan {
+        table_ref: TableRef {
+            database: Some(DatabaseRef {
+                name: "test_db".to_string(),
+            }),
+            name: "test_table".to_string(),
+        },
+        cursor: None,
+        filter: None,
+        projection: None,
+        cost: 1.0,
+        estimated_rows: 3.0,
+    }
+}
+
+async fn scan_batch(
+    evaluator: &mut Evaluator,
+    cursor: Cursor,
+) -> Result<TableScanResult, EvalError> {
+    match evaluator
+        .eval_with_cursor(&create_scan_plan(), Some(cursor))
+        .await?
+        .result
+    {
+        query_result::Result::Table(result) => Ok(result),
+        _ => panic!("Expected TableScan result"),
+    }
+}
+
+#[tokio::test]
+async fn test_snapshot_read_mode_pins_snapshot_across_batches() {
+    let storage = create_scan_test_storage(3).await;
+    let mut evaluator = Evaluator::new(storage.clone());
+    evaluator.set_read_mode(ReadMode::Snapshot);
+
+    let first = scan_batch(&mut evaluator, Cursor::new(None, Some(2)))
+        .await
+        .unwrap();
+    assert_eq!(first.documents.len(), 2);
+    let cursor = first.cursor.expect("Expected a continuation cursor");
+    assert!(cursor.snapshot.is_some());
+
+    // Writes made after the first batch are not visible to the rest of the cursor
+    for i in 0..3 {
+        storage
+            .delete("test_db", "test_table", &format!("doc{i}"), None)
+            .await
+            .unwrap();
+    }
+
+    let mut evaluator = Evaluator::new(storage.clone());
+    let second = scan_batch(&mut evaluator, cursor.clone()).await.unwrap();
+    assert_eq!(second.documents.len(), 2);
+    assert_eq!(second.cursor.unwrap().snapshot, cursor.snapshot);
+
+    // The final batch releases the snapshot
+    let last = scan_batch(
+        &mut evaluator,
+        Cursor {
+            batch_size: Some(10),
+            ..cursor.clone()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(last.documents.len(), 3);
+    assert!(last.cursor.is_none());
+
+    let expired = scan_batch(&mut evaluator, cursor).await;
+    assert!(matches!(
+        expired,
+        Err(EvalError::StorageError(StorageError::SnapshotNotFound(_)))
+    ));
+}
+
+#[tokio::test]
+async fn test_cursor_snapshots_belong_to_their_connection() {
+    let storage = create_scan_test_storage(3).await;
+    let connection = |snapshots: &Arc<CursorSnapshots>| {
+        let mut evaluator = Evaluator::new(storage.clone());
+        evaluator.set_read_mode(ReadMode::Snapshot);
+        evaluator.set_cursor_snapshots(snapshots.clone());
+        evaluator
+    };
+    let first_connection = Arc::new(CursorSnapshots::new(storage.clone()));
+    let second_connection = Arc::new(CursorSnapshots::new(storage.clone()));
+
+    let first = scan_batch(
+        &mut connection(&first_connection),
+        Cursor::new(None, Some(1)),
+    )
+    .await
+    .unwrap();
+    let cursor = first.cursor.expect("Expected a continuation cursor");
+    let snapshot = cursor.snapshot.expect("Expected a pinned snapshot");
+    assert!(first_connection.holds(snapshot));
+
+    // Another connection cannot read from the snapshot
+    let stolen = scan_batch(&mut connection(&second_connection), cursor.clone()).await;
+    assert!(matches!(
+        stolen,
+        Err(EvalError::StorageError(StorageError::SnapshotNotFound(_)))
+    ));
+
+    let second = scan_batch(&mut connection(&first_connection), cursor.clone())
+        .await
+        .unwrap();
+    assert_eq!(second.documents.len(), 1);
+
+    // Closing the connection releases the snapshots its cursors still hold
+    first_connection.release_all().await;
+    assert!(!first_connection.holds(snapshot));
+    let released = scan_batch(&mut Evaluator::new(storage.clone()), cursor).await;
+    assert!(matches!(
+        released,
+        Err(EvalError::StorageError(StorageError::SnapshotNotFound(_)))
+    ));
+}
+
+#[tokio::test]
+async fn test_single_read_mode_does_not_pin_cursor() {
+    for read_mode in [ReadMode::Single, ReadMode::Outdated] {
+        let storage = create_scan_test_storage(3).await;
+        let mut evaluator = Evaluator::new(storage.clone());
+        evaluator.set_read_mode(read_mode);
+
+        let first = scan_batch(&mut evaluator, Cursor::new(None, Some(2)))
+            .await
+            .unwrap();
+        let cursor = first.cursor.expect("Expected a continuation cursor");
+        assert!(cursor.snapshot.is_none());
+
+        for i in 0..3 {
+            storage
+                .delete("test_db", "test_table", &format!("doc{i}"), None)
+                .await
+                .unwrap();
+        }
+
+        let second = scan_batch(&mut evaluator, cursor).await.unwrap();
+        assert!(second.documents.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_analyze_table() {
+    let storage = create_scan_test_storage(10).await;
+    let mut evaluator = Evaluator::new(storage.clone());
+
+    let plan = PlanNode::Analyze {
+        table_ref: TableRef {
+            database: Some(DatabaseRef {
+                name: "test_db".to_string(),
+            }),
+            name: "test_table".to_string(),
+        },
+        sample_size: Some(5),
+        cost: 1.0,
+    };
+    let result = evaluator.eval(&plan).await.unwrap().result;
+    match result {
+        query_result::Result::Analyze(result) => assert_eq!(result.rows, 10),
+        _ => panic!("Expected Analyze result"),
+    }
+
+    let stats = storage.table_statistics("test_db", "test_table").unwrap();
+    assert_eq!(stats.row_count, 10);
+    assert_eq!(stats.fields["id"].distinct_values, 10.0);
+}
+
+#[tokio::test]
+async fn test_indexes_require_support_from_storage() {
+    let storage = create_scan_test_storage(1).await;
+    let mut evaluator = Evaluator::new(storage);
+    let table_ref = TableRef {
+        database: Some(DatabaseRef {
+            name: "test_db".to_string(),
+        }),
+        name: "test_table".to_string(),
+    };
+
+    let plan = PlanNode::ListIndexes {
+        table_ref: table_ref.clone(),
+        cost: 1.0,
+    };
+    assert!(matches!(
+        evaluator.eval(&plan).await,
+        Err(EvalError::StorageError(StorageError::Index(_)))
+    ));
+
+    let plan = PlanNode::Limit {
+        source: Box::new(PlanNode::Search {
+            table_ref: table_ref.clone(),
+            index: "body".to_string(),
+            query: "rust".to_string(),
+            cost: 1.0,
+            estimated_rows: 1.0,
+        }),
+        count: 1,
+        cost: 1.0,
+    };
+    assert!(matches!(
+        evaluator.eval(&plan).await,
+        Err(EvalError::StorageError(StorageError::Index(_)))
+    ));
+
+    let plan = PlanNode::GetNearest {
+        table_ref: table_ref.clone(),
+        index: "location".to_string(),
+        point: Point {
+            longitude: 0.0,
+            latitude: 0.0,
+        },
+        max_dist: None,
+        max_results: None,
+        cost: 1.0,
+        estimated_rows: 1.0,
+    };
+    assert!(matches!(
+        evaluator.eval(&plan).await,
+        Err(EvalError::StorageError(StorageError::Index(_)))
+    ));
+
+    let plan = PlanNode::Between {
+        table_ref: table_ref.clone(),
+        index: "created".to_string(),
+        lower: None,
+        upper: None,
+        cost: 1.0,
+        estimated_rows: 1.0,
+    };
+    assert!(matches!(
+        evaluator.eval(&plan).await,
+        Err(EvalError::StorageError(StorageError::Index(_)))
+    ));
+
+    let plan = PlanNode::Nearest {
+        table_ref,
+        index: "embedding".to_string(),
+        vector: vec![0.5, 0.5],
+        k: 3,
+        filter: None,
+        probes: None,
+        cost: 1.0,
+        estimated_rows: 1.0,
+    };
+    assert!(matches!(
+        evaluator.eval(&plan).await,
+        Err(EvalError::StorageError(StorageError::Index(_)))
+    ));
+}
+
+#[tokio::test]
+async fn test_sharding_requires_support_from_storage() {
+    let storage = create_scan_test_storage(1).await;
+    let table_ops = TableOperations::new(storage.clone());
+    let mut stats = EvalStats::new();
+
+    // A single shard is an ordinary table
+    table_ops
+        .create_table("test_db", "single", None, Some(1), &mut stats)
+        .await
+        .unwrap();
+
+    let result = table_ops
+        .create_table("test_db", "sharded", None, Some(4), &mut stats)
+        .await;
+    assert!(matches!(
+        result,
+        Err(EvalError::StorageError(StorageError::Sharding(_)))
+    ));
+
+    let mut evaluator = Evaluator::new(storage);
+    let plan = PlanNode::Rebalance {
+        table_ref: TableRef {
+            database: Some(DatabaseRef {
+                name: "test_db".to_string(),
+            }),
+            name: "test_table".to_string(),
+        },
+        shards: Some(2),
+        cost: 1.0,
+    };
+    assert!(matches!(
+        evaluator.eval(&plan).await,
+        Err(EvalError::StorageError(StorageError::Sharding(_)))
+    ));
+}
+
+#[test]
+fn test_geo_operations() {
+    fn geometry(kind: geometry::Kind) -> Box<Expression> {
+        Box::new(Expression {
+            expr: Some(Expr::Literal(Datum {
+                value: Some(datum::Value::Geometry(Geometry { kind: Some(kind) })),
+            })),
+        })
+    }
+    fn point(longitude: f64, latitude: f64) -> Point {
+        Point {
+            longitude,
+            latitude,
+        }
+    }
+    fn geo(op: GeoOperator, left: Box<Expression>, right: Box<Expression>) -> Expression {
+        Expression {
+            expr: Some(Expr::Geo(Box::new(GeoOp {
+                op: op as i32,
+                left: Some(left),
+                right: Some(right),
+            }))),
+        }
+    }
+
+    let evaluator = ExpressionEvaluator::new();
+    let context = null_datum();
+    let square = || {
+        geometry(geometry::Kind::Polygon(Polygon {
+            points: vec![
+                point(0.0, 0.0),
+                point(1.0, 0.0),
+                point(1.0, 1.0),
+                point(0.0, 1.0),
+            ],
+        }))
+    };
+    let inside = || geometry(geometry::Kind::Point(point(0.5, 0.5)));
+    let outside = || geometry(geometry::Kind::Point(point(2.0, 0.5)));
+
+    let includes = geo(GeoOperator::Includes, square(), inside());
+    let result = evaluator.evaluate_expression(&includes, &context).unwrap();
+    assert!(datum_to_bool(&result));
+    let intersects = geo(GeoOperator::Intersects, square(), outside());
+    let result = evaluator
+        .evaluate_expression(&intersects, &context)
+        .unwrap();
+    assert!(!datum_to_bool(&result));
+
+    // One degree of longitude at the equator is about 111 km
+    let distance = geo(GeoOperator::Distance, square(), outside());
+    match evaluator
+        .evaluate_expression(&distance, &context)
+        .unwrap()
+        .value
+    {
+        Some(datum::Value::Float(meters)) => assert!((meters - 111_195.0).abs() < 100.0),
+        other => panic!("Expected a distance, got {other:?}"),
+    }
+
+    // Only polygons include other shapes, and coordinates must be in range
+    let includes = geo(GeoOperator::Includes, inside(), square());
+    assert!(matches!(
+        evaluator.evaluate_expression(&includes, &context),
+        Err(EvalError::InvalidGeometry(_))
+    ));
+    let invalid = geo(
+        GeoOperator::Distance,
+        inside(),
+        geometry(geometry::Kind::Point(point(200.0, 0.0))),
+    );
+    assert!(matches!(
+        evaluator.evaluate_expression(&invalid, &context),
+        Err(EvalError::InvalidGeometry(_))
+    ));
+    let not_geometry = geo(
+        GeoOperator::Intersects,
+        inside(),
+        Box::new(Expression {
+            expr: Some(Expr::Literal(int_datum(1))),
+        }),
+    );
+    assert!(matches!(
+        evaluator.evaluate_expression(&not_geometry, &context),
+        Err(EvalError::InvalidGeometry(_))
+    ));
+}
+
+#[test]
+fn test_time_operations() {
+    fn literal(value: datum::Value) -> Expression {
+        Expression {
+            expr: Some(Expr::Literal(Datum { value: Some(value) })),
+        }
+    }
+    fn time_op(op: TimeOperator, args: Vec<Expression>) -> Expression {
+        Expression {
+            expr: Some(Expr::Time(TimeOp {
+                op: op as i32,
+                args,
+            })),
+        }
+    }
+    fn iso(text: &str) -> Expression {
+        time_op(
+            TimeOperator::Iso8601,
+            vec![literal(datum::Value::String(text.to_string()))],
+        )
+    }
+
+    let evaluator = ExpressionEvaluator::new();
+    let context = null_datum();
+    let evaluate = |expr: &Expression| evaluator.evaluate_expression(expr, &context).unwrap();
+
+    // 2024-02-29 was a Thursday, the 60th day of a leap year
+    let leap_day = || iso("2024-02-29T23:30:15.5+02:00");
+    let accessors = [
+        (TimeOperator::Year, int_datum(2024)),
+        (TimeOperator::Month, int_datum(2)),
+        (TimeOperator::Day, int_datum(29)),
+        (TimeOperator::DayOfWeek, int_datum(4)),
+        (TimeOperator::DayOfYear, int_datum(60)),
+        (TimeOperator::Hours, int_datum(23)),
+        (TimeOperator::Minutes, int_datum(30)),
+        (TimeOperator::Seconds, float_datum(15.5)),
+        (TimeOperator::Timezone, string_datum("+02:00".to_string())),
+        (TimeOperator::TimeOfDay, float_datum(84_615.5)),
+    ];
+    for (op, expected) in accessors {
+        assert_eq!(evaluate(&time_op(op, vec![leap_day()])), expected, "{op:?}");
+    }
+
+    // The same instant is equal whatever its offset, and converts back to text
+    let utc = time_op(
+        TimeOperator::InTimezone,
+        vec![leap_day(), literal(datum::Value::String("Z".to_string()))],
+    );
+    assert!(compare_values(&evaluate(&utc), &evaluate(&leap_day())).is_eq());
+    assert_eq!(
+        evaluate(&time_op(TimeOperator::ToIso8601, vec![utc])),
+        string_datum("2024-02-29T21:30:15.500+00:00".to_string())
+    );
+    let epoch = time_op(TimeOperator::EpochTime, vec![literal(datum::Value::Int(0))]);
+    assert_eq!(
+        evaluate(&time_op(TimeOperator::ToIso8601, vec![epoch])),
+        string_datum("1970-01-01T00:00:00+00:00".to_string())
+    );
+
+    // Adding half an hour crosses into March at the time's own offset
+    let later = time_op(
+        TimeOperator::Add,
+        vec![leap_day(), literal(datum::Value::Int(1_800))],
+    );
+    assert_eq!(
+        evaluate(&time_op(TimeOperator::Month, vec![later.clone()])),
+        int_datum(3)
+    );
+    assert_eq!(
+        evaluate(&time_op(TimeOperator::Sub, vec![later.clone(), leap_day()])),
+        float_datum(1_800.0)
+    );
+    let earlier = time_op(
+        TimeOperator::Sub,
+        vec![later.clone(), literal(datum::Value::Float(1_800.0))],
+    );
+    assert_eq!(evaluate(&earlier), evaluate(&leap_day()));
+    assert_eq!(
+        evaluate(&time_op(
+            TimeOperator::Date,
+            vec![iso("2024-03-01T05:00:00+02:00")]
+        )),
+        evaluate(&iso("2024-03-01T00:00:00+02:00"))
+    );
+
+    // During includes its start and excludes its end
+    let during =
+        |time: Expression| time_op(TimeOperator::During, vec![time, leap_day(), later.clone()]);
+    assert!(evaluator.is_boolean_expression(&during(leap_day())));
+    assert!(datum_to_bool(&evaluate(&during(leap_day()))));
+    assert!(!datum_to_bool(&evaluate(&during(later.clone()))));
+
+    let now = evaluate(&time_op(TimeOperator::Now, vec![]));
+    assert!(compare_values(&now, &evaluate(&leap_day())).is_gt());
+
+    // Bad arguments are reported rather than treated as false
+    for invalid in [
+        iso("yesterday"),
+        time_op(TimeOperator::Year, vec![literal(datum::Value::Int(1))]),
+        time_op(TimeOperator::Add, vec![leap_day()]),
+        time_op(
+            TimeOperator::InTimezone,
+            vec![
+                leap_day(),
+                literal(datum::Value::String("+25:00".to_string())),
+            ],
+        ),
+    ] {
+        assert!(matches!(
+            evaluator.evaluate_expression(&invalid, &context),
+            Err(EvalError::InvalidTime(_))
+        ));
+    }
+}
+
+fn object_datum(fields: Vec<(&str, Datum)>) -> Datum {
+    fields
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect::<Document>()
+        .into()
+}
+
+fn array_datum(items: Vec<Datum>) -> Datum {
+    Datum {
+        value: Some(datum::Value::Array(DatumArray {
+            items,
+            element_type: String::new(),
+        })),
+    }
+}
+
+fn field(path: &[&str]) -> FieldRef {
+    FieldRef {
+        path: path.iter().map(ToString::to_string).collect(),
+        separator: ".".to_string(),
+    }
+}
+
+#[test]
+fn test_document_operations() {
+    fn literal(value: Datum) -> Expression {
+        Expression {
+            expr: Some(Expr::Literal(value)),
+        }
+    }
+    fn document_op(op: DocumentOperator, args: Vec<Expression>) -> Expression {
+        Expression {
+            expr: Some(Expr::Document(DocumentOp {
+                op: op as i32,
+                args,
+                fields: vec![],
+            })),
+        }
+    }
+
+    let evaluator = ExpressionEvaluator::new();
+    let context = object_datum(vec![
+        ("name", string_datum("Ada".to_string())),
+        (
+            "address",
+            object_datum(vec![
+                ("city", string_datum("London".to_string())),
+                ("zip", string_datum("N1".to_string())),
+            ]),
+        ),
+        ("nickname", null_datum()),
+    ]);
+    let evaluate = |expr: &Expression| evaluator.evaluate_expression(expr, &context).unwrap();
+    let document = || Expression {
+        expr: Some(Expr::Field(field(&["address"]))),
+    };
+
+    // Merge is deep, unless a literal replaces the subtree or removes the field
+    let patch = object_datum(vec![
+        ("zip", string_datum("EC1".to_string())),
+        ("geo", object_datum(vec![("lat", float_datum(51.5))])),
+    ]);
+    assert_eq!(
+        evaluate(&document_op(
+            DocumentOperator::Merge,
+            vec![document(), literal(patch)]
+        )),
+        object_datum(vec![
+            ("city", string_datum("London".to_string())),
+            ("zip", string_datum("EC1".to_string())),
+            ("geo", object_datum(vec![("lat", float_datum(51.5))])),
+        ])
+    );
+    let replaced = evaluate(&document_op(
+        DocumentOperator::Merge,
+        vec![
+            literal(context.clone()),
+            literal(object_datum(vec![
+                (
+                    "address",
+                    evaluate(&document_op(
+                        DocumentOperator::Literal,
+                        vec![literal(object_datum(vec![("zip", int_datum(1))]))],
+                    )),
+                ),
+                (
+                    "name",
+                    evaluate(&document_op(DocumentOperator::Literal, vec![])),
+                ),
+            ])),
+        ],
+    ));
+    assert_eq!(
+        replaced,
+        object_datum(vec![
+            ("address", object_datum(vec![("zip", int_datum(1))])),
+            ("nickname", null_datum()),
+        ])
+    );
+
+    // Field tests see nested fields, and null counts as missing
+    let fields_op = |op: DocumentOperator, fields: Vec<FieldRef>| Expression {
+        expr: Some(Expr::Document(DocumentOp {
+            op: op as i32,
+            args: vec![literal(context.clone())],
+            fields,
+        })),
+    };
+    let has_city = fields_op(
+        DocumentOperator::HasFields,
+        vec![field(&["name"]), field(&["address", "city"])],
+    );
+    assert!(evaluator.is_boolean_expression(&has_city));
+    assert_eq!(evaluate(&has_city), bool_datum(true));
+    assert_eq!(
+        evaluate(&fields_op(
+            DocumentOperator::HasFields,
+            vec![field(&["nickname"])]
+        )),
+        bool_datum(false)
+    );
+    assert_eq!(
+        evaluate(&fields_op(
+            DocumentOperator::WithFields,
+            vec![field(&["address", "city"])]
+        )),
+        object_datum(vec![(
+            "address",
+            object_datum(vec![("city", string_datum("London".to_string()))])
+        )])
+    );
+    assert_eq!(
+        evaluate(&fields_op(
+            DocumentOperator::WithFields,
+            vec![field(&["age"])]
+        )),
+        Datum {
+            value: Some(datum::Value::Null(NullValue::NullValue.into())),
+        }
+    );
+
+    // Keys and values come in key order
+    assert_eq!(
+        evaluate(&document_op(DocumentOperator::Keys, vec![document()])),
+        array_datum(vec![
+            string_datum("city".to_string()),
+            string_datum("zip".to_string()),
+        ])
+    );
+    assert_eq!(
+        evaluate(&document_op(DocumentOperator::Values, vec![document()])),
+        array_datum(vec![
+            string_datum("London".to_string()),
+            string_datum("N1".to_string()),
+        ])
+    );
+
+    // Default replaces null and missing values only
+    let fallback = || literal(string_datum("none".to_string()));
+    for (path, expected) in [("nickname", "none"), ("missing", "none"), ("name", "Ada")] {
+        let value = Expression {
+            expr: Some(Expr::Field(field(&[path]))),
+        };
+        assert_eq!(
+            evaluate(&document_op(
+                DocumentOperator::Default,
+                vec![value, fallback()]
+            )),
+            string_datum(expected.to_string()),
+            "{path}"
+        );
+    }
+
+    let type_of =
+        |value: Datum| evaluate(&document_op(DocumentOperator::TypeOf, vec![literal(value)]));
+    assert_eq!(type_of(context.clone()), string_datum("OBJECT".to_string()));
+    assert_eq!(
+        type_of(float_datum(1.5)),
+        string_datum("NUMBER".to_string())
+    );
+    assert_eq!(
+        type_of(Datum { value: None }),
+        string_datum("NULL".to_string())
+    );
+
+    let coerce = |value: Datum, target: &str| {
+        evaluator.evaluate_expression(
+            &document_op(
+                DocumentOperator::CoerceTo,
+                vec![literal(value), literal(string_datum(target.to_string()))],
+            ),
+            &context,
+        )
+    };
+    let uuid_text = "123e4567-e89b-12d3-a456-426614174000";
+    let decimal = |text: &str| Datum {
+        value: Some(datum::Value::Decimal(Decimal {
+            value: text.to_string(),
+        })),
+    };
+    let coercions = [
+        (string_datum("42".to_string()), "number", int_datum(42)),
+        (string_datum("2.5".to_string()), "NUMBER", float_datum(2.5)),
+        (int_datum(42), "string", string_datum("42".to_string())),
+        (string_datum("0.10".to_string()), "decimal", decimal("0.10")),
+        (decimal("3.00"), "number", int_datum(3)),
+        (
+            string_datum(uuid_text.to_uppercase()),
+            "uuid",
+            Datum {
+                value: Some(datum::Value::Uuid(crate::uuid::parse(uuid_text).unwrap())),
+            },
+        ),
+        (
+            object_datum(vec![("b", int_datum(2)), ("a", int_datum(1))]),
+            "array",
+            array_datum(vec![
+                array_datum(vec![string_datum("a".to_string()), int_datum(1)]),
+                array_datum(vec![string_datum("b".to_string()), int_datum(2)]),
+            ]),
+        ),
+        (
+            array_datum(vec![array_datum(vec![
+                string_datum("a".to_string()),
+                int_datum(1),
+            ])]),
+            "object",
+            object_datum(vec![("a", int_datum(1))]),
+        ),
+        (null_datum(), "null", null_datum()),
+    ];
+    for (value, target, expected) in coercions {
+        assert_eq!(
+            coerce(value.clone(), target).unwrap(),
+            expected,
+            "{value} to {target}"
+        );
+    }
+    let time = coerce(string_datum("2024-02-29T12:00:00Z".to_string()), "time").unwrap();
+    assert_eq!(
+        coerce(time, "string").unwrap(),
+        string_datum("2024-02-29T12:00:00+00:00".to_string())
+    );
+
+    assert!(matches!(
+        coerce(string_datum("forty".to_string()), "number"),
+        Err(EvalError::InvalidCoercion(_))
+    ));
+    assert!(matches!(
+        coerce(string_datum("not-a-uuid".to_string()), "uuid"),
+        Err(EvalError::InvalidUuid(_))
+    ));
+    assert!(matches!(
+        coerce(context.clone(), "string"),
+        Err(EvalError::InvalidCoercion(_))
+    ));
+    assert!(matches!(
+        evaluator.evaluate_expression(&document_op(DocumentOperator::Keys, vec![]), &context),
+        Err(EvalError::InvalidArgument(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_document_functions_over_documents() {
+    let processor = QueryProcessor::new(Arc::new(MemoryStorage::new()));
+    let docs = || {
+        create_test_result(vec![
+            create_test_datum("1", "Ada", 36),
+            object_datum(vec![("id", string_datum("2".to_string()))]),
+        ])
+    };
+    let literal = |value: Datum| Expression {
+        expr: Some(Expr::Literal(value)),
+    };
+    async fn apply(
+        processor: &QueryProcessor,
+        source: query_result::Result,
+        op: DocumentOperator,
+        args: Vec<Expression>,
+        fields: Vec<FieldRef>,
+    ) -> document_function_result::Result {
+        let mut stats = EvalStats::new();
+        match processor
+            .apply_document_function(source, None, op, &args, &fields, &mut stats)
+            .await
+            .unwrap()
+        {
+            query_result::Result::DocumentFunction(result) => result.result.unwrap(),
+            other => panic!("Expected a document function result, got {other:?}"),
+        }
+    }
+    let collection = |result| match result {
+        document_function_result::Result::Collection(collection) => collection.documents,
+        other => panic!("Expected a collection, got {other:?}"),
+    };
+
+    // Over many documents, field tests keep the documents that have the fields
+    let kept = apply(
+        &processor,
+        docs(),
+        DocumentOperator::HasFields,
+        vec![],
+        vec![field(&["name"])],
+    )
+    .await;
+    assert_eq!(collection(kept), vec![create_test_datum("1", "Ada", 36)]);
+    let plucked = apply(
+        &processor,
+        docs(),
+        DocumentOperator::WithFields,
+        vec![],
+        vec![field(&["age"])],
+    )
+    .await;
+    assert_eq!(
+        collection(plucked),
+        vec![object_datum(vec![("age", int_datum(36))])]
+    );
+
+    // Merge evaluates its values against each document
+    let age_or_zero = Expression {
+        expr: Some(Expr::Document(DocumentOp {
+            op: DocumentOperator::Default as i32,
+            args: vec![
+                Expression {
+                    expr: Some(Expr::Field(field(&["age"]))),
+                },
+                literal(int_datum(0)),
+            ],
+            fields: vec![],
+        })),
+    };
+    let patch = Expression {
+        expr: Some(Expr::Document(DocumentOp {
+            op: DocumentOperator::Merge as i32,
+            args: vec![
+                literal(object_datum(vec![])),
+                Expression {
+                    expr: Some(Expr::Literal(object_datum(vec![("age", null_datum())]))),
+                },
+            ],
+            fields: vec![],
+        })),
+    };
+    let merged = apply(
+        &processor,
+        docs(),
+        DocumentOperator::Merge,
+        vec![patch, literal(object_datum(vec![]))],
+        vec![],
+    )
+    .await;
+    let merged = collection(merged);
+    assert_eq!(merged.len(), 2);
+    assert!(
+        merged
+            .iter()
+            .all(|doc| extract_field_value(doc, "age") == null_datum())
+    );
+    let evaluator = ExpressionEvaluator::new();
+    assert_eq!(
+        evaluator
+            .evaluate_expression(&age_or_zero, &merged[1])
+            .unwrap(),
+        int_datum(0)
+    );
+
+    // A missing document is a missing value, and Default replaces it
+    let defaulted = apply(
+        &processor,
+        query_result::Result::Get(GetResult { document: None }),
+        DocumentOperator::Default,
+        vec![literal(string_datum("missing".to_string()))],
+        vec![],
+    )
+    .await;
+    assert_eq!(
+        defaulted,
+        document_function_result::Result::Value(string_datum("missing".to_string()))
+    );
+
+    // Type and coercion take the documents together
+    assert_eq!(
+        apply(&processor, docs(), DocumentOperator::TypeOf, vec![], vec![]).await,
+        document_function_result::Result::Value(string_datum("SEQUENCE".to_string()))
+    );
+    let coerced = apply(
+        &processor,
+        docs(),
+        DocumentOperator::CoerceTo,
+        vec![literal(string_datum("array".to_string()))],
+        vec![],
+    )
+    .await;
+    let document_function_result::Result::Value(array) = coerced else {
+        panic!("Expected a value");
+    };
+    assert!(matches!(
+        array.value,
+        Some(datum::Value::Array(DatumArray { ref items, .. })) if items.len() == 2
+    ));
+}
+
+#[test]
+fn test_update_array_operations() {
+    let ints = |values: &[i64]| array_datum(values.iter().copied().map(int_datum).collect());
+    let op = |op: ArrayOperator, value: Option<Datum>, index: i64| ArrayOp {
+        op: op.into(),
+        field: Some(field(&["stats", "scores"])),
+        value,
+        index,
+    };
+    let doc: Document = [
+        ("id".to_string(), string_datum("1".to_string())),
+        (
+            "stats".to_string(),
+            object_datum(vec![("scores", ints(&[1, 2, 2, 3]))]),
+        ),
+    ]
+    .into();
+    let scores = |operations: &[ArrayOp]| {
+        let updated = update::apply(doc.clone(), &DatumObject::default(), operations)?;
+        Ok::<_, EvalError>(crate::evaluator::utils::extract_field_from_ref(
+            &updated.into(),
+            &field(&["stats", "scores"]),
+        ))
+    };
+
+    let cases = [
+        (
+            op(ArrayOperator::Append, Some(int_datum(4)), 0),
+            ints(&[1, 2, 2, 3, 4]),
+        ),
+        (
+            op(ArrayOperator::Prepend, Some(int_datum(0)), 0),
+            ints(&[0, 1, 2, 2, 3]),
+        ),
+        (
+            op(ArrayOperator::InsertAt, Some(int_datum(9)), 1),
+            ints(&[1, 9, 2, 2, 3]),
+        ),
+        (
+            op(ArrayOperator::InsertAt, Some(int_datum(9)), 4),
+            ints(&[1, 2, 2, 3, 9]),
+        ),
+        (
+            op(ArrayOperator::InsertAt, Some(int_datum(9)), -1),
+            ints(&[1, 2, 2, 3, 9]),
+        ),
+        (op(ArrayOperator::DeleteAt, None, -1), ints(&[1, 2, 2])),
+        (
+            op(ArrayOperator::ChangeAt, Some(int_datum(7)), 0),
+            ints(&[7, 2, 2, 3]),
+        ),
+        (
+            op(ArrayOperator::SetInsert, Some(int_datum(3)), 0),
+            ints(&[1, 2, 3]),
+        ),
+        (
+            op(ArrayOperator::SetUnion, Some(ints(&[3, 5, 5])), 0),
+            ints(&[1, 2, 3, 5]),
+        ),
+        (
+            op(ArrayOperator::SetDifference, Some(ints(&[1])), 0),
+            ints(&[2, 3]),
+        ),
+        (
+            op(ArrayOperator::Difference, Some(ints(&[2])), 0),
+            ints(&[1, 3]),
+        ),
+    ];
+    for (operation, expected) in cases {
+        assert_eq!(
+            scores(std::slice::from_ref(&operation)).unwrap(),
+            expected,
+            "{operation:?}"
+        );
+    }
+
+    // Operations apply in order, and a missing field is an empty array
+    let mut added = op(ArrayOperator::Append, Some(int_datum(1)), 0);
+    added.field = Some(field(&["stats", "new"]));
+    let updated = update::apply(
+        doc.clone(),
+        &DatumObject::default(),
+        &[added.clone(), added],
+    )
+    .unwrap();
+    assert_eq!(
+        crate::evaluator::utils::extract_field_from_ref(&updated.into(), &field(&["stats", "new"])),
+        ints(&[1, 1])
+    );
+
+    // Indexes out of range, non-array fields and non-array operands are refused
+    for invalid in [
+        op(ArrayOperator::DeleteAt, None, 4),
+        op(ArrayOperator::ChangeAt, Some(int_datum(0)), -5),
+        op(ArrayOperator::InsertAt, Some(int_datum(0)), 5),
+        op(ArrayOperator::SetUnion, Some(int_datum(1)), 0),
+        op(ArrayOperator::Append, None, 0),
+        ArrayOp {
+            field: Some(field(&["id"])),
+            ..op(ArrayOperator::Append, Some(int_datum(1)), 0)
+        },
+    ] {
+        assert!(
+            matches!(
+                scores(std::slice::from_ref(&invalid)),
+                Err(EvalError::InvalidUpdate(_))
+            ),
+            "{invalid:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_update_documents_patches_stored_versions() {
+    let storage = Arc::new(MemoryStorage::new());
+    storage.create_database("test_db").await.unwrap();
+    storage
+        .create_table("test_db", "test_table", &TableConfig::default())
+        .await
+        .unwrap();
+    let stored = object_datum(vec![
+        ("id", string_datum("1".to_string())),
+        ("tags", array_datum(vec![string_datum("a".to_string())])),
+    ]);
+    let Some(datum::Value::Object(obj)) = &stored.value else {
+        unreachable!()
+    };
+    storage
+        .put("test_db", "test_table", "1", &Document::from(obj), None)
+        .await
+        .unwrap();
+    let processor = Arc::new(QueryProcessor::new(storage.clone()));
+    let append = |tag: String| ArrayOp {
+        op: ArrayOperator::Append.into(),
+        field: Some(field(&["tags"])),
+        value: Some(string_datum(tag)),
+        index: 0,
+    };
+
+    // Every update starts from the same stale read, yet none of them is lost
+    let stale = create_test_result(vec![object_datum(vec![(
+        "id",
+        string_datum("1".to_string()),
+    )])]);
+    let mut updates = Vec::new();
+    for i in 0..10 {
+        let processor = processor.clone();
+        let stale = stale.clone();
+        updates.push(tokio::spawn(async move {
+            let mut stats = EvalStats::new();
+            processor
+                .update_documents(
+                    stale,
+                    &DatumObject::default(),
+                    &[append(format!("t{i}"))],
+                    &create_scan_plan(),
+                    None,
+                    &mut stats,
+                )
+                .await
+                .unwrap()
+        }));
+    }
+    for update in updates {
+        update.await.unwrap();
+    }
+    let doc = storage
+        .get("test_db", "test_table", "1", None)
+        .await
+        .unwrap()
+        .unwrap();
+    let Some(datum::Value::Array(tags)) = &doc["tags"].value else {
+        panic!("Expected tags to stay an array");
+    };
+    assert_eq!(tags.items.len(), 11);
+
+    // A failed operation changes nothing, and the primary key cannot change
+    let mut stats = EvalStats::new();
+    let result = processor
+        .update_documents(
+            stale.clone(),
+            &DatumObject::default(),
+            &[ArrayOp {
+                op: ArrayOperator::DeleteAt.into(),
+                index: 20,
+                ..append(String::new())
+            }],
+            &create_scan_plan(),
+            None,
+            &mut stats,
+        )
+        .await;
+    assert!(matches!(result, Err(EvalError::InvalidUpdate(_))));
+    let patch = DatumObject {
+        fields: [("id".to_string(), string_datum("2".to_string()))].into(),
+    };
+    let result = processor
+        .update_documents(stale, &patch, &[], &create_scan_plan(), None, &mut stats)
+        .await;
+    assert!(matches!(result, Err(EvalError::InvalidUpdate(_))));
+    assert_eq!(
+        storage
+            .get("test_db", "test_table", "1", None)
+            .await
+            .unwrap()
+            .unwrap(),
+        doc
+    );
+}
+
+#[test]
+fn test_branch_error_and_default() {
+    let evaluator = ExpressionEvaluator::new();
+    let context = create_test_context();
+    let literal = |value: Datum| Expression {
+        expr: Some(Expr::Literal(value)),
+    };
+    let field_expr = |name: &str| Expression {
+        expr: Some(Expr::Field(field(&[name]))),
+    };
+    let binary = |left: Expression, op: BinaryOperator, right: Expression| Expression {
+        expr: Some(Expr::Binary(Box::new(BinaryOp {
+            op: op.into(),
+            left: Some(Box::new(left)),
+            right: Some(Box::new(right)),
+        }))),
+    };
+    let error = |message: &str| Expression {
+        expr: Some(Expr::Error(Box::new(ErrorExpr {
+            message: Some(Box::new(literal(string_datum(message.to_string())))),
+        }))),
+    };
+    let at_least = |age: i64| {
+        binary(
+            field_expr("age"),
+            BinaryOperator::Ge,
+            literal(int_datum(age)),
+        )
+    };
+    let branch = |cases: Vec<(Expression, Expression)>, otherwise: Expression| Expression {
+        expr: Some(Expr::Branch(Box::new(BranchExpr {
+            cases: cases
+                .into_iter()
+                .map(|(condition, value)| branch_expr::Case {
+                    condition: Some(condition),
+                    value: Some(value),
+                })
+                .collect(),
+            otherwise: Some(Box::new(otherwise)),
+        }))),
+    };
+    let default = |value: Expression, fallback: Expression| Expression {
+        expr: Some(Expr::Document(DocumentOp {
+            op: DocumentOperator::Default.into(),
+            args: vec![value, fallback],
+            fields: vec![],
+        })),
+    };
+    let text = |value: &str| literal(string_datum(value.to_string()));
+
+    // The first case that holds is chosen, and only its value is evaluated
+    let tier = branch(
+        vec![
+            (at_least(65), text("senior")),
+            (at_least(18), text("adult")),
+            (at_least(65), error("unreachable")),
+        ],
+        error("too young"),
+    );
+    assert_eq!(
+        evaluator.evaluate_expression(&tier, &context).unwrap(),
+        string_datum("adult".to_string())
+    );
+    let child = object_datum(vec![("age", int_datum(7))]);
+    let err = evaluator.evaluate_expression(&tier, &child).unwrap_err();
+    assert!(matches!(&err, EvalError::UserError(message) if message == "too young"));
+    assert_eq!(err.to_string(), "too young");
+    assert!(evaluator.is_boolean_expression(&branch(
+        vec![(at_least(18), literal(bool_datum(true)))],
+        error("no")
+    )));
+    assert!(!evaluator.is_boolean_expression(&tier));
+
+    // Arithmetic on a missing field or null fails, and a default catches it
+    let next_year = binary(
+        field_expr("height"),
+        BinaryOperator::Add,
+        literal(int_datum(1)),
+    );
+    assert!(matches!(
+        evaluator.evaluate_expression(&next_year, &context),
+        Err(EvalError::MissingField(field)) if field == "height"
+    ));
+    let null_sum = binary(
+        literal(null_datum()),
+        BinaryOperator::Mul,
+        literal(int_datum(1)),
+    );
+    assert!(matches!(
+        evaluator.evaluate_expression(&null_sum, &context),
+        Err(EvalError::NullValue(_))
+    ));
+    for value in [next_year, null_sum, field_expr("height")] {
+        assert_eq!(
+            evaluator
+                .evaluate_expression(&default(value, literal(int_datum(0))), &context)
+                .unwrap(),
+            int_datum(0)
+        );
+    }
+
+    // A present value is kept without evaluating the fallback, and other errors pass
+    assert_eq!(
+        evaluator
+            .evaluate_expression(&default(field_expr("age"), error("unused")), &context)
+            .unwrap(),
+        int_datum(30)
+    );
+    let division = binary(
+        literal(int_datum(1)),
+        BinaryOperator::Div,
+        literal(int_datum(0)),
+    );
+    assert!(matches!(
+        evaluator.evaluate_expression(&default(division, literal(int_datum(0))), &context),
+        Err(EvalError::DivisionByZero)
+    ));
+    assert!(matches!(
+        evaluator.evaluate_expression(&default(error("boom"), literal(int_datum(0))), &context),
+        Err(EvalError::UserError(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_scan_filter_errors_fail_the_query() {
+    let storage = create_scan_test_storage(3).await;
+    let mut evaluator = Evaluator::new(storage);
+    let missing_plus_one = Expression {
+        expr: Some(Expr::Binary(Box::new(BinaryOp {
+            op: BinaryOperator::Gt.into(),
+            left: Some(Box::new(Expression {
+                expr: Some(Expr::Binary(Box::new(BinaryOp {
+                    op: BinaryOperator::Add.into(),
+                    left: Some(Box::new(Expression {
+                        expr: Some(Expr::Field(field(&["score"]))),
+                    })),
+                    right: Some(Box::new(Expression {
+                        expr: Some(Expr::Literal(int_datum(1))),
+                    })),
+                }))),
+            })),
+            right: Some(Box::new(Expression {
+                expr: Some(Expr::Literal(int_datum(0))),
+            })),
+        }))),
+    };
+    let PlanNode::TableScan {
+        table_ref,
+        cost,
+        estimated_rows,
+        ..
+    } = create_scan_plan()
+    else {
+        unreachable!()
+    };
+    let plan = PlanNode::TableScan {
+        table_ref,
+        cursor: None,
+        filter: Some(missing_plus_one),
+        projection: None,
+        cost,
+        estimated_rows,
+    };
+
+    // The evaluator's own error comes back rather than an empty result
+    let result = evaluator.eval_with_cursor(&plan, None).await;
+    assert!(
+        matches!(&result, Err(EvalError::MissingField(field)) if field == "score"),
+        "{:?}",
+        result.map(|result| result.result)
+    );
+}
+
+#[tokio::test]
+async fn test_sequence_operations() {
+    let storage = create_scan_test_storage(5).await;
+    let mut evaluator = Evaluator::new(storage);
+    let scan = || Box::new(create_scan_plan());
+    // The memory storage scans in no particular order
+    let ordered = || {
+        Box::new(PlanNode::OrderBy {
+            source: scan(),
+            fields: vec![OrderByField {
+                field_name: "id".to_string(),
+                ascending: true,
+            }],
+            cost: 1.0,
+        })
+    };
+    let id_is = |id: &str| Expression {
+        expr: Some(Expr::Binary(Box::new(BinaryOp {
+            op: BinaryOperator::Eq.into(),
+            left: Some(Box::new(Expression {
+                expr: Some(Expr::Field(field(&["id"]))),
+            })),
+            right: Some(Box::new(Expression {
+                expr: Some(Expr::Literal(string_datum(id.to_string()))),
+            })),
+        }))),
+    };
+    let slice = |start, end, left_open, right_closed| PlanNode::Slice {
+        source: ordered(),
+        start,
+        end,
+        left_open,
+        right_closed,
+        cost: 1.0,
+    };
+    let nth = |index| PlanNode::Nth {
+        source: ordered(),
+        index,
+        cost: 1.0,
+    };
+    let ids = |result: query_result::Result| -> Vec<String> {
+        let query_result::Result::Sequence(SequenceResult {
+            result: Some(sequence_result::Result::Collection(collection)),
+        }) = result
+        else {
+            panic!("Expected a sequence of documents, got {result:?}");
+        };
+        assert!(collection.cursor.is_none());
+        collection
+            .documents
+            .iter()
+            .map(|doc| datum_to_string(&extract_field_value(doc, "id")).unwrap())
+            .collect()
+    };
+    let value = |result: query_result::Result| -> Datum {
+        let query_result::Result::Sequence(SequenceResult {
+            result: Some(sequence_result::Result::Value(value)),
+        }) = result
+        else {
+            panic!("Expected a value, got {result:?}");
+        };
+        value
+    };
+
+    // Negative bounds count from the end; the start is included and the end is not,
+    // unless asked otherwise
+    let result = evaluator.eval(&slice(1, Some(-1), false, false)).await;
+    assert_eq!(ids(result.unwrap().result), ["doc1", "doc2", "doc3"]);
+    let result = evaluator.eval(&slice(1, Some(3), true, true)).await;
+    assert_eq!(ids(result.unwrap().result), ["doc2", "doc3"]);
+    let result = evaluator.eval(&slice(-2, None, false, false)).await;
+    assert_eq!(ids(result.unwrap().result), ["doc3", "doc4"]);
+
+    // A known end stops the scan there
+    let prefix = PlanNode::Slice {
+        source: scan(),
+        start: 0,
+        end: Some(2),
+        left_open: false,
+        right_closed: false,
+        cost: 1.0,
+    };
+    let result = evaluator.eval(&prefix).await.unwrap();
+    assert_eq!(result.stats.rows_processed, 2);
+    assert_eq!(ids(result.result).len(), 2);
+
+    let result = evaluator.eval(&nth(1)).await.unwrap();
+    assert_eq!(
+        extract_field_value(&value(result.result), "id"),
+        string_datum("doc1".to_string())
+    );
+    let result = evaluator.eval(&nth(-1)).await.unwrap();
+    assert_eq!(
+        extract_field_value(&value(result.result), "id"),
+        string_datum("doc4".to_string())
+    );
+    assert!(matches!(
+        evaluator.eval(&nth(5)).await,
+        Err(EvalError::IndexOutOfBounds(5))
+    ));
+
+    // The whole sequence is used, whatever the batch of the query's cursor
+    let result = evaluator
+        .eval_with_cursor(&nth(-1), Some(Cursor::new(None, Some(2))))
+        .await
+        .unwrap();
+    assert_eq!(
+        extract_field_value(&value(result.result), "id"),
+        string_datum("doc4".to_string())
+    );
+
+    // IsEmpty reads at most one document, honouring the scan's filter
+    let result = evaluator
+        .eval(&PlanNode::IsEmpty {
+            source: scan(),
+            cost: 1.0,
+        })
+        .await
+        .unwrap();
+    assert_eq!(value(result.result), bool_datum(false));
+    assert_eq!(result.stats.rows_processed, 1);
+    let PlanNode::TableScan {
+        table_ref,
+        cost,
+        estimated_rows,
+        ..
+    } = create_scan_plan()
+    else {
+        unreachable!()
+    };
+    let filtered = |id: &str| PlanNode::TableScan {
+        table_ref: table_ref.clone(),
+        cursor: None,
+        filter: Some(id_is(id)),
+        projection: None,
+        cost,
+        estimated_rows,
+    };
+    let result = evaluator
+        .eval(&PlanNode::IsEmpty {
+            source: Box::new(filtered("none")),
+            cost: 1.0,
+        })
+        .await
+        .unwrap();
+    assert_eq!(value(result.result), bool_datum(true));
+
+    let result = evaluator
+        .eval(&PlanNode::Sample {
+            source: scan(),
+            count: 3,
+            cost: 1.0,
+        })
+        .await
+        .unwrap();
+    let mut sampled = ids(result.result);
+    sampled.sort();
+    sampled.dedup();
+    assert_eq!(sampled.len(), 3);
+
+    // Union interleaves the sequences as they come, or merges sequences in the same
+    // order
+    let union = |interleave| PlanNode::Union {
+        source: ordered(),
+        other: Box::new(filtered("doc2")),
+        interleave,
+        cost: 1.0,
+    };
+    let result = evaluator.eval(&union(vec![])).await.unwrap();
+    let mut interleaved = ids(result.result);
+    interleaved.sort();
+    assert_eq!(
+        interleaved,
+        ["doc0", "doc1", "doc2", "doc2", "doc3", "doc4"]
+    );
+    let by_id = vec![OrderByField {
+        field_name: "id".to_string(),
+        ascending: true,
+    }];
+    let result = evaluator.eval(&union(by_id)).await.unwrap();
+    assert_eq!(
+        ids(result.result),
+        ["doc0", "doc1", "doc2", "doc2", "doc3", "doc4"]
+    );
+
+    // Offsets of a predicate over documents, or of a value in an array
+    let result = evaluator
+        .eval(&PlanNode::OffsetsOf {
+            source: ordered(),
+            target: offsets_of::Target::Predicate(Box::new(id_is("doc3"))),
+            cost: 1.0,
+        })
+        .await
+        .unwrap();
+    assert_eq!(value(result.result), array_datum(vec![int_datum(3)]));
+    let result = evaluator
+        .eval(&PlanNode::OffsetsOf {
+            source: Box::new(PlanNode::Constant {
+                value: array_datum(vec![int_datum(1), int_datum(2), int_datum(1)]),
+                cost: 0.0,
+            }),
+            target: offsets_of::Target::Value(int_datum(1)),
+            cost: 1.0,
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        value(result.result),
+        array_datum(vec![int_datum(0), int_datum(2)])
+    );
+}
+
+#[tokio::test]
+async fn test_filter_with_subqueries() {
+    use crate::ast::{Count, Filter, Table, Variable, query};
+    use crate::planner::Planner;
+
+    let storage = Arc::new(MemoryStorage::new());
+    storage.create_database("test_db").await.unwrap();
+    for table in ["users", "orders"] {
+        storage
+            .create_table("test_db", table, &TableConfig::default())
+            .await
+            .unwrap();
+    }
+    for user in ["u1", "u2", "u3"] {
+        let doc = Document::from([("id".to_string(), string_datum(user.to_string()))]);
+        storage
+            .put("test_db", "users", user, &doc, None)
+            .await
+            .unwrap();
+    }
+    for (order, user) in [("o1", "u1"), ("o2", "u3"), ("o3", "u1")] {
+        let doc = Document::from([
+            ("id".to_string(), string_datum(order.to_string())),
+            ("user_id".to_string(), string_datum(user.to_string())),
+        ]);
+        storage
+            .put("test_db", "orders", order, &doc, None)
+            .await
+            .unwrap();
+    }
+
+    let query_of = |kind| Query {
+        options: None,
+        cursor: None,
+        kind: Some(kind),
+    };
+    let table = |name: &str| {
+        query_of(query::Kind::Table(Table {
+            table: Some(TableRef {
+                database: Some(DatabaseRef {
+                    name: "test_db".to_string(),
+                }),
+                name: name.to_string(),
+            }),
+        }))
+    };
+    let filter = |source, predicate| {
+        query_of(query::Kind::Filter(Box::new(Filter {
+            source: Some(Box::new(source)),
+            predicate: Some(Box::new(predicate)),
+        })))
+    };
+    let count = |source| {
+        query_of(query::Kind::Count(Box::new(Count {
+            source: Some(Box::new(source)),
+        })))
+    };
+    let binary = |op: BinaryOperator, left, right| Expression {
+        expr: Some(Expr::Binary(Box::new(BinaryOp {
+            op: op.into(),
+            left: Some(Box::new(left)),
+            right: Some(Box::new(right)),
+        }))),
+    };
+    let subquery = |query| Expression {
+        expr: Some(Expr::Subquery(Box::new(query))),
+    };
+    let literal = |value| Expression {
+        expr: Some(Expr::Literal(value)),
+    };
+    let ids = |result: query_result::Result| -> Vec<String> {
+        let query_result::Result::Filter(result) = result else {
+            panic!("Expected a filter result, got {result:?}");
+        };
+        let mut ids: Vec<String> = result
+            .documents
+            .iter()
+            .map(|doc| datum_to_string(&extract_field_value(doc, "id")).unwrap())
+            .collect();
+        ids.sort();
+        ids
+    };
+    let run = |query: Query| {
+        let storage = storage.clone();
+        async move {
+            let plan = Planner::with_statistics(storage.clone())
+                .plan(&query)
+                .unwrap();
+            Evaluator::new(storage).eval(&plan).await
+        }
+    };
+
+    // Users with orders: the variable is the user's id, bound for each user
+    let orders_of_user = count(filter(
+        table("orders"),
+        binary(
+            BinaryOperator::Eq,
+            Expression {
+                expr: Some(Expr::Field(field(&["user_id"]))),
+            },
+            Expression {
+                expr: Some(Expr::Variable(Variable {
+                    name: "id".to_string(),
+                })),
+            },
+        ),
+    ));
+    let with_orders = filter(
+        table("users"),
+        binary(
+            BinaryOperator::Gt,
+            subquery(orders_of_user.clone()),
+            literal(int_datum(0)),
+        ),
+    );
+    let result = run(with_orders).await.unwrap();
+    assert_eq!(ids(result.result), ["u1", "u3"]);
+
+    // An uncorrelated subquery gives the same result for every user
+    let all_orders = filter(
+        table("users"),
+        binary(
+            BinaryOperator::Eq,
+            subquery(count(table("orders"))),
+            literal(int_datum(3)),
+        ),
+    );
+    let result = run(all_orders).await.unwrap();
+    assert_eq!(ids(result.result), ["u1", "u2", "u3"]);
+
+    // Subqueries run only where the predicate reaches them: reading a missing table
+    // fails, but not past an operand deciding a logical operation nor in a branch not
+    // taken
+    let failing = binary(
+        BinaryOperator::Gt,
+        subquery(count(table("missing"))),
+        literal(int_datum(0)),
+    );
+    let short_circuited = filter(
+        table("users"),
+        binary(
+            BinaryOperator::And,
+            literal(bool_datum(false)),
+            failing.clone(),
+        ),
+    );
+    let result = run(short_circuited).await.unwrap();
+    assert!(ids(result.result).is_empty());
+    let branch = |otherwise: Expression| Expression {
+        expr: Some(Expr::Branch(Box::new(BranchExpr {
+            cases: vec![branch_expr::Case {
+                condition: Some(binary(
+                    BinaryOperator::Gt,
+                    subquery(orders_of_user.clone()),
+                    literal(int_datum(0)),
+                )),
+                value: Some(literal(bool_datum(true))),
+            }],
+            otherwise: Some(Box::new(otherwise)),
+        }))),
+    };
+    let result = run(filter(table("users"), branch(literal(bool_datum(false)))))
+        .await
+        .unwrap();
+    assert_eq!(ids(result.result), ["u1", "u3"]);
+    let result = run(filter(
+        table("users"),
+        binary(
+            BinaryOperator::Or,
+            branch(literal(bool_datum(false))),
+            failing.clone(),
+        ),
+    ))
+    .await;
+    // u2 has no orders, so the failing operand is reached for it
+    assert!(result.is_err());
+    assert!(run(filter(table("users"), branch(failing))).await.is_err());
+
+    // A subquery whose result is not a boolean is not a predicate
+    let not_boolean = filter(table("users"), subquery(orders_of_user));
+    assert!(matches!(
+        run(not_boolean).await,
+        Err(EvalError::InvalidPredicate)
+    ));
+}
+
+#[test]
+fn test_match_expression() {
+    let evaluator = ExpressionEvaluator::new();
+    let context = create_test_context();
+    let match_name = |pattern: &str, flags: &str, capture| Expression {
+        expr: Some(Expr::Match(Box::new(MatchExpr {
+            value: Some(Box::new(Expression {
+                expr: Some(Expr::Field(field(&["name"]))),
+            })),
+            pattern: pattern.to_string(),
+            flags: flags.to_string(),
+            capture,
+        }))),
+    };
+
+    // Flags are options rather than part of the pattern
+    let result = evaluator
+        .evaluate_expression(&match_name("^al", "i", false), &context)
+        .unwrap();
+    assert_eq!(result, bool_datum(true));
+    let result = evaluator
+        .evaluate_expression(&match_name("^al", "", false), &context)
+        .unwrap();
+    assert_eq!(result, bool_datum(false));
+
+    // With capture the match is a value, or null without one
+    let expr = match_name("l(?<rest>i(c))", "", true);
+    assert!(!evaluator.is_boolean_expression(&expr));
+    let result = evaluator.evaluate_expression(&expr, &context).unwrap();
+    assert_eq!(
+        extract_field_value(&result, "str"),
+        string_datum("lic".to_string())
+    );
+    assert_eq!(extract_field_value(&result, "start"), int_datum(1));
+    assert_eq!(extract_field_value(&result, "end"), int_datum(4));
+    let Some(datum::Value::Array(groups)) = extract_field_value(&result, "groups").value else {
+        panic!("Expected the groups, got {result:?}");
+    };
+    assert_eq!(
+        extract_field_value(&groups.items[1], "str"),
+        string_datum("c".to_string())
+    );
+    let named = extract_field_value(&result, "named");
+    assert_eq!(extract_field_value(&named, "rest"), groups.items[0]);
+    let result = evaluator
+        .evaluate_expression(&match_name("^z", "", true), &context)
+        .unwrap();
+    assert!(matches!(result.value, Some(datum::Value::Null(_))));
+
+    // A bad pattern or flag is an error rather than no match
+    for (pattern, flags) in [("(unclosed", ""), ("a", "g")] {
+        assert!(matches!(
+            evaluator.evaluate_expression(&match_name(pattern, flags, false), &context),
+            Err(EvalError::InvalidMatchPattern(_))
+        ));
+    }
+}
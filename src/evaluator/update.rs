@@ -0,0 +1,136 @@
+//! Updates: a patch of top-level fields followed by edits of array fields, applied to
+//! the stored version of a document.
+
+use crate::ast::{
+    ArrayOp, Datum, DatumArray, DatumObject, Document, array_op::Operator as ArrayOperator, datum,
+};
+use crate::evaluator::error::EvalError;
+use crate::evaluator::utils::{datums_equal, extract_field_from_ref, insert_field_by_ref};
+
+/// Apply a patch and then each array operation in order to a document.
+///
+/// A patch field without a value removes the field.
+pub fn apply(
+    mut doc: Document,
+    patch: &DatumObject,
+    operations: &[ArrayOp],
+) -> Result<Document, EvalError> {
+    for (key, value) in &patch.fields {
+        if value.value.is_none() {
+            doc.remove(key);
+        } else {
+            doc.insert(key.clone(), value.clone());
+        }
+    }
+
+    for operation in operations {
+        let field = operation
+            .field
+            .as_ref()
+            .filter(|field| !field.path.is_empty())
+            .ok_or_else(|| EvalError::InvalidUpdate("array operation without a field".into()))?;
+        let current = extract_field_from_ref(&doc.clone().into(), field);
+        let items = match current.value {
+            None | Some(datum::Value::Null(_)) => Vec::new(),
+            Some(datum::Value::Array(array)) => array.items,
+            _ => {
+                return Err(EvalError::InvalidUpdate(format!(
+                    "{} is not an array",
+                    field.path.join(".")
+                )));
+            }
+        };
+        let items = apply_array_op(operation, items)?;
+        insert_field_by_ref(
+            &mut doc,
+            field,
+            Datum {
+                value: Some(datum::Value::Array(DatumArray {
+                    items,
+                    element_type: String::new(),
+                })),
+            },
+        );
+    }
+    Ok(doc)
+}
+
+/// Apply one array operation to the items of an array.
+fn apply_array_op(operation: &ArrayOp, mut items: Vec<Datum>) -> Result<Vec<Datum>, EvalError> {
+    let op = operation.op();
+    let value = || {
+        operation
+            .value
+            .clone()
+            .ok_or_else(|| EvalError::InvalidUpdate(format!("{op:?} needs a value")))
+    };
+    let value_items = || match value()?.value {
+        Some(datum::Value::Array(array)) => Ok(array.items),
+        _ => Err(EvalError::InvalidUpdate(format!(
+            "{op:?} needs an array value"
+        ))),
+    };
+
+    match op {
+        ArrayOperator::Append => items.push(value()?),
+        ArrayOperator::Prepend => items.insert(0, value()?),
+        ArrayOperator::InsertAt => {
+            let at = position(operation.index, items.len() + 1)?;
+            items.insert(at, value()?);
+        }
+        ArrayOperator::DeleteAt => {
+            let at = position(operation.index, items.len())?;
+            items.remove(at);
+        }
+        ArrayOperator::ChangeAt => {
+            let at = position(operation.index, items.len())?;
+            items[at] = value()?;
+        }
+        ArrayOperator::SetInsert => {
+            items.push(value()?);
+            items = distinct(items);
+        }
+        ArrayOperator::SetUnion => {
+            items.extend(value_items()?);
+            items = distinct(items);
+        }
+        ArrayOperator::SetDifference => {
+            let removed = value_items()?;
+            items = distinct(items);
+            items.retain(|item| !contains(&removed, item));
+        }
+        ArrayOperator::Difference => {
+            let removed = value_items()?;
+            items.retain(|item| !contains(&removed, item));
+        }
+    }
+    Ok(items)
+}
+
+/// The position of an index among `len` positions, counting from the end when
+/// negative.
+fn position(index: i64, len: usize) -> Result<usize, EvalError> {
+    let at = if index < 0 { len as i64 + index } else { index };
+    if (0..len as i64).contains(&at) {
+        Ok(at as usize)
+    } else {
+        Err(EvalError::InvalidUpdate(format!(
+            "index {index} is out of range"
+        )))
+    }
+}
+
+/// The items without repeats, each where it first occurs.
+fn distinct(items: Vec<Datum>) -> Vec<Datum> {
+    let mut unique: Vec<Datum> = Vec::with_capacity(items.len());
+    for item in items {
+        if !contains(&unique, &item) {
+            unique.push(item);
+        }
+    }
+    unique
+}
+
+fn contains(items: &[Datum], item: &Datum) -> bool {
+    items.iter().any(|other| datums_equal(other, item))
+}
@@ -1,5 +1,9 @@
-use crate::ast::{Datum, DatumObject, Document, FieldRef, datum, query_result};
+use crate::ast::{
+    Datum, DatumObject, Document, Durability, Expression, FieldRef, datum, expression, query_result,
+};
+use crate::decimal::{Decimal, ExactNumber};
 use crate::evaluator::error::EvalError;
+use crate::storage;
 
 /// Extract a field value from a datum using field name
 pub fn extract_field_value(datum: &Datum, field: &str) -> Datum {
@@ -30,6 +34,80 @@ pub fn extract_field_from_ref(datum: &Datum, field_ref: &FieldRef) -> Datum {
     result
 }
 
+/// Map the durability requested by a write query to the storage durability. Returns
+/// `None` if the table's default durability should be used.
+pub fn write_durability(durability: Durability) -> Option<storage::Durability> {
+    match durability {
+        Durability::Default => None,
+        Durability::Hard => Some(storage::Durability::Hard),
+        Durability::Soft => Some(storage::Durability::Soft),
+    }
+}
+
+/// Collect the top-level document fields an expression reads. Returns `None` if the
+/// expression may depend on the whole document, e.g. because it contains a subquery.
+pub fn referenced_fields(expr: &Expression) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    collect_referenced_fields(expr, &mut fields)?;
+    fields.sort_unstable();
+    fields.dedup();
+    Some(fields)
+}
+
+fn collect_referenced_fields(expr: &Expression, fields: &mut Vec<String>) -> Option<()> {
+    match &expr.expr {
+        Some(expression::Expr::Literal(_)) | None => {}
+        Some(expression::Expr::Field(field_ref)) => fields.extend(field_ref.path.first().cloned()),
+        Some(expression::Expr::Variable(var)) => fields.push(var.name.clone()),
+        Some(expression::Expr::Binary(op)) => {
+            for side in [&op.left, &op.right].into_iter().flatten() {
+                collect_referenced_fields(side, fields)?;
+            }
+        }
+        Some(expression::Expr::Geo(op)) => {
+            for side in [&op.left, &op.right].into_iter().flatten() {
+                collect_referenced_fields(side, fields)?;
+            }
+        }
+        Some(expression::Expr::Time(op)) => {
+            for arg in &op.args {
+                collect_referenced_fields(arg, fields)?;
+            }
+        }
+        Some(expression::Expr::Document(op)) => {
+            for arg in &op.args {
+                collect_referenced_fields(arg, fields)?;
+            }
+        }
+        Some(expression::Expr::Unary(op)) => {
+            if let Some(inner) = &op.expr {
+                collect_referenced_fields(inner, fields)?;
+            }
+        }
+        Some(expression::Expr::Match(m)) => {
+            if let Some(value) = &m.value {
+                collect_referenced_fields(value, fields)?;
+            }
+        }
+        Some(expression::Expr::Branch(branch)) => {
+            let cases = branch
+                .cases
+                .iter()
+                .flat_map(|case| [case.condition.as_ref(), case.value.as_ref()]);
+            for expr in cases.chain([branch.otherwise.as_deref()]).flatten() {
+                collect_referenced_fields(expr, fields)?;
+            }
+        }
+        Some(expression::Expr::Error(error)) => {
+            if let Some(message) = &error.message {
+                collect_referenced_fields(message, fields)?;
+            }
+        }
+        Some(expression::Expr::Subquery(_)) => return None,
+    }
+    Some(())
+}
+
 /// Exclude fields from an DatumObject based on field references
 pub fn exclude_field_refs(
     datum: &Datum,
@@ -104,28 +182,140 @@ pub fn insert_field_by_ref(doc: &mut Document, field_ref: &FieldRef, value: Datu
     }
 }
 
-/// Compare two datum values with proper type handling
+/// Compare two datum values in the total order of all values
+///
+/// Values of different types sort by type: null, then booleans, numbers, times,
+/// strings, UUIDs, binary, arrays, objects and geometries. Integers, floats and decimals
+/// are all numbers, compared exactly, and times compare by instant whatever their
+/// offset. Arrays compare item by item and objects field by field in key order, a
+/// shorter one first when it begins the other. A missing value is null.
 pub fn compare_values(a: &Datum, b: &Datum) -> std::cmp::Ordering {
-    match (&a.value, &b.value) {
-        (Some(datum::Value::String(a)), Some(datum::Value::String(b))) => a.cmp(b),
-        (Some(datum::Value::Int(a)), Some(datum::Value::Int(b))) => a.cmp(b),
-        (Some(datum::Value::Float(a)), Some(datum::Value::Float(b))) => {
-            a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
-        }
-        (Some(datum::Value::Int(a)), Some(datum::Value::Float(b))) => (*a as f64)
-            .partial_cmp(b)
-            .unwrap_or(std::cmp::Ordering::Equal),
-        (Some(datum::Value::Float(a)), Some(datum::Value::Int(b))) => a
-            .partial_cmp(&(*b as f64))
-            .unwrap_or(std::cmp::Ordering::Equal),
-        (Some(datum::Value::Bool(a)), Some(datum::Value::Bool(b))) => a.cmp(b),
-        (None, None) => std::cmp::Ordering::Equal,
-        (None, Some(_)) => std::cmp::Ordering::Less,
-        (Some(_), None) => std::cmp::Ordering::Greater,
-        _ => std::cmp::Ordering::Equal,
+    compare_value(a.value.as_ref(), b.value.as_ref())
+}
+
+fn compare_value(a: Option<&datum::Value>, b: Option<&datum::Value>) -> std::cmp::Ordering {
+    use datum::Value;
+
+    match (a, b) {
+        (Some(Value::Bool(a)), Some(Value::Bool(b))) => a.cmp(b),
+        (Some(a), Some(b)) if is_number(a) && is_number(b) => compare_numbers(a, b),
+        (Some(Value::Time(a)), Some(Value::Time(b))) => a.epoch_micros.cmp(&b.epoch_micros),
+        (Some(Value::String(a)), Some(Value::String(b))) => a.cmp(b),
+        (Some(Value::Uuid(a)), Some(Value::Uuid(b))) => a.value.cmp(&b.value),
+        (Some(Value::Binary(a)), Some(Value::Binary(b))) => a.cmp(b),
+        (Some(Value::Array(a)), Some(Value::Array(b))) => {
+            compare_sequences(&a.items, &b.items, |a, b| {
+                compare_value(a.value.as_ref(), b.value.as_ref())
+            })
+        }
+        (Some(Value::Object(a)), Some(Value::Object(b))) => {
+            let (mut a, mut b): (Vec<_>, Vec<_>) =
+                (a.fields.iter().collect(), b.fields.iter().collect());
+            a.sort_unstable_by(|x, y| x.0.cmp(y.0));
+            b.sort_unstable_by(|x, y| x.0.cmp(y.0));
+            compare_sequences(&a, &b, |(a_key, a_value), (b_key, b_value)| {
+                a_key
+                    .cmp(b_key)
+                    .then_with(|| compare_value(a_value.value.as_ref(), b_value.value.as_ref()))
+            })
+        }
+        (Some(Value::Geometry(a)), Some(Value::Geometry(b))) => {
+            use prost::Message;
+            a.encode_to_vec().cmp(&b.encode_to_vec())
+        }
+        // Different types, or both null
+        (a, b) => type_rank(a).cmp(&type_rank(b)),
     }
 }
 
+/// The position of a value's type in the order of values
+fn type_rank(value: Option<&datum::Value>) -> u8 {
+    match value {
+        None | Some(datum::Value::Null(_)) => 0,
+        Some(datum::Value::Bool(_)) => 1,
+        Some(datum::Value::Int(_))
+        | Some(datum::Value::Float(_))
+        | Some(datum::Value::Decimal(_)) => 2,
+        Some(datum::Value::Time(_)) => 3,
+        Some(datum::Value::String(_)) => 4,
+        Some(datum::Value::Uuid(_)) => 5,
+        Some(datum::Value::Binary(_)) => 6,
+        Some(datum::Value::Array(_)) => 7,
+        Some(datum::Value::Object(_)) => 8,
+        Some(datum::Value::Geometry(_)) => 9,
+    }
+}
+
+fn is_number(value: &datum::Value) -> bool {
+    matches!(
+        value,
+        datum::Value::Int(_) | datum::Value::Float(_) | datum::Value::Decimal(_)
+    )
+}
+
+/// Compare two numbers exactly whatever their types, NaN above every other number
+///
+/// Every number rounds to the nearest float, or to the float it is, and rounding keeps
+/// order, so numbers are only compared exactly when their floats are equal.
+pub fn compare_numbers(a: &datum::Value, b: &datum::Value) -> std::cmp::Ordering {
+    if let (datum::Value::Int(a), datum::Value::Int(b)) = (a, b) {
+        return a.cmp(b);
+    }
+    let (a_float, b_float) = (number_to_f64(a), number_to_f64(b));
+    match compare_floats(a_float, b_float) {
+        std::cmp::Ordering::Equal if a_float.is_finite() => exact_number(a).cmp(&exact_number(b)),
+        ordering => ordering,
+    }
+}
+
+/// The nearest float to a number, or NaN if it isn't one
+pub fn number_to_f64(value: &datum::Value) -> f64 {
+    match value {
+        datum::Value::Int(i) => *i as f64,
+        datum::Value::Float(f) => *f,
+        datum::Value::Decimal(d) => Decimal::parse(&d.value).map_or(f64::NAN, |d| d.to_f64()),
+        _ => f64::NAN,
+    }
+}
+
+/// Convert a number to a decimal, a float by its shortest text
+pub fn datum_to_decimal(datum: &Datum) -> Result<Decimal, EvalError> {
+    match &datum.value {
+        Some(datum::Value::Int(i)) => Ok(Decimal::from(*i)),
+        Some(datum::Value::Float(f)) => Ok(Decimal::from_f64(*f)?),
+        Some(datum::Value::Decimal(d)) => Ok(Decimal::parse(&d.value)?),
+        _ => Err(EvalError::TypeMismatch),
+    }
+}
+
+/// The exact value of a finite number
+pub fn exact_number(value: &datum::Value) -> Option<ExactNumber> {
+    match value {
+        datum::Value::Int(i) => Some(ExactNumber::from_i128((*i).into())),
+        datum::Value::Float(f) => ExactNumber::from_f64(*f),
+        datum::Value::Decimal(d) => Decimal::parse(&d.value).ok().map(|d| ExactNumber::from(&d)),
+        _ => None,
+    }
+}
+
+/// Compare two sequences element by element, a shorter one first when it begins the other
+fn compare_sequences<T>(
+    a: &[T],
+    b: &[T],
+    compare: impl Fn(&T, &T) -> std::cmp::Ordering,
+) -> std::cmp::Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| compare(a, b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+fn compare_floats(a: f64, b: f64) -> std::cmp::Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
 /// Convert a datum to boolean value
 pub fn datum_to_bool(datum: &Datum) -> bool {
     match &datum.value {
@@ -133,9 +323,13 @@ pub fn datum_to_bool(datum: &Datum) -> bool {
         Some(datum::Value::String(s)) => !s.is_empty(),
         Some(datum::Value::Int(i)) => *i != 0,
         Some(datum::Value::Float(f)) => *f != 0.0,
+        Some(datum::Value::Decimal(d)) => Decimal::parse(&d.value).is_ok_and(|d| !d.is_zero()),
         Some(datum::Value::Object(_)) => true,
         Some(datum::Value::Array(arr)) => !arr.items.is_empty(),
         Some(datum::Value::Binary(b)) => !b.is_empty(),
+        Some(datum::Value::Geometry(_))
+        | Some(datum::Value::Time(_))
+        | Some(datum::Value::Uuid(_)) => true,
         Some(datum::Value::Null(_)) => false,
         None => false,
     }
@@ -149,24 +343,9 @@ pub fn extract_document_key(datum: &Datum) -> Result<String, EvalError> {
     }
 }
 
-/// Check if two datums are equal
+/// Check if two datums are equal, comparing arrays and objects deeply
 pub fn datums_equal(a: &Datum, b: &Datum) -> bool {
-    match (&a.value, &b.value) {
-        (Some(datum::Value::String(a)), Some(datum::Value::String(b))) => a == b,
-        (Some(datum::Value::Int(a)), Some(datum::Value::Int(b))) => a == b,
-        (Some(datum::Value::Float(a)), Some(datum::Value::Float(b))) => {
-            (a - b).abs() < f64::EPSILON
-        }
-        (Some(datum::Value::Int(a)), Some(datum::Value::Float(b))) => {
-            (*a as f64 - b).abs() < f64::EPSILON
-        }
-        (Some(datum::Value::Float(a)), Some(datum::Value::Int(b))) => {
-            (a - *b as f64).abs() < f64::EPSILON
-        }
-        (Some(datum::Value::Bool(a)), Some(datum::Value::Bool(b))) => a == b,
-        (None, None) => true,
-        _ => false,
-    }
+    compare_values(a, b).is_eq()
 }
 
 /// Create a string datum
@@ -183,6 +362,13 @@ pub fn bool_datum(b: bool) -> Datum {
     }
 }
 
+/// Create an integer datum
+pub fn int_datum(i: i64) -> Datum {
+    Datum {
+        value: Some(datum::Value::Int(i)),
+    }
+}
+
 pub fn is_single_doc_source(source_result: &query_result::Result) -> bool {
     matches!(source_result, query_result::Result::Get(_))
 }
@@ -0,0 +1,463 @@
+//! Geometry values and the geospatial functions over them.
+//!
+//! Coordinates are longitude and latitude in degrees. Distances are great-circle
+//! distances in meters on a spherical Earth. Intersection and containment treat edges
+//! as straight lines in longitude and latitude, which is close to exact for shapes a
+//! few hundred kilometers across and never wraps around the antimeridian.
+
+use crate::ast::{self, geometry};
+
+/// Mean radius of the Earth in meters.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Meters a nearest neighbour lookup searches unless it asks for another distance.
+pub const DEFAULT_MAX_DIST: f64 = 100_000.0;
+
+/// Results a nearest neighbour lookup returns unless it asks for another number.
+pub const DEFAULT_MAX_RESULTS: u32 = 100;
+
+#[derive(Debug)]
+pub struct GeometryError(pub String);
+
+impl std::fmt::Display for GeometryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for GeometryError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub lon: f64,
+    pub lat: f64,
+}
+
+/// A validated geometry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Point(Point),
+    /// A line through two or more points.
+    Line(Vec<Point>),
+    /// A polygon bounded by three or more points, without repeating the first one.
+    Polygon(Vec<Point>),
+}
+
+/// The smallest longitude and latitude range holding a shape.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl Point {
+    pub fn new(lon: f64, lat: f64) -> Result<Self, GeometryError> {
+        if !(-180.0..=180.0).contains(&lon) || !(-90.0..=90.0).contains(&lat) {
+            return Err(GeometryError(format!(
+                "coordinates ({lon}, {lat}) are out of range"
+            )));
+        }
+        Ok(Self { lon, lat })
+    }
+
+    /// Great-circle distance to another point in meters.
+    pub fn distance(&self, other: &Point) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+    }
+
+    /// Points within `radius` meters of this one lie inside the returned box.
+    pub fn bounding_box(&self, radius: f64) -> BoundingBox {
+        let dlat = (radius / EARTH_RADIUS).to_degrees();
+        let min_lat = (self.lat - dlat).max(-90.0);
+        let max_lat = (self.lat + dlat).min(90.0);
+        let widest = min_lat.abs().max(max_lat.abs()).to_radians().cos();
+        let dlon = if widest > f64::EPSILON {
+            dlat / widest
+        } else {
+            360.0
+        };
+        if dlon >= 180.0 {
+            return BoundingBox {
+                min_lon: -180.0,
+                min_lat,
+                max_lon: 180.0,
+                max_lat,
+            };
+        }
+        BoundingBox {
+            min_lon: (self.lon - dlon).max(-180.0),
+            min_lat,
+            max_lon: (self.lon + dlon).min(180.0),
+            max_lat,
+        }
+    }
+}
+
+impl Shape {
+    pub fn points(&self) -> &[Point] {
+        match self {
+            Self::Point(point) => std::slice::from_ref(point),
+            Self::Line(points) | Self::Polygon(points) => points,
+        }
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (Point, Point)> + '_ {
+        let points = self.points();
+        let closing =
+            matches!(self, Self::Polygon(_)).then(|| (points[points.len() - 1], points[0]));
+        points
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .chain(closing)
+    }
+
+    pub fn bounding_box(&self) -> BoundingBox {
+        let mut bbox = BoundingBox {
+            min_lon: f64::INFINITY,
+            min_lat: f64::INFINITY,
+            max_lon: f64::NEG_INFINITY,
+            max_lat: f64::NEG_INFINITY,
+        };
+        for point in self.points() {
+            bbox.min_lon = bbox.min_lon.min(point.lon);
+            bbox.min_lat = bbox.min_lat.min(point.lat);
+            bbox.max_lon = bbox.max_lon.max(point.lon);
+            bbox.max_lat = bbox.max_lat.max(point.lat);
+        }
+        bbox
+    }
+
+    /// Whether the shapes share at least one point.
+    pub fn intersects(&self, other: &Shape) -> bool {
+        match (self, other) {
+            (Self::Point(a), Self::Point(b)) => a == b,
+            (Self::Point(point), shape) | (shape, Self::Point(point)) => shape.covers_point(point),
+            _ => {
+                self.edges()
+                    .any(|a| other.edges().any(|b| segments_intersect(a, b)))
+                    || other.points().iter().any(|point| self.covers_point(point))
+                    || self.points().iter().any(|point| other.covers_point(point))
+            }
+        }
+    }
+
+    /// Whether `other` lies entirely within this shape, which must be a polygon.
+    pub fn includes(&self, other: &Shape) -> Result<bool, GeometryError> {
+        if !matches!(self, Self::Polygon(_)) {
+            return Err(GeometryError(
+                "only a polygon can include another geometry".to_string(),
+            ));
+        }
+        if !other.points().iter().all(|point| self.covers_point(point)) {
+            return Ok(false);
+        }
+        // Every vertex is inside, so an edge can leave the polygon only by crossing it
+        Ok(other.edges().all(|(start, end)| {
+            let middle = Point {
+                lon: (start.lon + end.lon) / 2.0,
+                lat: (start.lat + end.lat) / 2.0,
+            };
+            self.covers_point(&middle)
+                && !self.edges().any(|edge| segments_cross(edge, (start, end)))
+        }))
+    }
+
+    /// Shortest distance between the shapes in meters, zero if they intersect.
+    pub fn distance(&self, other: &Shape) -> f64 {
+        if self.intersects(other) {
+            return 0.0;
+        }
+        let to_edges = |points: &[Point], shape: &Shape| {
+            points
+                .iter()
+                .flat_map(|point| match shape {
+                    Self::Point(other) => vec![point.distance(other)],
+                    _ => shape
+                        .edges()
+                        .map(|edge| segment_distance(point, edge))
+                        .collect(),
+                })
+                .fold(f64::INFINITY, f64::min)
+        };
+        to_edges(self.points(), other).min(to_edges(other.points(), self))
+    }
+
+    /// Whether the point lies on the shape, or inside it for a polygon.
+    fn covers_point(&self, point: &Point) -> bool {
+        if self.edges().any(|edge| on_segment(point, edge)) {
+            return true;
+        }
+        match self {
+            Self::Point(other) => other == point,
+            Self::Line(_) => false,
+            Self::Polygon(_) => {
+                let mut inside = false;
+                for (a, b) in self.edges() {
+                    if (a.lat > point.lat) != (b.lat > point.lat)
+                        && point.lon
+                            < (b.lon - a.lon) * (point.lat - a.lat) / (b.lat - a.lat) + a.lon
+                    {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+        }
+    }
+}
+
+impl BoundingBox {
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.min_lon <= other.max_lon
+            && other.min_lon <= self.max_lon
+            && self.min_lat <= other.max_lat
+            && other.min_lat <= self.max_lat
+    }
+}
+
+fn orientation(a: Point, b: Point, c: Point) -> f64 {
+    (b.lon - a.lon) * (c.lat - a.lat) - (b.lat - a.lat) * (c.lon - a.lon)
+}
+
+fn on_segment(point: &Point, (a, b): (Point, Point)) -> bool {
+    orientation(a, b, *point).abs() <= f64::EPSILON
+        && point.lon >= a.lon.min(b.lon)
+        && point.lon <= a.lon.max(b.lon)
+        && point.lat >= a.lat.min(b.lat)
+        && point.lat <= a.lat.max(b.lat)
+}
+
+/// Whether the segments share a point, including touching at an end.
+fn segments_intersect(first: (Point, Point), second: (Point, Point)) -> bool {
+    segments_cross(first, second)
+        || on_segment(&second.0, first)
+        || on_segment(&second.1, first)
+        || on_segment(&first.0, second)
+        || on_segment(&first.1, second)
+}
+
+/// Whether the segments cross at a point inside both of them.
+fn segments_cross((a, b): (Point, Point), (c, d): (Point, Point)) -> bool {
+    let (o1, o2) = (orientation(a, b, c), orientation(a, b, d));
+    let (o3, o4) = (orientation(c, d, a), orientation(c, d, b));
+    o1 * o2 < 0.0 && o3 * o4 < 0.0
+}
+
+/// Distance in meters from a point to the closest point of a segment, found in a
+/// local projection that scales longitude by the cosine of the latitude.
+fn segment_distance(point: &Point, (a, b): (Point, Point)) -> f64 {
+    let scale = point.lat.to_radians().cos();
+    let (dx, dy) = ((b.lon - a.lon) * scale, b.lat - a.lat);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 {
+        (((point.lon - a.lon) * scale * dx + (point.lat - a.lat) * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    point.distance(&Point {
+        lon: a.lon + t * (b.lon - a.lon),
+        lat: a.lat + t * (b.lat - a.lat),
+    })
+}
+
+fn points(points: &[ast::Point]) -> Result<Vec<Point>, GeometryError> {
+    points
+        .iter()
+        .map(|point| Point::new(point.longitude, point.latitude))
+        .collect()
+}
+
+impl TryFrom<&ast::Point> for Point {
+    type Error = GeometryError;
+
+    fn try_from(point: &ast::Point) -> Result<Self, Self::Error> {
+        Point::new(point.longitude, point.latitude)
+    }
+}
+
+impl TryFrom<&ast::Geometry> for Shape {
+    type Error = GeometryError;
+
+    fn try_from(geometry: &ast::Geometry) -> Result<Self, Self::Error> {
+        match &geometry.kind {
+            Some(geometry::Kind::Point(point)) => Ok(Self::Point(point.try_into()?)),
+            Some(geometry::Kind::Line(line)) => {
+                let points = points(&line.points)?;
+                if points.len() < 2 {
+                    return Err(GeometryError(
+                        "a line needs at least two points".to_string(),
+                    ));
+                }
+                Ok(Self::Line(points))
+            }
+            Some(geometry::Kind::Polygon(polygon)) => {
+                let mut points = points(&polygon.points)?;
+                if points.len() > 1 && points.first() == points.last() {
+                    points.pop();
+                }
+                if points.len() < 3 {
+                    return Err(GeometryError(
+                        "a polygon needs at least three points".to_string(),
+                    ));
+                }
+                Ok(Self::Polygon(points))
+            }
+            None => Err(GeometryError("geometry has no kind".to_string())),
+        }
+    }
+}
+
+impl From<Point> for ast::Point {
+    fn from(point: Point) -> Self {
+        ast::Point {
+            longitude: point.lon,
+            latitude: point.lat,
+        }
+    }
+}
+
+impl From<&Shape> for ast::Geometry {
+    fn from(shape: &Shape) -> Self {
+        let points = || shape.points().iter().map(|&point| point.into()).collect();
+        ast::Geometry {
+            kind: Some(match shape {
+                Shape::Point(point) => geometry::Kind::Point((*point).into()),
+                Shape::Line(_) => geometry::Kind::Line(ast::LineString { points: points() }),
+                Shape::Polygon(_) => geometry::Kind::Polygon(ast::Polygon { points: points() }),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lon: f64, lat: f64) -> Point {
+        Point::new(lon, lat).unwrap()
+    }
+
+    fn square(min: f64, max: f64) -> Shape {
+        Shape::Polygon(vec![
+            point(min, min),
+            point(max, min),
+            point(max, max),
+            point(min, max),
+        ])
+    }
+
+    #[test]
+    fn test_point_distance() {
+        let london = point(-0.1278, 51.5074);
+        let paris = point(2.3522, 48.8566);
+        let distance = london.distance(&paris);
+        assert!((distance - 343_560.0).abs() < 1_000.0, "{distance}");
+        assert_eq!(london.distance(&london), 0.0);
+        assert!(Point::new(181.0, 0.0).is_err());
+        assert!(Point::new(0.0, -90.5).is_err());
+    }
+
+    #[test]
+    fn test_intersects() {
+        let polygon = square(0.0, 2.0);
+        assert!(polygon.intersects(&Shape::Point(point(1.0, 1.0))));
+        assert!(polygon.intersects(&Shape::Point(point(2.0, 1.0))));
+        assert!(!polygon.intersects(&Shape::Point(point(3.0, 1.0))));
+
+        let crossing = Shape::Line(vec![point(-1.0, 1.0), point(3.0, 1.0)]);
+        let outside = Shape::Line(vec![point(3.0, 0.0), point(3.0, 3.0)]);
+        assert!(polygon.intersects(&crossing));
+        assert!(crossing.intersects(&polygon));
+        assert!(!polygon.intersects(&outside));
+        assert!(polygon.intersects(&square(0.5, 1.5)));
+        assert!(square(-1.0, 3.0).intersects(&polygon));
+        assert!(!polygon.intersects(&square(5.0, 6.0)));
+        assert!(crossing.intersects(&Shape::Point(point(0.0, 1.0))));
+    }
+
+    #[test]
+    fn test_includes() {
+        let polygon = square(0.0, 2.0);
+        assert!(polygon.includes(&square(0.5, 1.5)).unwrap());
+        assert!(polygon.includes(&Shape::Point(point(1.0, 1.0))).unwrap());
+        assert!(!polygon.includes(&square(1.0, 3.0)).unwrap());
+        assert!(
+            !polygon
+                .includes(&Shape::Line(vec![point(1.0, 1.0), point(3.0, 1.0)]))
+                .unwrap()
+        );
+
+        // A line between two inside points may still leave a concave polygon
+        let concave = Shape::Polygon(vec![
+            point(0.0, 0.0),
+            point(4.0, 0.0),
+            point(4.0, 4.0),
+            point(2.0, 1.0),
+            point(0.0, 4.0),
+        ]);
+        let line = Shape::Line(vec![point(0.3, 3.0), point(3.7, 3.0)]);
+        assert!(concave.covers_point(&point(0.3, 3.0)));
+        assert!(concave.covers_point(&point(3.7, 3.0)));
+        assert!(!concave.includes(&line).unwrap());
+        assert!(Shape::Point(point(0.0, 0.0)).includes(&line).is_err());
+    }
+
+    #[test]
+    fn test_shape_distance() {
+        let polygon = square(0.0, 1.0);
+        assert_eq!(polygon.distance(&Shape::Point(point(0.5, 0.5))), 0.0);
+
+        let east = Shape::Point(point(2.0, 0.5));
+        let expected = point(1.0, 0.5).distance(&point(2.0, 0.5));
+        assert!((polygon.distance(&east) - expected).abs() < 1.0);
+        assert!((east.distance(&polygon) - expected).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let bbox = point(10.0, 0.0).bounding_box(111_195.0);
+        assert!((bbox.max_lat - 1.0).abs() < 1e-3);
+        assert!((bbox.min_lon - 9.0).abs() < 1e-3);
+        assert_eq!(point(0.0, 89.9).bounding_box(100_000.0).min_lon, -180.0);
+        let line = |from: f64| Shape::Line(vec![point(from, 0.5), point(20.0, 0.5)]);
+        assert!(bbox.intersects(&line(10.5).bounding_box()));
+        assert!(!bbox.intersects(&line(12.0).bounding_box()));
+    }
+
+    #[test]
+    fn test_geometry_conversion() {
+        let geometry = ast::Geometry {
+            kind: Some(geometry::Kind::Polygon(ast::Polygon {
+                points: [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 0.0)]
+                    .map(|(longitude, latitude)| ast::Point {
+                        longitude,
+                        latitude,
+                    })
+                    .to_vec(),
+            })),
+        };
+        let shape = Shape::try_from(&geometry).unwrap();
+        assert_eq!(shape.points().len(), 3);
+        assert_eq!(
+            Shape::try_from(&ast::Geometry::from(&shape)).unwrap(),
+            shape
+        );
+
+        let line = ast::Geometry {
+            kind: Some(geometry::Kind::Line(ast::LineString {
+                points: vec![ast::Point {
+                    longitude: 0.0,
+                    latitude: 0.0,
+                }],
+            })),
+        };
+        assert!(Shape::try_from(&line).is_err());
+        assert!(Shape::try_from(&ast::Geometry { kind: None }).is_err());
+    }
+}
@@ -1,8 +1,13 @@
 pub mod ast;
+pub mod cluster;
+pub mod decimal;
 pub mod evaluator;
+pub mod geo;
 pub mod parser;
 pub mod planner;
 pub mod storage;
+pub mod time;
+pub mod uuid;
 
 // Re-export commonly used types
 pub use ast::{
@@ -4,12 +4,16 @@
 #![warn(clippy::nursery)]
 #![allow(clippy::multiple_crate_versions)]
 mod cli;
+mod replication;
 mod server;
 
 use crate::cli::{Cli, Commands};
 use clap::Parser;
+use rulodb::cluster::{ClusterNode, ClusterStorage, NodeConfig, RocksLogStore, TcpTransport};
+use rulodb::storage::replication::{ReplicationRole, WalRetention};
 use rulodb::{DefaultStorage, StorageBackend};
 use std::sync::Arc;
+use std::time::Duration;
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
@@ -46,12 +50,61 @@ async fn porcelain() -> anyhow::Result<()> {
                 write_buffer_size: engine.write_buffer_size,
                 max_write_buffer_number: engine.max_write_buffers,
                 min_write_buffer_number_to_merge: engine.min_write_buffers_to_merge,
+                wal_retention: cmd.replication.serve_replicas.then(|| WalRetention {
+                    ttl_seconds: engine.wal_ttl_seconds,
+                    size_limit_mb: engine.wal_size_limit_mb,
+                }),
             };
 
-            let db = DefaultStorage::open(&engine_config)?;
-            let storage: Arc<dyn StorageBackend + Send + Sync> = Arc::new(db);
+            let secondary_of = cmd.replication.secondary_of.clone();
+            let db = Arc::new(match &secondary_of {
+                Some(primary_dir) => DefaultStorage::open_secondary(&engine_config, primary_dir)?,
+                None => DefaultStorage::open(&engine_config)?,
+            });
+            let replica_of = cmd.replication.replica_of.clone();
+            if let Some(primary) = &replica_of {
+                db.set_replication_role(ReplicationRole::Replica {
+                    primary: primary.clone(),
+                });
+            }
+            let mut storage: Arc<dyn StorageBackend + Send + Sync> = db.clone();
 
-            server::start_server(storage, &cmd.server_config.address).await?;
+            let cluster = if let Some(node_id) = cmd.cluster.node_id {
+                let config = NodeConfig {
+                    node_id,
+                    members: cmd.cluster.members.clone(),
+                    heartbeat_interval: Duration::from_millis(cmd.cluster.heartbeat_interval),
+                    election_timeout: Duration::from_millis(cmd.cluster.election_timeout),
+                    snapshot_threshold: cmd.cluster.snapshot_threshold,
+                    ..NodeConfig::default()
+                };
+                // RocksDB owns the data directory, so the log lives beside it
+                let log_dir =
+                    cmd.cluster.raft_dir.clone().unwrap_or_else(|| {
+                        format!("{}-raft", engine.data_dir.trim_end_matches('/'))
+                    });
+                let log_store = RocksLogStore::open(&log_dir)?;
+                let node = ClusterNode::start(
+                    config,
+                    Box::new(log_store),
+                    storage.clone(),
+                    Arc::new(TcpTransport::new()),
+                )?;
+                storage = Arc::new(ClusterStorage::new(node.clone(), storage));
+                Some(node)
+            } else {
+                None
+            };
+
+            let poll_interval = Duration::from_millis(cmd.replication.replication_poll_interval);
+            if let Some(primary) = replica_of {
+                tokio::spawn(replication::follow(storage.clone(), primary, poll_interval));
+            }
+            if secondary_of.is_some() {
+                tokio::spawn(replication::catch_up(db, poll_interval));
+            }
+
+            server::start_server(storage, cluster, &cmd.server_config).await?;
         }
     }
 
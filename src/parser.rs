@@ -64,6 +64,7 @@ mod tests {
             options: Some(QueryOptions {
                 timeout_ms: 5000,
                 explain: false,
+                read_mode: ReadMode::Single.into(),
             }),
             cursor: None,
             kind: Some(query::Kind::DatabaseList(DatabaseList {})),
@@ -225,6 +226,7 @@ mod tests {
             options: Some(QueryOptions {
                 timeout_ms: 10000,
                 explain: true,
+                read_mode: ReadMode::Single.into(),
             }),
             cursor: Some(Cursor {
                 start_key: Some("start".to_string()),
@@ -235,6 +237,7 @@ mod tests {
                         direction: SortDirection::Asc.into(),
                     }],
                 }),
+                snapshot: None,
             }),
             kind: Some(query::Kind::Table(Table {
                 table: Some(table_ref),
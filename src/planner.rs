@@ -4,13 +4,17 @@ mod error;
 mod explain;
 mod node;
 mod optimizer;
+mod prepared;
 
 use crate::ast::{Cursor, Query};
+use crate::storage::statistics::StatisticsProvider;
+use std::sync::Arc;
 
 // Re-export commonly used types
 pub use error::{PlanError, PlanResult};
 pub use explain::{ExplanationNode, PlanExplanation};
 pub use node::{FILTER_COST, GET_COST, PlanNode, TABLE_SCAN_COST};
+pub use prepared::{BoundQuery, DEFAULT_PREPARED_QUERIES, PreparedQueries};
 
 use builder::PlanBuilder;
 use optimizer::PlanOptimizer;
@@ -23,6 +27,8 @@ pub struct Planner {
     builder: PlanBuilder,
     /// Optimizer for improving plans
     optimizer: PlanOptimizer,
+    /// Table statistics the cost estimates are based on
+    statistics: Option<Arc<dyn StatisticsProvider>>,
 }
 
 impl Default for Planner {
@@ -38,19 +44,30 @@ impl Planner {
             cursor_context: None,
             builder: PlanBuilder::new(),
             optimizer: PlanOptimizer::new(),
+            statistics: None,
+        }
+    }
+
+    /// Create a planner that estimates costs from table statistics
+    pub fn with_statistics(statistics: Arc<dyn StatisticsProvider>) -> Self {
+        Self {
+            statistics: Some(statistics),
+            ..Self::new()
         }
     }
 
     /// Plan a query and return an optimized execution plan
     pub fn plan(&mut self, query: &Query) -> PlanResult<PlanNode> {
         // Set cursor context in builder
-        self.builder = PlanBuilder::with_cursor(self.cursor_context.clone());
+        self.builder = PlanBuilder::with_cursor(self.cursor_context.clone())
+            .with_statistics(self.statistics.clone());
 
         // Build the initial plan
         let initial_plan = self.builder.build(query)?;
 
         // Optimize the plan
-        let old_builder = std::mem::replace(&mut self.builder, PlanBuilder::new());
+        let empty_builder = PlanBuilder::new().with_statistics(self.statistics.clone());
+        let old_builder = std::mem::replace(&mut self.builder, empty_builder);
         self.optimizer = PlanOptimizer::with_builder(old_builder);
         let optimized_plan = self.optimizer.optimize(initial_plan)?;
 
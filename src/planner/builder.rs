@@ -1,12 +1,34 @@
 use crate::ast::*;
+use crate::evaluator::expression::ExpressionEvaluator;
+use crate::evaluator::subquery::{is_correlated, table_subqueries};
+use crate::evaluator::utils::{compare_values, datums_equal, number_to_f64};
+use crate::geo::DEFAULT_MAX_RESULTS;
 use crate::planner::cache::PlanCache;
 use crate::planner::error::{PlanError, PlanResult};
-use crate::planner::node::{GET_COST, PlanNode, TABLE_SCAN_COST};
+use crate::planner::node::{FILTER_COST, GET_COST, PlanNode, TABLE_SCAN_COST};
+use crate::storage::statistics::{StatisticsProvider, TableStatistics};
+use std::sync::Arc;
+
+/// Rows assumed in a table when no statistics are available
+const DEFAULT_TABLE_ROWS: f64 = 1000.0;
+/// Databases assumed when no statistics are available
+const DEFAULT_DATABASE_COUNT: f64 = 10.0;
+/// Tables per database assumed when no statistics are available
+const DEFAULT_TABLE_COUNT: f64 = 50.0;
+/// Fraction of a table's documents assumed to match a full-text search
+const SEARCH_SELECTIVITY: f64 = 0.1;
+/// Fraction of a table's documents assumed to lie in a geospatial lookup's area
+const GEO_SELECTIVITY: f64 = 0.01;
+/// Fraction of a table's documents assumed to match one value of a secondary index
+const INDEX_EQ_SELECTIVITY: f64 = 0.1;
+/// Fraction of a table's documents assumed to fall in a bounded secondary index range
+const INDEX_RANGE_SELECTIVITY: f64 = 0.3;
 
 /// Builder for constructing query plans from AST nodes
 pub struct PlanBuilder {
     cursor_context: Option<Cursor>,
     cache: PlanCache,
+    statistics: Option<Arc<dyn StatisticsProvider>>,
 }
 
 impl PlanBuilder {
@@ -15,6 +37,7 @@ impl PlanBuilder {
         Self {
             cursor_context: None,
             cache: PlanCache::new(),
+            statistics: None,
         }
     }
 
@@ -23,9 +46,28 @@ impl PlanBuilder {
         Self {
             cursor_context: cursor,
             cache: PlanCache::new(),
+            statistics: None,
         }
     }
 
+    /// Base estimates on the statistics of the tables being queried
+    pub fn with_statistics(mut self, statistics: Option<Arc<dyn StatisticsProvider>>) -> Self {
+        self.statistics = statistics;
+        self
+    }
+
+    /// Look up the statistics of a table
+    pub fn table_statistics(&self, table_ref: &TableRef) -> Option<TableStatistics> {
+        let db_name = table_ref
+            .database
+            .as_ref()
+            .map(|d| d.name.as_str())
+            .unwrap_or("default");
+        self.statistics
+            .as_ref()?
+            .table_statistics(db_name, &table_ref.name)
+    }
+
     /// Build a plan from a query
     pub fn build(&mut self, query: &Query) -> PlanResult<PlanNode> {
         self.build_query_internal(query)
@@ -43,6 +85,127 @@ impl PlanBuilder {
             Some(query::Kind::Table(table_query)) => self.build_table_query(table_query),
             Some(query::Kind::Get(get_query)) => self.build_get_query(get_query),
             Some(query::Kind::GetAll(get_all_query)) => self.build_get_all_query(get_all_query),
+            Some(query::Kind::Search(search)) => {
+                let table_ref = search
+                    .table
+                    .clone()
+                    .ok_or(PlanError::MissingTableReference)?;
+                let estimated_rows = self
+                    .table_statistics(&table_ref)
+                    .map_or(DEFAULT_TABLE_ROWS, |stats| stats.row_count as f64)
+                    * SEARCH_SELECTIVITY;
+                Ok(PlanNode::Search {
+                    table_ref,
+                    index: search.index.clone(),
+                    query: search.query.clone(),
+                    cost: GET_COST * estimated_rows,
+                    estimated_rows,
+                })
+            }
+            Some(query::Kind::GetIntersecting(get_intersecting)) => {
+                let table_ref = get_intersecting
+                    .table
+                    .clone()
+                    .ok_or(PlanError::MissingTableReference)?;
+                let geometry = get_intersecting.geometry.clone().ok_or_else(|| {
+                    PlanError::InvalidExpression("Geometry is missing".to_string())
+                })?;
+                let estimated_rows = self
+                    .table_statistics(&table_ref)
+                    .map_or(DEFAULT_TABLE_ROWS, |stats| stats.row_count as f64)
+                    * GEO_SELECTIVITY;
+                Ok(PlanNode::GetIntersecting {
+                    table_ref,
+                    index: get_intersecting.index.clone(),
+                    geometry,
+                    cost: GET_COST * estimated_rows,
+                    estimated_rows,
+                })
+            }
+            Some(query::Kind::GetNearest(get_nearest)) => {
+                let table_ref = get_nearest
+                    .table
+                    .clone()
+                    .ok_or(PlanError::MissingTableReference)?;
+                let point = get_nearest
+                    .point
+                    .ok_or_else(|| PlanError::InvalidExpression("Point is missing".to_string()))?;
+                let estimated_rows = (self
+                    .table_statistics(&table_ref)
+                    .map_or(DEFAULT_TABLE_ROWS, |stats| stats.row_count as f64)
+                    * GEO_SELECTIVITY)
+                    .min(f64::from(
+                        get_nearest.max_results.unwrap_or(DEFAULT_MAX_RESULTS),
+                    ));
+                Ok(PlanNode::GetNearest {
+                    table_ref,
+                    index: get_nearest.index.clone(),
+                    point,
+                    max_dist: get_nearest.max_dist,
+                    max_results: get_nearest.max_results,
+                    cost: GET_COST * estimated_rows,
+                    estimated_rows,
+                })
+            }
+            Some(query::Kind::Nearest(nearest)) => {
+                let table_ref = nearest
+                    .table
+                    .clone()
+                    .ok_or(PlanError::MissingTableReference)?;
+                if nearest.k == 0 {
+                    return Err(PlanError::InvalidExpression(
+                        "Nearest must return at least one document".to_string(),
+                    ));
+                }
+                let rows = self
+                    .table_statistics(&table_ref)
+                    .map_or(DEFAULT_TABLE_ROWS, |stats| stats.row_count as f64);
+                let estimated_rows = rows.min(f64::from(nearest.k));
+                // A filter makes the search compare every vector of the index
+                let filter_cost = if nearest.filter.is_some() {
+                    rows * FILTER_COST
+                } else {
+                    0.0
+                };
+                Ok(PlanNode::Nearest {
+                    table_ref,
+                    index: nearest.index.clone(),
+                    vector: nearest.vector.clone(),
+                    k: nearest.k,
+                    filter: nearest.filter.as_deref().cloned(),
+                    probes: nearest.probes,
+                    cost: GET_COST * estimated_rows + filter_cost,
+                    estimated_rows,
+                })
+            }
+            Some(query::Kind::Between(between)) => {
+                let table_ref = between
+                    .table
+                    .clone()
+                    .ok_or(PlanError::MissingTableReference)?;
+                if between.index.is_empty() {
+                    return Err(PlanError::InvalidExpression(
+                        "Between requires an index".to_string(),
+                    ));
+                }
+                let selectivity = if between.lower.is_none() && between.upper.is_none() {
+                    1.0
+                } else {
+                    INDEX_RANGE_SELECTIVITY
+                };
+                let estimated_rows = self
+                    .table_statistics(&table_ref)
+                    .map_or(DEFAULT_TABLE_ROWS, |stats| stats.row_count as f64)
+                    * selectivity;
+                Ok(PlanNode::Between {
+                    table_ref,
+                    index: between.index.clone(),
+                    lower: between.lower.clone(),
+                    upper: between.upper.clone(),
+                    cost: GET_COST * estimated_rows,
+                    estimated_rows,
+                })
+            }
             Some(query::Kind::Filter(filter_query)) => self.build_filter_query(filter_query),
 
             // Transformations
@@ -50,12 +213,133 @@ impl PlanBuilder {
             Some(query::Kind::Limit(limit_query)) => self.build_limit_query(limit_query),
             Some(query::Kind::Skip(skip_query)) => self.build_skip_query(skip_query),
 
+            // Sequence operations
+            Some(query::Kind::Union(union_query)) => self.build_union_query(union_query),
+            Some(query::Kind::Slice(slice_query)) => {
+                let source_plan =
+                    self.build_sequence_source(slice_query.source.as_deref(), "Slice")?;
+                Ok(PlanNode::Slice {
+                    cost: source_plan.cost(),
+                    source: Box::new(source_plan),
+                    start: slice_query.start,
+                    end: slice_query.end,
+                    left_open: slice_query.left_open,
+                    right_closed: slice_query.right_closed,
+                })
+            }
+            Some(query::Kind::Nth(nth_query)) => {
+                let source_plan = self.build_sequence_source(nth_query.source.as_deref(), "Nth")?;
+                Ok(PlanNode::Nth {
+                    cost: source_plan.cost(),
+                    source: Box::new(source_plan),
+                    index: nth_query.index,
+                })
+            }
+            Some(query::Kind::Sample(sample_query)) => {
+                let source_plan =
+                    self.build_sequence_source(sample_query.source.as_deref(), "Sample")?;
+                Ok(PlanNode::Sample {
+                    cost: source_plan.cost(),
+                    source: Box::new(source_plan),
+                    count: sample_query.count,
+                })
+            }
+            Some(query::Kind::IsEmpty(is_empty_query)) => {
+                let source_plan =
+                    self.build_sequence_source(is_empty_query.source.as_deref(), "IsEmpty")?;
+                Ok(PlanNode::IsEmpty {
+                    cost: source_plan.cost(),
+                    source: Box::new(source_plan),
+                })
+            }
+            Some(query::Kind::OffsetsOf(offsets_of_query)) => {
+                let source_plan =
+                    self.build_sequence_source(offsets_of_query.source.as_deref(), "OffsetsOf")?;
+                let target =
+                    offsets_of_query
+                        .target
+                        .clone()
+                        .ok_or(PlanError::InvalidExpression(
+                            "OffsetsOf missing value or predicate".to_string(),
+                        ))?;
+                Ok(PlanNode::OffsetsOf {
+                    cost: source_plan.cost() + source_plan.estimated_rows() * FILTER_COST,
+                    source: Box::new(source_plan),
+                    target,
+                })
+            }
+
             // Aggregation & Grouping
             Some(query::Kind::Count(count_query)) => self.build_count_query(count_query),
 
             // Document Manipulation
             Some(query::Kind::Pluck(pluck_query)) => self.build_pluck_query(pluck_query),
             Some(query::Kind::Without(without_query)) => self.build_without_query(without_query),
+            Some(query::Kind::Merge(merge)) => self.build_document_function(
+                merge.source.as_deref(),
+                document_op::Operator::Merge,
+                merge.values.clone(),
+                vec![],
+            ),
+            Some(query::Kind::HasFields(has_fields)) => self.build_document_function(
+                has_fields.source.as_deref(),
+                document_op::Operator::HasFields,
+                vec![],
+                has_fields.fields.clone(),
+            ),
+            Some(query::Kind::WithFields(with_fields)) => self.build_document_function(
+                with_fields.source.as_deref(),
+                document_op::Operator::WithFields,
+                vec![],
+                with_fields.fields.clone(),
+            ),
+            Some(query::Kind::Keys(keys)) => self.build_document_function(
+                keys.source.as_deref(),
+                document_op::Operator::Keys,
+                vec![],
+                vec![],
+            ),
+            Some(query::Kind::Values(values)) => self.build_document_function(
+                values.source.as_deref(),
+                document_op::Operator::Values,
+                vec![],
+                vec![],
+            ),
+            Some(query::Kind::Default(default)) => {
+                let value =
+                    default
+                        .value
+                        .as_deref()
+                        .cloned()
+                        .ok_or(PlanError::InvalidExpression(
+                            "Default missing value".to_string(),
+                        ))?;
+                self.build_document_function(
+                    default.source.as_deref(),
+                    document_op::Operator::Default,
+                    vec![value],
+                    vec![],
+                )
+            }
+            Some(query::Kind::TypeOf(type_of)) => self.build_document_function(
+                type_of.source.as_deref(),
+                document_op::Operator::TypeOf,
+                vec![],
+                vec![],
+            ),
+            Some(query::Kind::CoerceTo(coerce_to)) => {
+                let target = Expression {
+                    expr: Some(expression::Expr::Literal(Datum {
+                        value: Some(datum::Value::String(coerce_to.r#type.clone())),
+                    })),
+                };
+                self.build_document_function(
+                    coerce_to.source.as_deref(),
+                    document_op::Operator::CoerceTo,
+                    vec![target],
+                    vec![],
+                )
+            }
 
             // Schema & Data Modeling
             Some(query::Kind::DatabaseCreate(create_db)) => Ok(PlanNode::CreateDatabase {
@@ -69,12 +353,19 @@ impl PlanBuilder {
             Some(query::Kind::DatabaseList(_list_db)) => Ok(PlanNode::ListDatabases {
                 cursor: self.cursor_context.clone(),
                 cost: 1.0,
+                estimated_rows: self
+                    .statistics
+                    .as_ref()
+                    .and_then(|s| s.database_count())
+                    .map_or(DEFAULT_DATABASE_COUNT, |count| count as f64),
             }),
             Some(query::Kind::TableCreate(create_table)) => Ok(PlanNode::CreateTable {
                 table_ref: create_table
                     .table
                     .clone()
                     .ok_or(PlanError::MissingTableReference)?,
+                options: create_table.options,
+                shards: create_table.shards,
                 cost: 1.0,
             }),
             Some(query::Kind::TableDrop(drop_table)) => Ok(PlanNode::DropTable {
@@ -84,13 +375,102 @@ impl PlanBuilder {
                     .ok_or(PlanError::MissingTableReference)?,
                 cost: 1.0,
             }),
-            Some(query::Kind::TableList(list_tables)) => Ok(PlanNode::ListTables {
-                database_ref: list_tables.database.clone().unwrap_or(DatabaseRef {
+            Some(query::Kind::TableList(list_tables)) => {
+                let database_ref = list_tables.database.clone().unwrap_or(DatabaseRef {
                     name: "default".to_string(),
-                }),
-                cursor: self.cursor_context.clone(),
+                });
+                let estimated_rows = self
+                    .statistics
+                    .as_ref()
+                    .and_then(|s| s.table_count(&database_ref.name))
+                    .map_or(DEFAULT_TABLE_COUNT, |count| count as f64);
+                Ok(PlanNode::ListTables {
+                    database_ref,
+                    cursor: self.cursor_context.clone(),
+                    cost: 1.0,
+                    estimated_rows,
+                })
+            }
+            Some(query::Kind::Analyze(analyze)) => {
+                let table_ref = analyze
+                    .table
+                    .clone()
+                    .ok_or(PlanError::MissingTableReference)?;
+                let cost = self
+                    .table_statistics(&table_ref)
+                    .map_or(DEFAULT_TABLE_ROWS, |stats| stats.row_count as f64)
+                    * FILTER_COST;
+                Ok(PlanNode::Analyze {
+                    table_ref,
+                    sample_size: analyze.sample_size,
+                    cost,
+                })
+            }
+            Some(query::Kind::Rebalance(rebalance)) => {
+                let table_ref = rebalance
+                    .table
+                    .clone()
+                    .ok_or(PlanError::MissingTableReference)?;
+                let cost = self
+                    .table_statistics(&table_ref)
+                    .map_or(DEFAULT_TABLE_ROWS, |stats| stats.row_count as f64)
+                    * FILTER_COST;
+                Ok(PlanNode::Rebalance {
+                    table_ref,
+                    shards: rebalance.shards,
+                    cost,
+                })
+            }
+            Some(query::Kind::IndexCreate(create_index)) => {
+                let table_ref = create_index
+                    .table
+                    .clone()
+                    .ok_or(PlanError::MissingTableReference)?;
+                let kind = create_index.kind.clone().ok_or_else(|| {
+                    PlanError::InvalidExpression("Index kind is missing".to_string())
+                })?;
+                let cost = self
+                    .table_statistics(&table_ref)
+                    .map_or(DEFAULT_TABLE_ROWS, |stats| stats.row_count as f64)
+                    * FILTER_COST;
+                Ok(PlanNode::CreateIndex {
+                    table_ref,
+                    name: create_index.name.clone(),
+                    kind,
+                    cost,
+                })
+            }
+            Some(query::Kind::IndexDrop(drop_index)) => Ok(PlanNode::DropIndex {
+                table_ref: drop_index
+                    .table
+                    .clone()
+                    .ok_or(PlanError::MissingTableReference)?,
+                name: drop_index.name.clone(),
+                cost: 1.0,
+            }),
+            Some(query::Kind::IndexList(list_indexes)) => Ok(PlanNode::ListIndexes {
+                table_ref: list_indexes
+                    .table
+                    .clone()
+                    .ok_or(PlanError::MissingTableReference)?,
                 cost: 1.0,
             }),
+            Some(query::Kind::ReplicationStatus(_)) => {
+                Ok(PlanNode::ReplicationStatus { cost: 1.0 })
+            }
+            Some(query::Kind::ClusterStatus(_)) => Ok(PlanNode::ClusterStatus { cost: 1.0 }),
+            Some(query::Kind::ClusterMembership(membership)) => Ok(PlanNode::ChangeMembership {
+                change: membership.change.clone().ok_or_else(|| {
+                    PlanError::InvalidExpression("Membership change is missing".to_string())
+                })?,
+                cost: 1.0,
+            }),
+            Some(query::Kind::ClusterTransferLeadership(transfer)) => {
+                Ok(PlanNode::TransferLeadership {
+                    node_id: transfer.node_id,
+                    cost: 1.0,
+                })
+            }
 
             // Control & Execution
             Some(query::Kind::Expression(expr)) => self.build_expression_plan(expr),
@@ -136,11 +516,14 @@ impl PlanBuilder {
             .table
             .clone()
             .ok_or(PlanError::MissingTableReference)?;
-        let estimated_rows = 1000.0; // Default estimate
+        let estimated_rows = self
+            .table_statistics(&table_ref)
+            .map_or(DEFAULT_TABLE_ROWS, |stats| stats.row_count as f64);
         Ok(PlanNode::TableScan {
             table_ref,
             cursor: self.cursor_context.clone(),
             filter: None,
+            projection: None,
             cost: TABLE_SCAN_COST,
             estimated_rows,
         })
@@ -182,25 +565,50 @@ impl PlanBuilder {
         let source_plan = self.build_query_internal(get_all_query.source.as_ref().ok_or(
             PlanError::InvalidExpression("GetAll missing source".to_string()),
         )?)?;
+        let mut values = get_all_query.keys.clone();
+        // Keys using parameters are kept as expressions, for a prepared query to bind
+        let mut key_expressions = Vec::new();
+        for key in &get_all_query.key_expressions {
+            if self.is_constant_expression(key) {
+                values.push(self.evaluate_constant_expression(key)?);
+            } else if is_parameterized_expression(key) {
+                key_expressions.push(key.clone());
+            } else {
+                return Err(PlanError::InvalidExpression(
+                    "GetAll key expressions must be constant or use parameters".to_string(),
+                ));
+            }
+        }
+        let key_count = values.len() + key_expressions.len();
 
         if let PlanNode::TableScan { table_ref, .. } = source_plan {
+            // With an index the values are looked up in it rather than used as keys
+            if !get_all_query.index.is_empty() {
+                let estimated_rows = self
+                    .table_statistics(&table_ref)
+                    .map_or(DEFAULT_TABLE_ROWS, |stats| stats.row_count as f64)
+                    * INDEX_EQ_SELECTIVITY
+                    * key_count as f64;
+                return Ok(PlanNode::GetAllByIndex {
+                    table_ref,
+                    index: get_all_query.index.clone(),
+                    values,
+                    value_expressions: key_expressions,
+                    cost: GET_COST * estimated_rows,
+                    estimated_rows,
+                });
+            }
+
             // Convert Datum keys to strings
-            let keys: Result<Vec<String>, PlanError> = get_all_query
-                .keys
-                .iter()
-                .map(|key| match &key.value {
-                    Some(datum::Value::String(s)) => Ok(s.clone()),
-                    Some(datum::Value::Int(i)) => Ok(i.to_string()),
-                    _ => Err(PlanError::InvalidExpression("Invalid key type".to_string())),
-                })
-                .collect();
+            let keys: Result<Vec<String>, PlanError> = values.iter().map(document_key).collect();
 
             let keys = keys?;
-            let cost = GET_COST * keys.len() as f64;
+            let cost = GET_COST * key_count as f64;
 
             Ok(PlanNode::GetAll {
                 table_ref,
                 keys,
+                key_expressions,
                 cursor: self.cursor_context.clone(),
                 cost,
             })
@@ -222,6 +630,7 @@ impl PlanBuilder {
             Ok(PlanNode::Insert {
                 table_ref,
                 documents: insert_query.documents.clone(),
+                durability: insert_query.durability(),
                 cost,
             })
         } else {
@@ -236,16 +645,22 @@ impl PlanBuilder {
         let source_plan = self.build_query_internal(update_query.source.as_ref().ok_or(
             PlanError::InvalidExpression("Update missing source".to_string()),
         )?)?;
-        let patch = update_query
-            .patch
-            .clone()
-            .ok_or(PlanError::InvalidExpression(
-                "Update missing patch".to_string(),
-            ))?;
+        // Array operations make the patch optional
+        let patch = match &update_query.patch {
+            Some(patch) => patch.clone(),
+            None if !update_query.operations.is_empty() => DatumObject::default(),
+            None => {
+                return Err(PlanError::InvalidExpression(
+                    "Update missing patch".to_string(),
+                ));
+            }
+        };
         let cost = source_plan.cost() + source_plan.estimated_rows() * 0.5;
         Ok(PlanNode::Update {
             source: Box::new(source_plan),
             patch,
+            operations: update_query.operations.clone(),
+            durability: update_query.durability(),
             cost,
         })
     }
@@ -258,6 +673,7 @@ impl PlanBuilder {
         let cost = source_plan.cost() + source_plan.estimated_rows() * 0.3;
         Ok(PlanNode::Delete {
             source: Box::new(source_plan),
+            durability: delete_query.durability(),
             cost,
         })
     }
@@ -273,8 +689,37 @@ impl PlanBuilder {
             .ok_or(PlanError::InvalidExpression(
                 "Filter missing predicate".to_string(),
             ))?;
-        let selectivity = self.estimate_selectivity(&predicate);
+        let stats = Self::source_table(&source_plan).and_then(|t| self.table_statistics(t));
+        let selectivity = self.estimate_selectivity(&predicate, stats.as_ref());
         let cost = source_plan.cost() + source_plan.estimated_rows() * 0.1;
+
+        // Every subquery is planned once here. A correlated one keeps its variables,
+        // bound to each row it runs for, and is costed per row
+        let subqueries = table_subqueries(&predicate);
+        if !subqueries.is_empty() {
+            let mut cost = cost;
+            let mut planned: Vec<(Query, PlanNode)> = Vec::new();
+            let mut slots = Vec::new();
+            for query in subqueries {
+                if let Some(slot) = planned.iter().position(|(planned, _)| *planned == query) {
+                    slots.push(slot);
+                    continue;
+                }
+                let plan = self.build_query_internal(&query)?;
+                cost += subquery_cost(&query, &plan, source_plan.estimated_rows());
+                slots.push(planned.len());
+                planned.push((query, plan));
+            }
+            return Ok(PlanNode::SubqueryFilter {
+                source: Box::new(source_plan),
+                predicate: *predicate,
+                subqueries: planned,
+                slots,
+                cost,
+                selectivity,
+            });
+        }
+
         Ok(PlanNode::Filter {
             source: Box::new(source_plan),
             predicate: *predicate,
@@ -290,22 +735,22 @@ impl PlanBuilder {
         )?)?;
         let n = source_plan.estimated_rows();
         let cost = source_plan.cost() + n * n.log2().max(1.0) * 0.01;
+        Ok(PlanNode::OrderBy {
+            source: Box::new(source_plan),
+            fields: Self::order_by_fields(&order_by_query.fields),
+            cost,
+        })
+    }
 
-        // Convert SortField to OrderByField
-        let fields = order_by_query
-            .fields
+    /// Convert SortField to OrderByField
+    fn order_by_fields(fields: &[SortField]) -> Vec<OrderByField> {
+        fields
             .iter()
             .map(|f| OrderByField {
                 field_name: f.field_name.clone(),
                 ascending: f.direction == SortDirection::Asc as i32,
             })
-            .collect();
-
-        Ok(PlanNode::OrderBy {
-            source: Box::new(source_plan),
-            fields,
-            cost,
-        })
+            .collect()
     }
 
     /// Build a plan for a limit query
@@ -334,6 +779,32 @@ impl PlanBuilder {
         })
     }
 
+    /// Build a plan for a union query
+    fn build_union_query(&mut self, union_query: &Union) -> PlanResult<PlanNode> {
+        let source_plan = self.build_sequence_source(union_query.source.as_deref(), "Union")?;
+        let other_plan = self.build_query_internal(union_query.other.as_ref().ok_or(
+            PlanError::InvalidExpression("Union missing other sequence".to_string()),
+        )?)?;
+        let cost = source_plan.cost() + other_plan.cost();
+        Ok(PlanNode::Union {
+            source: Box::new(source_plan),
+            other: Box::new(other_plan),
+            interleave: Self::order_by_fields(&union_query.interleave),
+            cost,
+        })
+    }
+
+    /// Build the plan of the sequence a sequence operation works on
+    fn build_sequence_source(
+        &mut self,
+        source: Option<&Query>,
+        operation: &str,
+    ) -> PlanResult<PlanNode> {
+        self.build_query_internal(source.ok_or(PlanError::InvalidExpression(format!(
+            "{operation} missing source"
+        )))?)
+    }
+
     /// Build a plan for a count query
     fn build_count_query(&mut self, count_query: &Count) -> PlanResult<PlanNode> {
         let source_plan = self.build_query_internal(count_query.source.as_ref().ok_or(
@@ -372,6 +843,27 @@ impl PlanBuilder {
         })
     }
 
+    /// Build a plan applying a document function to the value or documents of a source
+    fn build_document_function(
+        &mut self,
+        source: Option<&Query>,
+        op: document_op::Operator,
+        args: Vec<Expression>,
+        fields: Vec<FieldRef>,
+    ) -> PlanResult<PlanNode> {
+        let source_plan = self.build_query_internal(source.ok_or(
+            PlanError::InvalidExpression(format!("{op:?} missing source")),
+        )?)?;
+        let cost = source_plan.cost();
+        Ok(PlanNode::DocumentFunction {
+            source: Box::new(source_plan),
+            op,
+            args,
+            fields,
+            cost,
+        })
+    }
+
     /// Check if an expression is constant
     fn is_constant_expression(&mut self, expr: &Expression) -> bool {
         // Check cache first
@@ -458,20 +950,20 @@ impl PlanBuilder {
         use datum::Value;
 
         let result = match (op, &left.value, &right.value) {
-            // Comparison operations
-            (binary_op::Operator::Eq, Some(a), Some(b)) => Some(Value::Bool(a == b)),
-            (binary_op::Operator::Ne, Some(a), Some(b)) => Some(Value::Bool(a != b)),
-            (binary_op::Operator::Lt, Some(Value::Int(a)), Some(Value::Int(b))) => {
-                Some(Value::Bool(a < b))
+            // Comparison operations, in the same order as the evaluator's
+            (binary_op::Operator::Eq, _, _) => Some(Value::Bool(datums_equal(left, right))),
+            (binary_op::Operator::Ne, _, _) => Some(Value::Bool(!datums_equal(left, right))),
+            (binary_op::Operator::Lt, _, _) => {
+                Some(Value::Bool(compare_values(left, right).is_lt()))
             }
-            (binary_op::Operator::Le, Some(Value::Int(a)), Some(Value::Int(b))) => {
-                Some(Value::Bool(a <= b))
+            (binary_op::Operator::Le, _, _) => {
+                Some(Value::Bool(compare_values(left, right).is_le()))
             }
-            (binary_op::Operator::Gt, Some(Value::Int(a)), Some(Value::Int(b))) => {
-                Some(Value::Bool(a > b))
+            (binary_op::Operator::Gt, _, _) => {
+                Some(Value::Bool(compare_values(left, right).is_gt()))
             }
-            (binary_op::Operator::Ge, Some(Value::Int(a)), Some(Value::Int(b))) => {
-                Some(Value::Bool(a >= b))
+            (binary_op::Operator::Ge, _, _) => {
+                Some(Value::Bool(compare_values(left, right).is_ge()))
             }
 
             // Logical operations
@@ -482,6 +974,21 @@ impl PlanBuilder {
                 Some(Value::Bool(*a || *b))
             }
 
+            // Arithmetic operations
+            (
+                binary_op::Operator::Add
+                | binary_op::Operator::Sub
+                | binary_op::Operator::Mul
+                | binary_op::Operator::Div
+                | binary_op::Operator::Mod,
+                _,
+                _,
+            ) => {
+                return ExpressionEvaluator::new()
+                    .perform_binary_operation(left, op, right)
+                    .map_err(|e| PlanError::InvalidConstant(e.to_string()));
+            }
+
             _ => None,
         };
 
@@ -504,9 +1011,31 @@ impl PlanBuilder {
         Ok(Datum { value: result })
     }
 
-    /// Estimate the selectivity of a predicate (0.0 to 1.0)
-    #[allow(clippy::only_used_in_recursion)]
-    fn estimate_selectivity(&self, expr: &Expression) -> f64 {
+    /// Find the table a plan reads its documents from
+    fn source_table(plan: &PlanNode) -> Option<&TableRef> {
+        match plan {
+            PlanNode::TableScan { table_ref, .. }
+            | PlanNode::GetAll { table_ref, .. }
+            | PlanNode::GetAllByIndex { table_ref, .. }
+            | PlanNode::Between { table_ref, .. }
+            | PlanNode::Search { table_ref, .. }
+            | PlanNode::GetIntersecting { table_ref, .. }
+            | PlanNode::GetNearest { table_ref, .. }
+            | PlanNode::Nearest { table_ref, .. } => Some(table_ref),
+            PlanNode::Filter { source, .. }
+            | PlanNode::SubqueryFilter { source, .. }
+            | PlanNode::OrderBy { source, .. }
+            | PlanNode::Limit { source, .. }
+            | PlanNode::Skip { source, .. }
+            | PlanNode::Slice { source, .. }
+            | PlanNode::Sample { source, .. } => Self::source_table(source),
+            _ => None,
+        }
+    }
+
+    /// Estimate the selectivity of a predicate (0.0 to 1.0), using the statistics of
+    /// the filtered table where they cover the compared field
+    pub fn estimate_selectivity(&self, expr: &Expression, stats: Option<&TableStatistics>) -> f64 {
         match &expr.expr {
             Some(expression::Expr::Literal(lit)) => match &lit.value {
                 Some(datum::Value::Bool(true)) => 1.0,
@@ -515,6 +1044,13 @@ impl PlanBuilder {
             },
             Some(expression::Expr::Binary(bin)) => {
                 let op = binary_op::Operator::try_from(bin.op).ok();
+                let field_selectivity = op
+                    .zip(stats)
+                    .and_then(|(op, stats)| Self::comparison_selectivity(bin, op, stats));
+                if let Some(selectivity) = field_selectivity {
+                    return selectivity;
+                }
+
                 match op {
                     Some(binary_op::Operator::Eq) => 0.1,
                     Some(binary_op::Operator::Ne) => 0.9,
@@ -526,12 +1062,12 @@ impl PlanBuilder {
                         let left_sel = bin
                             .left
                             .as_ref()
-                            .map(|l| self.estimate_selectivity(l))
+                            .map(|l| self.estimate_selectivity(l, stats))
                             .unwrap_or(0.5);
                         let right_sel = bin
                             .right
                             .as_ref()
-                            .map(|r| self.estimate_selectivity(r))
+                            .map(|r| self.estimate_selectivity(r, stats))
                             .unwrap_or(0.5);
                         left_sel * right_sel
                     }
@@ -539,12 +1075,12 @@ impl PlanBuilder {
                         let left_sel = bin
                             .left
                             .as_ref()
-                            .map(|l| self.estimate_selectivity(l))
+                            .map(|l| self.estimate_selectivity(l, stats))
                             .unwrap_or(0.5);
                         let right_sel = bin
                             .right
                             .as_ref()
-                            .map(|r| self.estimate_selectivity(r))
+                            .map(|r| self.estimate_selectivity(r, stats))
                             .unwrap_or(0.5);
                         left_sel + right_sel - (left_sel * right_sel)
                     }
@@ -557,7 +1093,7 @@ impl PlanBuilder {
                     Some(unary_op::Operator::Not) => un
                         .expr
                         .as_ref()
-                        .map(|e| 1.0 - self.estimate_selectivity(e))
+                        .map(|e| 1.0 - self.estimate_selectivity(e, stats))
                         .unwrap_or(0.5),
                     _ => 0.5,
                 }
@@ -566,6 +1102,57 @@ impl PlanBuilder {
         }
     }
 
+    /// Estimate the selectivity of comparing a top-level field with a literal from the
+    /// field's statistics
+    fn comparison_selectivity(
+        bin: &BinaryOp,
+        op: binary_op::Operator,
+        stats: &TableStatistics,
+    ) -> Option<f64> {
+        use binary_op::Operator;
+
+        let (left, right) = (bin.left.as_deref()?, bin.right.as_deref()?);
+        // Normalize to `field <op> literal`, mirroring the operator if needed
+        let (field, literal, op) = match (&left.expr, &right.expr) {
+            (Some(expression::Expr::Field(f)), Some(expression::Expr::Literal(l))) => (f, l, op),
+            (Some(expression::Expr::Literal(l)), Some(expression::Expr::Field(f))) => {
+                let mirrored = match op {
+                    Operator::Lt => Operator::Gt,
+                    Operator::Le => Operator::Ge,
+                    Operator::Gt => Operator::Lt,
+                    Operator::Ge => Operator::Le,
+                    other => other,
+                };
+                (f, l, mirrored)
+            }
+            _ => return None,
+        };
+        let [field] = field.path.as_slice() else {
+            return None;
+        };
+
+        let number = match &literal.value {
+            Some(
+                value @ (datum::Value::Int(_) | datum::Value::Float(_) | datum::Value::Decimal(_)),
+            ) => Some(number_to_f64(value)),
+            _ => None,
+        };
+        let selectivity = match op {
+            Operator::Eq => stats.eq_selectivity(field)?,
+            Operator::Ne => 1.0 - stats.eq_selectivity(field)?,
+            Operator::Lt | Operator::Le => stats.range_selectivity(field, number?, true)?,
+            Operator::Gt | Operator::Ge => stats.range_selectivity(field, number?, false)?,
+            Operator::And
+            | Operator::Or
+            | Operator::Add
+            | Operator::Sub
+            | Operator::Mul
+            | Operator::Div
+            | Operator::Mod => return None,
+        };
+        Some(selectivity.clamp(0.0, 1.0))
+    }
+
     /// Get the internal cache
     pub fn cache(&self) -> &PlanCache {
         &self.cache
@@ -576,3 +1163,37 @@ impl PlanBuilder {
         &mut self.cache
     }
 }
+
+/// The document key a GetAll key stands for
+pub(crate) fn document_key(key: &Datum) -> PlanResult<String> {
+    match &key.value {
+        Some(datum::Value::String(s)) => Ok(s.clone()),
+        Some(datum::Value::Int(i)) => Ok(i.to_string()),
+        _ => Err(PlanError::InvalidExpression("Invalid key type".to_string())),
+    }
+}
+
+/// Cost of running a planned subquery for a filter over the given number of rows: once
+/// if it is uncorrelated, for every row otherwise
+pub(crate) fn subquery_cost(query: &Query, plan: &PlanNode, rows: f64) -> f64 {
+    if is_correlated(query) {
+        rows * plan.cost()
+    } else {
+        plan.cost()
+    }
+}
+
+/// Whether an expression is built only from literals and variables, which are
+/// parameters where a constant is expected
+fn is_parameterized_expression(expr: &Expression) -> bool {
+    match &expr.expr {
+        Some(expression::Expr::Literal(_)) | Some(expression::Expr::Variable(_)) => true,
+        Some(expression::Expr::Binary(bin)) => [&bin.left, &bin.right]
+            .into_iter()
+            .all(|operand| operand.as_deref().is_some_and(is_parameterized_expression)),
+        Some(expression::Expr::Unary(un)) => {
+            un.expr.as_deref().is_some_and(is_parameterized_expression)
+        }
+        _ => false,
+    }
+}
@@ -13,6 +13,10 @@ pub enum PlanError {
     InvalidConstant(String),
     /// Optimization failed
     OptimizationFailed(String),
+    /// No prepared query has the handle, or it was evicted
+    UnknownPreparedQuery(String),
+    /// A parameter of a prepared query was given no value
+    UnboundParameter(String),
 }
 
 impl fmt::Display for PlanError {
@@ -23,6 +27,10 @@ impl fmt::Display for PlanError {
             PlanError::MissingTableReference => write!(f, "Missing table reference"),
             PlanError::InvalidConstant(msg) => write!(f, "Invalid constant: {msg}"),
             PlanError::OptimizationFailed(msg) => write!(f, "Optimization failed: {msg}"),
+            PlanError::UnknownPreparedQuery(handle) => {
+                write!(f, "Unknown prepared query: {handle}")
+            }
+            PlanError::UnboundParameter(name) => write!(f, "Unbound parameter: {name}"),
         }
     }
 }
@@ -92,10 +92,28 @@ impl Explainer {
             | PlanNode::Limit { source, .. }
             | PlanNode::Skip { source, .. }
             | PlanNode::Count { source, .. }
+            | PlanNode::Slice { source, .. }
+            | PlanNode::Nth { source, .. }
+            | PlanNode::Sample { source, .. }
+            | PlanNode::IsEmpty { source, .. }
+            | PlanNode::OffsetsOf { source, .. }
             | PlanNode::Pluck { source, .. }
-            | PlanNode::Without { source, .. } => {
+            | PlanNode::Without { source, .. }
+            | PlanNode::DocumentFunction { source, .. } => {
                 self.explain_node(source, depth + 1, nodes);
             }
+            PlanNode::Union { source, other, .. } => {
+                self.explain_node(source, depth + 1, nodes);
+                self.explain_node(other, depth + 1, nodes);
+            }
+            PlanNode::SubqueryFilter {
+                source, subqueries, ..
+            } => {
+                self.explain_node(source, depth + 1, nodes);
+                for (_, plan) in subqueries {
+                    self.explain_node(plan, depth + 1, nodes);
+                }
+            }
             PlanNode::Subquery { query, .. } => {
                 self.explain_node(query, depth + 1, nodes);
             }
@@ -136,6 +154,7 @@ impl Explainer {
                 table_ref,
                 cursor,
                 filter,
+                projection,
                 ..
             } => {
                 let mut props = vec![(
@@ -164,11 +183,23 @@ impl Explainer {
                     props.push(("Filter".to_string(), self.describe_predicate(filter)));
                 }
 
+                if let Some(projection) = projection {
+                    props.push((
+                        "Projection".to_string(),
+                        projection
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    ));
+                }
+
                 ("TableScan".to_string(), props)
             }
-            PlanNode::CreateTable { table_ref, .. } => (
-                "CreateTable".to_string(),
-                vec![(
+            PlanNode::CreateTable {
+                table_ref, shards, ..
+            } => {
+                let mut props = vec![(
                     "Table".to_string(),
                     format!(
                         "{}.{}",
@@ -179,8 +210,14 @@ impl Explainer {
                             .unwrap_or("default"),
                         table_ref.name
                     ),
-                )],
-            ),
+                )];
+
+                if let Some(shards) = shards {
+                    props.push(("Shards".to_string(), shards.to_string()));
+                }
+
+                ("CreateTable".to_string(), props)
+            }
             PlanNode::DropTable { table_ref, .. } => (
                 "DropTable".to_string(),
                 vec![(
@@ -214,6 +251,186 @@ impl Explainer {
 
                 ("ListTables".to_string(), props)
             }
+            PlanNode::Analyze {
+                table_ref,
+                sample_size,
+                ..
+            } => {
+                let mut props = vec![(
+                    "Table".to_string(),
+                    format!(
+                        "{}.{}",
+                        table_ref
+                            .database
+                            .as_ref()
+                            .map(|d| d.name.as_str())
+                            .unwrap_or("default"),
+                        table_ref.name
+                    ),
+                )];
+
+                if let Some(sample_size) = sample_size {
+                    props.push(("SampleSize".to_string(), sample_size.to_string()));
+                }
+
+                ("Analyze".to_string(), props)
+            }
+            PlanNode::Rebalance {
+                table_ref, shards, ..
+            } => {
+                let mut props = vec![(
+                    "Table".to_string(),
+                    format!(
+                        "{}.{}",
+                        table_ref
+                            .database
+                            .as_ref()
+                            .map(|d| d.name.as_str())
+                            .unwrap_or("default"),
+                        table_ref.name
+                    ),
+                )];
+
+                if let Some(shards) = shards {
+                    props.push(("Shards".to_string(), shards.to_string()));
+                }
+
+                ("Rebalance".to_string(), props)
+            }
+            PlanNode::CreateIndex {
+                table_ref,
+                name,
+                kind,
+                ..
+            } => {
+                let mut props = vec![
+                    (
+                        "Table".to_string(),
+                        format!(
+                            "{}.{}",
+                            table_ref
+                                .database
+                                .as_ref()
+                                .map(|d| d.name.as_str())
+                                .unwrap_or("default"),
+                            table_ref.name
+                        ),
+                    ),
+                    ("Index".to_string(), name.clone()),
+                ];
+
+                match kind {
+                    index_create::Kind::FullText(full_text) => {
+                        props.push(("Kind".to_string(), "fulltext".to_string()));
+                        let fields: Vec<_> = full_text
+                            .fields
+                            .iter()
+                            .map(|field| field.path.join("."))
+                            .collect();
+                        props.push(("Fields".to_string(), fields.join(", ")));
+                        if full_text.stemming {
+                            props.push(("Stemming".to_string(), "true".to_string()));
+                        }
+                    }
+                    index_create::Kind::Geo(geo) => {
+                        props.push(("Kind".to_string(), "geo".to_string()));
+                        if let Some(field) = &geo.field {
+                            props.push(("Fields".to_string(), field.path.join(".")));
+                        }
+                    }
+                    index_create::Kind::Vector(vector) => {
+                        props.push(("Kind".to_string(), "vector".to_string()));
+                        if let Some(field) = &vector.field {
+                            props.push(("Fields".to_string(), field.path.join(".")));
+                        }
+                        props.push(("Dimension".to_string(), vector.dimension.to_string()));
+                        props.push((
+                            "Metric".to_string(),
+                            vector.metric().as_str_name().to_lowercase(),
+                        ));
+                    }
+                    index_create::Kind::Secondary(secondary) => {
+                        props.push(("Kind".to_string(), "secondary".to_string()));
+                        if let Some(expression) = &secondary.expression {
+                            props.push((
+                                "Expression".to_string(),
+                                self.describe_predicate(expression),
+                            ));
+                        } else {
+                            let fields: Vec<_> = secondary
+                                .fields
+                                .iter()
+                                .map(|field| field.path.join("."))
+                                .collect();
+                            props.push(("Fields".to_string(), fields.join(", ")));
+                        }
+                        if secondary.multi {
+                            props.push(("Multi".to_string(), "true".to_string()));
+                        }
+                        if secondary.unique {
+                            props.push(("Unique".to_string(), "true".to_string()));
+                        }
+                    }
+                }
+
+                ("CreateIndex".to_string(), props)
+            }
+            PlanNode::DropIndex {
+                table_ref, name, ..
+            } => (
+                "DropIndex".to_string(),
+                vec![
+                    (
+                        "Table".to_string(),
+                        format!(
+                            "{}.{}",
+                            table_ref
+                                .database
+                                .as_ref()
+                                .map(|d| d.name.as_str())
+                                .unwrap_or("default"),
+                            table_ref.name
+                        ),
+                    ),
+                    ("Index".to_string(), name.clone()),
+                ],
+            ),
+            PlanNode::ListIndexes { table_ref, .. } => (
+                "ListIndexes".to_string(),
+                vec![(
+                    "Table".to_string(),
+                    format!(
+                        "{}.{}",
+                        table_ref
+                            .database
+                            .as_ref()
+                            .map(|d| d.name.as_str())
+                            .unwrap_or("default"),
+                        table_ref.name
+                    ),
+                )],
+            ),
+            PlanNode::ReplicationStatus { .. } => ("ReplicationStatus".to_string(), vec![]),
+            PlanNode::ClusterStatus { .. } => ("ClusterStatus".to_string(), vec![]),
+            PlanNode::ChangeMembership { change, .. } => {
+                let props = match change {
+                    cluster_membership::Change::Add(member) => vec![
+                        ("Add".to_string(), member.id.to_string()),
+                        ("Address".to_string(), member.address.clone()),
+                    ],
+                    cluster_membership::Change::Remove(id) => {
+                        vec![("Remove".to_string(), id.to_string())]
+                    }
+                };
+                ("ChangeMembership".to_string(), props)
+            }
+            PlanNode::TransferLeadership { node_id, .. } => {
+                let props = node_id
+                    .iter()
+                    .map(|id| ("Target".to_string(), id.to_string()))
+                    .collect();
+                ("TransferLeadership".to_string(), props)
+            }
             PlanNode::Get { table_ref, key, .. } => (
                 "Get".to_string(),
                 vec![
@@ -235,6 +452,7 @@ impl Explainer {
             PlanNode::GetAll {
                 table_ref,
                 keys,
+                key_expressions,
                 cursor,
                 ..
             } => {
@@ -251,7 +469,10 @@ impl Explainer {
                             table_ref.name
                         ),
                     ),
-                    ("Keys".to_string(), format!("{} keys", keys.len())),
+                    (
+                        "Keys".to_string(),
+                        format!("{} keys", keys.len() + key_expressions.len()),
+                    ),
                 ];
 
                 if let Some(cursor) = cursor {
@@ -265,6 +486,177 @@ impl Explainer {
 
                 ("GetAll".to_string(), props)
             }
+            PlanNode::GetAllByIndex {
+                table_ref,
+                index,
+                values,
+                ..
+            } => (
+                "GetAllByIndex".to_string(),
+                vec![
+                    (
+                        "Table".to_string(),
+                        format!(
+                            "{}.{}",
+                            table_ref
+                                .database
+                                .as_ref()
+                                .map(|d| d.name.as_str())
+                                .unwrap_or("default"),
+                            table_ref.name
+                        ),
+                    ),
+                    ("Index".to_string(), index.clone()),
+                    ("Values".to_string(), format!("{} values", values.len())),
+                ],
+            ),
+            PlanNode::Between {
+                table_ref,
+                index,
+                lower,
+                upper,
+                ..
+            } => {
+                let mut props = vec![
+                    (
+                        "Table".to_string(),
+                        format!(
+                            "{}.{}",
+                            table_ref
+                                .database
+                                .as_ref()
+                                .map(|d| d.name.as_str())
+                                .unwrap_or("default"),
+                            table_ref.name
+                        ),
+                    ),
+                    ("Index".to_string(), index.clone()),
+                ];
+                if let Some(lower) = lower {
+                    props.push(("Lower".to_string(), lower.to_string()));
+                }
+                if let Some(upper) = upper {
+                    props.push(("Upper".to_string(), upper.to_string()));
+                }
+
+                ("Between".to_string(), props)
+            }
+            PlanNode::Search {
+                table_ref,
+                index,
+                query,
+                ..
+            } => (
+                "Search".to_string(),
+                vec![
+                    (
+                        "Table".to_string(),
+                        format!(
+                            "{}.{}",
+                            table_ref
+                                .database
+                                .as_ref()
+                                .map(|d| d.name.as_str())
+                                .unwrap_or("default"),
+                            table_ref.name
+                        ),
+                    ),
+                    ("Index".to_string(), index.clone()),
+                    ("Query".to_string(), query.clone()),
+                ],
+            ),
+            PlanNode::GetIntersecting {
+                table_ref,
+                index,
+                geometry,
+                ..
+            } => (
+                "GetIntersecting".to_string(),
+                vec![
+                    (
+                        "Table".to_string(),
+                        format!(
+                            "{}.{}",
+                            table_ref
+                                .database
+                                .as_ref()
+                                .map(|d| d.name.as_str())
+                                .unwrap_or("default"),
+                            table_ref.name
+                        ),
+                    ),
+                    ("Index".to_string(), index.clone()),
+                    ("Geometry".to_string(), geometry.to_string()),
+                ],
+            ),
+            PlanNode::GetNearest {
+                table_ref,
+                index,
+                point,
+                max_dist,
+                max_results,
+                ..
+            } => {
+                let mut props = vec![
+                    (
+                        "Table".to_string(),
+                        format!(
+                            "{}.{}",
+                            table_ref
+                                .database
+                                .as_ref()
+                                .map(|d| d.name.as_str())
+                                .unwrap_or("default"),
+                            table_ref.name
+                        ),
+                    ),
+                    ("Index".to_string(), index.clone()),
+                    ("Point".to_string(), point.to_string()),
+                ];
+                if let Some(max_dist) = max_dist {
+                    props.push(("MaxDist".to_string(), max_dist.to_string()));
+                }
+                if let Some(max_results) = max_results {
+                    props.push(("MaxResults".to_string(), max_results.to_string()));
+                }
+
+                ("GetNearest".to_string(), props)
+            }
+            PlanNode::Nearest {
+                table_ref,
+                index,
+                vector,
+                k,
+                filter,
+                probes,
+                ..
+            } => {
+                let mut props = vec![
+                    (
+                        "Table".to_string(),
+                        format!(
+                            "{}.{}",
+                            table_ref
+                                .database
+                                .as_ref()
+                                .map(|d| d.name.as_str())
+                                .unwrap_or("default"),
+                            table_ref.name
+                        ),
+                    ),
+                    ("Index".to_string(), index.clone()),
+                    ("Dimension".to_string(), vector.len().to_string()),
+                    ("K".to_string(), k.to_string()),
+                ];
+                if let Some(filter) = filter {
+                    props.push(("Filter".to_string(), self.describe_predicate(filter)));
+                }
+                if let Some(probes) = probes {
+                    props.push(("Probes".to_string(), probes.to_string()));
+                }
+
+                ("Nearest".to_string(), props)
+            }
             PlanNode::Insert {
                 table_ref,
                 documents,
@@ -290,9 +682,30 @@ impl Explainer {
                     ),
                 ],
             ),
-            PlanNode::Update { patch, .. } => {
+            PlanNode::Update {
+                patch, operations, ..
+            } => {
                 let patch_str = format!("{patch:?}");
-                ("Update".to_string(), vec![("Patch".to_string(), patch_str)])
+                let mut props = vec![("Patch".to_string(), patch_str)];
+                if !operations.is_empty() {
+                    let operations_str = operations
+                        .iter()
+                        .map(|operation| {
+                            let op_str = array_op::Operator::try_from(operation.op)
+                                .map(|op| format!("{op:?}"))
+                                .unwrap_or_else(|_| "UNKNOWN".to_string());
+                            let field = operation
+                                .field
+                                .as_ref()
+                                .map(ToString::to_string)
+                                .unwrap_or_default();
+                            format!("{op_str}({field})")
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    props.push(("Operations".to_string(), operations_str));
+                }
+                ("Update".to_string(), props)
             }
             PlanNode::Delete { .. } => ("Delete".to_string(), vec![]),
             PlanNode::Filter {
@@ -306,23 +719,23 @@ impl Explainer {
                     ("Selectivity".to_string(), format!("{selectivity:.2}")),
                 ],
             ),
-            PlanNode::OrderBy { fields, .. } => {
-                let fields_str = fields
-                    .iter()
-                    .map(|f| {
-                        format!(
-                            "{} {}",
-                            f.field_name,
-                            if f.ascending { "ASC" } else { "DESC" }
-                        )
-                    })
-                    .collect::<Vec<_>>()
-                    .join(", ");
-                (
-                    "OrderBy".to_string(),
-                    vec![("Fields".to_string(), fields_str)],
-                )
-            }
+            PlanNode::SubqueryFilter {
+                predicate,
+                subqueries,
+                selectivity,
+                ..
+            } => (
+                "SubqueryFilter".to_string(),
+                vec![
+                    ("Predicate".to_string(), self.describe_predicate(predicate)),
+                    ("Selectivity".to_string(), format!("{selectivity:.2}")),
+                    ("Subqueries".to_string(), subqueries.len().to_string()),
+                ],
+            ),
+            PlanNode::OrderBy { fields, .. } => (
+                "OrderBy".to_string(),
+                vec![("Fields".to_string(), Self::describe_order(fields))],
+            ),
             PlanNode::Limit { count, .. } => (
                 "Limit".to_string(),
                 vec![("Count".to_string(), count.to_string())],
@@ -332,6 +745,55 @@ impl Explainer {
                 vec![("Count".to_string(), count.to_string())],
             ),
             PlanNode::Count { .. } => ("Count".to_string(), vec![]),
+            PlanNode::Union { interleave, .. } => {
+                let mut props = vec![];
+                if !interleave.is_empty() {
+                    props.push(("Interleave".to_string(), Self::describe_order(interleave)));
+                }
+                ("Union".to_string(), props)
+            }
+            PlanNode::Slice {
+                start,
+                end,
+                left_open,
+                right_closed,
+                ..
+            } => {
+                let end_str = end.map_or_else(String::new, |end| end.to_string());
+                (
+                    "Slice".to_string(),
+                    vec![(
+                        "Range".to_string(),
+                        format!(
+                            "{}{start}, {end_str}{}",
+                            if *left_open { "(" } else { "[" },
+                            if *right_closed && end.is_some() {
+                                "]"
+                            } else {
+                                ")"
+                            }
+                        ),
+                    )],
+                )
+            }
+            PlanNode::Nth { index, .. } => (
+                "Nth".to_string(),
+                vec![("Index".to_string(), index.to_string())],
+            ),
+            PlanNode::Sample { count, .. } => (
+                "Sample".to_string(),
+                vec![("Count".to_string(), count.to_string())],
+            ),
+            PlanNode::IsEmpty { .. } => ("IsEmpty".to_string(), vec![]),
+            PlanNode::OffsetsOf { target, .. } => (
+                "OffsetsOf".to_string(),
+                vec![match target {
+                    offsets_of::Target::Value(value) => ("Value".to_string(), value.to_string()),
+                    offsets_of::Target::Predicate(predicate) => {
+                        ("Predicate".to_string(), self.describe_predicate(predicate))
+                    }
+                }],
+            ),
             PlanNode::Pluck { fields, .. } => (
                 "Pluck".to_string(),
                 vec![(
@@ -354,10 +816,46 @@ impl Explainer {
                         .join(", "),
                 )],
             ),
+            PlanNode::DocumentFunction {
+                op, args, fields, ..
+            } => {
+                let mut props = vec![];
+                if !args.is_empty() {
+                    let args_str = args
+                        .iter()
+                        .map(|arg| self.describe_predicate(arg))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    props.push(("Arguments".to_string(), args_str));
+                }
+                if !fields.is_empty() {
+                    let fields_str = fields
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    props.push(("Fields".to_string(), fields_str));
+                }
+                (format!("{op:?}"), props)
+            }
             PlanNode::Subquery { .. } => ("Subquery".to_string(), vec![]),
         }
     }
 
+    fn describe_order(fields: &[OrderByField]) -> String {
+        fields
+            .iter()
+            .map(|f| {
+                format!(
+                    "{} {}",
+                    f.field_name,
+                    if f.ascending { "ASC" } else { "DESC" }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     #[allow(clippy::only_used_in_recursion)]
     fn describe_predicate(&self, expr: &Expression) -> String {
         match &expr.expr {
@@ -393,6 +891,71 @@ impl Explainer {
             Some(expression::Expr::Subquery(_)) => "SUBQUERY".to_string(),
             Some(expression::Expr::Variable(var)) => format!("${}", var.name),
             Some(expression::Expr::Match(_)) => "MATCH".to_string(),
+            Some(expression::Expr::Geo(geo)) => {
+                let describe = |side: &Option<Box<Expression>>| {
+                    side.as_ref()
+                        .map(|e| self.describe_predicate(e))
+                        .unwrap_or_else(|| "NULL".to_string())
+                };
+                let op_str = geo_op::Operator::try_from(geo.op)
+                    .map(|op| format!("{op:?}"))
+                    .unwrap_or_else(|_| "UNKNOWN".to_string());
+                format!(
+                    "{op_str}({}, {})",
+                    describe(&geo.left),
+                    describe(&geo.right)
+                )
+            }
+            Some(expression::Expr::Time(time)) => {
+                let op_str = time_op::Operator::try_from(time.op)
+                    .map(|op| format!("{op:?}"))
+                    .unwrap_or_else(|_| "UNKNOWN".to_string());
+                let args: Vec<_> = time
+                    .args
+                    .iter()
+                    .map(|arg| self.describe_predicate(arg))
+                    .collect();
+                format!("{op_str}({})", args.join(", "))
+            }
+            Some(expression::Expr::Document(document)) => {
+                let op_str = document_op::Operator::try_from(document.op)
+                    .map(|op| format!("{op:?}"))
+                    .unwrap_or_else(|_| "UNKNOWN".to_string());
+                let args: Vec<_> = document
+                    .args
+                    .iter()
+                    .map(|arg| self.describe_predicate(arg))
+                    .chain(document.fields.iter().map(ToString::to_string))
+                    .collect();
+                format!("{op_str}({})", args.join(", "))
+            }
+            Some(expression::Expr::Branch(branch)) => {
+                let describe = |expr: Option<&Expression>| {
+                    expr.map(|e| self.describe_predicate(e))
+                        .unwrap_or_else(|| "NULL".to_string())
+                };
+                let cases: Vec<_> = branch
+                    .cases
+                    .iter()
+                    .map(|case| {
+                        format!(
+                            "{}, {}",
+                            describe(case.condition.as_ref()),
+                            describe(case.value.as_ref())
+                        )
+                    })
+                    .chain(std::iter::once(describe(branch.otherwise.as_deref())))
+                    .collect();
+                format!("Branch({})", cases.join(", "))
+            }
+            Some(expression::Expr::Error(error)) => {
+                let message = error
+                    .message
+                    .as_ref()
+                    .map(|e| self.describe_predicate(e))
+                    .unwrap_or_default();
+                format!("Error({message})")
+            }
             None => "EMPTY".to_string(),
         }
     }
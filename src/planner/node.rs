@@ -1,4 +1,8 @@
 use crate::ast::*;
+use crate::evaluator::expression::ExpressionEvaluator;
+use crate::evaluator::subquery::{Bindings, bind_expression, bind_query};
+use crate::planner::builder::document_key;
+use crate::planner::{PlanError, PlanResult};
 
 /// Cost constants for different operations
 pub const TABLE_SCAN_COST: f64 = 1.0;
@@ -26,6 +30,7 @@ pub enum PlanNode {
     ListDatabases {
         cursor: Option<Cursor>,
         cost: f64,
+        estimated_rows: f64,
     },
 
     // Table operations
@@ -33,11 +38,14 @@ pub enum PlanNode {
         table_ref: TableRef,
         cursor: Option<Cursor>,
         filter: Option<Expression>,
+        projection: Option<Vec<FieldRef>>,
         cost: f64,
         estimated_rows: f64,
     },
     CreateTable {
         table_ref: TableRef,
+        options: Option<TableOptions>,
+        shards: Option<u32>,
         cost: f64,
     },
     DropTable {
@@ -48,6 +56,46 @@ pub enum PlanNode {
         database_ref: DatabaseRef,
         cursor: Option<Cursor>,
         cost: f64,
+        estimated_rows: f64,
+    },
+    Analyze {
+        table_ref: TableRef,
+        sample_size: Option<u32>,
+        cost: f64,
+    },
+    Rebalance {
+        table_ref: TableRef,
+        shards: Option<u32>,
+        cost: f64,
+    },
+    CreateIndex {
+        table_ref: TableRef,
+        name: String,
+        kind: index_create::Kind,
+        cost: f64,
+    },
+    DropIndex {
+        table_ref: TableRef,
+        name: String,
+        cost: f64,
+    },
+    ListIndexes {
+        table_ref: TableRef,
+        cost: f64,
+    },
+    ReplicationStatus {
+        cost: f64,
+    },
+    ClusterStatus {
+        cost: f64,
+    },
+    ChangeMembership {
+        change: cluster_membership::Change,
+        cost: f64,
+    },
+    TransferLeadership {
+        node_id: Option<u64>,
+        cost: f64,
     },
 
     // Document operations
@@ -59,23 +107,79 @@ pub enum PlanNode {
     GetAll {
         table_ref: TableRef,
         keys: Vec<String>,
+        /// Keys given by parameters of a prepared query, turned into keys once bound
+        key_expressions: Vec<Expression>,
         cursor: Option<Cursor>,
         cost: f64,
     },
+    GetAllByIndex {
+        table_ref: TableRef,
+        index: String,
+        values: Vec<Datum>,
+        /// Values given by parameters of a prepared query, evaluated once bound
+        value_expressions: Vec<Expression>,
+        cost: f64,
+        estimated_rows: f64,
+    },
+    Between {
+        table_ref: TableRef,
+        index: String,
+        lower: Option<Datum>,
+        upper: Option<Datum>,
+        cost: f64,
+        estimated_rows: f64,
+    },
+    Search {
+        table_ref: TableRef,
+        index: String,
+        query: String,
+        cost: f64,
+        estimated_rows: f64,
+    },
+    GetIntersecting {
+        table_ref: TableRef,
+        index: String,
+        geometry: Geometry,
+        cost: f64,
+        estimated_rows: f64,
+    },
+    GetNearest {
+        table_ref: TableRef,
+        index: String,
+        point: Point,
+        max_dist: Option<f64>,
+        max_results: Option<u32>,
+        cost: f64,
+        estimated_rows: f64,
+    },
+    Nearest {
+        table_ref: TableRef,
+        index: String,
+        vector: Vec<f64>,
+        k: u32,
+        filter: Option<Expression>,
+        probes: Option<u32>,
+        cost: f64,
+        estimated_rows: f64,
+    },
 
     // Mutation operations
     Insert {
         table_ref: TableRef,
         documents: Vec<DatumObject>,
+        durability: Durability,
         cost: f64,
     },
     Update {
         source: Box<PlanNode>,
         patch: DatumObject,
+        operations: Vec<ArrayOp>,
+        durability: Durability,
         cost: f64,
     },
     Delete {
         source: Box<PlanNode>,
+        durability: Durability,
         cost: f64,
     },
 
@@ -86,6 +190,19 @@ pub enum PlanNode {
         cost: f64,
         selectivity: f64,
     },
+    /// A filter whose predicate runs table subqueries, each planned here next to the
+    /// query it stands for. The uncorrelated ones run once, the correlated ones for
+    /// each row they are reached for, bound to it.
+    SubqueryFilter {
+        source: Box<PlanNode>,
+        predicate: Expression,
+        subqueries: Vec<(Query, PlanNode)>,
+        /// The index in `subqueries` of each table subquery of the predicate, in the
+        /// order `table_subqueries` lists them
+        slots: Vec<usize>,
+        cost: f64,
+        selectivity: f64,
+    },
     OrderBy {
         source: Box<PlanNode>,
         fields: Vec<OrderByField>,
@@ -106,6 +223,42 @@ pub enum PlanNode {
         cost: f64,
     },
 
+    // Sequence operations
+    /// Concatenate two sequences, or merge them when each is in the interleave order
+    Union {
+        source: Box<PlanNode>,
+        other: Box<PlanNode>,
+        interleave: Vec<OrderByField>,
+        cost: f64,
+    },
+    Slice {
+        source: Box<PlanNode>,
+        start: i64,
+        end: Option<i64>,
+        left_open: bool,
+        right_closed: bool,
+        cost: f64,
+    },
+    Nth {
+        source: Box<PlanNode>,
+        index: i64,
+        cost: f64,
+    },
+    Sample {
+        source: Box<PlanNode>,
+        count: u32,
+        cost: f64,
+    },
+    IsEmpty {
+        source: Box<PlanNode>,
+        cost: f64,
+    },
+    OffsetsOf {
+        source: Box<PlanNode>,
+        target: offsets_of::Target,
+        cost: f64,
+    },
+
     // Document Manipulation
     Pluck {
         source: Box<PlanNode>,
@@ -117,6 +270,15 @@ pub enum PlanNode {
         fields: Vec<FieldRef>,
         cost: f64,
     },
+    /// Apply a document function to the single value of the source, or to each of its
+    /// documents, the value being its first argument
+    DocumentFunction {
+        source: Box<PlanNode>,
+        op: document_op::Operator,
+        args: Vec<Expression>,
+        fields: Vec<FieldRef>,
+        cost: f64,
+    },
 
     // Subquery
     Subquery {
@@ -137,18 +299,41 @@ impl PlanNode {
             PlanNode::CreateTable { cost, .. } => *cost,
             PlanNode::DropTable { cost, .. } => *cost,
             PlanNode::ListTables { cost, .. } => *cost,
+            PlanNode::Analyze { cost, .. } => *cost,
+            PlanNode::Rebalance { cost, .. } => *cost,
+            PlanNode::CreateIndex { cost, .. } => *cost,
+            PlanNode::DropIndex { cost, .. } => *cost,
+            PlanNode::ListIndexes { cost, .. } => *cost,
+            PlanNode::ReplicationStatus { cost } => *cost,
+            PlanNode::ClusterStatus { cost } => *cost,
+            PlanNode::ChangeMembership { cost, .. } => *cost,
+            PlanNode::TransferLeadership { cost, .. } => *cost,
             PlanNode::Get { cost, .. } => *cost,
             PlanNode::GetAll { cost, .. } => *cost,
+            PlanNode::Search { cost, .. } => *cost,
+            PlanNode::GetIntersecting { cost, .. } => *cost,
+            PlanNode::GetAllByIndex { cost, .. } => *cost,
+            PlanNode::Between { cost, .. } => *cost,
+            PlanNode::GetNearest { cost, .. } => *cost,
+            PlanNode::Nearest { cost, .. } => *cost,
             PlanNode::Insert { cost, .. } => *cost,
             PlanNode::Update { cost, .. } => *cost,
             PlanNode::Delete { cost, .. } => *cost,
             PlanNode::Filter { cost, .. } => *cost,
+            PlanNode::SubqueryFilter { cost, .. } => *cost,
             PlanNode::OrderBy { cost, .. } => *cost,
             PlanNode::Limit { cost, .. } => *cost,
             PlanNode::Skip { cost, .. } => *cost,
             PlanNode::Count { cost, .. } => *cost,
+            PlanNode::Union { cost, .. } => *cost,
+            PlanNode::Slice { cost, .. } => *cost,
+            PlanNode::Nth { cost, .. } => *cost,
+            PlanNode::Sample { cost, .. } => *cost,
+            PlanNode::IsEmpty { cost, .. } => *cost,
+            PlanNode::OffsetsOf { cost, .. } => *cost,
             PlanNode::Pluck { cost, .. } => *cost,
             PlanNode::Without { cost, .. } => *cost,
+            PlanNode::DocumentFunction { cost, .. } => *cost,
             PlanNode::Subquery { cost, .. } => *cost,
         }
     }
@@ -159,13 +344,32 @@ impl PlanNode {
             PlanNode::Constant { .. } => 1.0,
             PlanNode::CreateDatabase { .. } => 0.0,
             PlanNode::DropDatabase { .. } => 0.0,
-            PlanNode::ListDatabases { .. } => 10.0, // Assume 10 databases on average
+            PlanNode::ListDatabases { estimated_rows, .. } => *estimated_rows,
             PlanNode::TableScan { estimated_rows, .. } => *estimated_rows,
             PlanNode::CreateTable { .. } => 0.0,
             PlanNode::DropTable { .. } => 0.0,
-            PlanNode::ListTables { .. } => 50.0, // Assume 50 tables on average
+            PlanNode::ListTables { estimated_rows, .. } => *estimated_rows,
+            PlanNode::Analyze { .. } => 0.0,
+            PlanNode::Rebalance { .. } => 0.0,
+            PlanNode::CreateIndex { .. } => 0.0,
+            PlanNode::DropIndex { .. } => 0.0,
+            PlanNode::ListIndexes { .. } => 1.0,
+            PlanNode::ReplicationStatus { .. } => 1.0,
+            PlanNode::ClusterStatus { .. } => 1.0,
+            PlanNode::ChangeMembership { .. } => 1.0,
+            PlanNode::TransferLeadership { .. } => 1.0,
             PlanNode::Get { .. } => 1.0,
-            PlanNode::GetAll { keys, .. } => keys.len() as f64,
+            PlanNode::GetAll {
+                keys,
+                key_expressions,
+                ..
+            } => (keys.len() + key_expressions.len()) as f64,
+            PlanNode::Search { estimated_rows, .. } => *estimated_rows,
+            PlanNode::GetIntersecting { estimated_rows, .. } => *estimated_rows,
+            PlanNode::GetAllByIndex { estimated_rows, .. } => *estimated_rows,
+            PlanNode::Between { estimated_rows, .. } => *estimated_rows,
+            PlanNode::GetNearest { estimated_rows, .. } => *estimated_rows,
+            PlanNode::Nearest { estimated_rows, .. } => *estimated_rows,
             PlanNode::Insert { documents, .. } => documents.len() as f64,
             PlanNode::Update { source, .. } => source.estimated_rows(),
             PlanNode::Delete { source, .. } => source.estimated_rows(),
@@ -173,6 +377,11 @@ impl PlanNode {
                 source,
                 selectivity,
                 ..
+            }
+            | PlanNode::SubqueryFilter {
+                source,
+                selectivity,
+                ..
             } => source.estimated_rows() * selectivity,
             PlanNode::OrderBy { source, .. } => source.estimated_rows(),
             PlanNode::Limit { source, count, .. } => source.estimated_rows().min(*count as f64),
@@ -180,11 +389,196 @@ impl PlanNode {
                 (source.estimated_rows() - *count as f64).max(0.0)
             }
             PlanNode::Count { .. } => 1.0,
+            PlanNode::Union { source, other, .. } => {
+                source.estimated_rows() + other.estimated_rows()
+            }
+            PlanNode::Slice {
+                source, start, end, ..
+            } => match (*start, *end) {
+                (start, Some(end)) if start >= 0 && end >= 0 => {
+                    source.estimated_rows().min((end - start).max(0) as f64)
+                }
+                _ => source.estimated_rows(),
+            },
+            PlanNode::Nth { .. } => 1.0,
+            PlanNode::Sample { source, count, .. } => source.estimated_rows().min(*count as f64),
+            PlanNode::IsEmpty { .. } => 1.0,
+            PlanNode::OffsetsOf { .. } => 1.0,
             PlanNode::Pluck { source, .. } => source.estimated_rows(),
             PlanNode::Without { source, .. } => source.estimated_rows(),
+            PlanNode::DocumentFunction { source, .. } => source.estimated_rows(),
             PlanNode::Subquery { query, .. } => query.estimated_rows(),
         }
     }
+
+    /// Whether the plan reads documents from a table, the only reads served from a
+    /// snapshot and continued by a cursor
+    pub fn reads_tables(&self) -> bool {
+        match self {
+            PlanNode::TableScan { .. }
+            | PlanNode::Get { .. }
+            | PlanNode::GetAll { .. }
+            | PlanNode::GetAllByIndex { .. }
+            | PlanNode::Between { .. }
+            | PlanNode::Search { .. }
+            | PlanNode::GetIntersecting { .. }
+            | PlanNode::GetNearest { .. }
+            | PlanNode::Nearest { .. } => true,
+            PlanNode::Constant { .. }
+            | PlanNode::CreateDatabase { .. }
+            | PlanNode::DropDatabase { .. }
+            | PlanNode::ListDatabases { .. }
+            | PlanNode::CreateTable { .. }
+            | PlanNode::DropTable { .. }
+            | PlanNode::ListTables { .. }
+            | PlanNode::Analyze { .. }
+            | PlanNode::Rebalance { .. }
+            | PlanNode::CreateIndex { .. }
+            | PlanNode::DropIndex { .. }
+            | PlanNode::ListIndexes { .. }
+            | PlanNode::ReplicationStatus { .. }
+            | PlanNode::ClusterStatus { .. }
+            | PlanNode::ChangeMembership { .. }
+            | PlanNode::TransferLeadership { .. }
+            | PlanNode::Insert { .. } => false,
+            PlanNode::SubqueryFilter {
+                source, subqueries, ..
+            } => source.reads_tables() || subqueries.iter().any(|(_, plan)| plan.reads_tables()),
+            PlanNode::Union { source, other, .. } => source.reads_tables() || other.reads_tables(),
+            PlanNode::Update { source, .. }
+            | PlanNode::Delete { source, .. }
+            | PlanNode::Filter { source, .. }
+            | PlanNode::OrderBy { source, .. }
+            | PlanNode::Limit { source, .. }
+            | PlanNode::Skip { source, .. }
+            | PlanNode::Count { source, .. }
+            | PlanNode::Slice { source, .. }
+            | PlanNode::Nth { source, .. }
+            | PlanNode::Sample { source, .. }
+            | PlanNode::IsEmpty { source, .. }
+            | PlanNode::OffsetsOf { source, .. }
+            | PlanNode::Pluck { source, .. }
+            | PlanNode::Without { source, .. }
+            | PlanNode::DocumentFunction { source, .. }
+            | PlanNode::Subquery { query: source, .. } => source.reads_tables(),
+        }
+    }
+
+    /// Replace the variables of every expression of the plan with the values bound to
+    /// them, evaluating the keys of lookups that use them
+    pub fn bind(&mut self, bindings: &Bindings<'_>) -> PlanResult<()> {
+        let bind = |expr: &mut Expression| bind_expression(expr, bindings);
+        match self {
+            PlanNode::TableScan { filter, .. } | PlanNode::Nearest { filter, .. } => {
+                filter.iter_mut().for_each(bind);
+            }
+            PlanNode::GetAll {
+                keys,
+                key_expressions,
+                ..
+            } => {
+                for mut key in key_expressions.drain(..) {
+                    bind(&mut key);
+                    keys.push(document_key(&evaluate_bound(&key)?)?);
+                }
+            }
+            PlanNode::GetAllByIndex {
+                values,
+                value_expressions,
+                ..
+            } => {
+                for mut value in value_expressions.drain(..) {
+                    bind(&mut value);
+                    values.push(evaluate_bound(&value)?);
+                }
+            }
+            PlanNode::Filter {
+                source, predicate, ..
+            } => {
+                bind(predicate);
+                source.bind(bindings)?;
+            }
+            PlanNode::SubqueryFilter {
+                source,
+                predicate,
+                subqueries,
+                ..
+            } => {
+                // The subqueries are bound alike, to still match those of the predicate.
+                // The variables of a correlated one name fields of the rows of this filter
+                // rather than of the row the plan is bound to.
+                bind(predicate);
+                if bindings.binds_subqueries() {
+                    for (query, plan) in subqueries {
+                        bind_query(query, bindings);
+                        plan.bind(bindings)?;
+                    }
+                }
+                source.bind(bindings)?;
+            }
+            PlanNode::DocumentFunction { source, args, .. } => {
+                args.iter_mut().for_each(bind);
+                source.bind(bindings)?;
+            }
+            PlanNode::OffsetsOf { source, target, .. } => {
+                if let offsets_of::Target::Predicate(predicate) = target {
+                    bind(predicate);
+                }
+                source.bind(bindings)?;
+            }
+            PlanNode::Union { source, other, .. } => {
+                source.bind(bindings)?;
+                other.bind(bindings)?;
+            }
+            PlanNode::Update { source, .. }
+            | PlanNode::Delete { source, .. }
+            | PlanNode::OrderBy { source, .. }
+            | PlanNode::Limit { source, .. }
+            | PlanNode::Skip { source, .. }
+            | PlanNode::Count { source, .. }
+            | PlanNode::Slice { source, .. }
+            | PlanNode::Nth { source, .. }
+            | PlanNode::Sample { source, .. }
+            | PlanNode::IsEmpty { source, .. }
+            | PlanNode::Pluck { source, .. }
+            | PlanNode::Without { source, .. }
+            | PlanNode::Subquery { query: source, .. } => source.bind(bindings)?,
+            // Documents, patches, keys, bounds and the like are values by the time they
+            // are planned, and the expression of an index is stored with it rather than
+            // run: none of these nodes has a variable to bind
+            PlanNode::Constant { .. }
+            | PlanNode::CreateDatabase { .. }
+            | PlanNode::DropDatabase { .. }
+            | PlanNode::ListDatabases { .. }
+            | PlanNode::CreateTable { .. }
+            | PlanNode::DropTable { .. }
+            | PlanNode::ListTables { .. }
+            | PlanNode::Analyze { .. }
+            | PlanNode::Rebalance { .. }
+            | PlanNode::CreateIndex { .. }
+            | PlanNode::DropIndex { .. }
+            | PlanNode::ListIndexes { .. }
+            | PlanNode::ReplicationStatus { .. }
+            | PlanNode::ClusterStatus { .. }
+            | PlanNode::ChangeMembership { .. }
+            | PlanNode::TransferLeadership { .. }
+            | PlanNode::Get { .. }
+            | PlanNode::Between { .. }
+            | PlanNode::Search { .. }
+            | PlanNode::GetIntersecting { .. }
+            | PlanNode::GetNearest { .. }
+            | PlanNode::Insert { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+/// The value of an expression whose variables are bound
+fn evaluate_bound(expr: &Expression) -> PlanResult<Datum> {
+    let context = Datum { value: None };
+    ExpressionEvaluator::new()
+        .evaluate_expression(expr, &context)
+        .map_err(|e| PlanError::InvalidConstant(e.to_string()))
 }
 
 impl PartialEq for PlanNode {
@@ -208,18 +602,28 @@ impl PartialEq for PlanNode {
                 PlanNode::TableScan {
                     table_ref: t1,
                     filter: f1,
+                    projection: p1,
                     ..
                 },
                 PlanNode::TableScan {
                     table_ref: t2,
                     filter: f2,
+                    projection: p2,
                     ..
                 },
-            ) => t1 == t2 && f1 == f2,
+            ) => t1 == t2 && f1 == f2 && p1 == p2,
             (
-                PlanNode::CreateTable { table_ref: t1, .. },
-                PlanNode::CreateTable { table_ref: t2, .. },
-            ) => t1 == t2,
+                PlanNode::CreateTable {
+                    table_ref: t1,
+                    shards: s1,
+                    ..
+                },
+                PlanNode::CreateTable {
+                    table_ref: t2,
+                    shards: s2,
+                    ..
+                },
+            ) => t1 == t2 && s1 == s2,
             (
                 PlanNode::DropTable { table_ref: t1, .. },
                 PlanNode::DropTable { table_ref: t2, .. },
@@ -232,6 +636,70 @@ impl PartialEq for PlanNode {
                     database_ref: d2, ..
                 },
             ) => d1 == d2,
+            (
+                PlanNode::Analyze {
+                    table_ref: t1,
+                    sample_size: s1,
+                    ..
+                },
+                PlanNode::Analyze {
+                    table_ref: t2,
+                    sample_size: s2,
+                    ..
+                },
+            ) => t1 == t2 && s1 == s2,
+            (
+                PlanNode::Rebalance {
+                    table_ref: t1,
+                    shards: s1,
+                    ..
+                },
+                PlanNode::Rebalance {
+                    table_ref: t2,
+                    shards: s2,
+                    ..
+                },
+            ) => t1 == t2 && s1 == s2,
+            (
+                PlanNode::CreateIndex {
+                    table_ref: t1,
+                    name: n1,
+                    kind: k1,
+                    ..
+                },
+                PlanNode::CreateIndex {
+                    table_ref: t2,
+                    name: n2,
+                    kind: k2,
+                    ..
+                },
+            ) => t1 == t2 && n1 == n2 && k1 == k2,
+            (
+                PlanNode::DropIndex {
+                    table_ref: t1,
+                    name: n1,
+                    ..
+                },
+                PlanNode::DropIndex {
+                    table_ref: t2,
+                    name: n2,
+                    ..
+                },
+            ) => t1 == t2 && n1 == n2,
+            (
+                PlanNode::ListIndexes { table_ref: t1, .. },
+                PlanNode::ListIndexes { table_ref: t2, .. },
+            ) => t1 == t2,
+            (PlanNode::ReplicationStatus { .. }, PlanNode::ReplicationStatus { .. }) => true,
+            (PlanNode::ClusterStatus { .. }, PlanNode::ClusterStatus { .. }) => true,
+            (
+                PlanNode::ChangeMembership { change: c1, .. },
+                PlanNode::ChangeMembership { change: c2, .. },
+            ) => c1 == c2,
+            (
+                PlanNode::TransferLeadership { node_id: n1, .. },
+                PlanNode::TransferLeadership { node_id: n2, .. },
+            ) => n1 == n2,
             (
                 PlanNode::Get {
                     table_ref: t1,
@@ -248,14 +716,114 @@ impl PartialEq for PlanNode {
                 PlanNode::GetAll {
                     table_ref: t1,
                     keys: k1,
+                    key_expressions: e1,
                     ..
                 },
                 PlanNode::GetAll {
                     table_ref: t2,
                     keys: k2,
+                    key_expressions: e2,
                     ..
                 },
-            ) => t1 == t2 && k1 == k2,
+            ) => t1 == t2 && k1 == k2 && e1 == e2,
+            (
+                PlanNode::GetAllByIndex {
+                    table_ref: t1,
+                    index: i1,
+                    values: v1,
+                    value_expressions: e1,
+                    ..
+                },
+                PlanNode::GetAllByIndex {
+                    table_ref: t2,
+                    index: i2,
+                    values: v2,
+                    value_expressions: e2,
+                    ..
+                },
+            ) => t1 == t2 && i1 == i2 && v1 == v2 && e1 == e2,
+            (
+                PlanNode::Between {
+                    table_ref: t1,
+                    index: i1,
+                    lower: l1,
+                    upper: u1,
+                    ..
+                },
+                PlanNode::Between {
+                    table_ref: t2,
+                    index: i2,
+                    lower: l2,
+                    upper: u2,
+                    ..
+                },
+            ) => t1 == t2 && i1 == i2 && l1 == l2 && u1 == u2,
+            (
+                PlanNode::Search {
+                    table_ref: t1,
+                    index: i1,
+                    query: q1,
+                    ..
+                },
+                PlanNode::Search {
+                    table_ref: t2,
+                    index: i2,
+                    query: q2,
+                    ..
+                },
+            ) => t1 == t2 && i1 == i2 && q1 == q2,
+            (
+                PlanNode::GetIntersecting {
+                    table_ref: t1,
+                    index: i1,
+                    geometry: g1,
+                    ..
+                },
+                PlanNode::GetIntersecting {
+                    table_ref: t2,
+                    index: i2,
+                    geometry: g2,
+                    ..
+                },
+            ) => t1 == t2 && i1 == i2 && g1 == g2,
+            (
+                PlanNode::GetNearest {
+                    table_ref: t1,
+                    index: i1,
+                    point: p1,
+                    max_dist: d1,
+                    max_results: r1,
+                    ..
+                },
+                PlanNode::GetNearest {
+                    table_ref: t2,
+                    index: i2,
+                    point: p2,
+                    max_dist: d2,
+                    max_results: r2,
+                    ..
+                },
+            ) => t1 == t2 && i1 == i2 && p1 == p2 && d1 == d2 && r1 == r2,
+            (
+                PlanNode::Nearest {
+                    table_ref: t1,
+                    index: i1,
+                    vector: v1,
+                    k: k1,
+                    filter: f1,
+                    probes: p1,
+                    ..
+                },
+                PlanNode::Nearest {
+                    table_ref: t2,
+                    index: i2,
+                    vector: v2,
+                    k: k2,
+                    filter: f2,
+                    probes: p2,
+                    ..
+                },
+            ) => t1 == t2 && i1 == i2 && v1 == v2 && k1 == k2 && f1 == f2 && p1 == p2,
             (
                 PlanNode::Insert {
                     table_ref: t1,
@@ -272,14 +840,16 @@ impl PartialEq for PlanNode {
                 PlanNode::Update {
                     source: s1,
                     patch: p1,
+                    operations: o1,
                     ..
                 },
                 PlanNode::Update {
                     source: s2,
                     patch: p2,
+                    operations: o2,
                     ..
                 },
-            ) => s1 == s2 && p1 == p2,
+            ) => s1 == s2 && p1 == p2 && o1 == o2,
             (PlanNode::Delete { source: s1, .. }, PlanNode::Delete { source: s2, .. }) => s1 == s2,
             (
                 PlanNode::Filter {
@@ -293,6 +863,20 @@ impl PartialEq for PlanNode {
                     ..
                 },
             ) => s1 == s2 && p1 == p2,
+            (
+                PlanNode::SubqueryFilter {
+                    source: s1,
+                    predicate: p1,
+                    subqueries: q1,
+                    ..
+                },
+                PlanNode::SubqueryFilter {
+                    source: s2,
+                    predicate: p2,
+                    subqueries: q2,
+                    ..
+                },
+            ) => s1 == s2 && p1 == p2 && q1 == q2,
             (
                 PlanNode::OrderBy {
                     source: s1,
@@ -330,6 +914,77 @@ impl PartialEq for PlanNode {
                 },
             ) => s1 == s2 && c1 == c2,
             (PlanNode::Count { source: s1, .. }, PlanNode::Count { source: s2, .. }) => s1 == s2,
+            (
+                PlanNode::Union {
+                    source: s1,
+                    other: o1,
+                    interleave: i1,
+                    ..
+                },
+                PlanNode::Union {
+                    source: s2,
+                    other: o2,
+                    interleave: i2,
+                    ..
+                },
+            ) => s1 == s2 && o1 == o2 && i1 == i2,
+            (
+                PlanNode::Slice {
+                    source: s1,
+                    start: b1,
+                    end: e1,
+                    left_open: l1,
+                    right_closed: r1,
+                    ..
+                },
+                PlanNode::Slice {
+                    source: s2,
+                    start: b2,
+                    end: e2,
+                    left_open: l2,
+                    right_closed: r2,
+                    ..
+                },
+            ) => s1 == s2 && b1 == b2 && e1 == e2 && l1 == l2 && r1 == r2,
+            (
+                PlanNode::Nth {
+                    source: s1,
+                    index: i1,
+                    ..
+                },
+                PlanNode::Nth {
+                    source: s2,
+                    index: i2,
+                    ..
+                },
+            ) => s1 == s2 && i1 == i2,
+            (
+                PlanNode::Sample {
+                    source: s1,
+                    count: c1,
+                    ..
+                },
+                PlanNode::Sample {
+                    source: s2,
+                    count: c2,
+                    ..
+                },
+            ) => s1 == s2 && c1 == c2,
+            (PlanNode::IsEmpty { source: s1, .. }, PlanNode::IsEmpty { source: s2, .. }) => {
+                s1 == s2
+            }
+            (
+                PlanNode::OffsetsOf {
+                    source: s1,
+                    target: t1,
+                    ..
+                },
+                PlanNode::OffsetsOf {
+                    source: s2,
+                    target: t2,
+                    ..
+                },
+            ) => s1 == s2 && t1 == t2,
             (
                 PlanNode::Pluck {
                     source: s1,
@@ -354,6 +1009,22 @@ impl PartialEq for PlanNode {
                     ..
                 },
             ) => s1 == s2 && f1 == f2,
+            (
+                PlanNode::DocumentFunction {
+                    source: s1,
+                    op: o1,
+                    args: a1,
+                    fields: f1,
+                    ..
+                },
+                PlanNode::DocumentFunction {
+                    source: s2,
+                    op: o2,
+                    args: a2,
+                    fields: f2,
+                    ..
+                },
+            ) => s1 == s2 && o1 == o2 && a1 == a2 && f1 == f2,
             (PlanNode::Subquery { query: q1, .. }, PlanNode::Subquery { query: q2, .. }) => {
                 q1 == q2
             }
@@ -1,5 +1,7 @@
 use crate::ast::*;
-use crate::planner::builder::PlanBuilder;
+use crate::evaluator::expression::ExpressionEvaluator;
+use crate::evaluator::utils::{compare_values, datums_equal};
+use crate::planner::builder::{PlanBuilder, subquery_cost};
 use crate::planner::error::{PlanError, PlanResult};
 use crate::planner::node::{FILTER_COST, PlanNode, TABLE_SCAN_COST};
 
@@ -34,6 +36,7 @@ impl PlanOptimizer {
         optimized = self.optimize_constants(optimized)?;
         optimized = self.optimize_predicates(optimized)?;
         optimized = self.merge_adjacent_operations(optimized)?;
+        optimized = self.push_down_projections(optimized)?;
         optimized = self.optimize_costs(optimized)?;
 
         Ok(optimized)
@@ -97,19 +100,28 @@ impl PlanOptimizer {
             PlanNode::Update {
                 source,
                 patch,
+                operations,
+                durability,
                 cost,
             } => {
                 let optimized_source = self.optimize_constants(*source)?;
                 Ok(PlanNode::Update {
                     source: Box::new(optimized_source),
                     patch,
+                    operations,
+                    durability,
                     cost,
                 })
             }
-            PlanNode::Delete { source, cost } => {
+            PlanNode::Delete {
+                source,
+                durability,
+                cost,
+            } => {
                 let optimized_source = self.optimize_constants(*source)?;
                 Ok(PlanNode::Delete {
                     source: Box::new(optimized_source),
+                    durability,
                     cost,
                 })
             }
@@ -156,6 +168,17 @@ impl PlanOptimizer {
                     cost,
                 })
             }
+            plan @ (PlanNode::Union { .. }
+            | PlanNode::Slice { .. }
+            | PlanNode::Nth { .. }
+            | PlanNode::Sample { .. }
+            | PlanNode::IsEmpty { .. }
+            | PlanNode::OffsetsOf { .. }) => {
+                self.optimize_sequence_sources(plan, Self::optimize_constants)
+            }
+            plan @ PlanNode::SubqueryFilter { .. } => {
+                self.optimize_subquery_filter(plan, Self::optimize_constants)
+            }
             PlanNode::Subquery { query, cost } => {
                 let optimized_query = self.optimize_constants(*query)?;
                 Ok(PlanNode::Subquery {
@@ -184,6 +207,7 @@ impl PlanOptimizer {
                     table_ref,
                     cursor,
                     filter: existing_filter,
+                    projection,
                     cost: scan_cost,
                     estimated_rows,
                 } => {
@@ -197,6 +221,7 @@ impl PlanOptimizer {
                         table_ref,
                         cursor,
                         filter: Some(combined_filter),
+                        projection,
                         cost: scan_cost + FILTER_COST,
                         estimated_rows: estimated_rows * selectivity,
                     })
@@ -256,19 +281,28 @@ impl PlanOptimizer {
             PlanNode::Update {
                 source,
                 patch,
+                operations,
+                durability,
                 cost,
             } => {
                 let optimized_source = self.optimize_predicates(*source)?;
                 Ok(PlanNode::Update {
                     source: Box::new(optimized_source),
                     patch,
+                    operations,
+                    durability,
                     cost,
                 })
             }
-            PlanNode::Delete { source, cost } => {
+            PlanNode::Delete {
+                source,
+                durability,
+                cost,
+            } => {
                 let optimized_source = self.optimize_predicates(*source)?;
                 Ok(PlanNode::Delete {
                     source: Box::new(optimized_source),
+                    durability,
                     cost,
                 })
             }
@@ -315,6 +349,17 @@ impl PlanOptimizer {
                     cost,
                 })
             }
+            plan @ (PlanNode::Union { .. }
+            | PlanNode::Slice { .. }
+            | PlanNode::Nth { .. }
+            | PlanNode::Sample { .. }
+            | PlanNode::IsEmpty { .. }
+            | PlanNode::OffsetsOf { .. }) => {
+                self.optimize_sequence_sources(plan, Self::optimize_predicates)
+            }
+            plan @ PlanNode::SubqueryFilter { .. } => {
+                self.optimize_subquery_filter(plan, Self::optimize_predicates)
+            }
             PlanNode::Subquery { query, cost } => {
                 let optimized_query = self.optimize_predicates(*query)?;
                 Ok(PlanNode::Subquery {
@@ -428,19 +473,28 @@ impl PlanOptimizer {
             PlanNode::Update {
                 source,
                 patch,
+                operations,
+                durability,
                 cost,
             } => {
                 let optimized_source = self.merge_adjacent_operations(*source)?;
                 Ok(PlanNode::Update {
                     source: Box::new(optimized_source),
                     patch,
+                    operations,
+                    durability,
                     cost,
                 })
             }
-            PlanNode::Delete { source, cost } => {
+            PlanNode::Delete {
+                source,
+                durability,
+                cost,
+            } => {
                 let optimized_source = self.merge_adjacent_operations(*source)?;
                 Ok(PlanNode::Delete {
                     source: Box::new(optimized_source),
+                    durability,
                     cost,
                 })
             }
@@ -463,6 +517,17 @@ impl PlanOptimizer {
                     cost,
                 })
             }
+            plan @ (PlanNode::Union { .. }
+            | PlanNode::Slice { .. }
+            | PlanNode::Nth { .. }
+            | PlanNode::Sample { .. }
+            | PlanNode::IsEmpty { .. }
+            | PlanNode::OffsetsOf { .. }) => {
+                self.optimize_sequence_sources(plan, Self::merge_adjacent_operations)
+            }
+            plan @ PlanNode::SubqueryFilter { .. } => {
+                self.optimize_subquery_filter(plan, Self::merge_adjacent_operations)
+            }
             PlanNode::Subquery { query, cost } => {
                 let optimized_query = self.merge_adjacent_operations(*query)?;
                 Ok(PlanNode::Subquery {
@@ -476,27 +541,208 @@ impl PlanOptimizer {
         }
     }
 
+    /// Push plucked fields down into the table scan beneath them, so the scan only
+    /// decodes the fields the query returns
+    pub fn push_down_projections(&mut self, plan: PlanNode) -> PlanResult<PlanNode> {
+        match plan {
+            PlanNode::Pluck {
+                source,
+                fields,
+                cost,
+            } => {
+                let optimized_source = self.push_down_projections(*source)?;
+                Ok(PlanNode::Pluck {
+                    source: Box::new(Self::project_scan(optimized_source, &fields)),
+                    fields,
+                    cost,
+                })
+            }
+
+            // Recursively optimize child nodes
+            PlanNode::Filter {
+                source,
+                predicate,
+                cost,
+                selectivity,
+            } => {
+                let optimized_source = self.push_down_projections(*source)?;
+                Ok(PlanNode::Filter {
+                    source: Box::new(optimized_source),
+                    predicate,
+                    cost,
+                    selectivity,
+                })
+            }
+            PlanNode::OrderBy {
+                source,
+                fields,
+                cost,
+            } => {
+                let optimized_source = self.push_down_projections(*source)?;
+                Ok(PlanNode::OrderBy {
+                    source: Box::new(optimized_source),
+                    fields,
+                    cost,
+                })
+            }
+            PlanNode::Limit {
+                source,
+                count,
+                cost,
+            } => {
+                let optimized_source = self.push_down_projections(*source)?;
+                Ok(PlanNode::Limit {
+                    source: Box::new(optimized_source),
+                    count,
+                    cost,
+                })
+            }
+            PlanNode::Skip {
+                source,
+                count,
+                cost,
+            } => {
+                let optimized_source = self.push_down_projections(*source)?;
+                Ok(PlanNode::Skip {
+                    source: Box::new(optimized_source),
+                    count,
+                    cost,
+                })
+            }
+            PlanNode::Count { source, cost } => {
+                let optimized_source = self.push_down_projections(*source)?;
+                Ok(PlanNode::Count {
+                    source: Box::new(optimized_source),
+                    cost,
+                })
+            }
+            PlanNode::Without {
+                source,
+                fields,
+                cost,
+            } => {
+                let optimized_source = self.push_down_projections(*source)?;
+                Ok(PlanNode::Without {
+                    source: Box::new(optimized_source),
+                    fields,
+                    cost,
+                })
+            }
+            PlanNode::DocumentFunction {
+                source,
+                op,
+                args,
+                fields,
+                cost,
+            } => {
+                let optimized_source = self.push_down_projections(*source)?;
+                Ok(PlanNode::DocumentFunction {
+                    source: Box::new(optimized_source),
+                    op,
+                    args,
+                    fields,
+                    cost,
+                })
+            }
+            plan @ (PlanNode::Union { .. }
+            | PlanNode::Slice { .. }
+            | PlanNode::Nth { .. }
+            | PlanNode::Sample { .. }
+            | PlanNode::IsEmpty { .. }
+            | PlanNode::OffsetsOf { .. }) => {
+                self.optimize_sequence_sources(plan, Self::push_down_projections)
+            }
+            plan @ PlanNode::SubqueryFilter { .. } => {
+                self.optimize_subquery_filter(plan, Self::push_down_projections)
+            }
+            PlanNode::Subquery { query, cost } => {
+                let optimized_query = self.push_down_projections(*query)?;
+                Ok(PlanNode::Subquery {
+                    query: Box::new(optimized_query),
+                    cost,
+                })
+            }
+
+            // Base cases
+            _ => Ok(plan),
+        }
+    }
+
+    /// Attach a projection to the table scan under a pluck, looking through operations
+    /// that do not read document fields
+    fn project_scan(plan: PlanNode, fields: &[FieldRef]) -> PlanNode {
+        match plan {
+            PlanNode::TableScan {
+                table_ref,
+                cursor,
+                filter,
+                projection,
+                cost,
+                estimated_rows,
+            } => PlanNode::TableScan {
+                table_ref,
+                cursor,
+                filter,
+                projection: projection.or_else(|| Some(fields.to_vec())),
+                cost,
+                estimated_rows,
+            },
+            PlanNode::Limit {
+                source,
+                count,
+                cost,
+            } => PlanNode::Limit {
+                source: Box::new(Self::project_scan(*source, fields)),
+                count,
+                cost,
+            },
+            PlanNode::Skip {
+                source,
+                count,
+                cost,
+            } => PlanNode::Skip {
+                source: Box::new(Self::project_scan(*source, fields)),
+                count,
+                cost,
+            },
+            other => other,
+        }
+    }
+
     /// Optimize costs throughout the plan
-    #[allow(clippy::only_used_in_recursion)]
     pub fn optimize_costs(&mut self, plan: PlanNode) -> PlanResult<PlanNode> {
         match plan {
             PlanNode::TableScan {
                 table_ref,
                 cursor,
                 filter,
+                projection,
                 estimated_rows,
                 ..
             } => {
                 let base_cost = TABLE_SCAN_COST;
-                let filter_cost = if filter.is_some() {
-                    estimated_rows * FILTER_COST
-                } else {
-                    0.0
+                // With statistics, cost the filter over every row it reads and estimate
+                // its output from the table's field distributions
+                let (filter_cost, estimated_rows) = match self.builder.table_statistics(&table_ref)
+                {
+                    Some(stats) => {
+                        let rows = stats.row_count as f64;
+                        match &filter {
+                            Some(filter) => (
+                                rows * FILTER_COST,
+                                rows * self.builder.estimate_selectivity(filter, Some(&stats)),
+                            ),
+                            None => (0.0, rows),
+                        }
+                    }
+                    None if filter.is_some() => (estimated_rows * FILTER_COST, estimated_rows),
+                    None => (0.0, estimated_rows),
                 };
                 Ok(PlanNode::TableScan {
                     table_ref,
                     cursor,
                     filter,
+                    projection,
                     cost: base_cost + filter_cost,
                     estimated_rows,
                 })
@@ -528,22 +774,33 @@ impl PlanOptimizer {
                     cost: source_cost + sort_cost,
                 })
             }
-            PlanNode::Update { source, patch, .. } => {
+            PlanNode::Update {
+                source,
+                patch,
+                operations,
+                durability,
+                ..
+            } => {
                 let optimized_source = self.optimize_costs(*source)?;
                 let source_cost = optimized_source.cost();
                 let update_cost = optimized_source.estimated_rows() * 0.5;
                 Ok(PlanNode::Update {
                     source: Box::new(optimized_source),
                     patch,
+                    operations,
+                    durability,
                     cost: source_cost + update_cost,
                 })
             }
-            PlanNode::Delete { source, .. } => {
+            PlanNode::Delete {
+                source, durability, ..
+            } => {
                 let optimized_source = self.optimize_costs(*source)?;
                 let source_cost = optimized_source.cost();
                 let delete_cost = optimized_source.estimated_rows() * 0.3;
                 Ok(PlanNode::Delete {
                     source: Box::new(optimized_source),
+                    durability,
                     cost: source_cost + delete_cost,
                 })
             }
@@ -572,6 +829,17 @@ impl PlanOptimizer {
                     source: Box::new(optimized_source),
                 })
             }
+            plan @ (PlanNode::Union { .. }
+            | PlanNode::Slice { .. }
+            | PlanNode::Nth { .. }
+            | PlanNode::Sample { .. }
+            | PlanNode::IsEmpty { .. }
+            | PlanNode::OffsetsOf { .. }) => {
+                self.optimize_sequence_sources(plan, Self::optimize_costs)
+            }
+            plan @ PlanNode::SubqueryFilter { .. } => {
+                self.optimize_subquery_filter(plan, Self::optimize_costs)
+            }
             PlanNode::Subquery { query, .. } => {
                 let optimized_query = self.optimize_costs(*query)?;
                 Ok(PlanNode::Subquery {
@@ -585,6 +853,125 @@ impl PlanOptimizer {
         }
     }
 
+    /// Optimize the sources of a sequence operation with an optimization pass, costing
+    /// the operation again from them
+    fn optimize_sequence_sources(
+        &mut self,
+        plan: PlanNode,
+        optimize: fn(&mut Self, PlanNode) -> PlanResult<PlanNode>,
+    ) -> PlanResult<PlanNode> {
+        let mut optimize_source = |source: Box<PlanNode>| -> PlanResult<Box<PlanNode>> {
+            Ok(Box::new(optimize(self, *source)?))
+        };
+        Ok(match plan {
+            PlanNode::Union {
+                source,
+                other,
+                interleave,
+                ..
+            } => {
+                let source = optimize_source(source)?;
+                let other = optimize_source(other)?;
+                PlanNode::Union {
+                    cost: source.cost() + other.cost(),
+                    source,
+                    other,
+                    interleave,
+                }
+            }
+            PlanNode::Slice {
+                source,
+                start,
+                end,
+                left_open,
+                right_closed,
+                ..
+            } => {
+                let source = optimize_source(source)?;
+                PlanNode::Slice {
+                    cost: source.cost(),
+                    source,
+                    start,
+                    end,
+                    left_open,
+                    right_closed,
+                }
+            }
+            PlanNode::Nth { source, index, .. } => {
+                let source = optimize_source(source)?;
+                PlanNode::Nth {
+                    cost: source.cost(),
+                    source,
+                    index,
+                }
+            }
+            PlanNode::Sample { source, count, .. } => {
+                let source = optimize_source(source)?;
+                PlanNode::Sample {
+                    cost: source.cost(),
+                    source,
+                    count,
+                }
+            }
+            PlanNode::IsEmpty { source, .. } => {
+                let source = optimize_source(source)?;
+                PlanNode::IsEmpty {
+                    cost: source.cost(),
+                    source,
+                }
+            }
+            PlanNode::OffsetsOf { source, target, .. } => {
+                let source = optimize_source(source)?;
+                PlanNode::OffsetsOf {
+                    cost: source.cost() + source.estimated_rows() * FILTER_COST,
+                    source,
+                    target,
+                }
+            }
+            other => other,
+        })
+    }
+
+    /// Apply an optimization pass to the source of a filter running subqueries and to
+    /// the plans of its subqueries, with the filter costed again
+    fn optimize_subquery_filter(
+        &mut self,
+        plan: PlanNode,
+        optimize: fn(&mut Self, PlanNode) -> PlanResult<PlanNode>,
+    ) -> PlanResult<PlanNode> {
+        let PlanNode::SubqueryFilter {
+            source,
+            predicate,
+            subqueries,
+            slots,
+            selectivity,
+            ..
+        } = plan
+        else {
+            return Ok(plan);
+        };
+        let source = optimize(self, *source)?;
+        let subqueries = subqueries
+            .into_iter()
+            .map(|(query, plan)| Ok((query, optimize(self, plan)?)))
+            .collect::<PlanResult<Vec<_>>>()?;
+        let rows = source.estimated_rows();
+        let cost = source.cost()
+            + rows * FILTER_COST
+            + subqueries
+                .iter()
+                .map(|(query, plan)| subquery_cost(query, plan, rows))
+                .sum::<f64>();
+        Ok(PlanNode::SubqueryFilter {
+            source: Box::new(source),
+            predicate,
+            subqueries,
+            slots,
+            cost,
+            selectivity,
+        })
+    }
+
     /// Fold constant expressions
     fn fold_constants(&mut self, expr: Expression) -> PlanResult<Expression> {
         match expr.expr {
@@ -747,20 +1134,20 @@ impl PlanOptimizer {
         use datum::Value;
 
         let result = match (op, &left.value, &right.value) {
-            // Comparison operations
-            (binary_op::Operator::Eq, Some(a), Some(b)) => Some(Value::Bool(a == b)),
-            (binary_op::Operator::Ne, Some(a), Some(b)) => Some(Value::Bool(a != b)),
-            (binary_op::Operator::Lt, Some(Value::Int(a)), Some(Value::Int(b))) => {
-                Some(Value::Bool(a < b))
+            // Comparison operations, in the same order as the evaluator's
+            (binary_op::Operator::Eq, _, _) => Some(Value::Bool(datums_equal(left, right))),
+            (binary_op::Operator::Ne, _, _) => Some(Value::Bool(!datums_equal(left, right))),
+            (binary_op::Operator::Lt, _, _) => {
+                Some(Value::Bool(compare_values(left, right).is_lt()))
             }
-            (binary_op::Operator::Le, Some(Value::Int(a)), Some(Value::Int(b))) => {
-                Some(Value::Bool(a <= b))
+            (binary_op::Operator::Le, _, _) => {
+                Some(Value::Bool(compare_values(left, right).is_le()))
             }
-            (binary_op::Operator::Gt, Some(Value::Int(a)), Some(Value::Int(b))) => {
-                Some(Value::Bool(a > b))
+            (binary_op::Operator::Gt, _, _) => {
+                Some(Value::Bool(compare_values(left, right).is_gt()))
             }
-            (binary_op::Operator::Ge, Some(Value::Int(a)), Some(Value::Int(b))) => {
-                Some(Value::Bool(a >= b))
+            (binary_op::Operator::Ge, _, _) => {
+                Some(Value::Bool(compare_values(left, right).is_ge()))
             }
 
             // Logical operations
@@ -771,6 +1158,21 @@ impl PlanOptimizer {
                 Some(Value::Bool(*a || *b))
             }
 
+            // Arithmetic operations
+            (
+                binary_op::Operator::Add
+                | binary_op::Operator::Sub
+                | binary_op::Operator::Mul
+                | binary_op::Operator::Div
+                | binary_op::Operator::Mod,
+                _,
+                _,
+            ) => {
+                return ExpressionEvaluator::new()
+                    .perform_binary_operation(left, op, right)
+                    .map_err(|e| PlanError::InvalidConstant(e.to_string()));
+            }
+
             _ => None,
         };
 
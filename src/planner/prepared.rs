@@ -0,0 +1,376 @@
+//! Prepared queries.
+//!
+//! A prepared query is planned once and kept under a handle, its variables being
+//! parameters: each execution binds values to all of them in a copy of the plan rather
+//! than planning the query again. The variables of table subqueries are not parameters,
+//! as they name fields of the outer row. Plans are remade when a database, table or
+//! index has been created or dropped since they were made, and the least recently used
+//! query is forgotten when there are too many of them.
+
+use crate::ast::{Cursor, Datum, Query, QueryOptions};
+use crate::evaluator::subquery::{Bindings, variable_names};
+use crate::planner::{PlanError, PlanNode, PlanResult, Planner};
+use crate::storage::statistics::StatisticsProvider;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Number of prepared queries kept by default
+pub const DEFAULT_PREPARED_QUERIES: usize = 1024;
+
+/// Plans of prepared queries, by handle
+pub struct PreparedQueries {
+    statistics: Arc<dyn StatisticsProvider>,
+    capacity: usize,
+    state: Mutex<PreparedState>,
+}
+
+#[derive(Default)]
+struct PreparedState {
+    queries: HashMap<String, PreparedQuery>,
+    /// Counts uses, for the least recently used query to be evicted
+    access_counter: u64,
+}
+
+struct PreparedQuery {
+    query: Query,
+    plan: PlanNode,
+    /// Names of the variables an execution has to bind
+    parameters: Vec<String>,
+    /// Schema version of the storage when the plan was made
+    schema_version: u64,
+    last_access: u64,
+}
+
+/// The plan of a prepared query with its parameters bound, to be run with the options
+/// and cursor of the query
+#[derive(Debug, Clone)]
+pub struct BoundQuery {
+    pub plan: PlanNode,
+    pub options: Option<QueryOptions>,
+    pub cursor: Option<Cursor>,
+}
+
+impl PreparedQueries {
+    /// Create an empty cache keeping up to `capacity` queries, planned against the
+    /// statistics
+    pub fn new(statistics: Arc<dyn StatisticsProvider>, capacity: usize) -> Self {
+        Self {
+            statistics,
+            capacity: capacity.max(1),
+            state: Mutex::new(PreparedState::default()),
+        }
+    }
+
+    /// Plan a query and keep it, returning its handle
+    pub fn prepare(&self, query: Query) -> PlanResult<String> {
+        let schema_version = self.statistics.schema_version();
+        let plan = self.plan(&query)?;
+        let parameters = variable_names(&query);
+        let handle = ulid::Ulid::new().to_string();
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.access_counter += 1;
+        if state.queries.len() >= self.capacity {
+            let oldest = state
+                .queries
+                .iter()
+                .min_by_key(|(_, prepared)| prepared.last_access)
+                .map(|(handle, _)| handle.clone());
+            if let Some(oldest) = oldest {
+                state.queries.remove(&oldest);
+            }
+        }
+        let last_access = state.access_counter;
+        state.queries.insert(
+            handle.clone(),
+            PreparedQuery {
+                query,
+                plan,
+                parameters,
+                schema_version,
+                last_access,
+            },
+        );
+        Ok(handle)
+    }
+
+    /// The plan of a prepared query with the parameters bound to its variables, failing
+    /// when any of them is given no value
+    pub fn bind(
+        &self,
+        handle: &str,
+        parameters: &HashMap<String, Datum>,
+    ) -> PlanResult<BoundQuery> {
+        let schema_version = self.statistics.schema_version();
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.access_counter += 1;
+        let access = state.access_counter;
+        let prepared = state
+            .queries
+            .get_mut(handle)
+            .ok_or_else(|| PlanError::UnknownPreparedQuery(handle.to_string()))?;
+        prepared.last_access = access;
+        if let Some(unbound) = prepared
+            .parameters
+            .iter()
+            .find(|name| !parameters.contains_key(*name))
+        {
+            return Err(PlanError::UnboundParameter(unbound.clone()));
+        }
+        if prepared.schema_version != schema_version {
+            prepared.plan = self.plan(&prepared.query)?;
+            prepared.schema_version = schema_version;
+        }
+
+        let mut plan = prepared.plan.clone();
+        let options = prepared.query.options;
+        let cursor = prepared.query.cursor.clone();
+        drop(state);
+
+        plan.bind(&Bindings::Parameters(parameters))?;
+        Ok(BoundQuery {
+            plan,
+            options,
+            cursor,
+        })
+    }
+
+    /// Number of queries kept
+    pub fn len(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .queries
+            .len()
+    }
+
+    /// Whether no query is kept
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn plan(&self, query: &Query) -> PlanResult<PlanNode> {
+        let mut planner = Planner::with_statistics(self.statistics.clone());
+        let plan = planner.plan(query)?;
+        planner.optimize(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{
+        BinaryOp, DatabaseRef, Expression, FieldRef, Filter, GetAll, Table, TableRef, Variable,
+        binary_op, datum, expression, query,
+    };
+    use crate::storage::statistics::TableStatistics;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[derive(Default)]
+    struct Schema {
+        version: AtomicU64,
+    }
+
+    impl StatisticsProvider for Schema {
+        fn table_statistics(&self, _db: &str, _table: &str) -> Option<TableStatistics> {
+            None
+        }
+
+        fn table_count(&self, _db: &str) -> Option<u64> {
+            None
+        }
+
+        fn database_count(&self) -> Option<u64> {
+            None
+        }
+
+        fn schema_version(&self) -> u64 {
+            self.version.load(Ordering::Relaxed)
+        }
+    }
+
+    fn users() -> Query {
+        Query {
+            options: None,
+            cursor: None,
+            kind: Some(query::Kind::Table(Table {
+                table: Some(TableRef {
+                    database: Some(DatabaseRef {
+                        name: "db".to_string(),
+                    }),
+                    name: "users".to_string(),
+                }),
+            })),
+        }
+    }
+
+    fn variable(name: &str) -> Expression {
+        Expression {
+            expr: Some(expression::Expr::Variable(Variable {
+                name: name.to_string(),
+            })),
+        }
+    }
+
+    fn string(value: &str) -> Datum {
+        Datum {
+            value: Some(datum::Value::String(value.to_string())),
+        }
+    }
+
+    fn filter_by(name: &str) -> Query {
+        let predicate = Expression {
+            expr: Some(expression::Expr::Binary(Box::new(BinaryOp {
+                op: binary_op::Operator::Eq as i32,
+                left: Some(Box::new(Expression {
+                    expr: Some(expression::Expr::Field(FieldRef {
+                        path: vec!["name".to_string()],
+                        separator: ".".to_string(),
+                    })),
+                })),
+                right: Some(Box::new(variable(name))),
+            }))),
+        };
+        Query {
+            options: None,
+            cursor: None,
+            kind: Some(query::Kind::Filter(Box::new(Filter {
+                source: Some(Box::new(users())),
+                predicate: Some(Box::new(predicate)),
+            }))),
+        }
+    }
+
+    fn bind_one(prepared: &PreparedQueries, handle: &str, name: &str) -> PlanResult<BoundQuery> {
+        prepared.bind(handle, &HashMap::from([(name.to_string(), string(""))]))
+    }
+
+    fn predicate(plan: &PlanNode) -> &Expression {
+        match plan {
+            PlanNode::Filter { predicate, .. } => predicate,
+            PlanNode::TableScan {
+                filter: Some(filter),
+                ..
+            } => filter,
+            other => panic!("Expected a filter, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parameters_are_bound_in_a_copy_of_the_plan() {
+        let schema = Arc::new(Schema::default());
+        let prepared = PreparedQueries::new(schema, 8);
+        let handle = prepared.prepare(filter_by("wanted")).unwrap();
+
+        let value = string("Alice");
+        let parameters = HashMap::from([("wanted".to_string(), value.clone())]);
+        let bound = prepared.bind(&handle, &parameters).unwrap();
+        let Some(expression::Expr::Binary(op)) = &predicate(&bound.plan).expr else {
+            panic!("Expected a comparison");
+        };
+        assert_eq!(
+            op.right.as_ref().unwrap().expr,
+            Some(expression::Expr::Literal(value))
+        );
+        let state = prepared.state.lock().unwrap();
+        assert_eq!(
+            state.queries[&handle].plan,
+            prepared.plan(&filter_by("wanted")).unwrap()
+        );
+        drop(state);
+
+        assert_eq!(
+            prepared.bind("missing", &parameters).unwrap_err(),
+            PlanError::UnknownPreparedQuery("missing".to_string())
+        );
+    }
+
+    #[test]
+    fn test_least_recently_used_query_is_evicted() {
+        let schema = Arc::new(Schema::default());
+        let prepared = PreparedQueries::new(schema, 2);
+        let first = prepared.prepare(filter_by("a")).unwrap();
+        let second = prepared.prepare(filter_by("b")).unwrap();
+
+        bind_one(&prepared, &first, "a").unwrap();
+        let third = prepared.prepare(filter_by("c")).unwrap();
+        assert_eq!(prepared.len(), 2);
+        assert!(bind_one(&prepared, &first, "a").is_ok());
+        assert!(bind_one(&prepared, &second, "b").is_err());
+        assert!(bind_one(&prepared, &third, "c").is_ok());
+    }
+
+    #[test]
+    fn test_plans_are_remade_after_schema_changes() {
+        let schema = Arc::new(Schema::default());
+        let prepared = PreparedQueries::new(schema.clone(), 2);
+        let handle = prepared.prepare(filter_by("a")).unwrap();
+        let planned_at = |prepared: &PreparedQueries| {
+            prepared
+                .state
+                .lock()
+                .unwrap()
+                .queries
+                .get(&handle)
+                .unwrap()
+                .schema_version
+        };
+
+        schema.version.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(planned_at(&prepared), 0);
+        bind_one(&prepared, &handle, "a").unwrap();
+        assert_eq!(planned_at(&prepared), 1);
+    }
+
+    #[test]
+    fn test_unbound_parameters_are_rejected() {
+        let schema = Arc::new(Schema::default());
+        let prepared = PreparedQueries::new(schema, 2);
+        let handle = prepared.prepare(filter_by("wanted")).unwrap();
+
+        assert_eq!(
+            prepared.bind(&handle, &HashMap::new()).unwrap_err(),
+            PlanError::UnboundParameter("wanted".to_string())
+        );
+        assert!(bind_one(&prepared, &handle, "other").is_err());
+    }
+
+    #[test]
+    fn test_get_all_keys_are_bound() {
+        let schema = Arc::new(Schema::default());
+        let prepared = PreparedQueries::new(schema, 2);
+        let get_all = Query {
+            options: None,
+            cursor: None,
+            kind: Some(query::Kind::GetAll(Box::new(GetAll {
+                source: Some(Box::new(users())),
+                keys: vec![string("u1")],
+                index: String::new(),
+                key_expressions: vec![variable("id")],
+            }))),
+        };
+        let handle = prepared.prepare(get_all).unwrap();
+
+        let parameters = HashMap::from([("id".to_string(), string("u2"))]);
+        match prepared.bind(&handle, &parameters).unwrap().plan {
+            PlanNode::GetAll {
+                keys,
+                key_expressions,
+                ..
+            } => {
+                assert_eq!(keys, ["u1", "u2"]);
+                assert!(key_expressions.is_empty());
+            }
+            other => panic!("Expected GetAll, got {other:?}"),
+        }
+
+        let parameters = HashMap::from([(
+            "id".to_string(),
+            Datum {
+                value: Some(datum::Value::Bool(true)),
+            },
+        )]);
+        assert!(prepared.bind(&handle, &parameters).is_err());
+    }
+}
@@ -74,6 +74,7 @@ fn test_plan_node_cost() {
         table_ref: create_test_table_ref(),
         cursor: None,
         filter: None,
+        projection: None,
         cost: 5.0,
         estimated_rows: 100.0,
     };
@@ -92,6 +93,7 @@ fn test_plan_node_estimated_rows() {
         table_ref: create_test_table_ref(),
         cursor: None,
         filter: None,
+        projection: None,
         cost: 1.0,
         estimated_rows: 100.0,
     };
@@ -107,6 +109,7 @@ fn test_plan_node_estimated_rows() {
         table_ref: create_test_table_ref(),
         cursor: None,
         filter: None,
+        projection: None,
         cost: 1.0,
         estimated_rows: 100.0,
     });
@@ -123,6 +126,7 @@ fn test_plan_node_estimated_rows() {
         table_ref: create_test_table_ref(),
         cursor: None,
         filter: None,
+        projection: None,
         cost: 1.0,
         estimated_rows: 100.0,
     });
@@ -138,6 +142,7 @@ fn test_plan_node_estimated_rows() {
         table_ref: create_test_table_ref(),
         cursor: None,
         filter: None,
+        projection: None,
         cost: 1.0,
         estimated_rows: 100.0,
     });
@@ -150,6 +155,35 @@ fn test_plan_node_estimated_rows() {
     assert_eq!(node.estimated_rows(), 50.0);
 }
 
+#[test]
+fn test_plan_node_reads_tables() {
+    let scan = PlanNode::TableScan {
+        table_ref: create_test_table_ref(),
+        cursor: None,
+        filter: None,
+        projection: None,
+        cost: 1.0,
+        estimated_rows: 100.0,
+    };
+    let node = PlanNode::Count {
+        source: Box::new(scan),
+        cost: 1.0,
+    };
+    assert!(node.reads_tables());
+
+    let node = PlanNode::Constant {
+        value: create_test_datum_int(42),
+        cost: 0.0,
+    };
+    assert!(!node.reads_tables());
+
+    let node = PlanNode::CreateDatabase {
+        name: "test_db".to_string(),
+        cost: 1.0,
+    };
+    assert!(!node.reads_tables());
+}
+
 #[test]
 fn test_plan_error_display() {
     let err = PlanError::UnsupportedOperation("test operation".to_string());
@@ -205,12 +239,14 @@ fn test_build_plan_table() {
             table_ref,
             cursor,
             filter,
+            projection,
             cost,
             estimated_rows,
         } => {
             assert_eq!(table_ref.name, "test_table");
             assert!(cursor.is_none());
             assert!(filter.is_none());
+            assert!(projection.is_none());
             assert_eq!(cost, TABLE_SCAN_COST);
             assert_eq!(estimated_rows, 1000.0);
         }
@@ -257,6 +293,8 @@ fn test_build_plan_get_all() {
                 create_test_datum_string("key1"),
                 create_test_datum_string("key2"),
             ],
+            index: String::new(),
+            key_expressions: vec![],
         }))),
     };
     let plan = planner.plan(&query).unwrap();
@@ -265,9 +303,11 @@ fn test_build_plan_get_all() {
         PlanNode::GetAll {
             table_ref,
             keys,
+            key_expressions,
             cursor,
             cost,
         } => {
+            assert!(key_expressions.is_empty());
             assert_eq!(table_ref.name, "test_table");
             assert_eq!(keys.len(), 2);
             assert!(cursor.is_none());
@@ -287,6 +327,7 @@ fn test_build_plan_insert() {
         kind: Some(query::Kind::Insert(Box::new(Insert {
             source: Some(Box::new(create_test_table_query())),
             documents: docs.clone(),
+            durability: Durability::Hard.into(),
         }))),
     };
     let plan = planner.plan(&query).unwrap();
@@ -295,10 +336,12 @@ fn test_build_plan_insert() {
         PlanNode::Insert {
             table_ref,
             documents,
+            durability,
             cost,
         } => {
             assert_eq!(table_ref.name, "test_table");
             assert_eq!(documents.len(), 1);
+            assert_eq!(durability, Durability::Hard);
             assert_eq!(cost, 1.0);
         }
         _ => panic!("Expected Insert node"),
@@ -366,6 +409,7 @@ fn test_plan_explanation_display() {
             table_ref: create_test_table_ref(),
             cursor: None,
             filter: None,
+            projection: None,
             cost: 1.0,
             estimated_rows: 100.0,
         }),
@@ -410,6 +454,7 @@ fn test_plan_node_partial_eq() {
         table_ref: create_test_table_ref(),
         cursor: None,
         filter: None,
+        projection: None,
         cost: 1.0,
         estimated_rows: 100.0,
     };
@@ -417,6 +462,7 @@ fn test_plan_node_partial_eq() {
         table_ref: create_test_table_ref(),
         cursor: None,
         filter: None,
+        projection: None,
         cost: 2.0,
         estimated_rows: 200.0,
     };
@@ -433,6 +479,7 @@ fn test_cursor_context() {
         start_key: Some("start".to_string()),
         batch_size: Some(10),
         sort: None,
+        snapshot: None,
     };
 
     planner.cursor_context = Some(cursor.clone());
@@ -533,6 +580,105 @@ fn test_constant_folding() {
     }
 }
 
+#[test]
+fn test_constant_folding_comparisons() {
+    let mut planner = Planner::new();
+    let float = |value: f64| Datum {
+        value: Some(datum::Value::Float(value)),
+    };
+    let cases = [
+        (
+            create_test_datum_int(1),
+            binary_op::Operator::Eq,
+            float(1.0),
+            true,
+        ),
+        (
+            create_test_datum_string("a"),
+            binary_op::Operator::Lt,
+            create_test_datum_int(1),
+            false,
+        ),
+        (
+            float(2.5),
+            binary_op::Operator::Gt,
+            create_test_datum_int(2),
+            true,
+        ),
+        (
+            create_test_datum_string("b"),
+            binary_op::Operator::Ge,
+            create_test_datum_string("a"),
+            true,
+        ),
+        (
+            create_test_datum_bool(true),
+            binary_op::Operator::Le,
+            create_test_datum_int(0),
+            true,
+        ),
+    ];
+
+    // Folding compares constants as the evaluator does, whatever their types
+    for (left, op, right, expected) in cases {
+        let query = Query {
+            options: None,
+            cursor: None,
+            kind: Some(query::Kind::Expression(Box::new(create_test_binary_expr(
+                create_test_literal_expr(left),
+                op,
+                create_test_literal_expr(right),
+            )))),
+        };
+        match planner.plan(&query).unwrap() {
+            PlanNode::Constant { value, .. } => {
+                assert_eq!(value.value, Some(datum::Value::Bool(expected)), "{op:?}")
+            }
+            other => panic!("Expected Constant node after constant folding, got {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn test_constant_folding_arithmetic() {
+    let mut planner = Planner::new();
+    let expression_query = |expr: Expression| Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Expression(Box::new(expr))),
+    };
+
+    // (2 * 3) == 6.0 folds all the way to true
+    let product = create_test_binary_expr(
+        create_test_literal_expr(create_test_datum_int(2)),
+        binary_op::Operator::Mul,
+        create_test_literal_expr(create_test_datum_int(3)),
+    );
+    let query = expression_query(create_test_binary_expr(
+        product,
+        binary_op::Operator::Eq,
+        create_test_literal_expr(Datum {
+            value: Some(datum::Value::Float(6.0)),
+        }),
+    ));
+    match planner.plan(&query).unwrap() {
+        PlanNode::Constant { value, .. } => {
+            assert_eq!(value.value, Some(datum::Value::Bool(true)))
+        }
+        other => panic!("Expected Constant node after constant folding, got {other:?}"),
+    }
+
+    let query = expression_query(create_test_binary_expr(
+        create_test_literal_expr(create_test_datum_int(1)),
+        binary_op::Operator::Div,
+        create_test_literal_expr(create_test_datum_int(0)),
+    ));
+    assert!(matches!(
+        planner.plan(&query),
+        Err(PlanError::InvalidConstant(_))
+    ));
+}
+
 #[test]
 fn test_predicate_pushdown() {
     let mut planner = Planner::new();
@@ -673,6 +819,223 @@ fn test_comparison_operations() {
     }
 }
 
+#[test]
+fn test_build_plan_update_with_array_operations() {
+    let mut planner = Planner::new();
+    let append = ArrayOp {
+        op: array_op::Operator::Append.into(),
+        field: Some(FieldRef {
+            path: vec!["tags".to_string()],
+            separator: ".".to_string(),
+        }),
+        value: Some(create_test_datum_string("new")),
+        index: 0,
+    };
+    let update_query = |operations: Vec<ArrayOp>| Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Update(Box::new(Update {
+            source: Some(Box::new(create_test_table_query())),
+            patch: None,
+            durability: Durability::Default.into(),
+            operations,
+        }))),
+    };
+
+    // Array operations make the patch optional
+    let plan = planner.plan(&update_query(vec![append.clone()])).unwrap();
+    match &plan {
+        PlanNode::Update {
+            patch, operations, ..
+        } => {
+            assert!(patch.fields.is_empty());
+            assert_eq!(operations, &vec![append]);
+        }
+        _ => panic!("Expected Update node"),
+    }
+    let explanation = planner.explain(&plan).to_string();
+    assert!(explanation.contains("Append(tags)"), "{explanation}");
+
+    assert!(planner.plan(&update_query(vec![])).is_err());
+}
+
+#[test]
+fn test_build_plan_sequence_operations() {
+    let mut planner = Planner::new();
+    let query_of = |kind| Query {
+        options: None,
+        cursor: None,
+        kind: Some(kind),
+    };
+    let filtered = || {
+        Some(Box::new(query_of(query::Kind::Filter(Box::new(Filter {
+            source: Some(Box::new(create_test_table_query())),
+            predicate: Some(Box::new(create_test_binary_expr(
+                create_test_field_expr("age"),
+                binary_op::Operator::Gt,
+                create_test_literal_expr(create_test_datum_int(30)),
+            ))),
+        })))))
+    };
+
+    // The filter under a sequence operation is still pushed into the scan
+    let nth = query_of(query::Kind::Nth(Box::new(Nth {
+        source: filtered(),
+        index: -1,
+    })));
+    let plan = planner.plan(&nth).unwrap();
+    let plan = planner.optimize(plan).unwrap();
+    match &plan {
+        PlanNode::Nth { source, index, .. } => {
+            assert_eq!(*index, -1);
+            assert!(matches!(
+                source.as_ref(),
+                PlanNode::TableScan {
+                    filter: Some(_),
+                    ..
+                }
+            ));
+        }
+        _ => panic!("Expected Nth node"),
+    }
+    let explanation = planner.explain(&plan).to_string();
+    assert!(explanation.contains("Nth"), "{explanation}");
+    assert!(explanation.contains("-1"), "{explanation}");
+
+    // A union explains both of its sources
+    let union = query_of(query::Kind::Union(Box::new(Union {
+        source: Some(Box::new(create_test_table_query())),
+        other: filtered(),
+        interleave: vec![SortField {
+            field_name: "id".to_string(),
+            direction: SortDirection::Asc.into(),
+        }],
+    })));
+    let scan_cost = planner.plan(&create_test_table_query()).unwrap().cost();
+    let plan = planner.plan(&union).unwrap();
+    assert!(plan.cost() > scan_cost);
+    let explanation = planner.explain(&plan).to_string();
+    assert!(explanation.contains("id ASC"), "{explanation}");
+    assert_eq!(explanation.matches("TableScan").count(), 2, "{explanation}");
+
+    let slice = query_of(query::Kind::Slice(Box::new(Slice {
+        source: Some(Box::new(create_test_table_query())),
+        start: 1,
+        end: Some(3),
+        left_open: false,
+        right_closed: true,
+    })));
+    let plan = planner.plan(&slice).unwrap();
+    assert_eq!(plan.estimated_rows(), 2.0);
+    let explanation = planner.explain(&plan).to_string();
+    assert!(explanation.contains("[1, 3]"), "{explanation}");
+
+    let offsets_of = query_of(query::Kind::OffsetsOf(Box::new(OffsetsOf {
+        source: Some(Box::new(create_test_table_query())),
+        target: None,
+    })));
+    assert!(planner.plan(&offsets_of).is_err());
+}
+
+#[test]
+fn test_build_plan_filter_with_subqueries() {
+    let mut planner = Planner::new();
+    let query_of = |kind| Query {
+        options: None,
+        cursor: None,
+        kind: Some(kind),
+    };
+    let subquery = |query| Expression {
+        expr: Some(expression::Expr::Subquery(Box::new(query))),
+    };
+    let count = || {
+        query_of(query::Kind::Count(Box::new(Count {
+            source: Some(Box::new(create_test_table_query())),
+        })))
+    };
+    // Orders of the user, looked up in an index by the user's id
+    let orders_of_user = query_of(query::Kind::GetAll(Box::new(GetAll {
+        source: Some(Box::new(create_test_table_query())),
+        keys: vec![],
+        index: "user_id".to_string(),
+        key_expressions: vec![Expression {
+            expr: Some(expression::Expr::Variable(Variable {
+                name: "id".to_string(),
+            })),
+        }],
+    })));
+    let predicate = create_test_binary_expr(
+        create_test_binary_expr(
+            subquery(count()),
+            binary_op::Operator::Gt,
+            subquery(count()),
+        ),
+        binary_op::Operator::Or,
+        create_test_binary_expr(
+            subquery(orders_of_user),
+            binary_op::Operator::Ne,
+            create_test_literal_expr(Datum::default()),
+        ),
+    );
+    let filter = query_of(query::Kind::Filter(Box::new(Filter {
+        source: Some(Box::new(create_test_table_query())),
+        predicate: Some(Box::new(predicate)),
+    })));
+
+    // Each distinct subquery is planned once, the correlated one keeping its variable,
+    // and the filter is kept out of the scan, whose predicates cannot run subqueries
+    let plan = planner.plan(&filter).unwrap();
+    let plan = planner.optimize(plan).unwrap();
+    match &plan {
+        PlanNode::SubqueryFilter {
+            source,
+            subqueries,
+            slots,
+            ..
+        } => {
+            assert!(matches!(
+                source.as_ref(),
+                PlanNode::TableScan { filter: None, .. }
+            ));
+            assert_eq!(subqueries.len(), 2);
+            assert_eq!(slots, &[0, 0, 1]);
+            assert_eq!(subqueries[0].0, count());
+            assert!(matches!(subqueries[0].1, PlanNode::Count { .. }));
+            assert!(matches!(
+                &subqueries[1].1,
+                PlanNode::GetAllByIndex { value_expressions, .. } if value_expressions.len() == 1
+            ));
+        }
+        _ => panic!("Expected SubqueryFilter node, got {plan:?}"),
+    }
+    let explanation = planner.explain(&plan).to_string();
+    assert!(explanation.contains("SubqueryFilter"), "{explanation}");
+
+    // Key expressions of a GetAll are keys once they are constant
+    let get_all = |key_expressions| {
+        query_of(query::Kind::GetAll(Box::new(GetAll {
+            source: Some(Box::new(create_test_table_query())),
+            keys: vec![create_test_datum_string("a")],
+            index: String::new(),
+            key_expressions,
+        })))
+    };
+    let plan = planner
+        .plan(&get_all(vec![create_test_literal_expr(
+            create_test_datum_string("b"),
+        )]))
+        .unwrap();
+    match plan {
+        PlanNode::GetAll { keys, .. } => assert_eq!(keys, ["a", "b"]),
+        _ => panic!("Expected GetAll node"),
+    }
+    assert!(
+        planner
+            .plan(&get_all(vec![create_test_field_expr("id")]))
+            .is_err()
+    );
+}
+
 #[test]
 fn test_update_delete_optimization() {
     let mut planner = Planner::new();
@@ -695,12 +1058,17 @@ fn test_update_delete_optimization() {
                 }))),
             })),
             patch: Some(DatumObject::default()),
+            durability: Durability::Default.into(),
+            operations: vec![],
         }))),
     };
 
     let plan = planner.plan(&update_query).unwrap();
     match plan {
-        PlanNode::Update { source, .. } => {
+        PlanNode::Update {
+            source, durability, ..
+        } => {
+            assert_eq!(durability, Durability::Default);
             // Filter should be pushed down to TableScan
             match source.as_ref() {
                 PlanNode::TableScan { filter, .. } => {
@@ -729,17 +1097,23 @@ fn test_update_delete_optimization() {
                     ))),
                 }))),
             })),
+            durability: Durability::Soft.into(),
         }))),
     };
 
     let plan = planner.plan(&delete_query).unwrap();
     match plan {
-        PlanNode::Delete { source, .. } => match source.as_ref() {
-            PlanNode::TableScan { filter, .. } => {
-                assert!(filter.is_some());
+        PlanNode::Delete {
+            source, durability, ..
+        } => {
+            assert_eq!(durability, Durability::Soft);
+            match source.as_ref() {
+                PlanNode::TableScan { filter, .. } => {
+                    assert!(filter.is_some());
+                }
+                _ => panic!("Expected TableScan with filter"),
             }
-            _ => panic!("Expected TableScan with filter"),
-        },
+        }
         _ => panic!("Expected Delete node"),
     }
 }
@@ -814,12 +1188,89 @@ fn test_database_operations() {
     };
     let plan = planner.plan(&query).unwrap();
     match plan {
-        PlanNode::ListDatabases { cursor, cost } => {
+        PlanNode::ListDatabases {
+            cursor,
+            cost,
+            estimated_rows,
+        } => {
             assert!(cursor.is_none());
             assert_eq!(cost, 1.0);
+            assert_eq!(estimated_rows, 10.0);
         }
         _ => panic!("Expected ListDatabases node"),
     }
+
+    // Test ReplicationStatus
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::ReplicationStatus(ReplicationStatus {})),
+    };
+    let plan = planner.plan(&query).unwrap();
+    assert_eq!(plan, PlanNode::ReplicationStatus { cost: 1.0 });
+    assert_eq!(plan.estimated_rows(), 1.0);
+}
+
+#[test]
+fn test_cluster_operations() {
+    let mut planner = Planner::new();
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::ClusterStatus(ClusterStatus {})),
+    };
+    let plan = planner.plan(&query).unwrap();
+    assert_eq!(plan, PlanNode::ClusterStatus { cost: 1.0 });
+    assert_eq!(plan.estimated_rows(), 1.0);
+
+    let add = cluster_membership::Change::Add(ClusterMember {
+        id: 4,
+        address: "127.0.0.1:6094".to_string(),
+    });
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::ClusterMembership(ClusterMembership {
+            change: Some(add.clone()),
+        })),
+    };
+    let plan = planner.plan(&query).unwrap();
+    assert_eq!(
+        plan,
+        PlanNode::ChangeMembership {
+            change: add,
+            cost: 1.0
+        }
+    );
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::ClusterMembership(ClusterMembership {
+            change: None,
+        })),
+    };
+    assert!(matches!(
+        planner.plan(&query),
+        Err(PlanError::InvalidExpression(_))
+    ));
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::ClusterTransferLeadership(
+            ClusterTransferLeadership { node_id: Some(2) },
+        )),
+    };
+    let plan = planner.plan(&query).unwrap();
+    assert_eq!(
+        plan,
+        PlanNode::TransferLeadership {
+            node_id: Some(2),
+            cost: 1.0
+        }
+    );
 }
 
 #[test]
@@ -832,12 +1283,21 @@ fn test_table_operations() {
         cursor: None,
         kind: Some(query::Kind::TableCreate(TableCreate {
             table: Some(create_test_table_ref()),
+            options: None,
+            shards: Some(4),
         })),
     };
     let plan = planner.plan(&query).unwrap();
     match plan {
-        PlanNode::CreateTable { table_ref, cost } => {
+        PlanNode::CreateTable {
+            table_ref,
+            options,
+            shards,
+            cost,
+        } => {
             assert_eq!(table_ref.name, "test_table");
+            assert!(options.is_none());
+            assert_eq!(shards, Some(4));
             assert_eq!(cost, 1.0);
         }
         _ => panic!("Expected CreateTable node"),
@@ -876,10 +1336,12 @@ fn test_table_operations() {
             database_ref,
             cursor,
             cost,
+            estimated_rows,
         } => {
             assert_eq!(database_ref.name, "test_db");
             assert!(cursor.is_none());
             assert_eq!(cost, 1.0);
+            assert_eq!(estimated_rows, 50.0);
         }
         _ => panic!("Expected ListTables node"),
     }
@@ -948,6 +1410,84 @@ fn test_build_plan_pluck() {
     }
 }
 
+#[test]
+fn test_projection_pushdown() {
+    let mut planner = Planner::new();
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Pluck(Box::new(Pluck {
+            source: Some(Box::new(Query {
+                options: None,
+                cursor: None,
+                kind: Some(query::Kind::Limit(Box::new(Limit {
+                    source: Some(Box::new(create_test_table_query())),
+                    count: 10,
+                }))),
+            })),
+            fields: vec![FieldRef {
+                path: vec!["address".to_string(), "city".to_string()],
+                separator: ".".to_string(),
+            }],
+        }))),
+    };
+
+    let plan = planner.plan(&query).unwrap();
+
+    let PlanNode::Pluck { source, fields, .. } = plan else {
+        panic!("Expected Pluck node");
+    };
+    let PlanNode::Limit { source, .. } = *source else {
+        panic!("Expected Limit under Pluck");
+    };
+    match *source {
+        PlanNode::TableScan { projection, .. } => assert_eq!(projection, Some(fields)),
+        _ => panic!("Expected TableScan under Limit"),
+    }
+}
+
+#[test]
+fn test_projection_not_pushed_through_order_by() {
+    let mut optimizer = PlanOptimizer::new();
+
+    let plan = PlanNode::Pluck {
+        source: Box::new(PlanNode::OrderBy {
+            source: Box::new(PlanNode::TableScan {
+                table_ref: create_test_table_ref(),
+                cursor: None,
+                filter: None,
+                projection: None,
+                cost: TABLE_SCAN_COST,
+                estimated_rows: 1000.0,
+            }),
+            fields: vec![OrderByField {
+                field_name: "age".to_string(),
+                ascending: true,
+            }],
+            cost: TABLE_SCAN_COST,
+        }),
+        fields: vec![FieldRef {
+            path: vec!["name".to_string()],
+            separator: ".".to_string(),
+        }],
+        cost: TABLE_SCAN_COST,
+    };
+
+    let optimized = optimizer.push_down_projections(plan).unwrap();
+
+    let PlanNode::Pluck { source, .. } = optimized else {
+        panic!("Expected Pluck node");
+    };
+    let PlanNode::OrderBy { source, .. } = *source else {
+        panic!("Expected OrderBy under Pluck");
+    };
+    match *source {
+        PlanNode::TableScan { projection, .. } => assert!(projection.is_none()),
+        _ => panic!("Expected TableScan under OrderBy"),
+    }
+}
+
 #[test]
 fn test_pluck_missing_source() {
     let mut planner = Planner::new();
@@ -982,6 +1522,7 @@ fn test_pluck_estimated_rows() {
         table_ref,
         cursor: None,
         filter: None,
+        projection: None,
         cost: 1.0,
         estimated_rows: 100.0,
     };
@@ -1006,6 +1547,7 @@ fn test_pluck_node_partial_eq() {
         table_ref: table_ref.clone(),
         cursor: None,
         filter: None,
+        projection: None,
         cost: 1.0,
         estimated_rows: 100.0,
     };
@@ -1137,6 +1679,7 @@ fn test_without_estimated_rows() {
         table_ref,
         cursor: None,
         filter: None,
+        projection: None,
         cost: 1.0,
         estimated_rows: 100.0,
     };
@@ -1161,6 +1704,7 @@ fn test_without_node_partial_eq() {
         table_ref: table_ref.clone(),
         cursor: None,
         filter: None,
+        projection: None,
         cost: 1.0,
         estimated_rows: 100.0,
     };
@@ -1304,3 +1848,596 @@ fn test_without_with_complex_source() {
         _ => panic!("Expected Without node"),
     }
 }
+
+struct TestStatistics(crate::storage::statistics::TableStatistics);
+
+impl crate::storage::statistics::StatisticsProvider for TestStatistics {
+    fn table_statistics(
+        &self,
+        db: &str,
+        table: &str,
+    ) -> Option<crate::storage::statistics::TableStatistics> {
+        (db == "test_db" && table == "test_table").then(|| self.0.clone())
+    }
+
+    fn table_count(&self, _db: &str) -> Option<u64> {
+        Some(3)
+    }
+
+    fn database_count(&self) -> Option<u64> {
+        Some(2)
+    }
+}
+
+fn create_statistics_planner() -> Planner {
+    let mut builder = crate::storage::statistics::StatisticsBuilder::new();
+    for i in 0..1000 {
+        let doc = Document::from([
+            ("age".to_string(), create_test_datum_int(i)),
+            (
+                "status".to_string(),
+                create_test_datum_string(&format!("s{}", i % 4)),
+            ),
+        ]);
+        builder.add(&doc);
+    }
+    Planner::with_statistics(std::sync::Arc::new(TestStatistics(builder.build())))
+}
+
+fn create_test_filter_query(predicate: Expression) -> Query {
+    Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Filter(Box::new(Filter {
+            source: Some(Box::new(create_test_table_query())),
+            predicate: Some(Box::new(predicate)),
+        }))),
+    }
+}
+
+#[test]
+fn test_statistics_drive_row_estimates() {
+    let mut planner = create_statistics_planner();
+
+    let plan = planner.plan(&create_test_table_query()).unwrap();
+    assert_eq!(plan.estimated_rows(), 1000.0);
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::DatabaseList(DatabaseList {})),
+    };
+    assert_eq!(planner.plan(&query).unwrap().estimated_rows(), 2.0);
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::TableList(TableList { database: None })),
+    };
+    assert_eq!(planner.plan(&query).unwrap().estimated_rows(), 3.0);
+}
+
+#[test]
+fn test_statistics_drive_selectivity() {
+    let mut planner = create_statistics_planner();
+
+    let predicate = create_test_binary_expr(
+        create_test_field_expr("status"),
+        binary_op::Operator::Eq,
+        create_test_literal_expr(create_test_datum_string("s1")),
+    );
+    let plan = planner.plan(&create_test_filter_query(predicate)).unwrap();
+    assert!(matches!(plan, PlanNode::TableScan { .. }));
+    assert_eq!(plan.estimated_rows(), 250.0);
+
+    // Literal on the left mirrors the comparison: 200 > age
+    let predicate = create_test_binary_expr(
+        create_test_literal_expr(create_test_datum_int(200)),
+        binary_op::Operator::Gt,
+        create_test_field_expr("age"),
+    );
+    let plan = planner.plan(&create_test_filter_query(predicate)).unwrap();
+    assert!((plan.estimated_rows() - 200.0).abs() < 20.0);
+
+    // Fields without statistics fall back to the default guesses
+    let predicate = create_test_binary_expr(
+        create_test_field_expr("unknown"),
+        binary_op::Operator::Eq,
+        create_test_literal_expr(create_test_datum_int(1)),
+    );
+    let plan = planner.plan(&create_test_filter_query(predicate)).unwrap();
+    assert_eq!(plan.estimated_rows(), 100.0);
+}
+
+#[test]
+fn test_build_plan_analyze() {
+    let mut planner = Planner::new();
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Analyze(Analyze {
+            table: Some(create_test_table_ref()),
+            sample_size: Some(500),
+        })),
+    };
+    let plan = planner.plan(&query).unwrap();
+    match plan {
+        PlanNode::Analyze {
+            table_ref,
+            sample_size,
+            ..
+        } => {
+            assert_eq!(table_ref.name, "test_table");
+            assert_eq!(sample_size, Some(500));
+        }
+        _ => panic!("Expected Analyze node"),
+    }
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Analyze(Analyze {
+            table: None,
+            sample_size: None,
+        })),
+    };
+    assert!(matches!(
+        planner.plan(&query),
+        Err(PlanError::MissingTableReference)
+    ));
+}
+
+#[test]
+fn test_build_plan_rebalance() {
+    let mut planner = Planner::new();
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Rebalance(Rebalance {
+            table: Some(create_test_table_ref()),
+            shards: Some(8),
+        })),
+    };
+    let plan = planner.plan(&query).unwrap();
+    match &plan {
+        PlanNode::Rebalance {
+            table_ref, shards, ..
+        } => {
+            assert_eq!(table_ref.name, "test_table");
+            assert_eq!(*shards, Some(8));
+        }
+        _ => panic!("Expected Rebalance node"),
+    }
+    let explanation = planner.explain(&plan);
+    assert_eq!(explanation.nodes[0].operation, "Rebalance");
+    assert!(
+        explanation.nodes[0]
+            .properties
+            .contains(&("Shards".to_string(), "8".to_string()))
+    );
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Rebalance(Rebalance {
+            table: None,
+            shards: None,
+        })),
+    };
+    assert!(matches!(
+        planner.plan(&query),
+        Err(PlanError::MissingTableReference)
+    ));
+}
+
+#[test]
+fn test_build_plan_index_operations() {
+    let mut planner = Planner::new();
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::IndexCreate(Box::new(IndexCreate {
+            table: Some(create_test_table_ref()),
+            name: "body".to_string(),
+            kind: Some(index_create::Kind::FullText(FullTextIndex {
+                fields: vec![FieldRef {
+                    path: vec!["body".to_string()],
+                    separator: ".".to_string(),
+                }],
+                stemming: true,
+            })),
+        }))),
+    };
+    let plan = planner.plan(&query).unwrap();
+    assert!(matches!(&plan, PlanNode::CreateIndex { name, .. } if name == "body"));
+    let explanation = planner.explain(&plan);
+    assert_eq!(explanation.nodes[0].operation, "CreateIndex");
+    assert!(
+        explanation.nodes[0]
+            .properties
+            .contains(&("Fields".to_string(), "body".to_string()))
+    );
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::IndexCreate(Box::new(IndexCreate {
+            table: Some(create_test_table_ref()),
+            name: "body".to_string(),
+            kind: None,
+        }))),
+    };
+    assert!(matches!(
+        planner.plan(&query),
+        Err(PlanError::InvalidExpression(_))
+    ));
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::IndexDrop(IndexDrop {
+            table: Some(create_test_table_ref()),
+            name: "body".to_string(),
+        })),
+    };
+    let plan = planner.plan(&query).unwrap();
+    assert!(matches!(&plan, PlanNode::DropIndex { name, .. } if name == "body"));
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::IndexList(IndexList { table: None })),
+    };
+    assert!(matches!(
+        planner.plan(&query),
+        Err(PlanError::MissingTableReference)
+    ));
+}
+
+#[test]
+fn test_build_plan_search() {
+    let mut planner = Planner::new();
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Limit(Box::new(Limit {
+            source: Some(Box::new(Query {
+                options: None,
+                cursor: None,
+                kind: Some(query::Kind::Search(Search {
+                    table: Some(create_test_table_ref()),
+                    index: "body".to_string(),
+                    query: "rust databases".to_string(),
+                })),
+            })),
+            count: 5,
+        }))),
+    };
+    let plan = planner.plan(&query).unwrap();
+    let PlanNode::Limit { source, .. } = &plan else {
+        panic!("Expected Limit node");
+    };
+    match source.as_ref() {
+        PlanNode::Search {
+            table_ref,
+            index,
+            query,
+            ..
+        } => {
+            assert_eq!(table_ref.name, "test_table");
+            assert_eq!(index, "body");
+            assert_eq!(query, "rust databases");
+        }
+        _ => panic!("Expected Search node"),
+    }
+
+    let explanation = planner.explain(&plan);
+    assert_eq!(explanation.nodes[1].operation, "Search");
+    assert!(
+        explanation.nodes[1]
+            .properties
+            .contains(&("Query".to_string(), "rust databases".to_string()))
+    );
+}
+
+#[test]
+fn test_build_plan_geo_queries() {
+    let mut planner = Planner::new();
+    let point = Point {
+        longitude: 2.3522,
+        latitude: 48.8566,
+    };
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::GetIntersecting(GetIntersecting {
+            table: Some(create_test_table_ref()),
+            index: "location".to_string(),
+            geometry: Some(Geometry {
+                kind: Some(geometry::Kind::Point(point)),
+            }),
+        })),
+    };
+    let plan = planner.plan(&query).unwrap();
+    match &plan {
+        PlanNode::GetIntersecting {
+            table_ref, index, ..
+        } => {
+            assert_eq!(table_ref.name, "test_table");
+            assert_eq!(index, "location");
+        }
+        _ => panic!("Expected GetIntersecting node"),
+    }
+    let explanation = planner.explain(&plan);
+    assert_eq!(explanation.nodes[0].operation, "GetIntersecting");
+    assert!(
+        explanation.nodes[0]
+            .properties
+            .contains(&("Geometry".to_string(), "POINT(2.3522 48.8566)".to_string()))
+    );
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::GetNearest(GetNearest {
+            table: Some(create_test_table_ref()),
+            index: "location".to_string(),
+            point: Some(point),
+            max_dist: Some(5000.0),
+            max_results: Some(3),
+        })),
+    };
+    let plan = planner.plan(&query).unwrap();
+    match &plan {
+        PlanNode::GetNearest {
+            max_dist,
+            max_results,
+            estimated_rows,
+            ..
+        } => {
+            assert_eq!(*max_dist, Some(5000.0));
+            assert_eq!(*max_results, Some(3));
+            assert!(*estimated_rows <= 3.0);
+        }
+        _ => panic!("Expected GetNearest node"),
+    }
+    let explanation = planner.explain(&plan);
+    assert_eq!(explanation.nodes[0].operation, "GetNearest");
+    assert!(
+        explanation.nodes[0]
+            .properties
+            .contains(&("MaxResults".to_string(), "3".to_string()))
+    );
+
+    let query = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::GetNearest(GetNearest {
+            table: Some(create_test_table_ref()),
+            index: "location".to_string(),
+            point: None,
+            max_dist: None,
+            max_results: None,
+        })),
+    };
+    assert!(matches!(
+        planner.plan(&query),
+        Err(PlanError::InvalidExpression(_))
+    ));
+}
+
+#[test]
+fn test_build_plan_nearest() {
+    let mut planner = Planner::new();
+    let nearest = |k: u32, filter: Option<Expression>| Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Nearest(Box::new(Nearest {
+            table: Some(create_test_table_ref()),
+            index: "embedding".to_string(),
+            vector: vec![0.1, 0.2, 0.3],
+            k,
+            filter: filter.map(Box::new),
+            probes: Some(8),
+        }))),
+    };
+    let filter = Expression {
+        expr: Some(expression::Expr::Binary(Box::new(BinaryOp {
+            op: binary_op::Operator::Eq as i32,
+            left: Some(Box::new(Expression {
+                expr: Some(expression::Expr::Field(FieldRef {
+                    path: vec!["tag".to_string()],
+                    separator: ".".to_string(),
+                })),
+            })),
+            right: Some(Box::new(Expression {
+                expr: Some(expression::Expr::Literal(Datum {
+                    value: Some(datum::Value::String("news".to_string())),
+                })),
+            })),
+        }))),
+    };
+
+    let plan = planner.plan(&nearest(5, None)).unwrap();
+    match &plan {
+        PlanNode::Nearest {
+            index,
+            vector,
+            k,
+            filter,
+            probes,
+            estimated_rows,
+            ..
+        } => {
+            assert_eq!(index, "embedding");
+            assert_eq!(vector.len(), 3);
+            assert_eq!(*k, 5);
+            assert!(filter.is_none());
+            assert_eq!(*probes, Some(8));
+            assert_eq!(*estimated_rows, 5.0);
+        }
+        _ => panic!("Expected Nearest node"),
+    }
+
+    // A filter makes the search compare every vector
+    let filtered = planner.plan(&nearest(5, Some(filter))).unwrap();
+    assert!(filtered.cost() > plan.cost());
+    let explanation = planner.explain(&filtered);
+    assert_eq!(explanation.nodes[0].operation, "Nearest");
+    assert!(
+        explanation.nodes[0]
+            .properties
+            .iter()
+            .any(|(name, _)| name == "Filter")
+    );
+    assert!(
+        explanation.nodes[0]
+            .properties
+            .contains(&("K".to_string(), "5".to_string()))
+    );
+
+    assert!(matches!(
+        planner.plan(&nearest(0, None)),
+        Err(PlanError::InvalidExpression(_))
+    ));
+}
+
+#[test]
+fn test_build_plan_secondary_index_queries() {
+    let mut planner = Planner::new();
+    let get_all = Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::GetAll(Box::new(GetAll {
+            source: Some(Box::new(create_test_table_query())),
+            keys: vec![
+                create_test_datum_string("rust"),
+                create_test_datum_string("go"),
+            ],
+            index: "tags".to_string(),
+            key_expressions: vec![],
+        }))),
+    };
+    match planner.plan(&get_all).unwrap() {
+        PlanNode::GetAllByIndex { index, values, .. } => {
+            assert_eq!(index, "tags");
+            assert_eq!(values.len(), 2);
+        }
+        _ => panic!("Expected GetAllByIndex node"),
+    }
+
+    let between = |lower: Option<Datum>, upper: Option<Datum>| Query {
+        options: None,
+        cursor: None,
+        kind: Some(query::Kind::Between(Between {
+            table: Some(create_test_table_ref()),
+            index: "created".to_string(),
+            lower,
+            upper,
+        })),
+    };
+    let bounded = planner
+        .plan(&between(
+            Some(create_test_datum_int(10)),
+            Some(create_test_datum_int(20)),
+        ))
+        .unwrap();
+    match &bounded {
+        PlanNode::Between {
+            index,
+            lower,
+            upper,
+            ..
+        } => {
+            assert_eq!(index, "created");
+            assert_eq!(lower, &Some(create_test_datum_int(10)));
+            assert_eq!(upper, &Some(create_test_datum_int(20)));
+        }
+        _ => panic!("Expected Between node"),
+    }
+
+    // An unbounded range reads the whole index
+    let unbounded = planner.plan(&between(None, None)).unwrap();
+    assert!(unbounded.estimated_rows() > bounded.estimated_rows());
+    let explanation = planner.explain(&bounded);
+    assert_eq!(explanation.nodes[0].operation, "Between");
+    assert!(
+        explanation.nodes[0]
+            .properties
+            .contains(&("Index".to_string(), "created".to_string()))
+    );
+}
+
+#[test]
+fn test_build_plan_document_functions() {
+    let planner = Planner::new();
+    let table = || {
+        Some(Box::new(Query {
+            options: None,
+            cursor: None,
+            kind: Some(query::Kind::Table(Table {
+                table: Some(create_test_table_ref()),
+            })),
+        }))
+    };
+    let plan = |kind| {
+        Planner::new().plan(&Query {
+            options: None,
+            cursor: None,
+            kind: Some(kind),
+        })
+    };
+
+    let has_fields = plan(query::Kind::HasFields(Box::new(HasFields {
+        source: table(),
+        fields: vec![FieldRef {
+            path: vec!["name".to_string()],
+            separator: ".".to_string(),
+        }],
+    })))
+    .unwrap();
+    match &has_fields {
+        PlanNode::DocumentFunction {
+            source,
+            op,
+            args,
+            fields,
+            cost,
+        } => {
+            assert!(matches!(source.as_ref(), PlanNode::TableScan { .. }));
+            assert_eq!(*op, document_op::Operator::HasFields);
+            assert!(args.is_empty());
+            assert_eq!(fields[0].path, vec!["name".to_string()]);
+            assert_eq!(*cost, source.cost());
+        }
+        _ => panic!("Expected DocumentFunction node"),
+    }
+    let explanation = planner.explain(&has_fields);
+    assert_eq!(explanation.nodes[0].operation, "HasFields");
+    assert_eq!(explanation.nodes[1].operation, "TableScan");
+
+    // The type to coerce to becomes a literal argument
+    let coerce_to = plan(query::Kind::CoerceTo(Box::new(CoerceTo {
+        source: table(),
+        r#type: "array".to_string(),
+    })))
+    .unwrap();
+    let PlanNode::DocumentFunction { op, args, .. } = &coerce_to else {
+        panic!("Expected DocumentFunction node");
+    };
+    assert_eq!(*op, document_op::Operator::CoerceTo);
+    assert_eq!(
+        args[0].expr,
+        Some(expression::Expr::Literal(create_test_datum_string("array")))
+    );
+
+    // Functions need a source, and Default a value
+    assert!(plan(query::Kind::Keys(Box::new(Keys { source: None }))).is_err());
+    assert!(
+        plan(query::Kind::Default(Box::new(DefaultValue {
+            source: table(),
+            value: None,
+        })))
+        .is_err()
+    );
+}
@@ -0,0 +1,106 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use prost::Message;
+use rulodb::ast::proto;
+use rulodb::storage::replication::MAX_BATCHES_PER_REQUEST;
+use rulodb::{DefaultStorage, StorageBackend};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Delay before reconnecting to a primary that could not be reached.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Follow `primary`, applying its write batches to `db` until the process exits.
+pub async fn follow(
+    db: Arc<dyn StorageBackend + Send + Sync>,
+    primary: String,
+    poll_interval: Duration,
+) {
+    loop {
+        match TcpStream::connect(&primary).await {
+            Ok(stream) => {
+                log::info!("replicating from primary {primary}");
+                if let Err(e) = tail(db.as_ref(), stream, poll_interval).await {
+                    log::error!("replication from {primary} failed: {e}");
+                }
+            }
+            Err(e) => log::warn!("failed to connect to primary {primary}: {e}"),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Keep the secondary instance `db` caught up with its primary until the process exits.
+pub async fn catch_up(db: Arc<DefaultStorage>, poll_interval: Duration) {
+    loop {
+        if let Err(e) = db.catch_up_with_primary().await {
+            log::error!("failed to catch up with the primary: {e}");
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+async fn tail(
+    db: &(dyn StorageBackend + Send + Sync),
+    mut stream: TcpStream,
+    poll_interval: Duration,
+) -> anyhow::Result<()> {
+    let mut request_id = 0u64;
+
+    loop {
+        request_id += 1;
+        let from_sequence = db.replication_status().applied_sequence + 1;
+        let request = proto::ReplicationRequest {
+            from_sequence,
+            max_batches: u32::try_from(MAX_BATCHES_PER_REQUEST)?,
+        };
+        let envelope = proto::Envelope {
+            version: proto::ProtocolVersion::Version1.into(),
+            query_id: format!("replication-{request_id}"),
+            r#type: proto::MessageType::Replicate.into(),
+            payload: request.encode_to_vec(),
+        };
+
+        let updates = exchange(&mut stream, &envelope).await?;
+        let caught_up = updates.batches.is_empty();
+        db.apply_updates(updates.into()).await?;
+
+        if caught_up {
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+}
+
+/// Send a replication request and read back the primary's updates.
+async fn exchange(
+    stream: &mut TcpStream,
+    envelope: &proto::Envelope,
+) -> anyhow::Result<proto::ReplicationUpdates> {
+    let payload = envelope.encode_to_vec();
+    let mut out: Vec<u8> = Vec::new();
+    WriteBytesExt::write_u32::<BigEndian>(&mut out, u32::try_from(payload.len())?)?;
+    out.extend(payload);
+    stream.write_all(&out).await?;
+
+    let mut len_buf = [0u8; 4];
+    stream.read_exact(&mut len_buf).await?;
+    let mut buffer = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+    stream.read_exact(&mut buffer).await?;
+
+    let response = proto::Envelope::decode(buffer.as_slice())?;
+    match proto::MessageType::try_from(response.r#type) {
+        Ok(proto::MessageType::Response) => {
+            match proto::Response::decode(response.payload.as_slice())?.result {
+                Some(proto::response::Result::Replication(updates)) => Ok(updates),
+                _ => anyhow::bail!("unexpected response to a replication request"),
+            }
+        }
+        Ok(proto::MessageType::Error) => {
+            let error = proto::ErrorInfo::decode(response.payload.as_slice())?;
+            anyhow::bail!("{}", error.message)
+        }
+        _ => anyhow::bail!("invalid message type: {}", response.r#type),
+    }
+}
@@ -1,41 +1,71 @@
+use crate::cli::ServerConfig;
 use byteorder::{BigEndian, WriteBytesExt};
 use prost::Message;
 use rulodb::ast::proto;
-use rulodb::{Evaluator, Planner, StorageBackend, parse_query};
+use rulodb::cluster::{ClusterNode, decode_message, encode_message};
+use rulodb::evaluator::CursorSnapshots;
+use rulodb::planner::{DEFAULT_PREPARED_QUERIES, PreparedQueries};
+use rulodb::storage::replication::MAX_BATCHES_PER_REQUEST;
+use rulodb::{Evaluator, PlanNode, Planner, StorageBackend, parse_query};
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Semaphore, mpsc};
 
 const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub async fn start_server(
     db: Arc<dyn StorageBackend + Send + Sync>,
-    address: &str,
+    cluster: Option<Arc<ClusterNode>>,
+    config: &ServerConfig,
 ) -> anyhow::Result<()> {
+    let address = &config.address;
     let listener = TcpListener::bind(address).await?;
     log::info!("server listening on {address}");
+    let prepared = Arc::new(PreparedQueries::new(db.clone(), DEFAULT_PREPARED_QUERIES));
+    let max_inflight = config.max_inflight_queries.max(1);
 
     loop {
         let (stream, _) = listener.accept().await?;
         let db = db.clone();
+        let cluster = cluster.clone();
+        let prepared = prepared.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_client(db, stream).await {
+            if let Err(e) = handle_client(db, cluster, prepared, max_inflight, stream).await {
                 log::error!("client error: {e}");
             }
         });
     }
 }
 
+/// Serve the queries of a connection. They run concurrently, up to `max_inflight` at a
+/// time, and each is answered as soon as it completes, the client telling answers apart
+/// by their query IDs. No more requests are read while that many are running, and those
+/// that completed wait for their answers to be written, so a client that doesn't read
+/// them is not given more work. The snapshots its cursors still hold are released once
+/// it closes.
 async fn handle_client(
     db: Arc<dyn StorageBackend + Send + Sync>,
+    cluster: Option<Arc<ClusterNode>>,
+    prepared: Arc<PreparedQueries>,
+    max_inflight: usize,
     stream: TcpStream,
 ) -> anyhow::Result<()> {
     let peer = stream.peer_addr()?;
-    let (read_half, mut write_half) = stream.into_split();
+    let (read_half, write_half) = stream.into_split();
     let mut reader = BufReader::new(read_half);
 
+    let inflight = Arc::new(Semaphore::new(max_inflight));
+    let (responses, outgoing) = mpsc::channel(max_inflight);
+    let writer = tokio::spawn(write_responses(write_half, outgoing, peer));
+    let snapshots = Arc::new(CursorSnapshots::new(db.clone()));
+
     loop {
+        let permit = inflight.clone().acquire_owned().await?;
+
         // Read length prefix (big-endian 4-byte length)
         let mut len_buf = [0u8; 4];
         if reader.read_exact(&mut len_buf).await.is_err() {
@@ -49,15 +79,41 @@ async fn handle_client(
             break;
         }
 
-        // Process the envelope message and get response envelope
-        let response_envelope = process_envelope_message(db.clone(), &buffer)
-            .await
-            .unwrap_or_else(|err| {
-                log::error!("failed to process envelope from {peer}: {err}");
-                // Create error response envelope with default query ID
-                create_error_envelope("unknown".to_string(), &err.to_string())
-            });
+        let db = db.clone();
+        let cluster = cluster.clone();
+        let prepared = prepared.clone();
+        let snapshots = snapshots.clone();
+        let responses = responses.clone();
+        tokio::spawn(async move {
+            // Process the envelope message and get response envelope
+            let response_envelope =
+                process_envelope_message(db, cluster.as_ref(), &prepared, &snapshots, &buffer)
+                    .await
+                    .unwrap_or_else(|err| {
+                        log::error!("failed to process envelope from {peer}: {err}");
+                        // Create error response envelope with default query ID
+                        create_error_envelope("unknown".to_string(), &err.to_string())
+                    });
+            // The writer is gone only once the connection is
+            let _ = responses.send(response_envelope).await;
+            drop(permit);
+        });
+    }
+
+    // Answer the queries still running before closing the connection
+    drop(responses);
+    let written = writer.await?;
+    snapshots.release_all().await;
+    written
+}
 
+/// Write the responses of a connection's queries in the order they complete
+async fn write_responses(
+    mut write_half: OwnedWriteHalf,
+    mut outgoing: mpsc::Receiver<proto::Envelope>,
+    peer: SocketAddr,
+) -> anyhow::Result<()> {
+    while let Some(response_envelope) = outgoing.recv().await {
         // Serialize the response envelope
         let mut envelope_payload = Vec::new();
         if let Err(e) = response_envelope.encode(&mut envelope_payload) {
@@ -86,14 +142,22 @@ async fn handle_client(
 
 async fn process_envelope_message(
     db: Arc<dyn StorageBackend + Send + Sync>,
+    cluster: Option<&Arc<ClusterNode>>,
+    prepared: &PreparedQueries,
+    snapshots: &Arc<CursorSnapshots>,
     message: &[u8],
 ) -> anyhow::Result<proto::Envelope> {
     let envelope = proto::Envelope::decode(message)?;
 
     match proto::MessageType::try_from(envelope.r#type) {
-        Ok(proto::MessageType::Query) => {
-            // Process the query from the payload
-            match process_query(db, &envelope.payload).await {
+        Ok(message_type @ (proto::MessageType::Query | proto::MessageType::Execute)) => {
+            // Process the query, or the prepared query, from the payload
+            let query_result = if message_type == proto::MessageType::Query {
+                process_query(db, snapshots, &envelope.payload).await
+            } else {
+                process_execute(db, prepared, snapshots, &envelope.payload).await
+            };
+            match query_result {
                 Ok(query_result) => {
                     // Create proper Response wrapper
                     let response = create_response_wrapper(&envelope.query_id, query_result);
@@ -118,6 +182,46 @@ async fn process_envelope_message(
                 }
             }
         }
+        Ok(proto::MessageType::Prepare) => match process_prepare(prepared, &envelope.payload) {
+            Ok(handle) => {
+                let result = proto::response::Result::Prepared(proto::Prepared { handle });
+                Ok(create_response_envelope(envelope.query_id, result))
+            }
+            Err(err) => {
+                log::error!("Query preparation failed: {err}");
+                Ok(create_error_envelope(envelope.query_id, &err.to_string()))
+            }
+        },
+        Ok(proto::MessageType::Replicate) => {
+            match process_replication_request(db.as_ref(), &envelope.payload).await {
+                Ok(updates) => {
+                    let result = proto::response::Result::Replication(updates);
+                    Ok(create_response_envelope(envelope.query_id, result))
+                }
+                Err(err) => {
+                    log::error!("Replication request failed: {err}");
+                    Ok(create_error_envelope(envelope.query_id, &err.to_string()))
+                }
+            }
+        }
+        Ok(proto::MessageType::Raft) => {
+            let Some(cluster) = cluster else {
+                return Ok(create_error_envelope(
+                    envelope.query_id,
+                    "This node is not part of a cluster",
+                ));
+            };
+            let (from, request) = decode_message(&envelope.payload)?;
+            match cluster.handle_request(from, request).await {
+                Ok(response) => Ok(proto::Envelope {
+                    version: proto::ProtocolVersion::Version1.into(),
+                    query_id: envelope.query_id,
+                    r#type: proto::MessageType::Raft.into(),
+                    payload: encode_message(&response)?,
+                }),
+                Err(err) => Ok(create_error_envelope(envelope.query_id, &err.to_string())),
+            }
+        }
         Ok(
             proto::MessageType::AuthInit
             | proto::MessageType::AuthResponse
@@ -140,42 +244,95 @@ async fn process_envelope_message(
 
 async fn process_query(
     db: Arc<dyn StorageBackend + Send + Sync>,
+    snapshots: &Arc<CursorSnapshots>,
     payload: &[u8],
 ) -> Result<proto::query_result::Result, Box<dyn std::error::Error + Send + Sync>> {
     let query = parse_query(payload)?;
 
-    let mut planner = Planner::new();
+    let mut planner = Planner::with_statistics(db.clone());
     let plan = planner.plan(&query)?;
     let plan = planner.optimize(plan)?;
 
     let explanation = planner.explain(&plan);
     log::debug!("Plan explanation:\n{explanation}");
 
+    evaluate_plan(db, snapshots, &plan, query.options.as_ref(), query.cursor).await
+}
+
+fn process_prepare(
+    prepared: &PreparedQueries,
+    payload: &[u8],
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let query = parse_query(payload)?;
+    Ok(prepared.prepare(query)?)
+}
+
+async fn process_execute(
+    db: Arc<dyn StorageBackend + Send + Sync>,
+    prepared: &PreparedQueries,
+    snapshots: &Arc<CursorSnapshots>,
+    payload: &[u8],
+) -> Result<proto::query_result::Result, Box<dyn std::error::Error + Send + Sync>> {
+    let execute = proto::Execute::decode(payload)?;
+    let bound = prepared.bind(&execute.handle, &execute.parameters)?;
+
+    let cursor = execute.cursor.or(bound.cursor);
+
+    evaluate_plan(db, snapshots, &bound.plan, bound.options.as_ref(), cursor).await
+}
+
+async fn evaluate_plan(
+    db: Arc<dyn StorageBackend + Send + Sync>,
+    snapshots: &Arc<CursorSnapshots>,
+    plan: &PlanNode,
+    options: Option<&proto::QueryOptions>,
+    cursor: Option<proto::Cursor>,
+) -> Result<proto::query_result::Result, Box<dyn std::error::Error + Send + Sync>> {
     let mut evaluator = Evaluator::new(db);
-    let result = if let Some(cursor) = query.cursor.clone() {
-        evaluator.eval_with_cursor(&plan, Some(cursor)).await?
+    evaluator.set_cursor_snapshots(snapshots.clone());
+    if let Some(options) = options {
+        evaluator.set_read_mode(options.read_mode());
+    }
+    let result = if let Some(cursor) = cursor {
+        evaluator.eval_with_cursor(plan, Some(cursor)).await?
     } else {
-        evaluator.eval(&plan).await?
+        evaluator.eval(plan).await?
     };
 
     Ok(result.result)
 }
 
-fn create_response_wrapper(
-    query_id: &str,
-    query_result: proto::query_result::Result,
-) -> proto::Response {
+async fn process_replication_request(
+    db: &(dyn StorageBackend + Send + Sync),
+    payload: &[u8],
+) -> anyhow::Result<proto::ReplicationUpdates> {
+    let request = proto::ReplicationRequest::decode(payload)?;
+    let max_batches = usize::try_from(request.max_batches)
+        .unwrap_or(MAX_BATCHES_PER_REQUEST)
+        .clamp(1, MAX_BATCHES_PER_REQUEST);
+
+    let updates = db.updates_since(request.from_sequence, max_batches).await?;
+    Ok(updates.into())
+}
+
+fn create_response_metadata(query_id: &str) -> proto::ResponseMetadata {
     use std::time::{SystemTime, UNIX_EPOCH};
 
-    // Create metadata
-    let metadata = proto::ResponseMetadata {
+    proto::ResponseMetadata {
         query_id: query_id.to_string(),
         timestamp: SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs(),
         server_version: SERVER_VERSION.to_string(),
-    };
+    }
+}
+
+fn create_response_wrapper(
+    query_id: &str,
+    query_result: proto::query_result::Result,
+) -> proto::Response {
+    let metadata = create_response_metadata(query_id);
 
     let proto_query_result = proto::QueryResult {
         result: Some(query_result),
@@ -187,6 +344,20 @@ fn create_response_wrapper(
     }
 }
 
+fn create_response_envelope(query_id: String, result: proto::response::Result) -> proto::Envelope {
+    let response = proto::Response {
+        metadata: Some(create_response_metadata(&query_id)),
+        result: Some(result),
+    };
+
+    proto::Envelope {
+        version: proto::ProtocolVersion::Version1.into(),
+        query_id,
+        r#type: proto::MessageType::Response.into(),
+        payload: response.encode_to_vec(),
+    }
+}
+
 fn create_error_envelope(query_id: String, error_message: &str) -> proto::Envelope {
     let error_info = proto::ErrorInfo {
         code: 1, // Generic error code
@@ -271,7 +442,11 @@ mod tests {
             ..Default::default()
         };
         let storage = Arc::new(DefaultStorage::open(&config).unwrap());
-        let handle = tokio::spawn(start_server(storage, "127.0.0.1:0"));
+        let server_config = ServerConfig {
+            address: "127.0.0.1:0".to_string(),
+            max_inflight_queries: 4,
+        };
+        let handle = tokio::spawn(async move { start_server(storage, None, &server_config).await });
 
         tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
         handle.abort();
@@ -296,6 +471,8 @@ mod tests {
         assert_eq!(proto::MessageType::Response as i32, 1);
         assert_eq!(proto::MessageType::Error as i32, 2);
         assert_eq!(proto::MessageType::AuthInit as i32, 3);
+        assert_eq!(proto::MessageType::Prepare as i32, 19);
+        assert_eq!(proto::MessageType::Execute as i32, 20);
 
         assert_eq!(
             proto::MessageType::try_from(0).unwrap(),
@@ -1,20 +1,46 @@
-use crate::ast::{Document, Predicate};
+pub mod encoding;
+mod group_commit;
+pub mod index;
+pub mod replication;
+pub mod sharding;
+mod snapshot;
+pub mod statistics;
+
+use crate::ast::{Datum, Document, Predicate, PredicateError};
+use crate::cluster::{ClusterStatus, MembershipChange, NodeId};
+use crate::geo::{Point, Shape};
 use async_trait::async_trait;
+use encoding::{EncodedDocument, encode_document};
+use group_commit::GroupCommit;
+use index::{IndexDefinition, IndexKind, IndexRegistry, index_cf_name, is_index_name};
+use index::{fulltext, geo, secondary, vector};
+use replication::{
+    APPLIED_SEQUENCE_KEY, ReplicationRole, ReplicationState, ReplicationStatus, ReplicationUpdates,
+    WalBatch, WalOperation, WalRetention, decode_write_batch,
+};
 use rocksdb::{
-    BlockBasedOptions, Cache, ColumnFamilyDescriptor, DB, DBCompactionStyle, DBCompressionType,
-    DBWithThreadMode, Direction, IteratorMode, MultiThreaded, Options, ReadOptions, SliceTransform,
-    WriteBatch, WriteOptions,
+    AsColumnFamilyRef, BlockBasedOptions, BoundColumnFamily, Cache, ColumnFamilyDescriptor, DB,
+    DBCompactionStyle, DBCompressionType, DBWithThreadMode, DataBlockIndexType, Direction,
+    IteratorMode, MultiThreaded, Options, ReadOptions, WriteBatch, WriteOptions,
 };
 use serde::{Deserialize, Serialize};
+use sharding::{
+    MAX_SHARDS, RebalanceSummary, ShardMap, ShardRange, TableShards, balanced_positions,
+    is_shard_name, is_shard_of, shard_table_name, uniform_boundaries,
+};
+use snapshot::{PinnedSnapshot, SNAPSHOT_IDLE_TIMEOUT, SnapshotRegistry};
+use statistics::{StatisticsBuilder, StatisticsProvider, TableStatistics};
 use std::collections::{HashMap, HashSet};
 use std::sync::{
-    Arc, RwLock,
-    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, RwLock,
+    atomic::{AtomicU64, AtomicUsize, Ordering},
 };
 use tokio::sync::{Semaphore, mpsc};
 use tokio::task::spawn_blocking;
 use tokio_stream::wrappers::ReceiverStream;
 
+pub use snapshot::SnapshotId;
+
 /// The system database name, used for internal metadata storage.
 const SYSTEM_DATABASE: &str = "__system__";
 
@@ -30,6 +56,31 @@ const MAX_CONCURRENT_OPERATIONS: usize = 1000;
 /// Cache size for frequently accessed column family handles
 const CF_CACHE_SIZE: usize = 1024;
 
+/// Block size used by tables that don't configure one.
+const DEFAULT_BLOCK_SIZE: usize = 32 * 1024;
+
+/// Block size used by scan-optimised tables that don't configure one.
+const SCAN_BLOCK_SIZE: usize = 128 * 1024;
+
+/// Smallest and largest block size a table may configure.
+const MIN_BLOCK_SIZE: usize = 1024;
+const MAX_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Bloom filter bits per key used by tables that don't configure it.
+const DEFAULT_BLOOM_BITS_PER_KEY: f64 = 10.0;
+
+/// Prefix of the keys table statistics are stored under in the meta table.
+const STATISTICS_KEY_PREFIX: &str = "statistics:";
+
+/// Prefix of the keys shard maps of sharded tables are stored under in the meta table.
+const SHARD_MAP_KEY_PREFIX: &str = "shards:";
+
+/// Key of the last cluster log entry applied to the database, in the meta table.
+const APPLIED_INDEX_KEY: &str = "cluster:applied_index";
+
+/// RocksDB property holding the estimated number of keys in a column family.
+const ESTIMATE_NUM_KEYS: &str = "rocksdb.estimate-num-keys";
+
 /// List of system tables that are reserved and cannot be created or dropped by users.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum SystemTable {
@@ -74,6 +125,28 @@ pub enum StorageError {
     MissingColumnFamily(String),
     InvalidDatabaseName(String),
     InvalidTableName(String),
+    InvalidFieldName(String),
+    CorruptDocument(String),
+    InvalidTableOptions(String),
+    SnapshotNotFound(SnapshotId),
+    ReadOnlyReplica,
+    ReadOnlySecondary,
+    CorruptWriteBatch(String),
+    Replication(String),
+    NotLeader(Option<String>),
+    Cluster(String),
+    Sharding(String),
+    Index(String),
+    /// A write would file a second document under a value of a unique index.
+    ConstraintViolation {
+        index: String,
+        field: String,
+        key: String,
+    },
+    /// An update could not be applied to a stored document.
+    InvalidUpdate(String),
+    /// A scan predicate failed on a document.
+    PredicateFailed(PredicateError),
     ResourceExhausted,
 }
 
@@ -88,6 +161,37 @@ impl std::fmt::Display for StorageError {
             Self::MissingColumnFamily(cf) => write!(f, "Missing column family: {cf}"),
             Self::InvalidDatabaseName(db) => write!(f, "Invalid database name: {db}"),
             Self::InvalidTableName(table) => write!(f, "Invalid table name: {table}"),
+            Self::InvalidFieldName(field) => write!(f, "Invalid field name: {field}"),
+            Self::CorruptDocument(msg) => write!(f, "Corrupt document: {msg}"),
+            Self::InvalidTableOptions(msg) => write!(f, "Invalid table options: {msg}"),
+            Self::SnapshotNotFound(id) => write!(f, "Snapshot not found or expired: {id}"),
+            Self::ReadOnlyReplica => write!(f, "Cannot write to a read-only replica"),
+            Self::ReadOnlySecondary => {
+                write!(f, "Cannot write to a read-only secondary instance")
+            }
+            Self::CorruptWriteBatch(msg) => write!(f, "Corrupt write batch: {msg}"),
+            Self::Replication(msg) => write!(f, "Replication error: {msg}"),
+            Self::NotLeader(Some(leader)) => {
+                write!(
+                    f,
+                    "This node is not the cluster leader, the leader is at {leader}"
+                )
+            }
+            Self::NotLeader(None) => {
+                write!(
+                    f,
+                    "This node is not the cluster leader and no leader is known"
+                )
+            }
+            Self::Cluster(msg) => write!(f, "Cluster error: {msg}"),
+            Self::Sharding(msg) => write!(f, "Sharding error: {msg}"),
+            Self::Index(msg) => write!(f, "Index error: {msg}"),
+            Self::ConstraintViolation { index, field, key } => write!(
+                f,
+                "Constraint violation: unique index {index} already holds this value of {field} for document {key}"
+            ),
+            Self::InvalidUpdate(msg) => write!(f, "Invalid update: {msg}"),
+            Self::PredicateFailed(e) => write!(f, "Predicate failed: {e}"),
             Self::ResourceExhausted => {
                 write!(f, "Resource exhausted - too many concurrent operations")
             }
@@ -103,6 +207,7 @@ impl std::error::Error for StorageError {
             Self::InvalidDocument(e) => Some(e),
             Self::EncodeError(e) => Some(e),
             Self::DecodeError(e) => Some(e),
+            Self::PredicateFailed(e) => Some(e.as_ref()),
             _ => None,
         }
     }
@@ -134,6 +239,9 @@ impl From<bincode::error::DecodeError> for StorageError {
 
 pub type Result<T> = std::result::Result<T, StorageError>;
 
+/// Makes the new version of a document from its stored version.
+pub type DocumentUpdate = Arc<dyn Fn(Document) -> Result<Document> + Send + Sync>;
+
 // Cached column family handle to avoid repeated lookups
 #[derive(Clone)]
 struct CachedCF {
@@ -211,22 +319,226 @@ fn get_cf_cache() -> &'static CFCache {
     CF_CACHE.get_or_init(CFCache::new)
 }
 
+/// Top-level document fields a table scan has to decode. Leaving a list unset means
+/// the whole document is needed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanProjection {
+    /// Fields read by the scan predicate.
+    pub predicate_fields: Option<Vec<String>>,
+    /// Fields returned for each matching document.
+    pub output_fields: Option<Vec<String>>,
+}
+
+impl ScanProjection {
+    /// Project a document that has already been fully decoded.
+    pub fn apply(&self, doc: Document) -> Document {
+        match &self.output_fields {
+            Some(fields) => doc
+                .into_iter()
+                .filter(|(name, _)| fields.contains(name))
+                .collect(),
+            None => doc,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct DatabaseConfig {
     // Future database-specific configuration
 }
 
+/// Compression codec applied to a table's data blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum Compression {
+    None,
+    Snappy,
+    Lz4,
+    #[default]
+    Zstd,
+}
+
+impl From<Compression> for DBCompressionType {
+    fn from(value: Compression) -> Self {
+        match value {
+            Compression::None => Self::None,
+            Compression::Snappy => Self::Snappy,
+            Compression::Lz4 => Self::Lz4,
+            Compression::Zstd => Self::Zstd,
+        }
+    }
+}
+
+/// Workload a table's column family is tuned for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum AccessPattern {
+    #[default]
+    Balanced,
+    PointLookup,
+    Scan,
+}
+
+/// Whether a write is acknowledged before or after it reaches disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum Durability {
+    /// Sync the write-ahead log before acknowledging the write.
+    Hard,
+    /// Acknowledge the write once it is in the write-ahead log buffer.
+    #[default]
+    Soft,
+}
+
+/// Storage configuration of a table. It is persisted in the schemas system table and
+/// applied to the table's column family every time the database is opened.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct TableConfig {
+    pub compression: Compression,
+    pub compression_level: Option<i32>,
+    pub bloom_bits_per_key: Option<f64>,
+    pub block_size: Option<usize>,
+    pub access_pattern: AccessPattern,
+    pub durability: Durability,
+}
+
+impl TableConfig {
+    /// Check that the configuration can be applied to a column family.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(level) = self.compression_level {
+            if self.compression != Compression::Zstd {
+                return Err(StorageError::InvalidTableOptions(
+                    "compression level is only supported for zstd".to_string(),
+                ));
+            }
+            if !(1..=22).contains(&level) {
+                return Err(StorageError::InvalidTableOptions(format!(
+                    "zstd compression level must be between 1 and 22, got {level}"
+                )));
+            }
+        }
+
+        if let Some(bits) = self.bloom_bits_per_key {
+            if !bits.is_finite() || !(0.0..=64.0).contains(&bits) {
+                return Err(StorageError::InvalidTableOptions(format!(
+                    "bloom bits per key must be between 0 and 64, got {bits}"
+                )));
+            }
+        }
+
+        if let Some(size) = self.block_size {
+            if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&size) {
+                return Err(StorageError::InvalidTableOptions(format!(
+                    "block size must be between {MIN_BLOCK_SIZE} and {MAX_BLOCK_SIZE} bytes, got {size}"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the column family options for this configuration.
+    fn cf_options(&self, block_cache: &Cache) -> Options {
+        let mut cf_opts = Options::default();
+        cf_opts.set_compression_type(self.compression.into());
+        if let Some(level) = self.compression_level {
+            cf_opts.set_compression_options(-14, level, 0, 0);
+        }
+
+        let default_block_size = match self.access_pattern {
+            AccessPattern::Scan => SCAN_BLOCK_SIZE,
+            AccessPattern::Balanced | AccessPattern::PointLookup => DEFAULT_BLOCK_SIZE,
+        };
+
+        let mut block_opts = BlockBasedOptions::default();
+        block_opts.set_block_size(self.block_size.unwrap_or(default_block_size));
+        block_opts.set_cache_index_and_filter_blocks(true);
+        block_opts.set_pin_l0_filter_and_index_blocks_in_cache(true);
+        block_opts.set_block_cache(block_cache);
+
+        let bloom_bits = self
+            .bloom_bits_per_key
+            .unwrap_or(DEFAULT_BLOOM_BITS_PER_KEY);
+        if bloom_bits > 0.0 {
+            block_opts.set_bloom_filter(bloom_bits, false);
+        }
+
+        match self.access_pattern {
+            AccessPattern::Balanced => {}
+            AccessPattern::PointLookup => {
+                // Hash index inside data blocks and whole-key bloom filters make
+                // single-key gets cheaper. There is no prefix extractor, so the memtable
+                // filter holds whole keys only; the ratio sizes it, and without one
+                // RocksDB builds none.
+                block_opts.set_data_block_index_type(DataBlockIndexType::BinaryAndHash);
+                block_opts.set_data_block_hash_ratio(0.75);
+                block_opts.set_whole_key_filtering(true);
+                cf_opts.set_memtable_whole_key_filtering(true);
+                cf_opts.set_memtable_prefix_bloom_ratio(0.1);
+            }
+            AccessPattern::Scan => {
+                // Scans read every key, so skip building filters for the last level
+                cf_opts.set_optimize_filters_for_hits(true);
+            }
+        }
+
+        cf_opts.set_block_based_table_factory(&block_opts);
+        cf_opts
+    }
+}
+
 #[async_trait]
-pub trait StorageBackend: Send + Sync {
+pub trait StorageBackend: StatisticsProvider + Send + Sync {
     async fn create_database(&self, name: &str) -> Result<()>;
     async fn drop_database(&self, name: &str) -> Result<()>;
     async fn database_exists(&self, name: &str) -> Result<bool>;
-    async fn create_table(&self, db: &str, table: &str) -> Result<()>;
+    async fn create_table(&self, db: &str, table: &str, config: &TableConfig) -> Result<()>;
+    /// Create a table split into `shards` ranges of primary keys.
+    async fn create_sharded_table(
+        &self,
+        db: &str,
+        table: &str,
+        config: &TableConfig,
+        shards: u32,
+    ) -> Result<()> {
+        if shards > 1 {
+            return Err(not_sharded());
+        }
+        self.create_table(db, table, config).await
+    }
     async fn drop_table(&self, db: &str, table: &str) -> Result<()>;
     async fn table_exists(&self, db: &str, table: &str) -> Result<bool>;
-    async fn put(&self, db: &str, table: &str, key: &str, doc: &Document) -> Result<()>;
-    async fn put_batch(&self, db: &str, table: &str, docs: &[(String, Document)]) -> Result<()>;
-    async fn get(&self, db: &str, table: &str, key: &str) -> Result<Option<Document>>;
+    async fn put(
+        &self,
+        db: &str,
+        table: &str,
+        key: &str,
+        doc: &Document,
+        durability: Option<Durability>,
+    ) -> Result<()>;
+    async fn put_batch(
+        &self,
+        db: &str,
+        table: &str,
+        docs: &[(String, Document)],
+        durability: Option<Durability>,
+    ) -> Result<()>;
+    /// Rewrite documents from their stored versions, reading and writing them under one
+    /// lock so that no concurrent update of them is lost. Keys with no stored document
+    /// are skipped. Returns how many documents were rewritten.
+    async fn update_batch(
+        &self,
+        db: &str,
+        table: &str,
+        keys: &[String],
+        update: DocumentUpdate,
+        durability: Option<Durability>,
+    ) -> Result<usize>;
+    async fn get(
+        &self,
+        db: &str,
+        table: &str,
+        key: &str,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Option<Document>>;
+    #[allow(clippy::too_many_arguments)]
     async fn scan_table(
         &self,
         db: &str,
@@ -235,8 +547,187 @@ pub trait StorageBackend: Send + Sync {
         limit: Option<usize>,
         skip: Option<usize>,
         predicate: Option<Predicate>,
+        projection: ScanProjection,
+        snapshot: Option<SnapshotId>,
     ) -> Result<ReceiverStream<Result<Document>>>;
-    async fn delete(&self, db: &str, table: &str, key: &str) -> Result<()>;
+    async fn delete(
+        &self,
+        db: &str,
+        table: &str,
+        key: &str,
+        durability: Option<Durability>,
+    ) -> Result<()>;
+    /// Delete documents in one write. Keys with no stored document are skipped.
+    async fn delete_batch(
+        &self,
+        db: &str,
+        table: &str,
+        keys: &[String],
+        durability: Option<Durability>,
+    ) -> Result<()>;
+
+    /// Sample about `sample_size` documents of a table to refresh its statistics.
+    async fn analyze_table(
+        &self,
+        db: &str,
+        table: &str,
+        sample_size: usize,
+    ) -> Result<TableStatistics>;
+
+    /// Move a table's documents between its shards so that each of `shards` ranges,
+    /// or of its current ranges, holds about as many documents as the others.
+    async fn rebalance_table(
+        &self,
+        _db: &str,
+        _table: &str,
+        _shards: Option<u32>,
+    ) -> Result<RebalanceSummary> {
+        Err(not_sharded())
+    }
+
+    /// Build an index of a table from its current documents and keep it up to date
+    /// with every later write.
+    async fn create_index(
+        &self,
+        _db: &str,
+        _table: &str,
+        _definition: &IndexDefinition,
+    ) -> Result<()> {
+        Err(not_indexed())
+    }
+    async fn drop_index(&self, _db: &str, _table: &str, _name: &str) -> Result<()> {
+        Err(not_indexed())
+    }
+    async fn list_indexes(&self, _db: &str, _table: &str) -> Result<Vec<IndexDefinition>> {
+        Err(not_indexed())
+    }
+    /// Documents filed under any of `values` in a secondary index, in the order of the
+    /// values, each once.
+    async fn get_all_indexed(
+        &self,
+        _db: &str,
+        _table: &str,
+        _index: &str,
+        _values: &[Datum],
+        _snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<Document>> {
+        Err(not_indexed())
+    }
+    /// Documents filed under a value from `lower` up to, but not including, `upper` in a
+    /// secondary index, in index order, each once. A missing bound leaves that end open.
+    async fn between(
+        &self,
+        _db: &str,
+        _table: &str,
+        _index: &str,
+        _lower: Option<&Datum>,
+        _upper: Option<&Datum>,
+        _snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<Document>> {
+        Err(not_indexed())
+    }
+    /// Documents matching `query` in a full-text index, with their relevance scores,
+    /// most relevant first.
+    async fn search(
+        &self,
+        _db: &str,
+        _table: &str,
+        _index: &str,
+        _query: &str,
+        _limit: Option<usize>,
+        _snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<(Document, f64)>> {
+        Err(not_indexed())
+    }
+    /// Documents whose geometry in a geospatial index intersects `shape`, in key order.
+    async fn get_intersecting(
+        &self,
+        _db: &str,
+        _table: &str,
+        _index: &str,
+        _shape: &Shape,
+        _snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<Document>> {
+        Err(not_indexed())
+    }
+    /// Up to `max_results` documents whose geometry in a geospatial index lies within
+    /// `max_dist` meters of `point`, with their distances, nearest first.
+    #[allow(clippy::too_many_arguments)]
+    async fn get_nearest(
+        &self,
+        _db: &str,
+        _table: &str,
+        _index: &str,
+        _point: Point,
+        _max_dist: f64,
+        _max_results: usize,
+        _snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<(Document, f64)>> {
+        Err(not_indexed())
+    }
+    /// Up to `k` documents nearest to `vector` in a vector index, with their distances,
+    /// nearest first. The `probes` lists nearest to the vector are searched, or every
+    /// list if only the documents `predicate` accepts are wanted.
+    #[allow(clippy::too_many_arguments)]
+    async fn nearest(
+        &self,
+        _db: &str,
+        _table: &str,
+        _index: &str,
+        _vector: &[f64],
+        _k: usize,
+        _probes: usize,
+        _predicate: Option<Predicate>,
+        _snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<(Document, f64)>> {
+        Err(not_indexed())
+    }
+
+    /// Pin a snapshot of the current data for reads that need a consistent view.
+    async fn create_snapshot(&self) -> Result<SnapshotId>;
+    async fn release_snapshot(&self, snapshot: SnapshotId) -> Result<()>;
+
+    /// Read up to `max_batches` write batches, starting with the one at `sequence`, for
+    /// a replica to apply.
+    async fn updates_since(&self, sequence: u64, max_batches: usize) -> Result<ReplicationUpdates>;
+    /// Apply write batches read from the primary, returning the last applied sequence.
+    async fn apply_updates(&self, updates: ReplicationUpdates) -> Result<u64>;
+    fn replication_status(&self) -> ReplicationStatus;
+
+    /// Role and progress of this node in its cluster.
+    fn cluster_status(&self) -> Result<ClusterStatus> {
+        Err(not_clustered())
+    }
+    /// Add a node to the cluster or remove one from it.
+    async fn change_membership(&self, _change: MembershipChange) -> Result<ClusterStatus> {
+        Err(not_clustered())
+    }
+    /// Hand the leader role to `target`, or to the most up-to-date follower.
+    async fn transfer_leadership(&self, _target: Option<NodeId>) -> Result<ClusterStatus> {
+        Err(not_clustered())
+    }
+    /// Index of the last entry of a cluster's log applied to this backend, as the state
+    /// machine of one of its nodes.
+    fn applied_index(&self) -> Result<u64> {
+        Err(not_state_machine())
+    }
+    /// Record that a cluster's log is applied up to `index`. The record is written after
+    /// the writes of the entry at `index`, and a crash can't lose those without losing
+    /// it too, so a restarted node applies at most that entry again. With hard
+    /// durability, it returns once the record and every write before it are on disk.
+    async fn record_applied_index(&self, _index: u64, _durability: Durability) -> Result<()> {
+        Err(not_state_machine())
+    }
+    /// Everything this backend holds, for a node of the cluster that needs entries its
+    /// leader compacted from the log. The snapshot is built in memory.
+    async fn export_snapshot(&self) -> Result<Vec<u8>> {
+        Err(not_state_machine())
+    }
+    /// Replace everything this backend holds with a snapshot another one exported,
+    /// including the applied index, which is written last and durably.
+    async fn install_snapshot(&self, _snapshot: &[u8]) -> Result<()> {
+        Err(not_state_machine())
+    }
 
     // Streaming versions for cursor pagination
     async fn stream_databases(
@@ -252,6 +743,7 @@ pub trait StorageBackend: Send + Sync {
         limit: Option<usize>,
         skip: Option<usize>,
     ) -> Result<ReceiverStream<Result<String>>>;
+    #[allow(clippy::too_many_arguments)]
     async fn stream_get_all(
         &self,
         db: &str,
@@ -260,9 +752,37 @@ pub trait StorageBackend: Send + Sync {
         start_key: Option<String>,
         limit: Option<usize>,
         skip: Option<usize>,
+        snapshot: Option<SnapshotId>,
     ) -> Result<ReceiverStream<Result<Document>>>;
 }
 
+fn not_clustered() -> StorageError {
+    StorageError::Cluster("this node is not part of a cluster".to_string())
+}
+
+fn not_state_machine() -> StorageError {
+    StorageError::Cluster("this storage backend cannot apply a cluster's log".to_string())
+}
+
+fn not_sharded() -> StorageError {
+    StorageError::Sharding("this storage backend does not shard tables".to_string())
+}
+
+fn not_indexed() -> StorageError {
+    StorageError::Index("this storage backend does not support indexes".to_string())
+}
+
+/// Check a requested number of shards.
+fn validate_shard_count(shards: u32) -> Result<()> {
+    if (1..=MAX_SHARDS).contains(&shards) {
+        Ok(())
+    } else {
+        Err(StorageError::InvalidTableOptions(format!(
+            "shard count must be between 1 and {MAX_SHARDS}, got {shards}"
+        )))
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     pub data_dir: String,
@@ -282,6 +802,9 @@ pub struct Config {
     pub write_buffer_size: usize,
     pub max_write_buffer_number: i32,
     pub min_write_buffer_number_to_merge: i32,
+    /// Retention of obsolete WAL files, for servers replicas follow. Without it they are
+    /// deleted as soon as they are no longer needed for recovery.
+    pub wal_retention: Option<WalRetention>,
 }
 
 impl Default for Config {
@@ -308,20 +831,106 @@ impl Default for Config {
             write_buffer_size: 67_108_864,         // 64MB
             max_write_buffer_number: 3,
             min_write_buffer_number_to_merge: 1,
+            wal_retention: None,
         }
     }
 }
 
+/// Shard maps of sharded tables, by table
+type ShardMaps = RwLock<HashMap<String, Arc<TableShards>>>;
+
+/// Number of documents a rebalance copies or deletes per write.
+const REBALANCE_BATCH_SIZE: usize = 1000;
+
+/// Number of key-value pairs an installed snapshot writes per batch.
+const SNAPSHOT_BATCH_SIZE: usize = 1000;
+
+/// Contents of a database as a cluster node exports them for another to install: every
+/// column family with its key-value pairs.
+#[derive(Serialize, Deserialize)]
+struct DatabaseSnapshot {
+    column_families: Vec<(String, Vec<(Vec<u8>, Vec<u8>)>)>,
+}
+
 pub struct DefaultStorage {
     inner: Arc<DBWithThreadMode<MultiThreaded>>,
     schema_lock: Arc<RwLock<()>>,
     path: String,
     opts: Options,
+    block_cache: Cache,
+    table_configs: Arc<RwLock<HashMap<String, TableConfig>>>,
+    table_statistics: Arc<RwLock<HashMap<String, TableStatistics>>>,
+    /// Shard maps of sharded tables. Writes hold it for reading, and the map of their
+    /// table too, while they route keys and write them.
+    shard_maps: Arc<ShardMaps>,
+    /// Held through a rebalance, so that only one runs at a time
+    rebalance_lock: Arc<Mutex<()>>,
+    /// Index definitions. Writes lock them after the shard maps.
+    indexes: Arc<IndexRegistry>,
+    group_commit: Arc<GroupCommit>,
+    snapshots: Arc<SnapshotRegistry<PinnedSnapshot>>,
+    replication: Arc<ReplicationState>,
     operation_semaphore: Arc<Semaphore>,
+    /// Bumped by every change of databases, tables or indexes
+    schema_version: Arc<AtomicU64>,
 }
 
 impl DefaultStorage {
     pub fn open(cfg: &Config) -> Result<Self> {
+        let opts = Self::db_options(cfg);
+        // Create shared block cache; block-based table options are set per column family
+        let block_cache = Cache::new_lru_cache(cfg.block_cache_size);
+
+        let cfs_on_disk: Vec<String> = DB::list_cf(&opts, &cfg.data_dir)
+            .unwrap_or_else(|_| vec![format_table_name(DEFAULT_DATABASE, "default")]);
+        let table_configs = Self::load_table_configs(&opts, &cfg.data_dir, &cfs_on_disk)?;
+
+        let merged_cfs: Vec<String> = cfs_on_disk
+            .into_iter()
+            .chain(SystemTable::variants().iter().map(ToString::to_string))
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect();
+
+        let descriptors = Self::cf_descriptors(&merged_cfs, &table_configs, &block_cache);
+        let db: DBWithThreadMode<MultiThreaded> =
+            DBWithThreadMode::open_cf_descriptors(&opts, &cfg.data_dir, descriptors)?;
+        let storage = Self::with_db(db, &cfg.data_dir, opts, block_cache, table_configs)?;
+
+        storage.ensure_databases(&merged_cfs)?;
+
+        Ok(storage)
+    }
+
+    /// Open the database in `primary_dir`, which another process has open, as a read-only
+    /// RocksDB secondary instance keeping its own logs in `cfg.data_dir`. It sees the
+    /// primary's writes as of its last call to [`Self::catch_up_with_primary`].
+    pub fn open_secondary(cfg: &Config, primary_dir: &str) -> Result<Self> {
+        let mut opts = Self::db_options(cfg);
+        // The primary deletes table files as it compacts, so the secondary keeps them open
+        opts.set_max_open_files(-1);
+        let block_cache = Cache::new_lru_cache(cfg.block_cache_size);
+
+        let cfs_on_disk = DB::list_cf(&opts, primary_dir)?;
+        let table_configs = Self::load_table_configs(&opts, primary_dir, &cfs_on_disk)?;
+
+        let descriptors = Self::cf_descriptors(&cfs_on_disk, &table_configs, &block_cache);
+        let db: DBWithThreadMode<MultiThreaded> =
+            DBWithThreadMode::open_cf_descriptors_as_secondary(
+                &opts,
+                primary_dir,
+                &cfg.data_dir,
+                descriptors,
+            )?;
+        let storage = Self::with_db(db, primary_dir, opts, block_cache, table_configs)?;
+        storage.set_replication_role(ReplicationRole::Secondary {
+            primary: primary_dir.to_string(),
+        });
+
+        Ok(storage)
+    }
+
+    fn db_options(cfg: &Config) -> Options {
         let mut opts = Options::default();
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
@@ -358,61 +967,65 @@ impl DefaultStorage {
         opts.set_bytes_per_sync(cfg.bytes_per_sync);
         opts.set_wal_bytes_per_sync(cfg.wal_bytes_per_sync);
 
-        // WAL optimization
-        opts.set_wal_ttl_seconds(0);
-        opts.set_wal_size_limit_mb(0);
-
-        // Create shared block cache
-        let block_cache = Cache::new_lru_cache(cfg.block_cache_size);
-
-        // Block-based table options with optimizations
-        let mut block_opts = BlockBasedOptions::default();
-        block_opts.set_block_size(32 * 1024); // 32KB for better cache efficiency
-        block_opts.set_bloom_filter(10.0, false);
-        block_opts.set_cache_index_and_filter_blocks(true);
-        block_opts.set_pin_l0_filter_and_index_blocks_in_cache(true);
-        block_opts.set_block_cache(&block_cache);
-
-        // Use prefix extractor for better seek performance
-        opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(4));
-        opts.set_block_based_table_factory(&block_opts);
+        // Keep obsolete WAL files around for replicas to catch up from
+        if let Some(retention) = cfg.wal_retention {
+            opts.set_wal_ttl_seconds(retention.ttl_seconds);
+            opts.set_wal_size_limit_mb(retention.size_limit_mb);
+        }
 
         // Enable statistics for monitoring
         opts.enable_statistics();
 
-        let cfs_on_disk: Vec<String> = DB::list_cf(&opts, &cfg.data_dir)
-            .unwrap_or_else(|_| vec![format_table_name(DEFAULT_DATABASE, "default")]);
-
-        let merged_cfs: Vec<String> = cfs_on_disk
-            .into_iter()
-            .chain(SystemTable::variants().iter().map(ToString::to_string))
-            .collect::<HashSet<String>>()
-            .into_iter()
-            .collect();
+        opts
+    }
 
-        let descriptors = merged_cfs
-            .iter()
+    fn cf_descriptors(
+        cfs: &[String],
+        table_configs: &HashMap<String, TableConfig>,
+        block_cache: &Cache,
+    ) -> Vec<ColumnFamilyDescriptor> {
+        cfs.iter()
             .map(|name| {
-                let mut cf_opts = Options::default();
-                cf_opts.set_compression_type(DBCompressionType::Zstd);
-                ColumnFamilyDescriptor::new(name, cf_opts)
+                let config = table_configs.get(name).cloned().unwrap_or_default();
+                ColumnFamilyDescriptor::new(name, config.cf_options(block_cache))
             })
-            .collect::<Vec<_>>();
-
-        let db: DBWithThreadMode<MultiThreaded> =
-            DBWithThreadMode::open_cf_descriptors(&opts, &cfg.data_dir, descriptors)?;
+            .collect()
+    }
 
-        let storage = Self {
+    fn with_db(
+        db: DBWithThreadMode<MultiThreaded>,
+        path: &str,
+        opts: Options,
+        block_cache: Cache,
+        table_configs: HashMap<String, TableConfig>,
+    ) -> Result<Self> {
+        let table_statistics = Self::load_table_statistics(&db)?;
+        let shard_maps = Self::load_shard_maps(&db)?;
+        let indexes = IndexRegistry::load(&db, &SystemTable::Indexes.to_string())?;
+        let applied_sequence = Self::load_applied_sequence(&db)?;
+
+        Ok(Self {
             inner: Arc::new(db),
             schema_lock: Arc::new(RwLock::new(())),
-            path: cfg.data_dir.clone(),
+            path: path.to_string(),
             opts,
+            block_cache,
+            table_configs: Arc::new(RwLock::new(table_configs)),
+            table_statistics: Arc::new(RwLock::new(table_statistics)),
+            shard_maps: Arc::new(RwLock::new(shard_maps)),
+            rebalance_lock: Arc::new(Mutex::new(())),
+            indexes: Arc::new(indexes),
+            group_commit: Arc::new(GroupCommit::new()),
+            snapshots: Arc::new(SnapshotRegistry::new(SNAPSHOT_IDLE_TIMEOUT)),
+            replication: Arc::new(ReplicationState::new(applied_sequence)),
             operation_semaphore: Arc::new(Semaphore::new(MAX_CONCURRENT_OPERATIONS)),
-        };
-
-        storage.ensure_databases(&merged_cfs)?;
+            schema_version: Arc::new(AtomicU64::new(0)),
+        })
+    }
 
-        Ok(storage)
+    /// Count a change of the schema, for plans made against the old one to be remade
+    fn schema_changed(&self) {
+        self.schema_version.fetch_add(1, Ordering::AcqRel);
     }
 
     fn ensure_databases(&self, cfs: &[String]) -> Result<()> {
@@ -421,158 +1034,700 @@ impl DefaultStorage {
         for table in SystemTable::variants() {
             let cf_name = table.to_string();
             if !cfs.contains(&cf_name) {
-                self.inner.create_cf(&cf_name, &Options::default())?;
+                self.inner.create_cf(
+                    &cf_name,
+                    &TableConfig::default().cf_options(&self.block_cache),
+                )?;
             }
         }
 
         Ok(())
     }
 
-    fn serialize_batch(docs: &[(String, Document)]) -> Result<Vec<(String, Vec<u8>)>> {
-        docs.iter()
-            .map(|(k, d)| {
-                let serialized = bincode::serde::encode_to_vec(d, bincode::config::standard())?;
-                Ok((k.clone(), serialized))
-            })
-            .collect()
-    }
+    /// Read the persisted table configurations before the database is opened, so each
+    /// column family can be opened with its own options.
+    fn load_table_configs(
+        opts: &Options,
+        path: &str,
+        cfs_on_disk: &[String],
+    ) -> Result<HashMap<String, TableConfig>> {
+        let schemas_cf = SystemTable::Schemas.to_string();
+        let mut configs = if cfs_on_disk.contains(&schemas_cf) {
+            let db = DBWithThreadMode::<MultiThreaded>::open_cf_for_read_only(
+                opts,
+                path,
+                [&schemas_cf],
+                false,
+            )?;
+            Self::read_table_configs(&db)?
+        } else {
+            HashMap::new()
+        };
 
-    fn create_read_opts() -> ReadOptions {
-        let mut read_opts = ReadOptions::default();
-        read_opts.set_verify_checksums(false); // Disable for better read performance
-        read_opts.fill_cache(true);
-        read_opts
-    }
+        for name in cfs_on_disk.iter().filter(|name| name.contains(':')) {
+            configs.entry(name.clone()).or_default();
+        }
 
-    fn create_write_opts() -> WriteOptions {
-        let mut write_opts = WriteOptions::default();
-        write_opts.set_sync(false); // Use periodic sync for better performance
-        write_opts.disable_wal(false);
-        write_opts
+        Ok(configs)
     }
-}
 
-#[async_trait]
-impl StorageBackend for DefaultStorage {
-    async fn create_database(&self, name: &str) -> Result<()> {
-        if !is_valid_key(name) || is_system_db(name) {
-            return Err(StorageError::InvalidDatabaseName(name.to_string()));
+    /// Read the table configurations stored in the schemas table.
+    fn read_table_configs(
+        db: &DBWithThreadMode<MultiThreaded>,
+    ) -> Result<HashMap<String, TableConfig>> {
+        let schemas_cf = SystemTable::Schemas.to_string();
+        let cf = db
+            .cf_handle(&schemas_cf)
+            .ok_or(StorageError::MissingColumnFamily(schemas_cf))?;
+
+        let mut configs = HashMap::new();
+        for res in db.iterator_cf_opt(&cf, Self::create_read_opts(), IteratorMode::Start) {
+            let (key, value) = res?;
+            let table_name = String::from_utf8(key.to_vec())?;
+            let (config, _) = bincode::serde::decode_from_slice::<TableConfig, _>(
+                &value,
+                bincode::config::standard(),
+            )?;
+            configs.insert(table_name, config);
         }
 
-        let _permit = self
-            .operation_semaphore
-            .acquire()
-            .await
-            .map_err(|_| StorageError::ResourceExhausted)?;
+        Ok(configs)
+    }
 
-        let inner_db = self.inner.clone();
-        let write_opts = Self::create_write_opts();
-        let name = name.to_string();
+    /// Read the statistics of every analyzed table from the meta table.
+    fn load_table_statistics(
+        db: &DBWithThreadMode<MultiThreaded>,
+    ) -> Result<HashMap<String, TableStatistics>> {
+        let Some(cf) = db.cf_handle(&SystemTable::Meta.to_string()) else {
+            return Ok(HashMap::new());
+        };
 
-        spawn_blocking(move || {
-            let cf = inner_db
-                .cf_handle(&SystemTable::Databases.to_string())
-                .ok_or_else(|| {
-                    StorageError::MissingColumnFamily(SystemTable::Databases.to_string())
-                })?;
+        let mut statistics = HashMap::new();
+        let mode = IteratorMode::From(STATISTICS_KEY_PREFIX.as_bytes(), Direction::Forward);
+        for res in db.iterator_cf_opt(&cf, Self::create_read_opts(), mode) {
+            let (key, value) = res?;
+            let Some(table_name) = key.strip_prefix(STATISTICS_KEY_PREFIX.as_bytes()) else {
+                break;
+            };
+            let (table_statistics, _) = bincode::serde::decode_from_slice::<TableStatistics, _>(
+                &value,
+                bincode::config::standard(),
+            )?;
+            statistics.insert(String::from_utf8(table_name.to_vec())?, table_statistics);
+        }
 
-            let db_config = DatabaseConfig::default();
-            let serialized =
-                bincode::serde::encode_to_vec(&db_config, bincode::config::standard())?;
+        Ok(statistics)
+    }
 
-            inner_db.put_cf_opt(&cf, &name, serialized, &write_opts)?;
+    /// Read the shard maps of every sharded table from the meta table.
+    fn load_shard_maps(
+        db: &DBWithThreadMode<MultiThreaded>,
+    ) -> Result<HashMap<String, Arc<TableShards>>> {
+        let Some(cf) = db.cf_handle(&SystemTable::Meta.to_string()) else {
+            return Ok(HashMap::new());
+        };
 
-            let table_cf_name = format_table_name(&name, "default");
-            inner_db.create_cf(&table_cf_name, &Options::default())?;
+        let mut shard_maps = HashMap::new();
+        let mode = IteratorMode::From(SHARD_MAP_KEY_PREFIX.as_bytes(), Direction::Forward);
+        for res in db.iterator_cf_opt(&cf, Self::create_read_opts(), mode) {
+            let (key, value) = res?;
+            let Some(table_name) = key.strip_prefix(SHARD_MAP_KEY_PREFIX.as_bytes()) else {
+                break;
+            };
+            let (shard_map, _) = bincode::serde::decode_from_slice::<ShardMap, _>(
+                &value,
+                bincode::config::standard(),
+            )?;
+            shard_maps.insert(
+                String::from_utf8(table_name.to_vec())?,
+                TableShards::new(shard_map),
+            );
+        }
 
-            Ok(())
-        })
-        .await
-        .unwrap()
+        Ok(shard_maps)
     }
 
-    async fn drop_database(&self, name: &str) -> Result<()> {
-        if !is_valid_key(name) || is_system_db(name) || name == DEFAULT_DATABASE {
-            return Err(StorageError::InvalidDatabaseName(name.to_string()));
-        }
+    /// Read the last primary sequence number applied by a replica from the meta table.
+    fn load_applied_sequence(db: &DBWithThreadMode<MultiThreaded>) -> Result<u64> {
+        let Some(cf) = db.cf_handle(&SystemTable::Meta.to_string()) else {
+            return Ok(0);
+        };
 
-        let _permit = self
-            .operation_semaphore
-            .acquire()
-            .await
-            .map_err(|_| StorageError::ResourceExhausted)?;
+        Ok(db
+            .get_cf(&cf, APPLIED_SEQUENCE_KEY)?
+            .and_then(|value| value.try_into().ok())
+            .map_or(0, u64::from_be_bytes))
+    }
 
+    /// Catch a secondary instance up with the primary's latest writes, and reload the
+    /// metadata they may have changed. Column families the primary creates after the
+    /// secondary was opened are not open here, so until it is reopened the metadata
+    /// leaves out what lives in them: new tables, tables with new shards, which can no
+    /// longer be read, and new indexes.
+    pub async fn catch_up_with_primary(&self) -> Result<()> {
         let inner_db = self.inner.clone();
-        let name = name.to_string();
+        let schema_lock = self.schema_lock.clone();
+        let table_configs = self.table_configs.clone();
+        let table_statistics = self.table_statistics.clone();
+        let shard_maps = self.shard_maps.clone();
+        let indexes = self.indexes.clone();
+        let replication = self.replication.clone();
 
         spawn_blocking(move || {
-            let table_names: Vec<String> = DB::list_cf(&Options::default(), ".")
-                .unwrap_or_default()
-                .into_iter()
-                .filter(|cf_name| cf_name.starts_with(&format!("{name}:")))
+            inner_db.try_catch_up_with_primary()?;
+
+            let _lock = schema_lock.write().unwrap();
+            let visible = |cf_name: &str| inner_db.cf_handle(cf_name).is_some();
+            let mut shards = Self::load_shard_maps(&inner_db)?;
+            // The maps of tables with new shards stay, so that reading them fails rather
+            // than missing the documents in those shards
+            shards.retain(|table_name, _| visible(table_name));
+            let with_new_shards: HashSet<String> = shards
+                .iter()
+                .filter(|(table_name, table_shards)| {
+                    let shard_map = table_shards.read();
+                    shard_map
+                        .shards()
+                        .iter()
+                        .any(|shard| !visible(&shard_table_name(table_name, shard.id)))
+                })
+                .map(|(table_name, _)| table_name.clone())
                 .collect();
-
-            for table_name in table_names {
-                inner_db.drop_cf(&table_name)?;
-            }
-
-            let cf = inner_db
-                .cf_handle(&SystemTable::Databases.to_string())
-                .ok_or_else(|| {
-                    StorageError::MissingColumnFamily(SystemTable::Databases.to_string())
-                })?;
-
-            inner_db.delete_cf(&cf, &name)?;
-
+            let mut configs = Self::read_table_configs(&inner_db)?;
+            configs.retain(|name, _| visible(name) && !with_new_shards.contains(name));
+            let mut statistics = Self::load_table_statistics(&inner_db)?;
+            statistics.retain(|name, _| configs.contains_key(name));
+            indexes.reload(&inner_db, &SystemTable::Indexes.to_string(), |cf_name| {
+                visible(cf_name)
+                    && cf_name
+                        .split_once('@')
+                        .is_some_and(|(table_name, _)| configs.contains_key(table_name))
+            })?;
+            *table_configs.write().unwrap() = configs;
+            *table_statistics.write().unwrap() = statistics;
+            *shard_maps.write().unwrap() = shards;
+
+            let latest_sequence = inner_db.latest_sequence_number();
+            replication.record_contact(latest_sequence, latest_sequence);
             Ok(())
         })
         .await
         .unwrap()
     }
 
-    async fn database_exists(&self, name: &str) -> Result<bool> {
-        let inner_db = self.inner.clone();
-        let name = name.to_string();
+    /// Make this database a read-only replica of `primary`, or a primary again.
+    pub fn set_replication_role(&self, role: ReplicationRole) {
+        self.replication.set_role(role);
+    }
 
-        spawn_blocking(move || {
-            let cf = inner_db
-                .cf_handle(&SystemTable::Databases.to_string())
-                .ok_or_else(|| {
-                    StorageError::MissingColumnFamily(SystemTable::Databases.to_string())
-                })?;
+    /// Map the ids of the column families that currently exist to their names.
+    fn column_family_names(
+        db: &DBWithThreadMode<MultiThreaded>,
+        table_configs: &RwLock<HashMap<String, TableConfig>>,
+    ) -> HashMap<u32, String> {
+        let tables = table_configs
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        SystemTable::variants()
+            .iter()
+            .map(ToString::to_string)
+            .chain(tables)
+            .chain(std::iter::once("default".to_string()))
+            .filter_map(|name| {
+                let cf = db.cf_handle(&name)?;
+                // SAFETY: the handle stays alive for the duration of the call.
+                let id = unsafe { librocksdb_sys::rocksdb_column_family_handle_get_id(cf.inner()) };
+                Some((id, name))
+            })
+            .collect()
+    }
 
-            Ok(inner_db.get_cf(&cf, &name)?.is_some())
+    /// Whether a write batch of the primary creates or drops a database, table or index
+    fn changes_schema(batch: &WalBatch) -> bool {
+        let schema = [
+            SystemTable::Schemas.to_string(),
+            SystemTable::Databases.to_string(),
+            SystemTable::Indexes.to_string(),
+        ];
+        batch.operations.iter().any(|operation| match operation {
+            WalOperation::Put { column_family, .. }
+            | WalOperation::Delete { column_family, .. } => schema.contains(column_family),
         })
-        .await
-        .unwrap()
     }
 
-    async fn create_table(&self, db: &str, table: &str) -> Result<()> {
-        if !is_valid_key(db) || is_system_db(db) {
-            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+    /// Apply one write batch of the primary together with the replica's new position,
+    /// creating and dropping column families as the primary's schema changes.
+    fn apply_wal_batch(
+        db: &DBWithThreadMode<MultiThreaded>,
+        batch: &WalBatch,
+        table_configs: &RwLock<HashMap<String, TableConfig>>,
+        table_statistics: &RwLock<HashMap<String, TableStatistics>>,
+        shard_maps: &ShardMaps,
+        indexes: &IndexRegistry,
+        block_cache: &Cache,
+    ) -> Result<()> {
+        let schemas = SystemTable::Schemas.to_string();
+        let databases = SystemTable::Databases.to_string();
+        let index_definitions = SystemTable::Indexes.to_string();
+        let meta = SystemTable::Meta.to_string();
+
+        let mut created = Vec::new();
+        let mut dropped = Vec::new();
+        for operation in &batch.operations {
+            match operation {
+                WalOperation::Put {
+                    column_family,
+                    key,
+                    value,
+                } => {
+                    if *column_family == schemas {
+                        let (config, _) = bincode::serde::decode_from_slice::<TableConfig, _>(
+                            value,
+                            bincode::config::standard(),
+                        )?;
+                        created.push((String::from_utf8(key.clone())?, config));
+                    } else if *column_family == databases {
+                        let name = String::from_utf8(key.clone())?;
+                        created.push((format_table_name(&name, "default"), TableConfig::default()));
+                    } else if db.cf_handle(column_family).is_none() {
+                        created.push((column_family.clone(), TableConfig::default()));
+                    }
+                }
+                WalOperation::Delete { column_family, key } => {
+                    if *column_family == schemas {
+                        dropped.push(String::from_utf8(key.clone())?);
+                    } else if *column_family == databases {
+                        let prefix = format_table_name(&String::from_utf8(key.clone())?, "");
+                        dropped.extend(
+                            table_configs
+                                .read()
+                                .unwrap()
+                                .keys()
+                                .filter(|name| name.starts_with(&prefix))
+                                .cloned(),
+                        );
+                    } else if db.cf_handle(column_family).is_none() {
+                        created.push((column_family.clone(), TableConfig::default()));
+                    }
+                }
+            }
         }
-        if !is_valid_key(table) {
-            return Err(StorageError::InvalidTableName(table.to_string()));
+
+        for (name, config) in created {
+            if db.cf_handle(&name).is_none() {
+                db.create_cf(&name, &config.cf_options(block_cache))?;
+            }
+            table_configs.write().unwrap().insert(name, config);
         }
 
-        let _permit = self
-            .operation_semaphore
-            .acquire()
-            .await
-            .map_err(|_| StorageError::ResourceExhausted)?;
+        let mut write_batch = WriteBatch::default();
+        for operation in &batch.operations {
+            match operation {
+                WalOperation::Put {
+                    column_family,
+                    key,
+                    value,
+                } => {
+                    let cf = db
+                        .cf_handle(column_family)
+                        .ok_or_else(|| StorageError::MissingColumnFamily(column_family.clone()))?;
+                    write_batch.put_cf(&cf, key, value);
+                }
+                WalOperation::Delete { column_family, key } => {
+                    let cf = db
+                        .cf_handle(column_family)
+                        .ok_or_else(|| StorageError::MissingColumnFamily(column_family.clone()))?;
+                    write_batch.delete_cf(&cf, key);
+                }
+            }
+        }
+        let meta_cf = db
+            .cf_handle(&meta)
+            .ok_or_else(|| StorageError::MissingColumnFamily(meta.clone()))?;
+        let applied_sequence = batch.next_sequence() - 1;
+        write_batch.put_cf(
+            &meta_cf,
+            APPLIED_SEQUENCE_KEY,
+            applied_sequence.to_be_bytes(),
+        );
 
-        let inner_db = self.inner.clone();
-        let table_name = format_table_name(db, table);
+        // Reads route by the shard maps, so they change together with the documents
+        let mut shard_maps = shard_maps.write().unwrap();
+        db.write_opt(write_batch, &Self::create_write_opts())?;
+        for operation in &batch.operations {
+            match operation {
+                WalOperation::Put {
+                    column_family,
+                    key,
+                    value,
+                } if *column_family == meta => {
+                    let Some(table_name) = key.strip_prefix(SHARD_MAP_KEY_PREFIX.as_bytes()) else {
+                        continue;
+                    };
+                    let (shard_map, _) = bincode::serde::decode_from_slice::<ShardMap, _>(
+                        value,
+                        bincode::config::standard(),
+                    )?;
+                    shard_maps.insert(
+                        String::from_utf8(table_name.to_vec())?,
+                        TableShards::new(shard_map),
+                    );
+                }
+                WalOperation::Delete { column_family, key } if *column_family == meta => {
+                    if let Some(table_name) = key.strip_prefix(SHARD_MAP_KEY_PREFIX.as_bytes()) {
+                        shard_maps.remove(&String::from_utf8(table_name.to_vec())?);
+                    }
+                }
+                WalOperation::Put {
+                    column_family,
+                    key,
+                    value,
+                } if *column_family == index_definitions => {
+                    let (definition, _) = bincode::serde::decode_from_slice::<IndexDefinition, _>(
+                        value,
+                        bincode::config::standard(),
+                    )?;
+                    indexes.insert(&String::from_utf8(key.clone())?, definition);
+                }
+                WalOperation::Delete { column_family, key }
+                    if *column_family == index_definitions =>
+                {
+                    indexes.remove(&String::from_utf8(key.clone())?);
+                }
+                _ => {}
+            }
+        }
+        drop(shard_maps);
 
-        spawn_blocking(move || {
-            inner_db.create_cf(&table_name, &Options::default())?;
-            Ok(())
-        })
-        .await
-        .unwrap()
+        for name in dropped {
+            if db.cf_handle(&name).is_some() {
+                db.drop_cf(&name)?;
+            }
+            table_configs.write().unwrap().remove(&name);
+            table_statistics.write().unwrap().remove(&name);
+        }
+
+        // Keep the statistics cache in line with the replicated meta table
+        for operation in &batch.operations {
+            if let WalOperation::Put {
+                column_family,
+                key,
+                value,
+            } = operation
+            {
+                if *column_family != meta {
+                    continue;
+                }
+                let Some(table_name) = key.strip_prefix(STATISTICS_KEY_PREFIX.as_bytes()) else {
+                    continue;
+                };
+                let (statistics, _) = bincode::serde::decode_from_slice::<TableStatistics, _>(
+                    value,
+                    bincode::config::standard(),
+                )?;
+                table_statistics
+                    .write()
+                    .unwrap()
+                    .insert(String::from_utf8(table_name.to_vec())?, statistics);
+            }
+        }
+
+        Ok(())
     }
 
-    async fn drop_table(&self, db: &str, table: &str) -> Result<()> {
+    /// Delete every key of a column family.
+    fn clear_column_family(db: &DBWithThreadMode<MultiThreaded>, cf_name: &str) -> Result<()> {
+        let cf = db
+            .cf_handle(cf_name)
+            .ok_or_else(|| StorageError::MissingColumnFamily(cf_name.to_string()))?;
+        let mut batch = WriteBatch::default();
+        for res in db.iterator_cf_opt(&cf, Self::create_read_opts(), IteratorMode::Start) {
+            let (key, _) = res?;
+            batch.delete_cf(&cf, key);
+        }
+        db.write_opt(batch, &Self::create_write_opts())?;
+        Ok(())
+    }
+
+    /// Write the key-value pairs of a snapshot's column family.
+    fn write_snapshot_pairs(
+        db: &DBWithThreadMode<MultiThreaded>,
+        cf_name: &str,
+        pairs: &[(Vec<u8>, Vec<u8>)],
+    ) -> Result<()> {
+        let cf = db
+            .cf_handle(cf_name)
+            .ok_or_else(|| StorageError::MissingColumnFamily(cf_name.to_string()))?;
+        for chunk in pairs.chunks(SNAPSHOT_BATCH_SIZE) {
+            let mut batch = WriteBatch::default();
+            for (key, value) in chunk {
+                batch.put_cf(&cf, key, value);
+            }
+            db.write_opt(batch, &Self::create_write_opts())?;
+        }
+        Ok(())
+    }
+
+    /// Return the storage configuration of a table.
+    pub fn table_config(&self, db: &str, table: &str) -> Option<TableConfig> {
+        self.table_configs
+            .read()
+            .unwrap()
+            .get(&format_table_name(db, table))
+            .cloned()
+    }
+
+    /// Handles of the column families of a table's shards, in key order. A table without
+    /// a shard map is a single shard.
+    fn shard_handles<'a>(
+        db: &'a Arc<DBWithThreadMode<MultiThreaded>>,
+        table_name: &str,
+        shard_map: Option<&ShardMap>,
+    ) -> Result<Vec<Arc<BoundColumnFamily<'a>>>> {
+        let names = shard_map.map_or_else(
+            || vec![table_name.to_string()],
+            |shard_map| {
+                shard_map
+                    .shards()
+                    .iter()
+                    .map(|shard| shard_table_name(table_name, shard.id))
+                    .collect()
+            },
+        );
+        names
+            .into_iter()
+            .map(|name| {
+                get_cf_cache()
+                    .get(&name, db)
+                    .ok_or(StorageError::MissingColumnFamily(name))
+            })
+            .collect()
+    }
+
+    /// Handle of the column family of the shard owning `key`.
+    fn shard_handle<'a>(
+        db: &'a Arc<DBWithThreadMode<MultiThreaded>>,
+        table_name: &str,
+        shard_map: Option<&ShardMap>,
+        key: &str,
+    ) -> Result<Arc<BoundColumnFamily<'a>>> {
+        let name = shard_map.map_or_else(
+            || table_name.to_string(),
+            |shard_map| shard_table_name(table_name, shard_map.route(key).id),
+        );
+        get_cf_cache()
+            .get(&name, db)
+            .ok_or(StorageError::MissingColumnFamily(name))
+    }
+
+    /// Handles of the column families of a table's shards in key order, each with read
+    /// options keeping to the shard's range. Past its range, the column family of a shard
+    /// may hold documents a rebalance is moving in or out.
+    fn shard_scans<'a>(
+        db: &'a Arc<DBWithThreadMode<MultiThreaded>>,
+        table_name: &str,
+        shard_map: Option<&ShardMap>,
+        snapshot: Option<&PinnedSnapshot>,
+    ) -> Result<Vec<(Arc<BoundColumnFamily<'a>>, ReadOptions)>> {
+        let shards = Self::shard_handles(db, table_name, shard_map)?;
+        let ranges = shard_map.map_or_else(
+            || vec![None],
+            |map| map.ranges().into_iter().map(Some).collect(),
+        );
+        Ok(shards
+            .into_iter()
+            .zip(ranges)
+            .map(|(cf, range)| (cf, Self::range_read_opts(snapshot, range.as_ref())))
+            .collect())
+    }
+
+    /// Read options that see the data as of `snapshot` and keep to `range`.
+    fn range_read_opts(
+        snapshot: Option<&PinnedSnapshot>,
+        range: Option<&ShardRange>,
+    ) -> ReadOptions {
+        let mut read_opts = Self::snapshot_read_opts(snapshot);
+        if let Some(start) = range.and_then(|range| range.start.as_ref()) {
+            read_opts.set_iterate_lower_bound(start.as_bytes());
+        }
+        if let Some(end) = range.and_then(|range| range.end.as_ref()) {
+            read_opts.set_iterate_upper_bound(end.as_bytes());
+        }
+        read_opts
+    }
+
+    /// The shard map of a table, if it is sharded.
+    fn shard_map(shard_maps: &ShardMaps, table_name: &str) -> Option<ShardMap> {
+        let shard_maps = shard_maps.read().unwrap();
+        shard_maps
+            .get(table_name)
+            .map(|table_shards| table_shards.read().clone())
+    }
+
+    /// Pin a snapshot for a read spanning the shards of a sharded table, so that it
+    /// sees the documents where the shard map it routes by puts them.
+    fn shard_snapshot(
+        db: &Arc<DBWithThreadMode<MultiThreaded>>,
+        shard_maps: &ShardMaps,
+        table_name: &str,
+        snapshot: Option<Arc<PinnedSnapshot>>,
+    ) -> Option<Arc<PinnedSnapshot>> {
+        if snapshot.is_some() {
+            return snapshot;
+        }
+        let table_shards = shard_maps.read().unwrap().get(table_name)?.clone();
+        let shard_map = table_shards.read();
+        Some(Arc::new(PinnedSnapshot::new(
+            db.clone(),
+            HashMap::from([(table_name.to_string(), shard_map.clone())]),
+        )))
+    }
+
+    /// Write a batch of a rebalance once it holds enough documents, or whatever it
+    /// holds when `force`d.
+    fn write_rebalance_batch(
+        db: &DBWithThreadMode<MultiThreaded>,
+        batch: &mut WriteBatch,
+        write_opts: &WriteOptions,
+        force: bool,
+    ) -> Result<()> {
+        if batch.len() >= REBALANCE_BATCH_SIZE || (force && !batch.is_empty()) {
+            db.write_opt(std::mem::take(batch), write_opts)?;
+        }
+        Ok(())
+    }
+
+    /// Delete what a rebalance that didn't finish may have left in the column families
+    /// of the target shards, in the ranges they take over from other shards.
+    fn clear_taken_over_ranges(
+        db: &Arc<DBWithThreadMode<MultiThreaded>>,
+        table_name: &str,
+        current: &ShardMap,
+        target: &ShardMap,
+        write_opts: &WriteOptions,
+    ) -> Result<()> {
+        let current_ranges = current.ranges();
+        let target_shards = Self::shard_scans(db, table_name, Some(target), None)?;
+        let mut batch = WriteBatch::default();
+        for ((cf, read_opts), range) in target_shards.into_iter().zip(target.ranges()) {
+            let owned = current_ranges.iter().find(|owned| owned.id == range.id);
+            for res in db.iterator_cf_opt(&cf, read_opts, IteratorMode::Start) {
+                let (key, _) = res?;
+                if !owned.is_some_and(|owned| owned.contains(&key)) {
+                    batch.delete_cf(&cf, &key);
+                    Self::write_rebalance_batch(db, &mut batch, write_opts, false)?;
+                }
+            }
+        }
+        Self::write_rebalance_batch(db, &mut batch, write_opts, true)
+    }
+
+    /// Copy the documents of a snapshot whose shard changes to their target shards,
+    /// outside the ranges their column families currently serve.
+    fn copy_moving_documents(
+        db: &Arc<DBWithThreadMode<MultiThreaded>>,
+        snapshot: &PinnedSnapshot,
+        table_name: &str,
+        current: &ShardMap,
+        target: &ShardMap,
+        write_opts: &WriteOptions,
+    ) -> Result<()> {
+        let current_shards = Self::shard_scans(db, table_name, Some(current), Some(snapshot))?;
+        let target_shards = Self::shard_handles(db, table_name, Some(target))?;
+        let mut batch = WriteBatch::default();
+        for ((cf, read_opts), shard) in current_shards.into_iter().zip(current.shards()) {
+            for res in db.iterator_cf_opt(&cf, read_opts, IteratorMode::Start) {
+                let (key, value) = res?;
+                let index = target.index_of(&key);
+                if target.shards()[index].id != shard.id {
+                    batch.put_cf(&target_shards[index], &key, &value);
+                    Self::write_rebalance_batch(db, &mut batch, write_opts, false)?;
+                }
+            }
+        }
+        Self::write_rebalance_batch(db, &mut batch, write_opts, true)
+    }
+
+    /// Delete the documents a rebalance moved from the shards they were in, returning
+    /// how many were moved.
+    fn delete_moved_documents(
+        db: &Arc<DBWithThreadMode<MultiThreaded>>,
+        table_name: &str,
+        previous: &ShardMap,
+        target: &ShardMap,
+        write_opts: &WriteOptions,
+    ) -> Result<u64> {
+        let previous_shards = Self::shard_scans(db, table_name, Some(previous), None)?;
+        let mut batch = WriteBatch::default();
+        let mut moved = 0;
+        for ((cf, read_opts), shard) in previous_shards.into_iter().zip(previous.shards()) {
+            for res in db.iterator_cf_opt(&cf, read_opts, IteratorMode::Start) {
+                let (key, _) = res?;
+                if target.route(&key).id != shard.id {
+                    batch.delete_cf(&cf, &key);
+                    moved += 1;
+                    Self::write_rebalance_batch(db, &mut batch, write_opts, false)?;
+                }
+            }
+        }
+        Self::write_rebalance_batch(db, &mut batch, write_opts, true)?;
+        Ok(moved)
+    }
+
+    fn serialize_batch(docs: &[(String, Document)]) -> Result<Vec<(String, Vec<u8>)>> {
+        docs.iter()
+            .map(|(k, d)| Ok((k.clone(), encode_document(d)?)))
+            .collect()
+    }
+
+    fn create_read_opts() -> ReadOptions {
+        let mut read_opts = ReadOptions::default();
+        read_opts.set_verify_checksums(false); // Disable for better read performance
+        read_opts.fill_cache(true);
+        read_opts
+    }
+
+    /// Read options that see the data as of `snapshot`, or the latest data without one.
+    fn snapshot_read_opts(snapshot: Option<&PinnedSnapshot>) -> ReadOptions {
+        let mut read_opts = Self::create_read_opts();
+        if let Some(snapshot) = snapshot {
+            snapshot.apply(&mut read_opts);
+        }
+        read_opts
+    }
+
+    fn pinned_snapshot(&self, snapshot: Option<SnapshotId>) -> Result<Option<Arc<PinnedSnapshot>>> {
+        snapshot.map(|id| self.snapshots.get(id)).transpose()
+    }
+
+    /// Look up keys in an index of a table with `lookup` and fetch their documents in
+    /// the same order, reading the index and the table as of the same moment. Documents
+    /// `predicate` rejects are skipped, and fetching stops once `limit` are found.
+    #[allow(clippy::too_many_arguments)]
+    async fn read_index<T, F>(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        snapshot: Option<SnapshotId>,
+        predicate: Option<Predicate>,
+        limit: Option<usize>,
+        lookup: F,
+    ) -> Result<Vec<(Document, T)>>
+    where
+        T: Send + 'static,
+        F: FnOnce(
+                &DBWithThreadMode<MultiThreaded>,
+                &Arc<BoundColumnFamily<'_>>,
+                &IndexKind,
+                &dyn Fn() -> ReadOptions,
+            ) -> Result<Vec<(String, T)>>
+            + Send
+            + 'static,
+    {
         if !is_valid_key(db) || is_system_db(db) {
             return Err(StorageError::InvalidDatabaseName(db.to_string()));
         }
@@ -584,36 +1739,106 @@ impl StorageBackend for DefaultStorage {
             .map_err(|_| StorageError::ResourceExhausted)?;
 
         let inner_db = self.inner.clone();
+        let shard_maps = self.shard_maps.clone();
         let table_name = format_table_name(db, table);
+        let definition = self.indexes.definition(&table_name, index).ok_or_else(|| {
+            StorageError::Index(format!("index {index} does not exist on {table_name}"))
+        })?;
+        let index_name = index_cf_name(&table_name, index);
+        let snapshot = self.pinned_snapshot(snapshot)?;
 
         spawn_blocking(move || {
-            inner_db.drop_cf(&table_name)?;
-            Ok(())
+            let snapshot = Self::shard_snapshot(&inner_db, &shard_maps, &table_name, snapshot)
+                .unwrap_or_else(|| Arc::new(PinnedSnapshot::new(inner_db.clone(), HashMap::new())));
+            let read_opts = || Self::snapshot_read_opts(Some(&snapshot));
+
+            let index_cf = inner_db
+                .cf_handle(&index_name)
+                .ok_or_else(|| StorageError::MissingColumnFamily(index_name.clone()))?;
+            let hits = lookup(&inner_db, &index_cf, &definition.kind, &read_opts)?;
+
+            let shard_map = snapshot.shard_map(&table_name);
+            let shards = Self::shard_handles(&inner_db, &table_name, shard_map)?;
+            let limit = limit.unwrap_or(usize::MAX);
+            let mut results = Vec::with_capacity(hits.len().min(limit));
+            for (key, value) in hits {
+                if results.len() >= limit {
+                    break;
+                }
+                let shard = shard_map.map_or(0, |shard_map| shard_map.index_of(&key));
+                if let Some(doc) = inner_db.get_cf_opt(&shards[shard], &key, &read_opts())? {
+                    let doc = parse_doc(&doc)?;
+                    if predicate.as_ref().map_or(Ok(true), |predicate| {
+                        predicate(doc.clone()).map_err(StorageError::PredicateFailed)
+                    })? {
+                        results.push((doc, value));
+                    }
+                }
+            }
+            Ok(results)
         })
         .await
         .unwrap()
     }
 
-    async fn table_exists(&self, db: &str, table: &str) -> Result<bool> {
-        let _permit = self
-            .operation_semaphore
-            .acquire()
-            .await
-            .map_err(|_| StorageError::ResourceExhausted)?;
-
-        let inner_db = self.inner.clone();
+    /// The field a geospatial index of a table covers.
+    fn geo_index_field(&self, db: &str, table: &str, index: &str) -> Result<Vec<String>> {
         let table_name = format_table_name(db, table);
+        match self.indexes.definition(&table_name, index) {
+            Some(IndexDefinition {
+                kind: IndexKind::Geo(options),
+                ..
+            }) => Ok(options.field),
+            Some(_) => Err(StorageError::Index(format!(
+                "index {index} on {table_name} is not a geospatial index"
+            ))),
+            None => Err(StorageError::Index(format!(
+                "index {index} does not exist on {table_name}"
+            ))),
+        }
+    }
 
-        spawn_blocking(move || Ok(inner_db.cf_handle(&table_name).is_some()))
-            .await
-            .unwrap()
+    fn create_write_opts() -> WriteOptions {
+        let mut write_opts = WriteOptions::default();
+        write_opts.set_sync(false); // Hard writes are synced afterwards through group commit
+        write_opts.disable_wal(false);
+        write_opts
     }
 
-    async fn put(&self, db: &str, table: &str, key: &str, doc: &Document) -> Result<()> {
-        if !is_valid_key(db) || is_system_db(db) {
-            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+    /// Resolve the durability of a write, falling back to the table's default.
+    fn write_durability(&self, table_name: &str, requested: Option<Durability>) -> Durability {
+        requested.unwrap_or_else(|| {
+            self.table_configs
+                .read()
+                .unwrap()
+                .get(table_name)
+                .map(|config| config.durability)
+                .unwrap_or_default()
+        })
+    }
+
+    /// Wait for an applied write to reach disk if it was made with hard durability.
+    fn sync_write(
+        inner_db: &DBWithThreadMode<MultiThreaded>,
+        group_commit: &GroupCommit,
+        durability: Durability,
+    ) -> Result<()> {
+        match durability {
+            Durability::Soft => Ok(()),
+            Durability::Hard => group_commit.commit(|| Ok(inner_db.flush_wal(true)?)),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for DefaultStorage {
+    async fn create_database(&self, name: &str) -> Result<()> {
+        if !is_valid_key(name) || is_system_db(name) {
+            return Err(StorageError::InvalidDatabaseName(name.to_string()));
         }
 
+        self.replication.ensure_writable()?;
+
         let _permit = self
             .operation_semaphore
             .acquire()
@@ -621,28 +1846,46 @@ impl StorageBackend for DefaultStorage {
             .map_err(|_| StorageError::ResourceExhausted)?;
 
         let inner_db = self.inner.clone();
-        let table_name = format_table_name(db, table);
-        let key = key.to_string();
-        let serialized_doc = bincode::serde::encode_to_vec(doc, bincode::config::standard())?;
+        let table_configs = self.table_configs.clone();
         let write_opts = Self::create_write_opts();
+        let default_cf_opts = TableConfig::default().cf_options(&self.block_cache);
+        let name = name.to_string();
 
-        spawn_blocking(move || {
-            let cf = get_cf_cache()
-                .get(&table_name, &inner_db)
-                .ok_or_else(|| StorageError::MissingColumnFamily(table_name.clone()))?;
+        let result = spawn_blocking(move || {
+            let cf = inner_db
+                .cf_handle(&SystemTable::Databases.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Databases.to_string())
+                })?;
+
+            let db_config = DatabaseConfig::default();
+            let serialized =
+                bincode::serde::encode_to_vec(&db_config, bincode::config::standard())?;
+
+            inner_db.put_cf_opt(&cf, &name, serialized, &write_opts)?;
+
+            let table_cf_name = format_table_name(&name, "default");
+            inner_db.create_cf(&table_cf_name, &default_cf_opts)?;
+            table_configs
+                .write()
+                .unwrap()
+                .insert(table_cf_name, TableConfig::default());
 
-            inner_db.put_cf_opt(&cf, key, serialized_doc, &write_opts)?;
             Ok(())
         })
         .await
-        .unwrap()
+        .unwrap();
+        self.schema_changed();
+        result
     }
 
-    async fn put_batch(&self, db: &str, table: &str, docs: &[(String, Document)]) -> Result<()> {
-        if !is_valid_key(db) || is_system_db(db) {
-            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+    async fn drop_database(&self, name: &str) -> Result<()> {
+        if !is_valid_key(name) || is_system_db(name) || name == DEFAULT_DATABASE {
+            return Err(StorageError::InvalidDatabaseName(name.to_string()));
         }
 
+        self.replication.ensure_writable()?;
+
         let _permit = self
             .operation_semaphore
             .acquire()
@@ -650,32 +1893,277 @@ impl StorageBackend for DefaultStorage {
             .map_err(|_| StorageError::ResourceExhausted)?;
 
         let inner_db = self.inner.clone();
-        let table_name = format_table_name(db, table);
-        let docs = Self::serialize_batch(docs)?;
-        let write_opts = Self::create_write_opts();
+        let table_configs = self.table_configs.clone();
+        let table_statistics = self.table_statistics.clone();
+        let shard_maps = self.shard_maps.clone();
+        let indexes = self.indexes.clone();
+        let opts = self.opts.clone();
+        let path = self.path.clone();
+        let name = name.to_string();
 
-        spawn_blocking(move || {
-            let cf = get_cf_cache()
-                .get(&table_name, &inner_db)
-                .ok_or_else(|| StorageError::MissingColumnFamily(table_name.clone()))?;
+        let result = spawn_blocking(move || {
+            // Shards and indexes are named after their table, so they are listed too
+            let prefix = format!("{name}:");
+            let table_names: Vec<String> = DB::list_cf(&opts, &path)?
+                .into_iter()
+                .filter(|cf_name| cf_name.starts_with(&prefix))
+                .collect();
 
-            let mut batch = WriteBatch::default();
-            for (key, doc) in docs {
-                batch.put_cf(&cf, key, doc);
+            let schemas_cf = inner_db
+                .cf_handle(&SystemTable::Schemas.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Schemas.to_string())
+                })?;
+
+            let meta_cf = inner_db
+                .cf_handle(&SystemTable::Meta.to_string())
+                .ok_or_else(|| StorageError::MissingColumnFamily(SystemTable::Meta.to_string()))?;
+
+            let indexes_cf = inner_db
+                .cf_handle(&SystemTable::Indexes.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Indexes.to_string())
+                })?;
+
+            for table_name in table_names {
+                inner_db.drop_cf(&table_name)?;
+                inner_db.delete_cf(&schemas_cf, &table_name)?;
+                if is_index_name(&table_name) {
+                    inner_db.delete_cf(&indexes_cf, &table_name)?;
+                    indexes.remove(&table_name);
+                }
+                inner_db.delete_cf(&meta_cf, statistics_key(&table_name))?;
+                inner_db.delete_cf(&meta_cf, shard_map_key(&table_name))?;
+                table_configs.write().unwrap().remove(&table_name);
+                table_statistics.write().unwrap().remove(&table_name);
+                shard_maps.write().unwrap().remove(&table_name);
             }
 
-            inner_db.write_opt(batch, &write_opts)?;
+            let cf = inner_db
+                .cf_handle(&SystemTable::Databases.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Databases.to_string())
+                })?;
+
+            inner_db.delete_cf(&cf, &name)?;
+
             Ok(())
         })
         .await
+        .unwrap();
+        self.schema_changed();
+        result
+    }
+
+    async fn database_exists(&self, name: &str) -> Result<bool> {
+        let inner_db = self.inner.clone();
+        let name = name.to_string();
+
+        spawn_blocking(move || {
+            let cf = inner_db
+                .cf_handle(&SystemTable::Databases.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Databases.to_string())
+                })?;
+
+            Ok(inner_db.get_cf(&cf, &name)?.is_some())
+        })
+        .await
         .unwrap()
     }
 
-    async fn get(&self, db: &str, table: &str, key: &str) -> Result<Option<Document>> {
+    async fn create_table(&self, db: &str, table: &str, config: &TableConfig) -> Result<()> {
+        self.create_sharded_table(db, table, config, 1).await
+    }
+
+    async fn create_sharded_table(
+        &self,
+        db: &str,
+        table: &str,
+        config: &TableConfig,
+        shards: u32,
+    ) -> Result<()> {
+        if !is_valid_key(db) || is_system_db(db) {
+            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+        }
+        if !is_valid_key(table) {
+            return Err(StorageError::InvalidTableName(table.to_string()));
+        }
+        config.validate()?;
+        validate_shard_count(shards)?;
+
+        self.replication.ensure_writable()?;
+
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
+
+        let inner_db = self.inner.clone();
+        let table_configs = self.table_configs.clone();
+        let shard_maps = self.shard_maps.clone();
+        let table_name = format_table_name(db, table);
+        let cf_opts = config.cf_options(&self.block_cache);
+        let serialized = bincode::serde::encode_to_vec(config, bincode::config::standard())?;
+        let write_opts = Self::create_write_opts();
+        let config = config.clone();
+        let shard_map = (shards > 1).then(|| ShardMap::uniform(shards));
+
+        let result = spawn_blocking(move || {
+            let cf = inner_db
+                .cf_handle(&SystemTable::Schemas.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Schemas.to_string())
+                })?;
+
+            // The first shard is the table's own column family
+            let shard_ids = shard_map.as_ref().map_or_else(
+                || vec![0],
+                |shard_map| shard_map.shards().iter().map(|shard| shard.id).collect(),
+            );
+            for id in shard_ids {
+                let shard_name = shard_table_name(&table_name, id);
+                inner_db.create_cf(&shard_name, &cf_opts)?;
+                inner_db.put_cf_opt(&cf, &shard_name, &serialized, &write_opts)?;
+                table_configs
+                    .write()
+                    .unwrap()
+                    .insert(shard_name, config.clone());
+            }
+
+            if let Some(shard_map) = shard_map {
+                let meta_cf = inner_db
+                    .cf_handle(&SystemTable::Meta.to_string())
+                    .ok_or_else(|| {
+                        StorageError::MissingColumnFamily(SystemTable::Meta.to_string())
+                    })?;
+                let serialized =
+                    bincode::serde::encode_to_vec(&shard_map, bincode::config::standard())?;
+                inner_db.put_cf_opt(
+                    &meta_cf,
+                    shard_map_key(&table_name),
+                    serialized,
+                    &write_opts,
+                )?;
+                shard_maps
+                    .write()
+                    .unwrap()
+                    .insert(table_name, TableShards::new(shard_map));
+            }
+            Ok(())
+        })
+        .await
+        .unwrap();
+        self.schema_changed();
+        result
+    }
+
+    async fn drop_table(&self, db: &str, table: &str) -> Result<()> {
+        if !is_valid_key(db) || is_system_db(db) {
+            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+        }
+
+        self.replication.ensure_writable()?;
+
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
+
+        let inner_db = self.inner.clone();
+        let table_configs = self.table_configs.clone();
+        let table_statistics = self.table_statistics.clone();
+        let shard_maps = self.shard_maps.clone();
+        let indexes = self.indexes.clone();
+        let table_name = format_table_name(db, table);
+
+        let result = spawn_blocking(move || {
+            inner_db.drop_cf(&table_name)?;
+
+            let cf = inner_db
+                .cf_handle(&SystemTable::Schemas.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Schemas.to_string())
+                })?;
+            inner_db.delete_cf(&cf, &table_name)?;
+
+            // Including shards that no longer own a range since a rebalance
+            let shard_names: Vec<String> = table_configs
+                .read()
+                .unwrap()
+                .keys()
+                .filter(|name| is_shard_of(name, &table_name))
+                .cloned()
+                .collect();
+            for shard_name in shard_names {
+                inner_db.drop_cf(&shard_name)?;
+                inner_db.delete_cf(&cf, &shard_name)?;
+                table_configs.write().unwrap().remove(&shard_name);
+            }
+
+            let indexes_cf = inner_db
+                .cf_handle(&SystemTable::Indexes.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Indexes.to_string())
+                })?;
+            let mut definitions = indexes.lock_for_change();
+            for definition in definitions.remove(&table_name).unwrap_or_default() {
+                let index_name = index_cf_name(&table_name, &definition.name);
+                inner_db.drop_cf(&index_name)?;
+                inner_db.delete_cf(&cf, &index_name)?;
+                inner_db.delete_cf(&indexes_cf, &index_name)?;
+                table_configs.write().unwrap().remove(&index_name);
+            }
+            drop(definitions);
+
+            let meta_cf = inner_db
+                .cf_handle(&SystemTable::Meta.to_string())
+                .ok_or_else(|| StorageError::MissingColumnFamily(SystemTable::Meta.to_string()))?;
+            inner_db.delete_cf(&meta_cf, statistics_key(&table_name))?;
+            inner_db.delete_cf(&meta_cf, shard_map_key(&table_name))?;
+
+            table_configs.write().unwrap().remove(&table_name);
+            table_statistics.write().unwrap().remove(&table_name);
+            shard_maps.write().unwrap().remove(&table_name);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        self.schema_changed();
+        result
+    }
+
+    async fn table_exists(&self, db: &str, table: &str) -> Result<bool> {
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
+
+        let inner_db = self.inner.clone();
+        let table_name = format_table_name(db, table);
+
+        spawn_blocking(move || Ok(inner_db.cf_handle(&table_name).is_some()))
+            .await
+            .unwrap()
+    }
+
+    async fn put(
+        &self,
+        db: &str,
+        table: &str,
+        key: &str,
+        doc: &Document,
+        durability: Option<Durability>,
+    ) -> Result<()> {
         if !is_valid_key(db) || is_system_db(db) {
             return Err(StorageError::InvalidDatabaseName(db.to_string()));
         }
 
+        self.replication.ensure_writable()?;
+
         let _permit = self
             .operation_semaphore
             .acquire()
@@ -683,14 +2171,213 @@ impl StorageBackend for DefaultStorage {
             .map_err(|_| StorageError::ResourceExhausted)?;
 
         let inner_db = self.inner.clone();
+        let shard_maps = self.shard_maps.clone();
+        let indexes = self.indexes.clone();
         let table_name = format_table_name(db, table);
         let key = key.to_string();
-        let read_opts = Self::create_read_opts();
+        let serialized_doc = encode_document(doc)?;
+        let doc = doc.clone();
+        let write_opts = Self::create_write_opts();
+        let durability = self.write_durability(&table_name, durability);
+        let group_commit = self.group_commit.clone();
 
         spawn_blocking(move || {
-            let cf = get_cf_cache()
-                .get(&table_name, &inner_db)
-                .ok_or_else(|| StorageError::MissingColumnFamily(table_name.clone()))?;
+            {
+                let shard_maps = shard_maps.read().unwrap();
+                let table_shards = shard_maps.get(&table_name);
+                let shard_map = table_shards.map(|table_shards| table_shards.read());
+                let cf = Self::shard_handle(&inner_db, &table_name, shard_map.as_deref(), &key)?;
+                if let Some(table_shards) = table_shards {
+                    table_shards.record([key.as_str()]);
+                }
+                let indexes = indexes.lock_for_write(&table_name);
+                let mut batch = WriteBatch::default();
+                batch.put_cf(&cf, &key, serialized_doc);
+                index::update_indexes(
+                    &inner_db,
+                    &table_name,
+                    indexes.definitions(),
+                    &mut batch,
+                    [(key.as_str(), Some(&doc))],
+                )?;
+                inner_db.write_opt(batch, &write_opts)?;
+            }
+            Self::sync_write(&inner_db, &group_commit, durability)
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn put_batch(
+        &self,
+        db: &str,
+        table: &str,
+        docs: &[(String, Document)],
+        durability: Option<Durability>,
+    ) -> Result<()> {
+        if !is_valid_key(db) || is_system_db(db) {
+            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+        }
+
+        self.replication.ensure_writable()?;
+
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
+
+        let inner_db = self.inner.clone();
+        let shard_maps = self.shard_maps.clone();
+        let indexes = self.indexes.clone();
+        let table_name = format_table_name(db, table);
+        let serialized_docs = Self::serialize_batch(docs)?;
+        let docs = docs.to_vec();
+        let write_opts = Self::create_write_opts();
+        let durability = self.write_durability(&table_name, durability);
+        let group_commit = self.group_commit.clone();
+
+        spawn_blocking(move || {
+            {
+                let shard_maps = shard_maps.read().unwrap();
+                let table_shards = shard_maps.get(&table_name);
+                let shard_map = table_shards.map(|table_shards| table_shards.read());
+                let shard_map = shard_map.as_deref();
+                let shards = Self::shard_handles(&inner_db, &table_name, shard_map)?;
+                if let Some(table_shards) = table_shards {
+                    table_shards.record(serialized_docs.iter().map(|(key, _)| key.as_str()));
+                }
+                let indexes = indexes.lock_for_write(&table_name);
+
+                let mut batch = WriteBatch::default();
+                for (key, doc) in serialized_docs {
+                    let shard = shard_map.map_or(0, |shard_map| shard_map.index_of(&key));
+                    batch.put_cf(&shards[shard], key, doc);
+                }
+                index::update_indexes(
+                    &inner_db,
+                    &table_name,
+                    indexes.definitions(),
+                    &mut batch,
+                    docs.iter().map(|(key, doc)| (key.as_str(), Some(doc))),
+                )?;
+
+                inner_db.write_opt(batch, &write_opts)?;
+            }
+            Self::sync_write(&inner_db, &group_commit, durability)
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn update_batch(
+        &self,
+        db: &str,
+        table: &str,
+        keys: &[String],
+        update: DocumentUpdate,
+        durability: Option<Durability>,
+    ) -> Result<usize> {
+        if !is_valid_key(db) || is_system_db(db) {
+            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+        }
+
+        self.replication.ensure_writable()?;
+
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
+
+        let inner_db = self.inner.clone();
+        let shard_maps = self.shard_maps.clone();
+        let indexes = self.indexes.clone();
+        let table_name = format_table_name(db, table);
+        let keys = keys.to_vec();
+        let write_opts = Self::create_write_opts();
+        let durability = self.write_durability(&table_name, durability);
+        let group_commit = self.group_commit.clone();
+
+        spawn_blocking(move || {
+            let updated = {
+                let shard_maps = shard_maps.read().unwrap();
+                let table_shards = shard_maps.get(&table_name);
+                let shard_map = table_shards.map(|table_shards| table_shards.read());
+                let shard_map = shard_map.as_deref();
+                let shards = Self::shard_handles(&inner_db, &table_name, shard_map)?;
+                if let Some(table_shards) = table_shards {
+                    table_shards.record(keys.iter().map(String::as_str));
+                }
+                let indexes = indexes.lock_for_update(&table_name, &keys);
+                let shard_of = |key: &str| shard_map.map_or(0, |shard_map| shard_map.index_of(key));
+
+                // Read under the lock, so no other update can write in between
+                let mut docs = Vec::with_capacity(keys.len());
+                for key in keys {
+                    let Some(stored) = inner_db.get_cf(&shards[shard_of(&key)], &key)? else {
+                        continue;
+                    };
+                    let doc = update(parse_doc(&stored)?)?;
+                    docs.push((key, doc));
+                }
+
+                let mut batch = WriteBatch::default();
+                for (key, doc) in &docs {
+                    batch.put_cf(&shards[shard_of(key)], key, encode_document(doc)?);
+                }
+                index::update_indexes(
+                    &inner_db,
+                    &table_name,
+                    indexes.definitions(),
+                    &mut batch,
+                    docs.iter().map(|(key, doc)| (key.as_str(), Some(doc))),
+                )?;
+
+                inner_db.write_opt(batch, &write_opts)?;
+                docs.len()
+            };
+            Self::sync_write(&inner_db, &group_commit, durability)?;
+            Ok(updated)
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn get(
+        &self,
+        db: &str,
+        table: &str,
+        key: &str,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Option<Document>> {
+        if !is_valid_key(db) || is_system_db(db) {
+            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+        }
+
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
+
+        let inner_db = self.inner.clone();
+        let shard_maps = self.shard_maps.clone();
+        let table_name = format_table_name(db, table);
+        let key = key.to_string();
+        let snapshot = self.pinned_snapshot(snapshot)?;
+
+        spawn_blocking(move || {
+            let read_opts = Self::snapshot_read_opts(snapshot.as_deref());
+            let table_shards = shard_maps.read().unwrap().get(&table_name).cloned();
+            let current = table_shards
+                .as_ref()
+                .map(|table_shards| table_shards.read());
+            let shard_map = match &snapshot {
+                Some(snapshot) => snapshot.shard_map(&table_name),
+                None => current.as_deref(),
+            };
+            let cf = Self::shard_handle(&inner_db, &table_name, shard_map, &key)?;
 
             match inner_db.get_cf_opt(&cf, key, &read_opts)? {
                 Some(val) => Ok(Some(parse_doc(val.as_slice())?)),
@@ -709,6 +2396,8 @@ impl StorageBackend for DefaultStorage {
         limit: Option<usize>,
         skip: Option<usize>,
         predicate: Option<Predicate>,
+        projection: ScanProjection,
+        snapshot: Option<SnapshotId>,
     ) -> Result<ReceiverStream<Result<Document>>> {
         if !is_valid_key(db) || is_system_db(db) {
             return Err(StorageError::InvalidDatabaseName(db.to_string()));
@@ -721,24 +2410,36 @@ impl StorageBackend for DefaultStorage {
             .map_err(|_| StorageError::ResourceExhausted)?;
 
         let inner_db = self.inner.clone();
+        let shard_maps = self.shard_maps.clone();
         let table_name = format_table_name(db, table);
         // Don't apply artificial limits - use provided limit or no limit at all
         let skip = skip.unwrap_or(0);
-        let read_opts = Self::create_read_opts();
+        let snapshot = self.pinned_snapshot(snapshot)?;
         // Set channel capacity based on limit or use default
         let channel_capacity = limit.unwrap_or(1000).clamp(1, 1000);
         let (tx, rx) = mpsc::channel(channel_capacity);
 
         spawn_blocking(move || {
-            let cf = get_cf_cache()
-                .get(&table_name, &inner_db)
-                .ok_or_else(|| StorageError::MissingColumnFamily(table_name.clone()))?;
+            // The snapshot stays pinned by this closure until the iterator is done
+            let snapshot = Self::shard_snapshot(&inner_db, &shard_maps, &table_name, snapshot);
+            let shard_map = snapshot
+                .as_ref()
+                .and_then(|snapshot| snapshot.shard_map(&table_name));
+            let shards = Self::shard_scans(&inner_db, &table_name, shard_map, snapshot.as_deref())?;
+            let first_shard = match (&start_key, shard_map) {
+                (Some(key), Some(shard_map)) => shard_map.index_of(key),
+                _ => 0,
+            };
 
             let mode = start_key.as_ref().map_or(IteratorMode::Start, |key| {
                 IteratorMode::From(key.as_bytes(), Direction::Forward)
             });
 
-            let iterator = inner_db.iterator_cf_opt(&cf, read_opts, mode);
+            // Shards are visited in key order, so the documents come out sorted by key
+            let iterator = shards
+                .into_iter()
+                .skip(first_shard)
+                .flat_map(|(cf, read_opts)| inner_db.iterator_cf_opt(&cf, read_opts, mode));
             let iterator = match mode {
                 IteratorMode::Start => iterator.skip(skip),
                 _ => iterator.skip(1 + skip),
@@ -752,18 +2453,13 @@ impl StorageBackend for DefaultStorage {
 
             for res in limited_iterator {
                 match res {
-                    Ok((_, v)) => match parse_doc(v.as_ref()) {
-                        Ok(doc) => {
-                            if let Some(predicate) = &predicate {
-                                if !predicate(doc.clone()) {
-                                    continue;
-                                }
-                            }
-
+                    Ok((_, v)) => match scan_doc(v.as_ref(), predicate.as_ref(), &projection) {
+                        Ok(Some(doc)) => {
                             if tx.blocking_send(Ok(doc)).is_err() {
                                 break;
                             }
                         }
+                        Ok(None) => continue,
                         Err(e) => {
                             let _ = tx.blocking_send(Err(e));
                             break;
@@ -782,11 +2478,30 @@ impl StorageBackend for DefaultStorage {
         Ok(ReceiverStream::new(rx))
     }
 
-    async fn delete(&self, db: &str, table: &str, key: &str) -> Result<()> {
+    async fn delete(
+        &self,
+        db: &str,
+        table: &str,
+        key: &str,
+        durability: Option<Durability>,
+    ) -> Result<()> {
+        self.delete_batch(db, table, &[key.to_string()], durability)
+            .await
+    }
+
+    async fn delete_batch(
+        &self,
+        db: &str,
+        table: &str,
+        keys: &[String],
+        durability: Option<Durability>,
+    ) -> Result<()> {
         if !is_valid_key(db) || is_system_db(db) {
             return Err(StorageError::InvalidDatabaseName(db.to_string()));
         }
 
+        self.replication.ensure_writable()?;
+
         let _permit = self
             .operation_semaphore
             .acquire()
@@ -794,99 +2509,137 @@ impl StorageBackend for DefaultStorage {
             .map_err(|_| StorageError::ResourceExhausted)?;
 
         let inner_db = self.inner.clone();
+        let shard_maps = self.shard_maps.clone();
+        let indexes = self.indexes.clone();
         let table_name = format_table_name(db, table);
-        let key = key.to_string();
+        let keys = keys.to_vec();
         let write_opts = Self::create_write_opts();
+        let durability = self.write_durability(&table_name, durability);
+        let group_commit = self.group_commit.clone();
 
         spawn_blocking(move || {
-            let cf = get_cf_cache()
-                .get(&table_name, &inner_db)
-                .ok_or_else(|| StorageError::MissingColumnFamily(table_name.clone()))?;
+            {
+                let shard_maps = shard_maps.read().unwrap();
+                let table_shards = shard_maps.get(&table_name);
+                let shard_map = table_shards.map(|table_shards| table_shards.read());
+                let shard_map = shard_map.as_deref();
+                let shards = Self::shard_handles(&inner_db, &table_name, shard_map)?;
+                if let Some(table_shards) = table_shards {
+                    table_shards.record(keys.iter().map(String::as_str));
+                }
+                let indexes = indexes.lock_for_write(&table_name);
 
-            inner_db.delete_cf_opt(&cf, key, &write_opts)?;
-            Ok(())
+                let mut batch = WriteBatch::default();
+                for key in &keys {
+                    let shard = shard_map.map_or(0, |shard_map| shard_map.index_of(key));
+                    batch.delete_cf(&shards[shard], key);
+                }
+                index::update_indexes(
+                    &inner_db,
+                    &table_name,
+                    indexes.definitions(),
+                    &mut batch,
+                    keys.iter().map(|key| (key.as_str(), None)),
+                )?;
+
+                inner_db.write_opt(batch, &write_opts)?;
+            }
+            Self::sync_write(&inner_db, &group_commit, durability)
         })
         .await
         .unwrap()
     }
 
-    async fn stream_databases(
+    async fn analyze_table(
         &self,
-        start_key: Option<String>,
-        limit: Option<usize>,
-        skip: Option<usize>,
-    ) -> Result<ReceiverStream<Result<String>>> {
-        let _permit = self
-            .operation_semaphore
-            .acquire()
-            .await
-            .map_err(|_| StorageError::ResourceExhausted)?;
-
-        let inner_db = self.inner.clone();
-        let read_opts = Self::create_read_opts();
-        // Don't apply artificial limits - use provided limit or no limit at all
-        let skip = skip.unwrap_or(0);
-        // Set channel capacity based on limit or use default
-        let channel_capacity = limit.unwrap_or(1000).clamp(1, 1000);
-        let (tx, rx) = mpsc::channel(channel_capacity);
+        db: &str,
+        table: &str,
+        sample_size: usize,
+    ) -> Result<TableStatistics> {
+        if !is_valid_key(db) || is_system_db(db) {
+            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+        }
 
-        spawn_blocking(move || {
-            let cf = inner_db
-                .cf_handle(&SystemTable::Databases.to_string())
-                .ok_or_else(|| {
-                    StorageError::MissingColumnFamily(SystemTable::Databases.to_string())
-                })?;
+        self.replication.ensure_writable()?;
 
-            let mode = start_key.as_ref().map_or(IteratorMode::Start, |key| {
-                IteratorMode::From(key.as_bytes(), Direction::Forward)
-            });
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
 
-            let iterator = inner_db
-                .iterator_cf_opt(&cf, read_opts, mode)
-                .skip(if start_key.is_some() { 1 + skip } else { skip });
+        let inner_db = self.inner.clone();
+        let table_statistics = self.table_statistics.clone();
+        let shard_maps = self.shard_maps.clone();
+        let table_name = format_table_name(db, table);
+        let write_opts = Self::create_write_opts();
 
-            // Apply limit only if explicitly provided
-            let limited_iterator: Box<dyn Iterator<Item = _>> = match limit {
-                Some(l) => Box::new(iterator.take(l)),
-                None => Box::new(iterator),
-            };
+        spawn_blocking(move || {
+            let snapshot = Self::shard_snapshot(&inner_db, &shard_maps, &table_name, None);
+            let shard_map = snapshot
+                .as_ref()
+                .and_then(|snapshot| snapshot.shard_map(&table_name));
+            let shards = Self::shard_scans(&inner_db, &table_name, shard_map, snapshot.as_deref())?;
+
+            // Sample every n-th document so the sample spans the whole key range
+            let mut estimate = 0;
+            for (cf, _) in &shards {
+                estimate += inner_db
+                    .property_int_value_cf(cf, ESTIMATE_NUM_KEYS)?
+                    .unwrap_or(0);
+            }
+            let stride = estimate.div_ceil(sample_size.max(1) as u64).max(1);
 
-            for res in limited_iterator {
-                match res {
-                    Ok((key_bytes, _)) => match String::from_utf8(key_bytes.to_vec()) {
-                        Ok(db_name) => {
-                            if !is_system_db(&db_name) && tx.blocking_send(Ok(db_name)).is_err() {
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            let _ = tx.blocking_send(Err(StorageError::InvalidUtf8(e)));
-                            break;
-                        }
-                    },
-                    Err(e) => {
-                        let _ = tx.blocking_send(Err(StorageError::BackendError(e)));
-                        break;
-                    }
+            let mut builder = StatisticsBuilder::new();
+            let iterator = shards.into_iter().flat_map(|(cf, read_opts)| {
+                inner_db.iterator_cf_opt(&cf, read_opts, IteratorMode::Start)
+            });
+            for (i, res) in (0u64..).zip(iterator) {
+                let (_, value) = res?;
+                if i % stride == 0 {
+                    builder.add(&parse_doc(&value)?);
+                } else {
+                    builder.skip();
                 }
             }
+            let statistics = builder.build();
 
-            Ok::<(), StorageError>(())
-        });
-
-        Ok(ReceiverStream::new(rx))
+            let meta_cf = inner_db
+                .cf_handle(&SystemTable::Meta.to_string())
+                .ok_or_else(|| StorageError::MissingColumnFamily(SystemTable::Meta.to_string()))?;
+            let serialized =
+                bincode::serde::encode_to_vec(&statistics, bincode::config::standard())?;
+            inner_db.put_cf_opt(
+                &meta_cf,
+                statistics_key(&table_name),
+                serialized,
+                &write_opts,
+            )?;
+
+            table_statistics
+                .write()
+                .unwrap()
+                .insert(table_name, statistics.clone());
+            Ok(statistics)
+        })
+        .await
+        .unwrap()
     }
 
-    async fn stream_tables(
+    async fn rebalance_table(
         &self,
         db: &str,
-        start_key: Option<String>,
-        limit: Option<usize>,
-        skip: Option<usize>,
-    ) -> Result<ReceiverStream<Result<String>>> {
+        table: &str,
+        shards: Option<u32>,
+    ) -> Result<RebalanceSummary> {
         if !is_valid_key(db) || is_system_db(db) {
             return Err(StorageError::InvalidDatabaseName(db.to_string()));
         }
+        if let Some(shards) = shards {
+            validate_shard_count(shards)?;
+        }
+
+        self.replication.ensure_writable()?;
 
         let _permit = self
             .operation_semaphore
@@ -894,66 +2647,223 @@ impl StorageBackend for DefaultStorage {
             .await
             .map_err(|_| StorageError::ResourceExhausted)?;
 
-        let opts = self.opts.clone();
-        let path = self.path.clone();
-        let db = db.to_string();
-        // Don't apply artificial limits - use provided limit or no limit at all
-        let skip = skip.unwrap_or(0);
-        let channel_capacity = limit.unwrap_or(1000).clamp(1, 1000);
-        let (tx, rx) = mpsc::channel(channel_capacity);
+        let inner_db = self.inner.clone();
+        let table_configs = self.table_configs.clone();
+        let shard_maps = self.shard_maps.clone();
+        let block_cache = self.block_cache.clone();
+        let table_name = format_table_name(db, table);
+        let write_opts = Self::create_write_opts();
 
-        spawn_blocking(move || {
-            let prefix = format!("{db}:");
-            let mut tables: Vec<String> = DB::list_cf(&opts, &path)
-                .unwrap_or_default()
-                .into_iter()
-                .filter_map(|cf_name| {
-                    if cf_name.starts_with(&prefix) {
-                        extract_table_from_database(&cf_name).map(std::string::ToString::to_string)
-                    } else {
-                        None
-                    }
-                })
-                .collect();
+        let snapshots = self.snapshots.clone();
+        let rebalance_lock = self.rebalance_lock.clone();
 
-            // Sort for consistent pagination
-            tables.sort();
+        spawn_blocking(move || {
+            let _rebalance = rebalance_lock.lock().unwrap();
+            let config = table_configs
+                .read()
+                .unwrap()
+                .get(&table_name)
+                .cloned()
+                .ok_or_else(|| StorageError::MissingColumnFamily(table_name.clone()))?;
 
-            // Apply start_key filter
-            if let Some(start_key) = start_key {
-                tables.retain(|table| table >= &start_key);
+            // Writes to a table note their keys through its shard map, so a table
+            // without one gets a single shard map for the time of the rebalance
+            let existing = shard_maps.read().unwrap().get(&table_name).cloned();
+            let table_shards = match existing {
+                Some(table_shards) => table_shards,
+                None => shard_maps
+                    .write()
+                    .unwrap()
+                    .entry(table_name.clone())
+                    .or_insert_with(|| TableShards::new(ShardMap::single()))
+                    .clone(),
+            };
+            let current = table_shards.read().clone();
+
+            // Split the documents into ranges of about equal size, while writes go on
+            let snapshot = PinnedSnapshot::new(inner_db.clone(), HashMap::new());
+            let documents = || {
+                Self::shard_scans(&inner_db, &table_name, Some(&current), Some(&snapshot)).map(
+                    |shards| {
+                        shards.into_iter().flat_map(|(cf, read_opts)| {
+                            inner_db.iterator_cf_opt(&cf, read_opts, IteratorMode::Start)
+                        })
+                    },
+                )
+            };
+            let mut total = 0u64;
+            for res in documents()? {
+                res?;
+                total += 1;
             }
+            let count = shards.unwrap_or(current.len() as u32);
+            let boundaries = if total == 0 {
+                uniform_boundaries(count)
+            } else {
+                let positions = balanced_positions(total, count);
+                let mut positions = positions.iter().peekable();
+                let mut boundaries = Vec::new();
+                for (position, res) in (0u64..).zip(documents()?) {
+                    let Some(next) = positions.peek() else {
+                        break;
+                    };
+                    let (key, _) = res?;
+                    if position == **next {
+                        boundaries.push(String::from_utf8(key.to_vec())?);
+                        positions.next();
+                    }
+                }
+                boundaries
+            };
+            drop(snapshot);
+            let target = current.with_boundaries(boundaries);
 
-            // Apply skip and limit (only if limit is provided)
-            let tables: Vec<String> = match limit {
-                Some(l) => tables.into_iter().skip(skip).take(l).collect(),
-                None => tables.into_iter().skip(skip).collect(),
+            let schemas_cf = inner_db
+                .cf_handle(&SystemTable::Schemas.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Schemas.to_string())
+                })?;
+            let serialized_config =
+                bincode::serde::encode_to_vec(&config, bincode::config::standard())?;
+            for shard in target.shards() {
+                let shard_name = shard_table_name(&table_name, shard.id);
+                if inner_db.cf_handle(&shard_name).is_none() {
+                    inner_db.create_cf(&shard_name, &config.cf_options(&block_cache))?;
+                    inner_db.put_cf_opt(
+                        &schemas_cf,
+                        &shard_name,
+                        &serialized_config,
+                        &write_opts,
+                    )?;
+                    table_configs
+                        .write()
+                        .unwrap()
+                        .insert(shard_name, config.clone());
+                }
+            }
+            Self::clear_taken_over_ranges(&inner_db, &table_name, &current, &target, &write_opts)?;
+
+            // Copy the documents changing shard as of a snapshot, noting the keys
+            // written from then on to copy them again
+            let snapshot = {
+                let _map = table_shards.write();
+                table_shards.start_recording();
+                PinnedSnapshot::new(inner_db.clone(), HashMap::new())
             };
+            let copied = Self::copy_moving_documents(
+                &inner_db,
+                &snapshot,
+                &table_name,
+                &current,
+                &target,
+                &write_opts,
+            );
+            if copied.is_err() {
+                table_shards.stop_recording();
+            }
+            copied?;
+            drop(snapshot);
 
-            for table in tables {
-                if tx.blocking_send(Ok(table)).is_err() {
-                    break;
+            // Switch to the new map, holding back only the writes to this table while
+            // the documents written meanwhile are copied again
+            {
+                let mut map = table_shards.write();
+                let current_shards = Self::shard_handles(&inner_db, &table_name, Some(&current))?;
+                let target_shards = Self::shard_handles(&inner_db, &table_name, Some(&target))?;
+                let mut batch = WriteBatch::default();
+                for key in table_shards.stop_recording() {
+                    let from = current.index_of(&key);
+                    let to = target.index_of(&key);
+                    if current.shards()[from].id == target.shards()[to].id {
+                        continue;
+                    }
+                    match inner_db.get_cf(&current_shards[from], &key)? {
+                        Some(value) => batch.put_cf(&target_shards[to], &key, value),
+                        None => batch.delete_cf(&target_shards[to], &key),
+                    }
                 }
+
+                let meta_cf = inner_db
+                    .cf_handle(&SystemTable::Meta.to_string())
+                    .ok_or_else(|| {
+                        StorageError::MissingColumnFamily(SystemTable::Meta.to_string())
+                    })?;
+                if target.len() > 1 {
+                    let serialized =
+                        bincode::serde::encode_to_vec(&target, bincode::config::standard())?;
+                    batch.put_cf(&meta_cf, shard_map_key(&table_name), serialized);
+                } else {
+                    batch.delete_cf(&meta_cf, shard_map_key(&table_name));
+                }
+                inner_db.write_opt(batch, &write_opts)?;
+                *map = target.clone();
+            }
+            if target.len() == 1 {
+                shard_maps.write().unwrap().remove(&table_name);
             }
 
-            Ok::<(), StorageError>(())
-        });
+            let moved = Self::delete_moved_documents(
+                &inner_db,
+                &table_name,
+                &current,
+                &target,
+                &write_opts,
+            )?;
+
+            // Drop the shards no longer used, unless a pinned snapshot still reads them
+            let in_use: HashSet<String> = target
+                .shards()
+                .iter()
+                .map(|shard| shard_table_name(&table_name, shard.id))
+                .collect();
+            let unused: Vec<String> = table_configs
+                .read()
+                .unwrap()
+                .keys()
+                .filter(|name| is_shard_of(name, &table_name) && !in_use.contains(*name))
+                .cloned()
+                .collect();
+            for shard_name in unused {
+                let pinned = snapshots.any(|snapshot| {
+                    snapshot.shard_map(&table_name).is_some_and(|shard_map| {
+                        shard_map
+                            .shards()
+                            .iter()
+                            .any(|shard| shard_table_name(&table_name, shard.id) == shard_name)
+                    })
+                });
+                if pinned {
+                    continue;
+                }
+                inner_db.drop_cf(&shard_name)?;
+                inner_db.delete_cf_opt(&schemas_cf, &shard_name, &write_opts)?;
+                table_configs.write().unwrap().remove(&shard_name);
+            }
 
-        Ok(ReceiverStream::new(rx))
+            Ok(RebalanceSummary {
+                shards: target.ranges(),
+                moved,
+            })
+        })
+        .await
+        .unwrap()
     }
 
-    async fn stream_get_all(
+    async fn create_index(
         &self,
         db: &str,
         table: &str,
-        keys: &[String],
-        start_key: Option<String>,
-        limit: Option<usize>,
-        skip: Option<usize>,
-    ) -> Result<ReceiverStream<Result<Document>>> {
+        definition: &IndexDefinition,
+    ) -> Result<()> {
         if !is_valid_key(db) || is_system_db(db) {
             return Err(StorageError::InvalidDatabaseName(db.to_string()));
         }
+        if !is_valid_key(table) {
+            return Err(StorageError::InvalidTableName(table.to_string()));
+        }
+        definition.validate()?;
+
+        self.replication.ensure_writable()?;
 
         let _permit = self
             .operation_semaphore
@@ -962,969 +2872,4137 @@ impl StorageBackend for DefaultStorage {
             .map_err(|_| StorageError::ResourceExhausted)?;
 
         let inner_db = self.inner.clone();
+        let table_configs = self.table_configs.clone();
+        let shard_maps = self.shard_maps.clone();
+        let indexes = self.indexes.clone();
+        let block_cache = self.block_cache.clone();
         let table_name = format_table_name(db, table);
-        let keys = keys.to_vec();
-        // Don't apply artificial limits - use provided limit or no limit at all
-        let skip = skip.unwrap_or(0);
-        // Set channel capacity based on limit or use default
-        let channel_capacity = limit.unwrap_or(1000).clamp(1, 1000);
-        let (tx, rx) = mpsc::channel(channel_capacity);
-
-        spawn_blocking(move || {
-            let cf = get_cf_cache()
-                .get(&table_name, &inner_db)
-                .ok_or_else(|| StorageError::MissingColumnFamily(table_name.clone()))?;
-
-            let mut filtered_keys = keys;
+        let index_name = index_cf_name(&table_name, &definition.name);
+        let definition = definition.clone();
+        let write_opts = Self::create_write_opts();
 
-            // Apply start_key filter if provided
-            if let Some(start_key) = start_key {
-                let start_index = filtered_keys
-                    .iter()
-                    .position(|k| k >= &start_key)
-                    .unwrap_or(filtered_keys.len());
-                filtered_keys = filtered_keys.into_iter().skip(start_index).collect();
+        let result = spawn_blocking(move || {
+            // Writes to the table wait until the index holds all of its documents
+            let shard_maps = shard_maps.read().unwrap();
+            let mut definitions = indexes.lock_for_change();
+            if definitions
+                .get(&table_name)
+                .is_some_and(|existing| existing.iter().any(|d| d.name == definition.name))
+            {
+                return Err(StorageError::Index(format!(
+                    "index {} already exists on {table_name}",
+                    definition.name
+                )));
             }
+            let table_shards = shard_maps.get(&table_name).cloned();
+            drop(shard_maps);
+            let shard_map = table_shards
+                .as_ref()
+                .map(|table_shards| table_shards.read());
+            let shards = Self::shard_scans(&inner_db, &table_name, shard_map.as_deref(), None)?;
+
+            let schemas_cf = inner_db
+                .cf_handle(&SystemTable::Schemas.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Schemas.to_string())
+                })?;
+            let indexes_cf = inner_db
+                .cf_handle(&SystemTable::Indexes.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Indexes.to_string())
+                })?;
 
-            // Apply skip and limit (only if limit is provided)
-            let filtered_keys: Vec<String> = match limit {
-                Some(l) => filtered_keys.into_iter().skip(skip).take(l).collect(),
-                None => filtered_keys.into_iter().skip(skip).collect(),
-            };
-
-            for key in filtered_keys {
-                match inner_db.get_cf_opt(&cf, &key, &Self::create_read_opts()) {
-                    Ok(Some(val)) => match parse_doc(val.as_slice()) {
-                        Ok(doc) => {
-                            if tx.blocking_send(Ok(doc)).is_err() {
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            let _ = tx.blocking_send(Err(e));
-                            break;
-                        }
-                    },
-                    Ok(None) => {
-                        // Key not found, skip
-                        continue;
-                    }
-                    Err(e) => {
-                        let _ = tx.blocking_send(Err(StorageError::BackendError(e)));
-                        break;
-                    }
+            // Start over on a column family left behind by a build that didn't finish
+            if inner_db.cf_handle(&index_name).is_some() {
+                inner_db.drop_cf(&index_name)?;
+            }
+            let config = TableConfig::default();
+            inner_db.create_cf(&index_name, &config.cf_options(&block_cache))?;
+            inner_db.put_cf_opt(
+                &schemas_cf,
+                &index_name,
+                bincode::serde::encode_to_vec(&config, bincode::config::standard())?,
+                &write_opts,
+            )?;
+            table_configs
+                .write()
+                .unwrap()
+                .insert(index_name.clone(), config);
+
+            // Index the existing documents and save the definition at once
+            let mut writer = index::IndexWriter::new(&inner_db, &table_name, &definition)?;
+            let mut batch = WriteBatch::default();
+            for (cf, read_opts) in shards {
+                for res in inner_db.iterator_cf_opt(&cf, read_opts, IteratorMode::Start) {
+                    let (key, value) = res?;
+                    let key = String::from_utf8(key.to_vec())?;
+                    writer.update(&mut batch, &key, Some(&parse_doc(&value)?))?;
                 }
             }
+            writer.finish(&mut batch)?;
+            batch.put_cf(
+                &indexes_cf,
+                &index_name,
+                bincode::serde::encode_to_vec(&definition, bincode::config::standard())?,
+            );
+            inner_db.write_opt(batch, &write_opts)?;
 
-            Ok::<(), StorageError>(())
-        });
-
-        Ok(ReceiverStream::new(rx))
+            definitions.entry(table_name).or_default().push(definition);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        self.schema_changed();
+        result
     }
-}
 
-#[inline]
-fn is_system_db(db_name: &str) -> bool {
-    db_name == SYSTEM_DATABASE
-}
+    async fn drop_index(&self, db: &str, table: &str, name: &str) -> Result<()> {
+        if !is_valid_key(db) || is_system_db(db) {
+            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+        }
 
-#[inline]
-fn is_valid_key(key: &str) -> bool {
-    !key.is_empty() && key.len() <= 255 && // Add reasonable length limit
-        key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
-}
+        self.replication.ensure_writable()?;
 
-#[inline]
-fn format_table_name(db: &str, table: &str) -> String {
-    let mut result = String::with_capacity(db.len() + table.len() + 1);
-    result.push_str(db);
-    result.push(':');
-    result.push_str(table);
-    result
-}
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
 
-#[inline]
-fn extract_table_from_database(table_name: &str) -> Option<&str> {
-    table_name.split_once(':').map(|(_, table)| table)
-}
+        let inner_db = self.inner.clone();
+        let table_configs = self.table_configs.clone();
+        let indexes = self.indexes.clone();
+        let table_name = format_table_name(db, table);
+        let index_name = index_cf_name(&table_name, name);
+        let name = name.to_string();
 
-#[inline]
-fn parse_doc(data: &[u8]) -> Result<Document> {
-    log::trace!("Attempting to deserialize document, {} bytes", data.len());
-    bincode::serde::decode_from_slice(data, bincode::config::standard())
-        .map(|(doc, _)| doc)
-        .map_err(|e| {
-            log::error!(
-                "Failed to deserialize document: {} (data: {} bytes)",
-                e,
-                data.len()
-            );
+        let result = spawn_blocking(move || {
+            let mut definitions = indexes.lock_for_change();
+            let table_indexes = definitions
+                .get_mut(&table_name)
+                .filter(|existing| existing.iter().any(|d| d.name == name))
+                .ok_or_else(|| {
+                    StorageError::Index(format!("index {name} does not exist on {table_name}"))
+                })?;
+            table_indexes.retain(|d| d.name != name);
+            if table_indexes.is_empty() {
+                definitions.remove(&table_name);
+            }
 
-            StorageError::DecodeError(e)
-        })
-}
+            let schemas_cf = inner_db
+                .cf_handle(&SystemTable::Schemas.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Schemas.to_string())
+                })?;
+            let indexes_cf = inner_db
+                .cf_handle(&SystemTable::Indexes.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Indexes.to_string())
+                })?;
+            inner_db.delete_cf(&indexes_cf, &index_name)?;
+            inner_db.delete_cf(&schemas_cf, &index_name)?;
+            inner_db.drop_cf(&index_name)?;
+            table_configs.write().unwrap().remove(&index_name);
+            Ok(())
+        })
+        .await
+        .unwrap();
+        self.schema_changed();
+        result
+    }
 
-#[cfg(test)]
-pub mod memory {
-    use super::*;
-    use std::sync::Mutex;
+    async fn list_indexes(&self, db: &str, table: &str) -> Result<Vec<IndexDefinition>> {
+        if !is_valid_key(db) || is_system_db(db) {
+            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+        }
 
-    // Mock in-memory storage backend for benchmarking
-    #[derive(Debug, Clone)]
-    pub struct MemoryStorage {
-        #[allow(clippy::type_complexity)]
-        data: Arc<Mutex<HashMap<String, HashMap<String, HashMap<String, Document>>>>>,
-        databases: Arc<Mutex<Vec<String>>>,
-        operation_count: Arc<Mutex<u64>>,
+        let table_name = format_table_name(db, table);
+        if self.inner.cf_handle(&table_name).is_none() {
+            return Err(StorageError::MissingColumnFamily(table_name));
+        }
+        let mut definitions = self.indexes.definitions(&table_name);
+        definitions.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(definitions)
     }
 
-    impl Default for MemoryStorage {
-        fn default() -> Self {
-            Self::new()
-        }
+    async fn search(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        query: &str,
+        limit: Option<usize>,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<(Document, f64)>> {
+        let query = query.to_string();
+        let not_full_text = format!("index {index} is not a full-text index");
+        self.read_index(
+            db,
+            table,
+            index,
+            snapshot,
+            None,
+            None,
+            move |inner_db, index_cf, kind, read_opts| match kind {
+                IndexKind::FullText(options) => {
+                    fulltext::search(inner_db, index_cf, options, read_opts, &query, limit)
+                }
+                _ => Err(StorageError::Index(not_full_text)),
+            },
+        )
+        .await
     }
 
-    impl MemoryStorage {
-        pub fn new() -> Self {
-            Self {
-                data: Arc::new(Mutex::new(HashMap::new())),
-                databases: Arc::new(Mutex::new(vec!["default".to_string()])),
-                operation_count: Arc::new(Mutex::new(0)),
-            }
-        }
+    async fn get_all_indexed(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        values: &[Datum],
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<Document>> {
+        let values = values
+            .iter()
+            .map(secondary::encode_lookup)
+            .collect::<Result<Vec<_>>>()?;
+        let not_secondary = format!("index {index} is not a secondary index");
+        let results = self
+            .read_index(
+                db,
+                table,
+                index,
+                snapshot,
+                None,
+                None,
+                move |inner_db, index_cf, kind, read_opts| match kind {
+                    IndexKind::Secondary(_) => {
+                        Ok(secondary::get_all(inner_db, index_cf, read_opts, &values)?
+                            .into_iter()
+                            .map(|key| (key, ()))
+                            .collect())
+                    }
+                    _ => Err(StorageError::Index(not_secondary)),
+                },
+            )
+            .await?;
+        Ok(results.into_iter().map(|(doc, ())| doc).collect())
+    }
 
-        fn increment_operation_count(&self) {
-            let mut count = self.operation_count.lock().unwrap();
-            *count += 1;
-        }
+    async fn between(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        lower: Option<&Datum>,
+        upper: Option<&Datum>,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<Document>> {
+        let lower = lower.map(secondary::encode_lookup).transpose()?;
+        let upper = upper.map(secondary::encode_lookup).transpose()?;
+        let not_secondary = format!("index {index} is not a secondary index");
+        let results = self
+            .read_index(
+                db,
+                table,
+                index,
+                snapshot,
+                None,
+                None,
+                move |inner_db, index_cf, kind, read_opts| match kind {
+                    IndexKind::Secondary(_) => Ok(secondary::between(
+                        inner_db,
+                        index_cf,
+                        read_opts,
+                        lower.as_deref(),
+                        upper.as_deref(),
+                    )?
+                    .into_iter()
+                    .map(|key| (key, ()))
+                    .collect()),
+                    _ => Err(StorageError::Index(not_secondary)),
+                },
+            )
+            .await?;
+        Ok(results.into_iter().map(|(doc, ())| doc).collect())
+    }
 
-        pub fn get_operation_count(&self) -> u64 {
-            *self.operation_count.lock().unwrap()
-        }
+    async fn get_intersecting(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        shape: &Shape,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<Document>> {
+        let field = self.geo_index_field(db, table, index)?;
+        let bbox = shape.bounding_box();
+        let candidates = self
+            .read_index(
+                db,
+                table,
+                index,
+                snapshot,
+                None,
+                None,
+                move |inner_db, index_cf, _, read_opts| {
+                    Ok(geo::candidates(inner_db, index_cf, read_opts, &bbox)?
+                        .into_iter()
+                        .map(|key| (key, ()))
+                        .collect())
+                },
+            )
+            .await?;
 
-        pub fn reset_operation_count(&self) {
-            let mut count = self.operation_count.lock().unwrap();
-            *count = 0;
-        }
+        Ok(candidates
+            .into_iter()
+            .map(|(doc, ())| doc)
+            .filter(|doc| {
+                geo::document_shape(&field, doc).is_some_and(|other| other.intersects(shape))
+            })
+            .collect())
     }
 
-    #[async_trait]
-    impl StorageBackend for MemoryStorage {
-        async fn create_database(&self, name: &str) -> Result<()> {
-            self.increment_operation_count();
-            let mut databases = self.databases.lock().unwrap();
-            if !databases.contains(&name.to_string()) {
-                databases.push(name.to_string());
-            }
+    #[allow(clippy::too_many_arguments)]
+    async fn get_nearest(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        point: Point,
+        max_dist: f64,
+        max_results: usize,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<(Document, f64)>> {
+        let field = self.geo_index_field(db, table, index)?;
+        let bbox = point.bounding_box(max_dist);
+        let candidates = self
+            .read_index(
+                db,
+                table,
+                index,
+                snapshot,
+                None,
+                None,
+                move |inner_db, index_cf, _, read_opts| {
+                    Ok(geo::candidates(inner_db, index_cf, read_opts, &bbox)?
+                        .into_iter()
+                        .map(|key| (key, ()))
+                        .collect())
+                },
+            )
+            .await?;
 
-            let mut data = self.data.lock().unwrap();
-            data.insert(name.to_string(), HashMap::new());
-            Ok(())
-        }
+        let origin = Shape::Point(point);
+        let mut results: Vec<(Document, f64)> = candidates
+            .into_iter()
+            .filter_map(|(doc, ())| {
+                let distance = origin.distance(&geo::document_shape(&field, &doc)?);
+                (distance <= max_dist).then_some((doc, distance))
+            })
+            .collect();
+        // Candidates come in key order, which breaks ties between equal distances
+        results.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        results.truncate(max_results);
+        Ok(results)
+    }
 
-        async fn drop_database(&self, name: &str) -> Result<()> {
-            self.increment_operation_count();
-            let mut databases = self.databases.lock().unwrap();
-            databases.retain(|db| db != name);
+    #[allow(clippy::too_many_arguments)]
+    async fn nearest(
+        &self,
+        db: &str,
+        table: &str,
+        index: &str,
+        vector: &[f64],
+        k: usize,
+        probes: usize,
+        predicate: Option<Predicate>,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<Vec<(Document, f64)>> {
+        let vector = vector.to_vec();
+        let not_vector = format!("index {index} is not a vector index");
+        // A filter may reject the nearest documents, so every list is searched for
+        // enough of the others
+        let probes = predicate.is_none().then_some(probes);
+        self.read_index(
+            db,
+            table,
+            index,
+            snapshot,
+            predicate,
+            Some(k),
+            move |inner_db, index_cf, kind, read_opts| match kind {
+                IndexKind::Vector(options) => {
+                    let vector = options.query_vector(&vector)?;
+                    vector::nearest(inner_db, index_cf, options, read_opts, &vector, probes)
+                }
+                _ => Err(StorageError::Index(not_vector)),
+            },
+        )
+        .await
+    }
 
-            let mut data = self.data.lock().unwrap();
-            data.remove(name);
-            Ok(())
-        }
+    async fn create_snapshot(&self) -> Result<SnapshotId> {
+        // Hold the shard maps still so they match the data the snapshot sees
+        let shard_maps = self.shard_maps.read().unwrap();
+        let guards: Vec<_> = shard_maps
+            .iter()
+            .map(|(table_name, table_shards)| (table_name, table_shards.read()))
+            .collect();
+        let snapshot = PinnedSnapshot::new(
+            self.inner.clone(),
+            guards
+                .iter()
+                .map(|(table_name, shard_map)| (table_name.to_string(), (**shard_map).clone()))
+                .collect(),
+        );
+        drop(guards);
+        Ok(self.snapshots.insert(snapshot))
+    }
 
-        async fn database_exists(&self, name: &str) -> Result<bool> {
-            self.increment_operation_count();
-            let databases = self.databases.lock().unwrap();
-            Ok(databases.contains(&name.to_string()))
-        }
+    async fn release_snapshot(&self, snapshot: SnapshotId) -> Result<()> {
+        self.snapshots.release(snapshot);
+        Ok(())
+    }
 
-        async fn create_table(&self, db: &str, table: &str) -> Result<()> {
-            self.increment_operation_count();
-            let mut data = self.data.lock().unwrap();
-            if let Some(db_data) = data.get_mut(db) {
-                db_data.insert(table.to_string(), HashMap::new());
-                Ok(())
-            } else {
-                Err(StorageError::InvalidDatabaseName(db.to_string()))
-            }
+    async fn updates_since(&self, sequence: u64, max_batches: usize) -> Result<ReplicationUpdates> {
+        if !self.replication.is_primary() {
+            return Err(StorageError::Replication(
+                "only a primary can serve replicas".to_string(),
+            ));
         }
 
-        async fn drop_table(&self, db: &str, table: &str) -> Result<()> {
-            self.increment_operation_count();
-            let mut data = self.data.lock().unwrap();
-            if let Some(db_data) = data.get_mut(db) {
-                db_data.remove(table);
-                Ok(())
-            } else {
-                Err(StorageError::InvalidDatabaseName(db.to_string()))
-            }
-        }
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
 
-        async fn table_exists(&self, db: &str, table: &str) -> Result<bool> {
-            self.increment_operation_count();
-            let data = self.data.lock().unwrap();
-            if let Some(db_data) = data.get(db) {
-                Ok(db_data.contains_key(table))
-            } else {
-                Err(StorageError::InvalidDatabaseName(db.to_string()))
-            }
-        }
+        let inner_db = self.inner.clone();
+        let table_configs = self.table_configs.clone();
 
-        async fn put(&self, db: &str, table: &str, key: &str, doc: &Document) -> Result<()> {
-            self.increment_operation_count();
-            let mut data = self.data.lock().unwrap();
-            if let Some(db_data) = data.get_mut(db) {
-                if let Some(table_data) = db_data.get_mut(table) {
-                    table_data.insert(key.to_string(), doc.clone());
-                    Ok(())
-                } else {
-                    Err(StorageError::InvalidTableName(table.to_string()))
-                }
-            } else {
-                Err(StorageError::InvalidDatabaseName(db.to_string()))
+        spawn_blocking(move || {
+            let latest_sequence = inner_db.latest_sequence_number();
+            let mut updates = ReplicationUpdates {
+                batches: Vec::new(),
+                latest_sequence,
+            };
+            if sequence > latest_sequence {
+                return Ok(updates);
             }
-        }
 
-        async fn put_batch(
-            &self,
-            db: &str,
-            table: &str,
-            docs: &[(String, Document)],
-        ) -> Result<()> {
-            self.increment_operation_count();
-            let mut data = self.data.lock().unwrap();
-            if let Some(db_data) = data.get_mut(db) {
-                if let Some(table_data) = db_data.get_mut(table) {
-                    for (key, doc) in docs {
-                        table_data.insert(key.clone(), doc.clone());
-                    }
-                    Ok(())
-                } else {
-                    Err(StorageError::InvalidTableName(table.to_string()))
+            let column_families = Self::column_family_names(&inner_db, &table_configs);
+            for res in inner_db.get_updates_since(sequence)? {
+                let (batch_sequence, batch) = res?;
+                if updates.batches.is_empty() && batch_sequence > sequence {
+                    return Err(StorageError::Replication(format!(
+                        "the write-ahead log no longer holds sequence {sequence}, the oldest \
+                         available is {batch_sequence}; the replica must be restored from a \
+                         copy of the primary"
+                    )));
                 }
-            } else {
-                Err(StorageError::InvalidDatabaseName(db.to_string()))
-            }
-        }
 
-        async fn get(&self, db: &str, table: &str, key: &str) -> Result<Option<Document>> {
-            self.increment_operation_count();
-            let data = self.data.lock().unwrap();
-            if let Some(db_data) = data.get(db) {
-                if let Some(table_data) = db_data.get(table) {
-                    Ok(table_data.get(key).cloned())
-                } else {
-                    Err(StorageError::InvalidTableName(table.to_string()))
+                updates.batches.push(WalBatch {
+                    sequence: batch_sequence,
+                    count: batch.len() as u64,
+                    operations: decode_write_batch(batch.data(), &column_families)?,
+                });
+                if updates.batches.len() >= max_batches {
+                    break;
                 }
-            } else {
-                Err(StorageError::InvalidDatabaseName(db.to_string()))
             }
-        }
-
-        async fn scan_table(
-            &self,
-            db: &str,
-            table: &str,
-            _start_key: Option<String>,
-            limit: Option<usize>,
-            skip: Option<usize>,
-            predicate: Option<Box<dyn Fn(Document) -> bool + Send + Sync>>,
-        ) -> Result<ReceiverStream<Result<Document>>> {
-            self.increment_operation_count();
-            let (tx, rx) = mpsc::channel(100);
 
-            let data = self.data.lock().unwrap();
-            if let Some(db_data) = data.get(db) {
-                if let Some(table_data) = db_data.get(table) {
-                    let mut docs: Vec<Document> = table_data.values().cloned().collect();
+            Ok(updates)
+        })
+        .await
+        .unwrap()
+    }
 
-                    if let Some(pred) = predicate {
-                        docs.retain(|doc| pred(doc.clone()));
-                    }
+    async fn apply_updates(&self, updates: ReplicationUpdates) -> Result<u64> {
+        let inner_db = self.inner.clone();
+        let schema_lock = self.schema_lock.clone();
+        let table_configs = self.table_configs.clone();
+        let table_statistics = self.table_statistics.clone();
+        let shard_maps = self.shard_maps.clone();
+        let indexes = self.indexes.clone();
+        let block_cache = self.block_cache.clone();
+        let replication = self.replication.clone();
+        let schema_version = self.schema_version.clone();
 
-                    let skip = skip.unwrap_or(0);
-                    let limit = limit.unwrap_or(docs.len());
-                    let docs: Vec<Document> = docs.into_iter().skip(skip).take(limit).collect();
+        spawn_blocking(move || {
+            let _lock = schema_lock.write().unwrap();
+            let mut applied_sequence = replication.applied_sequence();
 
-                    tokio::spawn(async move {
-                        for doc in docs {
-                            if tx.send(Ok(doc)).await.is_err() {
-                                break;
-                            }
-                        }
-                    });
+            for batch in &updates.batches {
+                // Batches are resent when a request is retried
+                if batch.next_sequence() <= applied_sequence + 1 {
+                    continue;
+                }
 
-                    Ok(ReceiverStream::new(rx))
-                } else {
-                    Err(StorageError::InvalidTableName(table.to_string()))
+                let result = Self::apply_wal_batch(
+                    &inner_db,
+                    batch,
+                    &table_configs,
+                    &table_statistics,
+                    &shard_maps,
+                    &indexes,
+                    &block_cache,
+                );
+                if Self::changes_schema(batch) {
+                    schema_version.fetch_add(1, Ordering::AcqRel);
                 }
-            } else {
-                Err(StorageError::InvalidDatabaseName(db.to_string()))
+                result?;
+                applied_sequence = batch.next_sequence() - 1;
+                replication.record_contact(applied_sequence, updates.latest_sequence);
             }
-        }
 
-        async fn delete(&self, db: &str, table: &str, key: &str) -> Result<()> {
-            self.increment_operation_count();
-            let mut data = self.data.lock().unwrap();
-            if let Some(db_data) = data.get_mut(db) {
-                if let Some(table_data) = db_data.get_mut(table) {
-                    table_data.remove(key);
-                    Ok(())
-                } else {
-                    Err(StorageError::InvalidTableName(table.to_string()))
+            replication.record_contact(applied_sequence, updates.latest_sequence);
+            Ok(applied_sequence)
+        })
+        .await
+        .unwrap()
+    }
+
+    fn replication_status(&self) -> ReplicationStatus {
+        self.replication.status(self.inner.latest_sequence_number())
+    }
+
+    fn applied_index(&self) -> Result<u64> {
+        let meta = SystemTable::Meta.to_string();
+        let cf = self
+            .inner
+            .cf_handle(&meta)
+            .ok_or(StorageError::MissingColumnFamily(meta))?;
+        Ok(self
+            .inner
+            .get_cf(&cf, APPLIED_INDEX_KEY)?
+            .and_then(|value| value.try_into().ok())
+            .map_or(0, u64::from_be_bytes))
+    }
+
+    async fn record_applied_index(&self, index: u64, durability: Durability) -> Result<()> {
+        let inner_db = self.inner.clone();
+        let group_commit = self.group_commit.clone();
+        spawn_blocking(move || {
+            let meta = SystemTable::Meta.to_string();
+            let cf = inner_db
+                .cf_handle(&meta)
+                .ok_or(StorageError::MissingColumnFamily(meta))?;
+            // Written through the same log as the entry's writes, and after them
+            inner_db.put_cf_opt(
+                &cf,
+                APPLIED_INDEX_KEY,
+                index.to_be_bytes(),
+                &Self::create_write_opts(),
+            )?;
+            Self::sync_write(&inner_db, &group_commit, durability)
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn export_snapshot(&self) -> Result<Vec<u8>> {
+        let inner_db = self.inner.clone();
+        let schema_lock = self.schema_lock.clone();
+        let table_configs = self.table_configs.clone();
+
+        spawn_blocking(move || {
+            let _lock = schema_lock.read().unwrap();
+            let tables: Vec<String> = table_configs.read().unwrap().keys().cloned().collect();
+            let snapshot = inner_db.snapshot();
+
+            let mut column_families = Vec::new();
+            for cf_name in SystemTable::variants()
+                .iter()
+                .map(ToString::to_string)
+                .chain(tables)
+            {
+                let Some(cf) = inner_db.cf_handle(&cf_name) else {
+                    continue;
+                };
+                let mut read_opts = Self::create_read_opts();
+                read_opts.set_snapshot(&snapshot);
+                let pairs = inner_db
+                    .iterator_cf_opt(&cf, read_opts, IteratorMode::Start)
+                    .map(|res| res.map(|(key, value)| (key.to_vec(), value.to_vec())))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                column_families.push((cf_name, pairs));
+            }
+
+            Ok(bincode::serde::encode_to_vec(
+                &DatabaseSnapshot { column_families },
+                bincode::config::standard(),
+            )?)
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn install_snapshot(&self, snapshot: &[u8]) -> Result<()> {
+        let (snapshot, _) = bincode::serde::decode_from_slice::<DatabaseSnapshot, _>(
+            snapshot,
+            bincode::config::standard(),
+        )?;
+
+        let inner_db = self.inner.clone();
+        let schema_lock = self.schema_lock.clone();
+        let table_configs = self.table_configs.clone();
+        let table_statistics = self.table_statistics.clone();
+        let shard_maps = self.shard_maps.clone();
+        let indexes = self.indexes.clone();
+        let block_cache = self.block_cache.clone();
+
+        let result = spawn_blocking(move || {
+            let _lock = schema_lock.write().unwrap();
+            let meta = SystemTable::Meta.to_string();
+            let system_tables: HashSet<String> = SystemTable::variants()
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+
+            // The meta table is emptied first and filled last, so that a crash in between
+            // leaves no applied index behind
+            Self::clear_column_family(&inner_db, &meta)?;
+            let tables: Vec<String> = table_configs.read().unwrap().keys().cloned().collect();
+            for cf_name in tables {
+                if inner_db.cf_handle(&cf_name).is_some() {
+                    inner_db.drop_cf(&cf_name)?;
+                }
+            }
+            for cf_name in system_tables.iter().filter(|cf_name| **cf_name != meta) {
+                Self::clear_column_family(&inner_db, cf_name)?;
+            }
+
+            // Tables are created with the configurations in the schemas table
+            for (cf_name, pairs) in &snapshot.column_families {
+                if system_tables.contains(cf_name) && *cf_name != meta {
+                    Self::write_snapshot_pairs(&inner_db, cf_name, pairs)?;
+                }
+            }
+            let mut configs = Self::read_table_configs(&inner_db)?;
+            for (cf_name, pairs) in &snapshot.column_families {
+                if system_tables.contains(cf_name) {
+                    continue;
+                }
+                let config = configs.entry(cf_name.clone()).or_default();
+                inner_db.create_cf(cf_name, &config.cf_options(&block_cache))?;
+                Self::write_snapshot_pairs(&inner_db, cf_name, pairs)?;
+            }
+            for (cf_name, pairs) in &snapshot.column_families {
+                if *cf_name == meta {
+                    Self::write_snapshot_pairs(&inner_db, cf_name, pairs)?;
+                }
+            }
+            inner_db.flush_wal(true)?;
+
+            let statistics = Self::load_table_statistics(&inner_db)?;
+            let shards = Self::load_shard_maps(&inner_db)?;
+            indexes.reload(&inner_db, &SystemTable::Indexes.to_string(), |_| true)?;
+            *table_configs.write().unwrap() = configs;
+            *table_statistics.write().unwrap() = statistics;
+            *shard_maps.write().unwrap() = shards;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        self.schema_changed();
+        result
+    }
+
+    async fn stream_databases(
+        &self,
+        start_key: Option<String>,
+        limit: Option<usize>,
+        skip: Option<usize>,
+    ) -> Result<ReceiverStream<Result<String>>> {
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
+
+        let inner_db = self.inner.clone();
+        let read_opts = Self::create_read_opts();
+        // Don't apply artificial limits - use provided limit or no limit at all
+        let skip = skip.unwrap_or(0);
+        // Set channel capacity based on limit or use default
+        let channel_capacity = limit.unwrap_or(1000).clamp(1, 1000);
+        let (tx, rx) = mpsc::channel(channel_capacity);
+
+        spawn_blocking(move || {
+            let cf = inner_db
+                .cf_handle(&SystemTable::Databases.to_string())
+                .ok_or_else(|| {
+                    StorageError::MissingColumnFamily(SystemTable::Databases.to_string())
+                })?;
+
+            let mode = start_key.as_ref().map_or(IteratorMode::Start, |key| {
+                IteratorMode::From(key.as_bytes(), Direction::Forward)
+            });
+
+            let iterator = inner_db
+                .iterator_cf_opt(&cf, read_opts, mode)
+                .skip(if start_key.is_some() { 1 + skip } else { skip });
+
+            // Apply limit only if explicitly provided
+            let limited_iterator: Box<dyn Iterator<Item = _>> = match limit {
+                Some(l) => Box::new(iterator.take(l)),
+                None => Box::new(iterator),
+            };
+
+            for res in limited_iterator {
+                match res {
+                    Ok((key_bytes, _)) => match String::from_utf8(key_bytes.to_vec()) {
+                        Ok(db_name) => {
+                            if !is_system_db(&db_name) && tx.blocking_send(Ok(db_name)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            let _ = tx.blocking_send(Err(StorageError::InvalidUtf8(e)));
+                            break;
+                        }
+                    },
+                    Err(e) => {
+                        let _ = tx.blocking_send(Err(StorageError::BackendError(e)));
+                        break;
+                    }
                 }
-            } else {
-                Err(StorageError::InvalidDatabaseName(db.to_string()))
             }
+
+            Ok::<(), StorageError>(())
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    async fn stream_tables(
+        &self,
+        db: &str,
+        start_key: Option<String>,
+        limit: Option<usize>,
+        skip: Option<usize>,
+    ) -> Result<ReceiverStream<Result<String>>> {
+        if !is_valid_key(db) || is_system_db(db) {
+            return Err(StorageError::InvalidDatabaseName(db.to_string()));
         }
 
-        async fn stream_databases(
-            &self,
-            _start_key: Option<String>,
-            limit: Option<usize>,
-            skip: Option<usize>,
-        ) -> Result<ReceiverStream<Result<String>>> {
-            self.increment_operation_count();
-            let (tx, rx) = mpsc::channel(100);
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
+
+        let opts = self.opts.clone();
+        let path = self.path.clone();
+        let db = db.to_string();
+        // Don't apply artificial limits - use provided limit or no limit at all
+        let skip = skip.unwrap_or(0);
+        let channel_capacity = limit.unwrap_or(1000).clamp(1, 1000);
+        let (tx, rx) = mpsc::channel(channel_capacity);
+
+        spawn_blocking(move || {
+            let prefix = format!("{db}:");
+            let mut tables: Vec<String> = DB::list_cf(&opts, &path)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|cf_name| {
+                    if cf_name.starts_with(&prefix)
+                        && !is_shard_name(&cf_name)
+                        && !is_index_name(&cf_name)
+                    {
+                        extract_table_from_database(&cf_name).map(std::string::ToString::to_string)
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+
+            // Sort for consistent pagination
+            tables.sort();
+
+            // Apply start_key filter
+            if let Some(start_key) = start_key {
+                tables.retain(|table| table >= &start_key);
+            }
+
+            // Apply skip and limit (only if limit is provided)
+            let tables: Vec<String> = match limit {
+                Some(l) => tables.into_iter().skip(skip).take(l).collect(),
+                None => tables.into_iter().skip(skip).collect(),
+            };
+
+            for table in tables {
+                if tx.blocking_send(Ok(table)).is_err() {
+                    break;
+                }
+            }
+
+            Ok::<(), StorageError>(())
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    async fn stream_get_all(
+        &self,
+        db: &str,
+        table: &str,
+        keys: &[String],
+        start_key: Option<String>,
+        limit: Option<usize>,
+        skip: Option<usize>,
+        snapshot: Option<SnapshotId>,
+    ) -> Result<ReceiverStream<Result<Document>>> {
+        if !is_valid_key(db) || is_system_db(db) {
+            return Err(StorageError::InvalidDatabaseName(db.to_string()));
+        }
+
+        let _permit = self
+            .operation_semaphore
+            .acquire()
+            .await
+            .map_err(|_| StorageError::ResourceExhausted)?;
+
+        let inner_db = self.inner.clone();
+        let shard_maps = self.shard_maps.clone();
+        let table_name = format_table_name(db, table);
+        let keys = keys.to_vec();
+        let snapshot = self.pinned_snapshot(snapshot)?;
+        // Don't apply artificial limits - use provided limit or no limit at all
+        let skip = skip.unwrap_or(0);
+        // Set channel capacity based on limit or use default
+        let channel_capacity = limit.unwrap_or(1000).clamp(1, 1000);
+        let (tx, rx) = mpsc::channel(channel_capacity);
+
+        spawn_blocking(move || {
+            let snapshot = Self::shard_snapshot(&inner_db, &shard_maps, &table_name, snapshot);
+            let shard_map = snapshot
+                .as_ref()
+                .and_then(|snapshot| snapshot.shard_map(&table_name));
+            let shards = Self::shard_handles(&inner_db, &table_name, shard_map)?;
+
+            let mut filtered_keys = keys;
+
+            // Apply start_key filter if provided
+            if let Some(start_key) = start_key {
+                let start_index = filtered_keys
+                    .iter()
+                    .position(|k| k >= &start_key)
+                    .unwrap_or(filtered_keys.len());
+                filtered_keys = filtered_keys.into_iter().skip(start_index).collect();
+            }
+
+            // Apply skip and limit (only if limit is provided)
+            let filtered_keys: Vec<String> = match limit {
+                Some(l) => filtered_keys.into_iter().skip(skip).take(l).collect(),
+                None => filtered_keys.into_iter().skip(skip).collect(),
+            };
+
+            let read_opts = Self::snapshot_read_opts(snapshot.as_deref());
+            for key in filtered_keys {
+                let shard = shard_map.map_or(0, |shard_map| shard_map.index_of(&key));
+                match inner_db.get_cf_opt(&shards[shard], &key, &read_opts) {
+                    Ok(Some(val)) => match parse_doc(val.as_slice()) {
+                        Ok(doc) => {
+                            if tx.blocking_send(Ok(doc)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            let _ = tx.blocking_send(Err(e));
+                            break;
+                        }
+                    },
+                    Ok(None) => {
+                        // Key not found, skip
+                        continue;
+                    }
+                    Err(e) => {
+                        let _ = tx.blocking_send(Err(StorageError::BackendError(e)));
+                        break;
+                    }
+                }
+            }
+
+            Ok::<(), StorageError>(())
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+}
+
+impl StatisticsProvider for DefaultStorage {
+    fn schema_version(&self) -> u64 {
+        self.schema_version.load(Ordering::Acquire)
+    }
+
+    fn table_statistics(&self, db: &str, table: &str) -> Option<TableStatistics> {
+        let table_name = format_table_name(db, table);
+        let shard_map = Self::shard_map(&self.shard_maps, &table_name);
+        let shards = Self::shard_handles(&self.inner, &table_name, shard_map.as_ref()).ok()?;
+        let row_count = shards
+            .iter()
+            .map(|cf| {
+                self.inner
+                    .property_int_value_cf(cf, ESTIMATE_NUM_KEYS)
+                    .ok()
+                    .flatten()
+                    .unwrap_or(0)
+            })
+            .sum();
+
+        let mut statistics = self
+            .table_statistics
+            .read()
+            .unwrap()
+            .get(&table_name)
+            .cloned()
+            .unwrap_or_default();
+        statistics.row_count = row_count;
+        Some(statistics)
+    }
+
+    fn table_count(&self, db: &str) -> Option<u64> {
+        let prefix = format_table_name(db, "");
+        let table_configs = self.table_configs.read().unwrap();
+        Some(
+            table_configs
+                .keys()
+                .filter(|name| {
+                    name.starts_with(&prefix) && !is_shard_name(name) && !is_index_name(name)
+                })
+                .count() as u64,
+        )
+    }
+
+    fn database_count(&self) -> Option<u64> {
+        let cf = self.inner.cf_handle(&SystemTable::Databases.to_string())?;
+        self.inner
+            .property_int_value_cf(&cf, ESTIMATE_NUM_KEYS)
+            .ok()
+            .flatten()
+    }
+}
+
+#[inline]
+fn statistics_key(table_name: &str) -> String {
+    format!("{STATISTICS_KEY_PREFIX}{table_name}")
+}
+
+#[inline]
+fn shard_map_key(table_name: &str) -> String {
+    format!("{SHARD_MAP_KEY_PREFIX}{table_name}")
+}
+
+#[inline]
+fn is_system_db(db_name: &str) -> bool {
+    db_name == SYSTEM_DATABASE
+}
+
+#[inline]
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= 255 && // Add reasonable length limit
+        key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+#[inline]
+fn format_table_name(db: &str, table: &str) -> String {
+    let mut result = String::with_capacity(db.len() + table.len() + 1);
+    result.push_str(db);
+    result.push(':');
+    result.push_str(table);
+    result
+}
+
+#[inline]
+fn extract_table_from_database(table_name: &str) -> Option<&str> {
+    table_name.split_once(':').map(|(_, table)| table)
+}
+
+#[inline]
+fn parse_doc(data: &[u8]) -> Result<Document> {
+    log::trace!("Attempting to deserialize document, {} bytes", data.len());
+    encoding::decode_document(data).inspect_err(|e| {
+        log::error!(
+            "Failed to deserialize document: {} (data: {} bytes)",
+            e,
+            data.len()
+        );
+    })
+}
+
+/// Decode a scanned document, evaluating the predicate and projection against only the
+/// fields they need, so that only the documents the predicate accepts are decoded in
+/// full. Returns `None` if the predicate rejects the document.
+fn scan_doc(
+    data: &[u8],
+    predicate: Option<&Predicate>,
+    projection: &ScanProjection,
+) -> Result<Option<Document>> {
+    let predicate_fields = predicate.and(Some(&projection.predicate_fields));
+    if matches!(predicate_fields, Some(None)) {
+        let doc = parse_doc(data)?;
+        return Ok(match predicate {
+            Some(predicate)
+                if !predicate(doc.clone()).map_err(StorageError::PredicateFailed)? =>
+            {
+                None
+            }
+            _ => Some(projection.apply(doc)),
+        });
+    }
+
+    let encoded = EncodedDocument::parse(data)?;
+
+    if let (Some(predicate), Some(Some(fields))) = (predicate, predicate_fields) {
+        let input = encoded.decode_fields(fields)?;
+        if !predicate(input).map_err(StorageError::PredicateFailed)? {
+            return Ok(None);
+        }
+    }
+
+    match &projection.output_fields {
+        Some(fields) => encoded.decode_fields(fields).map(Some),
+        None => encoded.decode().map(Some),
+    }
+}
+
+#[cfg(test)]
+pub mod memory {
+    use super::*;
+    use std::sync::Mutex;
+
+    type Data = HashMap<String, HashMap<String, HashMap<String, Document>>>;
+
+    // Mock in-memory storage backend for benchmarking
+    #[derive(Debug, Clone)]
+    pub struct MemoryStorage {
+        data: Arc<Mutex<Data>>,
+        databases: Arc<Mutex<Vec<String>>>,
+        snapshots: Arc<Mutex<HashMap<SnapshotId, Arc<Data>>>>,
+        statistics: Arc<Mutex<HashMap<String, TableStatistics>>>,
+        operation_count: Arc<Mutex<u64>>,
+    }
+
+    impl Default for MemoryStorage {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl MemoryStorage {
+        pub fn new() -> Self {
+            Self {
+                data: Arc::new(Mutex::new(HashMap::new())),
+                databases: Arc::new(Mutex::new(vec!["default".to_string()])),
+                snapshots: Arc::new(Mutex::new(HashMap::new())),
+                statistics: Arc::new(Mutex::new(HashMap::new())),
+                operation_count: Arc::new(Mutex::new(0)),
+            }
+        }
+
+        fn snapshot_data(&self, snapshot: SnapshotId) -> Result<Arc<Data>> {
+            self.snapshots
+                .lock()
+                .unwrap()
+                .get(&snapshot)
+                .cloned()
+                .ok_or(StorageError::SnapshotNotFound(snapshot))
+        }
+
+        fn increment_operation_count(&self) {
+            let mut count = self.operation_count.lock().unwrap();
+            *count += 1;
+        }
+
+        pub fn get_operation_count(&self) -> u64 {
+            *self.operation_count.lock().unwrap()
+        }
+
+        pub fn reset_operation_count(&self) {
+            let mut count = self.operation_count.lock().unwrap();
+            *count = 0;
+        }
+    }
+
+    #[async_trait]
+    impl StorageBackend for MemoryStorage {
+        async fn create_database(&self, name: &str) -> Result<()> {
+            self.increment_operation_count();
+            let mut databases = self.databases.lock().unwrap();
+            if !databases.contains(&name.to_string()) {
+                databases.push(name.to_string());
+            }
+
+            let mut data = self.data.lock().unwrap();
+            data.insert(name.to_string(), HashMap::new());
+            Ok(())
+        }
+
+        async fn drop_database(&self, name: &str) -> Result<()> {
+            self.increment_operation_count();
+            let mut databases = self.databases.lock().unwrap();
+            databases.retain(|db| db != name);
+
+            let mut data = self.data.lock().unwrap();
+            data.remove(name);
+            Ok(())
+        }
+
+        async fn database_exists(&self, name: &str) -> Result<bool> {
+            self.increment_operation_count();
+            let databases = self.databases.lock().unwrap();
+            Ok(databases.contains(&name.to_string()))
+        }
+
+        async fn create_table(&self, db: &str, table: &str, config: &TableConfig) -> Result<()> {
+            config.validate()?;
+            self.increment_operation_count();
+            let mut data = self.data.lock().unwrap();
+            if let Some(db_data) = data.get_mut(db) {
+                db_data.insert(table.to_string(), HashMap::new());
+                Ok(())
+            } else {
+                Err(StorageError::InvalidDatabaseName(db.to_string()))
+            }
+        }
+
+        async fn drop_table(&self, db: &str, table: &str) -> Result<()> {
+            self.increment_operation_count();
+            let mut data = self.data.lock().unwrap();
+            if let Some(db_data) = data.get_mut(db) {
+                db_data.remove(table);
+                Ok(())
+            } else {
+                Err(StorageError::InvalidDatabaseName(db.to_string()))
+            }
+        }
+
+        async fn table_exists(&self, db: &str, table: &str) -> Result<bool> {
+            self.increment_operation_count();
+            let data = self.data.lock().unwrap();
+            if let Some(db_data) = data.get(db) {
+                Ok(db_data.contains_key(table))
+            } else {
+                Err(StorageError::InvalidDatabaseName(db.to_string()))
+            }
+        }
+
+        async fn put(
+            &self,
+            db: &str,
+            table: &str,
+            key: &str,
+            doc: &Document,
+            _durability: Option<Durability>,
+        ) -> Result<()> {
+            self.increment_operation_count();
+            let mut data = self.data.lock().unwrap();
+            if let Some(db_data) = data.get_mut(db) {
+                if let Some(table_data) = db_data.get_mut(table) {
+                    table_data.insert(key.to_string(), doc.clone());
+                    Ok(())
+                } else {
+                    Err(StorageError::InvalidTableName(table.to_string()))
+                }
+            } else {
+                Err(StorageError::InvalidDatabaseName(db.to_string()))
+            }
+        }
+
+        async fn put_batch(
+            &self,
+            db: &str,
+            table: &str,
+            docs: &[(String, Document)],
+            _durability: Option<Durability>,
+        ) -> Result<()> {
+            self.increment_operation_count();
+            let mut data = self.data.lock().unwrap();
+            if let Some(db_data) = data.get_mut(db) {
+                if let Some(table_data) = db_data.get_mut(table) {
+                    for (key, doc) in docs {
+                        table_data.insert(key.clone(), doc.clone());
+                    }
+                    Ok(())
+                } else {
+                    Err(StorageError::InvalidTableName(table.to_string()))
+                }
+            } else {
+                Err(StorageError::InvalidDatabaseName(db.to_string()))
+            }
+        }
+
+        async fn update_batch(
+            &self,
+            db: &str,
+            table: &str,
+            keys: &[String],
+            update: DocumentUpdate,
+            _durability: Option<Durability>,
+        ) -> Result<usize> {
+            self.increment_operation_count();
+            let mut data = self.data.lock().unwrap();
+            let table_data = data
+                .get_mut(db)
+                .ok_or_else(|| StorageError::InvalidDatabaseName(db.to_string()))?
+                .get_mut(table)
+                .ok_or_else(|| StorageError::InvalidTableName(table.to_string()))?;
+
+            // Apply every update before writing any, so a failed one changes nothing
+            let mut docs = Vec::with_capacity(keys.len());
+            for key in keys {
+                if let Some(stored) = table_data.get(key) {
+                    docs.push((key.clone(), update(stored.clone())?));
+                }
+            }
+            let updated = docs.len();
+            table_data.extend(docs);
+            Ok(updated)
+        }
+
+        async fn get(
+            &self,
+            db: &str,
+            table: &str,
+            key: &str,
+            snapshot: Option<SnapshotId>,
+        ) -> Result<Option<Document>> {
+            self.increment_operation_count();
+            let (live, pinned);
+            let data = match snapshot {
+                Some(id) => {
+                    pinned = self.snapshot_data(id)?;
+                    &*pinned
+                }
+                None => {
+                    live = self.data.lock().unwrap();
+                    &*live
+                }
+            };
+            if let Some(db_data) = data.get(db) {
+                if let Some(table_data) = db_data.get(table) {
+                    Ok(table_data.get(key).cloned())
+                } else {
+                    Err(StorageError::InvalidTableName(table.to_string()))
+                }
+            } else {
+                Err(StorageError::InvalidDatabaseName(db.to_string()))
+            }
+        }
+
+        async fn scan_table(
+            &self,
+            db: &str,
+            table: &str,
+            _start_key: Option<String>,
+            limit: Option<usize>,
+            skip: Option<usize>,
+            predicate: Option<Predicate>,
+            projection: ScanProjection,
+            snapshot: Option<SnapshotId>,
+        ) -> Result<ReceiverStream<Result<Document>>> {
+            self.increment_operation_count();
+            let (tx, rx) = mpsc::channel(100);
+
+            let (live, pinned);
+            let data = match snapshot {
+                Some(id) => {
+                    pinned = self.snapshot_data(id)?;
+                    &*pinned
+                }
+                None => {
+                    live = self.data.lock().unwrap();
+                    &*live
+                }
+            };
+            if let Some(db_data) = data.get(db) {
+                if let Some(table_data) = db_data.get(table) {
+                    let mut docs: Vec<Document> = table_data.values().cloned().collect();
+
+                    if let Some(pred) = predicate {
+                        let mut matched = Vec::with_capacity(docs.len());
+                        for doc in docs {
+                            if pred(doc.clone()).map_err(StorageError::PredicateFailed)? {
+                                matched.push(doc);
+                            }
+                        }
+                        docs = matched;
+                    }
+
+                    let skip = skip.unwrap_or(0);
+                    let limit = limit.unwrap_or(docs.len());
+                    let docs: Vec<Document> = docs
+                        .into_iter()
+                        .skip(skip)
+                        .take(limit)
+                        .map(|doc| projection.apply(doc))
+                        .collect();
+
+                    tokio::spawn(async move {
+                        for doc in docs {
+                            if tx.send(Ok(doc)).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+
+                    Ok(ReceiverStream::new(rx))
+                } else {
+                    Err(StorageError::InvalidTableName(table.to_string()))
+                }
+            } else {
+                Err(StorageError::InvalidDatabaseName(db.to_string()))
+            }
+        }
+
+        async fn delete(
+            &self,
+            db: &str,
+            table: &str,
+            key: &str,
+            _durability: Option<Durability>,
+        ) -> Result<()> {
+            self.increment_operation_count();
+            let mut data = self.data.lock().unwrap();
+            if let Some(db_data) = data.get_mut(db) {
+                if let Some(table_data) = db_data.get_mut(table) {
+                    table_data.remove(key);
+                    Ok(())
+                } else {
+                    Err(StorageError::InvalidTableName(table.to_string()))
+                }
+            } else {
+                Err(StorageError::InvalidDatabaseName(db.to_string()))
+            }
+        }
+
+        async fn delete_batch(
+            &self,
+            db: &str,
+            table: &str,
+            keys: &[String],
+            _durability: Option<Durability>,
+        ) -> Result<()> {
+            self.increment_operation_count();
+            let mut data = self.data.lock().unwrap();
+            let table_data = data
+                .get_mut(db)
+                .ok_or_else(|| StorageError::InvalidDatabaseName(db.to_string()))?
+                .get_mut(table)
+                .ok_or_else(|| StorageError::InvalidTableName(table.to_string()))?;
+            for key in keys {
+                table_data.remove(key);
+            }
+            Ok(())
+        }
+
+        async fn analyze_table(
+            &self,
+            db: &str,
+            table: &str,
+            _sample_size: usize,
+        ) -> Result<TableStatistics> {
+            self.increment_operation_count();
+            let mut builder = StatisticsBuilder::new();
+            {
+                let data = self.data.lock().unwrap();
+                let table_data = data
+                    .get(db)
+                    .ok_or_else(|| StorageError::InvalidDatabaseName(db.to_string()))?
+                    .get(table)
+                    .ok_or_else(|| StorageError::InvalidTableName(table.to_string()))?;
+                for doc in table_data.values() {
+                    builder.add(doc);
+                }
+            }
+
+            let statistics = builder.build();
+            self.statistics
+                .lock()
+                .unwrap()
+                .insert(format_table_name(db, table), statistics.clone());
+            Ok(statistics)
+        }
+
+        async fn create_snapshot(&self) -> Result<SnapshotId> {
+            let data = self.data.lock().unwrap().clone();
+            let mut snapshots = self.snapshots.lock().unwrap();
+            let id = snapshots.keys().max().map_or(1, |id| id + 1);
+            snapshots.insert(id, Arc::new(data));
+            Ok(id)
+        }
+
+        async fn release_snapshot(&self, snapshot: SnapshotId) -> Result<()> {
+            self.snapshots.lock().unwrap().remove(&snapshot);
+            Ok(())
+        }
+
+        async fn updates_since(
+            &self,
+            _sequence: u64,
+            _max_batches: usize,
+        ) -> Result<ReplicationUpdates> {
+            Ok(ReplicationUpdates::default())
+        }
+
+        async fn apply_updates(&self, _updates: ReplicationUpdates) -> Result<u64> {
+            Ok(0)
+        }
+
+        fn replication_status(&self) -> ReplicationStatus {
+            let operations = *self.operation_count.lock().unwrap();
+            ReplicationStatus {
+                role: ReplicationRole::Primary,
+                applied_sequence: operations,
+                primary_sequence: operations,
+                last_contact: None,
+            }
+        }
+
+        async fn stream_databases(
+            &self,
+            _start_key: Option<String>,
+            limit: Option<usize>,
+            skip: Option<usize>,
+        ) -> Result<ReceiverStream<Result<String>>> {
+            self.increment_operation_count();
+            let (tx, rx) = mpsc::channel(100);
+
+            let databases = self.databases.lock().unwrap();
+            let dbs = databases.clone();
+
+            let skip_count = skip.unwrap_or(0);
+            let limit_count = limit.unwrap_or(dbs.len());
+            let dbs: Vec<String> = dbs.into_iter().skip(skip_count).take(limit_count).collect();
+
+            tokio::spawn(async move {
+                for db in dbs {
+                    if tx.send(Ok(db)).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            Ok(ReceiverStream::new(rx))
+        }
+
+        async fn stream_tables(
+            &self,
+            db: &str,
+            _start_key: Option<String>,
+            limit: Option<usize>,
+            skip: Option<usize>,
+        ) -> Result<ReceiverStream<Result<String>>> {
+            self.increment_operation_count();
+            let (tx, rx) = mpsc::channel(100);
+
+            let data = self.data.lock().unwrap();
+            if let Some(db_data) = data.get(db) {
+                let tables: Vec<String> = db_data.keys().cloned().collect();
+
+                let skip_count = skip.unwrap_or(0);
+                let limit_count = limit.unwrap_or(tables.len());
+                let tables: Vec<String> = tables
+                    .into_iter()
+                    .skip(skip_count)
+                    .take(limit_count)
+                    .collect();
+
+                tokio::spawn(async move {
+                    for table in tables {
+                        if tx.send(Ok(table)).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                Ok(ReceiverStream::new(rx))
+            } else {
+                Err(StorageError::InvalidDatabaseName(db.to_string()))
+            }
+        }
+
+        async fn stream_get_all(
+            &self,
+            db: &str,
+            table: &str,
+            keys: &[String],
+            _start_key: Option<String>,
+            limit: Option<usize>,
+            skip: Option<usize>,
+            snapshot: Option<SnapshotId>,
+        ) -> Result<ReceiverStream<Result<Document>>> {
+            self.increment_operation_count();
+            let (tx, rx) = mpsc::channel(100);
+
+            let (live, pinned);
+            let data = match snapshot {
+                Some(id) => {
+                    pinned = self.snapshot_data(id)?;
+                    &*pinned
+                }
+                None => {
+                    live = self.data.lock().unwrap();
+                    &*live
+                }
+            };
+            if let Some(db_data) = data.get(db) {
+                if let Some(table_data) = db_data.get(table) {
+                    let mut docs = Vec::new();
+                    for key in keys {
+                        if let Some(doc) = table_data.get(key) {
+                            docs.push(doc.clone());
+                        }
+                    }
+
+                    let skip = skip.unwrap_or(0);
+                    let limit = limit.unwrap_or(docs.len());
+                    let docs: Vec<Document> = docs.into_iter().skip(skip).take(limit).collect();
+
+                    tokio::spawn(async move {
+                        for doc in docs {
+                            if tx.send(Ok(doc)).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+
+                    Ok(ReceiverStream::new(rx))
+                } else {
+                    Err(StorageError::InvalidTableName(table.to_string()))
+                }
+            } else {
+                Err(StorageError::InvalidDatabaseName(db.to_string()))
+            }
+        }
+    }
+
+    impl StatisticsProvider for MemoryStorage {
+        fn table_statistics(&self, db: &str, table: &str) -> Option<TableStatistics> {
+            let row_count = self.data.lock().unwrap().get(db)?.get(table)?.len() as u64;
+            let mut statistics = self
+                .statistics
+                .lock()
+                .unwrap()
+                .get(&format_table_name(db, table))
+                .cloned()
+                .unwrap_or_default();
+            statistics.row_count = row_count;
+            Some(statistics)
+        }
+
+        fn table_count(&self, db: &str) -> Option<u64> {
+            Some(self.data.lock().unwrap().get(db)?.len() as u64)
+        }
+
+        fn database_count(&self) -> Option<u64> {
+            Some(self.databases.lock().unwrap().len() as u64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Durability;
+    use super::*;
+    use crate::ast::*;
+
+    #[test]
+    fn test_document_serialization_roundtrip() {
+        // Create a document exactly like the one in our failing test
+        let mut doc = Document::new();
+        doc.insert(
+            "id".to_string(),
+            Datum {
+                value: Some(datum::Value::String("test1".to_string())),
+            },
+        );
+        doc.insert(
+            "value".to_string(),
+            Datum {
+                value: Some(datum::Value::String("hello".to_string())),
+            },
+        );
+
+        // Test the exact same serialization used in storage
+        let serialized = bincode::serde::encode_to_vec(&doc, bincode::config::standard())
+            .expect("Failed to serialize document");
+
+        // Test the exact same deserialization used in storage
+        let result = parse_doc(&serialized);
+        match result {
+            Ok(deserialized) => {
+                assert_eq!(doc, deserialized);
+            }
+            Err(e) => {
+                panic!("Failed to deserialize: {e}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_individual_datum_serialization() {
+        let datum = Datum {
+            value: Some(datum::Value::String("test".to_string())),
+        };
+
+        let serialized = bincode::serde::encode_to_vec(&datum, bincode::config::standard())
+            .expect("Failed to serialize datum");
+        let (deserialized, _): (Datum, _) =
+            bincode::serde::decode_from_slice(&serialized, bincode::config::standard())
+                .expect("Failed to deserialize datum");
+
+        assert_eq!(datum, deserialized);
+    }
+
+    #[test]
+    fn test_document_to_datum_conversion() {
+        // Create a document exactly like what's stored in storage
+        let mut doc = Document::new();
+        doc.insert(
+            "id".to_string(),
+            Datum {
+                value: Some(datum::Value::String("test1".to_string())),
+            },
+        );
+        doc.insert(
+            "value".to_string(),
+            Datum {
+                value: Some(datum::Value::String("hello".to_string())),
+            },
+        );
+
+        // Convert to Datum using the same conversion as in evaluator
+        let datum: Datum = doc.into();
+
+        // Test serialization of the converted Datum (this might be where it fails)
+        match bincode::serde::encode_to_vec(&datum, bincode::config::standard()) {
+            Ok(serialized) => {
+                // Test deserialization
+                let decode_result: std::result::Result<(Datum, usize), _> =
+                    bincode::serde::decode_from_slice(&serialized, bincode::config::standard());
+                match decode_result {
+                    Ok((deserialized, _)) => {
+                        assert_eq!(datum, deserialized);
+                    }
+                    Err(e) => {
+                        panic!("❌ Datum deserialization failed: {e}");
+                    }
+                }
+            }
+            Err(e) => {
+                panic!("❌ Datum serialization failed: {e}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_storage_layer_roundtrip() {
+        use tempfile::TempDir;
+
+        // Create temporary storage
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+
+        // Test data
+        let db_name = "test_db";
+        let table_name = "test_table";
+        let key = "test_key";
+
+        // Create a document exactly like in our failing case
+        let mut original_doc = Document::new();
+        original_doc.insert(
+            "id".to_string(),
+            Datum {
+                value: Some(datum::Value::String("test1".to_string())),
+            },
+        );
+        original_doc.insert(
+            "value".to_string(),
+            Datum {
+                value: Some(datum::Value::String("hello".to_string())),
+            },
+        );
+
+        // Step 1: Create database
+        storage
+            .create_database(db_name)
+            .await
+            .expect("Failed to create database");
+
+        // Step 2: Create table
+        storage
+            .create_table(db_name, table_name, &TableConfig::default())
+            .await
+            .expect("Failed to create table");
+
+        // Step 3: Put document
+        storage
+            .put(db_name, table_name, key, &original_doc, None)
+            .await
+            .expect("Failed to put document");
+
+        // Step 4: Get document (this is where the error should occur if it's a storage issue)
+        match storage.get(db_name, table_name, key, None).await {
+            Ok(Some(retrieved_doc)) => {
+                // Verify they match
+                if original_doc != retrieved_doc {
+                    panic!(
+                        "❌ Documents don't match!\nOriginal: {original_doc:?}\nRetrieved: {retrieved_doc:?}"
+                    );
+                }
+            }
+            Ok(None) => {
+                panic!("❌ Document not found");
+            }
+            Err(e) => {
+                panic!("❌ Failed to retrieve document: {e}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_async_execution_pattern() {
+        use std::sync::Arc;
+        use tempfile::TempDir;
+        use tokio::task;
+
+        // Create temporary storage exactly like the server does
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = Arc::new(DefaultStorage::open(&config).expect("Failed to create storage"));
+
+        // Test data
+        let db_name = "test_db";
+        let table_name = "test_table";
+        let key = "test_key";
+
+        // Create document
+        let mut original_doc = Document::new();
+        original_doc.insert(
+            "id".to_string(),
+            Datum {
+                value: Some(datum::Value::String("test1".to_string())),
+            },
+        );
+        original_doc.insert(
+            "value".to_string(),
+            Datum {
+                value: Some(datum::Value::String("hello".to_string())),
+            },
+        );
+
+        // Step 1: Setup storage (like server initialization)
+        let storage_clone = storage.clone();
+        task::spawn(async move {
+            storage_clone
+                .create_database(db_name)
+                .await
+                .expect("Failed to create database");
+        })
+        .await
+        .expect("Database creation task failed");
+
+        let storage_clone = storage.clone();
+        task::spawn(async move {
+            storage_clone
+                .create_table(db_name, table_name, &TableConfig::default())
+                .await
+                .expect("Failed to create table");
+        })
+        .await
+        .expect("Table creation task failed");
+
+        // Step 2: Insert operation (like server insert handling)
+        let storage_clone = storage.clone();
+        let doc_clone = original_doc.clone();
+        let _ = task::spawn(async move {
+            storage_clone
+                .put(db_name, table_name, key, &doc_clone, None)
+                .await
+        })
+        .await
+        .expect("Insert task panicked");
+
+        // Step 3: Get operation (like server get handling) - this should reproduce the error
+        let storage_clone = storage.clone();
+        let get_result =
+            task::spawn(async move { storage_clone.get(db_name, table_name, key, None).await })
+                .await
+                .expect("Get task panicked");
+
+        match get_result {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                panic!("❌ Async get returned None");
+            }
+            Err(e) => {
+                println!("❌ Async get failed: {e}");
+                println!("Error type: {e:?}");
+                panic!("This should reproduce the server error!");
+            }
+        }
+
+        // Step 5: Test concurrent operations (like real server load)
+        let mut tasks = vec![];
+
+        for i in 0..5 {
+            let storage_clone = storage.clone();
+            let test_key = format!("concurrent_key_{i}");
+            let test_doc = original_doc.clone();
+
+            // Spawn concurrent insert
+            let insert_task = task::spawn(async move {
+                storage_clone
+                    .put(db_name, table_name, &test_key, &test_doc, None)
+                    .await
+            });
+            tasks.push(insert_task);
+        }
+
+        // Wait for all inserts
+        for (i, task) in tasks.into_iter().enumerate() {
+            match task.await.expect("Concurrent insert task panicked") {
+                Ok(()) => {}
+                Err(e) => panic!("❌ Concurrent insert {i} failed: {e}"),
+            }
+        }
+
+        // Now test concurrent gets
+        let mut get_tasks = vec![];
+        for i in 0..5 {
+            let storage_clone = storage.clone();
+            let test_key = format!("concurrent_key_{i}");
+
+            let get_task = task::spawn(async move {
+                storage_clone
+                    .get(db_name, table_name, &test_key, None)
+                    .await
+            });
+            get_tasks.push((i, get_task));
+        }
+
+        // Check concurrent get results
+        for (i, task) in get_tasks.into_iter() {
+            match task.await.expect("Concurrent get task panicked") {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("❌ Concurrent get {i} returned None"),
+                Err(e) => {
+                    println!("❌ Concurrent get {i} failed: {e}");
+                    panic!("Concurrent operation error: {e}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_system_table_variants() {
+        let variants = SystemTable::variants();
+        assert_eq!(variants.len(), 5);
+        assert!(variants.contains(&SystemTable::Databases));
+        assert!(variants.contains(&SystemTable::Schemas));
+        assert!(variants.contains(&SystemTable::Indexes));
+        assert!(variants.contains(&SystemTable::Feeds));
+        assert!(variants.contains(&SystemTable::Meta));
+    }
+
+    #[test]
+    fn test_system_table_display() {
+        assert_eq!(SystemTable::Databases.to_string(), "__databases__");
+        assert_eq!(SystemTable::Schemas.to_string(), "__schemas__");
+        assert_eq!(SystemTable::Indexes.to_string(), "__indexes__");
+        assert_eq!(SystemTable::Feeds.to_string(), "__feeds__");
+        assert_eq!(SystemTable::Meta.to_string(), "__meta__");
+    }
+
+    #[test]
+    fn test_storage_error_display() {
+        let storage_error = StorageError::MissingColumnFamily("test_cf".to_string());
+        assert_eq!(storage_error.to_string(), "Missing column family: test_cf");
+
+        let storage_error = StorageError::InvalidDatabaseName("test_db".to_string());
+        assert_eq!(storage_error.to_string(), "Invalid database name: test_db");
+
+        let storage_error = StorageError::InvalidTableName("test_table".to_string());
+        assert_eq!(storage_error.to_string(), "Invalid table name: test_table");
+
+        let storage_error = StorageError::InvalidTableOptions("bad block size".to_string());
+        assert_eq!(
+            storage_error.to_string(),
+            "Invalid table options: bad block size"
+        );
+
+        let storage_error = StorageError::SnapshotNotFound(7);
+        assert_eq!(
+            storage_error.to_string(),
+            "Snapshot not found or expired: 7"
+        );
+
+        let storage_error = StorageError::Sharding("no shards".to_string());
+        assert_eq!(storage_error.to_string(), "Sharding error: no shards");
+
+        let storage_error = StorageError::Index("unknown index".to_string());
+        assert_eq!(storage_error.to_string(), "Index error: unknown index");
+
+        let storage_error = StorageError::ConstraintViolation {
+            index: "email".to_string(),
+            field: "email".to_string(),
+            key: "user1".to_string(),
+        };
+        assert_eq!(
+            storage_error.to_string(),
+            "Constraint violation: unique index email already holds this value of email for document user1"
+        );
+
+        let storage_error = StorageError::InvalidUpdate("tags is not an array".to_string());
+        assert_eq!(
+            storage_error.to_string(),
+            "Invalid update: tags is not an array"
+        );
+
+        let storage_error = StorageError::PredicateFailed("missing field".into());
+        assert_eq!(storage_error.to_string(), "Predicate failed: missing field");
+
+        let storage_error = StorageError::ReadOnlyReplica;
+        assert_eq!(
+            storage_error.to_string(),
+            "Cannot write to a read-only replica"
+        );
+
+        let storage_error = StorageError::ReadOnlySecondary;
+        assert_eq!(
+            storage_error.to_string(),
+            "Cannot write to a read-only secondary instance"
+        );
+
+        let storage_error = StorageError::ResourceExhausted;
+        assert_eq!(
+            storage_error.to_string(),
+            "Resource exhausted - too many concurrent operations"
+        );
+    }
+
+    #[test]
+    fn test_storage_error_source() {
+        use std::error::Error;
+
+        let storage_error = StorageError::MissingColumnFamily("test".to_string());
+        assert!(storage_error.source().is_none());
+
+        let storage_error = StorageError::ResourceExhausted;
+        assert!(storage_error.source().is_none());
+    }
+
+    #[test]
+    fn test_storage_error_from_utf8() {
+        // Test FromUtf8Error conversion
+        let invalid_bytes = vec![0, 159, 146, 150];
+        let utf8_error = String::from_utf8(invalid_bytes).unwrap_err();
+        let storage_error: StorageError = utf8_error.into();
+        matches!(storage_error, StorageError::InvalidUtf8(_));
+    }
+
+    #[test]
+    fn test_storage_error_from_bincode() {
+        // Test encode error case
+        let test_data: Vec<u8> = vec![1, 2, 3, 4];
+        let mut small_buffer = [0u8; 1]; // Too small buffer to cause encode error
+
+        if let Err(encode_error) = bincode::serde::encode_into_slice(
+            &test_data,
+            &mut small_buffer,
+            bincode::config::standard(),
+        ) {
+            let storage_error: StorageError = encode_error.into();
+            matches!(storage_error, StorageError::EncodeError(_));
+        }
+
+        // Test decode error case - invalid data
+        let invalid_data = [255u8; 10];
+        let decode_result: std::result::Result<(Vec<String>, usize), _> =
+            bincode::serde::decode_from_slice(&invalid_data, bincode::config::standard());
+        if let Err(decode_error) = decode_result {
+            let storage_error: StorageError = decode_error.into();
+            matches!(storage_error, StorageError::DecodeError(_));
+        }
+    }
+
+    #[test]
+    fn test_is_valid_key() {
+        assert!(is_valid_key("valid_key"));
+        assert!(is_valid_key("valid-key"));
+        assert!(is_valid_key("validkey123"));
+        assert!(is_valid_key("a"));
+        assert!(is_valid_key("test_table_123"));
+
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("invalid key")); // space
+        assert!(!is_valid_key("invalid@key")); // special char
+        assert!(!is_valid_key("invalid.key")); // dot
+        assert!(!is_valid_key("invalid/key")); // slash
+        assert!(!is_valid_key(&"a".repeat(256))); // too long
+    }
+
+    #[test]
+    fn test_format_table_name() {
+        assert_eq!(format_table_name("db", "table"), "db:table");
+        assert_eq!(
+            format_table_name("test_db", "test_table"),
+            "test_db:test_table"
+        );
+        assert_eq!(format_table_name("", "table"), ":table");
+        assert_eq!(format_table_name("db", ""), "db:");
+    }
+
+    #[test]
+    fn test_extract_table_from_database() {
+        assert_eq!(extract_table_from_database("db:table"), Some("table"));
+        assert_eq!(
+            extract_table_from_database("test_db:test_table"),
+            Some("test_table")
+        );
+        assert_eq!(
+            extract_table_from_database("db:table:with:colons"),
+            Some("table:with:colons")
+        );
+        assert_eq!(extract_table_from_database("no_colon"), None);
+        assert_eq!(extract_table_from_database(""), None);
+        assert_eq!(extract_table_from_database(":"), Some(""));
+        assert_eq!(extract_table_from_database("db:"), Some(""));
+    }
+
+    #[test]
+    fn test_config_default() {
+        let config = Config::default();
+        assert_eq!(config.data_dir, "./data");
+        assert!(config.max_background_jobs > 0);
+        assert!(config.parallelism > 0);
+        assert_eq!(config.level_zero_file_num_compaction_trigger, 4);
+        assert_eq!(config.level_zero_slowdown_writes_trigger, 20);
+        assert_eq!(config.level_zero_stop_writes_trigger, 36);
+        assert_eq!(config.block_cache_size, 268_435_456);
+        assert_eq!(config.max_open_files, 100);
+        assert!(config.use_direct_io_for_flush_and_compaction);
+        assert!(!config.use_direct_reads);
+        assert_eq!(config.bytes_per_sync, 1_048_576);
+        assert_eq!(config.wal_bytes_per_sync, 1_048_576);
+        assert_eq!(config.target_file_size_base, 67_108_864);
+        assert_eq!(config.max_bytes_for_level_base, 268_435_456);
+        assert_eq!(config.write_buffer_size, 67_108_864);
+        assert_eq!(config.max_write_buffer_number, 3);
+        assert_eq!(config.min_write_buffer_number_to_merge, 1);
+    }
+
+    #[test]
+    fn test_cf_cache() {
+        let cache = CFCache::new();
+
+        // Test that cache starts empty
+        // We can't directly test get() without a real database, but we can test the structure
+        assert_eq!(cache.access_counter.load(Ordering::Relaxed), 0);
+
+        // Test that the cache map is accessible
+        assert!(cache.cache.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_database_config_default() {
+        let config = DatabaseConfig::default();
+        // DatabaseConfig is empty for now, but this tests the Default implementation
+        let _ = format!("{config:?}"); // Just ensure it's debuggable
+    }
+
+    #[tokio::test]
+    async fn test_create_database_invalid_names() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+
+        // Test invalid database names
+        assert!(storage.create_database("").await.is_err());
+        assert!(storage.create_database("invalid name").await.is_err());
+        assert!(storage.create_database("invalid@name").await.is_err());
+        assert!(storage.create_database("__system__").await.is_err());
+        assert!(storage.create_database(&"a".repeat(256)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drop_database_invalid_names() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+
+        // Test invalid database names
+        assert!(storage.drop_database("").await.is_err());
+        assert!(storage.drop_database("invalid name").await.is_err());
+        assert!(storage.drop_database("__system__").await.is_err());
+        assert!(storage.drop_database("default").await.is_err());
+        assert!(storage.drop_database("nonexistent_db").await.is_ok()); // Should succeed even if doesn't exist
+    }
+
+    #[tokio::test]
+    async fn test_create_table_invalid_names() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+
+        // Create a valid database first
+        storage
+            .create_database("test_db")
+            .await
+            .expect("Failed to create database");
+
+        // Test invalid database names
+        assert!(
+            storage
+                .create_table("", "table", &TableConfig::default())
+                .await
+                .is_err()
+        );
+        assert!(
+            storage
+                .create_table("__system__", "table", &TableConfig::default())
+                .await
+                .is_err()
+        );
+        assert!(
+            storage
+                .create_table("invalid name", "table", &TableConfig::default())
+                .await
+                .is_err()
+        );
+
+        // Test invalid table names
+        assert!(
+            storage
+                .create_table("test_db", "", &TableConfig::default())
+                .await
+                .is_err()
+        );
+        assert!(
+            storage
+                .create_table("test_db", "invalid name", &TableConfig::default())
+                .await
+                .is_err()
+        );
+        assert!(
+            storage
+                .create_table("test_db", "invalid@table", &TableConfig::default())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_table_config_validate() {
+        assert!(TableConfig::default().validate().is_ok());
+
+        let config = TableConfig {
+            compression: Compression::Zstd,
+            compression_level: Some(6),
+            bloom_bits_per_key: Some(0.0),
+            block_size: Some(64 * 1024),
+            access_pattern: AccessPattern::Scan,
+            durability: Durability::Hard,
+        };
+        assert!(config.validate().is_ok());
+
+        let invalid = [
+            TableConfig {
+                compression: Compression::Lz4,
+                compression_level: Some(3),
+                ..Default::default()
+            },
+            TableConfig {
+                compression_level: Some(23),
+                ..Default::default()
+            },
+            TableConfig {
+                bloom_bits_per_key: Some(f64::NAN),
+                ..Default::default()
+            },
+            TableConfig {
+                block_size: Some(16),
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(matches!(
+                config.validate(),
+                Err(StorageError::InvalidTableOptions(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_table_config_persisted_across_reopen() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let table_config = TableConfig {
+            compression: Compression::Lz4,
+            compression_level: None,
+            bloom_bits_per_key: Some(16.0),
+            block_size: Some(8 * 1024),
+            access_pattern: AccessPattern::PointLookup,
+            durability: Durability::Hard,
+        };
+
+        {
+            let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+            storage
+                .create_database("test_db")
+                .await
+                .expect("Failed to create database");
+            storage
+                .create_table("test_db", "tuned", &table_config)
+                .await
+                .expect("Failed to create table");
+            storage
+                .create_table("test_db", "dropped", &table_config)
+                .await
+                .expect("Failed to create table");
+            storage
+                .drop_table("test_db", "dropped")
+                .await
+                .expect("Failed to drop table");
+
+            assert_eq!(
+                storage.table_config("test_db", "tuned"),
+                Some(table_config.clone())
+            );
+        }
+
+        let storage = DefaultStorage::open(&config).expect("Failed to reopen storage");
+        assert_eq!(storage.table_config("test_db", "tuned"), Some(table_config));
+        assert_eq!(storage.table_config("test_db", "dropped"), None);
+    }
+
+    #[tokio::test]
+    async fn test_hard_durability_writes() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+        storage
+            .create_database("test_db")
+            .await
+            .expect("Failed to create database");
+        storage
+            .create_table(
+                "test_db",
+                "hard",
+                &TableConfig {
+                    durability: Durability::Hard,
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("Failed to create table");
+
+        assert_eq!(
+            storage.write_durability("test_db:hard", None),
+            Durability::Hard
+        );
+        assert_eq!(
+            storage.write_durability("test_db:hard", Some(Durability::Soft)),
+            Durability::Soft
+        );
+
+        let doc = Document::new();
+        storage
+            .put("test_db", "hard", "key1", &doc, None)
+            .await
+            .expect("Failed to put with table durability");
+        storage
+            .put_batch(
+                "test_db",
+                "hard",
+                &[("key2".to_string(), doc.clone())],
+                Some(Durability::Hard),
+            )
+            .await
+            .expect("Failed to put batch with hard durability");
+        storage
+            .delete("test_db", "hard", "key1", Some(Durability::Hard))
+            .await
+            .expect("Failed to delete with hard durability");
+
+        assert!(
+            storage
+                .get("test_db", "hard", "key1", None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            storage
+                .get("test_db", "hard", "key2", None)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_reads() {
+        use tempfile::TempDir;
+        use tokio_stream::StreamExt;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+        storage.create_database("test_db").await.unwrap();
+        storage
+            .create_table("test_db", "test_table", &TableConfig::default())
+            .await
+            .unwrap();
+
+        let mut doc = Document::new();
+        doc.insert(
+            "version".to_string(),
+            Datum {
+                value: Some(datum::Value::Int(1)),
+            },
+        );
+        storage
+            .put("test_db", "test_table", "key1", &doc, None)
+            .await
+            .unwrap();
+
+        let snapshot = storage.create_snapshot().await.unwrap();
+
+        doc.insert(
+            "version".to_string(),
+            Datum {
+                value: Some(datum::Value::Int(2)),
+            },
+        );
+        storage
+            .put("test_db", "test_table", "key1", &doc, None)
+            .await
+            .unwrap();
+        storage
+            .put("test_db", "test_table", "key2", &doc, None)
+            .await
+            .unwrap();
+
+        let pinned = storage
+            .get("test_db", "test_table", "key1", Some(snapshot))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            pinned.get("version").unwrap().value,
+            Some(datum::Value::Int(1))
+        );
+        let latest = storage
+            .get("test_db", "test_table", "key1", None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            latest.get("version").unwrap().value,
+            Some(datum::Value::Int(2))
+        );
+
+        let scanned: Vec<_> = storage
+            .scan_table(
+                "test_db",
+                "test_table",
+                None,
+                None,
+                None,
+                None,
+                ScanProjection::default(),
+                Some(snapshot),
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(scanned.len(), 1);
+
+        let keys = vec!["key1".to_string(), "key2".to_string()];
+        let fetched: Vec<_> = storage
+            .stream_get_all(
+                "test_db",
+                "test_table",
+                &keys,
+                None,
+                None,
+                None,
+                Some(snapshot),
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(fetched.len(), 1);
+
+        storage.release_snapshot(snapshot).await.unwrap();
+        assert!(matches!(
+            storage
+                .get("test_db", "test_table", "key1", Some(snapshot))
+                .await,
+            Err(StorageError::SnapshotNotFound(id)) if id == snapshot
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_table_statistics_persisted_across_reopen() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+
+        {
+            let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+            storage.create_database("test_db").await.unwrap();
+            storage
+                .create_table("test_db", "users", &TableConfig::default())
+                .await
+                .unwrap();
+            storage
+                .create_table("test_db", "dropped", &TableConfig::default())
+                .await
+                .unwrap();
+
+            for i in 0..20 {
+                let mut doc = Document::new();
+                doc.insert(
+                    "status".to_string(),
+                    Datum {
+                        value: Some(datum::Value::String(format!("s{}", i / 10))),
+                    },
+                );
+                let key = format!("key{i:02}");
+                storage
+                    .put("test_db", "users", &key, &doc, None)
+                    .await
+                    .unwrap();
+                storage
+                    .put("test_db", "dropped", &key, &doc, None)
+                    .await
+                    .unwrap();
+            }
 
-            let databases = self.databases.lock().unwrap();
-            let dbs = databases.clone();
+            // Row counts are available before any analyze
+            let stats = storage.table_statistics("test_db", "users").unwrap();
+            assert_eq!(stats.row_count, 20);
+            assert!(stats.fields.is_empty());
+            assert_eq!(storage.table_count("test_db"), Some(3));
+            assert!(storage.table_statistics("test_db", "missing").is_none());
 
-            let skip_count = skip.unwrap_or(0);
-            let limit_count = limit.unwrap_or(dbs.len());
-            let dbs: Vec<String> = dbs.into_iter().skip(skip_count).take(limit_count).collect();
+            let analyzed = storage.analyze_table("test_db", "users", 5).await.unwrap();
+            assert_eq!(analyzed.row_count, 20);
+            assert_eq!(analyzed.sampled_rows, 5);
+            storage
+                .analyze_table("test_db", "dropped", 100)
+                .await
+                .unwrap();
+            storage.drop_table("test_db", "dropped").await.unwrap();
+        }
 
-            tokio::spawn(async move {
-                for db in dbs {
-                    if tx.send(Ok(db)).await.is_err() {
-                        break;
-                    }
-                }
-            });
+        let storage = DefaultStorage::open(&config).expect("Failed to reopen storage");
+        let stats = storage.table_statistics("test_db", "users").unwrap();
+        assert_eq!(stats.sampled_rows, 5);
+        assert_eq!(stats.fields["status"].distinct_values, 2.0);
+        assert!(
+            !storage
+                .table_statistics
+                .read()
+                .unwrap()
+                .contains_key(&format_table_name("test_db", "dropped"))
+        );
+    }
 
-            Ok(ReceiverStream::new(rx))
-        }
+    #[tokio::test]
+    async fn test_sharded_table() {
+        use tempfile::TempDir;
+        use tokio_stream::StreamExt;
 
-        async fn stream_tables(
-            &self,
-            db: &str,
-            _start_key: Option<String>,
+        async fn scan_ids(
+            storage: &DefaultStorage,
+            start_key: Option<&str>,
             limit: Option<usize>,
             skip: Option<usize>,
-        ) -> Result<ReceiverStream<Result<String>>> {
-            self.increment_operation_count();
-            let (tx, rx) = mpsc::channel(100);
+            snapshot: Option<SnapshotId>,
+        ) -> Vec<String> {
+            storage
+                .scan_table(
+                    "test_db",
+                    "users",
+                    start_key.map(str::to_string),
+                    limit,
+                    skip,
+                    None,
+                    ScanProjection::default(),
+                    snapshot,
+                )
+                .await
+                .unwrap()
+                .map(|doc| match doc.unwrap().get("id").unwrap().value.clone() {
+                    Some(datum::Value::String(id)) => id,
+                    other => panic!("unexpected id {other:?}"),
+                })
+                .collect()
+                .await
+        }
 
-            let data = self.data.lock().unwrap();
-            if let Some(db_data) = data.get(db) {
-                let tables: Vec<String> = db_data.keys().cloned().collect();
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let keys = ["Alice", "Hank", "Zed", "bob", "carol", "mallory", "trent"];
+
+        {
+            let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+            storage.create_database("test_db").await.unwrap();
+            assert!(matches!(
+                storage
+                    .create_sharded_table("test_db", "users", &TableConfig::default(), 0)
+                    .await,
+                Err(StorageError::InvalidTableOptions(_))
+            ));
+            storage
+                .create_sharded_table("test_db", "users", &TableConfig::default(), 4)
+                .await
+                .unwrap();
+
+            let docs: Vec<_> = keys
+                .iter()
+                .map(|key| {
+                    let mut doc = Document::new();
+                    doc.insert(
+                        "id".to_string(),
+                        Datum {
+                            value: Some(datum::Value::String(key.to_string())),
+                        },
+                    );
+                    (key.to_string(), doc)
+                })
+                .collect();
+            storage
+                .put_batch("test_db", "users", &docs, None)
+                .await
+                .unwrap();
+
+            // Keys are spread over the shards but read back as one table in key order
+            let map = storage.shard_maps.read().unwrap()["test_db:users"]
+                .read()
+                .clone();
+            assert_eq!(map.len(), 4);
+            assert_eq!(map.route("bob").id, 2);
+            assert!(
+                storage
+                    .get("test_db", "users", "mallory", None)
+                    .await
+                    .unwrap()
+                    .is_some()
+            );
+            assert_eq!(scan_ids(&storage, None, None, None, None).await, keys);
+            assert_eq!(
+                scan_ids(&storage, Some("Zed"), Some(2), Some(1), None).await,
+                ["carol", "mallory"]
+            );
+            let fetched: Vec<_> = storage
+                .stream_get_all(
+                    "test_db",
+                    "users",
+                    &["trent".to_string(), "Alice".to_string()],
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap()
+                .collect()
+                .await;
+            assert_eq!(fetched.len(), 2);
+            assert_eq!(
+                storage
+                    .table_statistics("test_db", "users")
+                    .unwrap()
+                    .row_count,
+                7
+            );
 
-                let skip_count = skip.unwrap_or(0);
-                let limit_count = limit.unwrap_or(tables.len());
-                let tables: Vec<String> = tables
-                    .into_iter()
-                    .skip(skip_count)
-                    .take(limit_count)
-                    .collect();
+            // Shards are not listed as tables
+            let tables: Vec<_> = storage
+                .stream_tables("test_db", None, None, None)
+                .await
+                .unwrap()
+                .map(|name| name.unwrap())
+                .collect()
+                .await;
+            assert_eq!(tables, ["default", "users"]);
+            assert_eq!(storage.table_count("test_db"), Some(2));
+
+            // Rebalancing splits the documents evenly, and a snapshot taken before
+            // still reads the old layout
+            let snapshot = storage.create_snapshot().await.unwrap();
+            let summary = storage
+                .rebalance_table("test_db", "users", Some(3))
+                .await
+                .unwrap();
+            assert_eq!(summary.shards.len(), 3);
+            assert_eq!(summary.shards[1].start.as_deref(), Some("Zed"));
+            assert_eq!(summary.shards[2].start.as_deref(), Some("carol"));
+            assert!(summary.moved > 0);
+            assert_eq!(scan_ids(&storage, None, None, None, None).await, keys);
+            assert_eq!(
+                scan_ids(&storage, None, None, None, Some(snapshot)).await,
+                keys
+            );
+            storage.release_snapshot(snapshot).await.unwrap();
 
-                tokio::spawn(async move {
-                    for table in tables {
-                        if tx.send(Ok(table)).await.is_err() {
-                            break;
-                        }
-                    }
-                });
+            // Rebalancing again without changes moves nothing
+            let summary = storage
+                .rebalance_table("test_db", "users", None)
+                .await
+                .unwrap();
+            assert_eq!(summary.shards.len(), 3);
+            assert_eq!(summary.moved, 0);
+
+            // The shard emptied by the first rebalance is dropped once no snapshot
+            // reads it
+            assert!(
+                !storage
+                    .table_configs
+                    .read()
+                    .unwrap()
+                    .contains_key("test_db:users#3")
+            );
+        }
 
-                Ok(ReceiverStream::new(rx))
-            } else {
-                Err(StorageError::InvalidDatabaseName(db.to_string()))
-            }
+        {
+            let storage = DefaultStorage::open(&config).expect("Failed to reopen storage");
+            assert_eq!(
+                storage.shard_maps.read().unwrap()["test_db:users"]
+                    .read()
+                    .len(),
+                3
+            );
+            assert_eq!(scan_ids(&storage, None, None, None, None).await, keys);
+
+            // Merging into a single shard removes the map
+            let summary = storage
+                .rebalance_table("test_db", "users", Some(1))
+                .await
+                .unwrap();
+            assert_eq!(summary.shards.len(), 1);
+            assert!(storage.shard_maps.read().unwrap().is_empty());
+            assert_eq!(scan_ids(&storage, None, None, None, None).await, keys);
+
+            storage.drop_table("test_db", "users").await.unwrap();
+            assert!(
+                !storage
+                    .table_configs
+                    .read()
+                    .unwrap()
+                    .keys()
+                    .any(|name| is_shard_of(name, "test_db:users"))
+            );
         }
+    }
 
-        async fn stream_get_all(
-            &self,
-            db: &str,
-            table: &str,
-            keys: &[String],
-            _start_key: Option<String>,
-            limit: Option<usize>,
-            skip: Option<usize>,
-        ) -> Result<ReceiverStream<Result<Document>>> {
-            self.increment_operation_count();
-            let (tx, rx) = mpsc::channel(100);
+    #[tokio::test]
+    async fn test_schema_version_follows_schema_changes() {
+        use index::fulltext::FullTextOptions;
+        use tempfile::TempDir;
 
-            let data = self.data.lock().unwrap();
-            if let Some(db_data) = data.get(db) {
-                if let Some(table_data) = db_data.get(table) {
-                    let mut docs = Vec::new();
-                    for key in keys {
-                        if let Some(doc) = table_data.get(key) {
-                            docs.push(doc.clone());
-                        }
-                    }
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+        let definition = IndexDefinition {
+            name: "body".to_string(),
+            kind: IndexKind::FullText(FullTextOptions {
+                fields: vec![vec!["body".to_string()]],
+                stemming: false,
+            }),
+        };
 
-                    let skip = skip.unwrap_or(0);
-                    let limit = limit.unwrap_or(docs.len());
-                    let docs: Vec<Document> = docs.into_iter().skip(skip).take(limit).collect();
+        let mut version = storage.schema_version();
+        storage.create_database("test_db").await.unwrap();
+        storage
+            .create_table("test_db", "posts", &TableConfig::default())
+            .await
+            .unwrap();
+        storage
+            .create_index("test_db", "posts", &definition)
+            .await
+            .unwrap();
+        assert_eq!(storage.schema_version(), version + 3);
 
-                    tokio::spawn(async move {
-                        for doc in docs {
-                            if tx.send(Ok(doc)).await.is_err() {
-                                break;
-                            }
-                        }
-                    });
+        // Documents don't change the schema
+        version = storage.schema_version();
+        storage
+            .put("test_db", "posts", "a", &Document::new(), None)
+            .await
+            .unwrap();
+        assert_eq!(storage.schema_version(), version);
 
-                    Ok(ReceiverStream::new(rx))
-                } else {
-                    Err(StorageError::InvalidTableName(table.to_string()))
-                }
-            } else {
-                Err(StorageError::InvalidDatabaseName(db.to_string()))
-            }
-        }
+        storage
+            .drop_index("test_db", "posts", "body")
+            .await
+            .unwrap();
+        storage.drop_table("test_db", "posts").await.unwrap();
+        storage.drop_database("test_db").await.unwrap();
+        assert_eq!(storage.schema_version(), version + 3);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::ast::*;
+    #[tokio::test]
+    async fn test_drop_database_drops_everything_of_its_tables() {
+        use index::fulltext::FullTextOptions;
+        use tempfile::TempDir;
 
-    #[test]
-    fn test_document_serialization_roundtrip() {
-        // Create a document exactly like the one in our failing test
-        let mut doc = Document::new();
-        doc.insert(
-            "id".to_string(),
-            Datum {
-                value: Some(datum::Value::String("test1".to_string())),
-            },
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+        let definition = IndexDefinition {
+            name: "body".to_string(),
+            kind: IndexKind::FullText(FullTextOptions {
+                fields: vec![vec!["body".to_string()]],
+                stemming: false,
+            }),
+        };
+
+        storage.create_database("test_db").await.unwrap();
+        let point_lookup = TableConfig {
+            access_pattern: AccessPattern::PointLookup,
+            ..Default::default()
+        };
+        storage
+            .create_table("test_db", "posts", &point_lookup)
+            .await
+            .unwrap();
+        storage
+            .create_index("test_db", "posts", &definition)
+            .await
+            .unwrap();
+        storage
+            .put("test_db", "posts", "a", &Document::new(), None)
+            .await
+            .unwrap();
+        storage
+            .create_sharded_table("test_db", "users", &TableConfig::default(), 4)
+            .await
+            .unwrap();
+        storage.analyze_table("test_db", "posts", 10).await.unwrap();
+        assert_eq!(storage.table_count("test_db"), Some(3));
+
+        // No drop_table first, the database takes its tables with it
+        storage.drop_database("test_db").await.unwrap();
+        storage.create_database("test_db").await.unwrap();
+
+        assert!(!storage.table_exists("test_db", "posts").await.unwrap());
+        assert!(!storage.table_exists("test_db", "users").await.unwrap());
+        assert!(storage.table_config("test_db", "posts").is_none());
+        assert!(storage.indexes.definitions("test_db:posts").is_empty());
+        assert!(storage.shard_maps.read().unwrap().is_empty());
+        assert!(storage.table_statistics.read().unwrap().is_empty());
+        assert_eq!(storage.table_count("test_db"), Some(1));
+        assert!(
+            !DB::list_cf(&storage.opts, &storage.path)
+                .unwrap()
+                .iter()
+                .any(|name| name.starts_with("test_db:posts") || is_shard_name(name))
         );
-        doc.insert(
-            "value".to_string(),
-            Datum {
-                value: Some(datum::Value::String("hello".to_string())),
-            },
+
+        // Nor is anything of them reloaded from disk
+        drop(storage);
+        let storage = DefaultStorage::open(&config).expect("Failed to reopen storage");
+        assert!(storage.shard_maps.read().unwrap().is_empty());
+        assert!(storage.table_statistics.read().unwrap().is_empty());
+        assert_eq!(storage.table_count("test_db"), Some(1));
+        assert!(storage.table_config("test_db", "posts").is_none());
+        storage
+            .create_table("test_db", "users", &TableConfig::default())
+            .await
+            .unwrap();
+        assert!(DefaultStorage::shard_map(&storage.shard_maps, "test_db:users").is_none());
+
+        storage
+            .create_table("test_db", "posts", &TableConfig::default())
+            .await
+            .unwrap();
+        assert!(storage.indexes.definitions("test_db:posts").is_empty());
+        assert!(
+            storage
+                .get("test_db", "posts", "a", None)
+                .await
+                .unwrap()
+                .is_none()
         );
 
-        // Test the exact same serialization used in storage
-        let serialized = bincode::serde::encode_to_vec(&doc, bincode::config::standard())
-            .expect("Failed to serialize document");
+        // A recreated table keeps the configuration it was created with
+        drop(storage);
+        let storage = DefaultStorage::open(&config).expect("Failed to reopen storage");
+        assert_eq!(
+            storage.table_config("test_db", "posts"),
+            Some(TableConfig::default())
+        );
+    }
 
-        // Test the exact same deserialization used in storage
-        let result = parse_doc(&serialized);
-        match result {
-            Ok(deserialized) => {
-                assert_eq!(doc, deserialized);
-            }
-            Err(e) => {
-                panic!("Failed to deserialize: {e}");
-            }
+    #[tokio::test]
+    async fn test_full_text_index() {
+        use index::fulltext::FullTextOptions;
+        use tempfile::TempDir;
+
+        fn doc(id: &str, body: &str) -> Document {
+            Document::from([
+                (
+                    "id".to_string(),
+                    Datum {
+                        value: Some(datum::Value::String(id.to_string())),
+                    },
+                ),
+                (
+                    "body".to_string(),
+                    Datum {
+                        value: Some(datum::Value::String(body.to_string())),
+                    },
+                ),
+            ])
         }
-    }
 
-    #[test]
-    fn test_individual_datum_serialization() {
-        let datum = Datum {
-            value: Some(datum::Value::String("test".to_string())),
-        };
+        async fn search_ids(storage: &DefaultStorage, query: &str) -> Vec<String> {
+            storage
+                .search("test_db", "posts", "body", query, None, None)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|(doc, _)| match doc.get("id").unwrap().value.clone() {
+                    Some(datum::Value::String(id)) => id,
+                    other => panic!("unexpected id {other:?}"),
+                })
+                .collect()
+        }
 
-        let serialized = bincode::serde::encode_to_vec(&datum, bincode::config::standard())
-            .expect("Failed to serialize datum");
-        let (deserialized, _): (Datum, _) =
-            bincode::serde::decode_from_slice(&serialized, bincode::config::standard())
-                .expect("Failed to deserialize datum");
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let definition = IndexDefinition {
+            name: "body".to_string(),
+            kind: IndexKind::FullText(FullTextOptions {
+                fields: vec![vec!["body".to_string()]],
+                stemming: true,
+            }),
+        };
 
-        assert_eq!(datum, deserialized);
-    }
+        {
+            let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+            storage.create_database("test_db").await.unwrap();
+            storage
+                .create_sharded_table("test_db", "posts", &TableConfig::default(), 2)
+                .await
+                .unwrap();
 
-    #[test]
-    fn test_document_to_datum_conversion() {
-        // Create a document exactly like what's stored in storage
-        let mut doc = Document::new();
-        doc.insert(
-            "id".to_string(),
-            Datum {
-                value: Some(datum::Value::String("test1".to_string())),
-            },
-        );
-        doc.insert(
-            "value".to_string(),
-            Datum {
-                value: Some(datum::Value::String("hello".to_string())),
-            },
-        );
+            // Documents written before the index exists are backfilled
+            storage
+                .put("test_db", "posts", "a", &doc("a", "Rust databases"), None)
+                .await
+                .unwrap();
+            storage
+                .create_index("test_db", "posts", &definition)
+                .await
+                .unwrap();
+            assert!(matches!(
+                storage.create_index("test_db", "posts", &definition).await,
+                Err(StorageError::Index(_))
+            ));
+
+            let docs = [
+                ("b".to_string(), doc("b", "Rust rust RUST, running fast")),
+                ("c".to_string(), doc("c", "Cooking with Python")),
+            ];
+            storage
+                .put_batch("test_db", "posts", &docs, None)
+                .await
+                .unwrap();
+
+            assert_eq!(search_ids(&storage, "rust").await, vec!["b", "a"]);
+            assert_eq!(search_ids(&storage, "runs").await, vec!["b"]);
+            assert_eq!(search_ids(&storage, "python rust").await.len(), 3);
+            assert!(search_ids(&storage, "java").await.is_empty());
+            let limited = storage
+                .search("test_db", "posts", "body", "rust", Some(1), None)
+                .await
+                .unwrap();
+            assert_eq!(limited.len(), 1);
+            assert!(limited[0].1 > 0.0);
 
-        // Convert to Datum using the same conversion as in evaluator
-        let datum: Datum = doc.into();
+            // Rewrites and deletes replace a document's postings
+            storage
+                .put("test_db", "posts", "b", &doc("b", "Python only"), None)
+                .await
+                .unwrap();
+            storage.delete("test_db", "posts", "a", None).await.unwrap();
+            assert!(search_ids(&storage, "rust").await.is_empty());
+            assert_eq!(search_ids(&storage, "python").await.len(), 2);
 
-        // Test serialization of the converted Datum (this might be where it fails)
-        match bincode::serde::encode_to_vec(&datum, bincode::config::standard()) {
-            Ok(serialized) => {
-                // Test deserialization
-                let decode_result: std::result::Result<(Datum, usize), _> =
-                    bincode::serde::decode_from_slice(&serialized, bincode::config::standard());
-                match decode_result {
-                    Ok((deserialized, _)) => {
-                        assert_eq!(datum, deserialized);
-                    }
-                    Err(e) => {
-                        panic!("❌ Datum deserialization failed: {e}");
-                    }
-                }
-            }
-            Err(e) => {
-                panic!("❌ Datum serialization failed: {e}");
-            }
+            // Index column families are hidden from the table listing
+            assert_eq!(storage.table_count("test_db"), Some(2));
         }
+
+        let storage = DefaultStorage::open(&config).expect("Failed to reopen storage");
+        assert_eq!(
+            storage.list_indexes("test_db", "posts").await.unwrap(),
+            vec![definition]
+        );
+        assert_eq!(search_ids(&storage, "python").await.len(), 2);
+
+        storage
+            .drop_index("test_db", "posts", "body")
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage.drop_index("test_db", "posts", "body").await,
+            Err(StorageError::Index(_))
+        ));
+        assert!(matches!(
+            storage
+                .search("test_db", "posts", "body", "python", None, None)
+                .await,
+            Err(StorageError::Index(_))
+        ));
+        assert!(
+            storage
+                .inner
+                .cf_handle(&index_cf_name("test_db:posts", "body"))
+                .is_none()
+        );
     }
 
     #[tokio::test]
-    async fn test_storage_layer_roundtrip() {
+    async fn test_geo_index() {
+        use crate::geo::Point;
+        use index::fulltext::FullTextOptions;
+        use index::geo::GeoOptions;
         use tempfile::TempDir;
 
-        // Create temporary storage
+        fn doc(id: &str, location: Shape) -> Document {
+            Document::from([
+                (
+                    "id".to_string(),
+                    Datum {
+                        value: Some(datum::Value::String(id.to_string())),
+                    },
+                ),
+                (
+                    "location".to_string(),
+                    Datum {
+                        value: Some(datum::Value::Geometry((&location).into())),
+                    },
+                ),
+            ])
+        }
+
+        fn point(lon: f64, lat: f64) -> Point {
+            Point::new(lon, lat).unwrap()
+        }
+
+        fn ids(docs: impl IntoIterator<Item = Document>) -> Vec<String> {
+            docs.into_iter()
+                .map(|doc| match doc.get("id").unwrap().value.clone() {
+                    Some(datum::Value::String(id)) => id,
+                    other => panic!("unexpected id {other:?}"),
+                })
+                .collect()
+        }
+
+        async fn nearest_ids(storage: &DefaultStorage, from: Point, max_dist: f64) -> Vec<String> {
+            let nearest = storage
+                .get_nearest("test_db", "places", "location", from, max_dist, 10, None)
+                .await
+                .unwrap();
+            assert!(nearest.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+            ids(nearest.into_iter().map(|(doc, _)| doc))
+        }
+
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
         let config = Config {
             data_dir: temp_dir.path().to_string_lossy().to_string(),
             ..Default::default()
         };
-        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+        let definition = IndexDefinition {
+            name: "location".to_string(),
+            kind: IndexKind::Geo(GeoOptions {
+                field: vec!["location".to_string()],
+            }),
+        };
+        let (london, paris, berlin) = (
+            point(-0.1276, 51.5072),
+            point(2.3522, 48.8566),
+            point(13.405, 52.52),
+        );
+        let channel = Shape::Polygon(vec![
+            point(-1.0, 49.5),
+            point(2.0, 49.5),
+            point(2.0, 51.0),
+            point(-1.0, 51.0),
+        ]);
+
+        {
+            let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+            storage.create_database("test_db").await.unwrap();
+            storage
+                .create_table("test_db", "places", &TableConfig::default())
+                .await
+                .unwrap();
 
-        // Test data
-        let db_name = "test_db";
-        let table_name = "test_table";
-        let key = "test_key";
+            // Documents written before the index exists are backfilled
+            storage
+                .put(
+                    "test_db",
+                    "places",
+                    "london",
+                    &doc("london", Shape::Point(london)),
+                    None,
+                )
+                .await
+                .unwrap();
+            storage
+                .create_index("test_db", "places", &definition)
+                .await
+                .unwrap();
 
-        // Create a document exactly like in our failing case
-        let mut original_doc = Document::new();
-        original_doc.insert(
-            "id".to_string(),
-            Datum {
-                value: Some(datum::Value::String("test1".to_string())),
-            },
-        );
-        original_doc.insert(
-            "value".to_string(),
-            Datum {
-                value: Some(datum::Value::String("hello".to_string())),
-            },
-        );
+            let docs = [
+                ("paris".to_string(), doc("paris", Shape::Point(paris))),
+                ("berlin".to_string(), doc("berlin", Shape::Point(berlin))),
+                ("channel".to_string(), doc("channel", channel.clone())),
+            ];
+            storage
+                .put_batch("test_db", "places", &docs, None)
+                .await
+                .unwrap();
 
-        // Step 1: Create database
-        storage
-            .create_database(db_name)
-            .await
-            .expect("Failed to create database");
+            // A line from London to Paris crosses the channel area
+            let line = Shape::Line(vec![london, paris]);
+            let intersecting = storage
+                .get_intersecting("test_db", "places", "location", &line, None)
+                .await
+                .unwrap();
+            assert_eq!(ids(intersecting), vec!["channel", "london", "paris"]);
 
-        // Step 2: Create table
-        storage
-            .create_table(db_name, table_name)
+            assert_eq!(
+                nearest_ids(&storage, london, 500_000.0).await,
+                vec!["london", "channel", "paris"]
+            );
+            assert_eq!(
+                nearest_ids(&storage, london, 1_000_000.0).await,
+                vec!["london", "channel", "paris", "berlin"]
+            );
+
+            // Rewrites and deletes replace a document's cells
+            storage
+                .put(
+                    "test_db",
+                    "places",
+                    "paris",
+                    &doc("paris", Shape::Point(berlin)),
+                    None,
+                )
+                .await
+                .unwrap();
+            storage
+                .delete("test_db", "places", "london", None)
+                .await
+                .unwrap();
+            assert_eq!(
+                nearest_ids(&storage, london, 500_000.0).await,
+                vec!["channel"]
+            );
+        }
+
+        let storage = DefaultStorage::open(&config).expect("Failed to reopen storage");
+        assert_eq!(
+            nearest_ids(&storage, berlin, 10_000.0).await,
+            vec!["berlin", "paris"]
+        );
+        let intersecting = storage
+            .get_intersecting(
+                "test_db",
+                "places",
+                "location",
+                &Shape::Point(point(0.0, 50.0)),
+                None,
+            )
             .await
-            .expect("Failed to create table");
+            .unwrap();
+        assert_eq!(ids(intersecting), vec!["channel"]);
 
-        // Step 3: Put document
+        // Full-text indexes can't answer geospatial queries
         storage
-            .put(db_name, table_name, key, &original_doc)
+            .create_index(
+                "test_db",
+                "places",
+                &IndexDefinition {
+                    name: "id".to_string(),
+                    kind: IndexKind::FullText(FullTextOptions {
+                        fields: vec![vec!["id".to_string()]],
+                        stemming: false,
+                    }),
+                },
+            )
             .await
-            .expect("Failed to put document");
-
-        // Step 4: Get document (this is where the error should occur if it's a storage issue)
-        match storage.get(db_name, table_name, key).await {
-            Ok(Some(retrieved_doc)) => {
-                // Verify they match
-                if original_doc != retrieved_doc {
-                    panic!(
-                        "❌ Documents don't match!\nOriginal: {original_doc:?}\nRetrieved: {retrieved_doc:?}"
-                    );
-                }
-            }
-            Ok(None) => {
-                panic!("❌ Document not found");
-            }
-            Err(e) => {
-                panic!("❌ Failed to retrieve document: {e}");
-            }
-        }
+            .unwrap();
+        assert!(matches!(
+            storage
+                .get_nearest("test_db", "places", "id", berlin, 10_000.0, 10, None)
+                .await,
+            Err(StorageError::Index(_))
+        ));
+        assert!(matches!(
+            storage
+                .search("test_db", "places", "location", "berlin", None, None)
+                .await,
+            Err(StorageError::Index(_))
+        ));
     }
 
     #[tokio::test]
-    async fn test_server_async_execution_pattern() {
-        use std::sync::Arc;
+    async fn test_vector_index() {
+        use index::geo::GeoOptions;
+        use index::vector::{Metric, VectorOptions};
         use tempfile::TempDir;
-        use tokio::task;
 
-        // Create temporary storage exactly like the server does
+        fn doc(id: &str, tag: &str, embedding: &[f64]) -> Document {
+            Document::from([
+                (
+                    "id".to_string(),
+                    Datum {
+                        value: Some(datum::Value::String(id.to_string())),
+                    },
+                ),
+                (
+                    "tag".to_string(),
+                    Datum {
+                        value: Some(datum::Value::String(tag.to_string())),
+                    },
+                ),
+                (
+                    "embedding".to_string(),
+                    Datum {
+                        value: Some(datum::Value::Array(crate::ast::DatumArray {
+                            items: embedding
+                                .iter()
+                                .map(|value| Datum {
+                                    value: Some(datum::Value::Float(*value)),
+                                })
+                                .collect(),
+                            element_type: String::new(),
+                        })),
+                    },
+                ),
+            ])
+        }
+
+        fn ids(results: &[(Document, f64)]) -> Vec<String> {
+            results
+                .iter()
+                .map(|(doc, _)| match doc.get("id").unwrap().value.clone() {
+                    Some(datum::Value::String(id)) => id,
+                    other => panic!("unexpected id {other:?}"),
+                })
+                .collect()
+        }
+
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
         let config = Config {
             data_dir: temp_dir.path().to_string_lossy().to_string(),
             ..Default::default()
         };
-        let storage = Arc::new(DefaultStorage::open(&config).expect("Failed to create storage"));
-
-        // Test data
-        let db_name = "test_db";
-        let table_name = "test_table";
-        let key = "test_key";
-
-        // Create document
-        let mut original_doc = Document::new();
-        original_doc.insert(
-            "id".to_string(),
-            Datum {
-                value: Some(datum::Value::String("test1".to_string())),
-            },
-        );
-        original_doc.insert(
-            "value".to_string(),
-            Datum {
-                value: Some(datum::Value::String("hello".to_string())),
-            },
-        );
+        let definition = IndexDefinition {
+            name: "embedding".to_string(),
+            kind: IndexKind::Vector(VectorOptions {
+                field: vec!["embedding".to_string()],
+                dimension: 2,
+                metric: Metric::L2,
+                lists: 2,
+            }),
+        };
+        let tagged = |tag: &'static str| -> Option<Predicate> {
+            Some(Box::new(move |doc: Document| {
+                Ok(doc
+                    .get("tag")
+                    .and_then(|datum| datum.value.clone())
+                    .is_some_and(|value| value == datum::Value::String(tag.to_string())))
+            }))
+        };
 
-        // Step 1: Setup storage (like server initialization)
-        let storage_clone = storage.clone();
-        task::spawn(async move {
-            storage_clone
-                .create_database(db_name)
+        {
+            let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+            storage.create_database("test_db").await.unwrap();
+            storage
+                .create_sharded_table("test_db", "items", &TableConfig::default(), 2)
                 .await
-                .expect("Failed to create database");
-        })
-        .await
-        .expect("Database creation task failed");
+                .unwrap();
 
-        let storage_clone = storage.clone();
-        task::spawn(async move {
-            storage_clone
-                .create_table(db_name, table_name)
+            // Documents written before the index exists are backfilled and seed its lists
+            storage
+                .put("test_db", "items", "a", &doc("a", "x", &[0.0, 0.0]), None)
                 .await
-                .expect("Failed to create table");
-        })
-        .await
-        .expect("Table creation task failed");
-
-        // Step 2: Insert operation (like server insert handling)
-        let storage_clone = storage.clone();
-        let doc_clone = original_doc.clone();
-        let _ = task::spawn(async move {
-            storage_clone
-                .put(db_name, table_name, key, &doc_clone)
+                .unwrap();
+            storage
+                .create_index("test_db", "items", &definition)
                 .await
-        })
-        .await
-        .expect("Insert task panicked");
-
-        // Step 3: Get operation (like server get handling) - this should reproduce the error
-        let storage_clone = storage.clone();
-        let get_result =
-            task::spawn(async move { storage_clone.get(db_name, table_name, key).await })
+                .unwrap();
+
+            let docs = [
+                ("b".to_string(), doc("b", "y", &[10.0, 10.0])),
+                ("c".to_string(), doc("c", "y", &[1.0, 0.0])),
+                ("d".to_string(), doc("d", "x", &[9.0, 9.0])),
+                ("e".to_string(), doc("e", "y", &[1.0])),
+            ];
+            storage
+                .put_batch("test_db", "items", &docs, None)
                 .await
-                .expect("Get task panicked");
+                .unwrap();
+
+            // One probe only searches the list around the nearest centroid
+            let results = storage
+                .nearest(
+                    "test_db",
+                    "items",
+                    "embedding",
+                    &[0.5, 0.0],
+                    3,
+                    1,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["a", "c"]);
+            assert_eq!(results[0].1, 0.5);
+            let results = storage
+                .nearest(
+                    "test_db",
+                    "items",
+                    "embedding",
+                    &[0.5, 0.0],
+                    3,
+                    2,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["a", "c", "d"]);
+
+            // A filter searches every list until enough documents pass it
+            let results = storage
+                .nearest(
+                    "test_db",
+                    "items",
+                    "embedding",
+                    &[0.5, 0.0],
+                    2,
+                    1,
+                    tagged("y"),
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["c", "b"]);
 
-        match get_result {
-            Ok(Some(_)) => {}
-            Ok(None) => {
-                panic!("❌ Async get returned None");
-            }
-            Err(e) => {
-                println!("❌ Async get failed: {e}");
-                println!("Error type: {e:?}");
-                panic!("This should reproduce the server error!");
-            }
+            // Rewrites and deletes replace a document's entry
+            storage
+                .put("test_db", "items", "c", &doc("c", "y", &[10.0, 9.0]), None)
+                .await
+                .unwrap();
+            storage.delete("test_db", "items", "a", None).await.unwrap();
+            let results = storage
+                .nearest(
+                    "test_db",
+                    "items",
+                    "embedding",
+                    &[0.0, 0.0],
+                    10,
+                    2,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["d", "c", "b"]);
         }
 
-        // Step 5: Test concurrent operations (like real server load)
-        let mut tasks = vec![];
+        let storage = DefaultStorage::open(&config).expect("Failed to reopen storage");
+        let results = storage
+            .nearest(
+                "test_db",
+                "items",
+                "embedding",
+                &[10.0, 10.0],
+                2,
+                1,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(ids(&results), vec!["b", "c"]);
+        assert!(matches!(
+            storage
+                .nearest("test_db", "items", "embedding", &[1.0], 2, 1, None, None)
+                .await,
+            Err(StorageError::Index(_))
+        ));
 
-        for i in 0..5 {
-            let storage_clone = storage.clone();
-            let test_key = format!("concurrent_key_{i}");
-            let test_doc = original_doc.clone();
+        // Geospatial indexes can't answer vector queries
+        storage
+            .create_index(
+                "test_db",
+                "items",
+                &IndexDefinition {
+                    name: "location".to_string(),
+                    kind: IndexKind::Geo(GeoOptions {
+                        field: vec!["location".to_string()],
+                    }),
+                },
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage
+                .nearest(
+                    "test_db",
+                    "items",
+                    "location",
+                    &[1.0, 1.0],
+                    2,
+                    1,
+                    None,
+                    None
+                )
+                .await,
+            Err(StorageError::Index(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_vector_index_trains_centroids() {
+        use index::vector::{Metric, VectorOptions};
+        use tempfile::TempDir;
+
+        // Clusters around four corners, written one cluster after the other so that the
+        // first vectors written all come from the first cluster
+        let corners = [[0.0, 0.0], [100.0, 0.0], [0.0, 100.0], [100.0, 100.0]];
+        let clusters: Vec<Vec<(String, Document)>> = corners
+            .iter()
+            .enumerate()
+            .map(|(cluster, [x, y])| {
+                (0..64)
+                    .map(|i| {
+                        let embedding = [x + f64::from(i % 8), y + f64::from(i / 8)];
+                        let doc = Document::from([(
+                            "embedding".to_string(),
+                            Datum {
+                                value: Some(datum::Value::Array(crate::ast::DatumArray {
+                                    items: embedding
+                                        .iter()
+                                        .map(|value| Datum {
+                                            value: Some(datum::Value::Float(*value)),
+                                        })
+                                        .collect(),
+                                    element_type: String::new(),
+                                })),
+                            },
+                        )]);
+                        (format!("{cluster}-{i:02}"), doc)
+                    })
+                    .collect()
+            })
+            .collect();
+        let definition = IndexDefinition {
+            name: "embedding".to_string(),
+            kind: IndexKind::Vector(VectorOptions {
+                field: vec!["embedding".to_string()],
+                dimension: 2,
+                metric: Metric::L2,
+                lists: 4,
+            }),
+        };
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+        storage.create_database("test_db").await.unwrap();
+        for table in ["built", "written"] {
+            storage
+                .create_table("test_db", table, &TableConfig::default())
+                .await
+                .unwrap();
+        }
+
+        // An index built over existing documents trains on all of them, and an index the
+        // documents are written to trains again each time it has doubled
+        for docs in &clusters {
+            storage
+                .put_batch("test_db", "built", docs, None)
+                .await
+                .unwrap();
+        }
+        storage
+            .create_index("test_db", "built", &definition)
+            .await
+            .unwrap();
+        storage
+            .create_index("test_db", "written", &definition)
+            .await
+            .unwrap();
+        for docs in &clusters {
+            storage
+                .put_batch("test_db", "written", docs, None)
+                .await
+                .unwrap();
+        }
 
-            // Spawn concurrent insert
-            let insert_task = task::spawn(async move {
-                storage_clone
-                    .put(db_name, table_name, &test_key, &test_doc)
+        // Each cluster has a list of its own, so one probe finds all of a cluster
+        for table in ["built", "written"] {
+            for (cluster, [x, y]) in corners.iter().enumerate() {
+                let results = storage
+                    .nearest("test_db", table, "embedding", &[*x, *y], 100, 1, None, None)
                     .await
-            });
-            tasks.push(insert_task);
+                    .unwrap();
+                assert_eq!(results.len(), 64, "cluster {cluster} of {table}");
+            }
         }
+    }
 
-        // Wait for all inserts
-        for (i, task) in tasks.into_iter().enumerate() {
-            match task.await.expect("Concurrent insert task panicked") {
-                Ok(()) => {}
-                Err(e) => panic!("❌ Concurrent insert {i} failed: {e}"),
+    #[tokio::test]
+    async fn test_secondary_index() {
+        use crate::ast::{BinaryOp, DatumArray, Expression, binary_op, expression};
+        use index::secondary::{IndexKey, SecondaryOptions};
+        use index::vector::{Metric, VectorOptions};
+        use tempfile::TempDir;
+
+        fn string(value: &str) -> Datum {
+            Datum {
+                value: Some(datum::Value::String(value.to_string())),
             }
         }
 
-        // Now test concurrent gets
-        let mut get_tasks = vec![];
-        for i in 0..5 {
-            let storage_clone = storage.clone();
-            let test_key = format!("concurrent_key_{i}");
-
-            let get_task =
-                task::spawn(async move { storage_clone.get(db_name, table_name, &test_key).await });
-            get_tasks.push((i, get_task));
+        fn doc(id: &str, last: &str, first: &str, age: i64, tags: &[&str]) -> Document {
+            Document::from([
+                ("id".to_string(), string(id)),
+                ("last".to_string(), string(last)),
+                ("first".to_string(), string(first)),
+                (
+                    "age".to_string(),
+                    Datum {
+                        value: Some(datum::Value::Int(age)),
+                    },
+                ),
+                (
+                    "tags".to_string(),
+                    Datum {
+                        value: Some(datum::Value::Array(DatumArray {
+                            items: tags.iter().map(|tag| string(tag)).collect(),
+                            element_type: String::new(),
+                        })),
+                    },
+                ),
+            ])
         }
 
-        // Check concurrent get results
-        for (i, task) in get_tasks.into_iter() {
-            match task.await.expect("Concurrent get task panicked") {
-                Ok(Some(_)) => {}
-                Ok(None) => panic!("❌ Concurrent get {i} returned None"),
-                Err(e) => {
-                    println!("❌ Concurrent get {i} failed: {e}");
-                    panic!("Concurrent operation error: {e}");
-                }
+        fn pair(last: &str, first: &str) -> Datum {
+            Datum {
+                value: Some(datum::Value::Array(DatumArray {
+                    items: vec![string(last), string(first)],
+                    element_type: String::new(),
+                })),
             }
         }
-    }
-
-    #[test]
-    fn test_system_table_variants() {
-        let variants = SystemTable::variants();
-        assert_eq!(variants.len(), 5);
-        assert!(variants.contains(&SystemTable::Databases));
-        assert!(variants.contains(&SystemTable::Schemas));
-        assert!(variants.contains(&SystemTable::Indexes));
-        assert!(variants.contains(&SystemTable::Feeds));
-        assert!(variants.contains(&SystemTable::Meta));
-    }
 
-    #[test]
-    fn test_system_table_display() {
-        assert_eq!(SystemTable::Databases.to_string(), "__databases__");
-        assert_eq!(SystemTable::Schemas.to_string(), "__schemas__");
-        assert_eq!(SystemTable::Indexes.to_string(), "__indexes__");
-        assert_eq!(SystemTable::Feeds.to_string(), "__feeds__");
-        assert_eq!(SystemTable::Meta.to_string(), "__meta__");
-    }
+        fn ids(docs: &[Document]) -> Vec<String> {
+            docs.iter()
+                .map(|doc| match doc.get("id").unwrap().value.clone() {
+                    Some(datum::Value::String(id)) => id,
+                    other => panic!("unexpected id {other:?}"),
+                })
+                .collect()
+        }
 
-    #[test]
-    fn test_storage_error_display() {
-        let storage_error = StorageError::MissingColumnFamily("test_cf".to_string());
-        assert_eq!(storage_error.to_string(), "Missing column family: test_cf");
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let definitions = [
+            IndexDefinition {
+                name: "name".to_string(),
+                kind: IndexKind::Secondary(SecondaryOptions {
+                    key: IndexKey::Fields(vec![
+                        vec!["last".to_string()],
+                        vec!["first".to_string()],
+                    ]),
+                    multi: false,
+                    unique: false,
+                }),
+            },
+            IndexDefinition {
+                name: "tags".to_string(),
+                kind: IndexKind::Secondary(SecondaryOptions {
+                    key: IndexKey::Fields(vec![vec!["tags".to_string()]]),
+                    multi: true,
+                    unique: false,
+                }),
+            },
+            IndexDefinition {
+                name: "adult".to_string(),
+                kind: IndexKind::Secondary(SecondaryOptions {
+                    key: IndexKey::Expression(Expression {
+                        expr: Some(expression::Expr::Binary(Box::new(BinaryOp {
+                            op: binary_op::Operator::Ge.into(),
+                            left: Some(Box::new(Expression {
+                                expr: Some(expression::Expr::Field(crate::ast::FieldRef {
+                                    path: vec!["age".to_string()],
+                                    separator: ".".to_string(),
+                                })),
+                            })),
+                            right: Some(Box::new(Expression {
+                                expr: Some(expression::Expr::Literal(Datum {
+                                    value: Some(datum::Value::Int(18)),
+                                })),
+                            })),
+                        }))),
+                    }),
+                    multi: false,
+                    unique: false,
+                }),
+            },
+        ];
+        let adult = Datum {
+            value: Some(datum::Value::Bool(true)),
+        };
 
-        let storage_error = StorageError::InvalidDatabaseName("test_db".to_string());
-        assert_eq!(storage_error.to_string(), "Invalid database name: test_db");
+        {
+            let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+            storage.create_database("test_db").await.unwrap();
+            storage
+                .create_sharded_table("test_db", "people", &TableConfig::default(), 2)
+                .await
+                .unwrap();
 
-        let storage_error = StorageError::InvalidTableName("test_table".to_string());
-        assert_eq!(storage_error.to_string(), "Invalid table name: test_table");
+            // Documents written before the indexes exist are backfilled
+            storage
+                .put(
+                    "test_db",
+                    "people",
+                    "a",
+                    &doc("a", "smith", "anna", 34, &["admin", "dev"]),
+                    None,
+                )
+                .await
+                .unwrap();
+            for definition in &definitions {
+                storage
+                    .create_index("test_db", "people", definition)
+                    .await
+                    .unwrap();
+            }
 
-        let storage_error = StorageError::ResourceExhausted;
-        assert_eq!(
-            storage_error.to_string(),
-            "Resource exhausted - too many concurrent operations"
-        );
-    }
+            let docs = [
+                ("b".to_string(), doc("b", "smith", "bob", 12, &["dev"])),
+                (
+                    "c".to_string(),
+                    doc("c", "jones", "carl", 40, &["ops", "ops"]),
+                ),
+                ("d".to_string(), doc("d", "smythe", "dana", 25, &[])),
+            ];
+            storage
+                .put_batch("test_db", "people", &docs, None)
+                .await
+                .unwrap();
 
-    #[test]
-    fn test_storage_error_source() {
-        use std::error::Error;
+            // Compound keys compare element by element
+            let results = storage
+                .get_all_indexed("test_db", "people", "name", &[pair("smith", "bob")], None)
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["b"]);
+            let results = storage
+                .between(
+                    "test_db",
+                    "people",
+                    "name",
+                    Some(&pair("smith", "")),
+                    Some(&pair("smith", "\u{10ffff}")),
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["a", "b"]);
+            let results = storage
+                .between(
+                    "test_db",
+                    "people",
+                    "name",
+                    None,
+                    Some(&pair("smith", "")),
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["c"]);
+            let results = storage
+                .between("test_db", "people", "name", None, None, None)
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["c", "a", "b", "d"]);
+
+            // Multi indexes file a document under each element, once per value
+            let results = storage
+                .get_all_indexed(
+                    "test_db",
+                    "people",
+                    "tags",
+                    &[string("dev"), string("admin")],
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["a", "b"]);
+            let results = storage
+                .get_all_indexed("test_db", "people", "tags", &[string("ops")], None)
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["c"]);
+
+            let results = storage
+                .get_all_indexed(
+                    "test_db",
+                    "people",
+                    "adult",
+                    std::slice::from_ref(&adult),
+                    None,
+                )
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["a", "c", "d"]);
 
-        let storage_error = StorageError::MissingColumnFamily("test".to_string());
-        assert!(storage_error.source().is_none());
+            // Rewrites and deletes replace a document's entries
+            storage
+                .put(
+                    "test_db",
+                    "people",
+                    "b",
+                    &doc("b", "smith", "bob", 18, &["ops"]),
+                    None,
+                )
+                .await
+                .unwrap();
+            storage
+                .delete("test_db", "people", "c", None)
+                .await
+                .unwrap();
+            let results = storage
+                .get_all_indexed("test_db", "people", "tags", &[string("ops")], None)
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["b"]);
+            let results = storage
+                .get_all_indexed("test_db", "people", "tags", &[string("dev")], None)
+                .await
+                .unwrap();
+            assert_eq!(ids(&results), vec!["a"]);
+        }
 
-        let storage_error = StorageError::ResourceExhausted;
-        assert!(storage_error.source().is_none());
-    }
+        let storage = DefaultStorage::open(&config).expect("Failed to reopen storage");
+        let results = storage
+            .get_all_indexed("test_db", "people", "adult", &[adult], None)
+            .await
+            .unwrap();
+        assert_eq!(ids(&results), vec!["a", "b", "d"]);
 
-    #[test]
-    fn test_storage_error_from_utf8() {
-        // Test FromUtf8Error conversion
-        let invalid_bytes = vec![0, 159, 146, 150];
-        let utf8_error = String::from_utf8(invalid_bytes).unwrap_err();
-        let storage_error: StorageError = utf8_error.into();
-        matches!(storage_error, StorageError::InvalidUtf8(_));
+        // Values that can't be ordered can't be looked up
+        assert!(matches!(
+            storage
+                .get_all_indexed(
+                    "test_db",
+                    "people",
+                    "tags",
+                    &[Datum {
+                        value: Some(datum::Value::Float(f64::NAN)),
+                    }],
+                    None,
+                )
+                .await,
+            Err(StorageError::Index(_))
+        ));
+
+        // Other kinds of index can't answer secondary lookups
+        storage
+            .create_index(
+                "test_db",
+                "people",
+                &IndexDefinition {
+                    name: "embedding".to_string(),
+                    kind: IndexKind::Vector(VectorOptions {
+                        field: vec!["embedding".to_string()],
+                        dimension: 2,
+                        metric: Metric::Cosine,
+                        lists: 2,
+                    }),
+                },
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage
+                .between("test_db", "people", "embedding", None, None, None)
+                .await,
+            Err(StorageError::Index(_))
+        ));
     }
 
-    #[test]
-    fn test_storage_error_from_bincode() {
-        // Test encode error case
-        let test_data: Vec<u8> = vec![1, 2, 3, 4];
-        let mut small_buffer = [0u8; 1]; // Too small buffer to cause encode error
+    #[tokio::test]
+    async fn test_unique_index() {
+        use index::secondary::{IndexKey, SecondaryOptions};
+        use tempfile::TempDir;
 
-        if let Err(encode_error) = bincode::serde::encode_into_slice(
-            &test_data,
-            &mut small_buffer,
-            bincode::config::standard(),
-        ) {
-            let storage_error: StorageError = encode_error.into();
-            matches!(storage_error, StorageError::EncodeError(_));
+        fn user(email: &str) -> Document {
+            Document::from([(
+                "email".to_string(),
+                Datum {
+                    value: Some(datum::Value::String(email.to_string())),
+                },
+            )])
         }
 
-        // Test decode error case - invalid data
-        let invalid_data = [255u8; 10];
-        let decode_result: std::result::Result<(Vec<String>, usize), _> =
-            bincode::serde::decode_from_slice(&invalid_data, bincode::config::standard());
-        if let Err(decode_error) = decode_result {
-            let storage_error: StorageError = decode_error.into();
-            matches!(storage_error, StorageError::DecodeError(_));
+        fn violation_key(result: Result<()>) -> String {
+            match result {
+                Err(StorageError::ConstraintViolation { index, field, key }) => {
+                    assert_eq!(index, "email");
+                    assert_eq!(field, "email");
+                    key
+                }
+                other => panic!("expected a constraint violation, got {other:?}"),
+            }
         }
-    }
-
-    #[test]
-    fn test_is_valid_key() {
-        assert!(is_valid_key("valid_key"));
-        assert!(is_valid_key("valid-key"));
-        assert!(is_valid_key("validkey123"));
-        assert!(is_valid_key("a"));
-        assert!(is_valid_key("test_table_123"));
-
-        assert!(!is_valid_key(""));
-        assert!(!is_valid_key("invalid key")); // space
-        assert!(!is_valid_key("invalid@key")); // special char
-        assert!(!is_valid_key("invalid.key")); // dot
-        assert!(!is_valid_key("invalid/key")); // slash
-        assert!(!is_valid_key(&"a".repeat(256))); // too long
-    }
 
-    #[test]
-    fn test_format_table_name() {
-        assert_eq!(format_table_name("db", "table"), "db:table");
-        assert_eq!(
-            format_table_name("test_db", "test_table"),
-            "test_db:test_table"
-        );
-        assert_eq!(format_table_name("", "table"), ":table");
-        assert_eq!(format_table_name("db", ""), "db:");
-    }
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let definition = IndexDefinition {
+            name: "email".to_string(),
+            kind: IndexKind::Secondary(SecondaryOptions {
+                key: IndexKey::Fields(vec![vec!["email".to_string()]]),
+                multi: false,
+                unique: true,
+            }),
+        };
+        let storage = Arc::new(DefaultStorage::open(&config).expect("Failed to create storage"));
+        storage.create_database("test_db").await.unwrap();
+        storage
+            .create_sharded_table("test_db", "users", &TableConfig::default(), 2)
+            .await
+            .unwrap();
 
-    #[test]
-    fn test_extract_table_from_database() {
-        assert_eq!(extract_table_from_database("db:table"), Some("table"));
-        assert_eq!(
-            extract_table_from_database("test_db:test_table"),
-            Some("test_table")
-        );
-        assert_eq!(
-            extract_table_from_database("db:table:with:colons"),
-            Some("table:with:colons")
+        // An index can't be built over documents that already share a value
+        let docs = [
+            ("a".to_string(), user("ann@example.com")),
+            ("b".to_string(), user("ann@example.com")),
+        ];
+        storage
+            .put_batch("test_db", "users", &docs, None)
+            .await
+            .unwrap();
+        let result = storage.create_index("test_db", "users", &definition).await;
+        assert!(["a", "b"].contains(&violation_key(result).as_str()));
+        assert!(
+            storage
+                .list_indexes("test_db", "users")
+                .await
+                .unwrap()
+                .is_empty()
         );
-        assert_eq!(extract_table_from_database("no_colon"), None);
-        assert_eq!(extract_table_from_database(""), None);
-        assert_eq!(extract_table_from_database(":"), Some(""));
-        assert_eq!(extract_table_from_database("db:"), Some(""));
-    }
 
-    #[test]
-    fn test_config_default() {
-        let config = Config::default();
-        assert_eq!(config.data_dir, "./data");
-        assert!(config.max_background_jobs > 0);
-        assert!(config.parallelism > 0);
-        assert_eq!(config.level_zero_file_num_compaction_trigger, 4);
-        assert_eq!(config.level_zero_slowdown_writes_trigger, 20);
-        assert_eq!(config.level_zero_stop_writes_trigger, 36);
-        assert_eq!(config.block_cache_size, 268_435_456);
-        assert_eq!(config.max_open_files, 100);
-        assert!(config.use_direct_io_for_flush_and_compaction);
-        assert!(!config.use_direct_reads);
-        assert_eq!(config.bytes_per_sync, 1_048_576);
-        assert_eq!(config.wal_bytes_per_sync, 1_048_576);
-        assert_eq!(config.target_file_size_base, 67_108_864);
-        assert_eq!(config.max_bytes_for_level_base, 268_435_456);
-        assert_eq!(config.write_buffer_size, 67_108_864);
-        assert_eq!(config.max_write_buffer_number, 3);
-        assert_eq!(config.min_write_buffer_number_to_merge, 1);
-    }
+        storage
+            .put("test_db", "users", "b", &user("bob@example.com"), None)
+            .await
+            .unwrap();
+        storage
+            .create_index("test_db", "users", &definition)
+            .await
+            .unwrap();
 
-    #[test]
-    fn test_cf_cache() {
-        let cache = CFCache::new();
+        // A refused batch writes none of its documents
+        let docs = [
+            ("c".to_string(), user("cat@example.com")),
+            ("d".to_string(), user("bob@example.com")),
+        ];
+        let result = storage.put_batch("test_db", "users", &docs, None).await;
+        assert_eq!(violation_key(result), "b");
+        assert!(
+            storage
+                .get("test_db", "users", "c", None)
+                .await
+                .unwrap()
+                .is_none()
+        );
 
-        // Test that cache starts empty
-        // We can't directly test get() without a real database, but we can test the structure
-        assert_eq!(cache.access_counter.load(Ordering::Relaxed), 0);
+        let docs = [
+            ("c".to_string(), user("cat@example.com")),
+            ("d".to_string(), user("cat@example.com")),
+        ];
+        let result = storage.put_batch("test_db", "users", &docs, None).await;
+        assert_eq!(violation_key(result), "c");
 
-        // Test that the cache map is accessible
-        assert!(cache.cache.read().unwrap().is_empty());
-    }
+        // Values given up earlier in a batch can be taken, and a document keeps its own
+        let docs = [
+            ("a".to_string(), user("bob@example.com")),
+            ("b".to_string(), user("ann@example.com")),
+        ];
+        storage
+            .put_batch("test_db", "users", &docs, None)
+            .await
+            .unwrap();
+        storage
+            .put("test_db", "users", "a", &user("bob@example.com"), None)
+            .await
+            .unwrap();
+        storage.delete("test_db", "users", "a", None).await.unwrap();
+        storage
+            .put("test_db", "users", "c", &user("bob@example.com"), None)
+            .await
+            .unwrap();
 
-    #[test]
-    fn test_database_config_default() {
-        let config = DatabaseConfig::default();
-        // DatabaseConfig is empty for now, but this tests the Default implementation
-        let _ = format!("{config:?}"); // Just ensure it's debuggable
+        // Documents without a value aren't constrained
+        for key in ["x", "y"] {
+            storage
+                .put("test_db", "users", key, &Document::new(), None)
+                .await
+                .unwrap();
+        }
+
+        // Of concurrent writers claiming one value, exactly one succeeds
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    storage
+                        .put(
+                            "test_db",
+                            "users",
+                            &format!("racer{i}"),
+                            &user("race@example.com"),
+                            None,
+                        )
+                        .await
+                })
+            })
+            .collect();
+        let mut succeeded = 0;
+        for writer in writers {
+            match writer.await.unwrap() {
+                Ok(()) => succeeded += 1,
+                Err(StorageError::ConstraintViolation { .. }) => {}
+                Err(e) => panic!("unexpected error {e}"),
+            }
+        }
+        assert_eq!(succeeded, 1);
     }
 
     #[tokio::test]
-    async fn test_create_database_invalid_names() {
+    async fn test_replica_applies_primary_updates() {
         use tempfile::TempDir;
 
-        let temp_dir = TempDir::new().expect("Failed to create temp dir");
-        let config = Config {
-            data_dir: temp_dir.path().to_string_lossy().to_string(),
-            ..Default::default()
+        let open = |dir: &TempDir| {
+            DefaultStorage::open(&Config {
+                data_dir: dir.path().to_string_lossy().to_string(),
+                ..Default::default()
+            })
+            .expect("Failed to create storage")
         };
-        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+        let doc = |name: &str| {
+            let mut doc = Document::new();
+            doc.insert(
+                "name".to_string(),
+                Datum {
+                    value: Some(datum::Value::String(name.to_string())),
+                },
+            );
+            doc
+        };
+        let (primary_dir, replica_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let primary = open(&primary_dir);
 
-        // Test invalid database names
-        assert!(storage.create_database("").await.is_err());
-        assert!(storage.create_database("invalid name").await.is_err());
-        assert!(storage.create_database("invalid@name").await.is_err());
-        assert!(storage.create_database("__system__").await.is_err());
-        assert!(storage.create_database(&"a".repeat(256)).await.is_err());
-    }
+        primary.create_database("test_db").await.unwrap();
+        primary
+            .create_table("test_db", "users", &TableConfig::default())
+            .await
+            .unwrap();
+        primary
+            .create_table("test_db", "dropped", &TableConfig::default())
+            .await
+            .unwrap();
+        primary
+            .put("test_db", "users", "a", &doc("alice"), None)
+            .await
+            .unwrap();
+        primary
+            .put("test_db", "users", "b", &doc("bob"), None)
+            .await
+            .unwrap();
+        primary
+            .put("test_db", "dropped", "x", &doc("xavier"), None)
+            .await
+            .unwrap();
+        primary.delete("test_db", "users", "b", None).await.unwrap();
+        primary.drop_table("test_db", "dropped").await.unwrap();
+
+        {
+            let replica = open(&replica_dir);
+            replica.set_replication_role(ReplicationRole::Replica {
+                primary: "primary".to_string(),
+            });
 
-    #[tokio::test]
-    async fn test_drop_database_invalid_names() {
-        use tempfile::TempDir;
+            // Apply the log in small chunks, resending the last chunk like a retry would
+            let mut from = 1;
+            loop {
+                let updates = primary.updates_since(from, 2).await.unwrap();
+                if updates.batches.is_empty() {
+                    break;
+                }
+                replica.apply_updates(updates.clone()).await.unwrap();
+                from = replica.apply_updates(updates).await.unwrap() + 1;
+            }
 
-        let temp_dir = TempDir::new().expect("Failed to create temp dir");
-        let config = Config {
-            data_dir: temp_dir.path().to_string_lossy().to_string(),
-            ..Default::default()
-        };
-        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+            let status = replica.replication_status();
+            assert_eq!(status.lag(), 0);
+            assert!(status.last_contact.is_some());
+
+            assert!(matches!(
+                replica
+                    .put("test_db", "users", "c", &doc("carol"), None)
+                    .await,
+                Err(StorageError::ReadOnlyReplica)
+            ));
+            assert!(matches!(
+                replica.updates_since(1, 10).await,
+                Err(StorageError::Replication(_))
+            ));
+        }
 
-        // Test invalid database names
-        assert!(storage.drop_database("").await.is_err());
-        assert!(storage.drop_database("invalid name").await.is_err());
-        assert!(storage.drop_database("__system__").await.is_err());
-        assert!(storage.drop_database("default").await.is_err());
-        assert!(storage.drop_database("nonexistent_db").await.is_ok()); // Should succeed even if doesn't exist
+        // The replica resumes from its persisted position after a restart
+        let replica = open(&replica_dir);
+        replica.set_replication_role(ReplicationRole::Replica {
+            primary: "primary".to_string(),
+        });
+        assert_eq!(
+            replica.replication_status().applied_sequence,
+            primary.replication_status().applied_sequence
+        );
+        let doc_a = replica.get("test_db", "users", "a", None).await.unwrap();
+        assert_eq!(doc_a, Some(doc("alice")));
+        assert!(
+            replica
+                .get("test_db", "users", "b", None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(replica.table_exists("test_db", "users").await.unwrap());
+        assert!(!replica.table_exists("test_db", "dropped").await.unwrap());
     }
 
     #[tokio::test]
-    async fn test_create_table_invalid_names() {
+    async fn test_secondary_follows_primary() {
         use tempfile::TempDir;
 
-        let temp_dir = TempDir::new().expect("Failed to create temp dir");
-        let config = Config {
-            data_dir: temp_dir.path().to_string_lossy().to_string(),
+        let doc = |name: &str| {
+            let mut doc = Document::new();
+            doc.insert(
+                "name".to_string(),
+                Datum {
+                    value: Some(datum::Value::String(name.to_string())),
+                },
+            );
+            doc
+        };
+        let (primary_dir, secondary_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let primary_path = primary_dir.path().to_string_lossy().to_string();
+        let secondary_config = Config {
+            data_dir: secondary_dir.path().to_string_lossy().to_string(),
             ..Default::default()
         };
-        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
 
-        // Create a valid database first
-        storage
-            .create_database("test_db")
+        // There is nothing to follow before the primary created its database
+        assert!(DefaultStorage::open_secondary(&secondary_config, &primary_path).is_err());
+
+        let primary = DefaultStorage::open(&Config {
+            data_dir: primary_path.clone(),
+            ..Default::default()
+        })
+        .expect("Failed to create storage");
+        primary.create_database("test_db").await.unwrap();
+        primary
+            .create_table("test_db", "users", &TableConfig::default())
             .await
-            .expect("Failed to create database");
+            .unwrap();
+        primary
+            .put("test_db", "users", "a", &doc("alice"), None)
+            .await
+            .unwrap();
+        primary
+            .create_sharded_table("test_db", "events", &TableConfig::default(), 2)
+            .await
+            .unwrap();
 
-        // Test invalid database names
-        assert!(storage.create_table("", "table").await.is_err());
-        assert!(storage.create_table("__system__", "table").await.is_err());
-        assert!(storage.create_table("invalid name", "table").await.is_err());
+        let secondary = DefaultStorage::open_secondary(&secondary_config, &primary_path)
+            .expect("Failed to open secondary");
+        assert_eq!(
+            secondary.get("test_db", "users", "a", None).await.unwrap(),
+            Some(doc("alice"))
+        );
 
-        // Test invalid table names
-        assert!(storage.create_table("test_db", "").await.is_err());
-        assert!(
-            storage
-                .create_table("test_db", "invalid name")
-                .await
-                .is_err()
+        // Writes and schema changes are rejected
+        assert!(matches!(
+            secondary
+                .put("test_db", "users", "b", &doc("bob"), None)
+                .await,
+            Err(StorageError::ReadOnlySecondary)
+        ));
+        assert!(matches!(
+            secondary.delete("test_db", "users", "a", None).await,
+            Err(StorageError::ReadOnlySecondary)
+        ));
+        assert!(matches!(
+            secondary
+                .create_table("test_db", "other", &TableConfig::default())
+                .await,
+            Err(StorageError::ReadOnlySecondary)
+        ));
+        assert!(matches!(
+            secondary.drop_database("test_db").await,
+            Err(StorageError::ReadOnlySecondary)
+        ));
+        assert!(matches!(
+            secondary.updates_since(1, 10).await,
+            Err(StorageError::Replication(_))
+        ));
+
+        // Catching up picks up new writes and metadata
+        primary
+            .put("test_db", "users", "b", &doc("bob"), None)
+            .await
+            .unwrap();
+        primary.analyze_table("test_db", "users", 10).await.unwrap();
+        secondary.catch_up_with_primary().await.unwrap();
+        assert_eq!(
+            secondary.get("test_db", "users", "b", None).await.unwrap(),
+            Some(doc("bob"))
+        );
+        assert_eq!(
+            secondary
+                .table_statistics("test_db", "users")
+                .unwrap()
+                .sampled_rows,
+            2
+        );
+
+        let status = secondary.replication_status();
+        assert_eq!(
+            status.role,
+            ReplicationRole::Secondary {
+                primary: primary_path
+            }
         );
+        assert_eq!(status.lag(), 0);
+        assert!(status.last_contact.is_some());
+
+        // Indexes and shards in column families created since it was opened are out of
+        // its reach
+        primary
+            .create_index(
+                "test_db",
+                "users",
+                &IndexDefinition {
+                    name: "name".to_string(),
+                    kind: IndexKind::FullText(index::fulltext::FullTextOptions {
+                        fields: vec![vec!["name".to_string()]],
+                        stemming: false,
+                    }),
+                },
+            )
+            .await
+            .unwrap();
+        primary
+            .rebalance_table("test_db", "events", Some(4))
+            .await
+            .unwrap();
+        secondary.catch_up_with_primary().await.unwrap();
         assert!(
-            storage
-                .create_table("test_db", "invalid@table")
+            secondary
+                .list_indexes("test_db", "users")
                 .await
-                .is_err()
+                .unwrap()
+                .is_empty()
         );
+        assert!(secondary.table_config("test_db", "users").is_some());
+        assert!(secondary.table_config("test_db", "events").is_none());
+        assert!(matches!(
+            secondary.get("test_db", "events", "z", None).await,
+            Err(StorageError::MissingColumnFamily(_))
+        ));
     }
 
     #[tokio::test]
@@ -1958,16 +7036,16 @@ mod tests {
         let doc = Document::new();
 
         // Test invalid database names
-        assert!(storage.put("", "table", "key", &doc).await.is_err());
+        assert!(storage.put("", "table", "key", &doc, None).await.is_err());
         assert!(
             storage
-                .put("__system__", "table", "key", &doc)
+                .put("__system__", "table", "key", &doc, None)
                 .await
                 .is_err()
         );
         assert!(
             storage
-                .put("invalid name", "table", "key", &doc)
+                .put("invalid name", "table", "key", &doc, None)
                 .await
                 .is_err()
         );
@@ -1987,16 +7065,16 @@ mod tests {
         let docs = vec![("key1".to_string(), Document::new())];
 
         // Test invalid database names
-        assert!(storage.put_batch("", "table", &docs).await.is_err());
+        assert!(storage.put_batch("", "table", &docs, None).await.is_err());
         assert!(
             storage
-                .put_batch("__system__", "table", &docs)
+                .put_batch("__system__", "table", &docs, None)
                 .await
                 .is_err()
         );
         assert!(
             storage
-                .put_batch("invalid name", "table", &docs)
+                .put_batch("invalid name", "table", &docs, None)
                 .await
                 .is_err()
         );
@@ -2014,9 +7092,19 @@ mod tests {
         let storage = DefaultStorage::open(&config).expect("Failed to create storage");
 
         // Test invalid database names
-        assert!(storage.get("", "table", "key").await.is_err());
-        assert!(storage.get("__system__", "table", "key").await.is_err());
-        assert!(storage.get("invalid name", "table", "key").await.is_err());
+        assert!(storage.get("", "table", "key", None).await.is_err());
+        assert!(
+            storage
+                .get("__system__", "table", "key", None)
+                .await
+                .is_err()
+        );
+        assert!(
+            storage
+                .get("invalid name", "table", "key", None)
+                .await
+                .is_err()
+        );
     }
 
     #[tokio::test]
@@ -2033,19 +7121,46 @@ mod tests {
         // Test invalid database names
         assert!(
             storage
-                .scan_table("", "table", None, None, None, None)
+                .scan_table(
+                    "",
+                    "table",
+                    None,
+                    None,
+                    None,
+                    None,
+                    ScanProjection::default(),
+                    None
+                )
                 .await
                 .is_err()
         );
         assert!(
             storage
-                .scan_table("__system__", "table", None, None, None, None)
+                .scan_table(
+                    "__system__",
+                    "table",
+                    None,
+                    None,
+                    None,
+                    None,
+                    ScanProjection::default(),
+                    None
+                )
                 .await
                 .is_err()
         );
         assert!(
             storage
-                .scan_table("invalid name", "table", None, None, None, None)
+                .scan_table(
+                    "invalid name",
+                    "table",
+                    None,
+                    None,
+                    None,
+                    None,
+                    ScanProjection::default(),
+                    None
+                )
                 .await
                 .is_err()
         );
@@ -2063,11 +7178,16 @@ mod tests {
         let storage = DefaultStorage::open(&config).expect("Failed to create storage");
 
         // Test invalid database names
-        assert!(storage.delete("", "table", "key").await.is_err());
-        assert!(storage.delete("__system__", "table", "key").await.is_err());
+        assert!(storage.delete("", "table", "key", None).await.is_err());
+        assert!(
+            storage
+                .delete("__system__", "table", "key", None)
+                .await
+                .is_err()
+        );
         assert!(
             storage
-                .delete("invalid name", "table", "key")
+                .delete("invalid name", "table", "key", None)
                 .await
                 .is_err()
         );
@@ -2116,19 +7236,19 @@ mod tests {
         // Test invalid database names
         assert!(
             storage
-                .stream_get_all("", "table", &[], None, None, None)
+                .stream_get_all("", "table", &[], None, None, None, None)
                 .await
                 .is_err()
         );
         assert!(
             storage
-                .stream_get_all("__system__", "table", &[], None, None, None)
+                .stream_get_all("__system__", "table", &[], None, None, None, None)
                 .await
                 .is_err()
         );
         assert!(
             storage
-                .stream_get_all("invalid name", "table", &keys, None, None, None)
+                .stream_get_all("invalid name", "table", &keys, None, None, None, None)
                 .await
                 .is_err()
         );
@@ -2151,7 +7271,7 @@ mod tests {
             .await
             .expect("Failed to create database");
         storage
-            .create_table("test_db", "test_table")
+            .create_table("test_db", "test_table", &TableConfig::default())
             .await
             .expect("Failed to create table");
 
@@ -2159,7 +7279,7 @@ mod tests {
         let empty_docs = vec![];
         assert!(
             storage
-                .put_batch("test_db", "test_table", &empty_docs)
+                .put_batch("test_db", "test_table", &empty_docs, None)
                 .await
                 .is_ok()
         );
@@ -2182,13 +7302,13 @@ mod tests {
             .await
             .expect("Failed to create database");
         storage
-            .create_table("test_db", "test_table")
+            .create_table("test_db", "test_table", &TableConfig::default())
             .await
             .expect("Failed to create table");
 
         // Test getting nonexistent key
         let result = storage
-            .get("test_db", "test_table", "nonexistent")
+            .get("test_db", "test_table", "nonexistent", None)
             .await
             .expect("Get should succeed");
         assert!(result.is_none());
@@ -2211,19 +7331,73 @@ mod tests {
             .await
             .expect("Failed to create database");
         storage
-            .create_table("test_db", "test_table")
+            .create_table("test_db", "test_table", &TableConfig::default())
             .await
             .expect("Failed to create table");
 
         // Test deleting nonexistent key (should succeed)
         assert!(
             storage
-                .delete("test_db", "test_table", "nonexistent")
+                .delete("test_db", "test_table", "nonexistent", None)
                 .await
                 .is_ok()
         );
     }
 
+    #[tokio::test]
+    async fn test_delete_batch() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+
+        // Setup, with the keys spread over shards
+        storage
+            .create_database("test_db")
+            .await
+            .expect("Failed to create database");
+        storage
+            .create_sharded_table("test_db", "test_table", &TableConfig::default(), 4)
+            .await
+            .expect("Failed to create table");
+        let keys: Vec<String> = ["a", "h", "p", "x"].map(String::from).to_vec();
+        let docs: Vec<(String, Document)> = keys
+            .iter()
+            .map(|key| {
+                let mut doc = Document::new();
+                doc.insert(
+                    "id".to_string(),
+                    Datum {
+                        value: Some(datum::Value::String(key.clone())),
+                    },
+                );
+                (key.clone(), doc)
+            })
+            .collect();
+        storage
+            .put_batch("test_db", "test_table", &docs, None)
+            .await
+            .expect("Failed to put documents");
+
+        // Keys with no document are skipped
+        let deleted = ["a", "p", "x", "nonexistent"].map(String::from);
+        storage
+            .delete_batch("test_db", "test_table", &deleted, None)
+            .await
+            .expect("Failed to delete documents");
+        for key in &keys {
+            let doc = storage
+                .get("test_db", "test_table", key, None)
+                .await
+                .expect("Get should succeed");
+            assert_eq!(doc.is_some(), key == "h", "document {key}");
+        }
+    }
+
     #[tokio::test]
     async fn test_scan_table_with_predicate() {
         use tempfile::TempDir;
@@ -2242,7 +7416,7 @@ mod tests {
             .await
             .expect("Failed to create database");
         storage
-            .create_table("test_db", "test_table")
+            .create_table("test_db", "test_table", &TableConfig::default())
             .await
             .expect("Failed to create table");
 
@@ -2263,25 +7437,36 @@ mod tests {
             );
 
             storage
-                .put("test_db", "test_table", &format!("key_{i}"), &doc)
+                .put("test_db", "test_table", &format!("key_{i}"), &doc, None)
                 .await
                 .expect("Failed to put document");
         }
 
         // Test scan with predicate that filters even numbers
-        let predicate = Box::new(|doc: Document| -> bool {
-            if let Some(Datum {
-                value: Some(datum::Value::Float(n)),
-            }) = doc.get("id")
-            {
-                *n as i32 % 2 == 0
-            } else {
-                false
-            }
-        });
+        let predicate = Box::new(
+            |doc: Document| -> std::result::Result<bool, PredicateError> {
+                if let Some(Datum {
+                    value: Some(datum::Value::Float(n)),
+                }) = doc.get("id")
+                {
+                    Ok(*n as i32 % 2 == 0)
+                } else {
+                    Ok(false)
+                }
+            },
+        );
 
         let mut stream = storage
-            .scan_table("test_db", "test_table", None, None, None, Some(predicate))
+            .scan_table(
+                "test_db",
+                "test_table",
+                None,
+                None,
+                None,
+                Some(predicate),
+                ScanProjection::default(),
+                None,
+            )
             .await
             .expect("Failed to scan table");
 
@@ -2300,6 +7485,124 @@ mod tests {
         assert_eq!(count, 3); // Should get 0, 2, 4
     }
 
+    #[tokio::test]
+    async fn test_scan_table_with_projection() {
+        use tempfile::TempDir;
+        use tokio_stream::StreamExt;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let config = Config {
+            data_dir: temp_dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let storage = DefaultStorage::open(&config).expect("Failed to create storage");
+
+        storage
+            .create_database("test_db")
+            .await
+            .expect("Failed to create database");
+        storage
+            .create_table("test_db", "test_table", &TableConfig::default())
+            .await
+            .expect("Failed to create table");
+
+        for i in 0..4 {
+            let mut doc = Document::new();
+            doc.insert(
+                "id".to_string(),
+                Datum {
+                    value: Some(datum::Value::Int(i)),
+                },
+            );
+            doc.insert(
+                "value".to_string(),
+                Datum {
+                    value: Some(datum::Value::String(format!("value_{i}"))),
+                },
+            );
+            doc.insert(
+                "payload".to_string(),
+                Datum {
+                    value: Some(datum::Value::Binary(vec![0; 64])),
+                },
+            );
+
+            storage
+                .put("test_db", "test_table", &format!("key_{i}"), &doc, None)
+                .await
+                .expect("Failed to put document");
+        }
+
+        // The predicate only sees the fields it asked for
+        let odd_ids = || {
+            Box::new(
+                |doc: Document| -> std::result::Result<bool, PredicateError> {
+                    assert!(!doc.contains_key("payload"));
+                    Ok(matches!(
+                        doc.get("id"),
+                        Some(Datum {
+                            value: Some(datum::Value::Int(n)),
+                        }) if n % 2 == 1
+                    ))
+                },
+            )
+        };
+
+        let projection = ScanProjection {
+            predicate_fields: Some(vec!["id".to_string()]),
+            output_fields: Some(vec!["id".to_string(), "value".to_string()]),
+        };
+
+        let mut stream = storage
+            .scan_table(
+                "test_db",
+                "test_table",
+                None,
+                None,
+                None,
+                Some(odd_ids()),
+                projection,
+                None,
+            )
+            .await
+            .expect("Failed to scan table");
+
+        let mut count = 0;
+        while let Some(result) = stream.next().await {
+            let doc = result.expect("Failed to get document from stream");
+            assert_eq!(doc.len(), 2);
+            assert!(doc.contains_key("id"));
+            assert!(doc.contains_key("value"));
+            count += 1;
+        }
+
+        assert_eq!(count, 2);
+
+        // Without a projection the matching documents are still returned whole
+        let projection = ScanProjection {
+            predicate_fields: Some(vec!["id".to_string()]),
+            output_fields: None,
+        };
+        let docs: Vec<Document> = storage
+            .scan_table(
+                "test_db",
+                "test_table",
+                None,
+                None,
+                None,
+                Some(odd_ids()),
+                projection,
+                None,
+            )
+            .await
+            .expect("Failed to scan table")
+            .map(|result| result.expect("Failed to get document from stream"))
+            .collect()
+            .await;
+        assert_eq!(docs.len(), 2);
+        assert!(docs.iter().all(|doc| doc.len() == 3));
+    }
+
     #[tokio::test]
     async fn test_scan_table_with_start_key_and_limit() {
         use tempfile::TempDir;
@@ -2318,7 +7621,7 @@ mod tests {
             .await
             .expect("Failed to create database");
         storage
-            .create_table("test_db", "test_table")
+            .create_table("test_db", "test_table", &TableConfig::default())
             .await
             .expect("Failed to create table");
 
@@ -2333,7 +7636,7 @@ mod tests {
             );
 
             storage
-                .put("test_db", "test_table", &format!("key_{i:02}"), &doc)
+                .put("test_db", "test_table", &format!("key_{i:02}"), &doc, None)
                 .await
                 .expect("Failed to put document");
         }
@@ -2347,6 +7650,8 @@ mod tests {
                 Some(3),
                 None,
                 None,
+                ScanProjection::default(),
+                None,
             )
             .await
             .expect("Failed to scan table");
@@ -2454,7 +7759,7 @@ mod tests {
         // Create some test tables
         for i in 0..3 {
             storage
-                .create_table("test_db", &format!("table_{i}"))
+                .create_table("test_db", &format!("table_{i}"), &TableConfig::default())
                 .await
                 .expect("Failed to create table");
         }
@@ -2496,7 +7801,7 @@ mod tests {
             .await
             .expect("Failed to create database");
         storage
-            .create_table("test_db", "test_table")
+            .create_table("test_db", "test_table", &TableConfig::default())
             .await
             .expect("Failed to create table");
 
@@ -2515,14 +7820,14 @@ mod tests {
             );
 
             storage
-                .put("test_db", "test_table", &key, &doc)
+                .put("test_db", "test_table", &key, &doc, None)
                 .await
                 .expect("Failed to put document");
         }
 
         // Test streaming get all
         let mut stream = storage
-            .stream_get_all("test_db", "test_table", &keys, None, None, None)
+            .stream_get_all("test_db", "test_table", &keys, None, None, None, None)
             .await
             .expect("Failed to stream get all");
 
@@ -2553,7 +7858,7 @@ mod tests {
             .await
             .expect("Failed to create database");
         storage
-            .create_table("test_db", "test_table")
+            .create_table("test_db", "test_table", &TableConfig::default())
             .await
             .expect("Failed to create table");
 
@@ -2566,7 +7871,7 @@ mod tests {
             },
         );
         storage
-            .put("test_db", "test_table", "key_1", &doc)
+            .put("test_db", "test_table", "key_1", &doc, None)
             .await
             .expect("Failed to put document");
 
@@ -2577,7 +7882,7 @@ mod tests {
             "key_2".to_string(),
         ];
         let mut stream = storage
-            .stream_get_all("test_db", "test_table", &keys, None, None, None)
+            .stream_get_all("test_db", "test_table", &keys, None, None, None, None)
             .await
             .expect("Failed to stream get all");
 
@@ -2657,7 +7962,7 @@ mod tests {
             .await
             .expect("Failed to create database");
         storage
-            .create_table("custom_test", "table")
+            .create_table("custom_test", "table", &TableConfig::default())
             .await
             .expect("Failed to create table");
 
@@ -2670,11 +7975,11 @@ mod tests {
         );
 
         storage
-            .put("custom_test", "table", "key", &doc)
+            .put("custom_test", "table", "key", &doc, None)
             .await
             .expect("Failed to put with custom config");
         let result = storage
-            .get("custom_test", "table", "key")
+            .get("custom_test", "table", "key", None)
             .await
             .expect("Failed to get with custom config");
         assert!(result.is_some());
@@ -2710,12 +8015,12 @@ mod tests {
 
         // These should fail because the column family doesn't exist
         let result = storage
-            .put("nonexistent_db", "nonexistent_table", "key", &doc)
+            .put("nonexistent_db", "nonexistent_table", "key", &doc, None)
             .await;
         assert!(result.is_err());
 
         let result = storage
-            .get("nonexistent_db", "nonexistent_table", "key")
+            .get("nonexistent_db", "nonexistent_table", "key", None)
             .await;
         assert!(result.is_err());
     }
@@ -2742,7 +8047,7 @@ mod tests {
 
         // Create table
         storage
-            .create_table(db_name, table_name)
+            .create_table(db_name, table_name, &TableConfig::default())
             .await
             .expect("Failed to create table");
 
@@ -2755,13 +8060,13 @@ mod tests {
             },
         );
         storage
-            .put(db_name, table_name, "test_key", &doc)
+            .put(db_name, table_name, "test_key", &doc, None)
             .await
             .expect("Failed to put document");
 
         // Verify data exists
         let result = storage
-            .get(db_name, table_name, "test_key")
+            .get(db_name, table_name, "test_key", None)
             .await
             .expect("Failed to get document");
         assert!(result.is_some());
@@ -2773,7 +8078,7 @@ mod tests {
             .expect("Failed to drop table");
 
         // Data should no longer be accessible
-        let result = storage.get(db_name, table_name, "test_key").await;
+        let result = storage.get(db_name, table_name, "test_key", None).await;
         assert!(result.is_err()); // Should fail because table doesn't exist
 
         // Drop database
@@ -2800,7 +8105,7 @@ mod tests {
             .await
             .expect("Failed to create database");
         storage
-            .create_table("test_db", "test_table")
+            .create_table("test_db", "test_table", &TableConfig::default())
             .await
             .expect("Failed to create table");
 
@@ -2819,11 +8124,11 @@ mod tests {
 
         // Should be able to store and retrieve large documents
         storage
-            .put("test_db", "test_table", "large_key", &large_doc)
+            .put("test_db", "test_table", "large_key", &large_doc, None)
             .await
             .expect("Failed to put large document");
         let result = storage
-            .get("test_db", "test_table", "large_key")
+            .get("test_db", "test_table", "large_key", None)
             .await
             .expect("Failed to get large document");
         assert!(result.is_some());
@@ -2848,7 +8153,7 @@ mod tests {
             .await
             .expect("Failed to create database");
         storage
-            .create_table("test_db", "test_table")
+            .create_table("test_db", "test_table", &TableConfig::default())
             .await
             .expect("Failed to create table");
 
@@ -2869,12 +8174,12 @@ mod tests {
 
         for key in valid_keys {
             storage
-                .put("test_db", "test_table", key, &doc)
+                .put("test_db", "test_table", key, &doc, None)
                 .await
                 .unwrap_or_else(|_| panic!("Failed to put document with key: {key}"));
 
             let result = storage
-                .get("test_db", "test_table", key)
+                .get("test_db", "test_table", key, None)
                 .await
                 .unwrap_or_else(|_| panic!("Failed to get document with key: {key}"));
 
@@ -0,0 +1,286 @@
+//! On-disk document encoding.
+//!
+//! Documents are stored with a field offset table in front of the values, so a
+//! reader can decode only the top-level fields it needs instead of the whole
+//! document. The layout is:
+//!
+//! ```text
+//! +-------+---------+-------------+-------------------------------+--------+
+//! | magic | version | field count | entries (sorted by field name)| values |
+//! | u8    | u8      | u32         | name len u16, name, off u32,  | bytes  |
+//! |       |         |             | len u32                       |        |
+//! +-------+---------+-------------+-------------------------------+--------+
+//! ```
+//!
+//! Integers are little-endian and every value is an individually bincode encoded
+//! `Datum`. The magic byte is `0xFF`, which bincode never emits as the first byte
+//! of a map length, so documents written before this format existed are still
+//! readable and are decoded with bincode as a whole.
+
+use super::{Result, StorageError};
+use crate::ast::{Datum, Document};
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Marks a document encoded with a field offset table.
+const MAGIC: u8 = 0xFF;
+
+/// Current version of the indexed document layout.
+const VERSION: u8 = 1;
+
+/// Size of the fixed header: magic, version and field count.
+const HEADER_LEN: usize = 6;
+
+/// Encode a document into the indexed on-disk layout.
+pub fn encode_document(doc: &Document) -> Result<Vec<u8>> {
+    let mut fields: Vec<(&String, &Datum)> = doc.iter().collect();
+    fields.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+    let mut values = Vec::new();
+    let mut entries = Vec::with_capacity(fields.len());
+    for (name, value) in fields {
+        let offset = values.len();
+        bincode::serde::encode_into_std_write(value, &mut values, bincode::config::standard())?;
+        entries.push((name, offset, values.len() - offset));
+    }
+
+    let table_len: usize = entries.iter().map(|(name, _, _)| 10 + name.len()).sum();
+    let mut out = Vec::with_capacity(HEADER_LEN + table_len + values.len());
+    out.push(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&encode_u32(entries.len())?);
+
+    for (name, offset, len) in entries {
+        let name_len =
+            u16::try_from(name.len()).map_err(|_| StorageError::InvalidFieldName(name.clone()))?;
+        out.extend_from_slice(&name_len.to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&encode_u32(offset)?);
+        out.extend_from_slice(&encode_u32(len)?);
+    }
+
+    out.extend_from_slice(&values);
+    Ok(out)
+}
+
+/// Decode a whole document, whichever layout it was written with.
+pub fn decode_document(data: &[u8]) -> Result<Document> {
+    EncodedDocument::parse(data)?.decode()
+}
+
+/// A stored document whose fields are decoded on demand.
+pub enum EncodedDocument<'a> {
+    /// A document in the indexed layout.
+    Indexed {
+        entries: Vec<FieldEntry<'a>>,
+        values: &'a [u8],
+    },
+    /// A document written as a plain bincode map, which is decoded eagerly.
+    Legacy(Document),
+}
+
+/// Location of a single field's value inside an indexed document.
+pub struct FieldEntry<'a> {
+    name: &'a str,
+    offset: usize,
+    len: usize,
+}
+
+impl<'a> EncodedDocument<'a> {
+    /// Parse the header and offset table of a stored document without decoding any value.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if data.first() != Some(&MAGIC) {
+            return bincode::serde::decode_from_slice(data, bincode::config::standard())
+                .map(|(doc, _)| Self::Legacy(doc))
+                .map_err(StorageError::DecodeError);
+        }
+
+        if data.len() < HEADER_LEN {
+            return Err(StorageError::CorruptDocument(
+                "truncated header".to_string(),
+            ));
+        }
+        if data[1] != VERSION {
+            return Err(StorageError::CorruptDocument(format!(
+                "unsupported document version {}",
+                data[1]
+            )));
+        }
+
+        let count = LittleEndian::read_u32(&data[2..HEADER_LEN]) as usize;
+        let mut entries = Vec::with_capacity(count);
+        let mut pos = HEADER_LEN;
+
+        for _ in 0..count {
+            let name_len = LittleEndian::read_u16(slice(data, pos, 2)?) as usize;
+            pos += 2;
+            let name = std::str::from_utf8(slice(data, pos, name_len)?)
+                .map_err(|e| StorageError::CorruptDocument(e.to_string()))?;
+            pos += name_len;
+            let offset = LittleEndian::read_u32(slice(data, pos, 4)?) as usize;
+            let len = LittleEndian::read_u32(slice(data, pos + 4, 4)?) as usize;
+            pos += 8;
+            entries.push(FieldEntry { name, offset, len });
+        }
+
+        let values = &data[pos..];
+        if entries.iter().any(|e| e.offset + e.len > values.len()) {
+            return Err(StorageError::CorruptDocument(
+                "field value out of bounds".to_string(),
+            ));
+        }
+
+        Ok(Self::Indexed { entries, values })
+    }
+
+    /// Decode every field of the document.
+    pub fn decode(self) -> Result<Document> {
+        match self {
+            Self::Indexed { entries, values } => entries
+                .iter()
+                .map(|entry| Ok((entry.name.to_string(), decode_value(values, entry)?)))
+                .collect(),
+            Self::Legacy(doc) => Ok(doc),
+        }
+    }
+
+    /// Decode a single top-level field, returning `None` if the document does not have it.
+    pub fn field(&self, name: &str) -> Result<Option<Datum>> {
+        match self {
+            Self::Indexed { entries, values } => entries
+                .binary_search_by(|entry| entry.name.cmp(name))
+                .ok()
+                .map(|idx| decode_value(values, &entries[idx]))
+                .transpose(),
+            Self::Legacy(doc) => Ok(doc.get(name).cloned()),
+        }
+    }
+
+    /// Decode only the given top-level fields. Fields missing from the document are skipped.
+    pub fn decode_fields<S: AsRef<str>>(&self, names: &[S]) -> Result<Document> {
+        let mut doc = Document::with_capacity(names.len());
+        for name in names {
+            let name = name.as_ref();
+            if doc.contains_key(name) {
+                continue;
+            }
+            if let Some(value) = self.field(name)? {
+                doc.insert(name.to_string(), value);
+            }
+        }
+        Ok(doc)
+    }
+}
+
+#[inline]
+fn decode_value(values: &[u8], entry: &FieldEntry<'_>) -> Result<Datum> {
+    bincode::serde::decode_from_slice(
+        &values[entry.offset..entry.offset + entry.len],
+        bincode::config::standard(),
+    )
+    .map(|(value, _)| value)
+    .map_err(StorageError::DecodeError)
+}
+
+#[inline]
+fn slice(data: &[u8], pos: usize, len: usize) -> Result<&[u8]> {
+    data.get(pos..pos + len)
+        .ok_or_else(|| StorageError::CorruptDocument("truncated offset table".to_string()))
+}
+
+#[inline]
+fn encode_u32(value: usize) -> Result<[u8; 4]> {
+    u32::try_from(value)
+        .map(u32::to_le_bytes)
+        .map_err(|_| StorageError::CorruptDocument("document too large".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::datum;
+
+    fn sample_doc() -> Document {
+        let mut doc = Document::new();
+        doc.insert(
+            "id".to_string(),
+            Datum {
+                value: Some(datum::Value::String("doc1".to_string())),
+            },
+        );
+        doc.insert(
+            "age".to_string(),
+            Datum {
+                value: Some(datum::Value::Int(42)),
+            },
+        );
+        doc.insert("empty".to_string(), Datum { value: None });
+        doc
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let doc = sample_doc();
+        let encoded = encode_document(&doc).unwrap();
+        assert_eq!(encoded[0], MAGIC);
+        assert_eq!(decode_document(&encoded).unwrap(), doc);
+    }
+
+    #[test]
+    fn test_decode_empty_document() {
+        let encoded = encode_document(&Document::new()).unwrap();
+        assert!(decode_document(&encoded).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_single_field() {
+        let doc = sample_doc();
+        let encoded = encode_document(&doc).unwrap();
+        let parsed = EncodedDocument::parse(&encoded).unwrap();
+
+        assert_eq!(parsed.field("age").unwrap(), doc.get("age").cloned());
+        assert_eq!(parsed.field("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_decode_selected_fields() {
+        let doc = sample_doc();
+        let encoded = encode_document(&doc).unwrap();
+        let parsed = EncodedDocument::parse(&encoded).unwrap();
+
+        let projected = parsed.decode_fields(&["id", "missing", "id"]).unwrap();
+        assert_eq!(projected.len(), 1);
+        assert_eq!(projected.get("id"), doc.get("id"));
+    }
+
+    #[test]
+    fn test_decode_legacy_bincode_document() {
+        let doc = sample_doc();
+        let legacy = bincode::serde::encode_to_vec(&doc, bincode::config::standard()).unwrap();
+        assert_ne!(legacy[0], MAGIC);
+
+        let parsed = EncodedDocument::parse(&legacy).unwrap();
+        assert_eq!(parsed.field("age").unwrap(), doc.get("age").cloned());
+        assert_eq!(decode_document(&legacy).unwrap(), doc);
+    }
+
+    #[test]
+    fn test_decode_corrupt_document() {
+        let encoded = encode_document(&sample_doc()).unwrap();
+
+        assert!(matches!(
+            EncodedDocument::parse(&encoded[..4]),
+            Err(StorageError::CorruptDocument(_))
+        ));
+        assert!(matches!(
+            EncodedDocument::parse(&encoded[..encoded.len() - 1]),
+            Err(StorageError::CorruptDocument(_))
+        ));
+
+        let mut bad_version = encoded.clone();
+        bad_version[1] = VERSION + 1;
+        assert!(matches!(
+            EncodedDocument::parse(&bad_version),
+            Err(StorageError::CorruptDocument(_))
+        ));
+    }
+}
@@ -0,0 +1,139 @@
+//! Group commit for hard-durability writes.
+//!
+//! A hard write is applied without syncing and then waits here until the
+//! write-ahead log has been synced past it. Only one sync runs at a time: the
+//! first waiter becomes the leader and syncs on behalf of every write that was
+//! applied before the sync started, while later arrivals wait for the next
+//! round. Concurrent hard writes therefore share a single fsync instead of
+//! paying for one each.
+
+use super::Result;
+use std::sync::{Condvar, Mutex};
+
+#[derive(Default)]
+struct State {
+    /// Number of writes that asked to be made durable.
+    requested: u64,
+    /// Highest request number covered by a completed sync.
+    synced: u64,
+    /// Whether a leader is currently syncing.
+    syncing: bool,
+}
+
+#[derive(Default)]
+pub struct GroupCommit {
+    state: Mutex<State>,
+    synced: Condvar,
+}
+
+impl GroupCommit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Block until every write applied before this call is durable. `sync` is called
+    /// if this caller ends up leading a sync round.
+    pub fn commit(&self, sync: impl Fn() -> Result<()>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.requested += 1;
+        let ticket = state.requested;
+
+        loop {
+            if state.synced >= ticket {
+                return Ok(());
+            }
+
+            if !state.syncing {
+                state.syncing = true;
+                let target = state.requested;
+                drop(state);
+
+                let result = sync();
+
+                state = self.state.lock().unwrap();
+                state.syncing = false;
+                if result.is_ok() {
+                    state.synced = state.synced.max(target);
+                }
+                // Waiters whose writes were not covered, or whose sync failed, retry
+                // and lead the next round themselves.
+                self.synced.notify_all();
+                return result;
+            }
+
+            state = self.synced.wait(state).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageError;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_single_commit_syncs_once() {
+        let group = GroupCommit::new();
+        let syncs = AtomicUsize::new(0);
+
+        group
+            .commit(|| {
+                syncs.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(syncs.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_concurrent_commits_share_syncs() {
+        const WRITERS: usize = 16;
+
+        let group = Arc::new(GroupCommit::new());
+        let syncs = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..WRITERS)
+            .map(|_| {
+                let group = group.clone();
+                let syncs = syncs.clone();
+                std::thread::spawn(move || {
+                    group.commit(|| {
+                        syncs.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(20));
+                        Ok(())
+                    })
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap().unwrap();
+        }
+
+        let syncs = syncs.load(Ordering::SeqCst);
+        assert!(syncs >= 1);
+        assert!(syncs < WRITERS, "expected shared syncs, got {syncs}");
+    }
+
+    #[test]
+    fn test_failed_sync_is_reported_and_retried() {
+        let group = GroupCommit::new();
+
+        let result = group.commit(|| Err(StorageError::ResourceExhausted));
+        assert!(matches!(result, Err(StorageError::ResourceExhausted)));
+
+        // The failed round did not mark anything as synced, so the next write syncs again
+        let syncs = AtomicUsize::new(0);
+        group
+            .commit(|| {
+                syncs.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(syncs.load(Ordering::SeqCst), 1);
+    }
+}
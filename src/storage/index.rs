@@ -0,0 +1,526 @@
+//! Secondary indexes of tables.
+//!
+//! Each index keeps its entries in its own column family, named after the table and
+//! the index, and its definition in the indexes system table. Writes to a table update
+//! its indexes in the same write batch as its documents, so an index never falls out of
+//! step with the table, on replicas included.
+
+pub mod fulltext;
+pub mod geo;
+pub mod secondary;
+pub mod vector;
+
+use super::{Result, StorageError};
+use crate::ast::{Document, datum};
+use fulltext::FullTextOptions;
+use geo::GeoOptions;
+use rocksdb::{
+    BoundColumnFamily, DBWithThreadMode, IteratorMode, MultiThreaded, ReadOptions, WriteBatch,
+};
+use secondary::SecondaryOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use vector::VectorOptions;
+
+/// Largest number of fields a single index may cover.
+pub const MAX_INDEX_FIELDS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub kind: IndexKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IndexKind {
+    FullText(FullTextOptions),
+    Geo(GeoOptions),
+    Vector(VectorOptions),
+    Secondary(SecondaryOptions),
+}
+
+impl IndexDefinition {
+    /// Check the definition before an index is built from it.
+    pub fn validate(&self) -> Result<()> {
+        if !super::is_valid_key(&self.name) {
+            return Err(StorageError::Index(format!(
+                "invalid index name: {}",
+                self.name
+            )));
+        }
+
+        match &self.kind {
+            IndexKind::FullText(options) => validate_fields(&options.fields),
+            IndexKind::Geo(options) => validate_fields(std::slice::from_ref(&options.field)),
+            IndexKind::Vector(options) => {
+                validate_fields(std::slice::from_ref(&options.field))?;
+                options.validate()
+            }
+            IndexKind::Secondary(options) => options.validate(),
+        }
+    }
+}
+
+fn validate_fields(fields: &[Vec<String>]) -> Result<()> {
+    if fields.is_empty() || fields.len() > MAX_INDEX_FIELDS {
+        return Err(StorageError::Index(format!(
+            "an index must cover between 1 and {MAX_INDEX_FIELDS} fields, got {}",
+            fields.len()
+        )));
+    }
+    if let Some(path) = fields
+        .iter()
+        .find(|path| path.is_empty() || path.iter().any(String::is_empty))
+    {
+        return Err(StorageError::InvalidFieldName(path.join(".")));
+    }
+    Ok(())
+}
+
+/// Name of the column family holding the index `name` of a table.
+pub fn index_cf_name(table_name: &str, name: &str) -> String {
+    format!("{table_name}@{name}")
+}
+
+/// Whether a column family holds an index rather than a table.
+pub fn is_index_name(cf_name: &str) -> bool {
+    cf_name.contains('@')
+}
+
+/// Whether a column family holds an index of the table `table_name`.
+pub fn is_index_of(cf_name: &str, table_name: &str) -> bool {
+    cf_name
+        .strip_prefix(table_name)
+        .is_some_and(|rest| rest.starts_with('@'))
+}
+
+/// The value at `path` in a document, descending into nested objects.
+pub fn field_value<'a>(doc: &'a Document, path: &[String]) -> Option<&'a datum::Value> {
+    let (first, rest) = path.split_first()?;
+    let mut value = doc.get(first)?.value.as_ref()?;
+    for name in rest {
+        let datum::Value::Object(object) = value else {
+            return None;
+        };
+        value = object.fields.get(name)?.value.as_ref()?;
+    }
+    Some(value)
+}
+
+/// The index definitions of every table, keyed by table name.
+#[derive(Default)]
+pub struct IndexRegistry {
+    definitions: RwLock<HashMap<String, Vec<IndexDefinition>>>,
+    /// Serializes the writes to each indexed table, which read an index's entries
+    /// before replacing them, and its updates, which read the documents they rewrite.
+    table_locks: NamedLocks<String>,
+    /// Serializes the updates of each document of a table without indexes.
+    document_locks: NamedLocks<(String, String)>,
+}
+
+/// Holds the indexes of a table in place while a write updates them.
+pub struct IndexWriteGuard<'a> {
+    definitions: RwLockReadGuard<'a, HashMap<String, Vec<IndexDefinition>>>,
+    table_name: String,
+    _table_lock: Option<NamedLockGuard<'a, String>>,
+    _document_locks: Option<NamedLockGuard<'a, (String, String)>>,
+}
+
+/// Locks taken by name, each held by one writer at a time.
+#[derive(Default)]
+struct NamedLocks<K> {
+    held: Mutex<HashSet<K>>,
+    released: Condvar,
+}
+
+/// Holds some locks of a `NamedLocks` until dropped.
+struct NamedLockGuard<'a, K: Eq + Hash> {
+    locks: &'a NamedLocks<K>,
+    names: Vec<K>,
+}
+
+impl<K: Eq + Hash + Clone> NamedLocks<K> {
+    /// Wait until none of the names is held, then hold them all at once so that two
+    /// writers never wait on each other.
+    fn lock(&self, names: Vec<K>) -> NamedLockGuard<'_, K> {
+        let mut held = self.held.lock().unwrap();
+        while names.iter().any(|name| held.contains(name)) {
+            held = self.released.wait(held).unwrap();
+        }
+        held.extend(names.iter().cloned());
+        NamedLockGuard { locks: self, names }
+    }
+}
+
+impl<K: Eq + Hash> Drop for NamedLockGuard<'_, K> {
+    fn drop(&mut self) {
+        let mut held = self.locks.held.lock().unwrap();
+        for name in &self.names {
+            held.remove(name);
+        }
+        drop(held);
+        self.locks.released.notify_all();
+    }
+}
+
+impl IndexWriteGuard<'_> {
+    pub fn definitions(&self) -> &[IndexDefinition] {
+        self.definitions
+            .get(&self.table_name)
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+impl IndexRegistry {
+    /// Read the definitions of every index from the indexes table.
+    pub fn load(db: &DBWithThreadMode<MultiThreaded>, indexes_cf: &str) -> Result<Self> {
+        let registry = Self::default();
+        registry.reload(db, indexes_cf, |_| true)?;
+        Ok(registry)
+    }
+
+    /// Replace the definitions with those in the indexes table whose column family
+    /// `visible` accepts.
+    pub fn reload(
+        &self,
+        db: &DBWithThreadMode<MultiThreaded>,
+        indexes_cf: &str,
+        visible: impl Fn(&str) -> bool,
+    ) -> Result<()> {
+        let mut definitions: HashMap<String, Vec<IndexDefinition>> = HashMap::new();
+        if let Some(cf) = db.cf_handle(indexes_cf) {
+            for res in db.iterator_cf_opt(&cf, ReadOptions::default(), IteratorMode::Start) {
+                let (key, value) = res?;
+                let cf_name = String::from_utf8(key.to_vec())?;
+                if !visible(&cf_name) {
+                    continue;
+                }
+                let (definition, _) = bincode::serde::decode_from_slice::<IndexDefinition, _>(
+                    &value,
+                    bincode::config::standard(),
+                )?;
+                if let Some((table_name, _)) = cf_name.split_once('@') {
+                    definitions
+                        .entry(table_name.to_string())
+                        .or_default()
+                        .push(definition);
+                }
+            }
+        }
+        *self.definitions.write().unwrap() = definitions;
+        Ok(())
+    }
+
+    /// Lock the indexes of a table for a write to it. Writes to a table without
+    /// indexes take no lock.
+    pub fn lock_for_write(&self, table_name: &str) -> IndexWriteGuard<'_> {
+        let definitions = self.definitions.read().unwrap();
+        let table_lock = definitions
+            .contains_key(table_name)
+            .then(|| self.table_locks.lock(vec![table_name.to_string()]));
+        IndexWriteGuard {
+            definitions,
+            table_name: table_name.to_string(),
+            _table_lock: table_lock,
+            _document_locks: None,
+        }
+    }
+
+    /// Lock the indexes of a table for an update of the documents with the keys, so
+    /// that no other update rewrites them in between. A table without indexes only has
+    /// those documents locked.
+    pub fn lock_for_update(&self, table_name: &str, keys: &[String]) -> IndexWriteGuard<'_> {
+        let definitions = self.definitions.read().unwrap();
+        let (table_lock, document_locks) = if definitions.contains_key(table_name) {
+            (
+                Some(self.table_locks.lock(vec![table_name.to_string()])),
+                None,
+            )
+        } else {
+            let names = keys
+                .iter()
+                .map(|key| (table_name.to_string(), key.clone()))
+                .collect();
+            (None, Some(self.document_locks.lock(names)))
+        };
+        IndexWriteGuard {
+            definitions,
+            table_name: table_name.to_string(),
+            _table_lock: table_lock,
+            _document_locks: document_locks,
+        }
+    }
+
+    /// Lock every index for a change to the set of indexes.
+    pub fn lock_for_change(&self) -> RwLockWriteGuard<'_, HashMap<String, Vec<IndexDefinition>>> {
+        self.definitions.write().unwrap()
+    }
+
+    pub fn definitions(&self, table_name: &str) -> Vec<IndexDefinition> {
+        self.definitions
+            .read()
+            .unwrap()
+            .get(table_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn definition(&self, table_name: &str, name: &str) -> Option<IndexDefinition> {
+        self.definitions
+            .read()
+            .unwrap()
+            .get(table_name)?
+            .iter()
+            .find(|definition| definition.name == name)
+            .cloned()
+    }
+
+    /// Record a definition read from a replicated write to the indexes table.
+    pub fn insert(&self, cf_name: &str, definition: IndexDefinition) {
+        let Some((table_name, _)) = cf_name.split_once('@') else {
+            return;
+        };
+        let mut definitions = self.definitions.write().unwrap();
+        let table = definitions.entry(table_name.to_string()).or_default();
+        table.retain(|existing| existing.name != definition.name);
+        table.push(definition);
+    }
+
+    /// Forget a definition removed by a replicated write to the indexes table.
+    pub fn remove(&self, cf_name: &str) {
+        let Some((table_name, name)) = cf_name.split_once('@') else {
+            return;
+        };
+        let mut definitions = self.definitions.write().unwrap();
+        if let Some(table) = definitions.get_mut(table_name) {
+            table.retain(|definition| definition.name != name);
+            if table.is_empty() {
+                definitions.remove(table_name);
+            }
+        }
+    }
+}
+
+/// Updates one index of a table along with a write batch.
+pub enum IndexWriter<'a> {
+    FullText(fulltext::IndexWriter<'a>),
+    Geo(geo::IndexWriter<'a>),
+    Vector(vector::IndexWriter<'a>),
+    Secondary(secondary::IndexWriter<'a>),
+}
+
+impl<'a> IndexWriter<'a> {
+    pub fn new(
+        db: &'a Arc<DBWithThreadMode<MultiThreaded>>,
+        table_name: &str,
+        definition: &IndexDefinition,
+    ) -> Result<Self> {
+        let cf_name = index_cf_name(table_name, &definition.name);
+        let cf: Arc<BoundColumnFamily<'a>> = db
+            .cf_handle(&cf_name)
+            .ok_or(StorageError::MissingColumnFamily(cf_name))?;
+        match &definition.kind {
+            IndexKind::FullText(options) => {
+                Ok(Self::FullText(fulltext::IndexWriter::new(db, cf, options)?))
+            }
+            IndexKind::Geo(options) => Ok(Self::Geo(geo::IndexWriter::new(db, cf, options))),
+            IndexKind::Vector(options) => {
+                Ok(Self::Vector(vector::IndexWriter::new(db, cf, options)?))
+            }
+            IndexKind::Secondary(options) => Ok(Self::Secondary(secondary::IndexWriter::new(
+                db,
+                cf,
+                &definition.name,
+                options,
+            ))),
+        }
+    }
+
+    /// Index the document at `key` as `doc`, or remove it if it was deleted.
+    pub fn update(
+        &mut self,
+        batch: &mut WriteBatch,
+        key: &str,
+        doc: Option<&Document>,
+    ) -> Result<()> {
+        match self {
+            Self::FullText(writer) => writer.update(batch, key, doc),
+            Self::Geo(writer) => writer.update(batch, key, doc),
+            Self::Vector(writer) => writer.update(batch, key, doc),
+            Self::Secondary(writer) => writer.update(batch, key, doc),
+        }
+    }
+
+    pub fn finish(self, batch: &mut WriteBatch) -> Result<()> {
+        match self {
+            Self::FullText(writer) => writer.finish(batch),
+            Self::Secondary(writer) => writer.finish(),
+            Self::Geo(_) => Ok(()),
+            Self::Vector(writer) => writer.finish(batch),
+        }
+    }
+}
+
+/// Update the indexes of a table for the documents written to it in `batch`, each
+/// given with its new content or `None` if it was deleted.
+pub fn update_indexes<'d>(
+    db: &Arc<DBWithThreadMode<MultiThreaded>>,
+    table_name: &str,
+    definitions: &[IndexDefinition],
+    batch: &mut WriteBatch,
+    changes: impl IntoIterator<Item = (&'d str, Option<&'d Document>)> + Clone,
+) -> Result<()> {
+    for definition in definitions {
+        let mut writer = IndexWriter::new(db, table_name, definition)?;
+        for (key, doc) in changes.clone() {
+            writer.update(batch, key, doc)?;
+        }
+        writer.finish(batch)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Datum, DatumObject};
+
+    #[test]
+    fn test_index_names() {
+        assert_eq!(index_cf_name("db:posts", "body"), "db:posts@body");
+        assert!(is_index_name("db:posts@body"));
+        assert!(!is_index_name("db:posts"));
+        assert!(is_index_of("db:posts@body", "db:posts"));
+        assert!(!is_index_of("db:posts2@body", "db:posts"));
+        assert!(!is_index_of("db:posts#1", "db:posts"));
+    }
+
+    #[test]
+    fn test_field_value() {
+        let title = Datum {
+            value: Some(datum::Value::String("Hello".to_string())),
+        };
+        let doc = Document::from([(
+            "meta".to_string(),
+            Datum {
+                value: Some(datum::Value::Object(DatumObject {
+                    fields: HashMap::from([("title".to_string(), title.clone())]),
+                })),
+            },
+        )]);
+
+        let path = |names: &[&str]| names.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            field_value(&doc, &path(&["meta", "title"])),
+            title.value.as_ref()
+        );
+        assert_eq!(field_value(&doc, &path(&["meta", "body"])), None);
+        assert_eq!(field_value(&doc, &path(&["meta", "title", "x"])), None);
+        assert_eq!(field_value(&doc, &[]), None);
+    }
+
+    #[test]
+    fn test_validate_definition() {
+        let definition = |name: &str, fields: Vec<Vec<String>>| IndexDefinition {
+            name: name.to_string(),
+            kind: IndexKind::FullText(FullTextOptions {
+                fields,
+                stemming: false,
+            }),
+        };
+        assert!(
+            definition("body", vec![vec!["body".to_string()]])
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            definition("bad@name", vec![vec!["body".to_string()]])
+                .validate()
+                .is_err()
+        );
+        assert!(definition("body", vec![]).validate().is_err());
+        assert!(
+            definition("body", vec![vec![String::new()]])
+                .validate()
+                .is_err()
+        );
+
+        let geo = |field: Vec<String>| IndexDefinition {
+            name: "location".to_string(),
+            kind: IndexKind::Geo(GeoOptions { field }),
+        };
+        assert!(geo(vec!["location".to_string()]).validate().is_ok());
+        assert!(geo(vec![]).validate().is_err());
+
+        let vector = |dimension: u32, lists: u32| IndexDefinition {
+            name: "embedding".to_string(),
+            kind: IndexKind::Vector(VectorOptions {
+                field: vec!["embedding".to_string()],
+                dimension,
+                metric: vector::Metric::Cosine,
+                lists,
+            }),
+        };
+        assert!(vector(384, 32).validate().is_ok());
+        assert!(vector(0, 32).validate().is_err());
+        assert!(vector(384, 0).validate().is_err());
+    }
+
+    /// Whether taking a lock on another thread has to wait, given up to a while
+    fn waits_for(lock: impl FnOnce() + Send + 'static) -> bool {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            lock();
+            let _ = sender.send(());
+        });
+        receiver
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err()
+    }
+
+    #[test]
+    fn test_write_locks_are_per_table() {
+        let registry = Arc::new(IndexRegistry::default());
+        let definition = IndexDefinition {
+            name: "body".to_string(),
+            kind: IndexKind::FullText(FullTextOptions {
+                fields: vec![vec!["body".to_string()]],
+                stemming: false,
+            }),
+        };
+        registry.insert("db:posts@body", definition.clone());
+        registry.insert("db:notes@body", definition);
+
+        let posts = registry.lock_for_write("db:posts");
+        let other = registry.clone();
+        assert!(!waits_for(move || drop(other.lock_for_write("db:notes"))));
+        let other = registry.clone();
+        assert!(!waits_for(move || drop(other.lock_for_write("db:drafts"))));
+        let other = registry.clone();
+        assert!(waits_for(move || drop(
+            other.lock_for_update("db:posts", &[])
+        )));
+        drop(posts);
+    }
+
+    #[test]
+    fn test_updates_of_tables_without_indexes_lock_their_documents() {
+        let registry = Arc::new(IndexRegistry::default());
+        let keys = |keys: &[&str]| keys.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        let update = registry.lock_for_update("db:drafts", &keys(&["a", "b"]));
+        let other = registry.clone();
+        assert!(!waits_for(move || {
+            drop(other.lock_for_update("db:drafts", &keys(&["c"])));
+        }));
+        let other = registry.clone();
+        assert!(!waits_for(move || drop(other.lock_for_write("db:drafts"))));
+        let other = registry.clone();
+        assert!(waits_for(move || {
+            drop(other.lock_for_update("db:drafts", &keys(&["c", "b"])));
+        }));
+        drop(update);
+    }
+}
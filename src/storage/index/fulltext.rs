@@ -0,0 +1,385 @@
+//! Full-text indexes ranked with BM25.
+//!
+//! The text of a document's indexed fields is split into lowercase terms, optionally
+//! reduced to their English stems. The index keeps a posting for every term of every
+//! document, holding how often the term occurs and how long the document is, so a
+//! search reads only the postings of its own terms. Each document's terms are kept as
+//! well, to remove its postings when it changes.
+
+use super::field_value;
+use crate::ast::{Document, datum};
+use crate::storage::{Result, StorageError};
+use rocksdb::{
+    BoundColumnFamily, DBWithThreadMode, Direction, IteratorMode, MultiThreaded, ReadOptions,
+    WriteBatch,
+};
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Term frequency saturation of BM25.
+const K1: f64 = 1.2;
+
+/// Document length normalisation of BM25.
+const B: f64 = 0.75;
+
+/// Prefix of the postings, keyed by term and document key.
+const POSTING_PREFIX: u8 = b'p';
+
+/// Prefix of the terms of each indexed document.
+const DOCUMENT_PREFIX: u8 = b'd';
+
+/// Key of the index-wide figures BM25 needs.
+const STATS_KEY: &[u8] = b"s";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FullTextOptions {
+    /// Paths of the fields whose text is indexed.
+    pub fields: Vec<Vec<String>>,
+    /// Whether terms are reduced to their English stems.
+    pub stemming: bool,
+}
+
+/// Splits text into the terms it is indexed and searched by.
+pub struct Analyzer {
+    stemmer: Option<Stemmer>,
+}
+
+impl Analyzer {
+    pub fn new(stemming: bool) -> Self {
+        Self {
+            stemmer: stemming.then(|| Stemmer::create(Algorithm::English)),
+        }
+    }
+
+    /// Lowercase terms of `text`, split on anything other than letters and digits.
+    pub fn terms<'a>(&'a self, text: &'a str) -> impl Iterator<Item = String> + 'a {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                let word = word.to_lowercase();
+                match &self.stemmer {
+                    Some(stemmer) => stemmer.stem(&word).into_owned(),
+                    None => word,
+                }
+            })
+    }
+}
+
+/// The terms of an indexed document.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct DocumentTerms {
+    /// Number of terms in the document, counting repeats.
+    length: u32,
+    /// Distinct terms with the number of times each occurs.
+    terms: Vec<(String, u32)>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct IndexStats {
+    documents: u64,
+    terms: u64,
+}
+
+fn document_terms(analyzer: &Analyzer, fields: &[Vec<String>], doc: &Document) -> DocumentTerms {
+    fn collect(analyzer: &Analyzer, value: &datum::Value, counts: &mut BTreeMap<String, u32>) {
+        match value {
+            datum::Value::String(text) => {
+                for term in analyzer.terms(text) {
+                    *counts.entry(term).or_default() += 1;
+                }
+            }
+            datum::Value::Array(array) => {
+                for item in array.items.iter().filter_map(|item| item.value.as_ref()) {
+                    collect(analyzer, item, counts);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut counts = BTreeMap::new();
+    for path in fields {
+        if let Some(value) = field_value(doc, path) {
+            collect(analyzer, value, &mut counts);
+        }
+    }
+    DocumentTerms {
+        length: counts.values().sum(),
+        terms: counts.into_iter().collect(),
+    }
+}
+
+fn posting_prefix(term: &str) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(term.len() + 2);
+    prefix.push(POSTING_PREFIX);
+    prefix.extend_from_slice(term.as_bytes());
+    prefix.push(0);
+    prefix
+}
+
+fn posting_key(term: &str, key: &str) -> Vec<u8> {
+    let mut posting_key = posting_prefix(term);
+    posting_key.extend_from_slice(key.as_bytes());
+    posting_key
+}
+
+fn document_key(key: &str) -> Vec<u8> {
+    let mut document_key = Vec::with_capacity(key.len() + 1);
+    document_key.push(DOCUMENT_PREFIX);
+    document_key.extend_from_slice(key.as_bytes());
+    document_key
+}
+
+fn posting_value(frequency: u32, length: u32) -> [u8; 8] {
+    let mut value = [0; 8];
+    value[..4].copy_from_slice(&frequency.to_be_bytes());
+    value[4..].copy_from_slice(&length.to_be_bytes());
+    value
+}
+
+fn parse_posting_value(value: &[u8]) -> Result<(u32, u32)> {
+    let value: [u8; 8] = value
+        .try_into()
+        .map_err(|_| StorageError::Index("corrupt full-text posting".to_string()))?;
+    let (frequency, length) = value.split_at(4);
+    Ok((
+        u32::from_be_bytes(frequency.try_into().unwrap()),
+        u32::from_be_bytes(length.try_into().unwrap()),
+    ))
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(value: &[u8]) -> Result<T> {
+    let (decoded, _) = bincode::serde::decode_from_slice(value, bincode::config::standard())?;
+    Ok(decoded)
+}
+
+fn read_stats(
+    db: &DBWithThreadMode<MultiThreaded>,
+    cf: &Arc<BoundColumnFamily<'_>>,
+    read_opts: &ReadOptions,
+) -> Result<IndexStats> {
+    db.get_cf_opt(cf, STATS_KEY, read_opts)?
+        .map_or_else(|| Ok(IndexStats::default()), |value| decode(&value))
+}
+
+/// Updates a full-text index along with the documents of a write batch.
+pub struct IndexWriter<'a> {
+    db: &'a DBWithThreadMode<MultiThreaded>,
+    cf: Arc<BoundColumnFamily<'a>>,
+    analyzer: Analyzer,
+    fields: Vec<Vec<String>>,
+    stats: IndexStats,
+    /// Terms of the documents already written in this batch, which the database
+    /// doesn't hold yet.
+    written: HashMap<String, Option<DocumentTerms>>,
+}
+
+impl<'a> IndexWriter<'a> {
+    pub fn new(
+        db: &'a DBWithThreadMode<MultiThreaded>,
+        cf: Arc<BoundColumnFamily<'a>>,
+        options: &FullTextOptions,
+    ) -> Result<Self> {
+        let stats = read_stats(db, &cf, &ReadOptions::default())?;
+        Ok(Self {
+            db,
+            cf,
+            analyzer: Analyzer::new(options.stemming),
+            fields: options.fields.clone(),
+            stats,
+            written: HashMap::new(),
+        })
+    }
+
+    /// Replace the postings of the document at `key` with those of `doc`, or remove
+    /// them if it was deleted.
+    pub fn update(
+        &mut self,
+        batch: &mut WriteBatch,
+        key: &str,
+        doc: Option<&Document>,
+    ) -> Result<()> {
+        let previous = match self.written.get(key) {
+            Some(terms) => terms.clone(),
+            None => self
+                .db
+                .get_cf(&self.cf, document_key(key))?
+                .map(|value| decode::<DocumentTerms>(&value))
+                .transpose()?,
+        };
+        if let Some(previous) = previous {
+            for (term, _) in &previous.terms {
+                batch.delete_cf(&self.cf, posting_key(term, key));
+            }
+            self.stats.documents = self.stats.documents.saturating_sub(1);
+            self.stats.terms = self.stats.terms.saturating_sub(u64::from(previous.length));
+        }
+
+        let terms = doc
+            .map(|doc| document_terms(&self.analyzer, &self.fields, doc))
+            .filter(|terms| terms.length > 0);
+        match &terms {
+            Some(terms) => {
+                for (term, frequency) in &terms.terms {
+                    batch.put_cf(
+                        &self.cf,
+                        posting_key(term, key),
+                        posting_value(*frequency, terms.length),
+                    );
+                }
+                batch.put_cf(
+                    &self.cf,
+                    document_key(key),
+                    bincode::serde::encode_to_vec(terms, bincode::config::standard())?,
+                );
+                self.stats.documents += 1;
+                self.stats.terms += u64::from(terms.length);
+            }
+            None => batch.delete_cf(&self.cf, document_key(key)),
+        }
+
+        self.written.insert(key.to_string(), terms);
+        Ok(())
+    }
+
+    /// Add the index's updated figures to the batch.
+    pub fn finish(self, batch: &mut WriteBatch) -> Result<()> {
+        batch.put_cf(
+            &self.cf,
+            STATS_KEY,
+            bincode::serde::encode_to_vec(self.stats, bincode::config::standard())?,
+        );
+        Ok(())
+    }
+}
+
+/// Keys of the documents matching any term of `query`, with their BM25 scores, best
+/// first. Documents with equal scores are ordered by key.
+pub fn search(
+    db: &DBWithThreadMode<MultiThreaded>,
+    cf: &Arc<BoundColumnFamily<'_>>,
+    options: &FullTextOptions,
+    read_opts: &dyn Fn() -> ReadOptions,
+    query: &str,
+    limit: Option<usize>,
+) -> Result<Vec<(String, f64)>> {
+    let analyzer = Analyzer::new(options.stemming);
+    let mut terms: Vec<String> = analyzer.terms(query).collect();
+    terms.sort();
+    terms.dedup();
+
+    let stats = read_stats(db, cf, &read_opts())?;
+    if stats.documents == 0 {
+        return Ok(Vec::new());
+    }
+    let documents = stats.documents as f64;
+    let average_length = stats.terms as f64 / documents;
+
+    let mut scores: HashMap<String, f64> = HashMap::new();
+    for term in &terms {
+        let prefix = posting_prefix(term);
+        let mut postings = Vec::new();
+        let mode = IteratorMode::From(&prefix, Direction::Forward);
+        for res in db.iterator_cf_opt(cf, read_opts(), mode) {
+            let (posting_key, value) = res?;
+            let Some(key) = posting_key.strip_prefix(prefix.as_slice()) else {
+                break;
+            };
+            postings.push((
+                String::from_utf8(key.to_vec())?,
+                parse_posting_value(&value)?,
+            ));
+        }
+
+        let matching = postings.len() as f64;
+        let idf = (1.0 + (documents - matching + 0.5) / (matching + 0.5)).ln();
+        for (key, (frequency, length)) in postings {
+            let frequency = f64::from(frequency);
+            let norm = K1 * (1.0 - B + B * f64::from(length) / average_length);
+            *scores.entry(key).or_default() += idf * frequency * (K1 + 1.0) / (frequency + norm);
+        }
+    }
+
+    let mut results: Vec<(String, f64)> = scores.into_iter().collect();
+    results.sort_by(|(a_key, a_score), (b_key, b_score)| {
+        b_score.total_cmp(a_score).then_with(|| a_key.cmp(b_key))
+    });
+    if let Some(limit) = limit {
+        results.truncate(limit);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Datum;
+
+    fn string(text: &str) -> Datum {
+        Datum {
+            value: Some(datum::Value::String(text.to_string())),
+        }
+    }
+
+    #[test]
+    fn test_analyzer_terms() {
+        let analyzer = Analyzer::new(false);
+        let terms: Vec<_> = analyzer.terms("The quick-brown Fox, 42 times!").collect();
+        assert_eq!(terms, vec!["the", "quick", "brown", "fox", "42", "times"]);
+
+        let analyzer = Analyzer::new(true);
+        let terms: Vec<_> = analyzer.terms("Running runners ran").collect();
+        assert_eq!(terms, vec!["run", "runner", "ran"]);
+    }
+
+    #[test]
+    fn test_document_terms() {
+        let doc = Document::from([
+            ("title".to_string(), string("Rust and more Rust")),
+            (
+                "tags".to_string(),
+                Datum {
+                    value: Some(datum::Value::Array(crate::ast::DatumArray {
+                        items: vec![string("rust"), string("databases")],
+                        element_type: String::new(),
+                    })),
+                },
+            ),
+            (
+                "views".to_string(),
+                Datum {
+                    value: Some(datum::Value::Int(3)),
+                },
+            ),
+        ]);
+        let fields = vec![
+            vec!["title".to_string()],
+            vec!["tags".to_string()],
+            vec!["views".to_string()],
+            vec!["missing".to_string()],
+        ];
+
+        let terms = document_terms(&Analyzer::new(false), &fields, &doc);
+        assert_eq!(terms.length, 6);
+        assert_eq!(
+            terms.terms,
+            vec![
+                ("and".to_string(), 1),
+                ("databases".to_string(), 1),
+                ("more".to_string(), 1),
+                ("rust".to_string(), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_posting_keys() {
+        assert_eq!(posting_key("rust", "doc1"), b"prust\0doc1");
+        assert!(posting_key("rust", "doc1").starts_with(&posting_prefix("rust")));
+        assert!(!posting_key("rusty", "doc1").starts_with(&posting_prefix("rust")));
+        assert_eq!(parse_posting_value(&posting_value(3, 17)).unwrap(), (3, 17));
+        assert!(parse_posting_value(b"short").is_err());
+    }
+}
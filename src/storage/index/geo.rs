@@ -0,0 +1,304 @@
+//! Geospatial indexes backed by geohashes.
+//!
+//! A geohash names a cell of a grid over longitude and latitude, and each character
+//! added to it splits the cell into 32 smaller ones, so a cell contains exactly the
+//! cells whose geohashes it prefixes. A geometry is indexed under the cells of the
+//! finest grid that covers its bounding box in at most `MAX_COVERING_CELLS` cells. A
+//! lookup covers the searched area the same way and reads every entry in a cell that
+//! contains, or is contained by, one of its cells. The candidates it returns still have
+//! to be checked against their geometry.
+
+use super::field_value;
+use crate::ast::{Document, datum};
+use crate::geo::{BoundingBox, Shape};
+use crate::storage::Result;
+use rocksdb::{
+    BoundColumnFamily, DBWithThreadMode, Direction, IteratorMode, MultiThreaded, ReadOptions,
+    WriteBatch,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+/// Characters of a geohash, each encoding five bits.
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Length of the geohashes of the finest grid, whose cells are a few centimeters wide.
+const MAX_PRECISION: usize = 12;
+
+/// Most cells a geometry or a lookup is covered with, unless even the coarsest grid
+/// needs more.
+const MAX_COVERING_CELLS: usize = 16;
+
+/// Prefix of the entries, keyed by cell and document key.
+const CELL_PREFIX: u8 = b'c';
+
+/// Prefix of the cells of each indexed document.
+const DOCUMENT_PREFIX: u8 = b'd';
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeoOptions {
+    /// Path of the field holding the indexed geometry.
+    pub field: Vec<String>,
+}
+
+/// Geohash of the cell of the grid with `precision` characters holding the point.
+fn geohash(lon: f64, lat: f64, precision: usize) -> String {
+    let (mut lon_range, mut lat_range) = ((-180.0, 180.0), (-90.0, 90.0));
+    let mut hash = String::with_capacity(precision);
+    let mut bits = 0;
+    for bit in 0..precision * 5 {
+        let (range, value) = if bit % 2 == 0 {
+            (&mut lon_range, lon)
+        } else {
+            (&mut lat_range, lat)
+        };
+        let middle = (range.0 + range.1) / 2.0;
+        bits <<= 1;
+        if value >= middle {
+            bits |= 1;
+            range.0 = middle;
+        } else {
+            range.1 = middle;
+        }
+        if bit % 5 == 4 {
+            hash.push(BASE32[bits] as char);
+            bits = 0;
+        }
+    }
+    hash
+}
+
+/// Width and height in degrees of the cells of the grid with `precision` characters.
+fn cell_size(precision: usize) -> (f64, f64) {
+    let bits = precision as i32 * 5;
+    (
+        360.0 / 2f64.powi((bits + 1) / 2),
+        180.0 / 2f64.powi(bits / 2),
+    )
+}
+
+/// Columns and rows of the cells of a grid that a bounding box overlaps.
+fn cell_ranges(bbox: &BoundingBox, precision: usize) -> ((u64, u64), (u64, u64)) {
+    let (width, height) = cell_size(precision);
+    let column = |lon: f64| (((lon + 180.0) / width) as u64).min((360.0 / width) as u64 - 1);
+    let row = |lat: f64| (((lat + 90.0) / height) as u64).min((180.0 / height) as u64 - 1);
+    (
+        (column(bbox.min_lon), column(bbox.max_lon)),
+        (row(bbox.min_lat), row(bbox.max_lat)),
+    )
+}
+
+/// Geohashes of the cells covering a bounding box, from the finest grid that needs no
+/// more than `MAX_COVERING_CELLS` of them.
+pub fn covering(bbox: &BoundingBox) -> Vec<String> {
+    let count = |precision| {
+        let ((first_column, last_column), (first_row, last_row)) = cell_ranges(bbox, precision);
+        (last_column - first_column + 1) * (last_row - first_row + 1)
+    };
+    let precision = (1..=MAX_PRECISION)
+        .rev()
+        .find(|&precision| count(precision) <= MAX_COVERING_CELLS as u64)
+        .unwrap_or(1);
+
+    let (width, height) = cell_size(precision);
+    let ((first_column, last_column), (first_row, last_row)) = cell_ranges(bbox, precision);
+    let mut cells = Vec::new();
+    for column in first_column..=last_column {
+        for row in first_row..=last_row {
+            cells.push(geohash(
+                -180.0 + (column as f64 + 0.5) * width,
+                -90.0 + (row as f64 + 0.5) * height,
+                precision,
+            ));
+        }
+    }
+    cells
+}
+
+/// The geometry a document holds at `field`, if it holds a valid one.
+pub fn document_shape(field: &[String], doc: &Document) -> Option<Shape> {
+    match field_value(doc, field)? {
+        datum::Value::Geometry(geometry) => Shape::try_from(geometry).ok(),
+        _ => None,
+    }
+}
+
+fn cell_prefix(cell: &str) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(cell.len() + 1);
+    prefix.push(CELL_PREFIX);
+    prefix.extend_from_slice(cell.as_bytes());
+    prefix
+}
+
+fn entry_key(cell: &str, key: &str) -> Vec<u8> {
+    let mut entry_key = cell_prefix(cell);
+    entry_key.push(0);
+    entry_key.extend_from_slice(key.as_bytes());
+    entry_key
+}
+
+fn document_key(key: &str) -> Vec<u8> {
+    let mut document_key = Vec::with_capacity(key.len() + 1);
+    document_key.push(DOCUMENT_PREFIX);
+    document_key.extend_from_slice(key.as_bytes());
+    document_key
+}
+
+fn decode_cells(value: &[u8]) -> Result<Vec<String>> {
+    let (cells, _) = bincode::serde::decode_from_slice(value, bincode::config::standard())?;
+    Ok(cells)
+}
+
+/// Updates a geospatial index along with the documents of a write batch.
+pub struct IndexWriter<'a> {
+    db: &'a DBWithThreadMode<MultiThreaded>,
+    cf: Arc<BoundColumnFamily<'a>>,
+    field: Vec<String>,
+    /// Cells of the documents already written in this batch, which the database
+    /// doesn't hold yet.
+    written: HashMap<String, Vec<String>>,
+}
+
+impl<'a> IndexWriter<'a> {
+    pub fn new(
+        db: &'a DBWithThreadMode<MultiThreaded>,
+        cf: Arc<BoundColumnFamily<'a>>,
+        options: &GeoOptions,
+    ) -> Self {
+        Self {
+            db,
+            cf,
+            field: options.field.clone(),
+            written: HashMap::new(),
+        }
+    }
+
+    /// Replace the entries of the document at `key` with those of `doc`, or remove
+    /// them if it was deleted.
+    pub fn update(
+        &mut self,
+        batch: &mut WriteBatch,
+        key: &str,
+        doc: Option<&Document>,
+    ) -> Result<()> {
+        let previous = match self.written.get(key) {
+            Some(cells) => cells.clone(),
+            None => self
+                .db
+                .get_cf(&self.cf, document_key(key))?
+                .map(|value| decode_cells(&value))
+                .transpose()?
+                .unwrap_or_default(),
+        };
+        for cell in &previous {
+            batch.delete_cf(&self.cf, entry_key(cell, key));
+        }
+
+        let cells = doc
+            .and_then(|doc| document_shape(&self.field, doc))
+            .map(|shape| covering(&shape.bounding_box()))
+            .unwrap_or_default();
+        for cell in &cells {
+            batch.put_cf(&self.cf, entry_key(cell, key), []);
+        }
+        if cells.is_empty() {
+            batch.delete_cf(&self.cf, document_key(key));
+        } else {
+            batch.put_cf(
+                &self.cf,
+                document_key(key),
+                bincode::serde::encode_to_vec(&cells, bincode::config::standard())?,
+            );
+        }
+
+        self.written.insert(key.to_string(), cells);
+        Ok(())
+    }
+}
+
+/// Keys of the documents whose geometry may overlap the bounding box, in key order.
+pub fn candidates(
+    db: &DBWithThreadMode<MultiThreaded>,
+    cf: &Arc<BoundColumnFamily<'_>>,
+    read_opts: &dyn Fn() -> ReadOptions,
+    bbox: &BoundingBox,
+) -> Result<BTreeSet<String>> {
+    // The cells inside each covering cell, then the cells around them
+    let cells = covering(bbox);
+    let mut prefixes: BTreeSet<Vec<u8>> = cells.iter().map(|cell| cell_prefix(cell)).collect();
+    for cell in &cells {
+        for end in 1..cell.len() {
+            let mut prefix = cell_prefix(&cell[..end]);
+            prefix.push(0);
+            prefixes.insert(prefix);
+        }
+    }
+
+    let mut keys = BTreeSet::new();
+    for prefix in &prefixes {
+        let mode = IteratorMode::From(prefix, Direction::Forward);
+        for res in db.iterator_cf_opt(cf, read_opts(), mode) {
+            let (entry_key, _) = res?;
+            if !entry_key.starts_with(prefix) {
+                break;
+            }
+            if let Some(separator) = entry_key.iter().position(|&byte| byte == 0) {
+                keys.insert(String::from_utf8(entry_key[separator + 1..].to_vec())?);
+            }
+        }
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geohash() {
+        assert_eq!(geohash(-5.6, 42.6, 5), "ezs42");
+        assert_eq!(geohash(10.40744, 57.64911, 11), "u4pruydqqvj");
+        assert!(geohash(-5.6, 42.6, 7).starts_with(&geohash(-5.6, 42.6, 4)));
+    }
+
+    #[test]
+    fn test_covering() {
+        let point = BoundingBox {
+            min_lon: 10.0,
+            min_lat: 50.0,
+            max_lon: 10.0,
+            max_lat: 50.0,
+        };
+        assert_eq!(covering(&point), vec![geohash(10.0, 50.0, MAX_PRECISION)]);
+
+        let area = BoundingBox {
+            min_lon: 10.0,
+            min_lat: 50.0,
+            max_lon: 10.5,
+            max_lat: 50.5,
+        };
+        let cells = covering(&area);
+        assert!(!cells.is_empty() && cells.len() <= MAX_COVERING_CELLS);
+        assert!(
+            cells
+                .iter()
+                .any(|cell| geohash(10.2, 50.2, 8).starts_with(cell))
+        );
+
+        let world = BoundingBox {
+            min_lon: -180.0,
+            min_lat: -90.0,
+            max_lon: 180.0,
+            max_lat: 90.0,
+        };
+        assert_eq!(covering(&world).len(), 32);
+    }
+
+    #[test]
+    fn test_entry_keys() {
+        assert_eq!(entry_key("u4pr", "doc1"), b"cu4pr\0doc1");
+        assert!(entry_key("u4pruy", "doc1").starts_with(&cell_prefix("u4pr")));
+        assert_eq!(document_key("doc1"), b"ddoc1");
+    }
+}
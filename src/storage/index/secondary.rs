@@ -0,0 +1,739 @@
+//! Secondary indexes keyed by the values of fields or of an expression.
+//!
+//! Each entry is keyed by an index value in an order-preserving encoding followed by
+//! the document key, so the entries of equal values are neighbours and a range of
+//! values is a range of keys. A compound index keys each document by the array of its
+//! fields' values, and a multi index files a document under each element of an array
+//! value. Values are ordered as the evaluator compares them, types in turn and arrays
+//! and objects element by element. Documents without a value, or whose value is null, a
+//! geometry or NaN, aren't indexed. Numbers are ordered exactly whatever their type, so
+//! `1`, `1.0` and the decimal `1.00` are the same value, and times are ordered by instant
+//! whatever their offset. Each document's encoded values are kept as well, to remove
+//! its entries when it changes. A unique index refuses a write that would file two
+//! documents under the same value; writes to indexed tables are serialized, so the
+//! check and the write can't be interleaved with another writer's.
+
+use super::field_value;
+use crate::ast::{Datum, Document, Expression, datum};
+use crate::decimal::{Decimal, ExactNumber};
+use crate::evaluator::expression::ExpressionEvaluator;
+use crate::storage::{Result, StorageError};
+use crate::uuid::UUID_LEN;
+use rocksdb::{
+    BoundColumnFamily, DBWithThreadMode, Direction, IteratorMode, MultiThreaded, ReadOptions,
+    WriteBatch,
+};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+
+/// Prefix of the entries, keyed by encoded value and document key.
+const ENTRY_PREFIX: u8 = b'e';
+
+/// Prefix of the encoded values of each indexed document.
+const DOCUMENT_PREFIX: u8 = b'd';
+
+// Tags starting each encoded value, in the order values sort in. Zero is left free to
+// end arrays, so that an array sorts before any longer array it begins.
+const NULL_TAG: u8 = 0x10;
+const FALSE_TAG: u8 = 0x20;
+const TRUE_TAG: u8 = 0x21;
+const NUMBER_TAG: u8 = 0x30;
+const TIME_TAG: u8 = 0x38;
+const STRING_TAG: u8 = 0x40;
+const UUID_TAG: u8 = 0x48;
+const BINARY_TAG: u8 = 0x50;
+const ARRAY_TAG: u8 = 0x60;
+const OBJECT_TAG: u8 = 0x70;
+const ARRAY_END: u8 = 0x00;
+
+// Markers following a number's float, for whether it is the number
+const EXACT_NUMBER: u8 = 0x00;
+const INEXACT_NUMBER: u8 = 0x01;
+
+/// What the entries of a secondary index are keyed by.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IndexKey {
+    /// The value of one field, or the array of the values of several.
+    Fields(Vec<Vec<String>>),
+    /// The value of an expression evaluated against the document.
+    Expression(Expression),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SecondaryOptions {
+    pub key: IndexKey,
+    /// Whether a document is filed under each element of an array value.
+    pub multi: bool,
+    /// Whether at most one document may be filed under each value.
+    pub unique: bool,
+}
+
+impl SecondaryOptions {
+    pub fn validate(&self) -> Result<()> {
+        match &self.key {
+            IndexKey::Fields(fields) => {
+                if self.multi && fields.len() > 1 {
+                    return Err(StorageError::Index(
+                        "a multi index covers a single field or an expression".to_string(),
+                    ));
+                }
+                super::validate_fields(fields)
+            }
+            IndexKey::Expression(expression) if expression.expr.is_none() => Err(
+                StorageError::Index("an index expression can't be empty".to_string()),
+            ),
+            IndexKey::Expression(_) => Ok(()),
+        }
+    }
+
+    /// What the index is keyed by, as named in errors.
+    pub fn describe_key(&self) -> String {
+        match &self.key {
+            IndexKey::Fields(fields) => fields
+                .iter()
+                .map(|field| field.join("."))
+                .collect::<Vec<_>>()
+                .join(", "),
+            IndexKey::Expression(_) => "expression".to_string(),
+        }
+    }
+
+    /// The value a document is indexed by, if it has one.
+    fn document_value(&self, doc: &Document) -> Option<datum::Value> {
+        let value = match &self.key {
+            IndexKey::Fields(fields) => match fields.as_slice() {
+                [field] => field_value(doc, field)?.clone(),
+                fields => {
+                    let items = fields
+                        .iter()
+                        .map(|field| {
+                            let value = field_value(doc, field)?;
+                            (!matches!(value, datum::Value::Null(_))).then(|| Datum {
+                                value: Some(value.clone()),
+                            })
+                        })
+                        .collect::<Option<Vec<_>>>()?;
+                    datum::Value::Array(crate::ast::DatumArray {
+                        items,
+                        element_type: String::new(),
+                    })
+                }
+            },
+            IndexKey::Expression(expression) => {
+                ExpressionEvaluator::new()
+                    .evaluate_expression(expression, &Datum::from(doc.clone()))
+                    .ok()?
+                    .value?
+            }
+        };
+        (!matches!(value, datum::Value::Null(_))).then_some(value)
+    }
+
+    /// The encoded values a document is filed under, without repeats.
+    fn document_entries(&self, doc: &Document) -> Vec<Vec<u8>> {
+        let Some(value) = self.document_value(doc) else {
+            return Vec::new();
+        };
+        let values = match value {
+            datum::Value::Array(array) if self.multi => array
+                .items
+                .into_iter()
+                .filter_map(|item| item.value)
+                .filter(|item| !matches!(item, datum::Value::Null(_)))
+                .collect(),
+            value => vec![value],
+        };
+
+        let mut seen = HashSet::new();
+        values
+            .iter()
+            .filter_map(encode_value)
+            .filter(|encoded| seen.insert(encoded.clone()))
+            .collect()
+    }
+}
+
+/// Encode a value so that encoded values sort as the values do, or `None` if it can't
+/// be indexed.
+pub fn encode_value(value: &datum::Value) -> Option<Vec<u8>> {
+    let mut encoded = Vec::new();
+    encode_into(value, &mut encoded)?;
+    Some(encoded)
+}
+
+fn encode_into(value: &datum::Value, out: &mut Vec<u8>) -> Option<()> {
+    match value {
+        datum::Value::Null(_) => out.push(NULL_TAG),
+        datum::Value::Bool(false) => out.push(FALSE_TAG),
+        datum::Value::Bool(true) => out.push(TRUE_TAG),
+        datum::Value::Int(value) => {
+            let float = *value as f64;
+            if float as i128 == i128::from(*value) {
+                encode_number(float, out)?
+            } else {
+                encode_exact_number(float, &ExactNumber::from_i128((*value).into()), out)?
+            }
+        }
+        datum::Value::Float(value) => encode_number(*value, out)?,
+        datum::Value::Decimal(value) => {
+            let decimal = Decimal::parse(&value.value).ok()?;
+            encode_exact_number(decimal.to_f64(), &ExactNumber::from(&decimal), out)?
+        }
+        datum::Value::Uuid(uuid) => {
+            if uuid.value.len() != UUID_LEN {
+                return None;
+            }
+            out.push(UUID_TAG);
+            out.extend_from_slice(&uuid.value);
+        }
+        datum::Value::Time(time) => {
+            // Flip the sign bit, so the bits sort as the instants do
+            out.push(TIME_TAG);
+            out.extend_from_slice(&((time.epoch_micros as u64) ^ 1 << 63).to_be_bytes());
+        }
+        datum::Value::String(value) => encode_bytes(STRING_TAG, value.as_bytes(), out),
+        datum::Value::Binary(value) => encode_bytes(BINARY_TAG, value, out),
+        datum::Value::Array(array) => {
+            out.push(ARRAY_TAG);
+            for item in &array.items {
+                encode_into(item.value.as_ref()?, out)?;
+            }
+            out.push(ARRAY_END);
+        }
+        datum::Value::Object(object) => {
+            // Fields in key order, each key followed by its value
+            let mut fields: Vec<_> = object.fields.iter().collect();
+            fields.sort_unstable_by(|a, b| a.0.cmp(b.0));
+            out.push(OBJECT_TAG);
+            for (key, value) in fields {
+                encode_bytes(STRING_TAG, key.as_bytes(), out);
+                encode_into(value.value.as_ref()?, out)?;
+            }
+            out.push(ARRAY_END);
+        }
+        datum::Value::Geometry(_) => return None,
+    }
+    Some(())
+}
+
+fn encode_number(value: f64, out: &mut Vec<u8>) -> Option<()> {
+    if value.is_nan() {
+        return None;
+    }
+    // Flip the sign bit of positive numbers and every bit of negative ones, so the
+    // bits sort as the numbers do. Adding zero turns -0 into 0.
+    let bits = (value + 0.0).to_bits();
+    let bits = if bits >> 63 == 0 {
+        bits | 1 << 63
+    } else {
+        !bits
+    };
+    out.push(NUMBER_TAG);
+    out.extend_from_slice(&bits.to_be_bytes());
+    out.push(EXACT_NUMBER);
+    Some(())
+}
+
+/// Encode a number a float may not hold exactly, as the greatest float not above it
+/// followed, if that isn't the number, by the number itself, so that the numbers
+/// between two floats sort after the lower one and by their exact values.
+fn encode_exact_number(nearest: f64, exact: &ExactNumber, out: &mut Vec<u8>) -> Option<()> {
+    let below = match exact.cmp(&ExactNumber::from_f64(nearest)?) {
+        Ordering::Equal => return encode_number(nearest, out),
+        Ordering::Less => nearest.next_down(),
+        Ordering::Greater => nearest,
+    };
+    encode_number(below, out)?;
+    out.pop();
+    out.push(INEXACT_NUMBER);
+    exact.encode_key(out);
+    Some(())
+}
+
+/// Bytes with every zero escaped as zero and 0xff, ended by zero and one, so that no
+/// encoding is a prefix of another and shorter strings sort first.
+fn encode_bytes(tag: u8, bytes: &[u8], out: &mut Vec<u8>) {
+    out.push(tag);
+    for &byte in bytes {
+        out.push(byte);
+        if byte == 0 {
+            out.push(0xff);
+        }
+    }
+    out.extend_from_slice(&[0, 1]);
+}
+
+/// Encode a value looked up in an index.
+pub fn encode_lookup(value: &Datum) -> Result<Vec<u8>> {
+    value
+        .value
+        .as_ref()
+        .and_then(encode_value)
+        .ok_or_else(|| StorageError::Index(format!("{value} can't be looked up in an index")))
+}
+
+fn entry_prefix(encoded: &[u8]) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(encoded.len() + 1);
+    prefix.push(ENTRY_PREFIX);
+    prefix.extend_from_slice(encoded);
+    prefix
+}
+
+/// The first key past every key starting with `prefix`.
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while end.last() == Some(&0xff) {
+        end.pop();
+    }
+    // Entry prefixes start with a byte other than 0xff, so some byte is left
+    *end.last_mut().unwrap() += 1;
+    end
+}
+
+fn entry_key(encoded: &[u8], key: &str) -> Vec<u8> {
+    let mut entry_key = entry_prefix(encoded);
+    entry_key.extend_from_slice(key.as_bytes());
+    entry_key
+}
+
+fn document_key(key: &str) -> Vec<u8> {
+    let mut document_key = Vec::with_capacity(key.len() + 1);
+    document_key.push(DOCUMENT_PREFIX);
+    document_key.extend_from_slice(key.as_bytes());
+    document_key
+}
+
+fn decode_entries(value: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let (entries, _) = bincode::serde::decode_from_slice(value, bincode::config::standard())?;
+    Ok(entries)
+}
+
+/// Updates a secondary index along with the documents of a write batch.
+pub struct IndexWriter<'a> {
+    db: &'a DBWithThreadMode<MultiThreaded>,
+    cf: Arc<BoundColumnFamily<'a>>,
+    name: String,
+    options: SecondaryOptions,
+    /// Encoded values of the documents already written in this batch, which the
+    /// database doesn't hold yet.
+    written: HashMap<String, Vec<Vec<u8>>>,
+    /// Documents of this batch filed under each value of a unique index, checked
+    /// once the batch is complete so that documents may trade values.
+    claims: HashMap<Vec<u8>, Vec<String>>,
+}
+
+impl<'a> IndexWriter<'a> {
+    pub fn new(
+        db: &'a DBWithThreadMode<MultiThreaded>,
+        cf: Arc<BoundColumnFamily<'a>>,
+        name: &str,
+        options: &SecondaryOptions,
+    ) -> Self {
+        Self {
+            db,
+            cf,
+            name: name.to_string(),
+            options: options.clone(),
+            written: HashMap::new(),
+            claims: HashMap::new(),
+        }
+    }
+
+    /// Replace the entries of the document at `key` with those of `doc`, or remove
+    /// them if it was deleted.
+    pub fn update(
+        &mut self,
+        batch: &mut WriteBatch,
+        key: &str,
+        doc: Option<&Document>,
+    ) -> Result<()> {
+        let previous = match self.written.get(key) {
+            Some(entries) => entries.clone(),
+            None => self
+                .db
+                .get_cf(&self.cf, document_key(key))?
+                .map(|value| decode_entries(&value))
+                .transpose()?
+                .unwrap_or_default(),
+        };
+        for encoded in &previous {
+            batch.delete_cf(&self.cf, entry_key(encoded, key));
+            if let Some(claimants) = self.claims.get_mut(encoded) {
+                claimants.retain(|claimant| claimant != key);
+            }
+        }
+
+        let entries = doc
+            .map(|doc| self.options.document_entries(doc))
+            .unwrap_or_default();
+        for encoded in &entries {
+            if self.options.unique {
+                self.claims
+                    .entry(encoded.clone())
+                    .or_default()
+                    .push(key.to_string());
+            }
+            batch.put_cf(&self.cf, entry_key(encoded, key), key.as_bytes());
+        }
+        if entries.is_empty() {
+            if !previous.is_empty() {
+                batch.delete_cf(&self.cf, document_key(key));
+            }
+        } else {
+            batch.put_cf(
+                &self.cf,
+                document_key(key),
+                bincode::serde::encode_to_vec(&entries, bincode::config::standard())?,
+            );
+        }
+
+        self.written.insert(key.to_string(), entries);
+        Ok(())
+    }
+
+    /// Refuse the batch if it leaves two documents filed under a value of a unique
+    /// index, naming the document already holding the value, or the first of the
+    /// batch to claim it.
+    pub fn finish(&self) -> Result<()> {
+        for (encoded, claimants) in &self.claims {
+            let Some(claimant) = claimants.first() else {
+                continue;
+            };
+            let holder = match claimants.get(1) {
+                Some(_) => Some(claimant.clone()),
+                None => self.holder(encoded)?,
+            };
+            if let Some(key) = holder {
+                return Err(StorageError::ConstraintViolation {
+                    index: self.name.clone(),
+                    field: self.options.describe_key(),
+                    key,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// A document outside this batch filed under an encoded value.
+    fn holder(&self, encoded: &[u8]) -> Result<Option<String>> {
+        let prefix = entry_prefix(encoded);
+        let mode = IteratorMode::From(&prefix, Direction::Forward);
+        for res in self
+            .db
+            .iterator_cf_opt(&self.cf, ReadOptions::default(), mode)
+        {
+            let (entry_key, value) = res?;
+            if !entry_key.starts_with(&prefix) {
+                break;
+            }
+            let key = String::from_utf8(value.to_vec())?;
+            if !self.written.contains_key(&key) {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Keys of the documents with an entry from `start` up to, but not including, `end`,
+/// in index order, each once.
+fn scan(
+    db: &DBWithThreadMode<MultiThreaded>,
+    cf: &Arc<BoundColumnFamily<'_>>,
+    read_opts: &dyn Fn() -> ReadOptions,
+    start: &[u8],
+    end: &[u8],
+    seen: &mut BTreeSet<String>,
+    keys: &mut Vec<String>,
+) -> Result<()> {
+    let mode = IteratorMode::From(start, Direction::Forward);
+    for res in db.iterator_cf_opt(cf, read_opts(), mode) {
+        let (entry_key, value) = res?;
+        if entry_key.as_ref() >= end {
+            break;
+        }
+        let key = String::from_utf8(value.to_vec())?;
+        if seen.insert(key.clone()) {
+            keys.push(key);
+        }
+    }
+    Ok(())
+}
+
+/// Keys of the documents filed under any of the encoded `values`, in the order of the
+/// values, each once.
+pub fn get_all(
+    db: &DBWithThreadMode<MultiThreaded>,
+    cf: &Arc<BoundColumnFamily<'_>>,
+    read_opts: &dyn Fn() -> ReadOptions,
+    values: &[Vec<u8>],
+) -> Result<Vec<String>> {
+    let (mut seen, mut keys) = (BTreeSet::new(), Vec::new());
+    for encoded in values {
+        let prefix = entry_prefix(encoded);
+        scan(
+            db,
+            cf,
+            read_opts,
+            &prefix,
+            &prefix_end(&prefix),
+            &mut seen,
+            &mut keys,
+        )?;
+    }
+    Ok(keys)
+}
+
+/// Keys of the documents filed under a value from the encoded `lower` up to, but not
+/// including, the encoded `upper`, in index order, each once.
+pub fn between(
+    db: &DBWithThreadMode<MultiThreaded>,
+    cf: &Arc<BoundColumnFamily<'_>>,
+    read_opts: &dyn Fn() -> ReadOptions,
+    lower: Option<&[u8]>,
+    upper: Option<&[u8]>,
+) -> Result<Vec<String>> {
+    let start = entry_prefix(lower.unwrap_or_default());
+    let end = upper.map_or_else(|| vec![ENTRY_PREFIX + 1], entry_prefix);
+    let (mut seen, mut keys) = (BTreeSet::new(), Vec::new());
+    scan(db, cf, read_opts, &start, &end, &mut seen, &mut keys)?;
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{DatumArray, DatumObject, NullValue, Time, Uuid};
+    use crate::evaluator::utils::compare_values;
+
+    fn array(items: Vec<datum::Value>) -> datum::Value {
+        datum::Value::Array(DatumArray {
+            items: items
+                .into_iter()
+                .map(|value| Datum { value: Some(value) })
+                .collect(),
+            element_type: String::new(),
+        })
+    }
+
+    fn string(value: &str) -> datum::Value {
+        datum::Value::String(value.to_string())
+    }
+
+    fn object(fields: Vec<(&str, datum::Value)>) -> datum::Value {
+        datum::Value::Object(DatumObject {
+            fields: fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), Datum { value: Some(value) }))
+                .collect(),
+        })
+    }
+
+    fn decimal(value: &str) -> datum::Value {
+        datum::Value::Decimal(crate::ast::Decimal {
+            value: value.to_string(),
+        })
+    }
+
+    fn uuid(text: &str) -> datum::Value {
+        datum::Value::Uuid(crate::uuid::parse(text).unwrap())
+    }
+
+    fn time(epoch_micros: i64, offset_minutes: i32) -> datum::Value {
+        datum::Value::Time(Time {
+            epoch_micros,
+            offset_minutes,
+        })
+    }
+
+    #[test]
+    fn test_encoding_preserves_order() {
+        let ordered = vec![
+            datum::Value::Null(NullValue::NullValue.into()),
+            datum::Value::Bool(false),
+            datum::Value::Bool(true),
+            datum::Value::Float(f64::NEG_INFINITY),
+            datum::Value::Int(i64::MIN + 1),
+            datum::Value::Int(-10),
+            decimal("-0.5000000000000000000001"),
+            datum::Value::Float(-0.5),
+            decimal("-0.4999999999999999999999"),
+            datum::Value::Int(0),
+            decimal("0.1"),
+            datum::Value::Float(0.1),
+            decimal("0.1000000000000000056"),
+            datum::Value::Float(0.5),
+            datum::Value::Int(10),
+            datum::Value::Float(9_007_199_254_740_992.0),
+            datum::Value::Int(9_007_199_254_740_993),
+            datum::Value::Float(9_007_199_254_740_994.0),
+            datum::Value::Int(i64::MAX - 1),
+            datum::Value::Int(i64::MAX),
+            datum::Value::Float(9_223_372_036_854_775_808.0),
+            decimal("99999999999999999999999999999999999999"),
+            datum::Value::Float(1e300),
+            time(i64::MIN, 0),
+            time(-1, 0),
+            time(0, 60),
+            time(1, -60),
+            string(""),
+            string("a"),
+            string("a\0"),
+            string("ab"),
+            string("b"),
+            uuid("00000000-0000-0000-0000-000000000001"),
+            uuid("0a000000-0000-0000-0000-000000000000"),
+            datum::Value::Binary(vec![0, 1]),
+            array(vec![]),
+            array(vec![string("a")]),
+            array(vec![string("a"), datum::Value::Int(1)]),
+            array(vec![string("a"), datum::Value::Int(2)]),
+            array(vec![string("b")]),
+            object(vec![]),
+            object(vec![("a", datum::Value::Int(1))]),
+            object(vec![
+                ("a", datum::Value::Int(1)),
+                ("b", datum::Value::Int(0)),
+            ]),
+            object(vec![("a", datum::Value::Int(2))]),
+            object(vec![("b", datum::Value::Int(0))]),
+        ];
+        let encoded: Vec<Vec<u8>> = ordered
+            .iter()
+            .map(|value| encode_value(value).unwrap())
+            .collect();
+        for pair in encoded.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} >= {:?}", pair[0], pair[1]);
+        }
+
+        // The encoding orders values as the evaluator compares them
+        for (a, a_encoded) in ordered.iter().zip(&encoded) {
+            for (b, b_encoded) in ordered.iter().zip(&encoded) {
+                let (a, b) = (
+                    Datum {
+                        value: Some(a.clone()),
+                    },
+                    Datum {
+                        value: Some(b.clone()),
+                    },
+                );
+                assert_eq!(
+                    compare_values(&a, &b),
+                    a_encoded.cmp(b_encoded),
+                    "{a} vs {b}"
+                );
+            }
+        }
+
+        assert_eq!(
+            encode_value(&datum::Value::Int(1)),
+            encode_value(&datum::Value::Float(1.0))
+        );
+        assert_eq!(
+            encode_value(&datum::Value::Float(-0.0)),
+            encode_value(&datum::Value::Int(0))
+        );
+        assert_eq!(encode_value(&time(7, 0)), encode_value(&time(7, 120)));
+        assert_eq!(
+            encode_value(&decimal("1.50")),
+            encode_value(&datum::Value::Float(1.5))
+        );
+        assert_eq!(
+            encode_value(&decimal("-7.000")),
+            encode_value(&datum::Value::Int(-7))
+        );
+        assert_eq!(encode_value(&decimal("1.5.0")), None);
+        assert_eq!(
+            encode_value(&datum::Value::Uuid(Uuid { value: vec![0; 15] })),
+            None
+        );
+        assert_eq!(encode_value(&datum::Value::Float(f64::NAN)), None);
+        assert_eq!(
+            encode_value(&datum::Value::Geometry(Default::default())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_document_entries() {
+        let doc = Document::from([
+            (
+                "tenant".to_string(),
+                Datum {
+                    value: Some(string("acme")),
+                },
+            ),
+            (
+                "created".to_string(),
+                Datum {
+                    value: Some(datum::Value::Int(5)),
+                },
+            ),
+            (
+                "tags".to_string(),
+                Datum {
+                    value: Some(array(vec![string("a"), string("b"), string("a")])),
+                },
+            ),
+        ]);
+        let fields = |names: &[&str]| {
+            IndexKey::Fields(names.iter().map(|name| vec![name.to_string()]).collect())
+        };
+
+        let compound = SecondaryOptions {
+            key: fields(&["tenant", "created"]),
+            multi: false,
+            unique: false,
+        };
+        assert_eq!(
+            compound.document_entries(&doc),
+            vec![encode_value(&array(vec![string("acme"), datum::Value::Int(5)])).unwrap()]
+        );
+        let missing = SecondaryOptions {
+            key: fields(&["tenant", "deleted"]),
+            multi: false,
+            unique: false,
+        };
+        assert!(missing.document_entries(&doc).is_empty());
+
+        let multi = SecondaryOptions {
+            key: fields(&["tags"]),
+            multi: true,
+            unique: false,
+        };
+        assert_eq!(
+            multi.document_entries(&doc),
+            vec![
+                encode_value(&string("a")).unwrap(),
+                encode_value(&string("b")).unwrap()
+            ]
+        );
+        let whole = SecondaryOptions {
+            key: fields(&["tags"]),
+            multi: false,
+            unique: false,
+        };
+        assert_eq!(whole.document_entries(&doc).len(), 1);
+
+        assert!(
+            SecondaryOptions {
+                key: fields(&["tenant", "tags"]),
+                multi: true,
+                unique: false,
+            }
+            .validate()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_entry_keys() {
+        let encoded = encode_value(&string("rust")).unwrap();
+        assert_eq!(encoded, b"\x40rust\0\x01");
+        assert_eq!(entry_key(&encoded, "doc1"), b"e\x40rust\0\x01doc1");
+        assert!(entry_key(&encoded, "doc1").starts_with(&entry_prefix(&encoded)));
+        assert_eq!(document_key("doc1"), b"ddoc1");
+    }
+}
@@ -0,0 +1,672 @@
+//! Vector indexes for nearest neighbour search.
+//!
+//! The index is an inverted file: its vectors are split into lists, each around a
+//! centroid, and a search compares the query only with the vectors of the lists whose
+//! centroids are nearest to it, so it is approximate: a neighbour filed in a list it
+//! doesn't probe is missed. Every vector joins the list of the nearest centroid. Until
+//! the index holds enough vectors to train on, the first `lists` distinct ones are the
+//! centroids. From then on the centroids are trained with k-means on a sample of the
+//! indexed vectors, when the index is built and again each time it has doubled since,
+//! and the vectors whose nearest centroid changed are filed again. Each document's list
+//! is kept as well, to remove its entry when it changes.
+
+use super::field_value;
+use crate::ast::{Document, datum};
+use crate::storage::{Result, StorageError};
+use rocksdb::{
+    BoundColumnFamily, DBWithThreadMode, Direction, IteratorMode, MultiThreaded, ReadOptions,
+    WriteBatch,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Largest number of dimensions an indexed vector may have.
+pub const MAX_DIMENSION: u32 = 4096;
+
+/// Largest number of lists an index may split its vectors into.
+pub const MAX_LISTS: u32 = 4096;
+
+/// Lists the vectors are split into unless the index asks for another number.
+pub const DEFAULT_LISTS: u32 = 32;
+
+/// Lists a search without a filter probes unless it asks for another number.
+pub const DEFAULT_PROBES: u32 = 4;
+
+/// Prefix of the entries, keyed by list and document key.
+const ENTRY_PREFIX: u8 = b'l';
+
+/// Prefix of the list of each indexed document.
+const DOCUMENT_PREFIX: u8 = b'd';
+
+/// Key of the centroids of the lists.
+const CENTROIDS_KEY: &[u8] = b"c";
+
+/// Key of the number of indexed vectors.
+const COUNT_KEY: &[u8] = b"n";
+
+/// Vectors per list an index must hold before its centroids are trained.
+const MIN_TRAINING_VECTORS_PER_LIST: u64 = 16;
+
+/// Vectors per list the centroids are trained on at most.
+const TRAINING_SAMPLE_PER_LIST: usize = 256;
+
+/// Number of vectors past which an index keeps its centroids, as training them again
+/// would file too many vectors again in a single write.
+const MAX_RETRAINING_VECTORS: u64 = 1 << 20;
+
+/// Rounds of k-means the centroids are trained with at most.
+const TRAINING_ITERATIONS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Metric {
+    /// One minus the cosine of the angle between the vectors.
+    Cosine,
+    /// Euclidean distance.
+    L2,
+    /// Negated dot product, so that nearer vectors have smaller distances.
+    Dot,
+}
+
+impl Metric {
+    pub fn distance(self, a: &[f32], b: &[f32]) -> f64 {
+        let dot = || {
+            a.iter()
+                .zip(b)
+                .map(|(x, y)| f64::from(*x) * f64::from(*y))
+                .sum::<f64>()
+        };
+        match self {
+            Self::Cosine => 1.0 - dot() / (norm(a) * norm(b)),
+            Self::L2 => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (f64::from(*x) - f64::from(*y)).powi(2))
+                .sum::<f64>()
+                .sqrt(),
+            Self::Dot => -dot(),
+        }
+    }
+}
+
+fn norm(vector: &[f32]) -> f64 {
+    vector
+        .iter()
+        .map(|x| f64::from(*x).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorOptions {
+    /// Path of the field holding the indexed vector.
+    pub field: Vec<String>,
+    pub dimension: u32,
+    pub metric: Metric,
+    pub lists: u32,
+}
+
+impl VectorOptions {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=MAX_DIMENSION).contains(&self.dimension) {
+            return Err(StorageError::Index(format!(
+                "a vector index must have between 1 and {MAX_DIMENSION} dimensions, got {}",
+                self.dimension
+            )));
+        }
+        if !(1..=MAX_LISTS).contains(&self.lists) {
+            return Err(StorageError::Index(format!(
+                "a vector index must have between 1 and {MAX_LISTS} lists, got {}",
+                self.lists
+            )));
+        }
+        Ok(())
+    }
+
+    /// The vector of `values` as the index stores it, if the index can hold it.
+    fn vector(&self, values: impl ExactSizeIterator<Item = f64>) -> Option<Vec<f32>> {
+        if values.len() != self.dimension as usize {
+            return None;
+        }
+        let vector: Vec<f32> = values.map(|value| value as f32).collect();
+        if vector.iter().any(|value| !value.is_finite())
+            || (self.metric == Metric::Cosine && norm(&vector) == 0.0)
+        {
+            return None;
+        }
+        Some(vector)
+    }
+
+    /// Check a vector searched for against the index.
+    pub fn query_vector(&self, values: &[f64]) -> Result<Vec<f32>> {
+        self.vector(values.iter().copied()).ok_or_else(|| {
+            StorageError::Index(format!(
+                "expected a finite {} vector of {} dimensions, got {} values",
+                if self.metric == Metric::Cosine {
+                    "non-zero"
+                } else {
+                    "numeric"
+                },
+                self.dimension,
+                values.len()
+            ))
+        })
+    }
+}
+
+/// The vector a document holds at `field`, if it holds one the index can hold.
+fn document_vector(options: &VectorOptions, doc: &Document) -> Option<Vec<f32>> {
+    let datum::Value::Array(array) = field_value(doc, &options.field)? else {
+        return None;
+    };
+    let values: Option<Vec<f64>> = array
+        .items
+        .iter()
+        .map(|item| match item.value {
+            Some(datum::Value::Int(value)) => Some(value as f64),
+            Some(datum::Value::Float(value)) => Some(value),
+            _ => None,
+        })
+        .collect();
+    options.vector(values?.into_iter())
+}
+
+fn entry_prefix(list: u32) -> [u8; 5] {
+    let mut prefix = [ENTRY_PREFIX; 5];
+    prefix[1..].copy_from_slice(&list.to_be_bytes());
+    prefix
+}
+
+fn entry_key(list: u32, key: &str) -> Vec<u8> {
+    let mut entry_key = entry_prefix(list).to_vec();
+    entry_key.extend_from_slice(key.as_bytes());
+    entry_key
+}
+
+fn document_key(key: &str) -> Vec<u8> {
+    let mut document_key = Vec::with_capacity(key.len() + 1);
+    document_key.push(DOCUMENT_PREFIX);
+    document_key.extend_from_slice(key.as_bytes());
+    document_key
+}
+
+fn encode_vector(vector: &[f32]) -> Vec<u8> {
+    vector
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn decode_vector(value: &[u8]) -> Result<Vec<f32>> {
+    if !value.len().is_multiple_of(4) {
+        return Err(StorageError::Index(
+            "corrupt vector index entry".to_string(),
+        ));
+    }
+    Ok(value
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect())
+}
+
+fn parse_list(value: &[u8]) -> Result<u32> {
+    let value: [u8; 4] = value
+        .try_into()
+        .map_err(|_| StorageError::Index("corrupt vector index list".to_string()))?;
+    Ok(u32::from_be_bytes(value))
+}
+
+/// The centroids of an index's lists.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Centroids {
+    /// Centroid of each list.
+    lists: Vec<Vec<f32>>,
+    /// Number of vectors the centroids were last trained on, or zero while they are the
+    /// first distinct vectors written.
+    trained_on: u64,
+}
+
+fn read_centroids(
+    db: &DBWithThreadMode<MultiThreaded>,
+    cf: &Arc<BoundColumnFamily<'_>>,
+    read_opts: &ReadOptions,
+) -> Result<Centroids> {
+    match db.get_cf_opt(cf, CENTROIDS_KEY, read_opts)? {
+        Some(value) => {
+            let (centroids, _) =
+                bincode::serde::decode_from_slice(&value, bincode::config::standard())?;
+            Ok(centroids)
+        }
+        None => Ok(Centroids::default()),
+    }
+}
+
+fn read_count(
+    db: &DBWithThreadMode<MultiThreaded>,
+    cf: &Arc<BoundColumnFamily<'_>>,
+) -> Result<u64> {
+    match db.get_cf(cf, COUNT_KEY)? {
+        Some(value) => {
+            let value: [u8; 8] = value
+                .as_slice()
+                .try_into()
+                .map_err(|_| StorageError::Index("corrupt vector index count".to_string()))?;
+            Ok(u64::from_be_bytes(value))
+        }
+        None => Ok(0),
+    }
+}
+
+/// Lists ordered by how near their centroids are to `vector`, nearest first.
+fn nearest_lists(metric: Metric, centroids: &[Vec<f32>], vector: &[f32]) -> Vec<u32> {
+    let mut lists: Vec<(u32, f64)> = centroids
+        .iter()
+        .enumerate()
+        .map(|(list, centroid)| (list as u32, metric.distance(centroid, vector)))
+        .collect();
+    lists.sort_by(|(a_list, a), (b_list, b)| a.total_cmp(b).then(a_list.cmp(b_list)));
+    lists.into_iter().map(|(list, _)| list).collect()
+}
+
+/// The list whose centroid is nearest to `vector`.
+fn nearest_list(metric: Metric, centroids: &[Vec<f32>], vector: &[f32]) -> u32 {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(list, centroid)| (list as u32, metric.distance(centroid, vector)))
+        .min_by(|(a_list, a), (b_list, b)| a.total_cmp(b).then(a_list.cmp(b_list)))
+        .map_or(0, |(list, _)| list)
+}
+
+/// Centroids of up to `lists` clusters of `sample`, found with k-means. They start out
+/// as sample vectors far apart from each other, each taken farthest from those before
+/// it, and then move to the mean of the vectors nearest to them until they settle.
+fn train(metric: Metric, sample: &[&[f32]], lists: usize) -> Vec<Vec<f32>> {
+    let Some(first) = sample.first() else {
+        return Vec::new();
+    };
+    let mut centroids = vec![first.to_vec()];
+    let mut spread: Vec<f64> = sample
+        .iter()
+        .map(|vector| Metric::L2.distance(first, vector))
+        .collect();
+    while centroids.len() < lists {
+        let Some((farthest, distance)) = spread
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        else {
+            break;
+        };
+        // Every vector of the sample is a centroid already
+        if distance <= 0.0 {
+            break;
+        }
+        let centroid = sample[farthest].to_vec();
+        for (spread, vector) in spread.iter_mut().zip(sample) {
+            *spread = spread.min(Metric::L2.distance(&centroid, vector));
+        }
+        centroids.push(centroid);
+    }
+
+    let mut assignments = vec![u32::MAX; sample.len()];
+    for _ in 0..TRAINING_ITERATIONS {
+        let mut moved = false;
+        for (assignment, vector) in assignments.iter_mut().zip(sample) {
+            let list = nearest_list(metric, &centroids, vector);
+            moved |= *assignment != list;
+            *assignment = list;
+        }
+        if !moved {
+            break;
+        }
+
+        let mut sums = vec![vec![0.0f64; first.len()]; centroids.len()];
+        let mut counts = vec![0usize; centroids.len()];
+        for (list, vector) in assignments.iter().zip(sample) {
+            let list = *list as usize;
+            counts[list] += 1;
+            for (sum, value) in sums[list].iter_mut().zip(*vector) {
+                *sum += f64::from(*value);
+            }
+        }
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count == 0 {
+                continue;
+            }
+            let mean: Vec<f32> = sum.iter().map(|sum| (sum / count as f64) as f32).collect();
+            // The mean of opposite directions has none
+            if metric != Metric::Cosine || norm(&mean) > 0.0 {
+                *centroid = mean;
+            }
+        }
+    }
+    centroids
+}
+
+/// Updates a vector index along with the documents of a write batch.
+pub struct IndexWriter<'a> {
+    db: &'a DBWithThreadMode<MultiThreaded>,
+    cf: Arc<BoundColumnFamily<'a>>,
+    options: VectorOptions,
+    centroids: Centroids,
+    /// Whether this batch changed the centroids.
+    changed: bool,
+    /// Number of indexed vectors, as stored and as of this batch.
+    stored_count: u64,
+    count: u64,
+    /// Lists and vectors of the documents already written in this batch, which the
+    /// database doesn't hold yet.
+    written: HashMap<String, Option<(u32, Vec<f32>)>>,
+}
+
+impl<'a> IndexWriter<'a> {
+    pub fn new(
+        db: &'a DBWithThreadMode<MultiThreaded>,
+        cf: Arc<BoundColumnFamily<'a>>,
+        options: &VectorOptions,
+    ) -> Result<Self> {
+        let centroids = read_centroids(db, &cf, &ReadOptions::default())?;
+        let count = read_count(db, &cf)?;
+        Ok(Self {
+            db,
+            cf,
+            options: options.clone(),
+            centroids,
+            changed: false,
+            stored_count: count,
+            count,
+            written: HashMap::new(),
+        })
+    }
+
+    /// The list a vector belongs to. Until the centroids are trained, a vector unlike
+    /// them becomes the centroid of a new list while the index has fewer than it asks
+    /// for.
+    fn assign(&mut self, vector: &[f32]) -> u32 {
+        let lists = &mut self.centroids.lists;
+        if self.centroids.trained_on == 0
+            && lists.len() < self.options.lists as usize
+            && !lists.iter().any(|centroid| centroid == vector)
+        {
+            lists.push(vector.to_vec());
+            self.changed = true;
+            return lists.len() as u32 - 1;
+        }
+        nearest_list(self.options.metric, lists, vector)
+    }
+
+    /// Replace the entry of the document at `key` with that of `doc`, or remove it if
+    /// it was deleted.
+    pub fn update(
+        &mut self,
+        batch: &mut WriteBatch,
+        key: &str,
+        doc: Option<&Document>,
+    ) -> Result<()> {
+        let previous = match self.written.get(key) {
+            Some(entry) => entry.as_ref().map(|(list, _)| *list),
+            None => self
+                .db
+                .get_cf(&self.cf, document_key(key))?
+                .map(|value| parse_list(&value))
+                .transpose()?,
+        };
+        if let Some(list) = previous {
+            batch.delete_cf(&self.cf, entry_key(list, key));
+            self.count = self.count.saturating_sub(1);
+        }
+
+        let vector = doc.and_then(|doc| document_vector(&self.options, doc));
+        let entry = vector.map(|vector| {
+            let list = self.assign(&vector);
+            batch.put_cf(&self.cf, entry_key(list, key), encode_vector(&vector));
+            batch.put_cf(&self.cf, document_key(key), list.to_be_bytes());
+            (list, vector)
+        });
+        if entry.is_some() {
+            self.count += 1;
+        } else if previous.is_some() {
+            batch.delete_cf(&self.cf, document_key(key));
+        }
+
+        self.written.insert(key.to_string(), entry);
+        Ok(())
+    }
+
+    /// Whether the index holds enough vectors to train its centroids for the first
+    /// time, or has doubled since they were last trained.
+    fn needs_training(&self) -> bool {
+        let trained_on = self.centroids.trained_on;
+        if trained_on == 0 {
+            self.count >= u64::from(self.options.lists) * MIN_TRAINING_VECTORS_PER_LIST
+        } else {
+            self.count >= 2 * trained_on && self.count <= MAX_RETRAINING_VECTORS
+        }
+    }
+
+    /// Train the centroids on a sample of every indexed vector, and file the vectors
+    /// whose nearest centroid changed in its list.
+    fn train(&mut self, batch: &mut WriteBatch) -> Result<()> {
+        let mut entries: Vec<(String, u32, Vec<f32>)> = Vec::new();
+        let prefix = [ENTRY_PREFIX];
+        let mode = IteratorMode::From(&prefix, Direction::Forward);
+        for res in self.db.iterator_cf(&self.cf, mode) {
+            let (stored_key, value) = res?;
+            let Some(entry) = stored_key.strip_prefix(prefix.as_slice()) else {
+                break;
+            };
+            let (list, key) = entry
+                .split_at_checked(4)
+                .ok_or_else(|| StorageError::Index("corrupt vector index entry".to_string()))?;
+            let key = String::from_utf8(key.to_vec())?;
+            if !self.written.contains_key(&key) {
+                entries.push((key, parse_list(list)?, decode_vector(&value)?));
+            }
+        }
+        entries.extend(self.written.iter().filter_map(|(key, entry)| {
+            let (list, vector) = entry.as_ref()?;
+            Some((key.clone(), *list, vector.clone()))
+        }));
+        // Sampling in key order rather than in the order they were written keeps
+        // the sample from leaning towards the vectors written first
+        entries.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+        let lists = self.options.lists as usize;
+        let step = entries
+            .len()
+            .div_ceil(lists * TRAINING_SAMPLE_PER_LIST)
+            .max(1);
+        let sample: Vec<&[f32]> = entries
+            .iter()
+            .step_by(step)
+            .map(|(_, _, vector)| vector.as_slice())
+            .collect();
+        let centroids = train(self.options.metric, &sample, lists);
+
+        for (key, list, vector) in &entries {
+            let nearest = nearest_list(self.options.metric, &centroids, vector);
+            if nearest != *list {
+                batch.delete_cf(&self.cf, entry_key(*list, key));
+                batch.put_cf(&self.cf, entry_key(nearest, key), encode_vector(vector));
+                batch.put_cf(&self.cf, document_key(key), nearest.to_be_bytes());
+            }
+        }
+        self.centroids = Centroids {
+            lists: centroids,
+            trained_on: entries.len() as u64,
+        };
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn finish(mut self, batch: &mut WriteBatch) -> Result<()> {
+        if self.needs_training() {
+            self.train(batch)?;
+        }
+        if self.count != self.stored_count {
+            batch.put_cf(&self.cf, COUNT_KEY, self.count.to_be_bytes());
+        }
+        if self.changed {
+            batch.put_cf(
+                &self.cf,
+                CENTROIDS_KEY,
+                bincode::serde::encode_to_vec(&self.centroids, bincode::config::standard())?,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Keys of the documents in the `probes` lists nearest to `vector`, or in every list
+/// if `probes` is `None`, with their distances, nearest first. Documents at equal
+/// distances are ordered by key.
+pub fn nearest(
+    db: &DBWithThreadMode<MultiThreaded>,
+    cf: &Arc<BoundColumnFamily<'_>>,
+    options: &VectorOptions,
+    read_opts: &dyn Fn() -> ReadOptions,
+    vector: &[f32],
+    probes: Option<usize>,
+) -> Result<Vec<(String, f64)>> {
+    let centroids = read_centroids(db, cf, &read_opts())?;
+    let mut lists = nearest_lists(options.metric, &centroids.lists, vector);
+    if let Some(probes) = probes {
+        lists.truncate(probes);
+    }
+
+    let mut results = Vec::new();
+    for list in lists {
+        let prefix = entry_prefix(list);
+        let mode = IteratorMode::From(&prefix, Direction::Forward);
+        for res in db.iterator_cf_opt(cf, read_opts(), mode) {
+            let (entry_key, value) = res?;
+            let Some(key) = entry_key.strip_prefix(prefix.as_slice()) else {
+                break;
+            };
+            results.push((
+                String::from_utf8(key.to_vec())?,
+                options.metric.distance(&decode_vector(&value)?, vector),
+            ));
+        }
+    }
+    results.sort_by(|(a_key, a), (b_key, b)| a.total_cmp(b).then_with(|| a_key.cmp(b_key)));
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Datum, DatumArray};
+
+    fn options(metric: Metric) -> VectorOptions {
+        VectorOptions {
+            field: vec!["embedding".to_string()],
+            dimension: 2,
+            metric,
+            lists: 4,
+        }
+    }
+
+    #[test]
+    fn test_metric_distance() {
+        let (a, b) = ([1.0, 0.0], [0.0, 2.0]);
+        assert!((Metric::Cosine.distance(&a, &b) - 1.0).abs() < 1e-9);
+        assert!(Metric::Cosine.distance(&a, &[3.0, 0.0]).abs() < 1e-9);
+        assert!((Metric::L2.distance(&a, &b) - 5f64.sqrt()).abs() < 1e-9);
+        assert_eq!(Metric::Dot.distance(&[1.0, 2.0], &[3.0, 4.0]), -11.0);
+    }
+
+    #[test]
+    fn test_document_vector() {
+        let doc = |items: Vec<datum::Value>| {
+            Document::from([(
+                "embedding".to_string(),
+                Datum {
+                    value: Some(datum::Value::Array(DatumArray {
+                        items: items
+                            .into_iter()
+                            .map(|value| Datum { value: Some(value) })
+                            .collect(),
+                        element_type: String::new(),
+                    })),
+                },
+            )])
+        };
+
+        let vector = doc(vec![datum::Value::Int(1), datum::Value::Float(0.5)]);
+        assert_eq!(
+            document_vector(&options(Metric::L2), &vector),
+            Some(vec![1.0, 0.5])
+        );
+        let short = doc(vec![datum::Value::Float(0.5)]);
+        assert_eq!(document_vector(&options(Metric::L2), &short), None);
+        let text = doc(vec![
+            datum::Value::Float(0.5),
+            datum::Value::String("x".to_string()),
+        ]);
+        assert_eq!(document_vector(&options(Metric::L2), &text), None);
+        let zero = doc(vec![datum::Value::Int(0), datum::Value::Int(0)]);
+        assert_eq!(
+            document_vector(&options(Metric::L2), &zero),
+            Some(vec![0.0, 0.0])
+        );
+        assert_eq!(document_vector(&options(Metric::Cosine), &zero), None);
+
+        assert!(options(Metric::Dot).query_vector(&[1.0, 2.0]).is_ok());
+        assert!(options(Metric::Dot).query_vector(&[1.0]).is_err());
+        assert!(options(Metric::Dot).query_vector(&[1.0, f64::NAN]).is_err());
+    }
+
+    #[test]
+    fn test_entry_keys() {
+        assert_eq!(entry_key(1, "doc1"), b"l\0\0\0\x01doc1");
+        assert!(entry_key(1, "doc1").starts_with(&entry_prefix(1)));
+        assert!(!entry_key(256, "doc1").starts_with(&entry_prefix(1)));
+        assert_eq!(document_key("doc1"), b"ddoc1");
+        assert_eq!(
+            decode_vector(&encode_vector(&[1.5, -2.0])).unwrap(),
+            vec![1.5, -2.0]
+        );
+        assert!(decode_vector(b"abc").is_err());
+        assert_eq!(parse_list(&7u32.to_be_bytes()).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_train() {
+        // Clusters around four corners, one after the other
+        let corners = [[0.0, 0.0], [100.0, 0.0], [0.0, 100.0], [100.0, 100.0]];
+        let vectors: Vec<Vec<f32>> = corners
+            .iter()
+            .flat_map(|[x, y]| (0..16).map(move |i| vec![x + (i % 4) as f32, y + (i / 4) as f32]))
+            .collect();
+        let sample: Vec<&[f32]> = vectors.iter().map(Vec::as_slice).collect();
+
+        let mut centroids = train(Metric::L2, &sample, 4);
+        centroids.sort_by(|a, b| a[0].total_cmp(&b[0]).then(a[1].total_cmp(&b[1])));
+        assert_eq!(
+            centroids,
+            vec![
+                vec![1.5, 1.5],
+                vec![1.5, 101.5],
+                vec![101.5, 1.5],
+                vec![101.5, 101.5]
+            ]
+        );
+
+        // Identical vectors make a single centroid
+        let same = [[1.0f32, 1.0].as_slice(); 8];
+        assert_eq!(train(Metric::L2, &same, 4), vec![vec![1.0, 1.0]]);
+        assert!(train(Metric::L2, &[], 4).is_empty());
+    }
+
+    #[test]
+    fn test_nearest_lists() {
+        let centroids = vec![vec![0.0, 0.0], vec![10.0, 0.0], vec![5.0, 5.0]];
+        assert_eq!(
+            nearest_lists(Metric::L2, &centroids, &[9.0, 1.0]),
+            vec![1, 2, 0]
+        );
+    }
+}
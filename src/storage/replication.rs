@@ -0,0 +1,382 @@
+//! Primary/replica replication.
+//!
+//! A replica follows its primary by asking for the write batches logged after the
+//! last sequence number it applied. The primary reads them back from the RocksDB
+//! write-ahead log and decodes them into operations addressed by column family name,
+//! because column family ids are local to each database. The replica applies every
+//! batch atomically together with its new position, so it resumes where it left off
+//! after a restart.
+
+use super::{Result, StorageError};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Key of the last applied primary sequence number in a replica's meta table.
+pub const APPLIED_SEQUENCE_KEY: &str = "replication:applied_sequence";
+
+/// Maximum number of write batches returned for a single request.
+pub const MAX_BATCHES_PER_REQUEST: usize = 1024;
+
+// Record tags of RocksDB's write batch format, see `db/dbformat.h`.
+const TYPE_DELETION: u8 = 0x0;
+const TYPE_VALUE: u8 = 0x1;
+const TYPE_LOG_DATA: u8 = 0x3;
+const TYPE_CF_DELETION: u8 = 0x4;
+const TYPE_CF_VALUE: u8 = 0x5;
+const TYPE_SINGLE_DELETION: u8 = 0x7;
+const TYPE_CF_SINGLE_DELETION: u8 = 0x8;
+const TYPE_NOOP: u8 = 0xD;
+
+/// Size of the sequence number and record count preceding a batch's records.
+const BATCH_HEADER_SIZE: usize = 12;
+
+/// A single write in a replicated batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalOperation {
+    Put {
+        column_family: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Delete {
+        column_family: String,
+        key: Vec<u8>,
+    },
+}
+
+/// A write batch of the primary and the sequence numbers it occupies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalBatch {
+    /// Sequence number of the batch's first record.
+    pub sequence: u64,
+    /// Number of sequence numbers the batch occupies.
+    pub count: u64,
+    /// Writes to column families that still exist on the primary.
+    pub operations: Vec<WalOperation>,
+}
+
+impl WalBatch {
+    /// Sequence number following the batch.
+    pub fn next_sequence(&self) -> u64 {
+        self.sequence + self.count
+    }
+}
+
+/// How long obsolete write-ahead log files are kept for replicas to catch up from. A
+/// file is deleted once it is older than the TTL or the kept files outgrow the size
+/// limit, whichever comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalRetention {
+    pub ttl_seconds: u64,
+    pub size_limit_mb: u64,
+}
+
+/// Write batches read from the primary's log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplicationUpdates {
+    pub batches: Vec<WalBatch>,
+    /// Latest sequence number of the primary when the batches were read.
+    pub latest_sequence: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationRole {
+    Primary,
+    Replica {
+        primary: String,
+    },
+    /// A RocksDB secondary instance reading the data directory `primary` of another process.
+    Secondary {
+        primary: String,
+    },
+}
+
+/// Replication progress as reported to clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicationStatus {
+    pub role: ReplicationRole,
+    /// Latest sequence number applied to this database.
+    pub applied_sequence: u64,
+    /// Latest sequence number of the primary, as of the last contact with it.
+    pub primary_sequence: u64,
+    /// Time since the replica last heard from its primary.
+    pub last_contact: Option<Duration>,
+}
+
+impl ReplicationStatus {
+    /// Number of sequence numbers the replica is behind its primary.
+    pub fn lag(&self) -> u64 {
+        self.primary_sequence.saturating_sub(self.applied_sequence)
+    }
+}
+
+struct Progress {
+    applied_sequence: u64,
+    primary_sequence: u64,
+    last_contact: Option<Instant>,
+}
+
+/// Replication role of a database and, for replicas, how far they got.
+pub struct ReplicationState {
+    role: Mutex<ReplicationRole>,
+    progress: Mutex<Progress>,
+}
+
+impl ReplicationState {
+    pub fn new(applied_sequence: u64) -> Self {
+        Self {
+            role: Mutex::new(ReplicationRole::Primary),
+            progress: Mutex::new(Progress {
+                applied_sequence,
+                primary_sequence: applied_sequence,
+                last_contact: None,
+            }),
+        }
+    }
+
+    pub fn set_role(&self, role: ReplicationRole) {
+        *self.role.lock().unwrap() = role;
+    }
+
+    pub fn is_primary(&self) -> bool {
+        matches!(*self.role.lock().unwrap(), ReplicationRole::Primary)
+    }
+
+    /// Fail if the database only accepts writes from its primary.
+    pub fn ensure_writable(&self) -> Result<()> {
+        match *self.role.lock().unwrap() {
+            ReplicationRole::Primary => Ok(()),
+            ReplicationRole::Replica { .. } => Err(StorageError::ReadOnlyReplica),
+            ReplicationRole::Secondary { .. } => Err(StorageError::ReadOnlySecondary),
+        }
+    }
+
+    pub fn applied_sequence(&self) -> u64 {
+        self.progress.lock().unwrap().applied_sequence
+    }
+
+    /// Record a successful exchange with the primary.
+    pub fn record_contact(&self, applied_sequence: u64, primary_sequence: u64) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.applied_sequence = applied_sequence;
+        progress.primary_sequence = primary_sequence.max(applied_sequence);
+        progress.last_contact = Some(Instant::now());
+    }
+
+    /// Current status; `latest_sequence` is the local database's latest sequence number,
+    /// which is what a primary reports as both applied and primary sequence.
+    pub fn status(&self, latest_sequence: u64) -> ReplicationStatus {
+        let role = self.role.lock().unwrap().clone();
+        let progress = self.progress.lock().unwrap();
+        match role {
+            ReplicationRole::Primary => ReplicationStatus {
+                role,
+                applied_sequence: latest_sequence,
+                primary_sequence: latest_sequence,
+                last_contact: None,
+            },
+            ReplicationRole::Replica { .. } | ReplicationRole::Secondary { .. } => {
+                ReplicationStatus {
+                    role,
+                    applied_sequence: progress.applied_sequence,
+                    primary_sequence: progress.primary_sequence,
+                    last_contact: progress.last_contact.map(|at| at.elapsed()),
+                }
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn corrupt() -> StorageError {
+        StorageError::CorruptWriteBatch("unexpected end of batch".to_string())
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let (&byte, rest) = self.data.split_first().ok_or_else(Self::corrupt)?;
+        self.data = rest;
+        Ok(byte)
+    }
+
+    fn varint32(&mut self) -> Result<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= u32::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(StorageError::CorruptWriteBatch(
+            "varint32 is too long".to_string(),
+        ))
+    }
+
+    fn slice(&mut self) -> Result<&'a [u8]> {
+        let len = self.varint32()? as usize;
+        if self.data.len() < len {
+            return Err(Self::corrupt());
+        }
+        let (slice, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(slice)
+    }
+}
+
+/// Decode the records of a serialized RocksDB write batch. Writes to column families
+/// missing from `column_families`, which maps ids to names, are left out: they belong
+/// to tables that were dropped since.
+pub fn decode_write_batch(
+    data: &[u8],
+    column_families: &HashMap<u32, String>,
+) -> Result<Vec<WalOperation>> {
+    let records = data.get(BATCH_HEADER_SIZE..).ok_or_else(|| {
+        StorageError::CorruptWriteBatch("batch is shorter than its header".to_string())
+    })?;
+    let mut reader = Reader { data: records };
+    let mut operations = Vec::new();
+
+    while !reader.data.is_empty() {
+        let tag = reader.byte()?;
+        let column_family_id = match tag {
+            TYPE_CF_VALUE | TYPE_CF_DELETION | TYPE_CF_SINGLE_DELETION => reader.varint32()?,
+            _ => 0,
+        };
+
+        let operation = match tag {
+            TYPE_VALUE | TYPE_CF_VALUE => {
+                let key = reader.slice()?.to_vec();
+                let value = reader.slice()?.to_vec();
+                column_families
+                    .get(&column_family_id)
+                    .map(|column_family| WalOperation::Put {
+                        column_family: column_family.clone(),
+                        key,
+                        value,
+                    })
+            }
+            TYPE_DELETION | TYPE_CF_DELETION | TYPE_SINGLE_DELETION | TYPE_CF_SINGLE_DELETION => {
+                let key = reader.slice()?.to_vec();
+                column_families
+                    .get(&column_family_id)
+                    .map(|column_family| WalOperation::Delete {
+                        column_family: column_family.clone(),
+                        key,
+                    })
+            }
+            TYPE_LOG_DATA => {
+                reader.slice()?;
+                None
+            }
+            TYPE_NOOP => None,
+            other => {
+                return Err(StorageError::CorruptWriteBatch(format!(
+                    "unsupported record type {other:#x}"
+                )));
+            }
+        };
+        operations.extend(operation);
+    }
+
+    Ok(operations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_slice(out: &mut Vec<u8>, bytes: &[u8]) {
+        out.push(u8::try_from(bytes.len()).unwrap());
+        out.extend_from_slice(bytes);
+    }
+
+    #[test]
+    fn test_decode_write_batch() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&7u64.to_le_bytes());
+        data.extend_from_slice(&4u32.to_le_bytes());
+        // Put into the default column family
+        data.push(TYPE_VALUE);
+        put_slice(&mut data, b"a");
+        put_slice(&mut data, b"1");
+        // Put into column family 3
+        data.push(TYPE_CF_VALUE);
+        data.push(3);
+        put_slice(&mut data, b"b");
+        put_slice(&mut data, b"2");
+        // Delete from a dropped column family
+        data.push(TYPE_CF_DELETION);
+        data.push(9);
+        put_slice(&mut data, b"c");
+        data.push(TYPE_CF_SINGLE_DELETION);
+        data.push(3);
+        put_slice(&mut data, b"d");
+
+        let column_families =
+            HashMap::from([(0, "default".to_string()), (3, "db:table".to_string())]);
+        let operations = decode_write_batch(&data, &column_families).unwrap();
+        assert_eq!(
+            operations,
+            vec![
+                WalOperation::Put {
+                    column_family: "default".to_string(),
+                    key: b"a".to_vec(),
+                    value: b"1".to_vec(),
+                },
+                WalOperation::Put {
+                    column_family: "db:table".to_string(),
+                    key: b"b".to_vec(),
+                    value: b"2".to_vec(),
+                },
+                WalOperation::Delete {
+                    column_family: "db:table".to_string(),
+                    key: b"d".to_vec(),
+                },
+            ]
+        );
+
+        data.truncate(data.len() - 1);
+        assert!(matches!(
+            decode_write_batch(&data, &column_families),
+            Err(StorageError::CorruptWriteBatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_replica_status() {
+        let state = ReplicationState::new(5);
+        assert!(state.ensure_writable().is_ok());
+        assert_eq!(state.status(12).lag(), 0);
+
+        state.set_role(ReplicationRole::Replica {
+            primary: "127.0.0.1:6090".to_string(),
+        });
+        assert!(matches!(
+            state.ensure_writable(),
+            Err(StorageError::ReadOnlyReplica)
+        ));
+
+        let status = state.status(0);
+        assert_eq!(status.applied_sequence, 5);
+        assert!(status.last_contact.is_none());
+
+        state.record_contact(8, 20);
+        let status = state.status(0);
+        assert_eq!(status.lag(), 12);
+        assert!(status.last_contact.is_some());
+
+        state.set_role(ReplicationRole::Secondary {
+            primary: "/var/lib/rulodb".to_string(),
+        });
+        assert!(!state.is_primary());
+        assert!(matches!(
+            state.ensure_writable(),
+            Err(StorageError::ReadOnlySecondary)
+        ));
+    }
+}
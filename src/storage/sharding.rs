@@ -0,0 +1,317 @@
+//! Horizontal sharding of tables by primary key range.
+//!
+//! A sharded table is split into contiguous ranges of primary keys, each stored in its
+//! own column family. The first shard keeps the table's own column family, so a table
+//! without a shard map is simply a table with a single shard. Every key is routed to the
+//! shard owning it through the table's shard map, which is kept in the meta table.
+//! Shards are addressed by id rather than by column family, leaving room to place them
+//! on other nodes later.
+//!
+//! A rebalance copies the documents changing shard while writes go on, switches to the
+//! new map once the documents written meanwhile are copied again, then deletes the
+//! documents it moved from their old shards. Until then a shard's column family may hold
+//! documents outside its range, so reads over a shard keep to its range.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Largest number of shards a table may be split into.
+pub const MAX_SHARDS: u32 = 32;
+
+/// Characters the key space of a new table is split on. Tables start out without data,
+/// so their initial ranges assume alphanumeric keys until they are rebalanced.
+const SPLIT_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    pub id: u32,
+    /// Smallest key of the shard's range, or `None` for the first shard.
+    pub start: Option<String>,
+}
+
+/// The key ranges of a sharded table, in key order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardMap {
+    shards: Vec<Shard>,
+}
+
+/// A shard's range of keys, from `start` inclusive to `end` exclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardRange {
+    pub id: u32,
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+impl ShardRange {
+    /// Whether `key` falls in the range.
+    pub fn contains(&self, key: impl AsRef<[u8]>) -> bool {
+        let key = key.as_ref();
+        self.start
+            .as_ref()
+            .is_none_or(|start| start.as_bytes() <= key)
+            && self.end.as_ref().is_none_or(|end| key < end.as_bytes())
+    }
+}
+
+/// Outcome of rebalancing a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebalanceSummary {
+    pub shards: Vec<ShardRange>,
+    /// Number of documents moved to another shard.
+    pub moved: u64,
+}
+
+impl ShardMap {
+    /// A map with a single shard owning every key.
+    pub fn single() -> Self {
+        Self {
+            shards: vec![Shard { id: 0, start: None }],
+        }
+    }
+
+    /// Split the key space into `count` ranges of about equal width.
+    pub fn uniform(count: u32) -> Self {
+        Self::single().with_boundaries(uniform_boundaries(count))
+    }
+
+    /// A map whose shards start at `boundaries`, reusing the ids of this map's shards
+    /// in key order and numbering any additional shards after them.
+    pub fn with_boundaries(&self, mut boundaries: Vec<String>) -> Self {
+        boundaries.sort();
+        boundaries.dedup();
+        let mut next_id = self.shards.iter().map(|shard| shard.id).max().unwrap_or(0) + 1;
+        let starts = std::iter::once(None).chain(boundaries.into_iter().map(Some));
+        let shards = starts
+            .enumerate()
+            .map(|(i, start)| {
+                let id = self.shards.get(i).map_or_else(
+                    || {
+                        next_id += 1;
+                        next_id - 1
+                    },
+                    |shard| shard.id,
+                );
+                Shard { id, start }
+            })
+            .collect();
+        Self { shards }
+    }
+
+    pub fn shards(&self) -> &[Shard] {
+        &self.shards
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.shards.iter().any(|shard| shard.id == id)
+    }
+
+    /// Position of the shard owning `key`.
+    pub fn index_of(&self, key: impl AsRef<[u8]>) -> usize {
+        let key = key.as_ref();
+        self.shards
+            .partition_point(|shard| {
+                shard
+                    .start
+                    .as_ref()
+                    .is_none_or(|start| start.as_bytes() <= key)
+            })
+            .saturating_sub(1)
+    }
+
+    /// The shard owning `key`.
+    pub fn route(&self, key: impl AsRef<[u8]>) -> &Shard {
+        &self.shards[self.index_of(key)]
+    }
+
+    pub fn ranges(&self) -> Vec<ShardRange> {
+        self.shards
+            .iter()
+            .enumerate()
+            .map(|(i, shard)| ShardRange {
+                id: shard.id,
+                start: shard.start.clone(),
+                end: self.shards.get(i + 1).and_then(|next| next.start.clone()),
+            })
+            .collect()
+    }
+}
+
+/// The shard map of a table as the storage holds it. Writes hold the map for reading
+/// while they route keys by it and write them, so it never changes under them.
+pub struct TableShards {
+    map: RwLock<ShardMap>,
+    /// Keys written while a rebalance copies the table's documents, to copy again
+    written: Mutex<Option<HashSet<String>>>,
+}
+
+impl TableShards {
+    pub fn new(map: ShardMap) -> Arc<Self> {
+        Arc::new(Self {
+            map: RwLock::new(map),
+            written: Mutex::new(None),
+        })
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, ShardMap> {
+        self.map.read().unwrap()
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, ShardMap> {
+        self.map.write().unwrap()
+    }
+
+    /// Note keys written through the map, if a rebalance is copying the documents.
+    pub fn record<'a>(&self, keys: impl IntoIterator<Item = &'a str>) {
+        if let Some(written) = self.written.lock().unwrap().as_mut() {
+            written.extend(keys.into_iter().map(str::to_string));
+        }
+    }
+
+    /// Start noting the keys written through the map.
+    pub fn start_recording(&self) {
+        *self.written.lock().unwrap() = Some(HashSet::new());
+    }
+
+    /// Stop noting the keys written through the map, returning those noted.
+    pub fn stop_recording(&self) -> HashSet<String> {
+        self.written.lock().unwrap().take().unwrap_or_default()
+    }
+}
+
+/// First keys of all but the first of `count` ranges of about equal width.
+pub fn uniform_boundaries(count: u32) -> Vec<String> {
+    let count = count.clamp(1, MAX_SHARDS) as usize;
+    (1..count)
+        .map(|i| char::from(SPLIT_ALPHABET[i * SPLIT_ALPHABET.len() / count]).to_string())
+        .collect()
+}
+
+/// Positions, in key order, of the first keys of all but the first of `count` ranges
+/// holding about as many of `total` keys each. Fewer than `count` keys make for fewer
+/// ranges.
+pub fn balanced_positions(total: u64, count: u32) -> Vec<u64> {
+    let count = u64::from(count.clamp(1, MAX_SHARDS));
+    let mut positions: Vec<u64> = (1..count)
+        .map(|i| i * total / count)
+        .filter(|position| *position > 0)
+        .collect();
+    positions.dedup();
+    positions
+}
+
+/// Name of the column family holding shard `id` of a table.
+pub fn shard_table_name(table_name: &str, id: u32) -> String {
+    if id == 0 {
+        table_name.to_string()
+    } else {
+        format!("{table_name}#{id}")
+    }
+}
+
+/// Whether a column family holds an additional shard of a table rather than a table.
+pub fn is_shard_name(cf_name: &str) -> bool {
+    cf_name.contains('#')
+}
+
+/// Whether a column family holds an additional shard of the table `table_name`.
+pub fn is_shard_of(cf_name: &str, table_name: &str) -> bool {
+    cf_name
+        .strip_prefix(table_name)
+        .is_some_and(|rest| rest.starts_with('#'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uniform_map_routes_keys() {
+        let map = ShardMap::uniform(4);
+        let starts: Vec<_> = map.shards().iter().map(|s| s.start.clone()).collect();
+        assert_eq!(
+            starts,
+            vec![
+                None,
+                Some("F".to_string()),
+                Some("V".to_string()),
+                Some("k".to_string()),
+            ]
+        );
+        assert_eq!(map.route("").id, 0);
+        assert_eq!(map.route("Alice").id, 0);
+        assert_eq!(map.route("F").id, 1);
+        assert_eq!(map.route("bob").id, 2);
+        assert_eq!(map.route("zed").id, 3);
+        assert_eq!(map.index_of(b"Wanda"), 2);
+
+        assert_eq!(ShardMap::uniform(1), ShardMap::single());
+        assert_eq!(ShardMap::uniform(1000).len(), MAX_SHARDS as usize);
+    }
+
+    #[test]
+    fn test_with_boundaries_reuses_ids() {
+        let map = ShardMap::uniform(3);
+        let grown = map.with_boundaries(vec!["m".into(), "c".into(), "t".into(), "c".into()]);
+        let ids: Vec<_> = grown.shards().iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3]);
+        assert_eq!(
+            grown.ranges()[1],
+            ShardRange {
+                id: 1,
+                start: Some("c".to_string()),
+                end: Some("m".to_string()),
+            }
+        );
+
+        let shrunk = grown.with_boundaries(vec!["k".into()]);
+        assert_eq!(shrunk.len(), 2);
+        assert!(shrunk.contains(1));
+        assert!(!shrunk.contains(2));
+        assert_eq!(shrunk.ranges()[1].end, None);
+    }
+
+    #[test]
+    fn test_range_contains_keys() {
+        let ranges = ShardMap::uniform(3).ranges();
+        assert!(ranges[0].contains("Alice"));
+        assert!(!ranges[0].contains("V"));
+        assert!(ranges[1].contains("V"));
+        assert!(!ranges[1].contains("k"));
+        assert!(ranges[2].contains("zed"));
+        assert!(
+            ranges
+                .iter()
+                .all(|range| range.contains("k") == (range.id == 2))
+        );
+    }
+
+    #[test]
+    fn test_balanced_positions() {
+        assert_eq!(balanced_positions(10, 3), vec![3, 6]);
+        assert_eq!(balanced_positions(10, 1), Vec::<u64>::new());
+        // Fewer keys than shards leaves fewer ranges
+        assert_eq!(balanced_positions(2, 4), vec![1]);
+        assert_eq!(balanced_positions(0, 4), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_shard_table_name() {
+        assert_eq!(shard_table_name("db:users", 0), "db:users");
+        assert_eq!(shard_table_name("db:users", 3), "db:users#3");
+        assert!(is_shard_name("db:users#3"));
+        assert!(!is_shard_name("db:users"));
+        assert!(is_shard_of("db:users#3", "db:users"));
+        assert!(!is_shard_of("db:users2#3", "db:users"));
+        assert!(!is_shard_of("db:users", "db:users"));
+    }
+}
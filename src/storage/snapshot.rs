@@ -0,0 +1,171 @@
+//! Snapshots pinned on behalf of queries and cursors.
+//!
+//! A query that needs a consistent view of the data pins a snapshot and passes its
+//! id to every read it makes. Snapshots held by cursors outlive a single request,
+//! so the registry releases any snapshot that has been idle for longer than its
+//! timeout to keep abandoned cursors from holding back compaction forever.
+
+use super::sharding::ShardMap;
+use super::{Result, StorageError};
+use rocksdb::{DBWithThreadMode, MultiThreaded, ReadOptions, SnapshotWithThreadMode};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub type SnapshotId = u64;
+
+/// How long a pinned snapshot may go unused before it is released.
+pub const SNAPSHOT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+type Db = DBWithThreadMode<MultiThreaded>;
+
+/// A RocksDB snapshot that keeps its database alive, along with the shard maps of
+/// sharded tables at the time it was taken.
+pub struct PinnedSnapshot {
+    // Declared before `_db` so the snapshot is released before the database it borrows.
+    snapshot: SnapshotWithThreadMode<'static, Db>,
+    shard_maps: HashMap<String, ShardMap>,
+    _db: Arc<Db>,
+}
+
+impl PinnedSnapshot {
+    /// Pin the current data of `db`. The shard maps must be held still while the
+    /// snapshot is taken so they match the data it sees.
+    pub fn new(db: Arc<Db>, shard_maps: HashMap<String, ShardMap>) -> Self {
+        let snapshot = db.snapshot();
+        // SAFETY: the snapshot borrows the database behind `db`, which this struct owns
+        // and drops only after the snapshot.
+        let snapshot = unsafe {
+            std::mem::transmute::<SnapshotWithThreadMode<'_, Db>, SnapshotWithThreadMode<'static, Db>>(
+                snapshot,
+            )
+        };
+        Self {
+            snapshot,
+            shard_maps,
+            _db: db,
+        }
+    }
+
+    /// The shard map a table had when the snapshot was taken.
+    pub fn shard_map(&self, table_name: &str) -> Option<&ShardMap> {
+        self.shard_maps.get(table_name)
+    }
+
+    /// Make reads through `read_opts` see the data as of this snapshot.
+    pub fn apply(&self, read_opts: &mut ReadOptions) {
+        read_opts.set_snapshot(&self.snapshot);
+    }
+}
+
+struct Entry<T> {
+    snapshot: Arc<T>,
+    last_used: Instant,
+}
+
+/// Pinned snapshots by id.
+pub struct SnapshotRegistry<T> {
+    next_id: AtomicU64,
+    entries: Mutex<HashMap<SnapshotId, Entry<T>>>,
+    idle_timeout: Duration,
+}
+
+impl<T> SnapshotRegistry<T> {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            entries: Mutex::new(HashMap::new()),
+            idle_timeout,
+        }
+    }
+
+    /// Register a snapshot, releasing any that have been idle for too long.
+    pub fn insert(&self, snapshot: T) -> SnapshotId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| now.duration_since(entry.last_used) < self.idle_timeout);
+        entries.insert(
+            id,
+            Entry {
+                snapshot: Arc::new(snapshot),
+                last_used: now,
+            },
+        );
+        id
+    }
+
+    /// Look up a pinned snapshot, resetting its idle timer.
+    pub fn get(&self, id: SnapshotId) -> Result<Arc<T>> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(&id) {
+            Some(entry) if now.duration_since(entry.last_used) < self.idle_timeout => {
+                entry.last_used = now;
+                Ok(entry.snapshot.clone())
+            }
+            Some(_) => {
+                entries.remove(&id);
+                Err(StorageError::SnapshotNotFound(id))
+            }
+            None => Err(StorageError::SnapshotNotFound(id)),
+        }
+    }
+
+    /// Release a snapshot. Reads already holding it keep it alive until they finish.
+    pub fn release(&self, id: SnapshotId) {
+        self.entries.lock().unwrap().remove(&id);
+    }
+
+    /// Whether any snapshot still pinned satisfies `predicate`.
+    pub fn any(&self, predicate: impl Fn(&T) -> bool) -> bool {
+        let now = Instant::now();
+        self.entries.lock().unwrap().values().any(|entry| {
+            now.duration_since(entry.last_used) < self.idle_timeout && predicate(&entry.snapshot)
+        })
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_get_release() {
+        let registry = SnapshotRegistry::new(SNAPSHOT_IDLE_TIMEOUT);
+
+        let first = registry.insert("first");
+        let second = registry.insert("second");
+        assert_ne!(first, second);
+        assert_eq!(*registry.get(first).unwrap(), "first");
+        assert_eq!(*registry.get(second).unwrap(), "second");
+
+        registry.release(first);
+        assert!(matches!(
+            registry.get(first),
+            Err(StorageError::SnapshotNotFound(id)) if id == first
+        ));
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn test_idle_snapshots_expire() {
+        let registry = SnapshotRegistry::new(Duration::from_millis(20));
+
+        let idle = registry.insert("idle");
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(registry.get(idle).is_err());
+
+        // Idle snapshots are also reaped when new ones are pinned
+        registry.insert("abandoned");
+        std::thread::sleep(Duration::from_millis(30));
+        registry.insert("fresh");
+        assert_eq!(registry.len(), 1);
+    }
+}
@@ -0,0 +1,306 @@
+//! Table statistics for the query planner's cost model.
+//!
+//! Row counts come from RocksDB's key estimate and are always current. Analyzing a
+//! table adds per-field figures built from a sample of its documents: how often a
+//! field is set, how many distinct values it holds and, for numeric fields, an
+//! equi-depth histogram of their distribution.
+
+use crate::ast::{Document, datum};
+use crate::decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Number of documents sampled by an analyze that doesn't ask for a sample size.
+pub const DEFAULT_SAMPLE_SIZE: usize = 10_000;
+
+/// Number of buckets in a numeric field's histogram.
+const HISTOGRAM_BUCKETS: usize = 32;
+
+/// Table and database figures the planner bases its estimates on.
+pub trait StatisticsProvider: Send + Sync {
+    /// Statistics of a table, or `None` if the table doesn't exist.
+    fn table_statistics(&self, db: &str, table: &str) -> Option<TableStatistics>;
+    /// Number of tables in a database.
+    fn table_count(&self, db: &str) -> Option<u64>;
+    /// Number of databases.
+    fn database_count(&self) -> Option<u64>;
+    /// A number that changes whenever a database, table or index is created or dropped,
+    /// for plans kept across queries to be remade. Providers whose schema never changes
+    /// keep the default.
+    fn schema_version(&self) -> u64 {
+        0
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TableStatistics {
+    /// Estimated number of documents in the table.
+    pub row_count: u64,
+    /// Documents sampled by the last analyze, or 0 if the table was never analyzed.
+    pub sampled_rows: u64,
+    /// Statistics of the top-level fields seen in the sample.
+    pub fields: HashMap<String, FieldStatistics>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldStatistics {
+    /// Fraction of documents where the field holds a non-null value.
+    pub non_null_fraction: f64,
+    /// Estimated number of distinct scalar values of the field across the table.
+    pub distinct_values: f64,
+    /// Distribution of the field's numeric values, if it has any.
+    pub histogram: Option<Histogram>,
+}
+
+/// Equi-depth histogram: each pair of adjacent bounds holds the same share of values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+}
+
+impl Histogram {
+    fn from_sorted(values: &[f64], buckets: usize) -> Option<Self> {
+        let last = values.len().checked_sub(1)?;
+        let buckets = buckets.min(values.len()).max(1);
+        let bounds = (0..=buckets).map(|i| values[i * last / buckets]).collect();
+        Some(Self { bounds })
+    }
+
+    /// Estimated fraction of values below `value`, interpolating within its bucket.
+    pub fn fraction_below(&self, value: f64) -> f64 {
+        let (Some(&min), Some(&max)) = (self.bounds.first(), self.bounds.last()) else {
+            return 0.5;
+        };
+        if value <= min {
+            return 0.0;
+        }
+        if value > max {
+            return 1.0;
+        }
+
+        let buckets = (self.bounds.len() - 1) as f64;
+        let bucket = self.bounds.partition_point(|&bound| bound < value) - 1;
+        let (low, high) = (self.bounds[bucket], self.bounds[bucket + 1]);
+        let within = if high > low {
+            (value - low) / (high - low)
+        } else {
+            1.0
+        };
+        (bucket as f64 + within) / buckets
+    }
+}
+
+impl TableStatistics {
+    /// Estimated fraction of documents whose `field` equals a given value.
+    pub fn eq_selectivity(&self, field: &str) -> Option<f64> {
+        let stats = self.fields.get(field)?;
+        Some(stats.non_null_fraction / stats.distinct_values.max(1.0))
+    }
+
+    /// Estimated fraction of documents whose numeric `field` is below `value`, or at or
+    /// above it when `below` is false.
+    pub fn range_selectivity(&self, field: &str, value: f64, below: bool) -> Option<f64> {
+        let stats = self.fields.get(field)?;
+        let fraction = stats.histogram.as_ref()?.fraction_below(value);
+        let fraction = if below { fraction } else { 1.0 - fraction };
+        Some(fraction * stats.non_null_fraction)
+    }
+}
+
+#[derive(Default)]
+struct FieldSample {
+    non_null: u64,
+    values: HashMap<String, u64>,
+    numbers: Vec<f64>,
+}
+
+/// Accumulates sampled documents into table statistics.
+pub struct StatisticsBuilder {
+    row_count: u64,
+    sampled_rows: u64,
+    fields: HashMap<String, FieldSample>,
+}
+
+impl StatisticsBuilder {
+    pub fn new() -> Self {
+        Self {
+            row_count: 0,
+            sampled_rows: 0,
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Count a document that was not sampled.
+    pub fn skip(&mut self) {
+        self.row_count += 1;
+    }
+
+    /// Count a document and add it to the sample.
+    pub fn add(&mut self, doc: &Document) {
+        self.row_count += 1;
+        self.sampled_rows += 1;
+
+        for (name, value) in doc {
+            let sample = self.fields.entry(name.clone()).or_default();
+            let Some(value) = &value.value else {
+                continue;
+            };
+            if matches!(value, datum::Value::Null(_)) {
+                continue;
+            }
+
+            sample.non_null += 1;
+            match value {
+                datum::Value::Int(i) => sample.numbers.push(*i as f64),
+                datum::Value::Float(f) if f.is_finite() => sample.numbers.push(*f),
+                datum::Value::Decimal(d) => {
+                    if let Ok(d) = Decimal::parse(&d.value) {
+                        sample.numbers.push(d.to_f64());
+                    }
+                }
+                _ => {}
+            }
+            if !matches!(value, datum::Value::Object(_) | datum::Value::Array(_)) {
+                *sample.values.entry(format!("{value:?}")).or_default() += 1;
+            }
+        }
+    }
+
+    pub fn build(self) -> TableStatistics {
+        let (row_count, sampled_rows) = (self.row_count, self.sampled_rows);
+        let fields = self
+            .fields
+            .into_iter()
+            .map(|(name, mut sample)| {
+                let non_null_fraction = if sampled_rows == 0 {
+                    0.0
+                } else {
+                    sample.non_null as f64 / sampled_rows as f64
+                };
+                let distinct_values =
+                    estimate_distinct(&sample, non_null_fraction * row_count as f64);
+
+                sample.numbers.sort_unstable_by(f64::total_cmp);
+                let histogram = Histogram::from_sorted(&sample.numbers, HISTOGRAM_BUCKETS);
+
+                let stats = FieldStatistics {
+                    non_null_fraction,
+                    distinct_values,
+                    histogram,
+                };
+                (name, stats)
+            })
+            .collect();
+
+        TableStatistics {
+            row_count,
+            sampled_rows,
+            fields,
+        }
+    }
+}
+
+impl Default for StatisticsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Scale the distinct values seen in a sample up to the whole table with the Duj1
+/// estimator of Haas and Stokes, which leans on how many values were seen only once.
+fn estimate_distinct(sample: &FieldSample, non_null_rows: f64) -> f64 {
+    let seen = sample.values.values().sum::<u64>() as f64;
+    let distinct = sample.values.len() as f64;
+    if seen == 0.0 || seen >= non_null_rows {
+        return distinct;
+    }
+
+    let singletons = sample.values.values().filter(|&&count| count == 1).count() as f64;
+    let estimate = seen * distinct / (seen - singletons + singletons * seen / non_null_rows);
+    estimate.clamp(distinct, non_null_rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Datum;
+
+    fn doc(fields: &[(&str, datum::Value)]) -> Document {
+        fields
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    Datum {
+                        value: Some(value.clone()),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_histogram_fraction_below() {
+        let values: Vec<f64> = (0..100).map(f64::from).collect();
+        let histogram = Histogram::from_sorted(&values, 10).unwrap();
+
+        assert_eq!(histogram.fraction_below(-1.0), 0.0);
+        assert_eq!(histogram.fraction_below(1000.0), 1.0);
+        assert!((histogram.fraction_below(50.0) - 0.5).abs() < 0.02);
+        assert!((histogram.fraction_below(25.0) - 0.25).abs() < 0.02);
+        assert!(Histogram::from_sorted(&[], 10).is_none());
+    }
+
+    #[test]
+    fn test_full_sample_statistics() {
+        let mut builder = StatisticsBuilder::new();
+        for i in 0..100 {
+            let mut fields = vec![
+                ("age", datum::Value::Int(i)),
+                ("status", datum::Value::String(format!("s{}", i % 4))),
+            ];
+            if i % 2 == 0 {
+                fields.push(("nickname", datum::Value::String(format!("n{i}"))));
+            }
+            builder.add(&doc(&fields));
+        }
+        let stats = builder.build();
+
+        assert_eq!(stats.row_count, 100);
+        assert_eq!(stats.sampled_rows, 100);
+        assert_eq!(stats.fields["status"].distinct_values, 4.0);
+        assert_eq!(stats.fields["age"].distinct_values, 100.0);
+        assert_eq!(stats.fields["nickname"].non_null_fraction, 0.5);
+        assert!(stats.fields["status"].histogram.is_none());
+
+        assert_eq!(stats.eq_selectivity("status"), Some(0.25));
+        assert_eq!(stats.eq_selectivity("missing"), None);
+        let below = stats.range_selectivity("age", 20.0, true).unwrap();
+        assert!((below - 0.2).abs() < 0.02);
+        let above = stats.range_selectivity("age", 20.0, false).unwrap();
+        assert!((above - 0.8).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_partial_sample_scales_distinct_values() {
+        let mut builder = StatisticsBuilder::new();
+        for i in 0..1000 {
+            if i % 10 == 0 {
+                builder.add(&doc(&[
+                    ("id", datum::Value::Int(i)),
+                    ("flag", datum::Value::Bool(i % 20 == 0)),
+                ]));
+            } else {
+                builder.skip();
+            }
+        }
+        let stats = builder.build();
+
+        assert_eq!(stats.row_count, 1000);
+        assert_eq!(stats.sampled_rows, 100);
+        // Every sampled id was unique, so ids are assumed unique across the table
+        assert_eq!(stats.fields["id"].distinct_values, 1000.0);
+        // Values seen many times are not scaled up
+        assert_eq!(stats.fields["flag"].distinct_values, 2.0);
+    }
+}
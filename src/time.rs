@@ -0,0 +1,434 @@
+//! Time values and the date and time functions over them.
+//!
+//! A time is an instant, in microseconds since the Unix epoch, together with the UTC
+//! offset it is shown in. Two times are the same value when they are the same instant,
+//! whatever their offsets. Calendar fields are those of the proleptic Gregorian
+//! calendar at the time's offset, and there are no leap seconds.
+
+use crate::ast::Time;
+
+pub const MICROS_PER_SECOND: i64 = 1_000_000;
+
+const MICROS_PER_MINUTE: i64 = 60 * MICROS_PER_SECOND;
+
+const MICROS_PER_DAY: i64 = 24 * 60 * MICROS_PER_MINUTE;
+
+/// Largest UTC offset in minutes, either side of UTC.
+const MAX_OFFSET_MINUTES: i32 = 24 * 60 - 1;
+
+#[derive(Debug)]
+pub struct TimeError(pub String);
+
+impl std::fmt::Display for TimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TimeError {}
+
+/// The calendar fields of a time at its offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Civil {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hours: u32,
+    pub minutes: u32,
+    /// Seconds into the minute, with their fraction.
+    pub seconds: f64,
+    /// Monday is 1 and Sunday is 7.
+    pub day_of_week: u32,
+    /// The first of January is 1.
+    pub day_of_year: u32,
+    /// Microseconds since midnight.
+    pub micros_of_day: i64,
+}
+
+/// The current time, in UTC.
+pub fn now() -> Time {
+    let micros = match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(elapsed) => i64::try_from(elapsed.as_micros()).unwrap_or(i64::MAX),
+        Err(before) => -i64::try_from(before.duration().as_micros()).unwrap_or(i64::MAX),
+    };
+    Time {
+        epoch_micros: micros,
+        offset_minutes: 0,
+    }
+}
+
+/// The time `seconds` after the epoch, in UTC.
+pub fn from_epoch_seconds(seconds: f64) -> Result<Time, TimeError> {
+    let micros = seconds_to_micros(seconds)?;
+    Ok(Time {
+        epoch_micros: micros,
+        offset_minutes: 0,
+    })
+}
+
+/// Seconds since the epoch, with their fraction.
+pub fn to_epoch_seconds(time: &Time) -> f64 {
+    time.epoch_micros as f64 / MICROS_PER_SECOND as f64
+}
+
+/// The time `seconds` later, which may be negative, at the same offset.
+pub fn add_seconds(time: &Time, seconds: f64) -> Result<Time, TimeError> {
+    let micros = time
+        .epoch_micros
+        .checked_add(seconds_to_micros(seconds)?)
+        .ok_or_else(|| TimeError("time is out of range".to_string()))?;
+    Ok(Time {
+        epoch_micros: micros,
+        offset_minutes: time.offset_minutes,
+    })
+}
+
+/// Seconds from `start` to `end`, negative if `end` is earlier.
+pub fn seconds_between(start: &Time, end: &Time) -> f64 {
+    (end.epoch_micros as i128 - start.epoch_micros as i128) as f64 / MICROS_PER_SECOND as f64
+}
+
+/// The same instant shown at another offset.
+pub fn in_timezone(time: &Time, offset_minutes: i32) -> Time {
+    Time {
+        epoch_micros: time.epoch_micros,
+        offset_minutes,
+    }
+}
+
+/// Midnight at the start of the time's day, at its offset.
+pub fn date(time: &Time) -> Time {
+    Time {
+        epoch_micros: time.epoch_micros - civil(time).micros_of_day,
+        offset_minutes: time.offset_minutes,
+    }
+}
+
+fn seconds_to_micros(seconds: f64) -> Result<i64, TimeError> {
+    let micros = (seconds * MICROS_PER_SECOND as f64).round();
+    if !micros.is_finite() || micros.abs() >= i64::MAX as f64 {
+        return Err(TimeError(format!("{seconds} seconds is out of range")));
+    }
+    Ok(micros as i64)
+}
+
+fn local_micros(time: &Time) -> i64 {
+    time.epoch_micros
+        .saturating_add(i64::from(time.offset_minutes) * MICROS_PER_MINUTE)
+}
+
+/// The calendar fields of a time at its offset.
+pub fn civil(time: &Time) -> Civil {
+    let local = local_micros(time);
+    let days = local.div_euclid(MICROS_PER_DAY);
+    let micros_of_day = local.rem_euclid(MICROS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let minutes_of_day = micros_of_day / MICROS_PER_MINUTE;
+
+    Civil {
+        year,
+        month,
+        day,
+        hours: (minutes_of_day / 60) as u32,
+        minutes: (minutes_of_day % 60) as u32,
+        seconds: (micros_of_day % MICROS_PER_MINUTE) as f64 / MICROS_PER_SECOND as f64,
+        // The epoch fell on a Thursday
+        day_of_week: ((days + 3).rem_euclid(7) + 1) as u32,
+        day_of_year: (days - days_from_civil(year, 1, 1) + 1) as u32,
+        micros_of_day,
+    }
+}
+
+/// Days since the epoch of a date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Count years from March, so the leap day ends the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (i64::from(month) + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The date of a number of days since the epoch.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parse an ISO 8601 date, or date and time, such as `2024-03-01` or
+/// `2024-03-01T12:30:00.250+02:00`. A time without an offset is in UTC.
+pub fn parse_iso8601(text: &str) -> Result<Time, TimeError> {
+    let invalid = || TimeError(format!("{text} is not an ISO 8601 time"));
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+
+    let year = parser.number(4).ok_or_else(invalid)?;
+    parser.expect(b'-').ok_or_else(invalid)?;
+    let month = parser.number(2).ok_or_else(invalid)? as u32;
+    parser.expect(b'-').ok_or_else(invalid)?;
+    let day = parser.number(2).ok_or_else(invalid)? as u32;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+
+    let mut micros_of_day = 0;
+    if parser
+        .expect(b'T')
+        .or_else(|| parser.expect(b' '))
+        .is_some()
+    {
+        let hours = parser.number(2).ok_or_else(invalid)?;
+        parser.expect(b':').ok_or_else(invalid)?;
+        let minutes = parser.number(2).ok_or_else(invalid)?;
+        let mut seconds = 0;
+        let mut fraction = 0;
+        if parser.expect(b':').is_some() {
+            seconds = parser.number(2).ok_or_else(invalid)?;
+            if parser.expect(b'.').is_some() {
+                fraction = parser.fraction().ok_or_else(invalid)?;
+            }
+        }
+        if hours > 23 || minutes > 59 || seconds > 59 {
+            return Err(invalid());
+        }
+        micros_of_day =
+            (hours * 60 + minutes) * MICROS_PER_MINUTE + seconds * MICROS_PER_SECOND + fraction;
+    }
+
+    let offset_minutes = match parser.rest() {
+        "" | "Z" | "z" => 0,
+        rest => parse_offset(rest).map_err(|_| invalid())?,
+    };
+
+    let local = days_from_civil(year, month, day)
+        .checked_mul(MICROS_PER_DAY)
+        .and_then(|micros| micros.checked_add(micros_of_day))
+        .ok_or_else(invalid)?;
+    Ok(Time {
+        epoch_micros: local - i64::from(offset_minutes) * MICROS_PER_MINUTE,
+        offset_minutes,
+    })
+}
+
+/// Parse a UTC offset such as `+02:00`, `-0530`, `+01` or `Z`, in minutes.
+pub fn parse_offset(text: &str) -> Result<i32, TimeError> {
+    let invalid = || TimeError(format!("{text} is not a UTC offset"));
+    if text == "Z" || text == "z" {
+        return Ok(0);
+    }
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+    let sign = match parser.next() {
+        Some(b'+') => 1,
+        Some(b'-') => -1,
+        _ => return Err(invalid()),
+    };
+    let hours = parser.number(2).ok_or_else(invalid)?;
+    parser.expect(b':');
+    let minutes = if parser.rest().is_empty() {
+        0
+    } else {
+        parser.number(2).ok_or_else(invalid)?
+    };
+    if !parser.rest().is_empty() || minutes > 59 {
+        return Err(invalid());
+    }
+    let offset = (hours * 60 + minutes) as i32;
+    if offset > MAX_OFFSET_MINUTES {
+        return Err(invalid());
+    }
+    Ok(sign * offset)
+}
+
+/// Write a UTC offset as `+HH:MM`.
+pub fn format_offset(offset_minutes: i32) -> String {
+    let sign = if offset_minutes < 0 { '-' } else { '+' };
+    let offset = offset_minutes.unsigned_abs();
+    format!("{sign}{:02}:{:02}", offset / 60, offset % 60)
+}
+
+/// Write a time in ISO 8601, with as many fractional digits as it needs.
+pub fn format_iso8601(time: &Time) -> String {
+    let civil = civil(time);
+    let micros = civil.micros_of_day % MICROS_PER_SECOND;
+    let fraction = if micros == 0 {
+        String::new()
+    } else if micros % 1000 == 0 {
+        format!(".{:03}", micros / 1000)
+    } else {
+        format!(".{micros:06}")
+    };
+    let year = if (0..10_000).contains(&civil.year) {
+        format!("{:04}", civil.year)
+    } else {
+        format!("{:+05}", civil.year)
+    };
+    format!(
+        "{year}-{:02}-{:02}T{:02}:{:02}:{:02}{fraction}{}",
+        civil.month,
+        civil.day,
+        civil.hours,
+        civil.minutes,
+        civil.seconds as u32,
+        format_offset(time.offset_minutes)
+    )
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        (self.bytes.get(self.pos) == Some(&byte)).then(|| self.pos += 1)
+    }
+
+    /// Exactly `digits` decimal digits.
+    fn number(&mut self, digits: usize) -> Option<i64> {
+        let text = self.bytes.get(self.pos..self.pos + digits)?;
+        if !text.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        self.pos += digits;
+        Some(
+            text.iter()
+                .fold(0, |value, digit| value * 10 + i64::from(digit - b'0')),
+        )
+    }
+
+    /// The digits of a fraction of a second, in microseconds. Digits past the sixth
+    /// are dropped.
+    fn fraction(&mut self) -> Option<i64> {
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        let digits = &self.bytes[start..self.pos];
+        if digits.is_empty() {
+            return None;
+        }
+        Some(
+            (0..6)
+                .map(|i| digits.get(i).map_or(0, |digit| i64::from(digit - b'0')))
+                .fold(0, |value, digit| value * 10 + digit),
+        )
+    }
+
+    fn rest(&self) -> &str {
+        std::str::from_utf8(&self.bytes[self.pos..]).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(text: &str) -> Time {
+        parse_iso8601(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        let t = time("2024-02-29T13:45:30.250+02:00");
+        assert_eq!(t.offset_minutes, 120);
+        assert_eq!(t.epoch_micros, 1_709_207_130_250_000);
+        assert_eq!(format_iso8601(&t), "2024-02-29T13:45:30.250+02:00");
+
+        assert_eq!(time("1970-01-01").epoch_micros, 0);
+        assert_eq!(time("1970-01-01T00:00:01Z").epoch_micros, MICROS_PER_SECOND);
+        assert_eq!(
+            format_iso8601(&time("1969-12-31 23:59:59.000001-0530")),
+            "1969-12-31T23:59:59.000001-05:30"
+        );
+        assert_eq!(
+            format_iso8601(&from_epoch_seconds(-62_135_596_800.0).unwrap()),
+            "0001-01-01T00:00:00+00:00"
+        );
+
+        for invalid in [
+            "2023-02-29",
+            "2024-13-01",
+            "2024-1-01",
+            "2024-01-01T24:00",
+            "2024-01-01T10:00+25:00",
+            "2024-01-01T10:00 UTC",
+            "yesterday",
+        ] {
+            assert!(parse_iso8601(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_civil() {
+        let c = civil(&time("2024-03-01T23:30:15.5-01:00"));
+        assert_eq!((c.year, c.month, c.day), (2024, 3, 1));
+        assert_eq!((c.hours, c.minutes, c.seconds), (23, 30, 15.5));
+        assert_eq!(c.day_of_week, 5);
+        assert_eq!(c.day_of_year, 61);
+
+        // The same instant is the next day in UTC
+        let utc = in_timezone(&time("2024-03-01T23:30:15.5-01:00"), 0);
+        let c = civil(&utc);
+        assert_eq!((c.month, c.day, c.hours), (3, 2, 0));
+        assert_eq!(c.day_of_week, 6);
+
+        let c = civil(&time("1900-12-31"));
+        assert_eq!((c.year, c.day_of_year, c.day_of_week), (1900, 365, 1));
+        for days in [-800_000, -1, 0, 59, 11_016, 800_000] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let t = time("2024-12-31T23:00:00+01:00");
+        let later = add_seconds(&t, 3600.0).unwrap();
+        assert_eq!(format_iso8601(&later), "2025-01-01T00:00:00+01:00");
+        assert_eq!(seconds_between(&t, &later), 3600.0);
+        assert_eq!(format_iso8601(&date(&later)), "2025-01-01T00:00:00+01:00");
+        assert_eq!(to_epoch_seconds(&from_epoch_seconds(1.5).unwrap()), 1.5);
+        assert!(add_seconds(&t, f64::INFINITY).is_err());
+        assert!(from_epoch_seconds(1e300).is_err());
+
+        assert_eq!(parse_offset("+05:30").unwrap(), 330);
+        assert_eq!(parse_offset("-08").unwrap(), -480);
+        assert_eq!(format_offset(-330), "-05:30");
+        assert!(parse_offset("05:00").is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! UUID values.
+//!
+//! A UUID is held as its 16 bytes and written in the usual lowercase form, such as
+//! `123e4567-e89b-12d3-a456-426614174000`, so UUIDs sort by their bytes in the order of
+//! their text. Strings in that form, in either case and with or without the hyphens,
+//! convert to UUIDs, and so do 16 bytes of binary.
+
+use crate::ast::Uuid;
+
+/// Bytes in a UUID.
+pub const UUID_LEN: usize = 16;
+
+#[derive(Debug)]
+pub struct UuidError(pub String);
+
+impl std::fmt::Display for UuidError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UuidError {}
+
+/// Parse a UUID written as 32 hexadecimal digits, with or without the four hyphens.
+pub fn parse(text: &str) -> Result<Uuid, UuidError> {
+    let invalid = || UuidError(format!("{text} is not a UUID"));
+    let hex: Vec<u8> = if text.len() == 36 {
+        let bytes = text.as_bytes();
+        if [8, 13, 18, 23].iter().any(|&at| bytes[at] != b'-') {
+            return Err(invalid());
+        }
+        bytes.iter().copied().filter(|&b| b != b'-').collect()
+    } else {
+        text.as_bytes().to_vec()
+    };
+    if hex.len() != 2 * UUID_LEN || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return Err(invalid());
+    }
+
+    let digit = |b: u8| (b as char).to_digit(16).unwrap_or_default() as u8;
+    let value = hex
+        .chunks(2)
+        .map(|pair| digit(pair[0]) << 4 | digit(pair[1]))
+        .collect();
+    Ok(Uuid { value })
+}
+
+/// The UUID of 16 bytes.
+pub fn from_bytes(bytes: &[u8]) -> Result<Uuid, UuidError> {
+    if bytes.len() != UUID_LEN {
+        return Err(UuidError(format!(
+            "a UUID has {UUID_LEN} bytes, not {}",
+            bytes.len()
+        )));
+    }
+    Ok(Uuid {
+        value: bytes.to_vec(),
+    })
+}
+
+/// Write a UUID in lowercase with hyphens, or just its digits if it isn't 16 bytes.
+pub fn format(uuid: &Uuid) -> String {
+    let mut text = String::with_capacity(36);
+    for (i, byte) in uuid.value.iter().enumerate() {
+        if uuid.value.len() == UUID_LEN && [4, 6, 8, 10].contains(&i) {
+            text.push('-');
+        }
+        text.push_str(&format!("{byte:02x}"));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let text = "123e4567-e89b-12d3-a456-426614174000";
+        let uuid = parse(text).unwrap();
+        assert_eq!(uuid.value.len(), UUID_LEN);
+        assert_eq!(uuid.value[..2], [0x12, 0x3e]);
+        assert_eq!(format(&uuid), text);
+        assert_eq!(parse("123E4567E89B12D3A456426614174000").unwrap(), uuid);
+        assert_eq!(from_bytes(&uuid.value).unwrap(), uuid);
+
+        for invalid in [
+            "",
+            "123e4567-e89b-12d3-a456-42661417400",
+            "123e4567e89b-12d3-a456-4266141740000",
+            "123e4567-e89b-12d3-a456-42661417400g",
+            "+23e4567-e89b-12d3-a456-426614174000",
+        ] {
+            assert!(parse(invalid).is_err(), "{invalid}");
+        }
+        assert!(from_bytes(&[0; 15]).is_err());
+    }
+
+    #[test]
+    fn test_text_order_is_byte_order() {
+        let mut texts = [
+            "ffffffff-0000-0000-0000-000000000000",
+            "00000000-0000-0000-0000-000000000001",
+            "0a000000-0000-0000-0000-000000000000",
+            "00000000-0000-0000-0000-000000000000",
+        ];
+        let mut uuids: Vec<_> = texts.iter().map(|text| parse(text).unwrap()).collect();
+        texts.sort();
+        uuids.sort_by(|a, b| a.value.cmp(&b.value));
+        assert_eq!(uuids.iter().map(format).collect::<Vec<_>>(), texts);
+    }
+}
@@ -24,12 +24,445 @@ pub fn create_database_list_query() -> proto::Query {
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::DatabaseList(proto::DatabaseList {})),
     }
 }
 
+/// Helper function to create a replication status query
+#[allow(dead_code)]
+pub fn create_replication_status_query() -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::ReplicationStatus(
+            proto::ReplicationStatus {},
+        )),
+    }
+}
+
+/// Helper function to create a cluster status query
+#[allow(dead_code)]
+pub fn create_cluster_status_query() -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::ClusterStatus(proto::ClusterStatus {})),
+    }
+}
+
+/// Helper function to create a leadership transfer query
+#[allow(dead_code)]
+pub fn create_transfer_leadership_query(node_id: Option<u64>) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::ClusterTransferLeadership(
+            proto::ClusterTransferLeadership { node_id },
+        )),
+    }
+}
+
+/// Helper function to create a rebalance query
+#[allow(dead_code)]
+pub fn create_rebalance_query(
+    database_name: &str,
+    table_name: &str,
+    shards: Option<u32>,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::Rebalance(proto::Rebalance {
+            table: Some(proto::TableRef {
+                database: Some(proto::DatabaseRef {
+                    name: database_name.to_string(),
+                }),
+                name: table_name.to_string(),
+            }),
+            shards,
+        })),
+    }
+}
+
+/// Helper function to create a full-text index create query
+#[allow(dead_code)]
+pub fn create_index_create_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    fields: Vec<&str>,
+    stemming: bool,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::IndexCreate(Box::new(
+            proto::IndexCreate {
+                table: Some(proto::TableRef {
+                    database: Some(proto::DatabaseRef {
+                        name: database_name.to_string(),
+                    }),
+                    name: table_name.to_string(),
+                }),
+                name: index_name.to_string(),
+                kind: Some(proto::index_create::Kind::FullText(proto::FullTextIndex {
+                    fields: fields
+                        .into_iter()
+                        .map(|field| proto::FieldRef {
+                            path: field.split('.').map(str::to_string).collect(),
+                            separator: ".".to_string(),
+                        })
+                        .collect(),
+                    stemming,
+                })),
+            },
+        ))),
+    }
+}
+
+/// Helper function to create a geospatial index create query
+#[allow(dead_code)]
+pub fn create_geo_index_create_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    field: &str,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::IndexCreate(Box::new(
+            proto::IndexCreate {
+                table: Some(proto::TableRef {
+                    database: Some(proto::DatabaseRef {
+                        name: database_name.to_string(),
+                    }),
+                    name: table_name.to_string(),
+                }),
+                name: index_name.to_string(),
+                kind: Some(proto::index_create::Kind::Geo(proto::GeoIndex {
+                    field: Some(proto::FieldRef {
+                        path: field.split('.').map(str::to_string).collect(),
+                        separator: ".".to_string(),
+                    }),
+                })),
+            },
+        ))),
+    }
+}
+
+/// Helper function to create a vector index create query
+#[allow(dead_code)]
+pub fn create_vector_index_create_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    field: &str,
+    dimension: u32,
+    metric: proto::vector_index::Metric,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::IndexCreate(Box::new(
+            proto::IndexCreate {
+                table: Some(proto::TableRef {
+                    database: Some(proto::DatabaseRef {
+                        name: database_name.to_string(),
+                    }),
+                    name: table_name.to_string(),
+                }),
+                name: index_name.to_string(),
+                kind: Some(proto::index_create::Kind::Vector(proto::VectorIndex {
+                    field: Some(proto::FieldRef {
+                        path: field.split('.').map(str::to_string).collect(),
+                        separator: ".".to_string(),
+                    }),
+                    dimension,
+                    metric: metric.into(),
+                    lists: None,
+                })),
+            },
+        ))),
+    }
+}
+
+/// Helper function to create a secondary index create query over one or more fields
+#[allow(dead_code)]
+pub fn create_secondary_index_create_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    fields: &[&str],
+    multi: bool,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::IndexCreate(Box::new(
+            proto::IndexCreate {
+                table: Some(proto::TableRef {
+                    database: Some(proto::DatabaseRef {
+                        name: database_name.to_string(),
+                    }),
+                    name: table_name.to_string(),
+                }),
+                name: index_name.to_string(),
+                kind: Some(proto::index_create::Kind::Secondary(Box::new(
+                    proto::SecondaryIndex {
+                        fields: fields
+                            .iter()
+                            .map(|field| proto::FieldRef {
+                                path: field.split('.').map(str::to_string).collect(),
+                                separator: ".".to_string(),
+                            })
+                            .collect(),
+                        expression: None,
+                        multi,
+                        unique: false,
+                    },
+                ))),
+            },
+        ))),
+    }
+}
+
+/// Helper function to create a unique secondary index create query over one field
+#[allow(dead_code)]
+pub fn create_unique_index_create_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    field: &str,
+) -> proto::Query {
+    let mut query =
+        create_secondary_index_create_query(database_name, table_name, index_name, &[field], false);
+    if let Some(proto::query::Kind::IndexCreate(index_create)) = &mut query.kind
+        && let Some(proto::index_create::Kind::Secondary(secondary)) = &mut index_create.kind
+    {
+        secondary.unique = true;
+    }
+    query
+}
+
+/// Helper function to create an index drop query
+#[allow(dead_code)]
+pub fn create_index_drop_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::IndexDrop(proto::IndexDrop {
+            table: Some(proto::TableRef {
+                database: Some(proto::DatabaseRef {
+                    name: database_name.to_string(),
+                }),
+                name: table_name.to_string(),
+            }),
+            name: index_name.to_string(),
+        })),
+    }
+}
+
+/// Helper function to create an index list query
+#[allow(dead_code)]
+pub fn create_index_list_query(database_name: &str, table_name: &str) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::IndexList(proto::IndexList {
+            table: Some(proto::TableRef {
+                database: Some(proto::DatabaseRef {
+                    name: database_name.to_string(),
+                }),
+                name: table_name.to_string(),
+            }),
+        })),
+    }
+}
+
+/// Helper function to create a full-text search query
+#[allow(dead_code)]
+pub fn create_search_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    text: &str,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::Search(proto::Search {
+            table: Some(proto::TableRef {
+                database: Some(proto::DatabaseRef {
+                    name: database_name.to_string(),
+                }),
+                name: table_name.to_string(),
+            }),
+            index: index_name.to_string(),
+            query: text.to_string(),
+        })),
+    }
+}
+
+/// Helper function to create a point geometry datum
+#[allow(dead_code)]
+pub fn create_point_datum(longitude: f64, latitude: f64) -> proto::Datum {
+    proto::Datum {
+        value: Some(proto::datum::Value::Geometry(proto::Geometry {
+            kind: Some(proto::geometry::Kind::Point(proto::Point {
+                longitude,
+                latitude,
+            })),
+        })),
+    }
+}
+
+/// Helper function to create a query for the documents intersecting a geometry
+#[allow(dead_code)]
+pub fn create_get_intersecting_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    geometry: proto::Geometry,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::GetIntersecting(
+            proto::GetIntersecting {
+                table: Some(proto::TableRef {
+                    database: Some(proto::DatabaseRef {
+                        name: database_name.to_string(),
+                    }),
+                    name: table_name.to_string(),
+                }),
+                index: index_name.to_string(),
+                geometry: Some(geometry),
+            },
+        )),
+    }
+}
+
+/// Helper function to create a query for the documents nearest to a point
+#[allow(dead_code)]
+pub fn create_get_nearest_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    point: proto::Point,
+    max_dist: Option<f64>,
+    max_results: Option<u32>,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::GetNearest(proto::GetNearest {
+            table: Some(proto::TableRef {
+                database: Some(proto::DatabaseRef {
+                    name: database_name.to_string(),
+                }),
+                name: table_name.to_string(),
+            }),
+            index: index_name.to_string(),
+            point: Some(point),
+            max_dist,
+            max_results,
+        })),
+    }
+}
+
+/// Helper function to create a query for the documents nearest to a vector
+#[allow(dead_code)]
+pub fn create_nearest_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    vector: Vec<f64>,
+    k: u32,
+    filter: Option<proto::Expression>,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::Nearest(Box::new(proto::Nearest {
+            table: Some(proto::TableRef {
+                database: Some(proto::DatabaseRef {
+                    name: database_name.to_string(),
+                }),
+                name: table_name.to_string(),
+            }),
+            index: index_name.to_string(),
+            vector,
+            k,
+            filter: filter.map(Box::new),
+            probes: None,
+        }))),
+    }
+}
+
 /// Helper function to create a database create query
 #[allow(dead_code)]
 pub fn create_database_create_query(database_name: &str) -> proto::Query {
@@ -37,6 +470,7 @@ pub fn create_database_create_query(database_name: &str) -> proto::Query {
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::DatabaseCreate(proto::DatabaseCreate {
@@ -52,6 +486,7 @@ pub fn create_database_drop_query(database_name: &str) -> proto::Query {
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::DatabaseDrop(proto::DatabaseDrop {
@@ -67,6 +502,7 @@ pub fn create_table_list_query(database_name: &str) -> proto::Query {
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::TableList(proto::TableList {
@@ -80,10 +516,21 @@ pub fn create_table_list_query(database_name: &str) -> proto::Query {
 /// Helper function to create a table create query
 #[allow(dead_code)]
 pub fn create_table_create_query(database_name: &str, table_name: &str) -> proto::Query {
+    create_sharded_table_create_query(database_name, table_name, None)
+}
+
+/// Helper function to create a table create query splitting the table into shards
+#[allow(dead_code)]
+pub fn create_sharded_table_create_query(
+    database_name: &str,
+    table_name: &str,
+    shards: Option<u32>,
+) -> proto::Query {
     proto::Query {
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::TableCreate(proto::TableCreate {
@@ -93,6 +540,8 @@ pub fn create_table_create_query(database_name: &str, table_name: &str) -> proto
                 }),
                 name: table_name.to_string(),
             }),
+            options: None,
+            shards,
         })),
     }
 }
@@ -104,6 +553,7 @@ pub fn create_table_drop_query(database_name: &str, table_name: &str) -> proto::
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::TableDrop(proto::TableDrop {
@@ -137,6 +587,16 @@ pub async fn send_envelope_to_server(
     stream: &mut TcpStream,
     envelope: &proto::Envelope,
 ) -> Result<proto::Envelope, Box<dyn std::error::Error + Send + Sync>> {
+    write_envelope_to_server(stream, envelope).await?;
+    read_envelope_from_server(stream).await
+}
+
+/// Helper function to send an envelope to the server without waiting for its response
+#[allow(dead_code)]
+pub async fn write_envelope_to_server(
+    stream: &mut TcpStream,
+    envelope: &proto::Envelope,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Encode the envelope
     let mut envelope_bytes = Vec::new();
     envelope.encode(&mut envelope_bytes)?;
@@ -147,7 +607,14 @@ pub async fn send_envelope_to_server(
     message.extend(envelope_bytes);
 
     stream.write_all(&message).await?;
+    Ok(())
+}
 
+/// Helper function to receive the next response envelope from the server
+#[allow(dead_code)]
+pub async fn read_envelope_from_server(
+    stream: &mut TcpStream,
+) -> Result<proto::Envelope, Box<dyn std::error::Error + Send + Sync>> {
     // Read response length
     let mut len_buf = [0u8; 4];
     stream.read_exact(&mut len_buf).await?;
@@ -378,6 +845,289 @@ pub fn decode_response_payload(
                             })),
                         })
                     }
+                    Some(proto::query_result::Result::Analyze(analyze_result)) => {
+                        Ok(proto::Datum {
+                            value: Some(proto::datum::Value::Object(proto::DatumObject {
+                                fields: std::collections::HashMap::from([
+                                    (
+                                        "rows".to_string(),
+                                        proto::Datum {
+                                            value: Some(proto::datum::Value::Int(
+                                                analyze_result.rows as i64,
+                                            )),
+                                        },
+                                    ),
+                                    (
+                                        "sampled".to_string(),
+                                        proto::Datum {
+                                            value: Some(proto::datum::Value::Int(
+                                                analyze_result.sampled as i64,
+                                            )),
+                                        },
+                                    ),
+                                ]),
+                            })),
+                        })
+                    }
+                    Some(proto::query_result::Result::ReplicationStatus(status)) => {
+                        let int = |value: u64| proto::Datum {
+                            value: Some(proto::datum::Value::Int(value as i64)),
+                        };
+                        let mut fields = std::collections::HashMap::from([
+                            (
+                                "role".to_string(),
+                                proto::Datum {
+                                    value: Some(proto::datum::Value::String(status.role)),
+                                },
+                            ),
+                            (
+                                "primary".to_string(),
+                                proto::Datum {
+                                    value: Some(proto::datum::Value::String(status.primary)),
+                                },
+                            ),
+                            ("applied_sequence".to_string(), int(status.applied_sequence)),
+                            ("primary_sequence".to_string(), int(status.primary_sequence)),
+                            ("lag".to_string(), int(status.lag)),
+                        ]);
+                        if let Some(last_contact_ms) = status.last_contact_ms {
+                            fields.insert("last_contact_ms".to_string(), int(last_contact_ms));
+                        }
+                        Ok(proto::Datum {
+                            value: Some(proto::datum::Value::Object(proto::DatumObject { fields })),
+                        })
+                    }
+                    Some(proto::query_result::Result::ClusterStatus(status)) => {
+                        let int = |value: u64| proto::Datum {
+                            value: Some(proto::datum::Value::Int(value as i64)),
+                        };
+                        let string = |value: String| proto::Datum {
+                            value: Some(proto::datum::Value::String(value)),
+                        };
+                        let members = status
+                            .members
+                            .into_iter()
+                            .map(|member| proto::Datum {
+                                value: Some(proto::datum::Value::Object(proto::DatumObject {
+                                    fields: std::collections::HashMap::from([
+                                        ("id".to_string(), int(member.id)),
+                                        ("address".to_string(), string(member.address)),
+                                    ]),
+                                })),
+                            })
+                            .collect();
+                        let mut fields = std::collections::HashMap::from([
+                            ("node_id".to_string(), int(status.node_id)),
+                            ("role".to_string(), string(status.role)),
+                            ("term".to_string(), int(status.term)),
+                            ("commit_index".to_string(), int(status.commit_index)),
+                            ("applied_index".to_string(), int(status.applied_index)),
+                            (
+                                "members".to_string(),
+                                proto::Datum {
+                                    value: Some(proto::datum::Value::Array(proto::DatumArray {
+                                        items: members,
+                                        element_type: String::new(),
+                                    })),
+                                },
+                            ),
+                        ]);
+                        if let Some(leader) = status.leader {
+                            fields.insert("leader_id".to_string(), int(leader.id));
+                            fields.insert("leader_address".to_string(), string(leader.address));
+                        }
+                        Ok(proto::Datum {
+                            value: Some(proto::datum::Value::Object(proto::DatumObject { fields })),
+                        })
+                    }
+                    Some(proto::query_result::Result::Search(search_result)) => Ok(proto::Datum {
+                        value: Some(proto::datum::Value::Object(proto::DatumObject {
+                            fields: std::collections::HashMap::from([
+                                (
+                                    "documents".to_string(),
+                                    proto::Datum {
+                                        value: Some(proto::datum::Value::Array(
+                                            proto::DatumArray {
+                                                items: search_result.documents,
+                                                element_type: String::new(),
+                                            },
+                                        )),
+                                    },
+                                ),
+                                (
+                                    "scores".to_string(),
+                                    proto::Datum {
+                                        value: Some(proto::datum::Value::Array(
+                                            proto::DatumArray {
+                                                items: search_result
+                                                    .scores
+                                                    .into_iter()
+                                                    .map(|score| proto::Datum {
+                                                        value: Some(proto::datum::Value::Float(
+                                                            score,
+                                                        )),
+                                                    })
+                                                    .collect(),
+                                                element_type: "float".to_string(),
+                                            },
+                                        )),
+                                    },
+                                ),
+                            ]),
+                        })),
+                    }),
+                    Some(proto::query_result::Result::Between(result)) => Ok(proto::Datum {
+                        value: Some(proto::datum::Value::Array(proto::DatumArray {
+                            items: result.documents,
+                            element_type: String::new(),
+                        })),
+                    }),
+                    Some(proto::query_result::Result::GetIntersecting(result)) => {
+                        Ok(proto::Datum {
+                            value: Some(proto::datum::Value::Array(proto::DatumArray {
+                                items: result.documents,
+                                element_type: String::new(),
+                            })),
+                        })
+                    }
+                    Some(proto::query_result::Result::GetNearest(proto::GetNearestResult {
+                        documents,
+                        distances,
+                    }))
+                    | Some(proto::query_result::Result::Nearest(proto::NearestResult {
+                        documents,
+                        distances,
+                    })) => Ok(proto::Datum {
+                        value: Some(proto::datum::Value::Object(proto::DatumObject {
+                            fields: std::collections::HashMap::from([
+                                (
+                                    "documents".to_string(),
+                                    proto::Datum {
+                                        value: Some(proto::datum::Value::Array(
+                                            proto::DatumArray {
+                                                items: documents,
+                                                element_type: String::new(),
+                                            },
+                                        )),
+                                    },
+                                ),
+                                (
+                                    "distances".to_string(),
+                                    proto::Datum {
+                                        value: Some(proto::datum::Value::Array(
+                                            proto::DatumArray {
+                                                items: distances
+                                                    .into_iter()
+                                                    .map(|distance| proto::Datum {
+                                                        value: Some(proto::datum::Value::Float(
+                                                            distance,
+                                                        )),
+                                                    })
+                                                    .collect(),
+                                                element_type: "float".to_string(),
+                                            },
+                                        )),
+                                    },
+                                ),
+                            ]),
+                        })),
+                    }),
+                    Some(proto::query_result::Result::IndexCreate(index_create_result)) => {
+                        Ok(proto::Datum {
+                            value: Some(proto::datum::Value::Object(proto::DatumObject {
+                                fields: std::collections::HashMap::from([(
+                                    "created".to_string(),
+                                    proto::Datum {
+                                        value: Some(proto::datum::Value::Int(
+                                            index_create_result.created as i64,
+                                        )),
+                                    },
+                                )]),
+                            })),
+                        })
+                    }
+                    Some(proto::query_result::Result::IndexDrop(index_drop_result)) => {
+                        Ok(proto::Datum {
+                            value: Some(proto::datum::Value::Object(proto::DatumObject {
+                                fields: std::collections::HashMap::from([(
+                                    "dropped".to_string(),
+                                    proto::Datum {
+                                        value: Some(proto::datum::Value::Int(
+                                            index_drop_result.dropped as i64,
+                                        )),
+                                    },
+                                )]),
+                            })),
+                        })
+                    }
+                    Some(proto::query_result::Result::IndexList(index_list_result)) => {
+                        let items = index_list_result
+                            .indexes
+                            .into_iter()
+                            .map(|index| proto::Datum {
+                                value: Some(proto::datum::Value::String(index.name)),
+                            })
+                            .collect();
+                        Ok(proto::Datum {
+                            value: Some(proto::datum::Value::Array(proto::DatumArray {
+                                items,
+                                element_type: "string".to_string(),
+                            })),
+                        })
+                    }
+                    Some(proto::query_result::Result::Rebalance(rebalance_result)) => {
+                        let string = |value: String| proto::Datum {
+                            value: Some(proto::datum::Value::String(value)),
+                        };
+                        let shards = rebalance_result
+                            .shards
+                            .into_iter()
+                            .map(|shard| {
+                                let mut fields = std::collections::HashMap::from([(
+                                    "id".to_string(),
+                                    proto::Datum {
+                                        value: Some(proto::datum::Value::Int(shard.id as i64)),
+                                    },
+                                )]);
+                                if let Some(start_key) = shard.start_key {
+                                    fields.insert("start_key".to_string(), string(start_key));
+                                }
+                                if let Some(end_key) = shard.end_key {
+                                    fields.insert("end_key".to_string(), string(end_key));
+                                }
+                                proto::Datum {
+                                    value: Some(proto::datum::Value::Object(proto::DatumObject {
+                                        fields,
+                                    })),
+                                }
+                            })
+                            .collect();
+                        Ok(proto::Datum {
+                            value: Some(proto::datum::Value::Object(proto::DatumObject {
+                                fields: std::collections::HashMap::from([
+                                    (
+                                        "moved".to_string(),
+                                        proto::Datum {
+                                            value: Some(proto::datum::Value::Int(
+                                                rebalance_result.moved as i64,
+                                            )),
+                                        },
+                                    ),
+                                    (
+                                        "shards".to_string(),
+                                        proto::Datum {
+                                            value: Some(proto::datum::Value::Array(
+                                                proto::DatumArray {
+                                                    items: shards,
+                                                    element_type: String::new(),
+                                                },
+                                            )),
+                                        },
+                                    ),
+                                ]),
+                            })),
+                        })
+                    }
                     Some(proto::query_result::Result::Literal(literal_result)) => literal_result
                         .value
                         .ok_or("Missing value in literal result".into()),
@@ -400,6 +1150,34 @@ pub fn decode_response_payload(
                             }),
                         }
                     }
+                    Some(proto::query_result::Result::DocumentFunction(result)) => {
+                        match result.result {
+                            Some(proto::document_function_result::Result::Value(value)) => {
+                                Ok(value)
+                            }
+                            Some(proto::document_function_result::Result::Collection(
+                                collection,
+                            )) => Ok(proto::Datum {
+                                value: Some(proto::datum::Value::Array(proto::DatumArray {
+                                    items: collection.documents,
+                                    element_type: String::new(),
+                                })),
+                            }),
+                            None => Ok(proto::Datum { value: None }),
+                        }
+                    }
+                    Some(proto::query_result::Result::Sequence(result)) => match result.result {
+                        Some(proto::sequence_result::Result::Value(value)) => Ok(value),
+                        Some(proto::sequence_result::Result::Collection(collection)) => {
+                            Ok(proto::Datum {
+                                value: Some(proto::datum::Value::Array(proto::DatumArray {
+                                    items: collection.documents,
+                                    element_type: String::new(),
+                                })),
+                            })
+                        }
+                        None => Ok(proto::Datum { value: None }),
+                    },
                     None => Err("No query result found".into()),
                 },
                 Some(proto::response::Result::Error(error_info)) => Err(format!(
@@ -435,9 +1213,15 @@ pub fn decode_response_payload(
                 Some(proto::response::Result::Plan(_)) => {
                     Err("Query plan responses not supported in this helper".into())
                 }
+                Some(proto::response::Result::Replication(_)) => {
+                    Err("Replication responses not supported in this helper".into())
+                }
                 Some(proto::response::Result::AuthResult(_)) => {
                     Err("Auth result responses not supported in this helper".into())
                 }
+                Some(proto::response::Result::Prepared(prepared)) => Ok(proto::Datum {
+                    value: Some(proto::datum::Value::String(prepared.handle)),
+                }),
                 None => Err("No result in response".into()),
             }
         }
@@ -451,12 +1235,71 @@ pub fn decode_response_payload(
 }
 
 /// Helper function to connect to the test server
+#[allow(dead_code)]
 pub async fn connect_to_server() -> Result<TcpStream, Box<dyn std::error::Error + Send + Sync>> {
     let server_addr = "127.0.0.1:6090";
     let stream = TcpStream::connect(server_addr).await?;
     Ok(stream)
 }
 
+/// Helper function to reserve a free local address for a server process
+#[allow(dead_code)]
+pub fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to find a free port");
+    listener.local_addr().unwrap().to_string()
+}
+
+/// A server process with its own directory, killed when dropped. The data directory is
+/// inside it, so that files the server keeps beside its data are removed as well.
+#[allow(dead_code)]
+pub struct Server {
+    pub address: String,
+    process: std::process::Child,
+    _dir: tempfile::TempDir,
+}
+
+#[allow(dead_code)]
+impl Server {
+    /// Start a server on `address` with extra `start` arguments.
+    pub fn start(address: String, args: &[String]) -> Self {
+        let dir = tempfile::TempDir::new().expect("Failed to create temp dir");
+        let process = std::process::Command::new(env!("CARGO_BIN_EXE_rulodb"))
+            .arg("start")
+            .arg("--address")
+            .arg(&address)
+            .arg("--data-dir")
+            .arg(dir.path().join("data"))
+            .args(args)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .expect("Failed to start server");
+
+        Self {
+            address,
+            process,
+            _dir: dir,
+        }
+    }
+
+    pub async fn connect(&self) -> TcpStream {
+        for _ in 0..100 {
+            if let Ok(stream) = TcpStream::connect(&self.address).await {
+                return stream;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("Server on {} did not start", self.address);
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
 /// Helper function to create an insert query
 #[allow(dead_code)]
 pub fn create_insert_query(
@@ -468,6 +1311,7 @@ pub fn create_insert_query(
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Insert(Box::new(proto::Insert {
@@ -484,6 +1328,7 @@ pub fn create_insert_query(
                 })),
             })),
             documents,
+            durability: proto::Durability::Default.into(),
         }))),
     }
 }
@@ -495,6 +1340,7 @@ pub fn create_table_query(database_name: &str, table_name: &str) -> proto::Query
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Table(proto::Table {
@@ -515,6 +1361,7 @@ pub fn create_get_query(database_name: &str, table_name: &str, key: proto::Datum
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Get(Box::new(proto::Get {
@@ -584,6 +1431,7 @@ pub fn create_delete_query(database_name: &str, table_name: &str) -> proto::Quer
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Delete(Box::new(proto::Delete {
@@ -599,6 +1447,7 @@ pub fn create_delete_query(database_name: &str, table_name: &str) -> proto::Quer
                     }),
                 })),
             })),
+            durability: proto::Durability::Default.into(),
         }))),
     }
 }
@@ -614,6 +1463,7 @@ pub fn create_update_query(
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Update(Box::new(proto::Update {
@@ -630,10 +1480,46 @@ pub fn create_update_query(
                 })),
             })),
             patch: Some(patch),
+            durability: proto::Durability::Default.into(),
+            operations: vec![],
         }))),
     }
 }
 
+/// Helper function to create an update query that applies array operations
+#[allow(dead_code)]
+pub fn create_array_update_query(
+    database_name: &str,
+    table_name: &str,
+    operations: Vec<proto::ArrayOp>,
+) -> proto::Query {
+    let mut query = create_update_query(database_name, table_name, proto::DatumObject::default());
+    if let Some(proto::query::Kind::Update(update)) = &mut query.kind {
+        update.patch = None;
+        update.operations = operations;
+    }
+    query
+}
+
+/// Helper function to create an array operation on a field
+#[allow(dead_code)]
+pub fn create_array_op(
+    op: proto::array_op::Operator,
+    field: &str,
+    value: Option<proto::Datum>,
+    index: i64,
+) -> proto::ArrayOp {
+    proto::ArrayOp {
+        op: op.into(),
+        field: Some(proto::FieldRef {
+            path: field.split('.').map(String::from).collect(),
+            separator: ".".to_string(),
+        }),
+        value,
+        index,
+    }
+}
+
 /// Helper function to create a get_all query
 #[allow(dead_code)]
 pub fn create_get_all_query(
@@ -645,6 +1531,7 @@ pub fn create_get_all_query(
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::GetAll(Box::new(proto::GetAll {
@@ -661,10 +1548,57 @@ pub fn create_get_all_query(
                 })),
             })),
             keys,
+            index: String::new(),
+            key_expressions: vec![],
         }))),
     }
 }
 
+/// Helper function to create a get_all query looking values up in a secondary index
+#[allow(dead_code)]
+pub fn create_get_all_by_index_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    values: Vec<proto::Datum>,
+) -> proto::Query {
+    let mut query = create_get_all_query(database_name, table_name, values);
+    if let Some(proto::query::Kind::GetAll(get_all)) = &mut query.kind {
+        get_all.index = index_name.to_string();
+    }
+    query
+}
+
+/// Helper function to create a query for a range of a secondary index
+#[allow(dead_code)]
+pub fn create_between_query(
+    database_name: &str,
+    table_name: &str,
+    index_name: &str,
+    lower: Option<proto::Datum>,
+    upper: Option<proto::Datum>,
+) -> proto::Query {
+    proto::Query {
+        options: Some(proto::QueryOptions {
+            timeout_ms: 30000,
+            explain: false,
+            read_mode: proto::ReadMode::Single.into(),
+        }),
+        cursor: None,
+        kind: Some(proto::query::Kind::Between(proto::Between {
+            table: Some(proto::TableRef {
+                database: Some(proto::DatabaseRef {
+                    name: database_name.to_string(),
+                }),
+                name: table_name.to_string(),
+            }),
+            index: index_name.to_string(),
+            lower,
+            upper,
+        })),
+    }
+}
+
 /// Helper function to create a filter query
 #[allow(dead_code)]
 pub fn create_filter_query(
@@ -676,6 +1610,7 @@ pub fn create_filter_query(
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Filter(Box::new(proto::Filter {
@@ -707,6 +1642,7 @@ pub fn create_order_by_query(
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::OrderBy(Box::new(proto::OrderBy {
@@ -734,6 +1670,7 @@ pub fn create_limit_query(database_name: &str, table_name: &str, count: u32) ->
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Limit(Box::new(proto::Limit {
@@ -761,6 +1698,7 @@ pub fn create_skip_query(database_name: &str, table_name: &str, count: u32) -> p
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Skip(Box::new(proto::Skip {
@@ -788,6 +1726,7 @@ pub fn create_count_query(database_name: &str, table_name: &str) -> proto::Query
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Count(Box::new(proto::Count {
@@ -834,6 +1773,7 @@ pub fn create_pluck_query(
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Pluck(Box::new(proto::Pluck {
@@ -865,6 +1805,7 @@ pub fn create_without_query(
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Without(Box::new(proto::Without {
@@ -939,6 +1880,7 @@ pub fn create_match_expression(
             value: Some(Box::new(value)),
             pattern: pattern.to_string(),
             flags: flags.to_string(),
+            capture: false,
         }))),
     }
 }
@@ -961,6 +1903,100 @@ pub fn create_subquery_expression(query: proto::Query) -> proto::Expression {
     }
 }
 
+/// Helper function to create a time datum from an ISO 8601 string
+#[allow(dead_code)]
+pub fn create_time_datum(iso8601: &str) -> proto::Datum {
+    proto::Datum {
+        value: Some(proto::datum::Value::Time(
+            rulodb::time::parse_iso8601(iso8601).expect("Invalid ISO 8601 time"),
+        )),
+    }
+}
+
+/// Helper function to create a decimal datum
+#[allow(dead_code)]
+pub fn create_decimal_datum(value: &str) -> proto::Datum {
+    proto::Datum {
+        value: Some(proto::datum::Value::Decimal(proto::Decimal {
+            value: value.to_string(),
+        })),
+    }
+}
+
+/// Helper function to create a UUID datum from its text
+#[allow(dead_code)]
+pub fn create_uuid_datum(text: &str) -> proto::Datum {
+    proto::Datum {
+        value: Some(proto::datum::Value::Uuid(
+            rulodb::uuid::parse(text).expect("Invalid UUID"),
+        )),
+    }
+}
+
+/// Helper function to create a time operation expression
+#[allow(dead_code)]
+pub fn create_time_expression(
+    operator: proto::time_op::Operator,
+    args: Vec<proto::Expression>,
+) -> proto::Expression {
+    proto::Expression {
+        expr: Some(proto::expression::Expr::Time(proto::TimeOp {
+            op: operator.into(),
+            args,
+        })),
+    }
+}
+
+/// Helper function to create a document function expression
+#[allow(dead_code)]
+pub fn create_document_expression(
+    operator: proto::document_op::Operator,
+    args: Vec<proto::Expression>,
+    fields: Vec<proto::FieldRef>,
+) -> proto::Expression {
+    proto::Expression {
+        expr: Some(proto::expression::Expr::Document(proto::DocumentOp {
+            op: operator.into(),
+            args,
+            fields,
+        })),
+    }
+}
+
+/// Helper function to create a branch expression from its cases and otherwise value
+#[allow(dead_code)]
+pub fn create_branch_expression(
+    cases: Vec<(proto::Expression, proto::Expression)>,
+    otherwise: proto::Expression,
+) -> proto::Expression {
+    proto::Expression {
+        expr: Some(proto::expression::Expr::Branch(Box::new(
+            proto::BranchExpr {
+                cases: cases
+                    .into_iter()
+                    .map(|(condition, value)| proto::branch_expr::Case {
+                        condition: Some(condition),
+                        value: Some(value),
+                    })
+                    .collect(),
+                otherwise: Some(Box::new(otherwise)),
+            },
+        ))),
+    }
+}
+
+/// Helper function to create an expression raising an error with a message
+#[allow(dead_code)]
+pub fn create_error_expression(message: &str) -> proto::Expression {
+    proto::Expression {
+        expr: Some(proto::expression::Expr::Error(Box::new(proto::ErrorExpr {
+            message: Some(Box::new(create_literal_expression(create_string_datum(
+                message,
+            )))),
+        }))),
+    }
+}
+
 /// Helper function to create a null datum
 #[allow(dead_code)]
 pub fn create_null_datum() -> proto::Datum {
@@ -980,6 +2016,7 @@ pub fn create_binary_datum(data: Vec<u8>) -> proto::Datum {
 }
 
 /// Helper function to validate basic response structure
+#[allow(dead_code)]
 pub fn validate_response_envelope(
     response_envelope: &proto::Envelope,
     expected_query_id: &str,
@@ -1003,6 +2040,7 @@ pub fn validate_response_envelope(
     Ok(())
 }
 
+#[allow(dead_code)]
 pub fn print_error_details(response_envelope: &proto::Envelope) {
     if response_envelope.r#type == proto::MessageType::Error as i32 {
         println!("\n=== SERVER ERROR DETAILS ===");
@@ -1037,6 +2075,12 @@ pub fn print_error_details(response_envelope: &proto::Envelope) {
                 Some(proto::response::Result::Pong(pong_result)) => {
                     println!("  Unexpected Pong result in error response: {pong_result:?}");
                 }
+                Some(proto::response::Result::Replication(updates)) => {
+                    println!("  Unexpected Replication result in error response: {updates:?}");
+                }
+                Some(proto::response::Result::Prepared(prepared)) => {
+                    println!("  Unexpected Prepared result in error response: {prepared:?}");
+                }
                 Some(proto::response::Result::Plan(plan_result)) => {
                     println!("  Plan-based error response:");
                     for (i, node) in plan_result.nodes.iter().enumerate() {
@@ -0,0 +1,175 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// Start a cluster of `size` server processes with fast elections.
+fn start_cluster(size: u64) -> HashMap<u64, Server> {
+    let addresses: Vec<(u64, String)> = (1..=size).map(|id| (id, free_address())).collect();
+    let members = addresses
+        .iter()
+        .map(|(id, address)| format!("{id}={address}"))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    addresses
+        .into_iter()
+        .map(|(id, address)| {
+            let args = [
+                "--node-id".to_string(),
+                id.to_string(),
+                "--cluster-member".to_string(),
+                members.clone(),
+                "--heartbeat-interval".to_string(),
+                "20".to_string(),
+                "--election-timeout".to_string(),
+                "200".to_string(),
+            ];
+            (id, Server::start(address, &args))
+        })
+        .collect()
+}
+
+async fn query(stream: &mut TcpStream, query: &proto::Query) -> proto::Envelope {
+    let envelope = create_envelope("test-cluster", query);
+    send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response")
+}
+
+fn field<'a>(object: &'a proto::DatumObject, name: &str) -> Option<&'a proto::datum::Value> {
+    object
+        .fields
+        .get(name)
+        .and_then(|datum| datum.value.as_ref())
+}
+
+/// Poll the nodes until one of them reports itself as the leader.
+async fn wait_for_leader(streams: &mut HashMap<u64, TcpStream>) -> u64 {
+    for _ in 0..200 {
+        for (id, stream) in streams.iter_mut() {
+            let response = query(stream, &create_cluster_status_query()).await;
+            if let Ok(proto::Datum {
+                value: Some(proto::datum::Value::Object(status)),
+            }) = decode_response_payload(&response)
+            {
+                if field(&status, "role") == Some(&proto::datum::Value::String("leader".into())) {
+                    return *id;
+                }
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("No leader was elected");
+}
+
+/// Poll a node until its local copy of the table holds the document with `key`.
+async fn wait_for_document(
+    stream: &mut TcpStream,
+    database_name: &str,
+    table_name: &str,
+    key: &str,
+) -> proto::DatumObject {
+    let get = create_get_query(database_name, table_name, create_string_datum(key));
+    for _ in 0..100 {
+        let response = query(stream, &get).await;
+        if let Ok(proto::Datum {
+            value: Some(proto::datum::Value::Object(document)),
+        }) = decode_response_payload(&response)
+        {
+            return document;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Document {key} was not replicated");
+}
+
+fn insert_query(database_name: &str, table_name: &str, key: &str) -> proto::Query {
+    create_insert_query(
+        database_name,
+        table_name,
+        vec![create_datum_object(vec![("id", create_string_datum(key))])],
+    )
+}
+
+#[tokio::test]
+async fn test_cluster_fails_over_to_a_new_leader() {
+    let mut servers = start_cluster(3);
+    let mut streams = HashMap::new();
+    for (id, server) in &servers {
+        streams.insert(*id, server.connect().await);
+    }
+
+    let leader = wait_for_leader(&mut streams).await;
+    let database_name = &generate_unique_name("test_cluster");
+    let table_name = "users";
+    for setup in [
+        create_database_create_query(database_name),
+        create_table_create_query(database_name, table_name),
+        insert_query(database_name, table_name, "alice"),
+    ] {
+        let response = query(streams.get_mut(&leader).unwrap(), &setup).await;
+        decode_response_payload(&response).expect("Setup query failed on the leader");
+    }
+
+    // Every node applies the write, and followers point writers at the leader
+    for stream in streams.values_mut() {
+        wait_for_document(stream, database_name, table_name, "alice").await;
+    }
+    let follower = *streams.keys().find(|id| **id != leader).unwrap();
+    let response = query(
+        streams.get_mut(&follower).unwrap(),
+        &insert_query(database_name, table_name, "bob"),
+    )
+    .await;
+    let error = decode_response_payload(&response).expect_err("Follower accepted a write");
+    assert!(
+        error.to_string().contains(&servers[&leader].address),
+        "unexpected error: {error}"
+    );
+
+    // The remaining two nodes elect a new leader and keep accepting writes
+    servers.remove(&leader);
+    streams.remove(&leader);
+    let new_leader = wait_for_leader(&mut streams).await;
+    assert_ne!(new_leader, leader);
+    let response = query(
+        streams.get_mut(&new_leader).unwrap(),
+        &insert_query(database_name, table_name, "carol"),
+    )
+    .await;
+    decode_response_payload(&response).expect("Write failed on the new leader");
+
+    for stream in streams.values_mut() {
+        wait_for_document(stream, database_name, table_name, "carol").await;
+    }
+}
+
+#[tokio::test]
+async fn test_cluster_transfers_leadership() {
+    let servers = start_cluster(3);
+    let mut streams = HashMap::new();
+    for (id, server) in &servers {
+        streams.insert(*id, server.connect().await);
+    }
+
+    let leader = wait_for_leader(&mut streams).await;
+    let target = *streams.keys().find(|id| **id != leader).unwrap();
+    let response = query(
+        streams.get_mut(&leader).unwrap(),
+        &create_transfer_leadership_query(Some(target)),
+    )
+    .await;
+    decode_response_payload(&response).expect("Leadership transfer failed");
+
+    for _ in 0..100 {
+        if wait_for_leader(&mut streams).await == target {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Node {target} did not take over the leadership");
+}
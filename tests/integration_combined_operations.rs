@@ -134,6 +134,7 @@ async fn test_filter_and_order_by() {
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::OrderBy(Box::new(proto::OrderBy {
@@ -322,6 +323,7 @@ async fn test_filter_order_limit_skip() {
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Limit(Box::new(proto::Limit {
@@ -540,6 +542,7 @@ async fn test_count_with_filter() {
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Count(Box::new(proto::Count {
@@ -711,6 +714,7 @@ async fn test_pagination_with_skip_and_limit() {
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Limit(Box::new(proto::Limit {
@@ -778,6 +782,7 @@ async fn test_pagination_with_skip_and_limit() {
         options: Some(proto::QueryOptions {
             timeout_ms: 30000,
             explain: false,
+            read_mode: proto::ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(proto::query::Kind::Limit(Box::new(proto::Limit {
@@ -0,0 +1,206 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn query_of(kind: proto::query::Kind) -> proto::Query {
+    proto::Query {
+        options: None,
+        cursor: None,
+        kind: Some(kind),
+    }
+}
+
+fn field(path: &[&str]) -> proto::FieldRef {
+    proto::FieldRef {
+        path: path.iter().map(ToString::to_string).collect(),
+        separator: ".".to_string(),
+    }
+}
+
+fn object_datum(fields: Vec<(&str, proto::Datum)>) -> proto::Datum {
+    proto::Datum {
+        value: Some(proto::datum::Value::Object(create_datum_object(fields))),
+    }
+}
+
+fn items(datum: &proto::Datum) -> &[proto::Datum] {
+    match &datum.value {
+        Some(proto::datum::Value::Array(array)) => &array.items,
+        other => panic!("Expected an array, got {other:?}"),
+    }
+}
+
+fn fields(datum: &proto::Datum) -> &std::collections::HashMap<String, proto::Datum> {
+    match &datum.value {
+        Some(proto::datum::Value::Object(object)) => &object.fields,
+        other => panic!("Expected a document, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_document_functions() {
+    let query_id = "test-document-functions-001";
+    let database_name = &generate_unique_name("test_db_document_functions");
+    let table_name = "profiles";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+
+    let profiles = vec![
+        create_datum_object(vec![
+            ("id", create_string_datum("ada")),
+            ("name", create_string_datum("Ada")),
+            (
+                "settings",
+                object_datum(vec![
+                    ("theme", create_string_datum("dark")),
+                    ("language", create_string_datum("en")),
+                ]),
+            ),
+        ]),
+        create_datum_object(vec![
+            ("id", create_string_datum("bob")),
+            ("name", create_null_datum()),
+        ]),
+    ];
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, profiles),
+    )
+    .await;
+    let table = || Some(Box::new(create_table_query(database_name, table_name)));
+    let get = |key: &str| {
+        Some(Box::new(create_get_query(
+            database_name,
+            table_name,
+            create_string_datum(key),
+        )))
+    };
+
+    // HasFields over a table keeps the documents whose fields are set and not null
+    let has_name = query_of(proto::query::Kind::HasFields(Box::new(proto::HasFields {
+        source: table(),
+        fields: vec![field(&["name"])],
+    })));
+    let result = query(&mut stream, &format!("{query_id}-has-fields"), &has_name).await;
+    assert_eq!(items(&result).len(), 1);
+    assert_eq!(fields(&items(&result)[0])["id"], create_string_datum("ada"));
+
+    // As an expression it filters on nested documents
+    let has_theme = create_document_expression(
+        proto::document_op::Operator::HasFields,
+        vec![create_field_expression(vec!["settings"])],
+        vec![field(&["theme"])],
+    );
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-filter"),
+        &create_filter_query(database_name, table_name, has_theme),
+    )
+    .await;
+    assert_eq!(items(&result).len(), 1);
+
+    // Merge is deep, a literal replaces its subtree and an empty literal removes a field
+    let merge = |patch: proto::Datum| {
+        query_of(proto::query::Kind::Merge(Box::new(proto::Merge {
+            source: get("ada"),
+            values: vec![create_literal_expression(patch)],
+        })))
+    };
+    let light = || object_datum(vec![("theme", create_string_datum("light"))]);
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-merge"),
+        &merge(object_datum(vec![("settings", light())])),
+    )
+    .await;
+    let settings = fields(&fields(&result)["settings"]);
+    assert_eq!(settings["theme"], create_string_datum("light"));
+    assert_eq!(settings["language"], create_string_datum("en"));
+
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-merge-literal"),
+        &merge(object_datum(vec![
+            ("settings", object_datum(vec![("$literal", light())])),
+            (
+                "name",
+                object_datum(vec![("$literal", proto::Datum { value: None })]),
+            ),
+        ])),
+    )
+    .await;
+    assert_eq!(fields(&result)["settings"], light());
+    assert!(!fields(&result).contains_key("name"));
+
+    // Keys come in key order
+    let keys = query_of(proto::query::Kind::Keys(Box::new(proto::Keys {
+        source: get("ada"),
+    })));
+    let result = query(&mut stream, &format!("{query_id}-keys"), &keys).await;
+    assert_eq!(
+        items(&result),
+        ["id", "name", "settings"].map(create_string_datum)
+    );
+
+    // Default stands in for a missing document
+    let default = query_of(proto::query::Kind::Default(Box::new(proto::DefaultValue {
+        source: get("carol"),
+        value: Some(Box::new(create_literal_expression(create_string_datum(
+            "no such profile",
+        )))),
+    })));
+    let result = query(&mut stream, &format!("{query_id}-default"), &default).await;
+    assert_eq!(result, create_string_datum("no such profile"));
+
+    // A table is a sequence, which coerces to an array of its documents
+    let type_of = query_of(proto::query::Kind::TypeOf(Box::new(proto::TypeOf {
+        source: table(),
+    })));
+    let result = query(&mut stream, &format!("{query_id}-type-of"), &type_of).await;
+    assert_eq!(result, create_string_datum("SEQUENCE"));
+
+    let coerce_to = query_of(proto::query::Kind::CoerceTo(Box::new(proto::CoerceTo {
+        source: get("ada"),
+        r#type: "array".to_string(),
+    })));
+    let result = query(&mut stream, &format!("{query_id}-coerce-to"), &coerce_to).await;
+    assert_eq!(items(&result).len(), 3);
+    assert_eq!(
+        items(&items(&result)[0]),
+        [create_string_datum("id"), create_string_datum("ada")]
+    );
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn documents(datum: &proto::Datum) -> Vec<&proto::DatumObject> {
+    match &datum.value {
+        Some(proto::datum::Value::Array(array)) => array
+            .items
+            .iter()
+            .map(|item| match &item.value {
+                Some(proto::datum::Value::Object(object)) => object,
+                other => panic!("Expected a document, got {other:?}"),
+            })
+            .collect(),
+        other => panic!("Expected documents, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_decimal_and_uuid_values() {
+    let query_id = "test-arithmetic-001";
+    let database_name = &generate_unique_name("test_db_arithmetic");
+    let table_name = "order_lines";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-index-create"),
+        &create_secondary_index_create_query(database_name, table_name, "price", &["price"], false),
+    )
+    .await;
+
+    let lines = [
+        ("a", "9e2b0c1e-0000-4000-8000-000000000003", "19.99", 3),
+        ("b", "1f6e3d2a-0000-4000-8000-000000000001", "0.10", 7),
+        ("c", "5c4a1b0d-0000-4000-8000-000000000002", "0.30", 2),
+    ];
+    let documents_to_insert = lines
+        .iter()
+        .map(|(id, product, price, quantity)| {
+            create_datum_object(vec![
+                ("id", create_string_datum(id)),
+                ("product", create_uuid_datum(product)),
+                ("price", create_decimal_datum(price)),
+                ("quantity", create_int_datum(*quantity)),
+            ])
+        })
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, documents_to_insert),
+    )
+    .await;
+
+    // Decimal values round-trip with their scale
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-get"),
+        &create_get_query(database_name, table_name, create_string_datum("b")),
+    )
+    .await;
+    match &result.value {
+        Some(proto::datum::Value::Object(object)) => {
+            assert_eq!(object.fields["price"], create_decimal_datum("0.10"));
+            assert_eq!(
+                object.fields["product"],
+                create_uuid_datum("1f6e3d2a-0000-4000-8000-000000000001")
+            );
+        }
+        other => panic!("Expected a document, got {other:?}"),
+    }
+
+    // Totals are exact: 0.10 * 7 is 0.70 and 0.30 * 2 is 0.60, with no float error
+    let total = create_binary_expression(
+        proto::binary_op::Operator::Mul,
+        create_field_expression(vec!["price"]),
+        create_field_expression(vec!["quantity"]),
+    );
+    let exact_total = create_binary_expression(
+        proto::binary_op::Operator::Eq,
+        total,
+        create_literal_expression(create_decimal_datum("0.7")),
+    );
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-filter-total"),
+        &create_filter_query(database_name, table_name, exact_total),
+    )
+    .await;
+    let ids: Vec<_> = documents(&result)
+        .iter()
+        .map(|document| document.fields["id"].clone())
+        .collect();
+    assert_eq!(ids, [create_string_datum("b")]);
+
+    // The index orders decimals by value and compares them exactly with other numbers, so
+    // the float 0.1, a little above the decimal 0.10, excludes it
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-between"),
+        &create_between_query(
+            database_name,
+            table_name,
+            "price",
+            Some(create_float_datum(0.1)),
+            Some(create_int_datum(20)),
+        ),
+    )
+    .await;
+    let ids: Vec<_> = documents(&result)
+        .iter()
+        .map(|document| document.fields["id"].clone())
+        .collect();
+    assert_eq!(ids, [create_string_datum("c"), create_string_datum("a")]);
+
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-get-all"),
+        &create_get_all_by_index_query(
+            database_name,
+            table_name,
+            "price",
+            vec![create_float_datum(0.3), create_decimal_datum("0.1")],
+        ),
+    )
+    .await;
+    let ids: Vec<_> = documents(&result)
+        .iter()
+        .map(|document| document.fields["id"].clone())
+        .collect();
+    assert_eq!(ids, [create_string_datum("b")]);
+
+    // UUIDs order by their bytes, as their text does
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-order-by"),
+        &create_order_by_query(
+            database_name,
+            table_name,
+            vec![create_sort_field("product", proto::SortDirection::Asc)],
+        ),
+    )
+    .await;
+    let ids: Vec<_> = documents(&result)
+        .iter()
+        .map(|document| document.fields["id"].clone())
+        .collect();
+    assert_eq!(ids, ["b", "c", "a"].map(create_string_datum));
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use common::*;
+use proto::binary_op::Operator;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+async fn query_error(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> String {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    decode_response_payload(&response)
+        .expect_err("Query should fail")
+        .to_string()
+}
+
+fn document_ids(datum: &proto::Datum) -> Vec<String> {
+    let items = match &datum.value {
+        Some(proto::datum::Value::Array(array)) => &array.items,
+        other => panic!("Expected documents, got {other:?}"),
+    };
+    let mut ids: Vec<String> = items
+        .iter()
+        .map(|item| match &item.value {
+            Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+                Some(proto::datum::Value::String(id)) => id.clone(),
+                other => panic!("Unexpected id {other:?}"),
+            },
+            other => panic!("Expected a document, got {other:?}"),
+        })
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[tokio::test]
+async fn test_branch_error_and_default_expressions() {
+    let query_id = "test-branch-expr-001";
+    let database_name = &generate_unique_name("test_db_branch");
+    let table_name = "accounts";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+
+    let documents = vec![
+        create_datum_object(vec![
+            ("id", create_string_datum("a")),
+            ("plan", create_string_datum("pro")),
+            ("seats", create_int_datum(12)),
+        ]),
+        create_datum_object(vec![
+            ("id", create_string_datum("b")),
+            ("plan", create_string_datum("free")),
+            ("seats", create_int_datum(1)),
+        ]),
+        create_datum_object(vec![
+            ("id", create_string_datum("c")),
+            ("plan", create_string_datum("pro")),
+        ]),
+    ];
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, documents),
+    )
+    .await;
+
+    let field = |name: &str| create_field_expression(vec![name]);
+    let int = |value: i64| create_literal_expression(create_int_datum(value));
+    let is_pro = create_binary_expression(
+        Operator::Eq,
+        field("plan"),
+        create_literal_expression(create_string_datum("pro")),
+    );
+    let seats_or_zero = create_document_expression(
+        proto::document_op::Operator::Default,
+        vec![
+            create_binary_expression(Operator::Add, field("seats"), int(0)),
+            int(0),
+        ],
+        vec![],
+    );
+
+    // Pro accounts need more than ten seats, others any; a missing count defaults
+    let large_pro = create_branch_expression(
+        vec![(
+            is_pro,
+            create_binary_expression(Operator::Gt, seats_or_zero, int(10)),
+        )],
+        create_literal_expression(create_bool_datum(true)),
+    );
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-branch"),
+        &create_filter_query(database_name, table_name, large_pro),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["a", "b"]);
+
+    // Without the default the missing field fails the query instead of hiding it
+    let more_seats = create_binary_expression(
+        Operator::Gt,
+        create_binary_expression(Operator::Add, field("seats"), int(1)),
+        int(1),
+    );
+    let error = query_error(
+        &mut stream,
+        &format!("{query_id}-missing"),
+        &create_filter_query(database_name, table_name, more_seats),
+    )
+    .await;
+    assert!(error.contains("seats"), "unexpected error {error}");
+
+    // An error expression aborts the query with its message
+    let checked = create_branch_expression(
+        vec![(
+            create_binary_expression(
+                Operator::Eq,
+                field("plan"),
+                create_literal_expression(create_string_datum("free")),
+            ),
+            create_error_expression("free accounts cannot be filtered"),
+        )],
+        create_literal_expression(create_bool_datum(true)),
+    );
+    let error = query_error(
+        &mut stream,
+        &format!("{query_id}-error"),
+        &create_filter_query(database_name, table_name, checked),
+    )
+    .await;
+    assert!(
+        error.contains("free accounts cannot be filtered"),
+        "unexpected error {error}"
+    );
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
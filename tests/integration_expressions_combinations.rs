@@ -85,6 +85,7 @@ async fn test_expression_complex_nested_combinations() {
             value: Some(Box::new(create_field_expression(vec!["email"]))),
             pattern: r".*@premium\.com$".to_string(),
             flags: "".to_string(),
+            capture: false,
         }))),
     };
 
@@ -657,6 +658,7 @@ async fn test_expression_unicode_and_special_characters() {
             value: Some(Box::new(create_field_expression(vec!["city"]))),
             pattern: r".*京$".to_string(), // Cities ending with 京 (Beijing)
             flags: "".to_string(),
+            capture: false,
         }))),
     };
     let filter_query = create_filter_query(database_name, table_name, unicode_regex);
@@ -679,6 +681,7 @@ async fn test_expression_unicode_and_special_characters() {
             value: Some(Box::new(create_field_expression(vec!["description"]))),
             pattern: r".*[@#\$%\^&\*\(\)].*".to_string(), // Contains special characters
             flags: "".to_string(),
+            capture: false,
         }))),
     };
     let filter_query = create_filter_query(database_name, table_name, special_char_regex);
@@ -79,6 +79,7 @@ async fn test_match_expression_basic_regex() {
             value: Some(Box::new(field_expr)),
             pattern: r".*@.*\.com$".to_string(),
             flags: "".to_string(),
+            capture: false,
         }))),
     };
     let filter_query = create_filter_query(database_name, table_name, match_expr);
@@ -195,6 +196,7 @@ async fn test_match_expression_phone_number_patterns() {
             pattern: r"^(\+1-\d{3}-\d{3}-\d{4}|\(\d{3}\) \d{3}-\d{4}|\d{3}\.\d{3}\.\d{4})$"
                 .to_string(),
             flags: "".to_string(),
+            capture: false,
         }))),
     };
     let filter_query = create_filter_query(database_name, table_name, match_expr);
@@ -302,7 +304,8 @@ async fn test_match_expression_case_insensitive() {
         expr: Some(proto::expression::Expr::Match(Box::new(proto::MatchExpr {
             value: Some(Box::new(field_expr)),
             pattern: r".*(smith|brown).*".to_string(),
-            flags: "(?i)".to_string(), // Case-insensitive flag
+            flags: "i".to_string(), // Case-insensitive flag
+            capture: false,
         }))),
     };
     let filter_query = create_filter_query(database_name, table_name, match_expr);
@@ -432,6 +435,7 @@ async fn test_match_expression_complex_patterns() {
             value: Some(Box::new(field_expr)),
             pattern: r"^https://[a-zA-Z0-9.-]+(?::[0-9]+)?/.*$".to_string(),
             flags: "".to_string(),
+            capture: false,
         }))),
     };
     let filter_query = create_filter_query(database_name, table_name, match_expr);
@@ -471,6 +475,7 @@ async fn test_match_expression_complex_patterns() {
             value: Some(Box::new(field_expr)),
             pattern: r"://[^/]*\.(com|org)".to_string(),
             flags: "".to_string(),
+            capture: false,
         }))),
     };
     let filter_query = create_filter_query(database_name, table_name, match_expr);
@@ -586,6 +591,7 @@ async fn test_match_expression_with_binary_operations() {
             value: Some(Box::new(create_field_expression(vec!["email"]))),
             pattern: r".*@company\.com$".to_string(),
             flags: "".to_string(),
+            capture: false,
         }))),
     };
 
@@ -646,6 +652,7 @@ async fn test_match_expression_with_binary_operations() {
             value: Some(Box::new(create_field_expression(vec!["email"]))),
             pattern: r".*\.org$".to_string(),
             flags: "".to_string(),
+            capture: false,
         }))),
     };
 
@@ -687,3 +694,112 @@ async fn test_match_expression_with_binary_operations() {
 
     println!("✓ Match expression with binary operations test completed successfully");
 }
+
+#[tokio::test]
+async fn test_match_expression_captures_and_errors() {
+    let query_id = "test-match-expr-captures-001";
+    let database_name = &generate_unique_name("test_db_match_captures");
+    let table_name = "people";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    for (step, query) in [
+        ("db-create", create_database_create_query(database_name)),
+        (
+            "table-create",
+            create_table_create_query(database_name, table_name),
+        ),
+        (
+            "insert",
+            create_insert_query(
+                database_name,
+                table_name,
+                vec![create_datum_object(vec![
+                    ("id", create_string_datum("p1")),
+                    ("email", create_string_datum("zoë@example.com")),
+                ])],
+            ),
+        ),
+    ] {
+        let response = send_envelope_to_server(
+            &mut stream,
+            &create_envelope(&format!("{query_id}-{step}"), &query),
+        )
+        .await
+        .expect("Failed to send envelope");
+        validate_response_envelope(&response, &format!("{query_id}-{step}"))
+            .expect("Response validation failed");
+    }
+
+    // The match merged into the document gives its string, offsets and groups
+    let capture = proto::Expression {
+        expr: Some(proto::expression::Expr::Match(Box::new(proto::MatchExpr {
+            value: Some(Box::new(create_field_expression(vec!["email"]))),
+            pattern: r"(?<user>\w+)@(\w+)".to_string(),
+            flags: "".to_string(),
+            capture: true,
+        }))),
+    };
+    let merge = proto::Query {
+        options: None,
+        cursor: None,
+        kind: Some(proto::query::Kind::Merge(Box::new(proto::Merge {
+            source: Some(Box::new(create_get_query(
+                database_name,
+                table_name,
+                create_string_datum("p1"),
+            ))),
+            values: vec![capture],
+        }))),
+    };
+    let response = send_envelope_to_server(
+        &mut stream,
+        &create_envelope(&format!("{query_id}-capture"), &merge),
+    )
+    .await
+    .expect("Failed to send capture envelope");
+    let result = decode_response_payload(&response).expect("Failed to decode response");
+    let Some(proto::datum::Value::Object(object)) = &result.value else {
+        panic!("Expected a document, got {result:?}");
+    };
+    assert_eq!(object.fields["str"], create_string_datum("zoë@example"));
+    assert_eq!(object.fields["start"], create_int_datum(0));
+    assert_eq!(object.fields["end"], create_int_datum(11));
+    let Some(proto::datum::Value::Object(named)) = &object.fields["named"].value else {
+        panic!("Expected the named groups");
+    };
+    let Some(proto::datum::Value::Object(user)) = &named.fields["user"].value else {
+        panic!("Expected the user group");
+    };
+    assert_eq!(user.fields["str"], create_string_datum("zoë"));
+
+    // A pattern that does not compile fails the query rather than matching nothing
+    let filter = create_filter_query(
+        database_name,
+        table_name,
+        create_match_expression(create_field_expression(vec!["email"]), "(unclosed", ""),
+    );
+    let response = send_envelope_to_server(
+        &mut stream,
+        &create_envelope(&format!("{query_id}-bad-pattern"), &filter),
+    )
+    .await
+    .expect("Failed to send bad pattern envelope");
+    let error = decode_response_payload(&response)
+        .expect_err("A bad pattern should fail")
+        .to_string();
+    assert!(
+        error.contains("Invalid match pattern"),
+        "unexpected error {error}"
+    );
+
+    let db_drop_query = create_database_drop_query(database_name);
+    send_envelope_to_server(
+        &mut stream,
+        &create_envelope(&format!("{query_id}-db-drop"), &db_drop_query),
+    )
+    .await
+    .expect("Failed to send database drop envelope");
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn document_ids(datum: &proto::Datum) -> Vec<String> {
+    let items = match &datum.value {
+        Some(proto::datum::Value::Array(array)) => &array.items,
+        other => panic!("Expected documents, got {other:?}"),
+    };
+    let mut ids: Vec<String> = items
+        .iter()
+        .map(|item| match &item.value {
+            Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+                Some(proto::datum::Value::String(id)) => id.clone(),
+                other => panic!("Unexpected id {other:?}"),
+            },
+            other => panic!("Expected a document, got {other:?}"),
+        })
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[tokio::test]
+async fn test_time_expressions() {
+    let query_id = "test-time-expr-001";
+    let database_name = &generate_unique_name("test_db_time");
+    let table_name = "orders";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+
+    let orders = [
+        ("a", "2024-03-01T09:00:00+01:00"),
+        ("b", "2024-03-02T23:30:00-05:00"),
+        ("c", "2024-03-04T10:00:00Z"),
+        ("d", "2024-02-29T23:59:59Z"),
+    ];
+    let documents = orders
+        .iter()
+        .map(|(id, placed)| {
+            create_datum_object(vec![
+                ("id", create_string_datum(id)),
+                ("placed", create_time_datum(placed)),
+            ])
+        })
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, documents),
+    )
+    .await;
+
+    // Times round-trip with their offset
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-get"),
+        &create_get_query(database_name, table_name, create_string_datum("b")),
+    )
+    .await;
+    match &result.value {
+        Some(proto::datum::Value::Object(object)) => {
+            assert_eq!(object.fields["placed"], create_time_datum(orders[1].1));
+        }
+        other => panic!("Expected a document, got {other:?}"),
+    }
+
+    // Orders placed during the first three days of March in UTC; b is on the 3rd in UTC
+    let start = create_time_expression(
+        proto::time_op::Operator::Iso8601,
+        vec![create_literal_expression(create_string_datum(
+            "2024-03-01T00:00:00Z",
+        ))],
+    );
+    let end = create_time_expression(
+        proto::time_op::Operator::Add,
+        vec![
+            start.clone(),
+            create_literal_expression(create_int_datum(2 * 86_400)),
+        ],
+    );
+    let during = create_time_expression(
+        proto::time_op::Operator::During,
+        vec![create_field_expression(vec!["placed"]), start, end],
+    );
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-filter-during"),
+        &create_filter_query(database_name, table_name, during),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["a"]);
+
+    // Accessors read the calendar at the time's own offset
+    let day_of_week = create_time_expression(
+        proto::time_op::Operator::DayOfWeek,
+        vec![create_field_expression(vec!["placed"])],
+    );
+    let saturday = create_binary_expression(
+        proto::binary_op::Operator::Eq,
+        day_of_week,
+        create_literal_expression(create_int_datum(6)),
+    );
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-filter-day-of-week"),
+        &create_filter_query(database_name, table_name, saturday),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["b"]);
+
+    // Times compare by instant
+    let before = create_binary_expression(
+        proto::binary_op::Operator::Lt,
+        create_field_expression(vec!["placed"]),
+        create_literal_expression(create_time_datum("2024-03-01T08:30:00Z")),
+    );
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-filter-before"),
+        &create_filter_query(database_name, table_name, before),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["a", "d"]);
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn items(datum: &proto::Datum) -> &[proto::Datum] {
+    match &datum.value {
+        Some(proto::datum::Value::Array(array)) => &array.items,
+        Some(proto::datum::Value::Object(object)) => items(&object.fields["documents"]),
+        other => panic!("Expected documents, got {other:?}"),
+    }
+}
+
+fn document_ids(datum: &proto::Datum) -> Vec<String> {
+    items(datum)
+        .iter()
+        .map(|item| match &item.value {
+            Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+                Some(proto::datum::Value::String(id)) => id.clone(),
+                other => panic!("Unexpected id {other:?}"),
+            },
+            other => panic!("Expected a document, got {other:?}"),
+        })
+        .collect()
+}
+
+fn point(longitude: f64, latitude: f64) -> proto::Point {
+    proto::Point {
+        longitude,
+        latitude,
+    }
+}
+
+fn geo_expression(
+    op: proto::geo_op::Operator,
+    left: proto::Expression,
+    right: proto::Expression,
+) -> proto::Expression {
+    proto::Expression {
+        expr: Some(proto::expression::Expr::Geo(Box::new(proto::GeoOp {
+            op: op.into(),
+            left: Some(Box::new(left)),
+            right: Some(Box::new(right)),
+        }))),
+    }
+}
+
+#[tokio::test]
+async fn test_geo_index_queries() {
+    let query_id = "test-geo-001";
+    let database_name = &generate_unique_name("test_db_geo");
+    let table_name = "places";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+
+    let places = [
+        ("london", -0.1276, 51.5072),
+        ("paris", 2.3522, 48.8566),
+        ("brussels", 4.3517, 50.8503),
+        ("berlin", 13.405, 52.52),
+    ];
+    let documents = places
+        .iter()
+        .map(|(id, longitude, latitude)| {
+            create_datum_object(vec![
+                ("id", create_string_datum(id)),
+                ("location", create_point_datum(*longitude, *latitude)),
+            ])
+        })
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, documents),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-index-create"),
+        &create_geo_index_create_query(database_name, table_name, "location", "location"),
+    )
+    .await;
+
+    // An area around the Benelux holds Brussels only
+    let area = proto::Geometry {
+        kind: Some(proto::geometry::Kind::Polygon(proto::Polygon {
+            points: vec![
+                point(2.5, 49.5),
+                point(7.0, 49.5),
+                point(7.0, 53.5),
+                point(2.5, 53.5),
+            ],
+        })),
+    };
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-intersecting"),
+        &create_get_intersecting_query(database_name, table_name, "location", area),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["brussels"]);
+
+    let nearest = create_get_nearest_query(
+        database_name,
+        table_name,
+        "location",
+        point(2.3522, 48.8566),
+        Some(400_000.0),
+        None,
+    );
+    let result = query(&mut stream, &format!("{query_id}-nearest"), &nearest).await;
+    assert_eq!(document_ids(&result), ["paris", "brussels", "london"]);
+    let Some(proto::datum::Value::Object(object)) = &result.value else {
+        panic!("Expected a nearest result, got {result:?}");
+    };
+    let distances = items(&object.fields["distances"]);
+    assert_eq!(distances[0], create_float_datum(0.0));
+    assert_eq!(distances.len(), 3);
+
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-nearest-limited"),
+        &create_get_nearest_query(
+            database_name,
+            table_name,
+            "location",
+            point(2.3522, 48.8566),
+            Some(400_000.0),
+            Some(2),
+        ),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["paris", "brussels"]);
+
+    // Geo queries compose with Filter over geospatial expressions
+    let filtered = proto::Query {
+        options: nearest.options,
+        cursor: None,
+        kind: Some(proto::query::Kind::Filter(Box::new(proto::Filter {
+            source: Some(Box::new(proto::Query {
+                options: None,
+                ..nearest.clone()
+            })),
+            predicate: Some(Box::new(create_binary_expression(
+                proto::binary_op::Operator::Gt,
+                geo_expression(
+                    proto::geo_op::Operator::Distance,
+                    create_field_expression(vec!["location"]),
+                    create_literal_expression(create_point_datum(2.3522, 48.8566)),
+                ),
+                create_literal_expression(create_float_datum(300_000.0)),
+            ))),
+        }))),
+    };
+    let result = query(&mut stream, &format!("{query_id}-filter"), &filtered).await;
+    assert_eq!(document_ids(&result), ["london"]);
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use std::collections::HashMap;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn id(doc: &proto::Datum) -> &str {
+    match &doc.value {
+        Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+            Some(proto::datum::Value::String(id)) => id,
+            other => panic!("Expected a string id, got {other:?}"),
+        },
+        other => panic!("Expected a document, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_pipelined_queries_are_answered_by_query_id() {
+    let query_id = "test-multiplexing-001";
+    let database_name = &generate_unique_name("test_db_multiplexing");
+    let table_name = "items";
+    let count = 100;
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+    let items = (0..count)
+        .map(|n| create_datum_object(vec![("id", create_string_datum(&format!("item{n}")))]))
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, items),
+    )
+    .await;
+
+    // More queries than may run at once on a connection, sent before reading any
+    // answer, with a failing one among them
+    let mut expected = HashMap::new();
+    for n in 0..count {
+        let get_id = format!("{query_id}-get-{n}");
+        let get = create_get_query(
+            database_name,
+            table_name,
+            create_string_datum(&format!("item{n}")),
+        );
+        write_envelope_to_server(&mut stream, &create_envelope(&get_id, &get))
+            .await
+            .expect("Failed to send envelope");
+        expected.insert(get_id, Some(format!("item{n}")));
+    }
+    let failing_id = format!("{query_id}-out-of-bounds");
+    let past_the_end = proto::Query {
+        options: None,
+        cursor: None,
+        kind: Some(proto::query::Kind::Nth(Box::new(proto::Nth {
+            source: Some(Box::new(create_table_query(database_name, table_name))),
+            index: count,
+        }))),
+    };
+    write_envelope_to_server(&mut stream, &create_envelope(&failing_id, &past_the_end))
+        .await
+        .expect("Failed to send envelope");
+    expected.insert(failing_id, None);
+
+    for _ in 0..expected.len() {
+        let response = read_envelope_from_server(&mut stream)
+            .await
+            .expect("Failed to receive response");
+        let wanted = expected
+            .remove(&response.query_id)
+            .unwrap_or_else(|| panic!("Unexpected answer to {}", response.query_id));
+        match wanted {
+            Some(item) => {
+                let doc = decode_response_payload(&response).expect("Failed to decode response");
+                assert_eq!(id(&doc), item);
+            }
+            None => assert!(decode_response_payload(&response).is_err()),
+        }
+    }
+    assert!(expected.is_empty());
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
@@ -0,0 +1,276 @@
+mod common;
+
+use common::*;
+use prost::Message;
+use rulodb::ast::proto;
+use std::collections::HashMap;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+async fn prepare(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> String {
+    let envelope = proto::Envelope {
+        r#type: proto::MessageType::Prepare.into(),
+        ..create_envelope(query_id, query)
+    };
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    match decode_response_payload(&response)
+        .expect("Failed to decode response payload")
+        .value
+    {
+        Some(proto::datum::Value::String(handle)) => handle,
+        other => panic!("Expected a handle, got {other:?}"),
+    }
+}
+
+async fn execute(
+    stream: &mut TcpStream,
+    query_id: &str,
+    handle: &str,
+    parameters: &[(&str, proto::Datum)],
+    cursor: Option<proto::Cursor>,
+) -> Result<proto::Datum, Box<dyn std::error::Error + Send + Sync>> {
+    let execute = proto::Execute {
+        handle: handle.to_string(),
+        parameters: parameters
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect::<HashMap<_, _>>(),
+        cursor,
+    };
+    let envelope = proto::Envelope {
+        version: proto::ProtocolVersion::Version1.into(),
+        query_id: query_id.to_string(),
+        r#type: proto::MessageType::Execute.into(),
+        payload: execute.encode_to_vec(),
+    };
+    let response = send_envelope_to_server(stream, &envelope).await?;
+    decode_response_payload(&response)
+}
+
+fn ids(datum: &proto::Datum) -> Vec<&str> {
+    let Some(proto::datum::Value::Array(array)) = &datum.value else {
+        panic!("Expected an array, got {datum:?}");
+    };
+    let mut ids: Vec<&str> = array
+        .items
+        .iter()
+        .map(|doc| match &doc.value {
+            Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+                Some(proto::datum::Value::String(id)) => id.as_str(),
+                other => panic!("Expected a string id, got {other:?}"),
+            },
+            other => panic!("Expected a document, got {other:?}"),
+        })
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+#[tokio::test]
+async fn test_prepared_query_with_parameters() {
+    let query_id = "test-prepared-001";
+    let database_name = &generate_unique_name("test_db_prepared");
+    let table_name = "users";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+    let users = [("u1", "alice", 30), ("u2", "bob", 25), ("u3", "carol", 35)]
+        .iter()
+        .map(|(id, name, age)| {
+            create_datum_object(vec![
+                ("id", create_string_datum(id)),
+                ("name", create_string_datum(name)),
+                ("age", create_int_datum(*age)),
+            ])
+        })
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, users),
+    )
+    .await;
+
+    // Users of at least a given age, one of them possibly left out by name
+    let template = create_filter_query(
+        database_name,
+        table_name,
+        create_binary_expression(
+            proto::binary_op::Operator::And,
+            create_binary_expression(
+                proto::binary_op::Operator::Ge,
+                create_field_expression(vec!["age"]),
+                create_variable_expression("min_age"),
+            ),
+            create_binary_expression(
+                proto::binary_op::Operator::Ne,
+                create_field_expression(vec!["name"]),
+                create_variable_expression("except"),
+            ),
+        ),
+    );
+    let handle = prepare(&mut stream, &format!("{query_id}-prepare"), &template).await;
+
+    let result = execute(
+        &mut stream,
+        &format!("{query_id}-execute-30"),
+        &handle,
+        &[
+            ("min_age", create_int_datum(30)),
+            ("except", create_string_datum("")),
+        ],
+        None,
+    )
+    .await
+    .expect("Failed to execute the prepared query");
+    assert_eq!(ids(&result), ["u1", "u3"]);
+
+    let result = execute(
+        &mut stream,
+        &format!("{query_id}-execute-25"),
+        &handle,
+        &[
+            ("min_age", create_int_datum(25)),
+            ("except", create_string_datum("carol")),
+        ],
+        None,
+    )
+    .await
+    .expect("Failed to execute the prepared query");
+    assert_eq!(ids(&result), ["u1", "u2"]);
+
+    // The plan is remade after an index is created, and the handle still works
+    query(
+        &mut stream,
+        &format!("{query_id}-index-create"),
+        &create_secondary_index_create_query(database_name, table_name, "name", &["name"], false),
+    )
+    .await;
+    let result = execute(
+        &mut stream,
+        &format!("{query_id}-execute-after-index"),
+        &handle,
+        &[
+            ("min_age", create_int_datum(35)),
+            ("except", create_string_datum("")),
+        ],
+        None,
+    )
+    .await
+    .expect("Failed to execute the prepared query");
+    assert_eq!(ids(&result), ["u3"]);
+
+    let error = execute(
+        &mut stream,
+        &format!("{query_id}-execute-unknown"),
+        "unknown",
+        &[],
+        None,
+    )
+    .await
+    .expect_err("An unknown handle should fail")
+    .to_string();
+    assert!(
+        error.contains("Unknown prepared query"),
+        "unexpected error {error}"
+    );
+
+    let error = execute(
+        &mut stream,
+        &format!("{query_id}-execute-unbound"),
+        &handle,
+        &[("min_age", create_int_datum(30))],
+        None,
+    )
+    .await
+    .expect_err("An unbound parameter should fail")
+    .to_string();
+    assert!(
+        error.contains("Unbound parameter: except"),
+        "unexpected error {error}"
+    );
+
+    // Keys given by parameters, paged by the cursor of each execution
+    let get_all = proto::Query {
+        options: None,
+        cursor: None,
+        kind: Some(proto::query::Kind::GetAll(Box::new(proto::GetAll {
+            source: Some(Box::new(create_table_query(database_name, table_name))),
+            keys: vec![],
+            index: String::new(),
+            key_expressions: vec![
+                create_variable_expression("first"),
+                create_variable_expression("second"),
+            ],
+        }))),
+    };
+    let handle = prepare(
+        &mut stream,
+        &format!("{query_id}-prepare-get-all"),
+        &get_all,
+    )
+    .await;
+    let keys = [
+        ("first", create_string_datum("u1")),
+        ("second", create_string_datum("u3")),
+    ];
+    let result = execute(
+        &mut stream,
+        &format!("{query_id}-execute-get-all"),
+        &handle,
+        &keys,
+        None,
+    )
+    .await
+    .expect("Failed to execute the prepared query");
+    assert_eq!(ids(&result), ["u1", "u3"]);
+
+    let cursor = proto::Cursor {
+        start_key: None,
+        batch_size: Some(1),
+        sort: None,
+        snapshot: None,
+    };
+    let result = execute(
+        &mut stream,
+        &format!("{query_id}-execute-get-all-page"),
+        &handle,
+        &keys,
+        Some(cursor),
+    )
+    .await
+    .expect("Failed to execute the prepared query");
+    assert_eq!(ids(&result).len(), 1);
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+fn start_server(replica_of: Option<&str>) -> Server {
+    let mut args = vec!["--replication-poll-interval".to_string(), "10".to_string()];
+    match replica_of {
+        Some(primary) => args.extend(["--replica-of".to_string(), primary.to_string()]),
+        None => args.push("--serve-replicas".to_string()),
+    }
+    Server::start(free_address(), &args)
+}
+
+async fn query(stream: &mut TcpStream, query: &proto::Query) -> proto::Envelope {
+    let envelope = create_envelope("test-replication", query);
+    send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response")
+}
+
+async fn replication_status(stream: &mut TcpStream) -> proto::DatumObject {
+    let response = query(stream, &create_replication_status_query()).await;
+    match decode_response_payload(&response)
+        .expect("Failed to decode response payload")
+        .value
+    {
+        Some(proto::datum::Value::Object(status)) => status,
+        other => panic!("Expected replication status object, got {other:?}"),
+    }
+}
+
+fn field<'a>(object: &'a proto::DatumObject, name: &str) -> &'a proto::datum::Value {
+    object.fields[name].value.as_ref().unwrap()
+}
+
+#[tokio::test]
+async fn test_replica_follows_primary() {
+    let primary = start_server(None);
+    let mut primary_stream = primary.connect().await;
+    let replica = start_server(Some(&primary.address));
+    let mut replica_stream = replica.connect().await;
+
+    let database_name = &generate_unique_name("test_replication");
+    let table_name = "users";
+    for setup in [
+        create_database_create_query(database_name),
+        create_table_create_query(database_name, table_name),
+        create_insert_query(
+            database_name,
+            table_name,
+            vec![create_datum_object(vec![
+                ("id", create_string_datum("alice")),
+                ("age", create_int_datum(30)),
+            ])],
+        ),
+    ] {
+        let response = query(&mut primary_stream, &setup).await;
+        decode_response_payload(&response).expect("Setup query failed on the primary");
+    }
+
+    // Poll the replica until the document shows up
+    let get = create_get_query(database_name, table_name, create_string_datum("alice"));
+    let mut document = None;
+    for _ in 0..100 {
+        let response = query(&mut replica_stream, &get).await;
+        if let Ok(proto::Datum {
+            value: Some(proto::datum::Value::Object(object)),
+        }) = decode_response_payload(&response)
+        {
+            document = Some(object);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let document = document.expect("Document was not replicated");
+    assert_eq!(field(&document, "age"), &proto::datum::Value::Int(30));
+
+    // The replica is caught up and reports its primary
+    let status = replication_status(&mut replica_stream).await;
+    assert_eq!(
+        field(&status, "role"),
+        &proto::datum::Value::String("replica".to_string())
+    );
+    assert_eq!(
+        field(&status, "primary"),
+        &proto::datum::Value::String(primary.address.clone())
+    );
+    assert_eq!(field(&status, "lag"), &proto::datum::Value::Int(0));
+    assert!(status.fields.contains_key("last_contact_ms"));
+
+    let status = replication_status(&mut primary_stream).await;
+    assert_eq!(
+        field(&status, "role"),
+        &proto::datum::Value::String("primary".to_string())
+    );
+
+    // Writes to the replica are rejected
+    let insert = create_insert_query(
+        database_name,
+        table_name,
+        vec![create_datum_object(vec![(
+            "id",
+            create_string_datum("bob"),
+        )])],
+    );
+    let response = query(&mut replica_stream, &insert).await;
+    let error = decode_response_payload(&response).expect_err("Replica accepted a write");
+    assert!(
+        error.to_string().contains("read-only replica"),
+        "unexpected error: {error}"
+    );
+}
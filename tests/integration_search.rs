@@ -0,0 +1,182 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn items(datum: &proto::Datum) -> &[proto::Datum] {
+    match &datum.value {
+        Some(proto::datum::Value::Array(array)) => &array.items,
+        Some(proto::datum::Value::Object(object)) => items(&object.fields["documents"]),
+        other => panic!("Expected documents, got {other:?}"),
+    }
+}
+
+fn document_ids(datum: &proto::Datum) -> Vec<String> {
+    items(datum)
+        .iter()
+        .map(|item| match &item.value {
+            Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+                Some(proto::datum::Value::String(id)) => id.clone(),
+                other => panic!("Unexpected id {other:?}"),
+            },
+            other => panic!("Expected a document, got {other:?}"),
+        })
+        .collect()
+}
+
+fn source(query: proto::Query) -> Option<Box<proto::Query>> {
+    Some(Box::new(proto::Query {
+        options: None,
+        ..query
+    }))
+}
+
+#[tokio::test]
+async fn test_search_ranks_by_relevance() {
+    let query_id = "test-search-001";
+    let database_name = &generate_unique_name("test_db_search");
+    let table_name = "posts";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+
+    let posts = [
+        ("a", "Rust databases", "draft"),
+        ("b", "Rust, rust and more RUST", "published"),
+        ("c", "Cooking with Python", "published"),
+        ("d", "Running a database in Rust", "published"),
+    ];
+    let documents = posts
+        .iter()
+        .map(|(id, title, status)| {
+            create_datum_object(vec![
+                ("id", create_string_datum(id)),
+                ("title", create_string_datum(title)),
+                ("status", create_string_datum(status)),
+            ])
+        })
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, documents),
+    )
+    .await;
+
+    query(
+        &mut stream,
+        &format!("{query_id}-index-create"),
+        &create_index_create_query(database_name, table_name, "title", vec!["title"], true),
+    )
+    .await;
+    let indexes = query(
+        &mut stream,
+        &format!("{query_id}-index-list"),
+        &create_index_list_query(database_name, table_name),
+    )
+    .await;
+    assert_eq!(items(&indexes), [create_string_datum("title")]);
+
+    let search = create_search_query(database_name, table_name, "title", "rust");
+    let result = query(&mut stream, &format!("{query_id}-search"), &search).await;
+    assert_eq!(document_ids(&result), ["b", "a", "d"]);
+    let Some(proto::datum::Value::Object(object)) = &result.value else {
+        panic!("Expected a search result, got {result:?}");
+    };
+    assert_eq!(items(&object.fields["scores"]).len(), 3);
+
+    // Stemming matches other forms of a word
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-search-stemmed"),
+        &create_search_query(database_name, table_name, "title", "databases runs"),
+    )
+    .await;
+    assert_eq!(document_ids(&result).len(), 2);
+
+    // Search composes with Filter, Limit and Pluck and keeps its order
+    let filtered = proto::Query {
+        options: search.options,
+        cursor: None,
+        kind: Some(proto::query::Kind::Filter(Box::new(proto::Filter {
+            source: source(search.clone()),
+            predicate: Some(Box::new(create_binary_expression(
+                proto::binary_op::Operator::Eq,
+                create_field_expression(vec!["status"]),
+                create_literal_expression(create_string_datum("published")),
+            ))),
+        }))),
+    };
+    let result = query(&mut stream, &format!("{query_id}-filter"), &filtered).await;
+    assert_eq!(document_ids(&result), ["b", "d"]);
+
+    let limited = proto::Query {
+        options: search.options,
+        cursor: None,
+        kind: Some(proto::query::Kind::Limit(Box::new(proto::Limit {
+            source: source(search.clone()),
+            count: 2,
+        }))),
+    };
+    let result = query(&mut stream, &format!("{query_id}-limit"), &limited).await;
+    assert_eq!(document_ids(&result), ["b", "a"]);
+
+    let plucked = proto::Query {
+        options: search.options,
+        cursor: None,
+        kind: Some(proto::query::Kind::Pluck(Box::new(proto::Pluck {
+            source: source(search.clone()),
+            fields: vec![proto::FieldRef {
+                path: vec!["id".to_string()],
+                separator: ".".to_string(),
+            }],
+        }))),
+    };
+    let result = query(&mut stream, &format!("{query_id}-pluck"), &plucked).await;
+    assert_eq!(document_ids(&result), ["b", "a", "d"]);
+
+    query(
+        &mut stream,
+        &format!("{query_id}-index-drop"),
+        &create_index_drop_query(database_name, table_name, "title"),
+    )
+    .await;
+    let indexes = query(
+        &mut stream,
+        &format!("{query_id}-index-list-dropped"),
+        &create_index_list_query(database_name, table_name),
+    )
+    .await;
+    assert!(items(&indexes).is_empty());
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
@@ -0,0 +1,280 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn items(datum: &proto::Datum) -> &[proto::Datum] {
+    match &datum.value {
+        Some(proto::datum::Value::Array(array)) => &array.items,
+        other => panic!("Expected documents, got {other:?}"),
+    }
+}
+
+fn document_ids(datum: &proto::Datum) -> Vec<String> {
+    items(datum)
+        .iter()
+        .map(|item| match &item.value {
+            Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+                Some(proto::datum::Value::String(id)) => id.clone(),
+                other => panic!("Unexpected id {other:?}"),
+            },
+            other => panic!("Expected a document, got {other:?}"),
+        })
+        .collect()
+}
+
+fn array_datum(items: Vec<proto::Datum>) -> proto::Datum {
+    proto::Datum {
+        value: Some(proto::datum::Value::Array(proto::DatumArray {
+            items,
+            element_type: String::new(),
+        })),
+    }
+}
+
+#[tokio::test]
+async fn test_secondary_indexes() {
+    let query_id = "test-secondary-001";
+    let database_name = &generate_unique_name("test_db_secondary");
+    let table_name = "events";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-index-create-compound"),
+        &create_secondary_index_create_query(
+            database_name,
+            table_name,
+            "tenant_created",
+            &["tenant", "created"],
+            false,
+        ),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-index-create-multi"),
+        &create_secondary_index_create_query(database_name, table_name, "tags", &["tags"], true),
+    )
+    .await;
+
+    let events = [
+        ("a", "acme", 3, vec!["deploy", "prod"]),
+        ("b", "acme", 1, vec!["deploy"]),
+        ("c", "globex", 2, vec!["prod"]),
+        ("d", "acme", 7, vec![]),
+    ];
+    let documents = events
+        .iter()
+        .map(|(id, tenant, created, tags)| {
+            create_datum_object(vec![
+                ("id", create_string_datum(id)),
+                ("tenant", create_string_datum(tenant)),
+                ("created", create_int_datum(*created)),
+                (
+                    "tags",
+                    array_datum(tags.iter().map(|tag| create_string_datum(tag)).collect()),
+                ),
+            ])
+        })
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, documents),
+    )
+    .await;
+
+    // A multi index files each document under every tag it carries
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-get-all-tags"),
+        &create_get_all_by_index_query(
+            database_name,
+            table_name,
+            "tags",
+            vec![create_string_datum("prod")],
+        ),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["a", "c"]);
+
+    // A compound index answers ranges over its trailing field in order
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-between"),
+        &create_between_query(
+            database_name,
+            table_name,
+            "tenant_created",
+            Some(array_datum(vec![
+                create_string_datum("acme"),
+                create_int_datum(1),
+            ])),
+            Some(array_datum(vec![
+                create_string_datum("acme"),
+                create_int_datum(5),
+            ])),
+        ),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["b", "a"]);
+
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-get-all-compound"),
+        &create_get_all_by_index_query(
+            database_name,
+            table_name,
+            "tenant_created",
+            vec![array_datum(vec![
+                create_string_datum("globex"),
+                create_int_datum(2),
+            ])],
+        ),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["c"]);
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_unique_index() {
+    let query_id = "test-secondary-002";
+    let database_name = &generate_unique_name("test_db_unique");
+    let table_name = "users";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-index-create"),
+        &create_unique_index_create_query(database_name, table_name, "email", "email"),
+    )
+    .await;
+
+    let user = |id: &str, email: &str| {
+        create_datum_object(vec![
+            ("id", create_string_datum(id)),
+            ("email", create_string_datum(email)),
+        ])
+    };
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(
+            database_name,
+            table_name,
+            vec![
+                user("ann", "ann@example.com"),
+                user("bob", "bob@example.com"),
+            ],
+        ),
+    )
+    .await;
+
+    // Inserting, or overwriting, a document with a taken value is refused
+    let duplicate_id = format!("{query_id}-insert-duplicate");
+    let envelope = create_envelope(
+        &duplicate_id,
+        &create_insert_query(
+            database_name,
+            table_name,
+            vec![user("cat", "ann@example.com")],
+        ),
+    );
+    let response = send_envelope_to_server(&mut stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    let error = decode_response_payload(&response)
+        .expect_err("Duplicate insert should fail")
+        .to_string();
+    assert!(error.contains("email"), "unexpected error {error}");
+    assert!(error.contains("ann"), "unexpected error {error}");
+
+    // An update giving every document the same value changes none of them
+    let update_id = format!("{query_id}-update-duplicate");
+    let envelope = create_envelope(
+        &update_id,
+        &create_update_query(
+            database_name,
+            table_name,
+            proto::DatumObject {
+                fields: [("email".to_string(), create_string_datum("same@example.com"))].into(),
+            },
+        ),
+    );
+    let response = send_envelope_to_server(&mut stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    assert!(decode_response_payload(&response).is_err());
+
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-get-all"),
+        &create_get_all_by_index_query(
+            database_name,
+            table_name,
+            "email",
+            vec![
+                create_string_datum("ann@example.com"),
+                create_string_datum("bob@example.com"),
+                create_string_datum("cat@example.com"),
+            ],
+        ),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["ann", "bob"]);
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
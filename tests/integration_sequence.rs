@@ -0,0 +1,244 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn query_of(kind: proto::query::Kind) -> proto::Query {
+    proto::Query {
+        options: None,
+        cursor: None,
+        kind: Some(kind),
+    }
+}
+
+fn array_datum(items: Vec<proto::Datum>) -> proto::Datum {
+    proto::Datum {
+        value: Some(proto::datum::Value::Array(proto::DatumArray {
+            items,
+            element_type: String::new(),
+        })),
+    }
+}
+
+fn items(datum: &proto::Datum) -> &[proto::Datum] {
+    match &datum.value {
+        Some(proto::datum::Value::Array(array)) => &array.items,
+        other => panic!("Expected an array, got {other:?}"),
+    }
+}
+
+fn id(doc: &proto::Datum) -> &str {
+    match &doc.value {
+        Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+            Some(proto::datum::Value::String(id)) => id,
+            other => panic!("Expected a string id, got {other:?}"),
+        },
+        other => panic!("Expected a document, got {other:?}"),
+    }
+}
+
+fn ids(datum: &proto::Datum) -> Vec<&str> {
+    items(datum).iter().map(id).collect()
+}
+
+#[tokio::test]
+async fn test_sequence_operations() {
+    let query_id = "test-sequence-001";
+    let database_name = &generate_unique_name("test_db_sequence");
+    let table_name = "letters";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+    let letters = ["a", "b", "c", "d", "e"]
+        .iter()
+        .enumerate()
+        .map(|(n, letter)| {
+            create_datum_object(vec![
+                ("id", create_string_datum(letter)),
+                ("n", create_int_datum(n as i64)),
+            ])
+        })
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, letters),
+    )
+    .await;
+    let table = || Some(Box::new(create_table_query(database_name, table_name)));
+    let n_above = |n: i64| {
+        Some(Box::new(create_filter_query(
+            database_name,
+            table_name,
+            create_binary_expression(
+                proto::binary_op::Operator::Gt,
+                create_field_expression(vec!["n"]),
+                create_literal_expression(create_int_datum(n)),
+            ),
+        )))
+    };
+
+    // Slices count from the end when negative, by default with the start and without
+    // the end
+    let slice = |start, end, left_open, right_closed| {
+        query_of(proto::query::Kind::Slice(Box::new(proto::Slice {
+            source: table(),
+            start,
+            end,
+            left_open,
+            right_closed,
+        })))
+    };
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-slice"),
+        &slice(1, Some(-1), false, false),
+    )
+    .await;
+    assert_eq!(ids(&result), ["b", "c", "d"]);
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-slice-bounds"),
+        &slice(1, Some(3), true, true),
+    )
+    .await;
+    assert_eq!(ids(&result), ["c", "d"]);
+
+    let nth = |index| {
+        query_of(proto::query::Kind::Nth(Box::new(proto::Nth {
+            source: table(),
+            index,
+        })))
+    };
+    let result = query(&mut stream, &format!("{query_id}-nth"), &nth(-2)).await;
+    assert_eq!(id(&result), "d");
+    let response = send_envelope_to_server(
+        &mut stream,
+        &create_envelope(&format!("{query_id}-nth-out-of-bounds"), &nth(5)),
+    )
+    .await
+    .expect("Failed to send envelope and receive response");
+    let error = decode_response_payload(&response)
+        .expect_err("An index past the end should fail")
+        .to_string();
+    assert!(
+        error.contains("Index out of bounds"),
+        "unexpected error {error}"
+    );
+
+    let is_empty = |source| {
+        query_of(proto::query::Kind::IsEmpty(Box::new(proto::IsEmpty {
+            source,
+        })))
+    };
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-is-empty"),
+        &is_empty(table()),
+    )
+    .await;
+    assert_eq!(result, create_bool_datum(false));
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-is-empty-filtered"),
+        &is_empty(n_above(10)),
+    )
+    .await;
+    assert_eq!(result, create_bool_datum(true));
+
+    let sample = query_of(proto::query::Kind::Sample(Box::new(proto::Sample {
+        source: table(),
+        count: 3,
+    })));
+    let result = query(&mut stream, &format!("{query_id}-sample"), &sample).await;
+    let mut sampled = ids(&result);
+    sampled.sort_unstable();
+    sampled.dedup();
+    assert_eq!(sampled.len(), 3);
+
+    // Interleaving merges two sequences already in the same order
+    let union = query_of(proto::query::Kind::Union(Box::new(proto::Union {
+        source: n_above(2),
+        other: Some(Box::new(create_get_all_query(
+            database_name,
+            table_name,
+            vec![create_string_datum("a"), create_string_datum("d")],
+        ))),
+        interleave: vec![create_sort_field("id", proto::SortDirection::Asc)],
+    })));
+    let result = query(&mut stream, &format!("{query_id}-union"), &union).await;
+    assert_eq!(ids(&result), ["a", "d", "d", "e"]);
+
+    let offsets_of = |source, target| {
+        query_of(proto::query::Kind::OffsetsOf(Box::new(proto::OffsetsOf {
+            source,
+            target: Some(target),
+        })))
+    };
+    let is_c = create_binary_expression(
+        proto::binary_op::Operator::Eq,
+        create_field_expression(vec!["id"]),
+        create_literal_expression(create_string_datum("c")),
+    );
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-offsets-of-predicate"),
+        &offsets_of(
+            table(),
+            proto::offsets_of::Target::Predicate(Box::new(is_c)),
+        ),
+    )
+    .await;
+    assert_eq!(result, array_datum(vec![create_int_datum(2)]));
+    let array = array_datum(vec![
+        create_string_datum("x"),
+        create_string_datum("y"),
+        create_string_datum("x"),
+    ]);
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-offsets-of-value"),
+        &offsets_of(
+            Some(Box::new(query_of(proto::query::Kind::Expression(
+                Box::new(create_literal_expression(array)),
+            )))),
+            proto::offsets_of::Target::Value(create_string_datum("x")),
+        ),
+    )
+    .await;
+    assert_eq!(
+        result,
+        array_datum(vec![create_int_datum(0), create_int_datum(2)])
+    );
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
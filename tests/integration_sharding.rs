@@ -0,0 +1,109 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn document_ids(datum: &proto::Datum) -> Vec<String> {
+    let Some(proto::datum::Value::Array(array)) = &datum.value else {
+        panic!("Expected an array, got {datum:?}");
+    };
+    array
+        .items
+        .iter()
+        .map(|item| match &item.value {
+            Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+                Some(proto::datum::Value::String(id)) => id.clone(),
+                other => panic!("Unexpected id {other:?}"),
+            },
+            other => panic!("Expected a document, got {other:?}"),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_sharded_table_rebalance() {
+    let query_id = "test-sharding-001";
+    let database_name = &generate_unique_name("test_db_sharding");
+    let table_name = "users";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_sharded_table_create_query(database_name, table_name, Some(4)),
+    )
+    .await;
+
+    let ids = ["Alice", "Mallory", "bob", "carol", "trent", "walter"];
+    let documents = ids
+        .iter()
+        .map(|id| create_datum_object(vec![("id", create_string_datum(id))]))
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, documents),
+    )
+    .await;
+
+    // Documents in different shards come back as one table, in key order
+    let scanned = query(
+        &mut stream,
+        &format!("{query_id}-scan"),
+        &create_table_query(database_name, table_name),
+    )
+    .await;
+    assert_eq!(document_ids(&scanned), ids);
+
+    let rebalanced = query(
+        &mut stream,
+        &format!("{query_id}-rebalance"),
+        &create_rebalance_query(database_name, table_name, Some(2)),
+    )
+    .await;
+    let Some(proto::datum::Value::Object(summary)) = rebalanced.value else {
+        panic!("Expected a rebalance summary, got {rebalanced:?}");
+    };
+    let Some(proto::datum::Value::Array(shards)) = &summary.fields["shards"].value else {
+        panic!("Expected a list of shards");
+    };
+    assert_eq!(shards.items.len(), 2);
+
+    let scanned = query(
+        &mut stream,
+        &format!("{query_id}-scan-rebalanced"),
+        &create_table_query(database_name, table_name),
+    )
+    .await;
+    assert_eq!(document_ids(&scanned), ids);
+
+    let fetched = query(
+        &mut stream,
+        &format!("{query_id}-get"),
+        &create_get_query(database_name, table_name, create_string_datum("walter")),
+    )
+    .await;
+    assert!(matches!(
+        fetched.value,
+        Some(proto::datum::Value::Object(_))
+    ));
+}
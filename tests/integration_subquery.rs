@@ -0,0 +1,166 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn query_of(kind: proto::query::Kind) -> proto::Query {
+    proto::Query {
+        options: None,
+        cursor: None,
+        kind: Some(kind),
+    }
+}
+
+fn ids(datum: &proto::Datum) -> Vec<&str> {
+    let Some(proto::datum::Value::Array(array)) = &datum.value else {
+        panic!("Expected an array, got {datum:?}");
+    };
+    array
+        .items
+        .iter()
+        .map(|doc| match &doc.value {
+            Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+                Some(proto::datum::Value::String(id)) => id.as_str(),
+                other => panic!("Expected a string id, got {other:?}"),
+            },
+            other => panic!("Expected a document, got {other:?}"),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_filter_with_correlated_subqueries() {
+    let query_id = "test-subquery-001";
+    let database_name = &generate_unique_name("test_db_subquery");
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    for table_name in ["users", "orders"] {
+        query(
+            &mut stream,
+            &format!("{query_id}-{table_name}-create"),
+            &create_table_create_query(database_name, table_name),
+        )
+        .await;
+    }
+    query(
+        &mut stream,
+        &format!("{query_id}-index-create"),
+        &create_secondary_index_create_query(
+            database_name,
+            "orders",
+            "user_id",
+            &["user_id"],
+            false,
+        ),
+    )
+    .await;
+    let users = ["u1", "u2", "u3"]
+        .iter()
+        .map(|id| create_datum_object(vec![("id", create_string_datum(id))]))
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-users-insert"),
+        &create_insert_query(database_name, "users", users),
+    )
+    .await;
+    let orders = [("o1", "u1"), ("o2", "u3"), ("o3", "u1")]
+        .iter()
+        .map(|(id, user_id)| {
+            create_datum_object(vec![
+                ("id", create_string_datum(id)),
+                ("user_id", create_string_datum(user_id)),
+            ])
+        })
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-orders-insert"),
+        &create_insert_query(database_name, "orders", orders),
+    )
+    .await;
+
+    // Users with orders, looked up in the index by each user's id
+    let orders_of_user = query_of(proto::query::Kind::Count(Box::new(proto::Count {
+        source: Some(Box::new(query_of(proto::query::Kind::GetAll(Box::new(
+            proto::GetAll {
+                source: Some(Box::new(create_table_query(database_name, "orders"))),
+                keys: vec![],
+                index: "user_id".to_string(),
+                key_expressions: vec![create_variable_expression("id")],
+            },
+        ))))),
+    })));
+    let with_orders = create_filter_query(
+        database_name,
+        "users",
+        create_binary_expression(
+            proto::binary_op::Operator::Gt,
+            create_subquery_expression(orders_of_user.clone()),
+            create_literal_expression(create_int_datum(0)),
+        ),
+    );
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-with-orders"),
+        &with_orders,
+    )
+    .await;
+    assert_eq!(ids(&result), ["u1", "u3"]);
+
+    let without_orders = create_filter_query(
+        database_name,
+        "users",
+        create_binary_expression(
+            proto::binary_op::Operator::Eq,
+            create_subquery_expression(orders_of_user),
+            create_literal_expression(create_int_datum(0)),
+        ),
+    );
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-without-orders"),
+        &without_orders,
+    )
+    .await;
+    assert_eq!(ids(&result), ["u2"]);
+
+    // An uncorrelated subquery runs once for all users
+    let any_orders = create_filter_query(
+        database_name,
+        "users",
+        create_binary_expression(
+            proto::binary_op::Operator::Gt,
+            create_subquery_expression(create_count_query(database_name, "orders")),
+            create_literal_expression(create_int_datum(2)),
+        ),
+    );
+    let result = query(&mut stream, &format!("{query_id}-any-orders"), &any_orders).await;
+    assert_eq!(ids(&result), ["u1", "u2", "u3"]);
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
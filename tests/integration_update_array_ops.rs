@@ -0,0 +1,181 @@
+mod common;
+
+use common::*;
+use proto::array_op::Operator;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn array_datum(items: Vec<proto::Datum>) -> proto::Datum {
+    proto::Datum {
+        value: Some(proto::datum::Value::Array(proto::DatumArray {
+            items,
+            element_type: String::new(),
+        })),
+    }
+}
+
+fn strings(values: &[&str]) -> proto::Datum {
+    array_datum(
+        values
+            .iter()
+            .map(|value| create_string_datum(value))
+            .collect(),
+    )
+}
+
+fn field<'a>(doc: &'a proto::Datum, path: &[&str]) -> &'a proto::Datum {
+    path.iter().fold(doc, |datum, key| match &datum.value {
+        Some(proto::datum::Value::Object(object)) => &object.fields[*key],
+        other => panic!("Expected a document, got {other:?}"),
+    })
+}
+
+#[tokio::test]
+async fn test_update_array_operations() {
+    let query_id = "test-update-array-ops-001";
+    let database_name = &generate_unique_name("test_db_update_array_ops");
+    let table_name = "posts";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+    let post = create_datum_object(vec![
+        ("id", create_string_datum("post")),
+        ("tags", strings(&["rust", "db", "db"])),
+        (
+            "meta",
+            proto::Datum {
+                value: Some(proto::datum::Value::Object(create_datum_object(vec![(
+                    "editors",
+                    strings(&["ann"]),
+                )]))),
+            },
+        ),
+    ]);
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, vec![post]),
+    )
+    .await;
+    let get = create_get_query(database_name, table_name, create_string_datum("post"));
+
+    // Operations apply in order, on nested fields too
+    let update = create_array_update_query(
+        database_name,
+        table_name,
+        vec![
+            create_array_op(
+                Operator::Append,
+                "tags",
+                Some(create_string_datum("web")),
+                0,
+            ),
+            create_array_op(
+                Operator::Prepend,
+                "tags",
+                Some(create_string_datum("new")),
+                0,
+            ),
+            create_array_op(Operator::DeleteAt, "tags", None, -1),
+            create_array_op(Operator::Difference, "tags", Some(strings(&["db"])), 0),
+            create_array_op(
+                Operator::InsertAt,
+                "meta.editors",
+                Some(create_string_datum("bob")),
+                0,
+            ),
+            create_array_op(
+                Operator::SetUnion,
+                "meta.editors",
+                Some(strings(&["ann", "cat"])),
+                0,
+            ),
+        ],
+    );
+    query(&mut stream, &format!("{query_id}-update"), &update).await;
+    let result = query(&mut stream, &format!("{query_id}-get"), &get).await;
+    assert_eq!(field(&result, &["tags"]), &strings(&["new", "rust"]));
+    assert_eq!(
+        field(&result, &["meta", "editors"]),
+        &strings(&["bob", "ann", "cat"])
+    );
+
+    // Concurrent appends from many connections are all kept
+    let mut appends = Vec::new();
+    for i in 0..8 {
+        let update = create_array_update_query(
+            database_name,
+            table_name,
+            vec![create_array_op(
+                Operator::Append,
+                "log",
+                Some(create_int_datum(i)),
+                0,
+            )],
+        );
+        appends.push(tokio::spawn(async move {
+            let mut stream = connect_to_server().await.unwrap();
+            query(&mut stream, &format!("{query_id}-append-{i}"), &update).await;
+        }));
+    }
+    for append in appends {
+        append.await.unwrap();
+    }
+    let result = query(&mut stream, &format!("{query_id}-get-log"), &get).await;
+    let Some(proto::datum::Value::Array(log)) = &field(&result, &["log"]).value else {
+        panic!("Expected the log to be an array");
+    };
+    assert_eq!(log.items.len(), 8);
+
+    // An index out of range fails the update
+    let update = create_array_update_query(
+        database_name,
+        table_name,
+        vec![create_array_op(
+            Operator::ChangeAt,
+            "tags",
+            Some(create_string_datum("x")),
+            2,
+        )],
+    );
+    let response = send_envelope_to_server(
+        &mut stream,
+        &create_envelope(&format!("{query_id}-out-of-range"), &update),
+    )
+    .await
+    .expect("Failed to send envelope and receive response");
+    let error = decode_response_payload(&response)
+        .expect_err("Out of range change should fail")
+        .to_string();
+    assert!(error.contains("out of range"), "unexpected error {error}");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
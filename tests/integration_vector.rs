@@ -0,0 +1,164 @@
+mod common;
+
+use common::*;
+use rulodb::ast::proto;
+use tokio::net::TcpStream;
+
+async fn query(stream: &mut TcpStream, query_id: &str, query: &proto::Query) -> proto::Datum {
+    let envelope = create_envelope(query_id, query);
+    let response = send_envelope_to_server(stream, &envelope)
+        .await
+        .expect("Failed to send envelope and receive response");
+    validate_response_envelope(&response, query_id).expect("Response validation failed");
+    decode_response_payload(&response).expect("Failed to decode response payload")
+}
+
+fn items(datum: &proto::Datum) -> &[proto::Datum] {
+    match &datum.value {
+        Some(proto::datum::Value::Array(array)) => &array.items,
+        Some(proto::datum::Value::Object(object)) => items(&object.fields["documents"]),
+        other => panic!("Expected documents, got {other:?}"),
+    }
+}
+
+fn document_ids(datum: &proto::Datum) -> Vec<String> {
+    items(datum)
+        .iter()
+        .map(|item| match &item.value {
+            Some(proto::datum::Value::Object(object)) => match &object.fields["id"].value {
+                Some(proto::datum::Value::String(id)) => id.clone(),
+                other => panic!("Unexpected id {other:?}"),
+            },
+            other => panic!("Expected a document, got {other:?}"),
+        })
+        .collect()
+}
+
+fn vector_datum(values: &[f64]) -> proto::Datum {
+    proto::Datum {
+        value: Some(proto::datum::Value::Array(proto::DatumArray {
+            items: values
+                .iter()
+                .map(|value| create_float_datum(*value))
+                .collect(),
+            element_type: "float".to_string(),
+        })),
+    }
+}
+
+#[tokio::test]
+async fn test_nearest_vectors() {
+    let query_id = "test-vector-001";
+    let database_name = &generate_unique_name("test_db_vector");
+    let table_name = "articles";
+
+    let mut stream = connect_to_server()
+        .await
+        .expect("Failed to connect to server. Make sure the server is running on 127.0.0.1:6090");
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-create"),
+        &create_database_create_query(database_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-table-create"),
+        &create_table_create_query(database_name, table_name),
+    )
+    .await;
+    query(
+        &mut stream,
+        &format!("{query_id}-index-create"),
+        &create_vector_index_create_query(
+            database_name,
+            table_name,
+            "embedding",
+            "embedding",
+            3,
+            proto::vector_index::Metric::Cosine,
+        ),
+    )
+    .await;
+
+    // Documents written after the index exists are indexed along with them
+    let articles = [
+        ("a", "news", [1.0, 0.0, 0.0]),
+        ("b", "sport", [0.9, 0.1, 0.0]),
+        ("c", "news", [0.0, 1.0, 0.0]),
+        ("d", "sport", [0.0, 0.0, 1.0]),
+    ];
+    let documents = articles
+        .iter()
+        .map(|(id, section, embedding)| {
+            create_datum_object(vec![
+                ("id", create_string_datum(id)),
+                ("section", create_string_datum(section)),
+                ("embedding", vector_datum(embedding)),
+            ])
+        })
+        .collect();
+    query(
+        &mut stream,
+        &format!("{query_id}-insert"),
+        &create_insert_query(database_name, table_name, documents),
+    )
+    .await;
+
+    let indexes = query(
+        &mut stream,
+        &format!("{query_id}-index-list"),
+        &create_index_list_query(database_name, table_name),
+    )
+    .await;
+    assert_eq!(items(&indexes), [create_string_datum("embedding")]);
+
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-nearest"),
+        &create_nearest_query(
+            database_name,
+            table_name,
+            "embedding",
+            vec![2.0, 0.1, 0.0],
+            2,
+            None,
+        ),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["a", "b"]);
+    let Some(proto::datum::Value::Object(object)) = &result.value else {
+        panic!("Expected a nearest result, got {result:?}");
+    };
+    let distances = items(&object.fields["distances"]);
+    assert_eq!(distances.len(), 2);
+
+    // A pre-filter keeps only the documents it accepts
+    let filter = create_binary_expression(
+        proto::binary_op::Operator::Eq,
+        create_field_expression(vec!["section"]),
+        create_literal_expression(create_string_datum("news")),
+    );
+    let result = query(
+        &mut stream,
+        &format!("{query_id}-nearest-filtered"),
+        &create_nearest_query(
+            database_name,
+            table_name,
+            "embedding",
+            vec![0.2, 0.1, 1.0],
+            2,
+            Some(filter),
+        ),
+    )
+    .await;
+    assert_eq!(document_ids(&result), ["a", "c"]);
+
+    query(
+        &mut stream,
+        &format!("{query_id}-db-drop"),
+        &create_database_drop_query(database_name),
+    )
+    .await;
+}
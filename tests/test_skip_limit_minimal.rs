@@ -23,6 +23,7 @@ async fn test_skip_limit_basic_functionality() {
         options: Some(QueryOptions {
             timeout_ms: 1000,
             explain: false,
+            read_mode: ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(query::Kind::DatabaseCreate(DatabaseCreate {
@@ -37,6 +38,7 @@ async fn test_skip_limit_basic_functionality() {
         options: Some(QueryOptions {
             timeout_ms: 1000,
             explain: false,
+            read_mode: ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(query::Kind::TableCreate(TableCreate {
@@ -46,6 +48,8 @@ async fn test_skip_limit_basic_functionality() {
                 }),
                 name: "users".to_string(),
             }),
+            options: None,
+            shards: None,
         })),
     };
     let plan = planner.plan(&create_table_query).unwrap();
@@ -80,6 +84,7 @@ async fn test_skip_limit_basic_functionality() {
         options: Some(QueryOptions {
             timeout_ms: 1000,
             explain: false,
+            read_mode: ReadMode::Single.into(),
         }),
         cursor: None,
         kind: Some(query::Kind::Insert(Box::new(Insert {
@@ -96,6 +101,7 @@ async fn test_skip_limit_basic_functionality() {
                 })),
             })),
             documents,
+            durability: Durability::Default.into(),
         }))),
     };
     let plan = planner.plan(&insert_query).unwrap();
@@ -107,11 +113,13 @@ async fn test_skip_limit_basic_functionality() {
         options: Some(QueryOptions {
             timeout_ms: 1000,
             explain: false,
+            read_mode: ReadMode::Single.into(),
         }),
         cursor: Some(Cursor {
             start_key: None,
             batch_size: Some(5),
             sort: None,
+            snapshot: None,
         }),
         kind: Some(query::Kind::Table(Table {
             table: Some(TableRef {
@@ -147,11 +155,13 @@ async fn test_skip_limit_basic_functionality() {
         options: Some(QueryOptions {
             timeout_ms: 1000,
             explain: false,
+            read_mode: ReadMode::Single.into(),
         }),
         cursor: Some(Cursor {
             start_key: None,
             batch_size: Some(3),
             sort: None,
+            snapshot: None,
         }),
         kind: Some(query::Kind::Skip(Box::new(Skip {
             source: Some(Box::new(Query {
@@ -211,11 +221,13 @@ async fn test_skip_limit_basic_functionality() {
         options: Some(QueryOptions {
             timeout_ms: 1000,
             explain: false,
+            read_mode: ReadMode::Single.into(),
         }),
         cursor: Some(Cursor {
             start_key: None,
             batch_size: Some(10),
             sort: None,
+            snapshot: None,
         }),
         kind: Some(query::Kind::Limit(Box::new(Limit {
             source: Some(Box::new(Query {
@@ -261,11 +273,13 @@ async fn test_skip_limit_basic_functionality() {
         options: Some(QueryOptions {
             timeout_ms: 1000,
             explain: false,
+            read_mode: ReadMode::Single.into(),
         }),
         cursor: Some(Cursor {
             start_key: None,
             batch_size: Some(2),
             sort: None,
+            snapshot: None,
         }),
         kind: Some(query::Kind::Skip(Box::new(Skip {
             source: Some(Box::new(Query {
@@ -325,11 +339,13 @@ async fn test_skip_limit_basic_functionality() {
         options: Some(QueryOptions {
             timeout_ms: 1000,
             explain: false,
+            read_mode: ReadMode::Single.into(),
         }),
         cursor: Some(Cursor {
             start_key: None,
             batch_size: Some(5),
             sort: None,
+            snapshot: None,
         }),
         kind: Some(query::Kind::Skip(Box::new(Skip {
             source: Some(Box::new(Query {